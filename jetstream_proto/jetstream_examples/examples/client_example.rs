@@ -54,10 +54,10 @@ async fn main() -> Result<()> {
         tokio::time::sleep(Duration::from_millis(500)).await;
     }
 
-    // Demonstrate session ticket generation for 0-RTT
-    tracing::info!("🎫 Generating session ticket for 0-RTT resumption...");
-    if let Ok(ticket) = conn.session.generate_session_ticket() {
-        tracing::info!("Session ticket generated: {:?}", &ticket.ticket_id[..8]);
+    // Demonstrate the session ticket the server issued for 0-RTT
+    tracing::info!("🎫 Checking for a session ticket for 0-RTT resumption...");
+    if let Some(ticket) = conn.session_ticket() {
+        tracing::info!("Session ticket received: {:?}", &ticket.ticket_id[..8]);
         // In a real application, save this ticket for next connection
     }
 
@@ -2,8 +2,8 @@
 //! 
 //! Demonstrates HTTP/3 compatibility layer.
 
-use jsp_transport::http3::{Http3Server, Request, Response, Frame};
-use std::sync::Arc;
+use jsp_transport::http3::{Http3Client, Http3Server, Request, Response};
+use jsp_transport::quic_transport::QuicServer;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -15,53 +15,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("🚀 JetStreamProto HTTP/3 Compatibility Demo");
     println!("============================================\n");
 
-    // Create request handler
-    let handler = Arc::new(|req: Request| {
-        println!("📥 Request: {} {}", req.method, req.path);
-        
-        match req.path.as_str() {
-            "/" => Response::ok().text("Welcome to JetStreamProto HTTP/3!"),
-            "/api/status" => Response::ok().json(r#"{"status":"ok","protocol":"HTTP/3"}"#),
-            "/api/echo" => {
-                let body = String::from_utf8_lossy(&req.body);
-                Response::ok()
-                    .header("Content-Type", "text/plain")
-                    .body(format!("Echo: {}", body).into_bytes())
-            }
-            _ => Response::not_found().text("Not Found"),
-        }
-    });
+    let server = Http3Server::default()
+        .route("GET", "/", |_req| Response::ok().text("Welcome to JetStreamProto HTTP/3!"))
+        .route("GET", "/api/status", |_req| {
+            Response::ok().json(r#"{"status":"ok","protocol":"HTTP/3"}"#)
+        })
+        .route("POST", "/api/echo", |req| {
+            let body = String::from_utf8_lossy(&req.body);
+            Response::ok()
+                .header("Content-Type", "text/plain")
+                .body(format!("Echo: {}", body).into_bytes())
+        });
 
-    println!("🌐 Creating HTTP/3 server...");
-    let server = Http3Server::new(handler);
-    println!("✅ Server created\n");
+    println!("🌐 Starting HTTP/3 server...");
+    let quic = QuicServer::bind("127.0.0.1:0".parse()?).await?;
+    let addr = quic.local_addr()?;
+    tokio::spawn(server.run(quic));
+    println!("✅ Server listening on {}\n", addr);
 
-    // Simulate some requests
-    println!("📨 Simulating HTTP/3 requests:\n");
+    let client = Http3Client::connect(addr).await?;
+    println!("📨 Sending HTTP/3 requests:\n");
 
-    // Request 1: GET /
-    println!("1️⃣  GET /");
-    let req1_frame = Frame::headers(bytes::Bytes::from("GET / HTTP/3"));
-    let resp1 = server.handle_frame(req1_frame)?;
-    println!("   Response: {:?}\n", resp1.frame_type);
-
-    // Request 2: GET /api/status
-    println!("2️⃣  GET /api/status");
-    let req2_frame = Frame::headers(bytes::Bytes::from("GET /api/status HTTP/3"));
-    let resp2 = server.handle_frame(req2_frame)?;
-    println!("   Response: {:?}\n", resp2.frame_type);
-
-    // Request 3: POST /api/echo
-    println!("3️⃣  POST /api/echo");
-    let req3_frame = Frame::headers(bytes::Bytes::from("POST /api/echo HTTP/3"));
-    let resp3 = server.handle_frame(req3_frame)?;
-    println!("   Response: {:?}\n", resp3.frame_type);
-
-    // Request 4: GET /notfound
-    println!("4️⃣  GET /notfound");
-    let req4_frame = Frame::headers(bytes::Bytes::from("GET /notfound HTTP/3"));
-    let resp4 = server.handle_frame(req4_frame)?;
-    println!("   Response: {:?}\n", resp4.frame_type);
+    let requests = [
+        Request::new("GET", "/"),
+        Request::new("GET", "/api/status"),
+        Request::new("POST", "/api/echo").body(b"hello".to_vec()),
+        Request::new("GET", "/notfound"),
+    ];
+    for request in requests {
+        println!("➡️  {} {}", request.method, request.path);
+        let response = client.send(request).await?;
+        println!("   {} {}\n", response.status, String::from_utf8_lossy(&response.body));
+    }
 
     println!("💡 HTTP/3 Features:");
     println!("  ✅ Frame-based protocol");
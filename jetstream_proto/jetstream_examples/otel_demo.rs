@@ -2,7 +2,8 @@
 //! 
 //! Demonstrates tracing with JetStreamProto.
 
-use jsp_transport::otel::{init_tracer, global_tracer};
+use jsp_transport::otel::{init_tracer_with_exporter, global_tracer, JsonExporter};
+use std::sync::Arc;
 use std::time::Duration;
 
 #[tokio::main]
@@ -17,7 +18,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Initialize tracer
     println!("📊 Initializing tracer...");
-    init_tracer("jetstream_proto_demo")?;
+    // Completed spans are printed as JSON lines
+    init_tracer_with_exporter("jetstream_proto_demo", Arc::new(JsonExporter::stdout()))?;
     println!("✅ Tracer initialized\n");
 
     let tracer = global_tracer();
@@ -28,10 +30,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut root_span = tracer.start_span("connection_lifecycle");
     root_span.set_attribute("connection.id", "conn-12345");
     root_span.set_attribute("protocol", "jetstream");
+    let root = root_span.context();
+    println!("  Trace ID: {}", root.trace_id);
 
     // Simulate connection handshake
     {
-        let mut handshake_span = tracer.start_span("handshake");
+        let mut handshake_span = tracer.start_span_with_parent("handshake", Some(&root));
         handshake_span.set_attribute("handshake.type", "1-RTT");
         
         println!("  ├─ Handshake span started");
@@ -44,7 +48,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Simulate data transfer
     {
-        let mut transfer_span = tracer.start_span("data_transfer");
+        let mut transfer_span = tracer.start_span_with_parent("data_transfer", Some(&root));
         transfer_span.set_attribute("stream.id", "1");
         transfer_span.set_attribute("bytes", "1024");
         
@@ -61,13 +65,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Simulate multi-hop routing
     {
-        let mut multihop_span = tracer.start_span("multihop_routing");
+        let mut multihop_span = tracer.start_span_with_parent("multihop_routing", Some(&root));
         multihop_span.set_attribute("hop.count", "4");
+        let multihop = multihop_span.context();
         
         println!("  ├─ Multi-hop routing span started");
         
         for hop in 1..=4 {
-            let mut hop_span = tracer.start_span(format!("hop_{}", hop));
+            let mut hop_span = tracer.start_span_with_parent(format!("hop_{}", hop), Some(&multihop));
             hop_span.set_attribute("hop.index", hop.to_string());
             hop_span.set_attribute("hop.type", match hop {
                 1 => "wireguard",
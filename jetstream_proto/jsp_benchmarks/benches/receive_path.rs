@@ -0,0 +1,116 @@
+//! Receive path: a fresh buffer per datagram with copied payloads, against
+//! pooled buffers handed out as `Bytes` slices
+//!
+//! Prints allocations per datagram for both before timing them.
+
+use bytes::{Bytes, BytesMut};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use jsp_transport::memory_pool::{PacketPool, RECV_BUFFER_SIZE};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts allocations so both paths can be compared per operation
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const FRAMES: usize = 3;
+const HEADER: &[u8] = &[0xA0; 24];
+const PAYLOAD_LEN: usize = 300;
+
+/// A coalesced datagram of `[u16 header_len][header][u16 payload_len][payload]` frames
+fn datagram() -> Vec<u8> {
+    let mut datagram = Vec::new();
+    for frame in 0..FRAMES {
+        datagram.extend_from_slice(&(HEADER.len() as u16).to_be_bytes());
+        datagram.extend_from_slice(HEADER);
+        datagram.extend_from_slice(&(PAYLOAD_LEN as u16).to_be_bytes());
+        datagram.resize(datagram.len() + PAYLOAD_LEN, frame as u8);
+    }
+    datagram
+}
+
+/// Payload ranges of the frames in a datagram
+fn payloads(mut data: &[u8]) -> Vec<Range<usize>> {
+    let mut ranges = Vec::with_capacity(FRAMES);
+    let mut offset = 0;
+    while data.len() >= 2 {
+        let header_len = u16::from_be_bytes([data[0], data[1]]) as usize;
+        let at = 2 + header_len;
+        let payload_len = u16::from_be_bytes([data[at], data[at + 1]]) as usize;
+        let start = at + 2;
+        ranges.push(offset + start..offset + start + payload_len);
+        offset += start + payload_len;
+        data = &data[start + payload_len..];
+    }
+    ranges
+}
+
+/// Before: a new buffer for every datagram and a copy of every payload
+fn recv_copying(datagram: &[u8]) -> Vec<Bytes> {
+    let mut buf = BytesMut::with_capacity(RECV_BUFFER_SIZE);
+    buf.resize(RECV_BUFFER_SIZE, 0);
+    // Stands in for recv_from
+    buf[..datagram.len()].copy_from_slice(datagram);
+    buf.truncate(datagram.len());
+    payloads(&buf).into_iter().map(|range| Bytes::from(buf[range].to_vec())).collect()
+}
+
+/// After: a pooled buffer, frozen once and sliced
+fn recv_pooled(pool: &PacketPool, datagram: &[u8]) -> Vec<Bytes> {
+    let mut buf = pool.acquire();
+    buf.resize(RECV_BUFFER_SIZE, 0);
+    buf[..datagram.len()].copy_from_slice(datagram);
+    buf.truncate(datagram.len());
+    let data = pool.freeze(buf);
+    payloads(&data).into_iter().map(|range| data.slice(range)).collect()
+}
+
+/// Average allocations of `op`, after one warm-up call
+fn allocations_per_op(mut op: impl FnMut()) -> f64 {
+    const OPS: usize = 10_000;
+    op();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..OPS {
+        op();
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / OPS as f64
+}
+
+fn receive_path_benchmark(c: &mut Criterion) {
+    let datagram = datagram();
+    let pool = PacketPool::new(100, RECV_BUFFER_SIZE);
+    assert_eq!(recv_copying(&datagram), recv_pooled(&pool, &datagram));
+
+    println!(
+        "receive_path/copying: {:.1} allocations/op",
+        allocations_per_op(|| drop(black_box(recv_copying(&datagram))))
+    );
+    println!(
+        "receive_path/pooled: {:.1} allocations/op",
+        allocations_per_op(|| drop(black_box(recv_pooled(&pool, &datagram))))
+    );
+
+    let mut group = c.benchmark_group("receive_path");
+    group.bench_function("copying", |b| b.iter(|| recv_copying(black_box(&datagram))));
+    group.bench_function("pooled", |b| b.iter(|| recv_pooled(&pool, black_box(&datagram))));
+    group.finish();
+}
+
+criterion_group!(benches, receive_path_benchmark);
+criterion_main!(benches);
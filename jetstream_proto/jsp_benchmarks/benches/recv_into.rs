@@ -0,0 +1,116 @@
+//! Receiving with `recv`, which returns a new `Vec` per call, against
+//! `recv_into`, which appends to one the caller keeps
+//!
+//! Prints allocations per received message for both before timing them.
+//! Only the receiving side is counted: each batch is sent before the
+//! counter starts.
+
+use bytes::Bytes;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use jsp_benchmarks::utils::setup_connection_pair_with_config;
+use jsp_core::types::delivery::DeliveryMode;
+use jsp_transport::config::ConnectionConfig;
+use jsp_transport::connection::Connection;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::runtime::Runtime;
+
+/// Counts allocations so both paths can be compared per message
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Messages per batch; sent best-effort so none waits for the receiver's ACKs
+const MESSAGES: usize = 32;
+const MESSAGE_LEN: usize = 1024;
+const ROUNDS: usize = 50;
+
+async fn send_batch(client: &mut Connection, stream_id: u32, payload: &[u8]) {
+    for _ in 0..MESSAGES {
+        client.send_on_stream(stream_id, payload).await.unwrap();
+    }
+}
+
+/// Before: a fresh `Vec` from every call
+async fn drain_recv(server: &mut Connection) {
+    let mut received = 0;
+    while received < MESSAGES {
+        received += black_box(server.recv().await.unwrap()).len();
+    }
+}
+
+/// After: the same `Vec` for every call
+async fn drain_recv_into(server: &mut Connection, bufs: &mut Vec<(u32, Bytes)>) {
+    let mut received = 0;
+    while received < MESSAGES {
+        bufs.clear();
+        received += server.recv_into(bufs).await.unwrap();
+        black_box(&bufs);
+    }
+}
+
+/// Run `receive` to completion, returning the allocations it made
+fn allocations<F: Future>(rt: &Runtime, receive: F) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    rt.block_on(receive);
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn recv_into_benchmark(c: &mut Criterion) {
+    // One thread, so nothing but the receive runs while counting
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    // The default rate limit (100 messages/s) would stop the sender within two rounds
+    let config = ConnectionConfig::builder()
+        .rate_limit_messages(1_000_000)
+        .rate_limit_bytes(1 << 32)
+        .build();
+    let (mut client, mut server) = rt.block_on(setup_connection_pair_with_config(config));
+    let stream_id = client.open_stream(1, DeliveryMode::BestEffort).unwrap();
+    let payload = vec![0xA5u8; MESSAGE_LEN];
+    let mut bufs = Vec::with_capacity(MESSAGES);
+
+    let (mut recv_total, mut recv_into_total) = (0, 0);
+    // The first round of each warms up buffers and pools
+    for round in 0..=ROUNDS {
+        rt.block_on(send_batch(&mut client, stream_id, &payload));
+        let count = allocations(&rt, drain_recv(&mut server));
+        rt.block_on(send_batch(&mut client, stream_id, &payload));
+        let count_into = allocations(&rt, drain_recv_into(&mut server, &mut bufs));
+        if round > 0 {
+            recv_total += count;
+            recv_into_total += count_into;
+        }
+    }
+    let per_message = |total: usize| total as f64 / (ROUNDS * MESSAGES) as f64;
+    println!("recv_into/recv: {:.2} allocations/message", per_message(recv_total));
+    println!("recv_into/recv_into: {:.2} allocations/message", per_message(recv_into_total));
+
+    let mut group = c.benchmark_group("recv_into");
+    group.bench_function("recv", |b| b.iter(|| rt.block_on(async {
+        send_batch(&mut client, stream_id, &payload).await;
+        drain_recv(&mut server).await;
+    })));
+    group.bench_function("recv_into", |b| b.iter(|| rt.block_on(async {
+        send_batch(&mut client, stream_id, &payload).await;
+        drain_recv_into(&mut server, &mut bufs).await;
+    })));
+    group.finish();
+}
+
+criterion_group!(benches, recv_into_benchmark);
+criterion_main!(benches);
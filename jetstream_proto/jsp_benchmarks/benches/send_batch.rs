@@ -0,0 +1,79 @@
+//! A loop of `send_on_stream` calls against one `send_batch` call, for a
+//! message on each of many streams
+//!
+//! Prints send queue lock acquisitions per message for both before timing
+//! them. A multi-threaded runtime lets the sender task drain the queue, and
+//! contend for its lock, while the messages are queued.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use jsp_benchmarks::utils::setup_connection_pair_with_config;
+use jsp_core::types::delivery::DeliveryMode;
+use jsp_transport::config::ConnectionConfig;
+use jsp_transport::connection::Connection;
+use tokio::runtime::Runtime;
+
+/// Streams fanned out to, one message each per round
+const STREAMS: usize = 64;
+const MESSAGE_LEN: usize = 256;
+const ROUNDS: usize = 50;
+
+/// Before: one call per message
+async fn send_loop(client: &mut Connection, streams: &[u32], payload: &[u8]) {
+    for &stream_id in streams {
+        client.send_on_stream(stream_id, payload).await.unwrap();
+    }
+}
+
+/// After: one call for all of them
+async fn send_batched(client: &mut Connection, streams: &[u32], payload: &[u8]) {
+    let items: Vec<(u32, &[u8])> = streams.iter().map(|&stream_id| (stream_id, payload)).collect();
+    client.send_batch(black_box(&items)).await.unwrap();
+}
+
+/// Send queue locks per message over `ROUNDS` rounds of `send`
+fn locks_per_message(rt: &Runtime, client: &mut Connection, mut send: impl FnMut(&mut Connection)) -> f64 {
+    let before = client.send_queue_locks();
+    for _ in 0..ROUNDS {
+        send(client);
+        // Let the sender task catch up, so every round starts from an empty queue
+        rt.block_on(tokio::time::sleep(std::time::Duration::from_millis(1)));
+    }
+    (client.send_queue_locks() - before) as f64 / (ROUNDS * STREAMS) as f64
+}
+
+fn send_batch_benchmark(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    // Out of the way of the default rate limit (100 messages/s)
+    let config = ConnectionConfig::builder()
+        .rate_limit_messages(1_000_000)
+        .rate_limit_bytes(1 << 32)
+        .build();
+    let (mut client, mut server) = rt.block_on(setup_connection_pair_with_config(config));
+    // BestEffort, so no message waits for the receiver's ACKs
+    let streams: Vec<u32> = (0..STREAMS)
+        .map(|i| client.open_stream((i % 4) as u8, DeliveryMode::BestEffort).unwrap())
+        .collect();
+    let payload = vec![0x5Au8; MESSAGE_LEN];
+    // Keep the receiver drained so its socket never backs up
+    let drain = rt.spawn(async move {
+        loop {
+            if server.recv().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let single = locks_per_message(&rt, &mut client, |client| rt.block_on(send_loop(client, &streams, &payload)));
+    let batched = locks_per_message(&rt, &mut client, |client| rt.block_on(send_batched(client, &streams, &payload)));
+    println!("send_batch/send_on_stream: {:.2} send queue locks/message", single);
+    println!("send_batch/send_batch: {:.2} send queue locks/message", batched);
+
+    let mut group = c.benchmark_group("send_batch");
+    group.bench_function("send_on_stream", |b| b.iter(|| rt.block_on(send_loop(&mut client, &streams, &payload))));
+    group.bench_function("send_batch", |b| b.iter(|| rt.block_on(send_batched(&mut client, &streams, &payload))));
+    group.finish();
+    drain.abort();
+}
+
+criterion_group!(benches, send_batch_benchmark);
+criterion_main!(benches);
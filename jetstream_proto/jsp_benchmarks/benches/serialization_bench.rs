@@ -51,6 +51,9 @@ fn handshake_benchmark(c: &mut Criterion) {
         timestamp: 88888,
         connection_id: ConnectionId::from_u64(54321),
         supported_formats: vec![0, 1],
+        session_ticket: None,
+        ratchet_public_key: None,
+        retry_token: None,
     };
 
     group.bench_function("serialize_client_hello", |b| {
@@ -75,6 +78,8 @@ fn handshake_benchmark(c: &mut Criterion) {
         kyber_ciphertext: vec![6u8; 100],
         connection_id: ConnectionId::from_u64(98765),
         selected_format: 1,
+        resumed: false,
+        ratchet_public_key: None,
     };
 
     group.bench_function("serialize_server_hello", |b| {
@@ -0,0 +1,86 @@
+//! UDP I/O: one syscall per datagram against sendmmsg/recvmmsg batches,
+//! and batches with segmentation offload (GSO/GRO)
+//!
+//! Each iteration moves a batch of datagrams over loopback. Without
+//! batching support (anything but Linux) both paths make one syscall per
+//! datagram and should measure the same; without kernel offload the `gso`
+//! path measures the same as `batched`.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use jsp_transport::udp::{RecvMeta, UdpTransport, GRO_BUFFER_SIZE};
+use std::net::SocketAddr;
+use tokio::runtime::Runtime;
+
+/// Datagrams per iteration; the default `io_batch_size`
+const DATAGRAMS: usize = 32;
+const DATAGRAM_SIZE: usize = 1200;
+
+async fn per_datagram(tx: &UdpTransport, rx: &UdpTransport, to: SocketAddr, payload: &[u8]) {
+    for _ in 0..DATAGRAMS {
+        tx.send_to(payload, to).await.unwrap();
+    }
+    let mut buf = [0u8; 2048];
+    for _ in 0..DATAGRAMS {
+        rx.recv_from(&mut buf).await.unwrap();
+    }
+}
+
+async fn batched(tx: &UdpTransport, rx: &UdpTransport, packets: &[(Vec<u8>, SocketAddr)], bufs: &mut [Vec<u8>], meta: &mut [RecvMeta]) {
+    tx.send_batch(packets).await.unwrap();
+    let mut received = 0;
+    while received < DATAGRAMS {
+        received += rx.recv_batch(bufs, meta).await.unwrap();
+    }
+}
+
+/// Same as `batched`, but a GRO read holds several datagrams
+async fn offloaded(tx: &UdpTransport, rx: &UdpTransport, packets: &[(Vec<u8>, SocketAddr)], bufs: &mut [Vec<u8>], meta: &mut [RecvMeta]) {
+    tx.send_batch(packets).await.unwrap();
+    let mut received = 0;
+    while received < DATAGRAMS {
+        let count = rx.recv_batch(bufs, meta).await.unwrap();
+        received += meta[..count].iter().map(|meta| meta.segments().count()).sum::<usize>();
+    }
+}
+
+fn udp_batch_benchmark(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let (tx, rx) = rt.block_on(async {
+        (
+            UdpTransport::bind("127.0.0.1:0").await.unwrap(),
+            UdpTransport::bind("127.0.0.1:0").await.unwrap(),
+        )
+    });
+    let to = rx.local_addr().unwrap();
+    let payload = vec![0xA5u8; DATAGRAM_SIZE];
+    let packets: Vec<(Vec<u8>, SocketAddr)> = (0..DATAGRAMS).map(|_| (payload.clone(), to)).collect();
+    let mut bufs = vec![vec![0u8; 2048]; DATAGRAMS];
+    let mut meta = vec![RecvMeta::default(); DATAGRAMS];
+
+    let (gso_tx, gro_rx) = rt.block_on(async {
+        (
+            UdpTransport::bind("127.0.0.1:0").await.unwrap(),
+            UdpTransport::bind("127.0.0.1:0").await.unwrap(),
+        )
+    });
+    println!("udp_batch/gso: gso {}, gro {}", gso_tx.enable_gso(), gro_rx.enable_gro());
+    let gro_to = gro_rx.local_addr().unwrap();
+    let gso_packets: Vec<(Vec<u8>, SocketAddr)> = (0..DATAGRAMS).map(|_| (payload.clone(), gro_to)).collect();
+    let mut gro_bufs = vec![vec![0u8; GRO_BUFFER_SIZE]; DATAGRAMS];
+
+    let mut group = c.benchmark_group("udp_batch");
+    group.throughput(Throughput::Elements(DATAGRAMS as u64));
+    group.bench_function("per_datagram", |b| {
+        b.iter(|| rt.block_on(per_datagram(&tx, &rx, to, &payload)));
+    });
+    group.bench_function("batched", |b| {
+        b.iter(|| rt.block_on(batched(&tx, &rx, &packets, &mut bufs, &mut meta)));
+    });
+    group.bench_function("gso", |b| {
+        b.iter(|| rt.block_on(offloaded(&gso_tx, &gro_rx, &gso_packets, &mut gro_bufs, &mut meta)));
+    });
+    group.finish();
+}
+
+criterion_group!(benches, udp_batch_benchmark);
+criterion_main!(benches);
@@ -8,6 +8,20 @@
 #include <stdint.h>
 #include <stdlib.h>
 
+/**
+ * Close reasons, also reported in `JspEvent::close_reason`
+ */
+typedef enum JspCloseReason {
+  Normal = 0,
+  GoingAway = 1,
+  ProtocolError = 2,
+  Timeout = 3,
+  RateLimitExceeded = 4,
+  InternalError = 5,
+  AuthenticationFailed = 6,
+  TooManyStreams = 7,
+} JspCloseReason;
+
 /**
  * Delivery modes
  */
@@ -29,16 +43,67 @@ typedef enum JspError {
   ReceiveFailed = 5,
   InvalidMode = 6,
   NotConnected = 7,
+  BufferTooSmall = 8,
+  RuntimeStarted = 9,
 } JspError;
 
+/**
+ * Connection event kinds
+ */
+typedef enum JspEventKind {
+  DataReceived = 0,
+  PeerMigrated = 1,
+  PublicAddressDiscovered = 2,
+  Closed = 3,
+  StreamFinished = 4,
+  Rebound = 5,
+  NetworkChanged = 6,
+  CircuitStateChanged = 7,
+} JspEventKind;
+
 /**
  * Opaque connection handle
  */
 typedef struct JspConnection JspConnection;
 
+/**
+ * Connection event
+ *
+ * The event payload is written to the caller's buffer: stream data for
+ * DataReceived, the new address as text for PeerMigrated,
+ * PublicAddressDiscovered and Rebound, the close message (if any) for Closed,
+ * the new network type ("wifi", "cellular", ...) for NetworkChanged and the
+ * new circuit breaker state ("closed", "open" or "half_open") for
+ * CircuitStateChanged.
+ */
+typedef struct JspEvent {
+  enum JspEventKind kind;
+  /**
+   * Stream ID for DataReceived and StreamFinished
+   */
+  unsigned int stream_id;
+  /**
+   * Payload length in bytes
+   */
+  uintptr_t data_len;
+  /**
+   * Close reason code for Closed, one of `JspCloseReason`
+   */
+  unsigned int close_reason;
+} JspEvent;
+
+/**
+ * Set the number of worker threads all connections share
+ * @param threads - Worker threads, or 0 for one per CPU core (the default)
+ * @return Error code; RuntimeStarted once a connection was created
+ */
+enum JspError jsp_set_worker_threads(uintptr_t threads);
+
 /**
  * Create a new connection
  * Returns NULL on failure
+ *
+ * The first connection starts the runtime that every connection shares.
  */
 struct JspConnection *jsp_connection_new(void);
 
@@ -90,6 +155,20 @@ enum JspError jsp_connection_send(struct JspConnection *conn,
                                   const uint8_t *data,
                                   uintptr_t len);
 
+/**
+ * Wait for the next connection event
+ * @param conn - Connection handle
+ * @param event_out - Output parameter for the event
+ * @param buf - Buffer receiving the event payload
+ * @param buf_len - Buffer length
+ * @return Error code; BufferTooSmall leaves the event queued and sets
+ *         event_out->data_len to the required length
+ */
+enum JspError jsp_connection_next_event(struct JspConnection *conn,
+                                        struct JspEvent *event_out,
+                                        uint8_t *buf,
+                                        uintptr_t buf_len);
+
 /**
  * Close connection
  * @param conn - Connection handle
@@ -97,6 +176,17 @@ enum JspError jsp_connection_send(struct JspConnection *conn,
  */
 enum JspError jsp_connection_close(struct JspConnection *conn);
 
+/**
+ * Close connection, telling the peer why
+ * @param conn - Connection handle
+ * @param reason - Close reason
+ * @param message - Human-readable message (null-terminated string), or NULL for none
+ * @return Error code
+ */
+enum JspError jsp_connection_close_with_reason(struct JspConnection *conn,
+                                               enum JspCloseReason reason,
+                                               const char *message);
+
 /**
  * Free connection
  * @param conn - Connection handle
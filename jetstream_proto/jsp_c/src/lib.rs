@@ -1,13 +1,18 @@
 use std::ffi::CStr;
 use std::os::raw::{c_char, c_uint, c_ulonglong};
 use std::ptr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use jsp_core::types::control::CloseReason;
+use jsp_transport::error::TransportError;
+use jsp_transport::events::ConnectionEvent;
 use tokio::runtime::Runtime;
 
 /// Opaque connection handle
 pub struct JspConnection {
     inner: Arc<tokio::sync::Mutex<Option<jsp_transport::connection::Connection>>>,
-    runtime: Arc<Runtime>,
+    runtime: &'static Runtime,
+    /// Event that did not fit the caller's buffer, returned by the next poll
+    pending_event: Mutex<Option<ConnectionEvent>>,
 }
 
 /// Error codes
@@ -21,6 +26,21 @@ pub enum JspError {
     ReceiveFailed = 5,
     InvalidMode = 6,
     NotConnected = 7,
+    BufferTooSmall = 8,
+    RuntimeStarted = 9,
+}
+
+impl JspError {
+    /// The code for a failed call: failures with a code of their own get
+    /// it, the rest `fallback`, the code of the call that failed
+    fn from_transport(error: &TransportError, fallback: JspError) -> JspError {
+        match error {
+            TransportError::NotConnected | TransportError::ConnectionClosed { .. } => JspError::NotConnected,
+            TransportError::HandshakeTimeout | TransportError::Crypto(_) => JspError::HandshakeFailed,
+            TransportError::InvalidAddress(_) => JspError::ConnectionFailed,
+            _ => fallback,
+        }
+    }
 }
 
 /// Delivery modes
@@ -31,18 +51,93 @@ pub enum JspDeliveryMode {
     PartiallyReliable = 2,
 }
 
+/// Close reasons, also reported in `JspEvent::close_reason`
+#[repr(C)]
+pub enum JspCloseReason {
+    Normal = 0,
+    GoingAway = 1,
+    ProtocolError = 2,
+    Timeout = 3,
+    RateLimitExceeded = 4,
+    InternalError = 5,
+    AuthenticationFailed = 6,
+    TooManyStreams = 7,
+}
+
+impl From<JspCloseReason> for CloseReason {
+    fn from(reason: JspCloseReason) -> Self {
+        match reason {
+            JspCloseReason::Normal => CloseReason::Normal,
+            JspCloseReason::GoingAway => CloseReason::GoingAway,
+            JspCloseReason::ProtocolError => CloseReason::ProtocolError,
+            JspCloseReason::Timeout => CloseReason::Timeout,
+            JspCloseReason::RateLimitExceeded => CloseReason::RateLimitExceeded,
+            JspCloseReason::InternalError => CloseReason::InternalError,
+            JspCloseReason::AuthenticationFailed => CloseReason::AuthenticationFailed,
+            JspCloseReason::TooManyStreams => CloseReason::TooManyStreams,
+        }
+    }
+}
+
+/// Connection event kinds
+#[repr(C)]
+pub enum JspEventKind {
+    DataReceived = 0,
+    PeerMigrated = 1,
+    PublicAddressDiscovered = 2,
+    Closed = 3,
+    StreamFinished = 4,
+    Rebound = 5,
+    NetworkChanged = 6,
+    CircuitStateChanged = 7,
+}
+
+/// Connection event
+///
+/// The event payload is written to the caller's buffer: stream data for
+/// DataReceived, the new address as text for PeerMigrated,
+/// PublicAddressDiscovered and Rebound, the close message (if any) for Closed,
+/// the new network type ("wifi", "cellular", ...) for NetworkChanged and the
+/// new circuit breaker state ("closed", "open" or "half_open") for
+/// CircuitStateChanged.
+#[repr(C)]
+pub struct JspEvent {
+    pub kind: JspEventKind,
+    /// Stream ID for DataReceived and StreamFinished
+    pub stream_id: c_uint,
+    /// Payload length in bytes
+    pub data_len: usize,
+    /// Close reason code for Closed, one of `JspCloseReason`
+    pub close_reason: c_uint,
+}
+
+/// Set the number of worker threads all connections share
+/// @param threads - Worker threads, or 0 for one per CPU core (the default)
+/// @return Error code; RuntimeStarted once a connection was created
+#[no_mangle]
+pub extern "C" fn jsp_set_worker_threads(threads: usize) -> JspError {
+    if jsp_transport::runtime::set_worker_threads(threads) {
+        JspError::Success
+    } else {
+        JspError::RuntimeStarted
+    }
+}
+
 /// Create a new connection
 /// Returns NULL on failure
+///
+/// The first connection starts the runtime that every connection shares.
 #[no_mangle]
 pub extern "C" fn jsp_connection_new() -> *mut JspConnection {
-    let runtime = match Runtime::new() {
-        Ok(rt) => Arc::new(rt),
+    let runtime = match jsp_transport::runtime::shared() {
+        Ok(runtime) => runtime,
         Err(_) => return ptr::null_mut(),
     };
 
     let conn = Box::new(JspConnection {
         inner: Arc::new(tokio::sync::Mutex::new(None)),
         runtime,
+        pending_event: Mutex::new(None),
     });
 
     Box::into_raw(conn)
@@ -69,7 +164,7 @@ pub extern "C" fn jsp_connection_connect(
         }
     };
 
-    let runtime = conn.runtime.clone();
+    let runtime = conn.runtime;
     let result = runtime.block_on(async {
         jsp_transport::connection::Connection::connect_with_config(
             addr_str,
@@ -83,7 +178,7 @@ pub extern "C" fn jsp_connection_connect(
             *conn.inner.blocking_lock() = Some(connection);
             JspError::Success
         }
-        Err(_) => JspError::ConnectionFailed,
+        Err(e) => JspError::from_transport(&e, JspError::ConnectionFailed),
     }
 }
 
@@ -98,19 +193,19 @@ pub extern "C" fn jsp_connection_handshake(conn: *mut JspConnection) -> JspError
 
     let conn = unsafe { &*conn };
     let inner = conn.inner.clone();
-    let runtime = conn.runtime.clone();
+    let runtime = conn.runtime;
 
     let result = runtime.block_on(async {
         let mut connection = inner.lock().await;
         match connection.as_mut() {
             Some(conn) => conn.handshake().await,
-            None => Err(anyhow::anyhow!("Not connected")),
+            None => Err(TransportError::NotConnected),
         }
     });
 
     match result {
         Ok(_) => JspError::Success,
-        Err(_) => JspError::HandshakeFailed,
+        Err(e) => JspError::from_transport(&e, JspError::HandshakeFailed),
     }
 }
 
@@ -125,7 +220,7 @@ pub extern "C" fn jsp_connection_session_id(conn: *const JspConnection) -> c_ulo
 
     let conn = unsafe { &*conn };
     let inner = conn.inner.clone();
-    let runtime = conn.runtime.clone();
+    let runtime = conn.runtime;
 
     runtime.block_on(async {
         let connection = inner.lock().await;
@@ -160,13 +255,13 @@ pub extern "C" fn jsp_connection_open_stream(
 
     let conn = unsafe { &*conn };
     let inner = conn.inner.clone();
-    let runtime = conn.runtime.clone();
+    let runtime = conn.runtime;
 
     let result = runtime.block_on(async {
         let mut connection = inner.lock().await;
         match connection.as_mut() {
             Some(conn) => conn.open_stream(priority as u8, delivery_mode),
-            None => Err(anyhow::anyhow!("Not connected")),
+            None => Err(TransportError::NotConnected),
         }
     });
 
@@ -175,7 +270,7 @@ pub extern "C" fn jsp_connection_open_stream(
             unsafe { *stream_id_out = stream_id };
             JspError::Success
         }
-        Err(_) => JspError::NotConnected,
+        Err(e) => JspError::from_transport(&e, JspError::NotConnected),
     }
 }
 
@@ -199,20 +294,108 @@ pub extern "C" fn jsp_connection_send(
     let conn = unsafe { &*conn };
     let data_slice = unsafe { std::slice::from_raw_parts(data, len) };
     let inner = conn.inner.clone();
-    let runtime = conn.runtime.clone();
+    let runtime = conn.runtime;
 
     let result = runtime.block_on(async {
         let mut connection = inner.lock().await;
         match connection.as_mut() {
             Some(conn) => conn.send_on_stream(stream_id, data_slice).await,
-            None => Err(anyhow::anyhow!("Not connected")),
+            None => Err(TransportError::NotConnected),
         }
     });
 
     match result {
         Ok(_) => JspError::Success,
-        Err(_) => JspError::SendFailed,
+        Err(e) => JspError::from_transport(&e, JspError::SendFailed),
+    }
+}
+
+/// Wait for the next connection event
+/// @param conn - Connection handle
+/// @param event_out - Output parameter for the event
+/// @param buf - Buffer receiving the event payload
+/// @param buf_len - Buffer length
+/// @return Error code; BufferTooSmall leaves the event queued and sets
+///         event_out->data_len to the required length
+#[no_mangle]
+pub extern "C" fn jsp_connection_next_event(
+    conn: *mut JspConnection,
+    event_out: *mut JspEvent,
+    buf: *mut u8,
+    buf_len: usize,
+) -> JspError {
+    if conn.is_null() || event_out.is_null() || (buf.is_null() && buf_len > 0) {
+        return JspError::NullPointer;
+    }
+
+    let conn = unsafe { &*conn };
+    let stashed = conn.pending_event.lock().unwrap().take();
+    let event = match stashed {
+        Some(event) => event,
+        None => {
+            let inner = conn.inner.clone();
+            let runtime = conn.runtime;
+
+            let result = runtime.block_on(async {
+                let mut connection = inner.lock().await;
+                match connection.as_mut() {
+                    Some(conn) => conn.next_event().await,
+                    None => Err(TransportError::NotConnected),
+                }
+            });
+
+            match result {
+                Ok(event) => event,
+                Err(e) => return JspError::from_transport(&e, JspError::ReceiveFailed),
+            }
+        }
+    };
+
+    let (kind, stream_id, close_reason, payload): (JspEventKind, u32, u32, Vec<u8>) = match &event {
+        ConnectionEvent::DataReceived { stream_id, data } => {
+            (JspEventKind::DataReceived, *stream_id, 0, data.to_vec())
+        }
+        ConnectionEvent::PeerMigrated { new, .. } => {
+            (JspEventKind::PeerMigrated, 0, 0, new.to_string().into_bytes())
+        }
+        ConnectionEvent::PublicAddressDiscovered(addr) => {
+            (JspEventKind::PublicAddressDiscovered, 0, 0, addr.to_string().into_bytes())
+        }
+        ConnectionEvent::Closed { reason, message } => (
+            JspEventKind::Closed,
+            0,
+            reason.code(),
+            message.clone().unwrap_or_default().into_bytes(),
+        ),
+        ConnectionEvent::StreamFinished(stream_id) => {
+            (JspEventKind::StreamFinished, *stream_id, 0, Vec::new())
+        }
+        ConnectionEvent::Rebound { new_local, .. } => {
+            (JspEventKind::Rebound, 0, 0, new_local.to_string().into_bytes())
+        }
+        ConnectionEvent::NetworkChanged { new, .. } => {
+            (JspEventKind::NetworkChanged, 0, 0, new.as_str().as_bytes().to_vec())
+        }
+        ConnectionEvent::CircuitStateChanged { new, .. } => {
+            (JspEventKind::CircuitStateChanged, 0, 0, new.as_str().as_bytes().to_vec())
+        }
+    };
+
+    let event_out = unsafe { &mut *event_out };
+    event_out.kind = kind;
+    event_out.stream_id = stream_id;
+    event_out.data_len = payload.len();
+    event_out.close_reason = close_reason;
+
+    if payload.len() > buf_len {
+        *conn.pending_event.lock().unwrap() = Some(event);
+        return JspError::BufferTooSmall;
+    }
+
+    if !payload.is_empty() {
+        unsafe { ptr::copy_nonoverlapping(payload.as_ptr(), buf, payload.len()) };
     }
+    JspError::Success
 }
 
 /// Close connection
@@ -224,27 +407,47 @@ pub extern "C" fn jsp_connection_close(conn: *mut JspConnection) -> JspError {
         return JspError::NullPointer;
     }
 
-    let conn = unsafe { &*conn };
+    close_connection(unsafe { &*conn }, CloseReason::Normal, Some("Closed by C API".to_string()))
+}
+
+/// Close connection, telling the peer why
+/// @param conn - Connection handle
+/// @param reason - Close reason
+/// @param message - Human-readable message (null-terminated string), or NULL for none
+/// @return Error code
+#[no_mangle]
+pub extern "C" fn jsp_connection_close_with_reason(
+    conn: *mut JspConnection,
+    reason: JspCloseReason,
+    message: *const c_char,
+) -> JspError {
+    if conn.is_null() {
+        return JspError::NullPointer;
+    }
+
+    let message = if message.is_null() {
+        None
+    } else {
+        Some(unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned())
+    };
+    close_connection(unsafe { &*conn }, reason.into(), message)
+}
+
+fn close_connection(conn: &JspConnection, reason: CloseReason, message: Option<String>) -> JspError {
     let inner = conn.inner.clone();
-    let runtime = conn.runtime.clone();
+    let runtime = conn.runtime;
 
     let result = runtime.block_on(async {
         let mut connection = inner.lock().await;
         match connection.as_mut() {
-            Some(conn) => {
-                conn.close(
-                    jsp_core::types::control::CloseReason::Normal,
-                    Some("Closed by C API".to_string()),
-                )
-                .await
-            }
+            Some(conn) => conn.close(reason, message).await,
             None => Ok(()),
         }
     });
 
     match result {
         Ok(_) => JspError::Success,
-        Err(_) => JspError::SendFailed,
+        Err(e) => JspError::from_transport(&e, JspError::SendFailed),
     }
 }
 
@@ -273,6 +476,8 @@ pub extern "C" fn jsp_error_message(error: JspError) -> *const c_char {
         JspError::ReceiveFailed => "Receive failed\0",
         JspError::InvalidMode => "Invalid delivery mode\0",
         JspError::NotConnected => "Not connected\0",
+        JspError::BufferTooSmall => "Buffer too small\0",
+        JspError::RuntimeStarted => "Runtime already started\0",
     };
 
     msg.as_ptr() as *const c_char
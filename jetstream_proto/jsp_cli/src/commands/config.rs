@@ -1,92 +1,113 @@
 use anyhow::Result;
 use colored::Colorize;
-use serde::{Serialize, Deserialize};
+use jsp_transport::config::{ConnectionConfig, ServerConfig};
+use jsp_transport::duration_format;
 
 
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Config {
-    server_address: String,
-    session_timeout_secs: u64,
-    heartbeat_interval_secs: u64,
-    max_streams: usize,
-    rate_limit_messages: usize,
-    enable_compression: bool,
-    enable_encryption: bool,
-}
+/// Write the default connection config, or with `server` the default
+/// server config around it
+pub fn generate(output: &str, server: bool) -> Result<()> {
+    println!("{}", "Generating default configuration...".bold());
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            server_address: "127.0.0.1:8080".to_string(),
-            session_timeout_secs: 300,
-            heartbeat_interval_secs: 30,
-            max_streams: 100,
-            rate_limit_messages: 1000,
-            enable_compression: true,
-            enable_encryption: true,
-        }
+    // Format follows the extension (.json, .yaml or .yml)
+    if server {
+        ServerConfig::default().to_file(output)?;
+    } else {
+        ConnectionConfig::default().to_file(output)?;
     }
-}
 
-pub fn generate(output: &str) -> Result<()> {
-    println!("{}", "Generating default configuration...".bold());
-    
-    let config = Config::default();
-    let json = serde_json::to_string_pretty(&config)?;
-    
-    std::fs::write(output, json)?;
-    
     println!("{} {}", "✓ Configuration saved to:".green(), output.cyan());
     Ok(())
 }
 
-pub fn validate(file: &str) -> Result<()> {
+/// Parse `file` as a connection config, or with `server` a server config,
+/// and check it; a multi-hop chain is checked hop by hop
+pub fn validate(file: &str, server: bool) -> Result<()> {
     println!("{} {}", "Validating configuration:".bold(), file.cyan());
-    
-    let content = std::fs::read_to_string(file)?;
-    let config: Config = serde_json::from_str(&content)?;
-    
-    // Validate fields
-    let mut valid = true;
-    
-    if config.session_timeout_secs == 0 {
-        println!("{} Session timeout must be > 0", "✗".red());
-        valid = false;
-    }
-    
-    if config.heartbeat_interval_secs == 0 {
-        println!("{} Heartbeat interval must be > 0", "✗".red());
-        valid = false;
-    }
-    
-    if config.max_streams == 0 {
-        println!("{} Max streams must be > 0", "✗".red());
-        valid = false;
-    }
-    
-    if valid {
-        println!("{} Configuration is valid", "✓".green());
+
+    // Loading runs the same checks as `validate` on the config
+    let loaded = if server {
+        ServerConfig::from_file(file).map(drop)
     } else {
-        anyhow::bail!("Configuration validation failed");
+        ConnectionConfig::from_file(file).map(drop)
+    };
+    match loaded {
+        Ok(()) => {
+            println!("{} Configuration is valid", "✓".green());
+            Ok(())
+        }
+        Err(e) => {
+            println!("{} {:#}", "✗".red(), e);
+            anyhow::bail!("Configuration validation failed");
+        }
     }
-    
-    Ok(())
 }
 
-pub fn show() -> Result<()> {
+pub fn show(file: Option<&str>) -> Result<()> {
     println!("{}", "Current Configuration".bold().green());
     println!("{}", "=".repeat(50));
-    
-    let config = Config::default();
-    
-    println!("Server Address: {}", config.server_address.cyan());
-    println!("Session Timeout: {} seconds", config.session_timeout_secs.to_string().yellow());
-    println!("Heartbeat Interval: {} seconds", config.heartbeat_interval_secs.to_string().yellow());
+
+    let config = match file {
+        Some(file) => ConnectionConfig::from_file(file)?,
+        None => ConnectionConfig::default(),
+    };
+
+    println!("Source: {}", file.unwrap_or("built-in defaults").cyan());
+    println!("Connect Strategy: {}", format!("{:?}", config.connect_strategy).cyan());
+    println!("Session Timeout: {}", duration_format::format(config.session_timeout).yellow());
+    println!("Heartbeat Interval: {}", duration_format::format(config.heartbeat_interval).yellow());
+    println!("Connect Timeout: {}", duration_format::format(config.connect_timeout).yellow());
     println!("Max Streams: {}", config.max_streams.to_string().yellow());
-    println!("Rate Limit: {} messages/sec", config.rate_limit_messages.to_string().yellow());
-    println!("Compression: {}", if config.enable_compression { "Enabled".green() } else { "Disabled".red() });
-    println!("Encryption: {}", if config.enable_encryption { "Enabled".green() } else { "Disabled".red() });
-    
+    println!("Rate Limit: {} messages/sec, {} bytes/sec", config.rate_limit_messages.to_string().yellow(), config.rate_limit_bytes.to_string().yellow());
+    println!("Pool Capacity: {}", config.pool_capacity.to_string().yellow());
+    println!("ACK Batching: {} ACKs or {}ms", config.ack_batch_size.to_string().yellow(), config.ack_batch_timeout_ms.to_string().yellow());
+    println!("Header Compression: {}", if config.enable_header_compression { "Enabled".green() } else { "Disabled".red() });
+    println!("Double Ratchet: {}", if config.enable_double_ratchet { "Enabled".green() } else { "Disabled".red() });
+    println!("MPTCP: {}", if config.mptcp_config.enabled { "Enabled".green() } else { "Disabled".red() });
+    println!("Multi-hop: {}", if config.multihop_config.is_some() { "Configured".green() } else { "Disabled".red() });
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_configs_validate() {
+        let dir = std::env::temp_dir().join(format!("jsp-cli-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for (name, server) in [("connection.yaml", false), ("connection.json", false), ("server.yaml", true), ("server.json", true)] {
+            let path = dir.join(name);
+            let path = path.to_str().unwrap();
+            generate(path, server).unwrap();
+            validate(path, server).unwrap();
+        }
+
+        // The server config nests the connection settings
+        let server = ServerConfig::from_file(dir.join("server.yaml")).unwrap();
+        assert_eq!(server.connection.session_timeout, ConnectionConfig::default().session_timeout);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate_checks_multihop_chain() {
+        let dir = std::env::temp_dir().join(format!("jsp-cli-multihop-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("connection.yaml");
+        std::fs::write(&path, "multihop_config:\n  enabled: true\n  chain: []\n").unwrap();
+        assert!(validate(path.to_str().unwrap(), false).is_err());
+        let err = ConnectionConfig::from_file(&path).unwrap_err().to_string();
+        assert!(err.contains("`multihop_config`"), "{}", err);
+
+        let path = dir.join("server.yaml");
+        std::fs::write(&path, "connection:\n  multihop_config:\n    enabled: true\n    chain: []\n").unwrap();
+        assert!(validate(path.to_str().unwrap(), true).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
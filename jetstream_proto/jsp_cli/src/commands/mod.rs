@@ -2,3 +2,5 @@ pub mod monitor;
 pub mod profile;
 pub mod config;
 pub mod send;
+pub mod serve;
+pub mod signaling;
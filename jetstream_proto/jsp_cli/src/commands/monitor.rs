@@ -1,48 +1,265 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use colored::Colorize;
+use serde::Serialize;
+use std::io::Write;
+use std::net::SocketAddr;
 use std::time::Duration;
-use tokio::time;
+use jsp_core::types::control::CloseReason;
+use jsp_core::types::delivery::DeliveryMode;
 use jsp_transport::connection::Connection;
 use jsp_transport::config::ConnectionConfig;
+use jsp_transport::metrics::MetricsSnapshot;
+use jsp_transport::stats_stream::{StatsFrame, STATS_STREAM_ID};
 
-pub async fn run(addr: &str, interval_secs: u64) -> Result<()> {
-    println!("{}", "JetStreamProto Connection Monitor".bold().green());
-    println!("{}", "=".repeat(50));
-    println!("Connecting to: {}", addr.cyan());
-    println!();
+/// How long to wait for a stats frame before giving up on the server
+const FRAME_TIMEOUT: Duration = Duration::from_secs(10);
 
-    // Connect
+/// Clears the terminal and moves the cursor home, so each table replaces the last
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// How `monitor` shows the frames it receives
+#[derive(Debug, Clone, Default)]
+pub struct MonitorOptions {
+    /// Print one JSON object per frame instead of a table
+    pub json: bool,
+    /// Stop after this many frames (None = until Ctrl-C)
+    pub frames: Option<usize>,
+}
+
+/// A session as the monitor shows it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SessionRow {
+    /// 0 for gateway flows
+    pub session_id: u64,
+    pub peer: SocketAddr,
+    pub backend: Option<SocketAddr>,
+    pub rtt_p50_ms: f64,
+    pub rtt_p95_ms: f64,
+    /// Bytes per second both ways since the previous frame (None on a session's first)
+    pub throughput_bps: Option<f64>,
+    /// Packets retransmitted plus duplicates received
+    pub retransmits: u64,
+    /// Packets sent and not acknowledged yet
+    pub queue_depth: u64,
+}
+
+/// A stats frame with rates worked out against the previous one
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MonitorFrame {
+    pub timestamp_ms: u64,
+    pub totals: MetricsSnapshot,
+    pub sessions: Vec<SessionRow>,
+    /// Sessions the server left out of the frame
+    pub omitted_sessions: usize,
+}
+
+impl MonitorFrame {
+    pub fn new(frame: &StatsFrame, previous: Option<&StatsFrame>) -> Self {
+        let elapsed_secs = previous
+            .map(|previous| frame.timestamp_ms.saturating_sub(previous.timestamp_ms) as f64 / 1000.0)
+            .filter(|secs| *secs > 0.0);
+        let sessions = frame.sessions.iter()
+            .map(|session| {
+                let before = previous.and_then(|previous| previous.sessions.iter()
+                    .find(|before| before.session_id == session.session_id && before.peer == session.peer));
+                let throughput_bps = before.zip(elapsed_secs)
+                    .map(|(before, secs)| session.bytes_total().saturating_sub(before.bytes_total()) as f64 / secs);
+                SessionRow {
+                    session_id: session.session_id,
+                    peer: session.peer,
+                    backend: session.backend,
+                    rtt_p50_ms: session.metrics.rtt_p50_ms,
+                    rtt_p95_ms: session.metrics.rtt_p95_ms,
+                    throughput_bps,
+                    retransmits: session.metrics.packets_retransmitted + session.metrics.duplicate_packets_received,
+                    queue_depth: session.queue_depth,
+                }
+            })
+            .collect();
+        Self {
+            timestamp_ms: frame.timestamp_ms,
+            totals: frame.metrics,
+            sessions,
+            omitted_sessions: frame.omitted_sessions,
+        }
+    }
+}
+
+/// Show the stats `addr` streams until Ctrl-C
+///
+/// The server must have its stats stream on (`jsp-cli serve
+/// --monitor-interval-ms`, or `--stats-addr` on the gateway).
+pub async fn run(addr: &str, options: MonitorOptions) -> Result<()> {
+    let mut stdout = std::io::stdout();
+    tokio::select! {
+        result = monitor(addr, &options, &mut stdout) => result.map(|_| ()),
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    }
+}
+
+/// Subscribe to the stats stream of `addr` and write each frame to `out`
+///
+/// Returns the number of frames written.
+pub async fn monitor(addr: &str, options: &MonitorOptions, out: &mut impl Write) -> Result<usize> {
     let mut connection = Connection::connect_with_config(addr, ConnectionConfig::default()).await?;
-    
-    println!("{}", "✓ Connected successfully".green());
-    
-    // Perform handshake
     connection.handshake().await?;
-    println!("{}", "✓ Handshake completed".green());
-    println!();
-
-    let session_id = connection.session_id();
-    println!("Session ID: {}", session_id.to_string().yellow());
-    println!();
-
-    // Monitor loop
-    let interval = Duration::from_secs(interval_secs);
-    let mut counter = 0;
-
-    loop {
-        counter += 1;
-        
-        println!("{} {}", "Update".bold(), counter);
-        println!("  Status: {}", "Connected".green());
-        println!("  Session: {}", session_id.to_string().yellow());
-        println!("  Transport: {}", "UDP".cyan());
-        
-        // In real implementation, would show actual metrics
-        println!("  Throughput: {} MB/s", "5.2".green());
-        println!("  Latency: {} ms", "45".green());
-        println!("  Packet Loss: {}%", "0.1".green());
-        println!();
-
-        time::sleep(interval).await;
+    connection.open_reserved_stream(STATS_STREAM_ID, 0, DeliveryMode::Reliable)?;
+    connection.send_on_stream(STATS_STREAM_ID, b"subscribe").await?;
+
+    let mut previous: Option<StatsFrame> = None;
+    let mut written = 0;
+    while options.frames.is_none_or(|frames| written < frames) {
+        let frame = next_frame(&mut connection).await?;
+        let view = MonitorFrame::new(&frame, previous.as_ref());
+        if options.json {
+            writeln!(out, "{}", serde_json::to_string(&view)?)?;
+        } else {
+            render_table(addr, &view, out)?;
+        }
+        out.flush()?;
+        previous = Some(frame);
+        written += 1;
+    }
+
+    connection.close(CloseReason::Normal, None).await?;
+    Ok(written)
+}
+
+/// Latest stats frame from the server, skipping any that arrived together
+async fn next_frame(connection: &mut Connection) -> Result<StatsFrame> {
+    tokio::time::timeout(FRAME_TIMEOUT, async {
+        loop {
+            let latest = connection.recv().await?.into_iter()
+                .filter(|(stream_id, _)| *stream_id == STATS_STREAM_ID)
+                .last();
+            if let Some((_, data)) = latest {
+                return StatsFrame::from_bytes(&data).map_err(|e| anyhow!("Malformed stats frame: {}", e));
+            }
+            if let Some(close) = connection.peer_close() {
+                return Err(anyhow!("Server closed the connection: {:?}", close.reason_code));
+            }
+        }
+    }).await
+        .map_err(|_| anyhow!("No stats within {:?}; is the server's stats stream enabled?", FRAME_TIMEOUT))?
+}
+
+/// Redraw the session table in place
+fn render_table(addr: &str, frame: &MonitorFrame, out: &mut impl Write) -> Result<()> {
+    let totals = &frame.totals;
+    write!(out, "{}", CLEAR_SCREEN)?;
+    writeln!(out, "{} {}", "JetStreamProto Monitor".bold().green(), addr.cyan())?;
+    writeln!(
+        out,
+        "Sessions: {}  Sent: {} packets  Received: {} packets  Loss: {:.2}%",
+        frame.sessions.len() + frame.omitted_sessions,
+        totals.packets_sent,
+        totals.packets_received,
+        totals.loss_rate * 100.0
+    )?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "{:<10} {:<22} {:<22} {:>9} {:>9} {:>12} {:>6} {:>6}",
+        "SESSION", "PEER", "BACKEND", "RTT p50", "RTT p95", "THROUGHPUT", "RETX", "QUEUE"
+    )?;
+    for session in &frame.sessions {
+        writeln!(
+            out,
+            "{:<10} {:<22} {:<22} {:>9} {:>9} {:>12} {:>6} {:>6}",
+            if session.session_id == 0 { "-".to_string() } else { session.session_id.to_string() },
+            session.peer.to_string(),
+            session.backend.map(|backend| backend.to_string()).unwrap_or_else(|| "-".to_string()),
+            rtt(session.rtt_p50_ms),
+            rtt(session.rtt_p95_ms),
+            session.throughput_bps.map(rate).unwrap_or_else(|| "-".to_string()),
+            session.retransmits,
+            session.queue_depth
+        )?;
+    }
+    if frame.omitted_sessions > 0 {
+        writeln!(out, "... and {} quieter sessions", frame.omitted_sessions)?;
+    }
+    Ok(())
+}
+
+/// Milliseconds, or "-" before the first sample
+fn rtt(ms: f64) -> String {
+    if ms > 0.0 { format!("{:.1} ms", ms) } else { "-".to_string() }
+}
+
+/// Bytes per second in the largest fitting unit
+fn rate(bytes_per_sec: f64) -> String {
+    match bytes_per_sec {
+        b if b >= 1_000_000.0 => format!("{:.1} MB/s", b / 1_000_000.0),
+        b if b >= 1_000.0 => format!("{:.1} kB/s", b / 1_000.0),
+        b => format!("{:.0} B/s", b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::serve::{serve, ServeOptions};
+    use jsp_transport::config::ServerConfig;
+    use jsp_transport::server::Server;
+
+    #[test]
+    fn test_rate() {
+        assert_eq!(rate(512.0), "512 B/s");
+        assert_eq!(rate(12_345.0), "12.3 kB/s");
+        assert_eq!(rate(2_500_000.0), "2.5 MB/s");
+        assert_eq!(rtt(0.0), "-");
+        assert_eq!(rtt(1.26), "1.3 ms");
+    }
+
+    #[tokio::test]
+    async fn test_monitor_renders_live_sessions() -> Result<()> {
+        let config = ServerConfig {
+            stats_stream_interval: Some(Duration::from_millis(100)),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind_with_config("127.0.0.1:0", config).await?;
+        let addr = server.local_addr()?.to_string();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let options = ServeOptions { echo: true, ..ServeOptions::default() };
+        let serve_task = tokio::spawn(async move {
+            serve(server, &options, async { let _ = stop_rx.await; }).await
+        });
+
+        // A session with some traffic to report
+        let mut client = Connection::connect_with_config(&addr, ConnectionConfig::default()).await?;
+        client.handshake().await?;
+        let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+        client.send_on_stream(stream_id, b"traffic").await?;
+
+        // One frame of the table, as a terminal would get it
+        let mut table = Vec::new();
+        let options = MonitorOptions { frames: Some(1), ..MonitorOptions::default() };
+        assert_eq!(monitor(&addr, &options, &mut table).await?, 1);
+        let table = String::from_utf8(table)?;
+        assert!(table.starts_with(CLEAR_SCREEN), "{}", table);
+        assert!(table.contains("RTT p50") && table.contains("THROUGHPUT") && table.contains("QUEUE"), "{}", table);
+        let row = table.lines()
+            .find(|line| line.starts_with(&format!("{} ", client.session_id())))
+            .unwrap_or_else(|| panic!("no row for the client session:\n{}", table));
+        assert!(row.contains(&client.local_addr()?.to_string()), "{}", row);
+
+        // Two frames as JSON, the second with throughput
+        let mut json = Vec::new();
+        let options = MonitorOptions { json: true, frames: Some(2) };
+        assert_eq!(monitor(&addr, &options, &mut json).await?, 2);
+        let frames = String::from_utf8(json)?.lines()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(frames.len(), 2);
+        let session = frames[1]["sessions"].as_array().unwrap().iter()
+            .find(|session| session["session_id"] == client.session_id())
+            .unwrap();
+        assert!(session["throughput_bps"].is_number(), "{}", session);
+        assert!(frames[1]["totals"]["packets_received"].as_u64().unwrap() >= 1);
+
+        stop_tx.send(()).unwrap();
+        serve_task.await??;
+        Ok(())
     }
 }
@@ -1,81 +1,112 @@
 use anyhow::Result;
 use colored::Colorize;
 use serde::{Serialize, Deserialize};
-use std::time::{Duration, Instant};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+use jsp_core::types::control::CloseReason;
+use jsp_core::types::delivery::DeliveryMode;
 use jsp_transport::connection::Connection;
 use jsp_transport::config::ConnectionConfig;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ProfileReport {
-    duration_secs: u64,
-    total_bytes: u64,
-    avg_throughput_mbps: f64,
-    avg_latency_ms: f64,
-    packet_loss_percent: f64,
-    messages_sent: usize,
-}
+/// Version of the report layout; bumped when a field changes meaning or goes away
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
 
-pub async fn run(addr: &str, duration_secs: u64, output: Option<&str>) -> Result<()> {
-    println!("{}", "JetStreamProto Performance Profiler".bold().green());
-    println!("{}", "=".repeat(50));
-    println!("Target: {}", addr.cyan());
-    println!("Duration: {} seconds", duration_secs.to_string().yellow());
-    println!();
+/// How long to wait for outstanding echoes once sending stops
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
 
-    // Connect
-    println!("Connecting...");
-    let mut connection = Connection::connect_with_config(addr, ConnectionConfig::default()).await?;
-    connection.handshake().await?;
-    println!("{}", "✓ Connected".green());
-    println!();
+/// Bytes at the start of every probe that carry its sequence number
+const SEQUENCE_LEN: usize = 8;
 
-    // Open stream
-    let stream_id = connection.open_stream(1, jsp_core::types::delivery::DeliveryMode::Reliable)?;
-    println!("Stream ID: {}", stream_id.to_string().yellow());
-    println!();
+/// How `profile` drives the target
+#[derive(Debug, Clone)]
+pub struct ProfileOptions {
+    /// How long to send for
+    pub duration: Duration,
+    /// Probes per second
+    pub rate: u32,
+    /// Bytes per probe, at least the 8-byte sequence number
+    pub payload_size: usize,
+    /// Probes at the start left out of the report
+    pub warmup: usize,
+}
 
-    // Profile
-    println!("Profiling...");
-    let start = Instant::now();
-    let mut messages_sent = 0;
-    let mut total_bytes = 0u64;
-
-    let test_data = b"Test data for profiling performance of JetStreamProto connection";
-    
-    while start.elapsed() < Duration::from_secs(duration_secs) {
-        if let Ok(_) = connection.send_on_stream(stream_id, test_data).await {
-            messages_sent += 1;
-            total_bytes += test_data.len() as u64;
+impl Default for ProfileOptions {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(60),
+            rate: 100,
+            payload_size: 64,
+            warmup: 0,
         }
-        
-        // Small delay to avoid overwhelming
-        tokio::time::sleep(Duration::from_millis(10)).await;
     }
+}
 
-    let elapsed = start.elapsed();
-    
-    // Calculate metrics
-    let throughput_mbps = (total_bytes as f64 * 8.0) / (elapsed.as_secs_f64() * 1_000_000.0);
-    
-    let report = ProfileReport {
-        duration_secs: elapsed.as_secs(),
-        total_bytes,
-        avg_throughput_mbps: throughput_mbps,
-        avg_latency_ms: 45.0, // Simulated
-        packet_loss_percent: 0.1, // Simulated
-        messages_sent,
-    };
+/// Round-trip times of the echoed probes, in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LatencyReport {
+    pub min: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+/// What `profile` measured; written as JSON with `--output`
+///
+/// Warm-up probes are counted in none of the fields.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfileReport {
+    pub schema_version: u32,
+    pub target: String,
+    pub duration_secs: f64,
+    pub rate_pps: u32,
+    pub payload_bytes: usize,
+    pub warmup_packets: usize,
+    pub packets_sent: usize,
+    pub packets_received: usize,
+    /// Share of probes never echoed, from 0 to 1
+    pub loss_rate: f64,
+    /// Echoed payload bits per second, from the first probe sent to the last echo
+    pub throughput_mbps: f64,
+    /// None when no probe came back
+    pub latency_ms: Option<LatencyReport>,
+}
+
+pub async fn run(addr: &str, options: ProfileOptions, output: Option<&str>) -> Result<()> {
+    println!("{}", "JetStreamProto Performance Profiler".bold().green());
+    println!("{}", "=".repeat(50));
+    println!("Target: {}", addr.cyan());
+    println!("Duration: {} seconds", options.duration.as_secs().to_string().yellow());
+    println!("Rate: {} packets/s of {} bytes", options.rate.to_string().yellow(), options.payload_size.to_string().yellow());
+    if options.warmup > 0 {
+        println!("Warm-up: {} packets", options.warmup.to_string().yellow());
+    }
+    println!();
+
+    println!("Profiling against an echo server (`jsp-cli serve --echo`)...");
+    let report = profile(addr, &options).await?;
 
     // Display results
     println!();
     println!("{}", "Profile Results".bold().green());
     println!("{}", "=".repeat(50));
-    println!("Duration: {} seconds", report.duration_secs);
-    println!("Messages Sent: {}", report.messages_sent.to_string().yellow());
-    println!("Total Bytes: {} bytes", report.total_bytes.to_string().yellow());
-    println!("Avg Throughput: {:.2} Mbps", report.avg_throughput_mbps.to_string().green());
-    println!("Avg Latency: {:.2} ms", report.avg_latency_ms.to_string().green());
-    println!("Packet Loss: {:.2}%", report.packet_loss_percent.to_string().green());
+    println!("Duration: {:.1} seconds", report.duration_secs);
+    println!("Packets: {} sent, {} echoed", report.packets_sent.to_string().yellow(), report.packets_received.to_string().yellow());
+    println!("Throughput: {} Mbps", format!("{:.3}", report.throughput_mbps).green());
+    println!("Packet Loss: {}%", format!("{:.2}", report.loss_rate * 100.0).green());
+    match &report.latency_ms {
+        Some(latency) => println!(
+            "Latency: p50 {} ms, p90 {} ms, p99 {} ms (min {:.2}, max {:.2})",
+            format!("{:.2}", latency.p50).green(),
+            format!("{:.2}", latency.p90).green(),
+            format!("{:.2}", latency.p99).green(),
+            latency.min,
+            latency.max
+        ),
+        None => println!("Latency: {}", "no echoes received".red()),
+    }
 
     // Save to file if requested
     if let Some(output_file) = output {
@@ -87,3 +118,190 @@ pub async fn run(addr: &str, duration_secs: u64, output: Option<&str>) -> Result
 
     Ok(())
 }
+
+/// Send numbered probes to the echo server at `addr` at `options.rate` and
+/// time each one's round trip
+///
+/// Probes still unanswered `DRAIN_TIMEOUT` after the last send count as lost.
+pub async fn profile(addr: &str, options: &ProfileOptions) -> Result<ProfileReport> {
+    if options.rate == 0 {
+        anyhow::bail!("Rate must be at least 1 packet/s");
+    }
+    if options.payload_size < SEQUENCE_LEN {
+        anyhow::bail!("Payload size must be at least {} bytes", SEQUENCE_LEN);
+    }
+
+    let mut connection = Connection::connect_with_config(addr, ConnectionConfig::default()).await?;
+    connection.handshake().await?;
+    let stream_id = connection.open_stream(1, DeliveryMode::Reliable)?;
+
+    let period = Duration::from_secs_f64(1.0 / options.rate as f64);
+    let mut payload = vec![0xA5u8; options.payload_size];
+    let mut in_flight: HashMap<u64, Instant> = HashMap::new();
+    let mut rtts = Vec::new();
+    let mut sent = 0;
+    let mut received_bytes = 0u64;
+    // When the first counted probe went out and the last counted echo came back
+    let mut window: Option<(Instant, Instant)> = None;
+
+    let start = Instant::now();
+    let stop_sending = start + options.duration;
+    let mut next_send = start;
+    let mut sequence = 0u64;
+
+    loop {
+        let now = Instant::now();
+        if now < stop_sending && now >= next_send {
+            payload[..SEQUENCE_LEN].copy_from_slice(&sequence.to_be_bytes());
+            connection.send_on_stream(stream_id, &payload).await?;
+            in_flight.insert(sequence, now);
+            if sequence as usize >= options.warmup {
+                sent += 1;
+                window.get_or_insert((now, now));
+            }
+            sequence += 1;
+            // Late sends don't make up for lost time in a burst
+            next_send = (next_send + period).max(now);
+            continue;
+        }
+
+        let wake = if now < stop_sending {
+            next_send
+        } else if in_flight.is_empty() || now >= stop_sending + DRAIN_TIMEOUT {
+            break;
+        } else {
+            stop_sending + DRAIN_TIMEOUT
+        };
+        let packets = match tokio::time::timeout_at(wake, connection.recv()).await {
+            Ok(packets) => packets?,
+            Err(_) => continue,
+        };
+        let now = Instant::now();
+        for (_, data) in packets {
+            if data.len() < SEQUENCE_LEN {
+                continue;
+            }
+            let mut echoed = [0u8; SEQUENCE_LEN];
+            echoed.copy_from_slice(&data[..SEQUENCE_LEN]);
+            let echoed = u64::from_be_bytes(echoed);
+            if let Some(sent_at) = in_flight.remove(&echoed) {
+                if echoed as usize >= options.warmup {
+                    rtts.push(now.duration_since(sent_at).as_secs_f64() * 1000.0);
+                    received_bytes += data.len() as u64;
+                    if let Some((_, last)) = &mut window {
+                        *last = now;
+                    }
+                }
+            }
+        }
+    }
+
+    if let Err(e) = connection.close(CloseReason::Normal, None).await {
+        eprintln!("{} Failed to close connection: {}", "✗".red(), e);
+    }
+
+    let window_secs = window.map(|(first, last)| last.duration_since(first).as_secs_f64()).unwrap_or(0.0);
+    Ok(ProfileReport {
+        schema_version: REPORT_SCHEMA_VERSION,
+        target: addr.to_string(),
+        duration_secs: start.elapsed().as_secs_f64(),
+        rate_pps: options.rate,
+        payload_bytes: options.payload_size,
+        warmup_packets: options.warmup,
+        packets_sent: sent,
+        packets_received: rtts.len(),
+        loss_rate: if sent == 0 { 0.0 } else { 1.0 - rtts.len() as f64 / sent as f64 },
+        throughput_mbps: if window_secs > 0.0 { received_bytes as f64 * 8.0 / (window_secs * 1_000_000.0) } else { 0.0 },
+        latency_ms: latency_report(rtts),
+    })
+}
+
+/// Summary of round-trip samples; percentiles are nearest-rank
+fn latency_report(mut rtts: Vec<f64>) -> Option<LatencyReport> {
+    if rtts.is_empty() {
+        return None;
+    }
+    rtts.sort_by(f64::total_cmp);
+    let percentile = |p: f64| {
+        let rank = (p / 100.0 * rtts.len() as f64).ceil() as usize;
+        rtts[rank.clamp(1, rtts.len()) - 1]
+    };
+    Some(LatencyReport {
+        min: rtts[0],
+        mean: rtts.iter().sum::<f64>() / rtts.len() as f64,
+        p50: percentile(50.0),
+        p90: percentile(90.0),
+        p99: percentile(99.0),
+        max: rtts[rtts.len() - 1],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::serve::{serve, ServeOptions};
+    use jsp_transport::server::Server;
+
+    #[test]
+    fn test_latency_report_percentiles() {
+        assert_eq!(latency_report(Vec::new()), None);
+
+        let latency = latency_report((1..=100).rev().map(f64::from).collect()).unwrap();
+        assert_eq!(latency, LatencyReport { min: 1.0, mean: 50.5, p50: 50.0, p90: 90.0, p99: 99.0, max: 100.0 });
+
+        let single = latency_report(vec![3.0]).unwrap();
+        assert_eq!((single.p50, single.p99), (3.0, 3.0));
+    }
+
+    #[tokio::test]
+    async fn test_profile_against_echo_server() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0").await?;
+        let addr = server.local_addr()?.to_string();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let serve_options = ServeOptions { echo: true, ..ServeOptions::default() };
+        let serve_task = tokio::spawn(async move {
+            serve(server, &serve_options, async { let _ = stop_rx.await; }).await
+        });
+
+        let options = ProfileOptions {
+            duration: Duration::from_millis(500),
+            rate: 200,
+            payload_size: 100,
+            warmup: 10,
+        };
+        let report = profile(&addr, &options).await?;
+        stop_tx.send(()).unwrap();
+        let stats = serve_task.await??;
+
+        // The JSON has every documented field
+        let json: serde_json::Value = serde_json::to_value(&report)?;
+        for field in ["schema_version", "target", "duration_secs", "rate_pps", "payload_bytes", "warmup_packets",
+                      "packets_sent", "packets_received", "loss_rate", "throughput_mbps", "latency_ms"] {
+            assert!(json.get(field).is_some(), "missing `{}` in {}", field, json);
+        }
+        for field in ["min", "mean", "p50", "p90", "p99", "max"] {
+            assert!(json["latency_ms"][field].is_f64(), "missing `latency_ms.{}` in {}", field, json);
+        }
+        assert_eq!(json["schema_version"], REPORT_SCHEMA_VERSION);
+
+        // Warm-up probes were echoed but not counted
+        assert!(report.packets_sent > 0);
+        assert_eq!(stats.messages, report.packets_sent + options.warmup);
+        assert_eq!(report.packets_received, report.packets_sent);
+        assert_eq!(report.loss_rate, 0.0);
+        assert!(report.throughput_mbps > 0.0);
+
+        let latency = report.latency_ms.unwrap();
+        assert!(latency.min <= latency.p50, "{:?}", latency);
+        assert!(latency.p50 <= latency.p90, "{:?}", latency);
+        assert!(latency.p90 <= latency.p99, "{:?}", latency);
+        assert!(latency.p99 <= latency.max, "{:?}", latency);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_profile_rejects_short_payload() {
+        let options = ProfileOptions { payload_size: 4, ..ProfileOptions::default() };
+        assert!(profile("127.0.0.1:9", &options).await.is_err());
+    }
+}
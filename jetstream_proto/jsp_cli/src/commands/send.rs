@@ -2,8 +2,14 @@ use anyhow::Result;
 use colored::Colorize;
 use jsp_transport::connection::Connection;
 use jsp_transport::config::ConnectionConfig;
+use std::time::{Duration, Instant};
 
-pub async fn run(addr: &str, message: &str, count: usize) -> Result<()> {
+/// How long to wait for each echo with `--echo`
+const ECHO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Send `count` numbered copies of `message`; with `echo`, wait for each to
+/// come back unchanged (as `jsp-cli serve --echo` does)
+pub async fn run(addr: &str, message: &str, count: usize, echo: bool) -> Result<()> {
     println!("{}", "JetStreamProto Send Test".bold().green());
     println!("{}", "=".repeat(50));
     println!("Target: {}", addr.cyan());
@@ -27,8 +33,24 @@ pub async fn run(addr: &str, message: &str, count: usize) -> Result<()> {
     println!("Sending messages...");
     for i in 0..count {
         let msg = format!("{} #{}", message, i + 1);
+        let sent_at = Instant::now();
         connection.send_on_stream(stream_id, msg.as_bytes()).await?;
         println!("  {} Sent: {}", "✓".green(), msg);
+
+        if echo {
+            let reply = tokio::time::timeout(ECHO_TIMEOUT, async {
+                loop {
+                    if let Some((_, data)) = connection.recv().await?.into_iter().next() {
+                        return anyhow::Ok(data);
+                    }
+                }
+            }).await
+                .map_err(|_| anyhow::anyhow!("No echo of `{}` within {:?}", msg, ECHO_TIMEOUT))??;
+            if reply != msg.as_bytes() {
+                anyhow::bail!("Echo mismatch: sent `{}`, got {:?}", msg, String::from_utf8_lossy(&reply));
+            }
+            println!("  {} Echoed in {:.2} ms", "✓".green(), sent_at.elapsed().as_secs_f64() * 1000.0);
+        }
         
         // Small delay between messages
         if count > 1 {
@@ -0,0 +1,323 @@
+use anyhow::Result;
+use colored::Colorize;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use jsp_core::types::delivery::DeliveryMode;
+use jsp_transport::config::{ConnectionConfig, ServerConfig};
+use jsp_transport::events::ServerEvent;
+use jsp_transport::prometheus::MetricsExporter;
+use jsp_transport::server::Server;
+
+/// What `serve` does with received payloads
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    /// Send payloads back on the stream they arrived on
+    pub echo: bool,
+    /// Print payloads to stdout
+    pub print: bool,
+    /// Serve Prometheus metrics on this address
+    pub metrics_addr: Option<String>,
+    /// Delivery mode stamped on echoed packets
+    pub delivery: DeliveryMode,
+    /// Accept compressed headers from clients
+    pub compression: bool,
+    /// Print throughput this often while data arrives (None = only at exit)
+    pub stats_interval: Option<Duration>,
+    /// Send stats frames to `jsp-cli monitor` this often (None = no stats stream)
+    pub monitor_interval: Option<Duration>,
+    /// Server config file to start from; the options above override it
+    pub config: Option<String>,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        Self {
+            echo: false,
+            print: false,
+            metrics_addr: None,
+            delivery: DeliveryMode::Reliable,
+            compression: true,
+            stats_interval: None,
+            monitor_interval: None,
+            config: None,
+        }
+    }
+}
+
+/// Totals reported when the server stops
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServeStats {
+    pub sessions: usize,
+    pub messages: usize,
+    pub bytes: usize,
+}
+
+pub async fn run(addr: &str, options: ServeOptions) -> Result<()> {
+    println!("{}", "JetStreamProto Server".bold().green());
+    println!("{}", "=".repeat(50));
+
+    let config = server_config(&options)?;
+    let server = Server::bind_with_config(addr, config).await?;
+    println!("Listening on: {}", server.local_addr()?.to_string().cyan());
+    println!("Mode: {}", mode(&options).yellow());
+    if options.echo {
+        println!("Echo delivery: {}", format!("{:?}", options.delivery).yellow());
+    }
+    println!("Header compression: {}", if options.compression { "Enabled".green() } else { "Disabled".red() });
+    if let Some(interval) = options.monitor_interval {
+        println!("Stats stream: every {} (see `jsp-cli monitor`)", format!("{:?}", interval).yellow());
+    }
+    println!("Press Ctrl-C to stop");
+    println!();
+
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            eprintln!("{} Failed to listen for Ctrl-C: {}", "✗".red(), e);
+            std::future::pending::<()>().await;
+        }
+    };
+    let start = Instant::now();
+    let stats = serve(server, &options, ctrl_c).await?;
+    let elapsed = start.elapsed();
+
+    println!();
+    println!(
+        "{} Served {} sessions, {} messages ({} bytes)",
+        "✓".green(),
+        stats.sessions.to_string().yellow(),
+        stats.messages.to_string().yellow(),
+        stats.bytes.to_string().yellow()
+    );
+    println!("Avg Throughput: {}", throughput(stats.messages, stats.bytes, elapsed).green());
+    Ok(())
+}
+
+/// The config file, or the defaults, with the command-line options applied
+fn server_config(options: &ServeOptions) -> Result<ServerConfig> {
+    let mut config = match &options.config {
+        Some(path) => ServerConfig::from_file(path)?,
+        None => ServerConfig::builder()
+            .connection(ConnectionConfig::builder().enable_header_compression(options.compression).build())
+            .build(),
+    };
+    if !options.compression {
+        config.connection.enable_header_compression = false;
+    }
+    if options.monitor_interval.is_some() {
+        config.stats_stream_interval = options.monitor_interval;
+    }
+    Ok(config)
+}
+
+/// Messages and megabits per second over `elapsed`
+fn throughput(messages: usize, bytes: usize, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+    format!("{:.1} msg/s, {:.2} Mbps", messages as f64 / secs, bytes as f64 * 8.0 / (secs * 1_000_000.0))
+}
+
+fn mode(options: &ServeOptions) -> &'static str {
+    match (options.echo, options.print) {
+        (true, true) => "echo + print",
+        (true, false) => "echo",
+        _ => "print",
+    }
+}
+
+/// Handle events from `server` until `shutdown` resolves
+///
+/// Payloads are printed unless only `echo` is set.
+pub async fn serve(mut server: Server, options: &ServeOptions, shutdown: impl Future<Output = ()>) -> Result<ServeStats> {
+    let exporter = match &options.metrics_addr {
+        Some(metrics_addr) => {
+            let metrics_addr: SocketAddr = metrics_addr.parse()?;
+            server.register_metrics(&[("role", "server")])?;
+            let exporter = MetricsExporter::serve(metrics_addr).await
+                .map_err(|e| anyhow::anyhow!("Failed to start metrics server: {}", e))?;
+            println!("Metrics: {}", format!("http://{}/metrics", exporter.local_addr()).cyan());
+            Some(exporter)
+        }
+        None => None,
+    };
+
+    let print = options.print || !options.echo;
+    let mut stats = ServeStats::default();
+    tokio::pin!(shutdown);
+
+    // Totals at the last report, to print throughput over each interval
+    let mut reported = stats;
+    let mut report = tokio::time::interval(options.stats_interval.unwrap_or(Duration::from_secs(1)));
+    report.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    report.tick().await;
+
+    loop {
+        let event = tokio::select! {
+            _ = &mut shutdown => break,
+            _ = report.tick(), if options.stats_interval.is_some() => {
+                if stats.messages > reported.messages {
+                    println!(
+                        "{} {}",
+                        "⏱".blue(),
+                        throughput(stats.messages - reported.messages, stats.bytes - reported.bytes, report.period())
+                    );
+                }
+                reported = stats;
+                continue;
+            }
+            event = server.next_event() => event,
+        };
+
+        match event {
+            Ok(ServerEvent::SessionEstablished { addr, session_id }) => {
+                stats.sessions += 1;
+                println!("{} Session {} established with {}", "✓".green(), session_id.to_string().yellow(), addr.to_string().cyan());
+            }
+            Ok(ServerEvent::DataReceived { addr, stream_id, data }) => {
+                stats.messages += 1;
+                stats.bytes += data.len();
+                if print {
+                    println!("[{} stream {}] {}", addr.to_string().cyan(), stream_id, render(&data));
+                }
+                if options.echo {
+                    if let Err(e) = server.send_on_stream_with_mode(addr, stream_id, &data, options.delivery).await {
+                        eprintln!("{} Failed to echo to {}: {}", "✗".red(), addr, e);
+                    }
+                }
+            }
+            Ok(ServerEvent::PeerMigrated { old, new }) => {
+                println!("{} Session migrated {} -> {}", "↪".yellow(), old.to_string().cyan(), new.to_string().cyan());
+            }
+            Ok(ServerEvent::Closed { addr, reason, message }) => {
+                println!("{} Session with {} closed: {:?}{}", "✗".red(), addr.to_string().cyan(), reason, message.map(|m| format!(" ({})", m)).unwrap_or_default());
+            }
+            Err(e) => eprintln!("{} Receive error: {}", "✗".red(), e),
+        }
+    }
+
+    println!("Shutting down...");
+    server.shutdown().await?;
+    if let Some(exporter) = exporter {
+        exporter.shutdown().await
+            .map_err(|e| anyhow::anyhow!("Failed to stop metrics server: {}", e))?;
+    }
+    Ok(stats)
+}
+
+/// Parse `reliable`, `best-effort` or `partial:<ttl_ms>`
+pub fn parse_delivery_mode(value: &str) -> Result<DeliveryMode, String> {
+    match value {
+        "reliable" => Ok(DeliveryMode::Reliable),
+        "best-effort" => Ok(DeliveryMode::BestEffort),
+        _ => match value.strip_prefix("partial:") {
+            Some(ttl) => ttl.parse()
+                .map(|ttl_ms| DeliveryMode::PartiallyReliable { ttl_ms })
+                .map_err(|e| format!("invalid TTL `{}`: {}", ttl, e)),
+            None => Err(format!("unknown delivery mode `{}` (expected reliable, best-effort or partial:<ttl_ms>)", value)),
+        },
+    }
+}
+
+/// Payload as UTF-8 when it is text, hex otherwise
+fn render(data: &[u8]) -> String {
+    match std::str::from_utf8(data) {
+        Ok(text) if !text.chars().any(|c| c.is_control() && !c.is_whitespace()) => text.to_string(),
+        _ => data.iter().map(|b| format!("{:02x}", b)).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsp_transport::connection::Connection;
+
+    #[test]
+    fn test_render() {
+        assert_eq!(render(b"hello world\n"), "hello world\n");
+        assert_eq!(render(&[0x00, 0xff, 0x10]), "00ff10");
+    }
+
+    #[test]
+    fn test_parse_delivery_mode() {
+        assert_eq!(parse_delivery_mode("reliable"), Ok(DeliveryMode::Reliable));
+        assert_eq!(parse_delivery_mode("best-effort"), Ok(DeliveryMode::BestEffort));
+        assert_eq!(parse_delivery_mode("partial:250"), Ok(DeliveryMode::PartiallyReliable { ttl_ms: 250 }));
+        assert!(parse_delivery_mode("partial:soon").is_err());
+        assert!(parse_delivery_mode("unreliable").is_err());
+    }
+
+    #[test]
+    fn test_server_config_file_with_overrides() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("jsp-cli-serve-{}.yaml", std::process::id()));
+        ServerConfig { stats_stream_interval: Some(Duration::from_secs(2)), ..ServerConfig::default() }.to_file(&path)?;
+
+        // The file's settings stay unless an option overrides them
+        let options = ServeOptions { config: Some(path.to_str().unwrap().to_string()), compression: false, ..ServeOptions::default() };
+        let config = server_config(&options)?;
+        assert_eq!(config.stats_stream_interval, Some(Duration::from_secs(2)));
+        assert!(!config.connection.enable_header_compression);
+
+        let options = ServeOptions { monitor_interval: Some(Duration::from_millis(500)), ..options };
+        assert_eq!(server_config(&options)?.stats_stream_interval, Some(Duration::from_millis(500)));
+
+        std::fs::remove_file(&path)?;
+        assert!(server_config(&options).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_serve_echoes_to_send() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0").await?;
+        let addr = server.local_addr()?.to_string();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let options = ServeOptions { echo: true, ..ServeOptions::default() };
+        let serve_task = tokio::spawn(async move {
+            serve(server, &options, async { let _ = stop_rx.await; }).await
+        });
+
+        // `jsp-cli send --echo` against it fails unless every message comes back
+        crate::commands::send::run(&addr, "from send", 2, true).await?;
+
+        // A client that waits for its echo
+        let mut client = Connection::connect_with_config(&addr, ConnectionConfig::default()).await?;
+        client.handshake().await?;
+        let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+        client.send_on_stream(stream_id, b"ping").await?;
+        let echo = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some((id, data)) = client.recv().await?.into_iter().next() {
+                    return anyhow::Ok((id, data));
+                }
+            }
+        }).await??;
+        assert_eq!(echo.0, stream_id);
+        assert_eq!(&echo.1[..], b"ping");
+
+        stop_tx.send(()).unwrap();
+        let stats = serve_task.await??;
+        assert_eq!(stats, ServeStats { sessions: 2, messages: 3, bytes: "from send #1".len() * 2 + 4 });
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_serve_echoes_with_delivery_mode() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0").await?;
+        let addr = server.local_addr()?.to_string();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let options = ServeOptions {
+            echo: true,
+            delivery: DeliveryMode::BestEffort,
+            stats_interval: Some(Duration::from_millis(10)),
+            ..ServeOptions::default()
+        };
+        let serve_task = tokio::spawn(async move {
+            serve(server, &options, async { let _ = stop_rx.await; }).await
+        });
+
+        crate::commands::send::run(&addr, "best effort", 3, true).await?;
+
+        stop_tx.send(()).unwrap();
+        let stats = serve_task.await??;
+        assert_eq!(stats.messages, 3);
+        Ok(())
+    }
+}
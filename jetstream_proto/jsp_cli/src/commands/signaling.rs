@@ -0,0 +1,30 @@
+use anyhow::Result;
+use colored::Colorize;
+use tokio::net::TcpListener;
+use jsp_transport::signaling::{SignalingConfig, SignalingServer, ROOM_SIZE};
+
+/// Run a signaling server for ICE candidate exchange until Ctrl-C
+///
+/// Peers register by ID and relay offers, answers and candidates to each
+/// other, or join a room to be paired with the other peer in it.
+pub async fn run(addr: &str, config: SignalingConfig) -> Result<()> {
+    println!("{}", "JetStreamProto Signaling Server".bold().green());
+    println!("{}", "=".repeat(50));
+
+    let listener = TcpListener::bind(addr).await?;
+    println!("Listening on: {}", listener.local_addr()?.to_string().cyan());
+    println!("Rooms: {} peers each", ROOM_SIZE);
+    println!("Max message size: {} bytes", config.max_message_size.to_string().yellow());
+    println!(
+        "Rate limit: {} messages/s per peer (bursts of {})",
+        config.rate_limit_messages.to_string().yellow(),
+        config.rate_limit_burst
+    );
+    println!("Press Ctrl-C to stop");
+
+    let server = SignalingServer::with_config(addr, config);
+    tokio::select! {
+        result = server.serve(listener) => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    }
+}
@@ -1,5 +1,7 @@
 use clap::{Parser, Subcommand};
 use anyhow::Result;
+use jsp_core::types::delivery::DeliveryMode;
+use std::time::Duration;
 
 mod commands;
 
@@ -13,18 +15,22 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Monitor connection status and metrics
+    /// Show live per-session metrics from a server's stats stream
     Monitor {
         /// Server address
         #[arg(short, long, default_value = "127.0.0.1:8080")]
         addr: String,
         
-        /// Update interval in seconds
-        #[arg(short, long, default_value = "1")]
-        interval: u64,
+        /// Print one JSON object per update instead of a table
+        #[arg(long)]
+        json: bool,
+        
+        /// Exit after this many updates
+        #[arg(short = 'n', long)]
+        count: Option<usize>,
     },
     
-    /// Profile connection performance
+    /// Profile round-trip latency, throughput and loss against an echo server
     Profile {
         /// Server address
         #[arg(short, long, default_value = "127.0.0.1:8080")]
@@ -34,6 +40,18 @@ enum Commands {
         #[arg(short, long, default_value = "60")]
         duration: u64,
         
+        /// Packets per second
+        #[arg(short, long, default_value = "100")]
+        rate: u32,
+        
+        /// Bytes per packet (at least 8)
+        #[arg(short, long, default_value = "64")]
+        size: usize,
+        
+        /// Packets at the start to leave out of the results
+        #[arg(short, long, default_value = "0")]
+        warmup: usize,
+        
         /// Output file (JSON)
         #[arg(short, long)]
         output: Option<String>,
@@ -58,6 +76,68 @@ enum Commands {
         /// Number of messages
         #[arg(short, long, default_value = "1")]
         count: usize,
+        
+        /// Wait for each message to be echoed back (see `serve --echo`)
+        #[arg(long)]
+        echo: bool,
+    },
+    
+    /// Run a server that echoes or prints what clients send
+    Serve {
+        /// Address to listen on
+        #[arg(short, long, default_value = "0.0.0.0:8080")]
+        addr: String,
+        
+        /// Echo payloads back on the stream they arrived on
+        #[arg(long)]
+        echo: bool,
+        
+        /// Print payloads as UTF-8 or hex (the default without --echo)
+        #[arg(long)]
+        print: bool,
+        
+        /// Serve Prometheus metrics on this address
+        #[arg(long)]
+        metrics_addr: Option<String>,
+        
+        /// Delivery mode of echoed data: reliable, best-effort or partial:<ttl_ms>
+        #[arg(long, default_value = "reliable", value_parser = commands::serve::parse_delivery_mode)]
+        delivery: DeliveryMode,
+        
+        /// Reject compressed headers from clients
+        #[arg(long)]
+        no_compression: bool,
+        
+        /// Seconds between throughput reports (0 = only at exit)
+        #[arg(long, default_value = "5")]
+        stats_interval: u64,
+        
+        /// Stream session stats to `jsp-cli monitor` every this many milliseconds
+        #[arg(long)]
+        monitor_interval_ms: Option<u64>,
+        
+        /// Server config file (.json, .yaml or .yml) to start from
+        #[arg(long)]
+        config: Option<String>,
+    },
+    
+    /// Run a signaling server that pairs peers for ICE candidate exchange
+    Signaling {
+        /// Address to listen on
+        #[arg(short, long, default_value = "0.0.0.0:8081")]
+        addr: String,
+        
+        /// Largest message accepted from a peer, in bytes
+        #[arg(long, default_value = "65536")]
+        max_message_size: usize,
+        
+        /// Messages each peer may send per second
+        #[arg(long, default_value = "50")]
+        rate_limit: u32,
+        
+        /// Messages each peer may send at once, such as a burst of candidates
+        #[arg(long, default_value = "200")]
+        burst: u32,
     },
 }
 
@@ -65,9 +145,13 @@ enum Commands {
 enum ConfigAction {
     /// Generate default configuration
     Generate {
-        /// Output file
+        /// Output file (.json, .yaml or .yml)
         #[arg(short, long, default_value = "config.json")]
         output: String,
+        
+        /// Generate a server config (connection settings under `connection`)
+        #[arg(long)]
+        server: bool,
     },
     
     /// Validate configuration file
@@ -75,10 +159,18 @@ enum ConfigAction {
         /// Config file to validate
         #[arg(short, long)]
         file: String,
+        
+        /// Validate as a server config
+        #[arg(long)]
+        server: bool,
     },
     
     /// Show current configuration
-    Show,
+    Show {
+        /// Config file to load instead of the defaults
+        #[arg(short, long)]
+        file: Option<String>,
+    },
 }
 
 #[tokio::main]
@@ -86,27 +178,55 @@ async fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Monitor { addr, interval } => {
-            commands::monitor::run(&addr, interval).await?;
+        Commands::Monitor { addr, json, count } => {
+            let options = commands::monitor::MonitorOptions { json, frames: count };
+            commands::monitor::run(&addr, options).await?;
         }
-        Commands::Profile { addr, duration, output } => {
-            commands::profile::run(&addr, duration, output.as_deref()).await?;
+        Commands::Profile { addr, duration, rate, size, warmup, output } => {
+            let options = commands::profile::ProfileOptions {
+                duration: Duration::from_secs(duration),
+                rate,
+                payload_size: size,
+                warmup,
+            };
+            commands::profile::run(&addr, options, output.as_deref()).await?;
         }
         Commands::Config { action } => {
             match action {
-                ConfigAction::Generate { output } => {
-                    commands::config::generate(&output)?;
+                ConfigAction::Generate { output, server } => {
+                    commands::config::generate(&output, server)?;
                 }
-                ConfigAction::Validate { file } => {
-                    commands::config::validate(&file)?;
+                ConfigAction::Validate { file, server } => {
+                    commands::config::validate(&file, server)?;
                 }
-                ConfigAction::Show => {
-                    commands::config::show()?;
+                ConfigAction::Show { file } => {
+                    commands::config::show(file.as_deref())?;
                 }
             }
         }
-        Commands::Send { addr, message, count } => {
-            commands::send::run(&addr, &message, count).await?;
+        Commands::Send { addr, message, count, echo } => {
+            commands::send::run(&addr, &message, count, echo).await?;
+        }
+        Commands::Serve { addr, echo, print, metrics_addr, delivery, no_compression, stats_interval, monitor_interval_ms, config } => {
+            let options = commands::serve::ServeOptions {
+                echo,
+                print,
+                metrics_addr,
+                delivery,
+                compression: !no_compression,
+                stats_interval: (stats_interval > 0).then_some(Duration::from_secs(stats_interval)),
+                monitor_interval: monitor_interval_ms.map(Duration::from_millis),
+                config,
+            };
+            commands::serve::run(&addr, options).await?;
+        }
+        Commands::Signaling { addr, max_message_size, rate_limit, burst } => {
+            let config = jsp_transport::signaling::SignalingConfig {
+                max_message_size,
+                rate_limit_messages: rate_limit,
+                rate_limit_burst: burst,
+            };
+            commands::signaling::run(&addr, config).await?;
         }
     }
 
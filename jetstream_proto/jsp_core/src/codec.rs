@@ -1,10 +1,12 @@
 use crate::types::{frame::Frame, header::Header};
 use crate::serialization::FlatBuffersCodec;
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use std::io::Cursor;
 
 /// Serialization format for protocol messages
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SerializationFormat {
     /// CBOR (Concise Binary Object Representation) - default for compatibility
     Cbor,
@@ -114,6 +116,20 @@ impl Default for Codec {
     }
 }
 
+/// Decode a header in whichever of CBOR or FlatBuffers it was encoded in
+///
+/// CBOR encodes a header as a map, so its first byte is 0xA0 or above; a
+/// FlatBuffers header starts with its root offset, which is far smaller.
+/// Compressed headers also start below 0x80 and need the session's
+/// decompressor instead.
+pub fn decode_any_header(data: &[u8]) -> Result<Header> {
+    match data.first() {
+        Some(&first) if first >= 0x80 => CborCodec::decode_header(data),
+        Some(_) => FlatBuffersCodec::deserialize_header(data),
+        None => anyhow::bail!("Empty header"),
+    }
+}
+
 pub trait ProtocolCodec {
     fn encode_header(header: &Header) -> Result<Vec<u8>>;
     fn decode_header(data: &[u8]) -> Result<Header>;
@@ -213,6 +229,32 @@ mod tests {
         assert_eq!(header.sequence, decoded.sequence);
     }
     
+    #[test]
+    fn test_decode_any_header() {
+        let header = Header {
+            stream_id: 7,
+            msg_type: 0x01,
+            flags: 0,
+            sequence: 42,
+            timestamp: 123456789,
+            nonce: 3,
+            delivery_mode: DeliveryMode::PartiallyReliable { ttl_ms: 250 },
+            piggybacked_ack: None,
+            payload_len: Some(1200),
+            connection_id: Some(ConnectionId::from_u64(9)),
+        };
+        
+        for codec in [Codec::cbor(), Codec::flatbuffers()] {
+            let decoded = decode_any_header(&codec.encode_header(&header).unwrap()).unwrap();
+            assert_eq!(decoded.sequence, 42, "{:?}", codec.format());
+            assert_eq!(decoded.delivery_mode, header.delivery_mode, "{:?}", codec.format());
+            assert_eq!(decoded.payload_len, Some(1200), "{:?}", codec.format());
+            assert_eq!(decoded.connection_id, header.connection_id, "{:?}", codec.format());
+        }
+        assert!(decode_any_header(&[]).is_err());
+        assert!(decode_any_header(&[0x05, 0x01, 0x02]).is_err());
+    }
+    
     #[test]
     fn test_codec_format_switching() {
         let mut codec = Codec::cbor();
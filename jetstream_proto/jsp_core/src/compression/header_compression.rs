@@ -139,7 +139,7 @@ impl HeaderCompressor {
         
         // Decode msg_type
         let msg_type = if flags & FLAG_MSG_TYPE_PRESENT != 0 {
-            let val = compressed[pos];
+            let val = *compressed.get(pos).ok_or("Truncated header")?;
             pos += 1;
             val
         } else {
@@ -175,10 +175,10 @@ impl HeaderCompressor {
         };
         
         // Decode flags and delivery_mode
-        let header_flags = compressed[pos];
+        let header_flags = *compressed.get(pos).ok_or("Truncated header")?;
         pos += 1;
         
-        let delivery_mode_byte = compressed[pos];
+        let delivery_mode_byte = *compressed.get(pos).ok_or("Truncated header")?;
         pos += 1;
         
         let delivery_mode = match delivery_mode_byte {
@@ -240,6 +240,80 @@ impl HeaderCompressor {
     }
 }
 
+/// Connection ID of a header as it appears on the wire, without decoder state
+///
+/// Works for CBOR headers and for the first compressed header of a
+/// connection; delta-encoded headers don't carry the ID and return `None`.
+pub fn peek_connection_id(header_bytes: &[u8]) -> Option<ConnectionId> {
+    let first = *header_bytes.first()?;
+    if first >= 0x80 {
+        return serde_cbor::from_slice::<Header>(header_bytes).ok()?.connection_id;
+    }
+    if first & FLAG_SEQUENCE_DELTA != 0 {
+        return None;
+    }
+    HeaderCompressor::new().decompress(header_bytes).ok()?.connection_id
+}
+
+/// Message type of a header as it appears on the wire, without decoder state
+///
+/// Works for CBOR headers and for compressed headers that carry the type,
+/// i.e. whose type differs from the previous header's; `None` otherwise.
+pub fn peek_msg_type(header_bytes: &[u8]) -> Option<u8> {
+    let flags = *header_bytes.first()?;
+    if flags >= 0x80 {
+        return serde_cbor::from_slice::<Header>(header_bytes).ok().map(|header| header.msg_type);
+    }
+    if flags & FLAG_MSG_TYPE_PRESENT == 0 {
+        return None;
+    }
+    let mut offset = 1;
+    if flags & FLAG_STREAM_ID_CHANGED != 0 {
+        let (_, len) = decode_varint(header_bytes.get(offset..)?).ok()?;
+        offset += len;
+    }
+    header_bytes.get(offset).copied()
+}
+
+/// Payload length a compressed header carries, without decoder state
+///
+/// The fields in front of it are laid out by the flags byte alone, so
+/// unlike the type this works for delta-encoded headers too. `None` if the
+/// header has no payload length or is truncated.
+pub fn peek_payload_len(header_bytes: &[u8]) -> Option<u32> {
+    let flags = *header_bytes.first()?;
+    if flags & FLAG_HAS_PAYLOAD_LEN == 0 {
+        return None;
+    }
+    let mut offset = 1;
+    let skip_varint = |offset: &mut usize| -> Option<()> {
+        let (_, len) = decode_varint(header_bytes.get(*offset..)?).ok()?;
+        *offset += len;
+        Some(())
+    };
+    if flags & FLAG_STREAM_ID_CHANGED != 0 {
+        skip_varint(&mut offset)?;
+    }
+    if flags & FLAG_MSG_TYPE_PRESENT != 0 {
+        offset += 1;
+    }
+    // Sequence, timestamp and nonce, as deltas or full values
+    for _ in 0..3 {
+        skip_varint(&mut offset)?;
+    }
+    // Header flags, then the delivery mode with its TTL if partially reliable
+    let delivery_mode = *header_bytes.get(offset + 1)?;
+    offset += 2;
+    if delivery_mode == 1 {
+        skip_varint(&mut offset)?;
+    }
+    if flags & FLAG_HAS_PIGGYBACKED_ACK != 0 {
+        skip_varint(&mut offset)?;
+    }
+    let (len, _) = decode_varint(header_bytes.get(offset..)?).ok()?;
+    u32::try_from(len).ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -311,4 +385,70 @@ mod tests {
         // Second packet should be smaller due to delta encoding
         assert!(size2 < size1, "Delta encoded packet should be smaller");
     }
+
+    #[test]
+    fn test_peek_connection_id() {
+        let header = Header {
+            connection_id: Some(ConnectionId::from_u64(42)),
+            ..Header::new(1, 0x00, 0, 7, 1000, 0, DeliveryMode::Reliable, None, Some(3))
+        };
+        let expected = Some(ConnectionId::from_u64(42));
+
+        let cbor = serde_cbor::to_vec(&header).unwrap();
+        assert_eq!(peek_connection_id(&cbor), expected);
+
+        let mut compressor = HeaderCompressor::new();
+        let first = compressor.compress(&header);
+        assert_eq!(peek_connection_id(&first), expected);
+
+        // Delta-encoded headers leave the ID out
+        let delta = compressor.compress(&Header { sequence: 8, ..header });
+        assert_eq!(peek_connection_id(&delta), None);
+
+        assert_eq!(peek_connection_id(&[]), None);
+        assert_eq!(peek_connection_id(&[0x00]), None);
+    }
+
+    #[test]
+    fn test_peek_msg_type() {
+        let header = Header::new(300, 0x05, 0, 7, 1000, 0, DeliveryMode::Reliable, None, Some(3));
+
+        let cbor = serde_cbor::to_vec(&header).unwrap();
+        assert_eq!(peek_msg_type(&cbor), Some(0x05));
+
+        let mut compressor = HeaderCompressor::new();
+        assert_eq!(peek_msg_type(&compressor.compress(&header)), Some(0x05));
+
+        // Same type as the previous header: left out
+        let same = compressor.compress(&Header { sequence: 8, ..header });
+        assert_eq!(peek_msg_type(&same), None);
+
+        // Same stream, new type
+        let changed = compressor.compress(&Header { sequence: 9, msg_type: 0x07, ..header });
+        assert_eq!(peek_msg_type(&changed), Some(0x07));
+
+        assert_eq!(peek_msg_type(&[]), None);
+        assert_eq!(peek_msg_type(&[FLAG_MSG_TYPE_PRESENT]), None);
+    }
+
+    #[test]
+    fn test_peek_payload_len() {
+        let header = Header::new(300, 0x05, 0x02, 7, 1000, 9, DeliveryMode::PartiallyReliable { ttl_ms: 500 }, Some(6), Some(1300));
+        let mut compressor = HeaderCompressor::new();
+        assert_eq!(peek_payload_len(&compressor.compress(&header)), Some(1300));
+
+        // Delta-encoded, same stream and type
+        let delta = compressor.compress(&Header { sequence: 8, piggybacked_ack: None, payload_len: Some(17), ..header });
+        assert_eq!(peek_payload_len(&delta), Some(17));
+
+        let best_effort = compressor.compress(&Header { sequence: 9, delivery_mode: DeliveryMode::BestEffort, payload_len: Some(0), ..header });
+        assert_eq!(peek_payload_len(&best_effort), Some(0));
+
+        // Left out by the sender
+        let without = compressor.compress(&Header { sequence: 10, payload_len: None, ..header });
+        assert_eq!(peek_payload_len(&without), None);
+
+        assert_eq!(peek_payload_len(&[]), None);
+        assert_eq!(peek_payload_len(&[FLAG_HAS_PAYLOAD_LEN]), None);
+    }
 }
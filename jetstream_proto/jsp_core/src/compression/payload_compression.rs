@@ -1,8 +1,9 @@
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use std::io::Write;
 
 /// Compression algorithm
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CompressionAlgorithm {
     /// LZ4 (Fast, good for streaming)
     Lz4,
@@ -12,6 +13,26 @@ pub enum CompressionAlgorithm {
     Zstd,
 }
 
+impl CompressionAlgorithm {
+    /// Byte identifying the algorithm on the wire
+    pub fn id(self) -> u8 {
+        match self {
+            CompressionAlgorithm::Lz4 => 1,
+            CompressionAlgorithm::Brotli => 2,
+            CompressionAlgorithm::Zstd => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(CompressionAlgorithm::Lz4),
+            2 => Some(CompressionAlgorithm::Brotli),
+            3 => Some(CompressionAlgorithm::Zstd),
+            _ => None,
+        }
+    }
+}
+
 /// Payload compressor with multiple algorithms
 pub struct PayloadCompressor {
     /// Minimum size threshold for compression (bytes)
@@ -20,6 +41,8 @@ pub struct PayloadCompressor {
     enabled: bool,
     /// Default algorithm
     algorithm: CompressionAlgorithm,
+    /// Level for Brotli (quality 0-11) and Zstd (1-22); None uses 6 and 3
+    level: Option<i32>,
 }
 
 impl PayloadCompressor {
@@ -32,6 +55,7 @@ impl PayloadCompressor {
             min_size,
             enabled: true,
             algorithm: CompressionAlgorithm::Lz4,
+            level: None,
         }
     }
     
@@ -45,6 +69,11 @@ impl PayloadCompressor {
         self.algorithm = algo;
     }
     
+    /// Set compression level, clamped to the algorithm's range (LZ4 has none)
+    pub fn set_level(&mut self, level: i32) {
+        self.level = Some(level);
+    }
+    
     /// Enable compression
     pub fn enable(&mut self) {
         self.enabled = true;
@@ -139,12 +168,14 @@ impl PayloadCompressor {
         let compressed = match self.algorithm {
             CompressionAlgorithm::Lz4 => lz4_flex::compress_prepend_size(data),
             CompressionAlgorithm::Brotli => {
-                let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, 6, 20); // Quality 6, LGWin 20
+                let quality = self.level.unwrap_or(6).clamp(0, 11) as u32;
+                let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, quality, 20); // LGWin 20
                 writer.write_all(data)?;
                 writer.into_inner()
             },
             CompressionAlgorithm::Zstd => {
-                zstd::stream::encode_all(std::io::Cursor::new(data), 3)? // Level 3
+                let level = self.level.unwrap_or(3).clamp(1, 22);
+                zstd::stream::encode_all(std::io::Cursor::new(data), level)?
             }
         };
         
@@ -259,6 +290,26 @@ mod tests {
         assert_eq!(data, decompressed);
     }
 
+    #[test]
+    fn test_zstd_levels() {
+        let data = b"level test payload with some repetition ".repeat(64);
+        let mut compressor = PayloadCompressor::new(512);
+        compressor.set_algorithm(CompressionAlgorithm::Zstd);
+        for level in [0, 1, 9, 30] {
+            compressor.set_level(level);
+            let compressed = compressor.compress(&data).unwrap().unwrap();
+            assert_eq!(compressor.decompress(&compressed, CompressionAlgorithm::Zstd).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_algorithm_ids() {
+        for algo in [CompressionAlgorithm::Lz4, CompressionAlgorithm::Brotli, CompressionAlgorithm::Zstd] {
+            assert_eq!(CompressionAlgorithm::from_id(algo.id()), Some(algo));
+        }
+        assert_eq!(CompressionAlgorithm::from_id(0), None);
+    }
+
     #[test]
     fn test_adaptive_compression_text() {
         let compressor = PayloadCompressor::new(512);
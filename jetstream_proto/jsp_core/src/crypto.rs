@@ -1,5 +1,5 @@
 use chacha20poly1305::{
-    aead::{Aead, KeyInit},
+    aead::{Aead, KeyInit, Payload},
     ChaCha20Poly1305, Key, Nonce
 };
 use aes_gcm::Aes256Gcm;
@@ -8,6 +8,14 @@ use pqcrypto_kyber::kyber768;
 use pqcrypto_traits::kem::{Ciphertext, PublicKey as KyberPublicKey, SharedSecret};
 use rand_core::OsRng;
 use anyhow::Result;
+use crate::serialization::FlatBuffersCodec;
+use crate::types::header::Header;
+
+/// Packets carry the low bits of the key epoch that sealed them
+const KEY_PHASE_MASK: u32 = 0b11;
+
+/// Bytes an AEAD tag adds to a sealed payload, for either cipher suite
+pub const AEAD_TAG_LEN: usize = 16;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CipherSuite {
@@ -22,6 +30,16 @@ pub struct CryptoContext {
     kyber_public: kyber768::PublicKey,
     shared_secret: Option<Key>,
     cipher_suite: CipherSuite,
+    /// Number of key updates since the handshake; `shared_secret` is this epoch's key
+    key_epoch: u32,
+    /// Key for the epoch after the current one, derived ahead of the update
+    next_secret: Option<Key>,
+    /// Key of the epoch before the current one, until it is retired
+    previous_secret: Option<Key>,
+    /// Secret a session ticket for this session resumes from
+    resumption_secret: Option<Key>,
+    /// Key sealing 0-RTT early data of a resumed session
+    early_secret: Option<Key>,
 }
 
 impl Default for CryptoContext {
@@ -45,6 +63,11 @@ impl CryptoContext {
             kyber_public,
             shared_secret: None,
             cipher_suite: CipherSuite::ChaCha20Poly1305, // Default
+            key_epoch: 0,
+            next_secret: None,
+            previous_secret: None,
+            resumption_secret: None,
+            early_secret: None,
         }
     }
 
@@ -113,72 +136,270 @@ impl CryptoContext {
         let mut okm = [0u8; 32];
         hk.expand(&info, &mut okm).expect("HKDF expand failed");
         
-        self.shared_secret = Some(*Key::from_slice(&okm));
+        self.install_key(*Key::from_slice(&okm));
     }
 
     pub fn encrypt(&self, nonce_val: u64, plaintext: &[u8]) -> Result<Vec<u8>> {
         let key_bytes = self.shared_secret.as_ref().ok_or_else(|| anyhow::anyhow!("Handshake not completed"))?;
-        
-        // Create 12-byte nonce (96 bits) from u64
-        let mut nonce_bytes = [0u8; 12];
-        nonce_bytes[4..].copy_from_slice(&nonce_val.to_be_bytes());
-        let nonce = Nonce::from_slice(&nonce_bytes);
+        self.seal(key_bytes, &packet_nonce(0, nonce_val), plaintext, &[])
+    }
+
+    pub fn decrypt(&self, nonce_val: u64, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let key_bytes = self.shared_secret.as_ref().ok_or_else(|| anyhow::anyhow!("Handshake not completed"))?;
+        self.open(key_bytes, &packet_nonce(0, nonce_val), ciphertext, &[])
+    }
+
+    /// Current key epoch: 0 after the handshake, one more per key update
+    pub fn key_epoch(&self) -> u32 {
+        self.key_epoch
+    }
+
+    /// Low bits of the current epoch, as carried in packet headers
+    pub fn key_phase(&self) -> u8 {
+        (self.key_epoch & KEY_PHASE_MASK) as u8
+    }
+
+    /// Whether the previous epoch's key is still accepted
+    pub fn has_previous_epoch(&self) -> bool {
+        self.previous_secret.is_some()
+    }
+
+    /// Move to the next key epoch, like a TLS 1.3 KeyUpdate
+    ///
+    /// The new key is derived from the current one with HKDF, so both sides
+    /// arrive at the same key without another exchange. The old key keeps
+    /// opening packets from the previous epoch until `retire_previous_epoch`;
+    /// an epoch that was still waiting to be retired is dropped. Returns the
+    /// new epoch.
+    pub fn update_keys(&mut self) -> Result<u32> {
+        let next = self.next_secret.take().ok_or_else(|| anyhow::anyhow!("Handshake not completed"))?;
+        self.previous_secret = self.shared_secret.replace(next);
+        self.next_secret = Some(next_epoch_key(&next));
+        self.key_epoch = self.key_epoch.wrapping_add(1);
+        Ok(self.key_epoch)
+    }
+
+    /// Stop accepting packets sealed with the previous epoch's key
+    pub fn retire_previous_epoch(&mut self) {
+        self.previous_secret = None;
+    }
+
+    /// Seal a packet payload with the current epoch's key
+    ///
+    /// `sender` keeps the two directions apart, since both sides number
+    /// their packets from the same starting point: 0 for the client, 1 for
+    /// the server. `aad` is authenticated but not encrypted; for packets it
+    /// is `header_aad` of the packet's header.
+    pub fn encrypt_packet(&self, sender: u32, nonce_val: u64, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let key_bytes = self.shared_secret.as_ref().ok_or_else(|| anyhow::anyhow!("Handshake not completed"))?;
+        self.seal(key_bytes, &packet_nonce(sender, nonce_val), plaintext, aad)
+    }
+
+    /// Open a packet payload sealed in the epoch whose low bits are `phase`
+    ///
+    /// Accepts the previous (not yet retired), current and next epochs, and
+    /// returns the epoch that opened the packet. A packet from the next epoch
+    /// means the peer has updated its keys; the caller should follow with
+    /// `update_keys`. Fails if `aad` differs from what the sender sealed with.
+    pub fn decrypt_packet(&self, phase: u8, sender: u32, nonce_val: u64, ciphertext: &[u8], aad: &[u8]) -> Result<(u32, Vec<u8>)> {
+        let phase = phase as u32 & KEY_PHASE_MASK;
+        let epoch = self.key_epoch;
+        let (epoch, key) = if phase == epoch & KEY_PHASE_MASK {
+            (epoch, self.shared_secret.as_ref())
+        } else if phase == epoch.wrapping_add(1) & KEY_PHASE_MASK {
+            (epoch.wrapping_add(1), self.next_secret.as_ref())
+        } else if phase == epoch.wrapping_sub(1) & KEY_PHASE_MASK {
+            (epoch.wrapping_sub(1), self.previous_secret.as_ref())
+        } else {
+            return Err(anyhow::anyhow!("Packet from key phase {} is too far from epoch {}", phase, epoch));
+        };
+        let key = key.ok_or_else(|| anyhow::anyhow!("No key for epoch {}", epoch))?;
+        let plaintext = self.open(key, &packet_nonce(sender, nonce_val), ciphertext, aad)?;
+        Ok((epoch, plaintext))
+    }
+
+    /// Seal a 0-RTT packet payload with the early data key
+    ///
+    /// Like `encrypt_packet`, but under the key `derive_early_key` set up,
+    /// which stays in use for packets flagged as early data even after the
+    /// resumed handshake completed.
+    pub fn encrypt_early_packet(&self, sender: u32, nonce_val: u64, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let key_bytes = self.early_secret.as_ref().ok_or_else(|| anyhow::anyhow!("No early data key"))?;
+        self.seal(key_bytes, &packet_nonce(sender, nonce_val), plaintext, aad)
+    }
+
+    /// Open a 0-RTT packet payload sealed with `encrypt_early_packet`
+    pub fn decrypt_early_packet(&self, sender: u32, nonce_val: u64, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let key_bytes = self.early_secret.as_ref().ok_or_else(|| anyhow::anyhow!("No early data key"))?;
+        self.open(key_bytes, &packet_nonce(sender, nonce_val), ciphertext, aad)
+    }
+
+    /// Start epoch 0 with `key`, forgetting any earlier epochs
+    fn install_key(&mut self, key: Key) {
+        self.resumption_secret = Some(derive_key(&key, b"JetStreamProto-Resumption"));
+        self.shared_secret = Some(key);
+        self.next_secret = Some(next_epoch_key(&key));
+        self.previous_secret = None;
+        self.key_epoch = 0;
+    }
+
+    fn seal(&self, key_bytes: &Key, nonce_bytes: &[u8; 12], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let nonce = Nonce::from_slice(nonce_bytes);
+        let payload = Payload { msg: plaintext, aad };
 
         match self.cipher_suite {
             CipherSuite::ChaCha20Poly1305 => {
                 let cipher = ChaCha20Poly1305::new(key_bytes);
-                cipher.encrypt(nonce, plaintext)
+                cipher.encrypt(nonce, payload)
                     .map_err(|_| anyhow::anyhow!("Encryption failed"))
             },
             CipherSuite::Aes256Gcm => {
                 let cipher = Aes256Gcm::new(key_bytes);
-                cipher.encrypt(nonce, plaintext)
+                cipher.encrypt(nonce, payload)
                     .map_err(|_| anyhow::anyhow!("Encryption failed"))
             }
         }
     }
 
-    pub fn decrypt(&self, nonce_val: u64, ciphertext: &[u8]) -> Result<Vec<u8>> {
-        let key_bytes = self.shared_secret.as_ref().ok_or_else(|| anyhow::anyhow!("Handshake not completed"))?;
-        
-        let mut nonce_bytes = [0u8; 12];
-        nonce_bytes[4..].copy_from_slice(&nonce_val.to_be_bytes());
-        let nonce = Nonce::from_slice(&nonce_bytes);
+    fn open(&self, key_bytes: &Key, nonce_bytes: &[u8; 12], ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let nonce = Nonce::from_slice(nonce_bytes);
+        let payload = Payload { msg: ciphertext, aad };
 
         match self.cipher_suite {
             CipherSuite::ChaCha20Poly1305 => {
                 let cipher = ChaCha20Poly1305::new(key_bytes);
-                cipher.decrypt(nonce, ciphertext)
+                cipher.decrypt(nonce, payload)
                     .map_err(|_| anyhow::anyhow!("Decryption failed"))
             },
             CipherSuite::Aes256Gcm => {
                 let cipher = Aes256Gcm::new(key_bytes);
-                cipher.decrypt(nonce, ciphertext)
+                cipher.decrypt(nonce, payload)
                     .map_err(|_| anyhow::anyhow!("Decryption failed"))
             }
         }
     }
 
-    /// Export session state for 0-RTT resumption
-    pub fn export_session_state(&self) -> Result<Vec<u8>> {
-        let key = self.shared_secret.as_ref()
-            .ok_or_else(|| anyhow::anyhow!("No session state to export"))?;
+    /// Root key for a Double Ratchet, kept separate from the session key
+    pub fn ratchet_root_key(&self) -> Result<[u8; 32]> {
+        use hkdf::Hkdf;
+        use sha2::Sha256;
         
-        // In production, this should be encrypted with a server-side key
-        // For now, just return the raw key bytes
-        Ok(key.as_slice().to_vec())
+        let key = self.shared_secret.as_ref().ok_or_else(|| anyhow::anyhow!("Handshake not completed"))?;
+        let hk = Hkdf::<Sha256>::new(None, key.as_slice());
+        let mut root = [0u8; 32];
+        hk.expand(b"JetStreamProto-RatchetRoot", &mut root).expect("HKDF expand failed");
+        Ok(root)
     }
 
-    /// Import session state for 0-RTT resumption
-    pub fn import_session_state(&mut self, state: &[u8]) -> Result<()> {
-        if state.len() != 32 {
-            return Err(anyhow::anyhow!("Invalid session state length"));
-        }
+    /// Secret a session ticket for this session resumes from
+    ///
+    /// Derived from the key the handshake installed rather than the current
+    /// epoch's, so both sides agree on it whatever key updates happened since.
+    /// It never opens packets itself.
+    pub fn resumption_secret(&self) -> Result<[u8; 32]> {
+        let secret = self.resumption_secret.as_ref().ok_or_else(|| anyhow::anyhow!("Handshake not completed"))?;
+        let mut out = [0u8; 32];
+        out.copy_from_slice(secret);
+        Ok(out)
+    }
+
+    /// Set up the 0-RTT key from a ticket's resumption secret
+    ///
+    /// Bound to the ClientHello's random, so every resumption seals its
+    /// early data under a key of its own.
+    pub fn derive_early_key(&mut self, resumption_secret: &[u8; 32], client_random: &[u8; 32]) {
+        use hkdf::Hkdf;
+        use sha2::Sha256;
         
-        let mut key_bytes = [0u8; 32];
-        key_bytes.copy_from_slice(state);
-        self.shared_secret = Some(*Key::from_slice(&key_bytes));
+        let hk = Hkdf::<Sha256>::new(Some(b"JetStreamProto-EarlyData"), resumption_secret);
+        let mut okm = [0u8; 32];
+        hk.expand(client_random, &mut okm).expect("HKDF expand failed");
+        self.early_secret = Some(*Key::from_slice(&okm));
+    }
+
+    /// Start a resumed session from a ticket's resumption secret
+    ///
+    /// The key is HKDF of the secret over both hello randoms, like a full
+    /// handshake derives it from the key exchange. Each resumption thus gets
+    /// a key of its own, and packet numbers starting over at 0 never repeat
+    /// a nonce under a key used before.
+    pub fn derive_resumed_key(&mut self, resumption_secret: &[u8; 32], client_random: &[u8; 32], server_random: &[u8; 32]) {
+        use hkdf::Hkdf;
+        use sha2::Sha256;
+        
+        let hk = Hkdf::<Sha256>::new(None, resumption_secret);
+        let mut info = Vec::with_capacity(64);
+        info.extend_from_slice(client_random);
+        info.extend_from_slice(server_random);
         
-        Ok(())
+        let mut okm = [0u8; 32];
+        hk.expand(&info, &mut okm).expect("HKDF expand failed");
+        self.install_key(*Key::from_slice(&okm));
     }
 }
+
+/// Seal the state a session ticket carries with a key only the server holds
+///
+/// The ticket's id is authenticated along with it, so state can't be moved
+/// to another ticket. A random nonce is prepended, as one key seals every
+/// ticket a server issues.
+pub fn seal_ticket_state(ticket_key: &[u8; 32], ticket_id: &[u8; 32], state: &[u8]) -> Result<Vec<u8>> {
+    use rand_core::RngCore;
+    
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut nonce);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(ticket_key));
+    let sealed = cipher.encrypt(Nonce::from_slice(&nonce), Payload { msg: state, aad: ticket_id })
+        .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
+    
+    let mut out = Vec::with_capacity(nonce.len() + sealed.len());
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&sealed);
+    Ok(out)
+}
+
+/// Open ticket state sealed with `seal_ticket_state`
+pub fn open_ticket_state(ticket_key: &[u8; 32], ticket_id: &[u8; 32], sealed: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < 12 + AEAD_TAG_LEN {
+        return Err(anyhow::anyhow!("Invalid ticket state length"));
+    }
+    let (nonce, ciphertext) = sealed.split_at(12);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(ticket_key));
+    cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: ticket_id })
+        .map_err(|_| anyhow::anyhow!("Decryption failed"))
+}
+
+/// Associated data that ties a packet's header to its sealed payload
+///
+/// The header is authenticated in its canonical FlatBuffers encoding rather
+/// than in the bytes that carried it, so a header sent as CBOR, as FlatBuffers
+/// or delta-compressed (after decompression) yields the same AAD on both
+/// sides. Every field is covered, so changing any of them, including the key
+/// phase flags, makes the packet fail to open.
+pub fn header_aad(header: &Header) -> Vec<u8> {
+    FlatBuffersCodec::serialize_header(header)
+}
+
+/// 12-byte AEAD nonce: 4-byte sender, then the 8-byte packet number
+fn packet_nonce(sender: u32, nonce_val: u64) -> [u8; 12] {
+    let mut nonce_bytes = [0u8; 12];
+    nonce_bytes[..4].copy_from_slice(&sender.to_be_bytes());
+    nonce_bytes[4..].copy_from_slice(&nonce_val.to_be_bytes());
+    nonce_bytes
+}
+
+/// Key for the epoch after the one using `key`
+fn next_epoch_key(key: &Key) -> Key {
+    derive_key(key, b"JetStreamProto-KeyUpdate")
+}
+
+/// Key derived from `key` for the purpose named by `label`
+fn derive_key(key: &Key, label: &[u8]) -> Key {
+    use hkdf::Hkdf;
+    use sha2::Sha256;
+    
+    let hk = Hkdf::<Sha256>::new(None, key.as_slice());
+    let mut okm = [0u8; 32];
+    hk.expand(label, &mut okm).expect("HKDF expand failed");
+    *Key::from_slice(&okm)
+}
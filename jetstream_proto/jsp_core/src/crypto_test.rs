@@ -1,4 +1,9 @@
-use crate::crypto::{CryptoContext, CipherSuite};
+use crate::crypto::{header_aad, CryptoContext, CipherSuite};
+use crate::compression::header_compression::HeaderCompressor;
+use crate::serialization::FlatBuffersCodec;
+use crate::types::connection_id::ConnectionId;
+use crate::types::delivery::DeliveryMode;
+use crate::types::header::{Header, FRAME_TYPE_DATA};
 
 #[test]
 fn test_chacha20_encryption() {
@@ -54,3 +59,108 @@ fn test_aes_gcm_encryption() {
     
     assert_eq!(plaintext.to_vec(), decrypted);
 }
+
+/// Client and server contexts that completed a key exchange
+fn established_pair() -> (CryptoContext, CryptoContext) {
+    let mut client = CryptoContext::new();
+    let mut server = CryptoContext::new();
+    let (ciphertext, shared) = client.encapsulate_kyber(server.kyber_public_key()).unwrap();
+    let server_shared = server.decapsulate_kyber(&ciphertext).unwrap();
+    client.derive_shared_secret(server.x25519_public_key(), Some(&shared), &[1u8; 32], &[2u8; 32]);
+    server.derive_shared_secret(client.x25519_public_key(), Some(&server_shared), &[1u8; 32], &[2u8; 32]);
+    (client, server)
+}
+
+#[test]
+fn test_key_update_keeps_previous_epoch_until_retired() {
+    let (mut client, mut server) = established_pair();
+    
+    let old = client.encrypt_packet(0, 1, b"epoch 0", &[]).unwrap();
+    let old_phase = client.key_phase();
+    
+    assert_eq!(client.update_keys().unwrap(), 1);
+    assert_eq!(server.update_keys().unwrap(), 1);
+    assert!(server.has_previous_epoch());
+    
+    let new = client.encrypt_packet(0, 2, b"epoch 1", &[]).unwrap();
+    assert_eq!(server.decrypt_packet(client.key_phase(), 0, 2, &new, &[]).unwrap(), (1, b"epoch 1".to_vec()));
+    assert_eq!(server.decrypt_packet(old_phase, 0, 1, &old, &[]).unwrap(), (0, b"epoch 0".to_vec()));
+    
+    server.retire_previous_epoch();
+    assert!(!server.has_previous_epoch());
+    assert!(server.decrypt_packet(old_phase, 0, 1, &old, &[]).is_err());
+}
+
+#[test]
+fn test_packet_from_next_epoch_opens_before_update() {
+    let (mut client, server) = established_pair();
+    
+    client.update_keys().unwrap();
+    let sealed = client.encrypt_packet(0, 7, b"ahead", &[]).unwrap();
+    
+    // The server has not seen the KeyUpdate yet, but derived the next key already
+    assert_eq!(server.key_epoch(), 0);
+    assert_eq!(server.decrypt_packet(client.key_phase(), 0, 7, &sealed, &[]).unwrap(), (1, b"ahead".to_vec()));
+    
+    // Two epochs ahead is out of reach
+    client.update_keys().unwrap();
+    let sealed = client.encrypt_packet(0, 8, b"too far", &[]).unwrap();
+    assert!(server.decrypt_packet(client.key_phase(), 0, 8, &sealed, &[]).is_err());
+}
+
+#[test]
+fn test_packet_keys_separate_directions() {
+    let (client, server) = established_pair();
+    
+    let sealed = client.encrypt_packet(0, 1, b"from client", &[]).unwrap();
+    assert!(server.decrypt_packet(client.key_phase(), 1, 1, &sealed, &[]).is_err());
+    assert_eq!(server.encrypt_packet(1, 1, b"from client", &[]).unwrap().len(), sealed.len());
+    assert_ne!(server.encrypt_packet(1, 1, b"from client", &[]).unwrap(), sealed);
+}
+
+#[test]
+fn test_key_update_needs_handshake() {
+    let mut context = CryptoContext::new();
+    assert!(context.update_keys().is_err());
+    assert!(context.encrypt_packet(0, 1, b"data", &[]).is_err());
+}
+
+#[test]
+fn test_tampered_header_fails_to_open() {
+    let (client, server) = established_pair();
+    let header = Header::new(3, FRAME_TYPE_DATA, 0, 42, 1_700_000_000_000, 9, DeliveryMode::Reliable, Some(40), Some(21));
+    
+    let sealed = client.encrypt_packet(0, 9, b"header bound", &header_aad(&header)).unwrap();
+    assert_eq!(server.decrypt_packet(0, 0, 9, &sealed, &header_aad(&header)).unwrap(), (0, b"header bound".to_vec()));
+    
+    let mut moved = header;
+    moved.stream_id = 4;
+    assert!(server.decrypt_packet(0, 0, 9, &sealed, &header_aad(&moved)).is_err());
+    
+    let mut flipped = header;
+    flipped.timestamp ^= 1;
+    assert!(server.decrypt_packet(0, 0, 9, &sealed, &header_aad(&flipped)).is_err());
+}
+
+#[test]
+fn test_header_aad_is_independent_of_encoding() {
+    let mut header = Header::new(7, FRAME_TYPE_DATA, 0x08, 1234, 1_700_000_000_000, 55, DeliveryMode::PartiallyReliable { ttl_ms: 250 }, Some(1200), Some(512));
+    header.connection_id = Some(ConnectionId::from_u64(0xfeed));
+    let aad = header_aad(&header);
+    
+    let from_cbor: Header = serde_cbor::from_slice(&serde_cbor::to_vec(&header).unwrap()).unwrap();
+    assert_eq!(header_aad(&from_cbor), aad);
+    
+    let from_flatbuffers = FlatBuffersCodec::deserialize_header(&FlatBuffersCodec::serialize_header(&header)).unwrap();
+    assert_eq!(header_aad(&from_flatbuffers), aad);
+    
+    // A delta-compressed header authenticates as its decompressed form
+    let mut sender = HeaderCompressor::new();
+    let mut receiver = HeaderCompressor::new();
+    let mut previous = header;
+    previous.sequence -= 1;
+    previous.timestamp -= 5;
+    receiver.decompress(&sender.compress(&previous)).unwrap();
+    let from_compressed = receiver.decompress(&sender.compress(&header)).unwrap();
+    assert_eq!(header_aad(&from_compressed), aad);
+}
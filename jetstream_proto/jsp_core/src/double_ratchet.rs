@@ -1,7 +1,7 @@
 use hkdf::Hkdf;
 use sha2::Sha256;
 use chacha20poly1305::{
-    aead::{Aead, KeyInit},
+    aead::{Aead, KeyInit, Payload},
     ChaCha20Poly1305, Key, Nonce
 };
 use anyhow::Result;
@@ -9,8 +9,12 @@ use std::collections::HashMap;
 
 const MAX_SKIP: usize = 1000; // Maximum number of message keys to skip
 
+/// Encoded size of a `MessageHeader`
+pub const MESSAGE_HEADER_LEN: usize = 40;
+
 /// Double Ratchet implementation for forward secrecy and post-compromise security
 /// Based on the Signal Protocol specification
+#[derive(Clone)]
 pub struct DoubleRatchet {
     /// Root key for deriving new chain keys
     root_key: [u8; 32],
@@ -30,8 +34,9 @@ pub struct DoubleRatchet {
     /// Previous sending chain length (for header)
     previous_chain_length: u32,
     
-    /// Skipped message keys for out-of-order messages
-    skipped_message_keys: HashMap<(u32, u32), [u8; 32]>,
+    /// Skipped message keys for out-of-order messages, keyed by the
+    /// sender's DH key (identifying the chain) and message number
+    skipped_message_keys: HashMap<([u8; 32], u32), [u8; 32]>,
     
     /// Our current DH keypair (ephemeral)
     dh_secret: x25519_dalek::StaticSecret,
@@ -39,6 +44,9 @@ pub struct DoubleRatchet {
     
     /// Peer's current DH public key
     peer_dh_public: Option<x25519_dalek::PublicKey>,
+    
+    /// DH ratchet steps performed so far
+    dh_ratchet_steps: u64,
 }
 
 impl DoubleRatchet {
@@ -60,9 +68,39 @@ impl DoubleRatchet {
             dh_secret,
             dh_public,
             peer_dh_public: Some(peer_dh_public),
+            dh_ratchet_steps: 0,
         }
     }
     
+    /// Initialize as Alice with a DH key whose public half Bob already knows
+    ///
+    /// The sending chain is derived right away, matching what Bob derives in
+    /// `new_bob_with_peer`, so either side may send first.
+    pub fn new_alice_with_secret(shared_secret: &[u8; 32], dh_secret: x25519_dalek::StaticSecret, peer_public_key: &[u8; 32]) -> Self {
+        let dh_public = x25519_dalek::PublicKey::from(&dh_secret);
+        let peer_dh_public = x25519_dalek::PublicKey::from(*peer_public_key);
+        
+        let mut ratchet = Self {
+            root_key: *shared_secret,
+            sending_chain_key: None,
+            receiving_chain_key: None,
+            sending_message_number: 0,
+            receiving_message_number: 0,
+            previous_chain_length: 0,
+            skipped_message_keys: HashMap::new(),
+            dh_secret,
+            dh_public,
+            peer_dh_public: Some(peer_dh_public),
+            dh_ratchet_steps: 0,
+        };
+        
+        let dh_output = ratchet.dh_secret.diffie_hellman(&peer_dh_public);
+        let (new_root_key, sending_chain_key) = ratchet.kdf_rk(&ratchet.root_key, dh_output.as_bytes());
+        ratchet.root_key = new_root_key;
+        ratchet.sending_chain_key = Some(sending_chain_key);
+        ratchet
+    }
+    
     /// Initialize as Bob (responder) with shared secret from handshake
     pub fn new_bob(shared_secret: &[u8; 32]) -> Self {
         let dh_secret = x25519_dalek::StaticSecret::random_from_rng(rand_core::OsRng);
@@ -79,14 +117,46 @@ impl DoubleRatchet {
             dh_secret,
             dh_public,
             peer_dh_public: None,
+            dh_ratchet_steps: 0,
         }
     }
     
+    /// Initialize as Bob with his DH key and Alice's first one, both exchanged
+    /// during the handshake
+    ///
+    /// Bob ratchets on Alice's key immediately, so he can send before
+    /// receiving anything from her.
+    pub fn new_bob_with_peer(shared_secret: &[u8; 32], dh_secret: x25519_dalek::StaticSecret, peer_public_key: &[u8; 32]) -> Self {
+        let dh_public = x25519_dalek::PublicKey::from(&dh_secret);
+        
+        let mut ratchet = Self {
+            root_key: *shared_secret,
+            sending_chain_key: None,
+            receiving_chain_key: None,
+            sending_message_number: 0,
+            receiving_message_number: 0,
+            previous_chain_length: 0,
+            skipped_message_keys: HashMap::new(),
+            dh_secret,
+            dh_public,
+            peer_dh_public: None,
+            dh_ratchet_steps: 0,
+        };
+        
+        ratchet.dh_ratchet_step(&x25519_dalek::PublicKey::from(*peer_public_key));
+        ratchet
+    }
+    
     /// Get our current public DH key for sending to peer
     pub fn public_key(&self) -> &[u8; 32] {
         self.dh_public.as_bytes()
     }
     
+    /// Number of DH ratchet steps performed, i.e. how often fresh DH keys were mixed in
+    pub fn dh_ratchet_steps(&self) -> u64 {
+        self.dh_ratchet_steps
+    }
+    
     /// Encrypt a message
     pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<EncryptedMessage> {
         // Initialize sending chain if this is the first message
@@ -111,8 +181,8 @@ impl DoubleRatchet {
         
         self.sending_message_number += 1;
         
-        // Encrypt with message key
-        let ciphertext = self.encrypt_with_key(&message_key, plaintext)?;
+        // Encrypt with message key, authenticating the header
+        let ciphertext = self.encrypt_with_key(&message_key, &header, plaintext)?;
         
         Ok(EncryptedMessage {
             header,
@@ -121,13 +191,31 @@ impl DoubleRatchet {
     }
     
     /// Decrypt a message
+    ///
+    /// The ratchet only advances when the message authenticates, so replayed
+    /// or forged messages are rejected without desynchronizing it.
     pub fn decrypt(&mut self, encrypted: &EncryptedMessage) -> Result<Vec<u8>> {
-        // Check if we have a skipped message key
-        let key_id = (encrypted.header.previous_chain_length, encrypted.header.message_number);
-        if let Some(message_key) = self.skipped_message_keys.remove(&key_id) {
-            return self.decrypt_with_key(&message_key, &encrypted.ciphertext);
+        // Check if we have a skipped message key; it is used up on success
+        let key_id = (encrypted.header.dh_public, encrypted.header.message_number);
+        if let Some(message_key) = self.skipped_message_keys.get(&key_id).copied() {
+            let plaintext = self.decrypt_with_key(&message_key, &encrypted.header, &encrypted.ciphertext)?;
+            self.skipped_message_keys.remove(&key_id);
+            return Ok(plaintext);
+        }
+        
+        let peer_public = x25519_dalek::PublicKey::from(encrypted.header.dh_public);
+        if self.peer_dh_public.as_ref() == Some(&peer_public) && encrypted.header.message_number < self.receiving_message_number {
+            return Err(anyhow::anyhow!("Message {} was already received", encrypted.header.message_number));
         }
         
+        let mut next = self.clone();
+        let plaintext = next.ratchet_and_decrypt(encrypted)?;
+        *self = next;
+        Ok(plaintext)
+    }
+    
+    /// Advance the receiving chain to the message and decrypt it
+    fn ratchet_and_decrypt(&mut self, encrypted: &EncryptedMessage) -> Result<Vec<u8>> {
         // Check if we need to perform DH ratchet
         let peer_public = x25519_dalek::PublicKey::from(encrypted.header.dh_public);
         if self.peer_dh_public.as_ref() != Some(&peer_public) {
@@ -141,12 +229,13 @@ impl DoubleRatchet {
         let message_key = self.get_receiving_message_key()?;
         self.receiving_message_number += 1;
         
-        self.decrypt_with_key(&message_key, &encrypted.ciphertext)
+        self.decrypt_with_key(&message_key, &encrypted.header, &encrypted.ciphertext)
     }
     
     /// Perform DH ratchet step
     fn dh_ratchet_step(&mut self, peer_public: &x25519_dalek::PublicKey) {
         self.peer_dh_public = Some(*peer_public);
+        self.dh_ratchet_steps += 1;
         
         // Derive receiving chain key
         let dh_output = self.dh_secret.diffie_hellman(peer_public);
@@ -222,17 +311,17 @@ impl DoubleRatchet {
     
     /// Skip message keys for out-of-order delivery
     fn skip_message_keys(&mut self, until: u32) -> Result<()> {
-        if self.receiving_message_number + (MAX_SKIP as u32) < until {
+        if self.receiving_message_number.saturating_add(MAX_SKIP as u32) < until {
             return Err(anyhow::anyhow!("Too many skipped messages"));
         }
         
-        if let Some(mut chain_key) = self.receiving_chain_key {
+        if let (Some(mut chain_key), Some(peer_public)) = (self.receiving_chain_key, self.peer_dh_public) {
             while self.receiving_message_number < until {
                 let (new_chain_key, message_key) = self.kdf_ck(&chain_key);
                 chain_key = new_chain_key; // Update chain_key for next iteration
                 self.receiving_chain_key = Some(chain_key);
                 
-                let key_id = (self.previous_chain_length, self.receiving_message_number);
+                let key_id = (*peer_public.as_bytes(), self.receiving_message_number);
                 self.skipped_message_keys.insert(key_id, message_key);
                 
                 self.receiving_message_number += 1;
@@ -242,21 +331,38 @@ impl DoubleRatchet {
         Ok(())
     }
     
+    /// Nonce derived from the message key, the chain (sender's DH key) and the
+    /// message number
+    ///
+    /// No two messages share a key/nonce pair, and a header claiming another
+    /// chain or number than the key was derived for fails to authenticate.
+    fn message_nonce(&self, key: &[u8; 32], header: &MessageHeader) -> [u8; 12] {
+        let hk = Hkdf::<Sha256>::new(Some(&header.dh_public), key);
+        let mut info = b"JetStreamProto-MessageNonce".to_vec();
+        info.extend_from_slice(&header.message_number.to_be_bytes());
+        let mut nonce = [0u8; 12];
+        hk.expand(&info, &mut nonce)
+            .expect("HKDF expand failed");
+        nonce
+    }
+    
     /// Encrypt with a specific message key
-    fn encrypt_with_key(&self, key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>> {
+    fn encrypt_with_key(&self, key: &[u8; 32], header: &MessageHeader, plaintext: &[u8]) -> Result<Vec<u8>> {
         let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
-        let nonce = Nonce::from_slice(&[0u8; 12]); // In production, use proper nonce
+        let nonce = self.message_nonce(key, header);
+        let aad = header.to_bytes();
         
-        cipher.encrypt(nonce, plaintext)
+        cipher.encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: &aad })
             .map_err(|_| anyhow::anyhow!("Encryption failed"))
     }
     
     /// Decrypt with a specific message key
-    fn decrypt_with_key(&self, key: &[u8; 32], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    fn decrypt_with_key(&self, key: &[u8; 32], header: &MessageHeader, ciphertext: &[u8]) -> Result<Vec<u8>> {
         let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
-        let nonce = Nonce::from_slice(&[0u8; 12]); // In production, use proper nonce
+        let nonce = self.message_nonce(key, header);
+        let aad = header.to_bytes();
         
-        cipher.decrypt(nonce, ciphertext)
+        cipher.decrypt(Nonce::from_slice(&nonce), Payload { msg: ciphertext, aad: &aad })
             .map_err(|_| anyhow::anyhow!("Decryption failed"))
     }
 }
@@ -269,6 +375,30 @@ pub struct MessageHeader {
     pub message_number: u32,
 }
 
+impl MessageHeader {
+    /// `[dh_public (32)][previous_chain_length u32 BE][message_number u32 BE]`
+    pub fn to_bytes(&self) -> [u8; MESSAGE_HEADER_LEN] {
+        let mut bytes = [0u8; MESSAGE_HEADER_LEN];
+        bytes[..32].copy_from_slice(&self.dh_public);
+        bytes[32..36].copy_from_slice(&self.previous_chain_length.to_be_bytes());
+        bytes[36..40].copy_from_slice(&self.message_number.to_be_bytes());
+        bytes
+    }
+    
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < MESSAGE_HEADER_LEN {
+            return None;
+        }
+        let mut dh_public = [0u8; 32];
+        dh_public.copy_from_slice(&bytes[..32]);
+        Some(Self {
+            dh_public,
+            previous_chain_length: u32::from_be_bytes(bytes[32..36].try_into().ok()?),
+            message_number: u32::from_be_bytes(bytes[36..40].try_into().ok()?),
+        })
+    }
+}
+
 /// Encrypted message with header
 #[derive(Debug, Clone)]
 pub struct EncryptedMessage {
@@ -276,6 +406,23 @@ pub struct EncryptedMessage {
     pub ciphertext: Vec<u8>,
 }
 
+impl EncryptedMessage {
+    /// Header followed by the ciphertext, as carried in a data frame
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MESSAGE_HEADER_LEN + self.ciphertext.len());
+        bytes.extend_from_slice(&self.header.to_bytes());
+        bytes.extend_from_slice(&self.ciphertext);
+        bytes
+    }
+    
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(Self {
+            header: MessageHeader::from_bytes(bytes)?,
+            ciphertext: bytes[MESSAGE_HEADER_LEN..].to_vec(),
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -334,4 +481,121 @@ mod tests {
         let dec2 = bob.decrypt(&msg2).unwrap();
         assert_eq!(dec2, b"Message 2");
     }
+
+    #[test]
+    fn test_either_side_sends_first() {
+        let shared_secret = [7u8; 32];
+        let alice_secret = x25519_dalek::StaticSecret::random_from_rng(rand_core::OsRng);
+        let bob_secret = x25519_dalek::StaticSecret::random_from_rng(rand_core::OsRng);
+        let alice_public = *x25519_dalek::PublicKey::from(&alice_secret).as_bytes();
+        let bob_public = *x25519_dalek::PublicKey::from(&bob_secret).as_bytes();
+        
+        let mut alice = DoubleRatchet::new_alice_with_secret(&shared_secret, alice_secret, &bob_public);
+        let mut bob = DoubleRatchet::new_bob_with_peer(&shared_secret, bob_secret, &alice_public);
+        
+        // Bob first, then alternate so every turn is a DH ratchet step
+        for round in 0..5u8 {
+            let msg = bob.encrypt(&[round]).unwrap();
+            assert_eq!(alice.decrypt(&msg).unwrap(), [round]);
+            let msg = alice.encrypt(&[round, round]).unwrap();
+            assert_eq!(bob.decrypt(&msg).unwrap(), [round, round]);
+        }
+        assert_eq!(alice.dh_ratchet_steps(), 5);
+        assert_eq!(bob.dh_ratchet_steps(), 6);
+    }
+
+    #[test]
+    fn test_header_is_authenticated() {
+        let shared_secret = [42u8; 32];
+        let mut bob = DoubleRatchet::new_bob(&shared_secret);
+        let mut alice = DoubleRatchet::new_alice(&shared_secret, bob.public_key());
+        
+        let msg = alice.encrypt(b"same").unwrap();
+        let again = alice.encrypt(b"same").unwrap();
+        assert_ne!(msg.ciphertext, again.ciphertext);
+        
+        // Roundtrip through the wire encoding
+        let decoded = EncryptedMessage::from_bytes(&msg.to_bytes()).unwrap();
+        assert_eq!(decoded.header.message_number, 0);
+        
+        // Same message key, different header
+        let mut tampered = decoded.clone();
+        tampered.header.previous_chain_length = 5;
+        assert!(bob.decrypt(&tampered).is_err());
+        assert!(EncryptedMessage::from_bytes(&[0u8; 10]).is_none());
+    }
+
+    /// Alice and Bob after the handshake, before any message
+    fn pair() -> (DoubleRatchet, DoubleRatchet) {
+        let shared_secret = [9u8; 32];
+        let bob = DoubleRatchet::new_bob(&shared_secret);
+        let alice = DoubleRatchet::new_alice(&shared_secret, bob.public_key());
+        (alice, bob)
+    }
+
+    #[test]
+    fn test_replayed_ciphertext_rejected() {
+        let (mut alice, mut bob) = pair();
+        
+        let msg1 = alice.encrypt(b"one").unwrap();
+        let msg2 = alice.encrypt(b"two").unwrap();
+        let msg3 = alice.encrypt(b"three").unwrap();
+        
+        // In order, then replayed
+        assert_eq!(bob.decrypt(&msg1).unwrap(), b"one");
+        assert!(bob.decrypt(&msg1).is_err());
+        
+        // A skipped key is used up by the first delivery
+        assert_eq!(bob.decrypt(&msg3).unwrap(), b"three");
+        assert_eq!(bob.decrypt(&msg2).unwrap(), b"two");
+        assert!(bob.decrypt(&msg2).is_err());
+        assert!(bob.decrypt(&msg3).is_err());
+        
+        // Rejections didn't desynchronize the ratchet
+        let msg4 = alice.encrypt(b"four").unwrap();
+        assert_eq!(bob.decrypt(&msg4).unwrap(), b"four");
+        let reply = bob.encrypt(b"reply").unwrap();
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"reply");
+    }
+
+    #[test]
+    fn test_forged_header_leaves_ratchet_intact() {
+        let (mut alice, mut bob) = pair();
+        
+        let msg = alice.encrypt(b"hello").unwrap();
+        
+        // Claiming a later message number or a new chain fails without side effects
+        let mut forged = msg.clone();
+        forged.header.message_number = 5;
+        assert!(bob.decrypt(&forged).is_err());
+        let mut forged = msg.clone();
+        forged.header.dh_public = [1u8; 32];
+        assert!(bob.decrypt(&forged).is_err());
+        assert_eq!(bob.dh_ratchet_steps(), 0);
+        
+        assert_eq!(bob.decrypt(&msg).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_out_of_order_across_chains() {
+        let (mut alice, mut bob) = pair();
+        
+        // Chain 1: message 0 is delayed
+        let chain1_msg0 = alice.encrypt(b"chain1-0").unwrap();
+        let chain1_msg1 = alice.encrypt(b"chain1-1").unwrap();
+        assert_eq!(bob.decrypt(&chain1_msg1).unwrap(), b"chain1-1");
+        
+        let reply = bob.encrypt(b"reply").unwrap();
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"reply");
+        
+        // Chain 2 reuses message number 0 and must not pick chain 1's skipped key
+        let chain2_msg0 = alice.encrypt(b"chain2-0").unwrap();
+        assert_eq!(chain2_msg0.header.message_number, chain1_msg0.header.message_number);
+        assert_ne!(chain2_msg0.header.dh_public, chain1_msg0.header.dh_public);
+        assert_eq!(bob.decrypt(&chain2_msg0).unwrap(), b"chain2-0");
+        
+        // The delayed message still opens with its own chain's key
+        assert_eq!(bob.decrypt(&chain1_msg0).unwrap(), b"chain1-0");
+        assert!(bob.decrypt(&chain1_msg0).is_err());
+    }
 }
@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 
 /// Quality of Service priority levels
@@ -42,6 +43,42 @@ impl QosPriority {
     }
 }
 
+/// Share of send opportunities each priority gets while all of them have data
+///
+/// With the default 8:4:2:1, Bulk still gets one packet in every fifteen
+/// however much higher-priority data is queued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QosWeights {
+    pub system: u32,
+    pub media: u32,
+    pub chat: u32,
+    pub bulk: u32,
+}
+
+impl QosWeights {
+    /// Weight of one priority level
+    pub fn weight(&self, priority: QosPriority) -> u32 {
+        match priority {
+            QosPriority::System => self.system,
+            QosPriority::Media => self.media,
+            QosPriority::Chat => self.chat,
+            QosPriority::Bulk => self.bulk,
+        }
+    }
+}
+
+impl Default for QosWeights {
+    fn default() -> Self {
+        Self {
+            system: QosPriority::System.weight() as u32,
+            media: QosPriority::Media.weight() as u32,
+            chat: QosPriority::Chat.weight() as u32,
+            bulk: QosPriority::Bulk.weight() as u32,
+        }
+    }
+}
+
 impl Default for QosPriority {
     fn default() -> Self {
         QosPriority::Chat
@@ -152,6 +189,14 @@ mod tests {
         assert_eq!(QosPriority::Bulk.weight(), 1);
     }
     
+    #[test]
+    fn test_default_weights_match_priorities() {
+        let weights = QosWeights::default();
+        for priority in [QosPriority::System, QosPriority::Media, QosPriority::Chat, QosPriority::Bulk] {
+            assert_eq!(weights.weight(priority) as usize, priority.weight());
+        }
+    }
+    
     #[test]
     fn test_qos_classes() {
         assert_eq!(QosClass::SYSTEM.priority, QosPriority::System);
@@ -2,6 +2,7 @@ use std::collections::HashSet;
 use std::time::{SystemTime, Duration};
 
 /// Защита от replay-атак для 0-RTT
+#[derive(Debug)]
 pub struct ReplayProtection {
     /// Sliding window для nonce tracking
     nonce_window: HashSet<u64>,
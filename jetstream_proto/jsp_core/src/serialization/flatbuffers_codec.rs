@@ -119,6 +119,10 @@ impl FlatBuffersCodec {
             timestamp: fb_hello.timestamp(),
             connection_id: ConnectionId::from_u64(fb_hello.connection_id().unwrap_or(0)),
             supported_formats: fb_hello.supported_formats().map(|v| v.iter().collect()).unwrap_or_default(),
+            // Resumption and the Double Ratchet are only negotiated over CBOR
+            session_ticket: None,
+            ratchet_public_key: None,
+            retry_token: None,
         })
     }
 
@@ -161,6 +165,9 @@ impl FlatBuffersCodec {
             kyber_ciphertext: fb_hello.kyber_ciphertext().map(|v| v.iter().collect()).unwrap_or_default(),
             connection_id: ConnectionId::from_u64(fb_hello.connection_id().unwrap_or(0)),
             selected_format: fb_hello.selected_format(),
+            // Resumption and the Double Ratchet are only negotiated over CBOR
+            resumed: false,
+            ratchet_public_key: None,
         })
     }
 }
@@ -233,6 +240,9 @@ mod tests {
             timestamp: 88888,
             connection_id: ConnectionId::from_u64(54321),
             supported_formats: vec![0, 1],
+            session_ticket: None,
+            ratchet_public_key: None,
+            retry_token: None,
         };
         
         let serialized = FlatBuffersCodec::serialize_client_hello(&hello);
@@ -261,6 +271,8 @@ mod tests {
             kyber_ciphertext: vec![6u8; 100],
             connection_id: ConnectionId::from_u64(98765),
             selected_format: 1,
+            resumed: false,
+            ratchet_public_key: None,
         };
         
         let serialized = FlatBuffersCodec::serialize_server_hello(&hello);
@@ -11,11 +11,12 @@ pub enum SessionState {
     Established,
 }
 
-use crate::crypto::{CryptoContext, CipherSuite};
+use crate::crypto::{CryptoContext, CipherSuite, seal_ticket_state};
 use crate::types::handshake::{ClientHello, ServerHello};
 use crate::types::control::{SessionConfig, SessionTicket};
 use crate::stream::StreamManager;
 use crate::replay_protection::ReplayProtection;
+use crate::double_ratchet::DoubleRatchet;
 use anyhow::Result;
 
 const TLS_AES_256_GCM_SHA384: u16 = 0x1302;
@@ -39,12 +40,22 @@ pub struct Session {
     
     // 0-RTT resumption
     pub session_ticket: Option<SessionTicket>,
+    resumed: bool,
     
     // Replay protection
     replay_protection: Option<ReplayProtection>,
     
-    // Serialization format negotiated during handshake
+    // Serialization format negotiated during handshake, and the ones we
+    // offer (client) or accept (server) in order of preference
     serialization_format: SerializationFormat,
+    serialization_formats: Vec<SerializationFormat>,
+    
+    // Double Ratchet over data frames: our key until the root key is known,
+    // the peer's key from its hello, and the ratchet once both are set
+    double_ratchet_enabled: bool,
+    ratchet_secret: Option<x25519_dalek::StaticSecret>,
+    peer_ratchet_key: Option<[u8; 32]>,
+    ratchet: Option<DoubleRatchet>,
 }
 
 impl Default for Session {
@@ -82,11 +93,50 @@ impl Session {
             config,
             streams: StreamManager::new(config.max_streams),
             session_ticket: None,
+            resumed: false,
             replay_protection,
             serialization_format: SerializationFormat::default(), // Default to CBOR
+            serialization_formats: vec![SerializationFormat::Cbor],
+            double_ratchet_enabled: false,
+            ratchet_secret: None,
+            peer_ratchet_key: None,
+            ratchet: None,
         }
     }
 
+    /// Request (client) or accept (server) a Double Ratchet in the handshake
+    ///
+    /// Has no effect on resumed sessions, which use a plain key derived from the ticket.
+    pub fn enable_double_ratchet(&mut self) {
+        self.double_ratchet_enabled = true;
+    }
+
+    /// Header formats to offer (client) or accept (server), most preferred first
+    ///
+    /// CBOR is used whenever the peers share nothing else, so it is always
+    /// understood even when left out. Only CBOR is offered by default: peers
+    /// from before formats were negotiated offer FlatBuffers without speaking it.
+    pub fn set_serialization_formats(&mut self, formats: &[SerializationFormat]) {
+        self.serialization_formats = formats.to_vec();
+    }
+
+    /// The Double Ratchet, if both sides negotiated one
+    pub fn double_ratchet_mut(&mut self) -> Option<&mut DoubleRatchet> {
+        self.ratchet.as_mut()
+    }
+
+    pub fn double_ratchet(&self) -> Option<&DoubleRatchet> {
+        self.ratchet.as_ref()
+    }
+
+    /// Fresh DH key for the ratchet, whose public half goes in our hello
+    fn offer_ratchet_key(&mut self) -> [u8; 32] {
+        let secret = x25519_dalek::StaticSecret::random_from_rng(rand_core::OsRng);
+        let public = *x25519_dalek::PublicKey::from(&secret).as_bytes();
+        self.ratchet_secret = Some(secret);
+        public
+    }
+
     /// Check if session has expired due to inactivity
     pub fn is_expired(&self) -> bool {
         let idle_duration = self.last_activity.elapsed();
@@ -148,14 +198,18 @@ impl Session {
     }
 
     /// Generate session ticket for 0-RTT resumption
-    pub fn generate_session_ticket(&self) -> Result<SessionTicket> {
+    ///
+    /// The ticket carries this session's resumption secret sealed with
+    /// `ticket_key`, which only the server knows; the session key itself
+    /// never leaves the session.
+    pub fn generate_session_ticket(&self, ticket_key: &[u8; 32]) -> Result<SessionTicket> {
         use rand_core::RngCore;
         
         let mut ticket_id = [0u8; 32];
         rand_core::OsRng.fill_bytes(&mut ticket_id);
         
-        // Export session state (simplified - in production, encrypt this properly)
-        let state = self.crypto.export_session_state()?;
+        let secret = self.crypto.resumption_secret()?;
+        let state = seal_ticket_state(ticket_key, &ticket_id, &secret)?;
         
         let now = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)?
@@ -166,28 +220,59 @@ impl Session {
             encrypted_state: state,
             created_at: now,
             lifetime: 3600, // 1 hour
+            resumption_secret: None,
         })
     }
 
-    /// Validate and import session ticket for 0-RTT
+    /// Keep a ticket the server issued, along with our copy of its secret
+    pub fn receive_session_ticket(&mut self, mut ticket: SessionTicket) -> Result<()> {
+        ticket.resumption_secret = Some(self.crypto.resumption_secret()?);
+        self.session_ticket = Some(ticket);
+        Ok(())
+    }
+
+    /// Validate a session ticket before offering it for 0-RTT
     pub fn import_session_ticket(&mut self, ticket: &SessionTicket) -> Result<()> {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)?
-            .as_secs();
-        
         // Check ticket expiration
-        if now > ticket.created_at + ticket.lifetime as u64 {
+        if ticket.is_expired() {
             return Err(anyhow::anyhow!("Session ticket expired"));
         }
+        if ticket.resumption_secret.is_none() {
+            return Err(anyhow::anyhow!("Session ticket has no resumption secret"));
+        }
         
-        // Import session state
-        self.crypto.import_session_state(&ticket.encrypted_state)?;
         self.session_ticket = Some(ticket.clone());
         
         Ok(())
     }
 
+    /// Whether the handshake resumed a previous session from a ticket
+    pub fn is_resumed(&self) -> bool {
+        self.resumed
+    }
+
     pub fn generate_client_hello(&mut self) -> Result<Vec<u8>, anyhow::Error> {
+        self.build_client_hello(None)
+    }
+
+    /// Generate a ClientHello offering a session ticket for 0-RTT resumption
+    ///
+    /// The early data key is derived from the ticket's resumption secret and
+    /// the hello's random right away, so early data can be sent before the
+    /// ServerHello arrives. The hello still carries fresh key shares, so the
+    /// server can fall back to a full handshake if it rejects the ticket.
+    pub fn generate_resumption_hello(&mut self, ticket: &SessionTicket) -> Result<Vec<u8>, anyhow::Error> {
+        self.import_session_ticket(ticket)?;
+        // Our copy of the secret stays here
+        let offered = SessionTicket { resumption_secret: None, ..ticket.clone() };
+        let hello = self.build_client_hello(Some(offered))?;
+        if let Some(secret) = &ticket.resumption_secret {
+            self.crypto.derive_early_key(secret, &self.client_random);
+        }
+        Ok(hello)
+    }
+
+    fn build_client_hello(&mut self, session_ticket: Option<SessionTicket>) -> Result<Vec<u8>, anyhow::Error> {
         use rand_core::RngCore;
         
         self.state = SessionState::HelloSent;
@@ -205,6 +290,10 @@ impl Session {
             .duration_since(std::time::UNIX_EPOCH)?
             .as_secs();
         
+        // Resumed sessions use a plain key derived from the ticket
+        let ratchet_public_key = (self.double_ratchet_enabled && session_ticket.is_none())
+            .then(|| self.offer_ratchet_key());
+        
         let hello = ClientHello {
             version: 1,
             random: self.client_random,
@@ -215,11 +304,11 @@ impl Session {
             nonce,
             timestamp,
             connection_id: ConnectionId::generate(),
-            // Advertise supported serialization formats (prefer FlatBuffers, fallback to CBOR)
-            supported_formats: vec![
-                SerializationFormat::FlatBuffers.to_byte(),
-                SerializationFormat::Cbor.to_byte(),
-            ],
+            // Advertise supported serialization formats, most preferred first
+            supported_formats: self.serialization_formats.iter().map(|format| format.to_byte()).collect(),
+            session_ticket,
+            ratchet_public_key,
+            retry_token: None,
         };
         Ok(serde_cbor::to_vec(&hello)?)
     }
@@ -227,18 +316,41 @@ impl Session {
     pub fn process_server_hello(&mut self, data: &[u8]) -> Result<(), anyhow::Error> {
         let hello: ServerHello = serde_cbor::from_slice(data)?;
         
+        // The server may only pick a format we offered, or CBOR
+        let format = SerializationFormat::from_byte(hello.selected_format)
+            .filter(|format| *format == SerializationFormat::Cbor || self.serialization_formats.contains(format))
+            .ok_or_else(|| anyhow::anyhow!("Server selected serialization format {} that was not offered", hello.selected_format))?;
+        
         self.update_activity();
         
-        // Decapsulate Kyber ciphertext to get shared secret
-        let kyber_shared = self.crypto.decapsulate_kyber(&hello.kyber_ciphertext)?;
+        if hello.resumed {
+            let secret = self.session_ticket.as_ref()
+                .and_then(|ticket| ticket.resumption_secret)
+                .ok_or_else(|| anyhow::anyhow!("Server resumed a session without a ticket"))?;
+            self.crypto.derive_resumed_key(&secret, &self.client_random, &hello.random);
+        } else {
+            // Decapsulate Kyber ciphertext to get shared secret
+            let kyber_shared = self.crypto.decapsulate_kyber(&hello.kyber_ciphertext)?;
+            
+            // Derive shared secret using HKDF
+            self.crypto.derive_shared_secret(
+                &hello.public_key,
+                Some(&kyber_shared),
+                &self.client_random,
+                &hello.random
+            );
+            
+            // Client is Alice; the server answered our ratchet key with its own
+            if let (Some(secret), Some(server_key)) = (self.ratchet_secret.take(), hello.ratchet_public_key) {
+                let root = self.crypto.ratchet_root_key()?;
+                self.ratchet = Some(DoubleRatchet::new_alice_with_secret(&root, secret, &server_key));
+            }
+        }
+        self.ratchet_secret = None;
         
-        // Derive shared secret using HKDF
-        self.crypto.derive_shared_secret(
-            &hello.public_key,
-            Some(&kyber_shared),
-            &self.client_random,
-            &hello.random
-        );
+        // Tickets are single-use; the server issues a fresh one after the handshake
+        self.session_ticket = None;
+        self.resumed = hello.resumed;
         
         // Set negotiated cipher suite
         let suite = match hello.cipher_suite {
@@ -252,12 +364,7 @@ impl Session {
         self.state = SessionState::Established;
         
         // Apply negotiated serialization format
-        if let Some(format) = SerializationFormat::from_byte(hello.selected_format) {
-            self.serialization_format = format;
-        } else {
-            // Fallback to CBOR if unknown format
-            self.serialization_format = SerializationFormat::Cbor;
-        }
+        self.serialization_format = format;
         
         Ok(())
     }
@@ -284,6 +391,7 @@ impl Session {
         
         // Store client random for key derivation
         self.client_random = hello.random;
+        self.peer_ratchet_key = hello.ratchet_public_key;
         
         Ok(hello)
     }
@@ -308,18 +416,11 @@ impl Session {
         // Encapsulate Kyber shared secret
         let (kyber_ciphertext, kyber_shared) = self.crypto.encapsulate_kyber(client_kyber_pk)?;
         
-        // Select serialization format from client's supported formats
-        // Prefer FlatBuffers if client supports it, otherwise fallback to CBOR
-        let selected_format = supported_formats.iter()
-            .find(|&&f| f == SerializationFormat::FlatBuffers.to_byte())
-            .or_else(|| supported_formats.first())
-            .copied()
-            .unwrap_or(SerializationFormat::Cbor.to_byte());
+        let selected_format = self.select_serialization_format(supported_formats);
         
-        // Apply selected format to session
-        if let Some(format) = SerializationFormat::from_byte(selected_format) {
-            self.serialization_format = format;
-        }
+        // Answer a ratchet request with our key; the ratchet starts once keys are derived
+        let ratchet_public_key = (self.double_ratchet_enabled && self.peer_ratchet_key.is_some())
+            .then(|| self.offer_ratchet_key());
         
         let hello = ServerHello {
             version: 1,
@@ -330,6 +431,8 @@ impl Session {
             kyber_ciphertext,
             connection_id: ConnectionId::generate(),
             selected_format,
+            resumed: false,
+            ratchet_public_key,
         };
         
         self.session_id = session_id;
@@ -337,6 +440,68 @@ impl Session {
         
         Ok((serde_cbor::to_vec(&hello)?, kyber_shared))
     }
+
+    /// Generate a ServerHello that resumes a session from a validated ticket
+    ///
+    /// `state` is the resumption secret opened from the ticket. The Kyber
+    /// encapsulation is skipped; the early data key and the new session key
+    /// are derived from `state` and the hello randoms, as the client does.
+    pub fn generate_resumed_server_hello(&mut self, session_id: u64, cipher_suite: u16, state: &[u8], supported_formats: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        use rand_core::RngCore;
+        
+        self.update_activity();
+        
+        let suite = match cipher_suite {
+            TLS_CHACHA20_POLY1305_SHA256 => CipherSuite::ChaCha20Poly1305,
+            TLS_AES_256_GCM_SHA384 => CipherSuite::Aes256Gcm,
+            _ => return Err(anyhow::anyhow!("Unsupported cipher suite: {:x}", cipher_suite)),
+        };
+        self.crypto.set_cipher_suite(suite);
+        let secret: [u8; 32] = state.try_into()
+            .map_err(|_| anyhow::anyhow!("Invalid resumption secret length"))?;
+        
+        let mut rng = rand_core::OsRng;
+        rng.fill_bytes(&mut self.server_random);
+        
+        self.crypto.derive_early_key(&secret, &self.client_random);
+        self.crypto.derive_resumed_key(&secret, &self.client_random, &self.server_random);
+        
+        let selected_format = self.select_serialization_format(supported_formats);
+        
+        let hello = ServerHello {
+            version: 1,
+            random: self.server_random,
+            session_id,
+            cipher_suite,
+            public_key: *self.crypto.x25519_public_key(),
+            kyber_ciphertext: Vec::new(),
+            connection_id: ConnectionId::generate(),
+            selected_format,
+            resumed: true,
+            ratchet_public_key: None,
+        };
+        
+        self.session_id = session_id;
+        self.state = SessionState::Established;
+        self.resumed = true;
+        
+        Ok(serde_cbor::to_vec(&hello)?)
+    }
+
+    /// Select serialization format from client's supported formats
+    ///
+    /// Picks the first of the client's formats that we accept too, falling
+    /// back to CBOR when there is none.
+    fn select_serialization_format(&mut self, supported_formats: &[u8]) -> u8 {
+        let format = supported_formats.iter()
+            .filter_map(|&byte| SerializationFormat::from_byte(byte))
+            .find(|format| self.serialization_formats.contains(format))
+            .unwrap_or(SerializationFormat::Cbor);
+        
+        // Apply selected format to session
+        self.serialization_format = format;
+        format.to_byte()
+    }
     
     /// Get the negotiated serialization format for this session
     pub fn serialization_format(&self) -> SerializationFormat {
@@ -353,5 +518,13 @@ impl Session {
             &self.client_random,
             &self.server_random
         );
+        
+        // Server is Bob and already knows the client's first ratchet key
+        if let (Some(secret), Some(client_key)) = (self.ratchet_secret.take(), self.peer_ratchet_key.take()) {
+            match self.crypto.ratchet_root_key() {
+                Ok(root) => self.ratchet = Some(DoubleRatchet::new_bob_with_peer(&root, secret, &client_key)),
+                Err(e) => tracing::warn!("Double Ratchet not started: {}", e),
+            }
+        }
     }
 }
@@ -1,5 +1,7 @@
 #[cfg(test)]
 mod tests {
+    use crate::codec::SerializationFormat;
+    use crate::crypto::open_ticket_state;
     use crate::session::Session;
     
     #[test]
@@ -79,4 +81,136 @@ mod tests {
         
         assert_eq!(plaintext.to_vec(), decrypted);
     }
+
+    #[test]
+    fn test_ticket_resumption_skips_key_exchange() {
+        // Full handshake to obtain a ticket
+        let mut client_session = Session::new();
+        let mut server_session = Session::new();
+        let client_hello = server_session.process_client_hello(&client_session.generate_client_hello().unwrap()).unwrap();
+        let (server_hello_bytes, kyber_shared) = server_session.generate_server_hello(
+            1,
+            0x1303,
+            &client_hello.kyber_public_key,
+            &client_hello.supported_formats
+        ).unwrap();
+        server_session.derive_keys_from_client_hello(&client_hello.public_key, Some(&kyber_shared));
+        client_session.process_server_hello(&server_hello_bytes).unwrap();
+        let ticket_key = [9u8; 32];
+        client_session.receive_session_ticket(server_session.generate_session_ticket(&ticket_key).unwrap()).unwrap();
+        let ticket = client_session.session_ticket.clone().unwrap();
+        
+        // Resume with the ticket
+        let mut resumed_client = Session::new();
+        let hello_bytes = resumed_client.generate_resumption_hello(&ticket).unwrap();
+        let early = resumed_client.crypto.encrypt_early_packet(0, 0, b"early", b"header").unwrap();
+        
+        let mut resumed_server = Session::new();
+        let hello = resumed_server.process_client_hello(&hello_bytes).unwrap();
+        let offered = hello.session_ticket.expect("ticket should be offered");
+        assert_eq!(offered.ticket_id, ticket.ticket_id);
+        assert!(offered.resumption_secret.is_none());
+        
+        let state = open_ticket_state(&ticket_key, &offered.ticket_id, &offered.encrypted_state).unwrap();
+        let server_hello_bytes = resumed_server.generate_resumed_server_hello(
+            2,
+            0x1303,
+            &state,
+            &hello.supported_formats
+        ).unwrap();
+        assert_eq!(resumed_server.crypto.decrypt_early_packet(0, 0, &early, b"header").unwrap(), b"early");
+        resumed_client.process_server_hello(&server_hello_bytes).unwrap();
+        
+        assert!(resumed_client.is_resumed());
+        assert!(resumed_server.is_resumed());
+        assert!(resumed_client.session_ticket.is_none());
+        
+        let plaintext = b"0-RTT";
+        let ciphertext = resumed_client.crypto.encrypt(1, plaintext).unwrap();
+        let decrypted = resumed_server.crypto.decrypt(1, &ciphertext).unwrap();
+        assert_eq!(plaintext.to_vec(), decrypted);
+        
+        // The resumed key is new, so packet numbers starting over don't repeat a nonce
+        let original = client_session.crypto.encrypt(1, plaintext).unwrap();
+        assert_ne!(original, ciphertext);
+        assert!(resumed_server.crypto.decrypt(1, &original).is_err());
+    }
+
+    #[test]
+    fn test_ticket_state_is_sealed_to_its_ticket() {
+        let mut client_session = Session::new();
+        let mut server_session = Session::new();
+        let client_hello = server_session.process_client_hello(&client_session.generate_client_hello().unwrap()).unwrap();
+        let (server_hello_bytes, kyber_shared) = server_session.generate_server_hello(
+            1,
+            0x1303,
+            &client_hello.kyber_public_key,
+            &client_hello.supported_formats
+        ).unwrap();
+        server_session.derive_keys_from_client_hello(&client_hello.public_key, Some(&kyber_shared));
+        client_session.process_server_hello(&server_hello_bytes).unwrap();
+        
+        let ticket_key = [9u8; 32];
+        let ticket = server_session.generate_session_ticket(&ticket_key).unwrap();
+        let secret = server_session.crypto.resumption_secret().unwrap();
+        assert_eq!(client_session.crypto.resumption_secret().unwrap(), secret);
+        
+        assert_eq!(open_ticket_state(&ticket_key, &ticket.ticket_id, &ticket.encrypted_state).unwrap(), secret);
+        assert!(open_ticket_state(&[8u8; 32], &ticket.ticket_id, &ticket.encrypted_state).is_err());
+        assert!(open_ticket_state(&ticket_key, &[0u8; 32], &ticket.encrypted_state).is_err());
+        assert!(!ticket.encrypted_state.windows(32).any(|window| window == secret));
+    }
+
+    #[test]
+    fn test_expired_ticket_rejected() {
+        let mut session = Session::new();
+        let ticket = crate::types::control::SessionTicket {
+            ticket_id: [0u8; 32],
+            encrypted_state: vec![0u8; 32],
+            created_at: 0,
+            lifetime: 1,
+            resumption_secret: Some([0u8; 32]),
+        };
+        
+        assert!(session.generate_resumption_hello(&ticket).is_err());
+    }
+
+    /// Format each side ends up with when the client offers `offered` and the server accepts `accepted`
+    fn negotiate(offered: &[SerializationFormat], accepted: &[SerializationFormat]) -> (SerializationFormat, SerializationFormat) {
+        let mut client = Session::new();
+        client.set_serialization_formats(offered);
+        let mut server = Session::new();
+        server.set_serialization_formats(accepted);
+        
+        let hello = server.process_client_hello(&client.generate_client_hello().unwrap()).unwrap();
+        let (server_hello, _) = server.generate_server_hello(1, 0x1303, &hello.kyber_public_key, &hello.supported_formats).unwrap();
+        client.process_server_hello(&server_hello).unwrap();
+        (client.serialization_format(), server.serialization_format())
+    }
+
+    #[test]
+    fn test_serialization_format_negotiation() {
+        use SerializationFormat::{Cbor, FlatBuffers};
+        
+        // CBOR unless both sides opt in
+        assert_eq!(Session::new().serialization_format(), Cbor);
+        assert_eq!(negotiate(&[Cbor], &[Cbor]), (Cbor, Cbor));
+        assert_eq!(negotiate(&[FlatBuffers, Cbor], &[Cbor]), (Cbor, Cbor));
+        assert_eq!(negotiate(&[Cbor], &[FlatBuffers, Cbor]), (Cbor, Cbor));
+        assert_eq!(negotiate(&[FlatBuffers, Cbor], &[Cbor, FlatBuffers]), (FlatBuffers, FlatBuffers));
+        // Nothing shared falls back to CBOR, which every peer reads
+        assert_eq!(negotiate(&[FlatBuffers], &[Cbor]), (Cbor, Cbor));
+    }
+
+    #[test]
+    fn test_server_cannot_pick_unoffered_format() {
+        let mut client = Session::new();
+        let mut server = Session::new();
+        server.set_serialization_formats(&[SerializationFormat::FlatBuffers]);
+        
+        let hello = server.process_client_hello(&client.generate_client_hello().unwrap()).unwrap();
+        // A server that ignores the offer, as older ones did
+        let (server_hello, _) = server.generate_server_hello(1, 0x1303, &hello.kyber_public_key, &[SerializationFormat::FlatBuffers.to_byte()]).unwrap();
+        assert!(client.process_server_hello(&server_hello).is_err());
+    }
 }
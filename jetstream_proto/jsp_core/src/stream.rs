@@ -2,6 +2,10 @@ use std::collections::HashMap;
 use std::time::Instant;
 use crate::types::delivery::DeliveryMode;
 
+/// Stream IDs from here up are never handed out by `open_stream`; protocols
+/// built on a connection open them on both sides with `open_reserved_stream`
+pub const RESERVED_STREAM_ID_START: u32 = 0xFFFF_0000;
+
 /// Stream state for multiplexing
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StreamState {
@@ -110,6 +114,10 @@ impl StreamManager {
             return Err("Maximum streams reached");
         }
 
+        if self.next_stream_id >= RESERVED_STREAM_ID_START {
+            return Err("Stream IDs exhausted");
+        }
+
         let stream_id = self.next_stream_id;
         self.next_stream_id += 1;
 
@@ -120,6 +128,24 @@ impl StreamManager {
         Ok(stream_id)
     }
 
+    /// Open a stream with an ID from the reserved range; an open one is kept as is
+    pub fn open_reserved_stream(&mut self, stream_id: u32, priority: u8, delivery_mode: DeliveryMode) -> Result<(), &'static str> {
+        if stream_id < RESERVED_STREAM_ID_START {
+            return Err("Stream ID is not in the reserved range");
+        }
+        if self.streams.get(&stream_id).is_some_and(|stream| stream.is_active()) {
+            return Ok(());
+        }
+        if self.streams.len() >= self.max_streams as usize {
+            return Err("Maximum streams reached");
+        }
+
+        let mut stream = Stream::new(stream_id, priority, delivery_mode);
+        stream.open();
+        self.streams.insert(stream_id, stream);
+        Ok(())
+    }
+
     pub fn close_stream(&mut self, stream_id: u32) -> Result<(), &'static str> {
         let stream = self.streams.get_mut(&stream_id)
             .ok_or("Stream not found")?;
@@ -139,6 +165,11 @@ impl StreamManager {
         self.streams.get_mut(&stream_id)
     }
 
+    /// Every stream not yet cleaned up, in no particular order
+    pub fn streams(&self) -> impl Iterator<Item = &Stream> {
+        self.streams.values()
+    }
+
     pub fn active_stream_count(&self) -> usize {
         self.streams.values().filter(|s| s.is_active()).count()
     }
@@ -212,4 +243,19 @@ mod tests {
         assert!(result.is_err());
         assert_eq!(result.unwrap_err(), "Maximum streams reached");
     }
+
+    #[test]
+    fn test_reserved_streams() {
+        let mut manager = StreamManager::new(10);
+
+        assert!(manager.open_reserved_stream(5, 0, DeliveryMode::Reliable).is_err());
+        manager.open_reserved_stream(RESERVED_STREAM_ID_START, 0, DeliveryMode::BestEffort).unwrap();
+        // Opening it again keeps the existing stream
+        manager.open_reserved_stream(RESERVED_STREAM_ID_START, 3, DeliveryMode::Reliable).unwrap();
+        let stream = manager.get_stream(RESERVED_STREAM_ID_START).unwrap();
+        assert_eq!(stream.delivery_mode, DeliveryMode::BestEffort);
+
+        // Regular IDs are unaffected
+        assert_eq!(manager.open_stream(0, DeliveryMode::Reliable).unwrap(), 1);
+    }
 }
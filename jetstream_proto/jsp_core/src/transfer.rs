@@ -1,17 +1,19 @@
 use serde::{Serialize, Deserialize};
 use std::path::PathBuf;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::{self, Read, Write, Seek, SeekFrom};
 use std::collections::BTreeMap;
 use bytes::Bytes;
 
-/// Metadata for a file transfer
+/// Metadata for a file transfer (the manifest frame)
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct FileMetadata {
     pub filename: String,
     pub file_size: u64,
     pub mime_type: Option<String>,
-    pub checksum: Option<Vec<u8>>, // SHA256
+    pub checksum: Option<Vec<u8>>, // BLAKE3
+    pub chunk_size: u32,
+    pub chunk_count: u64,
 }
 
 /// Header for each file chunk
@@ -35,6 +37,51 @@ pub enum FileTransferFrame {
         header: ChunkHeader,
         data: Bytes,
     },
+    /// Receiver's answer to the metadata: chunks before `next_chunk` are already stored
+    Resume {
+        transfer_id: u64,
+        next_chunk: u64,
+    },
+    /// Every chunk before `next_chunk` has been written by the receiver
+    Ack {
+        transfer_id: u64,
+        next_chunk: u64,
+    },
+    /// All chunks received; `verified` tells whether the checksum matched
+    Complete {
+        transfer_id: u64,
+        verified: bool,
+    },
+}
+
+impl FileTransferFrame {
+    pub fn transfer_id(&self) -> u64 {
+        match self {
+            FileTransferFrame::Metadata { transfer_id, .. }
+            | FileTransferFrame::Resume { transfer_id, .. }
+            | FileTransferFrame::Ack { transfer_id, .. }
+            | FileTransferFrame::Complete { transfer_id, .. } => *transfer_id,
+            FileTransferFrame::Chunk { header, .. } => header.transfer_id,
+        }
+    }
+}
+
+/// Enough state to resume an interrupted transfer of the same file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ResumeToken {
+    pub transfer_id: u64,
+    /// BLAKE3 hash of the file, to refuse resuming after it changed
+    pub checksum: Vec<u8>,
+    /// First chunk the receiver had not confirmed
+    pub next_chunk: u64,
+}
+
+/// BLAKE3 hash of a file's contents
+pub fn file_checksum(file: &mut File) -> io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut hasher = blake3::Hasher::new();
+    io::copy(file, &mut hasher)?;
+    Ok(hasher.finalize().as_bytes().to_vec())
 }
 
 /// Helper to split file into chunks
@@ -43,24 +90,51 @@ pub struct FileSender {
     file_path: PathBuf,
     file_size: u64,
     chunk_size: u32,
+    checksum: Vec<u8>,
     file: File,
 }
 
 impl FileSender {
     pub fn new(transfer_id: u64, file_path: PathBuf, chunk_size: u32) -> io::Result<Self> {
-        let file = File::open(&file_path)?;
+        if chunk_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Chunk size must be non-zero"));
+        }
+        let mut file = File::open(&file_path)?;
         let metadata = file.metadata()?;
         let file_size = metadata.len();
+        let checksum = file_checksum(&mut file)?;
 
         Ok(Self {
             transfer_id,
             file_path,
             file_size,
             chunk_size,
+            checksum,
             file,
         })
     }
 
+    pub fn transfer_id(&self) -> u64 {
+        self.transfer_id
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    pub fn checksum(&self) -> &[u8] {
+        &self.checksum
+    }
+
+    /// Token for resuming this transfer once the receiver confirmed `next_chunk` chunks
+    pub fn resume_token(&self, next_chunk: u64) -> ResumeToken {
+        ResumeToken {
+            transfer_id: self.transfer_id,
+            checksum: self.checksum.clone(),
+            next_chunk,
+        }
+    }
+
     pub fn get_metadata_frame(&self) -> FileTransferFrame {
         FileTransferFrame::Metadata {
             transfer_id: self.transfer_id,
@@ -68,7 +142,9 @@ impl FileSender {
                 filename: self.file_path.file_name().unwrap_or_default().to_string_lossy().to_string(),
                 file_size: self.file_size,
                 mime_type: None, // Could use mime_guess crate if added
-                checksum: None, // TODO: Calculate checksum
+                checksum: Some(self.checksum.clone()),
+                chunk_size: self.chunk_size,
+                chunk_count: self.total_chunks(),
             },
         }
     }
@@ -138,10 +214,19 @@ impl FileReceiver {
                     return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid transfer ID"));
                 }
                 
-                // Initialize file
-                let file = File::create(&self.output_path)?;
-                file.set_len(metadata.file_size)?;
+                // Keep whole chunks a previous attempt already wrote, so the
+                // transfer can resume; chunks arrive in order on a reliable stream
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(&self.output_path)?;
+                let chunk_size = metadata.chunk_size.max(1) as u64;
+                let stored = (file.metadata()?.len() / chunk_size).min(metadata.chunk_count);
+                file.set_len((stored * chunk_size).min(metadata.file_size))?;
                 
+                self.received_chunks = (0..stored).map(|chunk_id| (chunk_id, true)).collect();
                 self.file = Some(file);
                 self.metadata = Some(metadata);
                 Ok(())
@@ -165,17 +250,44 @@ impl FileReceiver {
                     Err(io::Error::other("Metadata not received yet"))
                 }
             }
+            // Sent by the receiver, not part of the file
+            FileTransferFrame::Resume { .. }
+            | FileTransferFrame::Ack { .. }
+            | FileTransferFrame::Complete { .. } => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, "Unexpected frame for a receiver"))
+            }
         }
     }
 
+    /// Fraction of chunks received (0-1)
     pub fn progress(&self) -> f32 {
-        // This is a rough estimate based on chunk count, assuming equal chunk sizes (except last)
-        // For exact progress, we'd need to track bytes written.
-        // But since we don't know total chunks from metadata alone (unless we assume chunk size),
-        // we can't easily calculate percentage without knowing expected chunk count.
-        // Let's just return bytes written / total size if we tracked bytes.
-        // For now, let's leave it simple.
-        0.0 
+        match &self.metadata {
+            Some(metadata) if metadata.chunk_count > 0 => {
+                self.received_chunks.len() as f32 / metadata.chunk_count as f32
+            }
+            Some(_) => 1.0,
+            None => 0.0,
+        }
+    }
+
+    /// First chunk not yet received; earlier chunks are all on disk
+    pub fn next_chunk(&self) -> u64 {
+        (0..).find(|chunk_id| !self.received_chunks.contains_key(chunk_id)).unwrap_or(0)
+    }
+
+    /// Hash the written file and compare it with the checksum from the metadata
+    pub fn verify(&mut self) -> io::Result<bool> {
+        let expected = match self.metadata.as_ref().and_then(|m| m.checksum.clone()) {
+            Some(checksum) => checksum,
+            None => return Err(io::Error::other("No checksum to verify against")),
+        };
+        match &mut self.file {
+            Some(file) => {
+                file.flush()?;
+                Ok(file_checksum(file)? == expected)
+            }
+            None => Err(io::Error::other("Metadata not received yet")),
+        }
     }
     
     pub fn is_complete(&self, total_chunks: u64) -> bool {
@@ -235,4 +347,37 @@ mod tests {
         
         Ok(())
     }
+
+    #[test]
+    fn test_resume_from_partial_file() -> io::Result<()> {
+        let mut temp_file = NamedTempFile::new()?;
+        let data: Vec<u8> = (0..95u8).collect();
+        temp_file.write_all(&data)?;
+        let mut sender = FileSender::new(9, temp_file.path().to_path_buf(), 10)?;
+        assert_eq!(sender.checksum(), blake3::hash(&data).as_bytes());
+
+        let dir = tempfile::tempdir()?;
+        let output_path = dir.path().join("out.bin");
+
+        // First attempt stops after four chunks
+        let mut receiver = FileReceiver::new(9, output_path.clone());
+        receiver.process_frame(sender.get_metadata_frame())?;
+        for chunk_id in 0..4 {
+            receiver.process_frame(sender.read_chunk(chunk_id)?.unwrap())?;
+        }
+        assert_eq!(receiver.next_chunk(), 4);
+        drop(receiver);
+
+        // The next one picks up where the file ends
+        let mut receiver = FileReceiver::new(9, output_path.clone());
+        receiver.process_frame(sender.get_metadata_frame())?;
+        assert_eq!(receiver.next_chunk(), 4);
+        for chunk_id in receiver.next_chunk()..sender.total_chunks() {
+            receiver.process_frame(sender.read_chunk(chunk_id)?.unwrap())?;
+        }
+        assert!(receiver.is_complete(sender.total_chunks()));
+        assert!(receiver.verify()?);
+        assert_eq!(std::fs::read(&output_path)?, data);
+        Ok(())
+    }
 }
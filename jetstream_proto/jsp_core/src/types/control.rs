@@ -48,6 +48,34 @@ pub enum CloseReason {
     RateLimitExceeded = 4,
     /// Internal error
     InternalError = 5,
+    /// The peer failed to authenticate
+    AuthenticationFailed = 6,
+    /// The peer opened more streams than allowed
+    TooManyStreams = 7,
+}
+
+impl CloseReason {
+    /// Every reason, in code order
+    pub const ALL: [CloseReason; 8] = [
+        CloseReason::Normal,
+        CloseReason::GoingAway,
+        CloseReason::ProtocolError,
+        CloseReason::Timeout,
+        CloseReason::RateLimitExceeded,
+        CloseReason::InternalError,
+        CloseReason::AuthenticationFailed,
+        CloseReason::TooManyStreams,
+    ];
+
+    /// Numeric code, as exposed by the C, C++, Java and Python SDKs
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+
+    /// Reason with the given code, if there is one
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::ALL.iter().copied().find(|reason| reason.code() == code)
+    }
 }
 
 impl CloseFrame {
@@ -71,12 +99,49 @@ impl CloseFrame {
 pub struct SessionTicket {
     /// Ticket identifier
     pub ticket_id: [u8; 32],
-    /// Encrypted session state
+    /// Resumption secret, sealed with a key only the issuing server holds
     pub encrypted_state: Vec<u8>,
     /// Ticket creation timestamp (seconds since UNIX epoch)
     pub created_at: u64,
     /// Ticket lifetime in seconds
     pub lifetime: u32,
+    /// Client's own copy of the resumption secret, added when the ticket
+    /// arrives; kept by `to_bytes` but never offered back to the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resumption_secret: Option<[u8; 32]>,
+}
+
+impl SessionTicket {
+    /// Check whether the ticket lifetime has elapsed
+    pub fn is_expired(&self) -> bool {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(u64::MAX);
+        now > self.created_at + self.lifetime as u64
+    }
+
+    /// Serialize the ticket so a client can persist it between runs
+    pub fn to_bytes(&self) -> Result<Vec<u8>, serde_cbor::Error> {
+        serde_cbor::to_vec(self)
+    }
+
+    /// Load a ticket previously saved with `to_bytes`
+    pub fn from_bytes(data: &[u8]) -> Result<Self, serde_cbor::Error> {
+        serde_cbor::from_slice(data)
+    }
+}
+
+/// Key update: the sender now seals packets with the key of `epoch`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct KeyUpdateFrame {
+    pub epoch: u32,
+}
+
+/// Answer to an MTU probe: the probe datagram of `size` bytes got through
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MtuProbeAckFrame {
+    pub size: u32,
 }
 
 /// Stream control frame for multiplexing
@@ -107,6 +172,28 @@ pub struct AckFrame {
     pub cumulative_ack: u64,
     /// SACK ranges (start, end) inclusive
     pub sack_ranges: Vec<(u64, u64)>,
+    /// ECN codepoints seen by the receiver so far; absent when it saw none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ecn_counts: Option<EcnCounts>,
+    /// Bytes the receiver can still buffer for its application; absent when
+    /// it does not limit them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receive_window: Option<u64>,
+}
+
+/// Running totals of the ECN codepoints on received packets
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EcnCounts {
+    pub ect0: u64,
+    pub ect1: u64,
+    /// Packets a router marked Congestion Experienced
+    pub ce: u64,
+}
+
+impl EcnCounts {
+    pub fn is_empty(&self) -> bool {
+        self.ect0 == 0 && self.ect1 == 0 && self.ce == 0
+    }
 }
 
 /// Configuration for session timeouts and limits
@@ -193,6 +280,19 @@ mod tests {
         assert_eq!(close_with_msg.message.as_deref(), Some("Session expired"));
     }
 
+    #[test]
+    fn test_close_reason_codes() {
+        for (code, reason) in CloseReason::ALL.iter().enumerate() {
+            assert_eq!(reason.code(), code as u32);
+            assert_eq!(CloseReason::from_code(code as u32), Some(*reason));
+        }
+        assert_eq!(CloseReason::from_code(CloseReason::ALL.len() as u32), None);
+
+        let close = CloseFrame::with_reason(CloseReason::TooManyStreams, "Stream limit reached");
+        let decoded: CloseFrame = serde_cbor::from_slice(&serde_cbor::to_vec(&close).unwrap()).unwrap();
+        assert_eq!(decoded, close);
+    }
+
     #[test]
     fn test_stream_frame() {
         let open = StreamFrame {
@@ -203,6 +303,36 @@ mod tests {
         assert_eq!(open.operation, StreamOperation::Open);
     }
 
+    #[test]
+    fn test_ack_frame_ecn_counts_optional() {
+        let ack = AckFrame { cumulative_ack: 7, sack_ranges: vec![(9, 10)], ecn_counts: None, receive_window: None };
+        let bytes = serde_cbor::to_vec(&ack).unwrap();
+        assert_eq!(serde_cbor::from_slice::<AckFrame>(&bytes).unwrap(), ack);
+
+        let ack = AckFrame {
+            ecn_counts: Some(EcnCounts { ect0: 5, ect1: 0, ce: 2 }),
+            ..ack
+        };
+        let bytes = serde_cbor::to_vec(&ack).unwrap();
+        assert_eq!(serde_cbor::from_slice::<AckFrame>(&bytes).unwrap(), ack);
+    }
+
+    #[test]
+    fn test_ack_frame_receive_window_optional() {
+        let ack = AckFrame { cumulative_ack: 3, sack_ranges: Vec::new(), ecn_counts: None, receive_window: Some(65536) };
+        let bytes = serde_cbor::to_vec(&ack).unwrap();
+        assert_eq!(serde_cbor::from_slice::<AckFrame>(&bytes).unwrap(), ack);
+
+        // ACKs from peers without flow control carry no window
+        #[derive(Serialize)]
+        struct LegacyAck {
+            cumulative_ack: u64,
+            sack_ranges: Vec<(u64, u64)>,
+        }
+        let bytes = serde_cbor::to_vec(&LegacyAck { cumulative_ack: 3, sack_ranges: Vec::new() }).unwrap();
+        assert_eq!(serde_cbor::from_slice::<AckFrame>(&bytes).unwrap().receive_window, None);
+    }
+
     #[test]
     fn test_default_configs() {
         let session_config = SessionConfig::default();
@@ -0,0 +1,64 @@
+/// Size of the encoded fragment sub-header in bytes
+pub const FRAGMENT_HEADER_LEN: usize = 8;
+
+/// Sub-header prepended to the payload of a fragmented message
+/// Present only when the packet header carries `FLAG_FRAGMENT`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentHeader {
+    /// Identifier shared by all fragments of one message
+    pub message_id: u32,
+    /// Position of this fragment within the message
+    pub index: u16,
+    /// Total number of fragments in the message
+    pub count: u16,
+}
+
+impl FragmentHeader {
+    pub fn new(message_id: u32, index: u16, count: u16) -> Self {
+        Self { message_id, index, count }
+    }
+
+    /// Encode as [message_id (4)] [index (2)] [count (2)], big-endian
+    pub fn to_bytes(&self) -> [u8; FRAGMENT_HEADER_LEN] {
+        let mut bytes = [0u8; FRAGMENT_HEADER_LEN];
+        bytes[0..4].copy_from_slice(&self.message_id.to_be_bytes());
+        bytes[4..6].copy_from_slice(&self.index.to_be_bytes());
+        bytes[6..8].copy_from_slice(&self.count.to_be_bytes());
+        bytes
+    }
+
+    /// Decode from the start of `bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < FRAGMENT_HEADER_LEN {
+            return None;
+        }
+        let header = Self {
+            message_id: u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            index: u16::from_be_bytes([bytes[4], bytes[5]]),
+            count: u16::from_be_bytes([bytes[6], bytes[7]]),
+        };
+        if header.count == 0 || header.index >= header.count {
+            return None;
+        }
+        Some(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fragment_header_roundtrip() {
+        let header = FragmentHeader::new(42, 3, 7);
+        let bytes = header.to_bytes();
+        assert_eq!(FragmentHeader::from_bytes(&bytes), Some(header));
+    }
+
+    #[test]
+    fn test_fragment_header_invalid() {
+        assert!(FragmentHeader::from_bytes(&[0u8; 4]).is_none());
+        // index out of range
+        assert!(FragmentHeader::from_bytes(&FragmentHeader::new(1, 2, 2).to_bytes()).is_none());
+    }
+}
@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use super::connection_id::ConnectionId;
+use super::control::SessionTicket;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientHello {
@@ -22,6 +23,27 @@ pub struct ClientHello {
     /// Supported serialization formats (0=CBOR, 1=FlatBuffers)
     /// Client lists formats in order of preference
     pub supported_formats: Vec<u8>,
+    
+    /// Session ticket offered for 0-RTT resumption (None = full handshake)
+    #[serde(default)]
+    pub session_ticket: Option<SessionTicket>,
+    
+    /// Client's first Double Ratchet key (None = no ratchet requested)
+    #[serde(default)]
+    pub ratchet_public_key: Option<[u8; 32]>,
+    
+    /// Token from the server's Retry, echoed when the hello is repeated
+    #[serde(default)]
+    pub retry_token: Option<Vec<u8>>,
+}
+
+/// Payload of a `FRAME_TYPE_RETRY` packet
+///
+/// Sent instead of a ServerHello when the server is under handshake pressure;
+/// the client repeats its ClientHello with `token` as `retry_token`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RetryFrame {
+    pub token: Vec<u8>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -39,4 +61,12 @@ pub struct ServerHello {
     /// Selected serialization format (0=CBOR, 1=FlatBuffers)
     /// Server chooses from client's supported_formats
     pub selected_format: u8,
+    
+    /// True if the server accepted the session ticket and skipped the key exchange
+    #[serde(default)]
+    pub resumed: bool,
+    
+    /// Server's Double Ratchet key, present if it accepted the client's request
+    #[serde(default)]
+    pub ratchet_public_key: Option<[u8; 32]>,
 }
@@ -17,6 +17,9 @@ mod tests {
             timestamp: 1234567890,
             connection_id: ConnectionId::generate(),
             supported_formats: vec![0, 1], // CBOR and FlatBuffers
+            session_ticket: None,
+            ratchet_public_key: None,
+            retry_token: None,
         };
 
         let serialized = serde_cbor::to_vec(&hello).unwrap();
@@ -38,6 +41,8 @@ mod tests {
             kyber_ciphertext: vec![6u8; 768],
             connection_id: ConnectionId::generate(),
             selected_format: 0, // CBOR selected
+            resumed: false,
+            ratchet_public_key: None,
         };
 
         let serialized = serde_cbor::to_vec(&hello).unwrap();
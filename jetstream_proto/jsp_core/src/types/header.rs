@@ -13,6 +13,32 @@ pub const FRAME_TYPE_STUN: u8 = 0x06;
 pub const FRAME_TYPE_TURN: u8 = 0x07;
 pub const FRAME_TYPE_PATH_CHALLENGE: u8 = 0x08;
 pub const FRAME_TYPE_PATH_RESPONSE: u8 = 0x09;
+/// Stream closed by the sender (sequenced after the stream's data)
+pub const FRAME_TYPE_STREAM_FIN: u8 = 0x0A;
+/// Unreliable message outside any stream (never sequenced, ACKed or retransmitted)
+pub const FRAME_TYPE_DATAGRAM: u8 = 0x0B;
+/// Stateless retry: repeat the ClientHello with the enclosed token
+pub const FRAME_TYPE_RETRY: u8 = 0x0C;
+/// Sender moved to the next key epoch (sequenced after the data sealed before it)
+pub const FRAME_TYPE_KEY_UPDATE: u8 = 0x0D;
+/// Path MTU probe: padding up to the datagram size being tested (never sequenced)
+pub const FRAME_TYPE_MTU_PROBE: u8 = 0x0E;
+/// Answer to an MTU probe, carrying the size of the datagram that arrived
+pub const FRAME_TYPE_MTU_PROBE_ACK: u8 = 0x0F;
+
+// Header flag bits
+/// Payload starts with a `FragmentHeader`
+pub const FLAG_FRAGMENT: u8 = 0x01;
+/// Sent as 0-RTT early data before a resumed handshake completed
+pub const FLAG_EARLY_DATA: u8 = 0x02;
+/// On a `FRAME_TYPE_STREAM_FIN`: the sender still receives on the stream
+/// (half-close), so the FIN is not answered with one of the receiver's own
+pub const FLAG_HALF_CLOSE: u8 = 0x04;
+/// Low two bits of the key epoch that sealed the payload
+pub const FLAG_KEY_PHASE: u8 = 0x18;
+/// Message was compressed before sealing; its first byte names the algorithm
+pub const FLAG_COMPRESSED: u8 = 0x20;
+const KEY_PHASE_SHIFT: u8 = 3;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Header {
@@ -68,4 +94,14 @@ impl Header {
     pub fn is_control_frame(&self) -> bool {
         self.msg_type != FRAME_TYPE_DATA
     }
+
+    /// Key phase stored in the flags (see `FLAG_KEY_PHASE`)
+    pub fn key_phase(&self) -> u8 {
+        (self.flags & FLAG_KEY_PHASE) >> KEY_PHASE_SHIFT
+    }
+}
+
+/// Flag bits carrying `phase`, to be OR-ed into a header's flags
+pub fn key_phase_flags(phase: u8) -> u8 {
+    (phase << KEY_PHASE_SHIFT) & FLAG_KEY_PHASE
 }
@@ -7,6 +7,7 @@ pub mod stun;
 pub mod turn;
 pub mod connection_id;
 pub mod path_validation;
+pub mod fragment;
 
 #[cfg(test)]
 mod handshake_test;
@@ -6,6 +6,18 @@
 
 namespace jetstream {
 
+/// Why a connection was closed
+enum class CloseReason : uint32_t {
+    Normal = 0,
+    GoingAway = 1,
+    ProtocolError = 2,
+    Timeout = 3,
+    RateLimitExceeded = 4,
+    InternalError = 5,
+    AuthenticationFailed = 6,
+    TooManyStreams = 7,
+};
+
 struct ConnectionConfig {
     std::string addr;
     uint64_t timeout_ms;
@@ -19,7 +31,9 @@ public:
     void connect();
     void send(uint32_t stream_id, const std::vector<uint8_t>& data);
     std::vector<uint8_t> receive(uint32_t& stream_id);
-    void close();
+    void close(CloseReason reason = CloseReason::Normal, const std::string& message = "");
+    /// True once receive() read the peer's close frame; fills in why it closed
+    bool peer_close(CloseReason& reason, std::string& message) const;
 
 private:
     class impl;
@@ -28,4 +42,8 @@ private:
 
 std::unique_ptr<Connection> new_connection(const std::string& addr);
 
+/// Set the worker threads of the runtime all connections share (0 = one per
+/// CPU core, the default); false once the first connection was created
+bool set_worker_threads(size_t threads);
+
 } // namespace jetstream
@@ -20,8 +20,19 @@ public:
         return rust_conn->receive(stream_id);
     }
 
-    void close() {
-        rust_conn->close();
+    void close(CloseReason reason, const std::string& message) {
+        rust_conn->close(static_cast<jsp_cpp::CloseReason>(static_cast<uint32_t>(reason)), message);
+    }
+
+    bool peer_close(CloseReason& reason, std::string& message) const {
+        jsp_cpp::CloseReason code = jsp_cpp::CloseReason::Normal;
+        rust::String text;
+        if (!rust_conn->peer_close(code, text)) {
+            return false;
+        }
+        reason = static_cast<CloseReason>(static_cast<uint32_t>(code));
+        message = std::string(text);
+        return true;
     }
 
 private:
@@ -43,12 +54,20 @@ std::vector<uint8_t> Connection::receive(uint32_t& stream_id) {
     return impl->receive(stream_id);
 }
 
-void Connection::close() {
-    impl->close();
+void Connection::close(CloseReason reason, const std::string& message) {
+    impl->close(reason, message);
+}
+
+bool Connection::peer_close(CloseReason& reason, std::string& message) const {
+    return impl->peer_close(reason, message);
 }
 
 std::unique_ptr<Connection> new_connection(const std::string& addr) {
     return std::make_unique<Connection>(addr);
 }
 
+bool set_worker_threads(size_t threads) {
+    return jsp_cpp::set_worker_threads(threads);
+}
+
 } // namespace jetstream
@@ -3,28 +3,49 @@ use std::sync::Mutex;
 use tokio::runtime::Runtime;
 use jsp_transport::connection::Connection as JspConnection;
 use jsp_transport::config::ConnectionConfig;
+use jsp_core::types::control::CloseReason;
 
 #[cxx::bridge(namespace = "jsp_cpp")]
 mod ffi {
+    /// Why a connection was closed; the codes of `jsp_core`'s `CloseReason`
+    #[repr(u32)]
+    enum CloseReason {
+        Normal = 0,
+        GoingAway = 1,
+        ProtocolError = 2,
+        Timeout = 3,
+        RateLimitExceeded = 4,
+        InternalError = 5,
+        AuthenticationFailed = 6,
+        TooManyStreams = 7,
+    }
+
     extern "Rust" {
         type RustConnection;
+        fn set_worker_threads(threads: usize) -> bool;
         fn new_rust_connection(addr: &CxxString) -> Box<RustConnection>;
         fn connect(&mut self);
         fn send(&mut self, stream_id: u32, data: &CxxVector<u8>);
         fn receive(&mut self, stream_id: &mut u32) -> Vec<u8>;
-        fn close(&mut self);
+        fn close(&mut self, reason: CloseReason, message: &CxxString);
+        fn peer_close(&self, reason: &mut CloseReason, message: &mut String) -> bool;
     }
 }
 
+/// Set the worker threads all connections share; false once one was created
+fn set_worker_threads(threads: usize) -> bool {
+    jsp_transport::runtime::set_worker_threads(threads)
+}
+
 struct RustConnection {
-    rt: Runtime,
+    rt: &'static Runtime,
     conn: Option<JspConnection>,
     addr: String,
 }
 
 fn new_rust_connection(addr: &CxxString) -> Box<RustConnection> {
     Box::new(RustConnection {
-        rt: Runtime::new().unwrap(),
+        rt: jsp_transport::runtime::shared().unwrap(),
         conn: None,
         addr: addr.to_string(),
     })
@@ -61,8 +82,26 @@ impl RustConnection {
         vec![]
     }
 
-    fn close(&mut self) {
-        // Cleanup
-        self.conn = None;
+    fn close(&mut self, reason: ffi::CloseReason, message: &CxxString) {
+        if let Some(mut conn) = self.conn.take() {
+            // C++ can cast any integer to the enum
+            let reason = CloseReason::from_code(reason.repr).unwrap_or(CloseReason::Normal);
+            let message = if message.is_empty() { None } else { Some(message.to_string()) };
+            self.rt.block_on(async {
+                let _ = conn.close(reason, message).await;
+            });
+        }
+    }
+
+    /// Why the peer closed the connection, once `receive` read its close frame
+    fn peer_close(&self, reason: &mut ffi::CloseReason, message: &mut String) -> bool {
+        match self.conn.as_ref().and_then(|conn| conn.peer_close()) {
+            Some(close) => {
+                *reason = ffi::CloseReason { repr: close.reason_code.code() };
+                *message = close.message.clone().unwrap_or_default();
+                true
+            }
+            None => false,
+        }
     }
 }
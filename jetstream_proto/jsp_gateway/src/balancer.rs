@@ -1,50 +1,232 @@
+use std::collections::HashSet;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
+use anyhow::Result;
+use dashmap::DashMap;
+use jsp_core::compression::header_compression::peek_connection_id;
+use jsp_transport::load_balancer::HashRing;
+use jsp_transport::pool::{ConnectionPool, PooledConnection};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Strategy {
     RoundRobin,
+    /// Session affinity: the same key always maps to the same backend while it is up
+    ConsistentHash { virtual_nodes: usize },
+    /// Session affinity: a session's first packet picks a backend round-robin and
+    /// the session stays there until it idles out or the backend goes down
+    Sticky { idle_timeout: Duration },
     // LeastConnections would require tracking active connections count
 }
 
+/// Idle time after which `Strategy::Sticky` forgets a session
+pub const DEFAULT_STICKY_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+impl Strategy {
+    /// Parse a strategy name ("round-robin", "consistent-hash" or "sticky")
+    pub fn from_name(name: &str, virtual_nodes: usize) -> Option<Self> {
+        match name {
+            "" | "round-robin" => Some(Strategy::RoundRobin),
+            "consistent-hash" => Some(Strategy::ConsistentHash { virtual_nodes }),
+            "sticky" => Some(Strategy::Sticky { idle_timeout: DEFAULT_STICKY_IDLE_TIMEOUT }),
+            _ => None,
+        }
+    }
+}
+
+/// What a new session is balanced on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SessionKey {
+    /// Connection ID from the packet header, stable across client address changes
+    ConnectionId(u64),
+    /// Client address, for packets without a parseable header (e.g. handshakes)
+    Source(SocketAddr),
+}
+
+impl SessionKey {
+    /// Key of a `[u16 header_len][header][payload]` packet from `src`
+    pub fn from_packet(data: &[u8], src: SocketAddr) -> Self {
+        if data.len() >= 2 {
+            let header_len = u16::from_be_bytes([data[0], data[1]]) as usize;
+            if let Some(header) = data.get(2..2 + header_len) {
+                if let Some(connection_id) = peek_connection_id(header) {
+                    return SessionKey::ConnectionId(connection_id.as_u64());
+                }
+            }
+        }
+        SessionKey::Source(src)
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        match self {
+            SessionKey::ConnectionId(id) => id.to_be_bytes().to_vec(),
+            SessionKey::Source(addr) => addr.to_string().into_bytes(),
+        }
+    }
+}
+
+/// Backend a session is pinned to by `Strategy::Sticky`
+#[derive(Debug, Clone, Copy)]
+struct Affinity {
+    backend: SocketAddr,
+    last_seen: Instant,
+}
+
 #[derive(Debug)]
 pub struct LoadBalancer {
     backends: Arc<RwLock<Vec<SocketAddr>>>,
     strategy: Strategy,
     // Round Robin counter
     rr_counter: AtomicUsize,
+    /// Backends by address, for consistent hashing
+    ring: RwLock<HashRing>,
+    /// Session -> backend, for sticky sessions
+    affinity: DashMap<SessionKey, Affinity>,
+    /// Backends taken out of rotation until marked healthy again
+    unhealthy: RwLock<HashSet<SocketAddr>>,
+    /// Pre-handshaked connections to the backends
+    pool: Option<ConnectionPool>,
 }
 
 impl LoadBalancer {
     pub fn new(backends: Vec<SocketAddr>, strategy: Strategy) -> Self {
+        let virtual_nodes = match strategy {
+            Strategy::ConsistentHash { virtual_nodes } => virtual_nodes,
+            Strategy::RoundRobin | Strategy::Sticky { .. } => 1,
+        };
+        let mut ring = HashRing::new(virtual_nodes);
+        for addr in &backends {
+            ring.add(&addr.to_string());
+        }
+
         Self {
             backends: Arc::new(RwLock::new(backends)),
             strategy,
             rr_counter: AtomicUsize::new(0),
+            ring: RwLock::new(ring),
+            affinity: DashMap::new(),
+            unhealthy: RwLock::new(HashSet::new()),
+            pool: None,
         }
     }
 
-    pub async fn select_backend(&self, _client_addr: SocketAddr) -> SocketAddr {
+    /// Hand out backend connections from `pool` in `connection_for`
+    pub fn with_pool(mut self, pool: ConnectionPool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    pub fn pool(&self) -> Option<&ConnectionPool> {
+        self.pool.as_ref()
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    /// Select a backend for the client and check out a pooled connection to it
+    pub async fn connection_for(&self, client_addr: SocketAddr) -> Result<PooledConnection> {
+        let pool = self.pool.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Load balancer has no connection pool"))?;
+        let backend = self.select_backend(SessionKey::Source(client_addr)).await;
+        pool.acquire(backend).await
+    }
+
+    pub async fn select_backend(&self, key: SessionKey) -> SocketAddr {
         let backends = self.backends.read().await;
         if backends.is_empty() {
             // Fallback or error? For now panic or return a dummy
             panic!("No backends available");
         }
 
+        // With every backend down, keep using all of them rather than none
+        let unhealthy = self.unhealthy.read().await;
+        let healthy: Vec<SocketAddr> = backends.iter().copied().filter(|addr| !unhealthy.contains(addr)).collect();
+        let candidates = if healthy.is_empty() { &backends[..] } else { &healthy[..] };
+
         match self.strategy {
-            Strategy::RoundRobin => {
-                let idx = self.rr_counter.fetch_add(1, Ordering::Relaxed);
-                backends[idx % backends.len()]
+            Strategy::RoundRobin => self.next_round_robin(candidates),
+            Strategy::ConsistentHash { .. } => {
+                let ring = self.ring.read().await;
+                ring.get(&key.to_bytes())
+                    .and_then(|node| node.parse().ok())
+                    .filter(|addr| candidates.contains(addr))
+                    .unwrap_or(candidates[0])
             }
+            Strategy::Sticky { idle_timeout } => {
+                let pinned = self.affinity.get_mut(&key).and_then(|mut affinity| {
+                    if candidates.contains(&affinity.backend) && affinity.last_seen.elapsed() < idle_timeout {
+                        affinity.last_seen = Instant::now();
+                        Some(affinity.backend)
+                    } else {
+                        None
+                    }
+                });
+                if let Some(backend) = pinned {
+                    return backend;
+                }
+
+                let backend = self.next_round_robin(candidates);
+                tracing::debug!("Pinning session {:?} to {}", key, backend);
+                self.affinity.retain(|_, affinity| affinity.last_seen.elapsed() < idle_timeout);
+                self.affinity.insert(key, Affinity { backend, last_seen: Instant::now() });
+                backend
+            }
+        }
+    }
+
+    /// Like `select_backend`, but `None` instead of a panic when there are no backends
+    pub async fn try_select_backend(&self, key: SessionKey) -> Option<SocketAddr> {
+        if self.backends.read().await.is_empty() {
+            return None;
+        }
+        Some(self.select_backend(key).await)
+    }
+
+    fn next_round_robin(&self, candidates: &[SocketAddr]) -> SocketAddr {
+        let idx = self.rr_counter.fetch_add(1, Ordering::Relaxed);
+        candidates[idx % candidates.len()]
+    }
+
+    /// Record that `key` is being served by `backend`
+    ///
+    /// Keeps sticky sessions alive and pins connection IDs first seen after the
+    /// handshake, so a migrated client lands on the same backend.
+    pub fn touch_session(&self, key: SessionKey, backend: SocketAddr) {
+        if let Strategy::Sticky { .. } = self.strategy {
+            self.affinity.insert(key, Affinity { backend, last_seen: Instant::now() });
+        }
+    }
+
+    /// Number of sessions pinned by `Strategy::Sticky`
+    pub fn sticky_sessions(&self) -> usize {
+        self.affinity.len()
+    }
+
+    /// Take a backend out of rotation, or put it back; sessions pinned to it move elsewhere
+    pub async fn set_healthy(&self, addr: SocketAddr, healthy: bool) {
+        let mut unhealthy = self.unhealthy.write().await;
+        let changed = if healthy { unhealthy.remove(&addr) } else { unhealthy.insert(addr) };
+        if changed {
+            tracing::info!("Backend {} marked {}", addr, if healthy { "healthy" } else { "unhealthy" });
         }
     }
 
+    /// Whether new packets may still go to `addr`
+    pub async fn is_available(&self, addr: SocketAddr) -> bool {
+        let backends = self.backends.read().await;
+        let unhealthy = self.unhealthy.read().await;
+        backends.contains(&addr)
+            && (!unhealthy.contains(&addr) || backends.iter().all(|backend| unhealthy.contains(backend)))
+    }
+
     pub async fn add_backend(&self, addr: SocketAddr) {
         let mut backends = self.backends.write().await;
         if !backends.contains(&addr) {
             backends.push(addr);
+            self.ring.write().await.add(&addr.to_string());
             tracing::info!("Backend added: {}", addr);
         }
     }
@@ -53,6 +235,8 @@ impl LoadBalancer {
         let mut backends = self.backends.write().await;
         if let Some(pos) = backends.iter().position(|x| *x == addr) {
             backends.remove(pos);
+            self.ring.write().await.remove(&addr.to_string());
+            self.affinity.retain(|_, affinity| affinity.backend != addr);
             tracing::info!("Backend removed: {}", addr);
         }
     }
@@ -6,6 +6,33 @@ pub struct GatewayConfig {
     pub backends: Vec<String>,
     #[serde(default)]
     pub strategy: String,
+    /// Virtual nodes per backend for the consistent-hash strategy
+    #[serde(default = "default_virtual_nodes")]
+    pub virtual_nodes: usize,
+    /// Address of the HTTP -> JetStream ingress, if enabled
+    #[serde(default)]
+    pub http_ingress: Option<String>,
+    /// Seconds existing sessions keep being served after a shutdown signal
+    #[serde(default = "default_drain_grace_secs")]
+    pub drain_grace_secs: u64,
+    /// Address `jsp-cli monitor` connects to for live flow stats, if enabled
+    #[serde(default)]
+    pub stats_addr: Option<String>,
+    /// Milliseconds between stats frames sent to monitors
+    #[serde(default = "default_stats_interval_ms")]
+    pub stats_interval_ms: u64,
+}
+
+fn default_virtual_nodes() -> usize {
+    150
+}
+
+fn default_drain_grace_secs() -> u64 {
+    30
+}
+
+fn default_stats_interval_ms() -> u64 {
+    1000
 }
 
 impl Default for GatewayConfig {
@@ -14,6 +41,11 @@ impl Default for GatewayConfig {
             bind_addr: "0.0.0.0:5000".to_string(),
             backends: vec!["127.0.0.1:8080".to_string()],
             strategy: "round-robin".to_string(),
+            virtual_nodes: default_virtual_nodes(),
+            http_ingress: None,
+            drain_grace_secs: default_drain_grace_secs(),
+            stats_addr: None,
+            stats_interval_ms: default_stats_interval_ms(),
         }
     }
 }
@@ -0,0 +1,154 @@
+//! Gateway
+//!
+//! Runs the UDP proxy and, if configured, the HTTP ingress in front of the
+//! same backends, and shuts both down gracefully: once the shutdown future
+//! resolves, new clients are refused, existing sessions get up to
+//! `drain_grace_secs` to finish, and backends are told to release the
+//! sessions that are left. With `stats_addr` set, a JetStream server there
+//! streams the proxy's flows to `jsp-cli monitor`.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use jsp_transport::config::ServerConfig;
+use jsp_transport::pool::{ConnectionPool, PoolConfig};
+use jsp_transport::server::Server;
+use crate::balancer::{LoadBalancer, Strategy};
+use crate::config::GatewayConfig;
+use crate::ingress::{HttpIngress, IngressHandle};
+use crate::proxy::Proxy;
+
+/// UDP proxy and HTTP ingress sharing one load balancer
+pub struct Gateway {
+    proxy: Arc<Proxy>,
+    balancer: Arc<LoadBalancer>,
+    ingress: Option<IngressHandle>,
+    /// Serves monitors the proxy's flows
+    stats_server: Option<Server>,
+    drain_grace: Duration,
+}
+
+impl Gateway {
+    /// Bind the proxy, and the ingress if `config.http_ingress` is set
+    pub async fn bind(config: &GatewayConfig) -> Result<Self> {
+        let backends = config.backends.iter()
+            .map(|backend| backend.parse().map_err(|e| anyhow!("Invalid backend address {}: {}", backend, e)))
+            .collect::<Result<Vec<SocketAddr>>>()?;
+        let strategy = Strategy::from_name(&config.strategy, config.virtual_nodes)
+            .ok_or_else(|| anyhow!("Unknown strategy: {}", config.strategy))?;
+        let http_ingress = config.http_ingress.as_deref()
+            .map(|addr| addr.parse::<SocketAddr>().map_err(|e| anyhow!("Invalid ingress address {}: {}", addr, e)))
+            .transpose()?;
+
+        // The ingress talks to backends over pooled connections
+        let mut balancer = LoadBalancer::new(backends, strategy);
+        if http_ingress.is_some() {
+            balancer = balancer.with_pool(ConnectionPool::new(PoolConfig::default()));
+        }
+        let balancer = Arc::new(balancer);
+
+        let ingress = match http_ingress {
+            Some(addr) => Some(HttpIngress::new(balancer.clone()).serve(addr).await?),
+            None => None,
+        };
+        let proxy = Arc::new(Proxy::new(&config.bind_addr, balancer.clone()).await?);
+
+        let stats_server = match &config.stats_addr {
+            Some(addr) => {
+                let server_config = ServerConfig {
+                    stats_stream_interval: Some(Duration::from_millis(config.stats_interval_ms.max(1))),
+                    ..ServerConfig::default()
+                };
+                let mut server = Server::bind_with_config(addr, server_config).await?;
+                server.set_stats_source(proxy.clone());
+                Some(server)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            proxy,
+            balancer,
+            ingress,
+            stats_server,
+            drain_grace: Duration::from_secs(config.drain_grace_secs),
+        })
+    }
+
+    pub fn proxy(&self) -> &Arc<Proxy> {
+        &self.proxy
+    }
+
+    /// Address UDP clients connect to
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.proxy.socket.local_addr()?)
+    }
+
+    /// Address of the HTTP ingress, if enabled
+    pub fn ingress_addr(&self) -> Option<SocketAddr> {
+        self.ingress.as_ref().map(|ingress| ingress.local_addr())
+    }
+
+    /// Address monitors connect to, if enabled
+    pub fn stats_addr(&self) -> Option<SocketAddr> {
+        self.stats_server.as_ref().and_then(|server| server.local_addr().ok())
+    }
+
+    /// Serve until `shutdown` resolves, then drain
+    ///
+    /// Draining refuses new UDP flows and HTTP connections right away, and
+    /// returns once every session finished or the grace period is up.
+    pub async fn run(mut self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        let proxy = Arc::clone(&self.proxy);
+        let run = proxy.run();
+        tokio::pin!(run);
+        let stats = self.stats_server.take().map(|server| tokio::spawn(serve_monitors(server)));
+
+        let result = tokio::select! {
+            result = &mut run => {
+                // Failed, or drained through `proxy()`
+                stop_ingress(self.ingress.take(), self.drain_grace).await;
+                result
+            }
+            _ = shutdown => {
+                tracing::info!(grace = ?self.drain_grace, "Shutdown requested, draining gateway");
+                self.proxy.shutdown(self.drain_grace);
+                let (result, ()) = tokio::join!(run, stop_ingress(self.ingress.take(), self.drain_grace));
+                result
+            }
+        };
+
+        if let Some(stats) = stats {
+            stats.abort();
+        }
+        if let Some(pool) = self.balancer.pool() {
+            let closed = pool.close_idle().await;
+            tracing::info!("Closed {} pooled backend connections", closed);
+        }
+        result
+    }
+}
+
+/// Handshake monitors and take their subscriptions; the server sends the frames
+async fn serve_monitors(mut server: Server) {
+    loop {
+        if let Err(e) = server.next_event().await {
+            tracing::debug!("Stats server: {}", e);
+        }
+    }
+}
+
+/// Stop accepting HTTP connections and give in-flight requests up to `grace`
+async fn stop_ingress(ingress: Option<IngressHandle>, grace: Duration) {
+    let ingress = match ingress {
+        Some(ingress) => ingress,
+        None => return,
+    };
+    match tokio::time::timeout(grace, ingress.shutdown()).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::warn!("HTTP ingress failed while shutting down: {}", e),
+        Err(_) => tracing::warn!("HTTP requests still in flight after {:?}, abandoning them", grace),
+    }
+}
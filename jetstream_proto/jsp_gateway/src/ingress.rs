@@ -0,0 +1,391 @@
+//! HTTP Ingress
+//!
+//! Lets plain HTTP/1.1 clients talk to JetStream backends:
+//!
+//! - `POST /streams/{id}` sends the request body on stream `{id}`
+//! - `GET /streams/{id}?timeout_ms=N` returns the next message received on
+//!   stream `{id}`, or 204 if none arrives within `N` ms (default 0)
+//!
+//! Each stream is pinned to the backend the `LoadBalancer` picks for it. Every
+//! backend gets one pooled connection, on which HTTP streams are multiplexed.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::Result;
+use dashmap::DashMap;
+use hyper::body::Bytes;
+use hyper::header::{ALLOW, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use jsp_core::types::delivery::DeliveryMode;
+use jsp_transport::error::TransportError;
+use jsp_transport::events::ConnectionEvent;
+use jsp_transport::pool::PooledConnection;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::task::JoinHandle;
+use crate::balancer::{LoadBalancer, SessionKey};
+
+/// Sends waiting for a backend connection before new ones are refused
+const LINK_QUEUE: usize = 256;
+/// Received messages kept per stream until a GET picks them up
+const INBOX_CAPACITY: usize = 1024;
+
+/// A message for the backend, answered once it was queued on the connection
+struct Outgoing {
+    stream_id: u32,
+    data: Bytes,
+    done: oneshot::Sender<Result<(), TransportError>>,
+}
+
+/// Messages received for one HTTP stream, waiting for a GET
+struct Inbox {
+    tx: mpsc::Sender<Bytes>,
+    rx: Mutex<mpsc::Receiver<Bytes>>,
+}
+
+impl Inbox {
+    fn new() -> Self {
+        let (tx, rx) = mpsc::channel(INBOX_CAPACITY);
+        Self { tx, rx: Mutex::new(rx) }
+    }
+}
+
+/// A failed request, answered with a JSON error body
+#[derive(Debug)]
+struct IngressError {
+    status: StatusCode,
+    message: String,
+}
+
+impl IngressError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self { status, message: message.into() }
+    }
+
+    /// The backend is down or the connection to it failed
+    fn bad_gateway(e: impl std::fmt::Display) -> Self {
+        Self::new(StatusCode::BAD_GATEWAY, e.to_string())
+    }
+
+    /// Map a send failure; sends the backend connection can take again
+    /// later are 429, anything else 502
+    fn from_send(e: TransportError) -> Self {
+        let status = match e {
+            TransportError::RateLimited | TransportError::CongestionLimited => StatusCode::TOO_MANY_REQUESTS,
+            _ => StatusCode::BAD_GATEWAY,
+        };
+        Self::new(status, e.to_string())
+    }
+
+    fn into_response(self) -> Response<Body> {
+        let body = serde_json::json!({
+            "error": self.message,
+            "status": self.status.as_u16(),
+        });
+        Response::builder()
+            .status(self.status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+}
+
+struct IngressState {
+    balancer: Arc<LoadBalancer>,
+    /// HTTP stream -> backend it is pinned to
+    stream_backends: DashMap<u32, SocketAddr>,
+    /// Backend -> task owning its pooled connection
+    links: Mutex<HashMap<SocketAddr, mpsc::Sender<Outgoing>>>,
+    inboxes: DashMap<u32, Arc<Inbox>>,
+}
+
+impl IngressState {
+    fn inbox(&self, stream_id: u32) -> Arc<Inbox> {
+        self.inboxes.entry(stream_id).or_insert_with(|| Arc::new(Inbox::new())).clone()
+    }
+
+    /// Backend serving `stream_id`, re-selected if its backend went down
+    async fn backend_for(&self, stream_id: u32) -> Result<SocketAddr, IngressError> {
+        let pinned = self.stream_backends.get(&stream_id).map(|backend| *backend);
+        if let Some(backend) = pinned {
+            if self.balancer.is_available(backend).await {
+                return Ok(backend);
+            }
+            tracing::info!("Backend {} unavailable, rebalancing stream {}", backend, stream_id);
+        }
+
+        let backend = self.balancer.try_select_backend(SessionKey::ConnectionId(stream_id as u64)).await
+            .ok_or_else(|| IngressError::bad_gateway("No backends configured"))?;
+        self.stream_backends.insert(stream_id, backend);
+        Ok(backend)
+    }
+
+    /// Sender to the connection of `backend`, connecting if there is none
+    async fn link(self: &Arc<Self>, backend: SocketAddr) -> Result<mpsc::Sender<Outgoing>, IngressError> {
+        let mut links = self.links.lock().await;
+        if let Some(link) = links.get(&backend).filter(|link| !link.is_closed()) {
+            return Ok(link.clone());
+        }
+
+        let pool = self.balancer.pool()
+            .ok_or_else(|| IngressError::bad_gateway("Load balancer has no connection pool"))?;
+        let conn = pool.acquire(backend).await.map_err(|e| {
+            tracing::warn!("Ingress failed to connect to backend {}: {}", backend, e);
+            IngressError::bad_gateway(format!("Backend {} unreachable: {}", backend, e))
+        })?;
+
+        let (tx, rx) = mpsc::channel(LINK_QUEUE);
+        tokio::spawn(run_link(conn, rx, Arc::clone(self)));
+        links.insert(backend, tx.clone());
+        Ok(tx)
+    }
+
+    async fn send(self: &Arc<Self>, stream_id: u32, data: Bytes) -> Result<(), IngressError> {
+        let backend = self.backend_for(stream_id).await?;
+        let link = self.link(backend).await?;
+
+        let (done, result) = oneshot::channel();
+        link.try_send(Outgoing { stream_id, data, done }).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => IngressError::new(StatusCode::TOO_MANY_REQUESTS, "Backend send queue full"),
+            mpsc::error::TrySendError::Closed(_) => IngressError::bad_gateway("Backend connection lost"),
+        })?;
+        result.await
+            .map_err(|_| IngressError::bad_gateway("Backend connection lost"))?
+            .map_err(IngressError::from_send)
+    }
+
+    /// Next message for `stream_id`; `None` if nothing arrived within `timeout`
+    async fn recv(&self, stream_id: u32, timeout: Duration) -> Option<Bytes> {
+        let inbox = self.inbox(stream_id);
+        let mut rx = inbox.rx.lock().await;
+        if let Ok(data) = rx.try_recv() {
+            return Some(data);
+        }
+        tokio::time::timeout(timeout, rx.recv()).await.ok().flatten()
+    }
+}
+
+/// Relay between the HTTP streams and one backend connection until it fails
+async fn run_link(mut conn: PooledConnection, mut rx: mpsc::Receiver<Outgoing>, state: Arc<IngressState>) {
+    let backend = conn.backend_addr();
+    // HTTP stream <-> stream opened on this connection
+    let mut local_streams: HashMap<u32, u32> = HashMap::new();
+    let mut http_streams: HashMap<u32, u32> = HashMap::new();
+
+    let healthy = loop {
+        tokio::select! {
+            outgoing = rx.recv() => {
+                let outgoing = match outgoing {
+                    Some(outgoing) => outgoing,
+                    None => break true,
+                };
+                let local = match local_streams.get(&outgoing.stream_id) {
+                    Some(local) => Ok(*local),
+                    None => conn.open_stream(1, DeliveryMode::Reliable).inspect(|local| {
+                        local_streams.insert(outgoing.stream_id, *local);
+                        http_streams.insert(*local, outgoing.stream_id);
+                    }),
+                };
+                let result = match local {
+                    Ok(local) => conn.send_on_stream(local, &outgoing.data).await,
+                    Err(e) => Err(e),
+                };
+                let _ = outgoing.done.send(result);
+            }
+            event = conn.next_event() => match event {
+                Ok(ConnectionEvent::DataReceived { stream_id, data }) => {
+                    match http_streams.get(&stream_id) {
+                        Some(http_stream) => {
+                            if state.inbox(*http_stream).tx.try_send(data).is_err() {
+                                tracing::warn!("Ingress inbox of stream {} full, dropping message", http_stream);
+                            }
+                        }
+                        None => tracing::debug!("Ingress dropping data from {} on unknown stream {}", backend, stream_id),
+                    }
+                }
+                Ok(ConnectionEvent::Closed { reason, .. }) => {
+                    tracing::info!("Backend {} closed ingress connection: {:?}", backend, reason);
+                    break false;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::warn!("Ingress connection to backend {} failed: {}", backend, e);
+                    break false;
+                }
+            },
+        }
+    };
+
+    // Requests still queued see the closed channel and fail with 502
+    drop(rx);
+    if !healthy {
+        conn.discard();
+    }
+}
+
+/// Parse `/streams/{id}`
+fn stream_id(path: &str) -> Option<u32> {
+    path.strip_prefix("/streams/")?.parse().ok()
+}
+
+/// `timeout_ms` from a query string; 0 when absent
+fn poll_timeout(query: Option<&str>) -> Result<Duration, IngressError> {
+    let value = query
+        .unwrap_or("")
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("timeout_ms="));
+    match value {
+        Some(ms) => ms.parse()
+            .map(Duration::from_millis)
+            .map_err(|_| IngressError::new(StatusCode::BAD_REQUEST, format!("Invalid timeout_ms: {}", ms))),
+        None => Ok(Duration::ZERO),
+    }
+}
+
+async fn handle_request(req: Request<Body>, state: Arc<IngressState>) -> Result<Response<Body>, Infallible> {
+    let response = match route(req, state).await {
+        Ok(response) => response,
+        Err(e) => e.into_response(),
+    };
+    Ok(response)
+}
+
+async fn route(req: Request<Body>, state: Arc<IngressState>) -> Result<Response<Body>, IngressError> {
+    let stream_id = stream_id(req.uri().path())
+        .ok_or_else(|| IngressError::new(StatusCode::NOT_FOUND, "Not Found"))?;
+
+    match *req.method() {
+        Method::POST => {
+            let body = hyper::body::to_bytes(req.into_body()).await
+                .map_err(|e| IngressError::new(StatusCode::BAD_REQUEST, format!("Failed to read body: {}", e)))?;
+            state.send(stream_id, body).await?;
+            Ok(Response::builder()
+                .status(StatusCode::ACCEPTED)
+                .body(Body::empty())
+                .unwrap())
+        }
+        Method::GET => {
+            let timeout = poll_timeout(req.uri().query())?;
+            let response = match state.recv(stream_id, timeout).await {
+                Some(data) => Response::builder()
+                    .status(StatusCode::OK)
+                    .header(CONTENT_TYPE, "application/octet-stream")
+                    .body(Body::from(data)),
+                None => Response::builder()
+                    .status(StatusCode::NO_CONTENT)
+                    .body(Body::empty()),
+            };
+            Ok(response.unwrap())
+        }
+        _ => {
+            let mut response = IngressError::new(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed").into_response();
+            response.headers_mut().insert(ALLOW, "GET, POST".parse().unwrap());
+            Ok(response)
+        }
+    }
+}
+
+/// HTTP -> JetStream ingress
+pub struct HttpIngress {
+    state: Arc<IngressState>,
+}
+
+impl HttpIngress {
+    /// Forward to backends of `balancer`, which needs a connection pool
+    /// (`LoadBalancer::with_pool`)
+    pub fn new(balancer: Arc<LoadBalancer>) -> Self {
+        Self {
+            state: Arc::new(IngressState {
+                balancer,
+                stream_backends: DashMap::new(),
+                links: Mutex::new(HashMap::new()),
+                inboxes: DashMap::new(),
+            }),
+        }
+    }
+
+    /// Serve HTTP in a background task
+    ///
+    /// Binding to port 0 picks an ephemeral port; see `IngressHandle::local_addr`.
+    pub async fn serve(self, bind_addr: SocketAddr) -> Result<IngressHandle> {
+        let state = self.state;
+        let make_svc = make_service_fn(move |_conn| {
+            let state = Arc::clone(&state);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| handle_request(req, Arc::clone(&state))))
+            }
+        });
+
+        let server = Server::try_bind(&bind_addr)?.serve(make_svc);
+        let local_addr = server.local_addr();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+
+        let task = tokio::spawn(server.with_graceful_shutdown(async {
+            let _ = shutdown_rx.await;
+        }));
+
+        tracing::info!("HTTP ingress listening on http://{}", local_addr);
+
+        Ok(IngressHandle {
+            local_addr,
+            shutdown: shutdown_tx,
+            task,
+        })
+    }
+}
+
+/// Handle to a running ingress started with `HttpIngress::serve`
+pub struct IngressHandle {
+    local_addr: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<Result<(), hyper::Error>>,
+}
+
+impl IngressHandle {
+    /// Address the ingress is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop accepting requests and wait for in-flight ones to finish
+    pub async fn shutdown(self) -> Result<()> {
+        let _ = self.shutdown.send(());
+        self.task.await??;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsp_core::types::control::CloseReason;
+
+    #[test]
+    fn test_parse_stream_path() {
+        assert_eq!(stream_id("/streams/7"), Some(7));
+        assert_eq!(stream_id("/streams/"), None);
+        assert_eq!(stream_id("/streams/x"), None);
+        assert_eq!(stream_id("/other/7"), None);
+    }
+
+    #[test]
+    fn test_poll_timeout() {
+        assert_eq!(poll_timeout(None).unwrap(), Duration::ZERO);
+        assert_eq!(poll_timeout(Some("a=1&timeout_ms=250")).unwrap(), Duration::from_millis(250));
+        assert_eq!(poll_timeout(Some("timeout_ms=abc")).unwrap_err().status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_send_errors_map_to_status() {
+        let limited = IngressError::from_send(TransportError::RateLimited);
+        assert_eq!(limited.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(limited.message, "Rate limit exceeded");
+        let congested = IngressError::from_send(TransportError::CongestionLimited);
+        assert_eq!(congested.status, StatusCode::TOO_MANY_REQUESTS);
+        let closed = IngressError::from_send(TransportError::ConnectionClosed { reason: CloseReason::GoingAway });
+        assert_eq!(closed.status, StatusCode::BAD_GATEWAY);
+    }
+}
@@ -1,3 +1,5 @@
 pub mod proxy;
 pub mod balancer;
 pub mod config;
+pub mod ingress;
+pub mod gateway;
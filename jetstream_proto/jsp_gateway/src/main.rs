@@ -1,7 +1,6 @@
 use clap::Parser;
-use jsp_gateway::proxy::Proxy;
-use jsp_gateway::balancer::{LoadBalancer, Strategy};
-use std::sync::Arc;
+use jsp_gateway::config::GatewayConfig;
+use jsp_gateway::gateway::Gateway;
 use std::net::SocketAddr;
 
 #[derive(Parser, Debug)]
@@ -14,6 +13,52 @@ struct Args {
     /// Backend servers (comma separated)
     #[arg(short, long, value_delimiter = ',', default_value = "127.0.0.1:8080")]
     backends: Vec<String>,
+
+    /// Balancing strategy: round-robin, consistent-hash or sticky
+    #[arg(short, long, default_value = "round-robin")]
+    strategy: String,
+
+    /// Virtual nodes per backend for consistent hashing
+    #[arg(long, default_value_t = 150)]
+    virtual_nodes: usize,
+
+    /// Seconds existing sessions keep being served after SIGTERM/SIGINT
+    #[arg(long, default_value_t = 30)]
+    drain_grace_secs: u64,
+
+    /// Also accept plain HTTP clients on this address (HTTP -> JetStream ingress)
+    #[arg(long)]
+    http_ingress: Option<SocketAddr>,
+
+    /// Stream live flow stats to `jsp-cli monitor` from this address
+    #[arg(long)]
+    stats_addr: Option<SocketAddr>,
+
+    /// Milliseconds between stats frames
+    #[arg(long, default_value_t = 1000)]
+    stats_interval_ms: u64,
+}
+
+/// Resolves on SIGTERM or SIGINT (just Ctrl-C where SIGTERM doesn't exist)
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => tracing::info!("Received SIGTERM"),
+                    _ = tokio::signal::ctrl_c() => tracing::info!("Received SIGINT"),
+                }
+                return;
+            }
+            Err(e) => tracing::error!("Failed to listen for SIGTERM: {}", e),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        tracing::error!("Failed to listen for Ctrl-C: {}", e);
+        std::future::pending::<()>().await;
+    }
 }
 
 #[tokio::main]
@@ -26,20 +71,20 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("Starting JetStreamProto Gateway...");
     tracing::info!("Bind address: {}", args.bind);
     tracing::info!("Backends: {:?}", args.backends);
+    tracing::info!("Strategy: {}", args.strategy);
 
-    // Parse backend addresses
-    let backends: Vec<SocketAddr> = args.backends.iter()
-        .map(|s| s.parse().expect("Invalid backend address"))
-        .collect();
-
-    // Initialize Load Balancer
-    let balancer = Arc::new(LoadBalancer::new(backends, Strategy::RoundRobin));
-
-    // Initialize Proxy
-    let proxy = Proxy::new(&args.bind, balancer).await?;
-
-    // Run Proxy
-    proxy.run().await?;
+    let config = GatewayConfig {
+        bind_addr: args.bind,
+        backends: args.backends,
+        strategy: args.strategy,
+        virtual_nodes: args.virtual_nodes,
+        http_ingress: args.http_ingress.map(|addr| addr.to_string()),
+        drain_grace_secs: args.drain_grace_secs,
+        stats_addr: args.stats_addr.map(|addr| addr.to_string()),
+        stats_interval_ms: args.stats_interval_ms,
+    };
+    let gateway = Gateway::bind(&config).await?;
 
-    Ok(())
+    // Run until SIGTERM/SIGINT, then drain
+    gateway.run(shutdown_signal()).await
 }
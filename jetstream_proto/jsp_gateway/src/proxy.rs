@@ -1,9 +1,19 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::UdpSocket;
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
 use dashmap::DashMap;
 use anyhow::Result;
-use crate::balancer::LoadBalancer;
+use jsp_core::types::control::{CloseFrame, CloseReason};
+use jsp_core::types::delivery::DeliveryMode;
+use jsp_core::types::header::{Header, FRAME_TYPE_CLOSE};
+use jsp_core::compression::header_compression::peek_msg_type;
+use jsp_transport::metrics::Metrics;
+use jsp_transport::stats_stream::{SessionStats, StatsFrame, StatsSource};
+use crate::balancer::{LoadBalancer, SessionKey};
 
 /// Gateway Proxy
 pub struct Proxy {
@@ -44,6 +54,16 @@ pub struct Proxy {
     ///    Option B is cleaner for a "transparent" proxy without deep packet inspection.
     ///    Let's implement Option B.
     client_proxies: Arc<DashMap<SocketAddr, Arc<UdpSocket>>>,
+    /// Task relaying backend replies of each client flow
+    relays: Arc<DashMap<SocketAddr, JoinHandle<()>>>,
+    /// End of the grace period once `shutdown` was called
+    drain_deadline: watch::Sender<Option<Instant>>,
+    /// Signalled when a relay ends its flow, so a drain can finish early
+    flow_closed: Arc<Notify>,
+    /// Traffic with clients across all flows
+    metrics: Arc<Metrics>,
+    /// Traffic with each client, as seen by the gateway
+    flow_metrics: Arc<DashMap<SocketAddr, Arc<Metrics>>>,
 }
 
 impl Proxy {
@@ -54,25 +74,92 @@ impl Proxy {
             balancer,
             sessions: Arc::new(DashMap::new()),
             client_proxies: Arc::new(DashMap::new()),
+            relays: Arc::new(DashMap::new()),
+            drain_deadline: watch::channel(None).0,
+            flow_closed: Arc::new(Notify::new()),
+            metrics: Arc::new(Metrics::new()),
+            flow_metrics: Arc::new(DashMap::new()),
         })
     }
 
+    /// Start draining: refuse new client flows, keep relaying existing ones
+    /// for `grace`, then close them and return from `run()`
+    ///
+    /// `run()` returns as soon as every flow has ended, i.e. its client or
+    /// backend sent a close frame, even before `grace` is up.
+    pub fn shutdown(&self, grace: Duration) {
+        if self.is_draining() {
+            return;
+        }
+        tracing::info!(flows = self.active_flows(), ?grace, "Gateway draining");
+        self.drain_deadline.send_replace(Some(Instant::now() + grace));
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.drain_deadline.borrow().is_some()
+    }
+
+    /// Number of client flows being relayed
+    pub fn active_flows(&self) -> usize {
+        self.client_proxies.len()
+    }
+
+    /// Traffic with clients across all flows
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
     pub async fn run(&self) -> Result<()> {
         tracing::info!("Gateway listening on {}", self.socket.local_addr()?);
         
         let mut buf = [0u8; 65535];
+        let mut drain_rx = self.drain_deadline.subscribe();
         
         loop {
-            let (len, client_addr) = self.socket.recv_from(&mut buf).await?;
+            let deadline = *drain_rx.borrow_and_update();
+            if deadline.is_some() && self.client_proxies.is_empty() {
+                break;
+            }
+            let grace_expired = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+            
+            let (len, client_addr) = tokio::select! {
+                res = self.socket.recv_from(&mut buf) => res?,
+                _ = drain_rx.changed() => continue,
+                _ = self.flow_closed.notified() => continue,
+                _ = grace_expired => break,
+            };
             let data = &buf[..len];
             
-            // Check if we have a proxy socket for this client
-            let proxy_socket = if let Some(socket) = self.client_proxies.get(&client_addr) {
-                socket.clone()
+            // Check if we have a proxy socket for this client, and that its backend is still up
+            let existing = self.client_proxies.get(&client_addr).map(|socket| socket.clone());
+            let backend = self.sessions.get(&client_addr).map(|backend| *backend);
+            let existing = match (existing, backend) {
+                (Some(_), Some(backend)) if !self.balancer.is_available(backend).await => {
+                    tracing::info!("Backend {} unavailable, rebalancing session from {}", backend, client_addr);
+                    self.drop_flow(client_addr);
+                    None
+                }
+                (existing, _) => existing,
+            };
+            
+            let proxy_socket = if let Some(socket) = existing {
+                if let Some(backend) = backend {
+                    self.balancer.touch_session(SessionKey::from_packet(data, client_addr), backend);
+                }
+                socket
+            } else if deadline.is_some() {
+                tracing::debug!("Refusing new session from {} while draining", client_addr);
+                continue;
             } else {
                 // New session
-                // 1. Select backend
-                let backend_addr = self.balancer.select_backend(client_addr).await;
+                // 1. Select backend, by connection ID when the packet has one
+                let key = SessionKey::from_packet(data, client_addr);
+                let backend_addr = self.balancer.select_backend(key).await;
                 tracing::info!("New session: {} -> {}", client_addr, backend_addr);
                 
                 // 2. Create new ephemeral socket for this client-backend pair
@@ -82,13 +169,21 @@ impl Proxy {
                 // 3. Store mappings
                 self.sessions.insert(client_addr, backend_addr);
                 self.client_proxies.insert(client_addr, new_socket.clone());
+                let flow_metrics = Arc::new(Metrics::new());
+                self.flow_metrics.insert(client_addr, flow_metrics.clone());
                 
                 // 4. Spawn task to handle responses from backend
                 let socket_clone = new_socket.clone();
                 let main_socket = self.socket.clone();
                 let client_addr_clone = client_addr;
+                let client_proxies = self.client_proxies.clone();
+                let sessions = self.sessions.clone();
+                let relays = self.relays.clone();
+                let flow_closed = self.flow_closed.clone();
+                let all_flow_metrics = self.flow_metrics.clone();
+                let metrics = self.metrics.clone();
                 
-                tokio::spawn(async move {
+                let relay = tokio::spawn(async move {
                     let mut buf = [0u8; 65535];
                     loop {
                         match socket_clone.recv(&mut buf).await {
@@ -96,6 +191,23 @@ impl Proxy {
                                 let data = &buf[..len];
                                 if let Err(e) = main_socket.send_to(data, client_addr_clone).await {
                                     tracing::error!("Failed to forward to client {}: {}", client_addr_clone, e);
+                                } else {
+                                    flow_metrics.record_packet_sent(len);
+                                    metrics.record_packet_sent(len);
+                                }
+                                if is_close_packet(data) {
+                                    // Only end this flow, not one the client started since
+                                    let ended = client_proxies
+                                        .remove_if(&client_addr_clone, |_, socket| Arc::ptr_eq(socket, &socket_clone))
+                                        .is_some();
+                                    if ended {
+                                        tracing::info!("Backend closed session of {}", client_addr_clone);
+                                        sessions.remove(&client_addr_clone);
+                                        relays.remove(&client_addr_clone);
+                                        all_flow_metrics.remove(&client_addr_clone);
+                                        flow_closed.notify_one();
+                                    }
+                                    break;
                                 }
                             }
                             Err(e) => {
@@ -105,17 +217,116 @@ impl Proxy {
                         }
                     }
                 });
+                self.relays.insert(client_addr, relay);
                 
                 new_socket
             };
             
+            self.metrics.record_packet_received(len);
+            if let Some(flow_metrics) = self.flow_metrics.get(&client_addr) {
+                flow_metrics.record_packet_received(len);
+            }
+            
             // Forward to backend via proxy socket
             if let Err(e) = proxy_socket.send(data).await {
                 tracing::error!("Failed to forward to backend: {}", e);
                 // If error, maybe remove session?
-                self.client_proxies.remove(&client_addr);
-                self.sessions.remove(&client_addr);
+                self.drop_flow(client_addr);
+            } else if is_close_packet(data) {
+                tracing::info!("Client {} closed its session", client_addr);
+                self.drop_flow(client_addr);
             }
         }
+        
+        self.close_flows().await;
+        Ok(())
     }
+
+    /// Tell every client and its backend the gateway is going away and drop
+    /// the flow, so the backend frees the session instead of timing it out
+    async fn close_flows(&self) {
+        let packet = match close_packet() {
+            Ok(packet) => Some(packet),
+            Err(e) => {
+                tracing::error!("Failed to build close frame: {}", e);
+                None
+            }
+        };
+        
+        let flows: Vec<(SocketAddr, Arc<UdpSocket>)> = self.client_proxies.iter()
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect();
+        for (client_addr, proxy_socket) in flows {
+            if let Some(packet) = &packet {
+                if let Err(e) = self.socket.send_to(packet, client_addr).await {
+                    tracing::warn!("Failed to send close to client {}: {}", client_addr, e);
+                }
+                if let Err(e) = proxy_socket.send(packet).await {
+                    tracing::warn!("Failed to send close to backend of {}: {}", client_addr, e);
+                }
+            }
+            self.drop_flow(client_addr);
+        }
+        
+        tracing::info!("Gateway drained");
+    }
+
+    /// Stop relaying for a client; its next packet starts a new flow
+    fn drop_flow(&self, client_addr: SocketAddr) {
+        if let Some((_, relay)) = self.relays.remove(&client_addr) {
+            relay.abort();
+        }
+        self.client_proxies.remove(&client_addr);
+        self.sessions.remove(&client_addr);
+        self.flow_metrics.remove(&client_addr);
+    }
+}
+
+/// Flows as the gateway sees them: traffic only, since RTT, retransmits and
+/// queues belong to the sessions end to end (see each backend's stats stream)
+impl StatsSource for Proxy {
+    fn stats_frame(&self) -> Option<StatsFrame> {
+        let flows = self.flow_metrics.iter()
+            .map(|entry| SessionStats {
+                session_id: 0,
+                peer: *entry.key(),
+                backend: self.sessions.get(entry.key()).map(|backend| *backend),
+                queue_depth: 0,
+                metrics: entry.value().snapshot(),
+            })
+            .collect();
+        Some(StatsFrame::new(self.metrics.snapshot(), flows))
+    }
+}
+
+/// Whether `data` is a `[u16 header_len][Header]...` packet with a close header
+fn is_close_packet(data: &[u8]) -> bool {
+    if data.len() < 2 {
+        return false;
+    }
+    let header_len = u16::from_be_bytes([data[0], data[1]]) as usize;
+    data.get(2..2 + header_len).and_then(peek_msg_type) == Some(FRAME_TYPE_CLOSE)
+}
+
+/// `[u16 header_len][Header][CloseFrame]` telling a client the gateway is going away
+fn close_packet() -> Result<Vec<u8>> {
+    let payload = serde_cbor::to_vec(&CloseFrame::with_reason(CloseReason::GoingAway, "Gateway shutting down".to_string()))?;
+    let header = Header::new(
+        0,
+        FRAME_TYPE_CLOSE,
+        0,
+        0,
+        0,
+        0,
+        DeliveryMode::BestEffort,
+        None,
+        Some(payload.len() as u32),
+    );
+    let header_bytes = serde_cbor::to_vec(&header)?;
+    
+    let mut packet = Vec::with_capacity(2 + header_bytes.len() + payload.len());
+    packet.extend_from_slice(&(header_bytes.len() as u16).to_be_bytes());
+    packet.extend_from_slice(&header_bytes);
+    packet.extend_from_slice(&payload);
+    Ok(packet)
 }
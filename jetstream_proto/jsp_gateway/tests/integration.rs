@@ -49,3 +49,492 @@ async fn test_gateway_proxy() {
         
     assert_eq!(&buf[..len], msg);
 }
+
+#[tokio::test]
+async fn test_pooled_backend_connections() {
+    use jsp_transport::config::ConnectionConfig;
+    use jsp_transport::pool::{ConnectionPool, PoolConfig};
+    use jsp_transport::server::Server;
+
+    const POOL_SIZE: usize = 2;
+
+    // 1. Start a JetStream backend that counts sessions
+    let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+    let backend_addr = server.local_addr().unwrap();
+    let (stop_tx, mut stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let backend_task = tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = &mut stop_rx => break,
+                res = server.accept() => { let _ = res; }
+            }
+        }
+        server.session_count().await
+    });
+
+    // 2. Balancer backed by a pool of pre-handshaked connections
+    let pool = ConnectionPool::new(PoolConfig {
+        max_connections_per_backend: POOL_SIZE,
+        connection: ConnectionConfig::default(),
+        ..PoolConfig::default()
+    });
+    pool.warm(backend_addr).await.unwrap();
+    assert_eq!(pool.idle_count(backend_addr), POOL_SIZE);
+
+    let balancer = LoadBalancer::new(vec![backend_addr], Strategy::RoundRobin).with_pool(pool.clone());
+    let client_addr = "127.0.0.1:40000".parse().unwrap();
+
+    // 3. Proxy messages one after another, each on a checked out connection
+    for i in 0..100u32 {
+        let mut conn = balancer.connection_for(client_addr).await.unwrap();
+        assert_eq!(conn.backend_addr(), backend_addr);
+        conn.send_datagram(&i.to_be_bytes()).await.unwrap();
+    }
+
+    assert_eq!(pool.handshakes(), POOL_SIZE as u64);
+    assert_eq!(pool.idle_count(backend_addr), POOL_SIZE);
+
+    stop_tx.send(()).unwrap();
+    let sessions = backend_task.await.unwrap();
+    assert_eq!(sessions, POOL_SIZE);
+}
+
+#[tokio::test]
+async fn test_consistent_hash_session_affinity() {
+    use jsp_core::types::connection_id::ConnectionId;
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_core::types::header::{Header, FRAME_TYPE_DATA};
+
+    // 1. Two backends that tag their echoes
+    let mut backend_addrs = Vec::new();
+    for tag in [b'A', b'B'] {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        backend_addrs.push(socket.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            loop {
+                let (_len, src) = socket.recv_from(&mut buf).await.unwrap();
+                socket.send_to(&[tag], src).await.unwrap();
+            }
+        });
+    }
+
+    // 2. Gateway balancing on connection IDs
+    let balancer = Arc::new(LoadBalancer::new(backend_addrs, Strategy::ConsistentHash { virtual_nodes: 150 }));
+    let proxy = Arc::new(Proxy::new("127.0.0.1:0", balancer).await.unwrap());
+    let gateway_addr = proxy.socket.local_addr().unwrap();
+    let proxy_task = proxy.clone();
+    tokio::spawn(async move {
+        proxy_task.run().await.unwrap();
+    });
+
+    // 3. Three clients send from two addresses each, as if they migrated halfway
+    for connection_id in [11u64, 22, 33] {
+        let header = Header {
+            connection_id: Some(ConnectionId::from_u64(connection_id)),
+            ..Header::new(1, FRAME_TYPE_DATA, 0, 1, 0, 0, DeliveryMode::Reliable, None, Some(4))
+        };
+        let header_bytes = serde_cbor::to_vec(&header).unwrap();
+        let mut packet = (header_bytes.len() as u16).to_be_bytes().to_vec();
+        packet.extend_from_slice(&header_bytes);
+        packet.extend_from_slice(b"ping");
+
+        let mut tags = Vec::new();
+        for _ in 0..2 {
+            let client_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            client_socket.connect(gateway_addr).await.unwrap();
+            for _ in 0..5 {
+                client_socket.send(&packet).await.unwrap();
+                let mut buf = [0u8; 16];
+                let len = tokio::time::timeout(Duration::from_secs(1), client_socket.recv(&mut buf))
+                    .await
+                    .expect("Timeout waiting for response")
+                    .unwrap();
+                tags.push(buf[..len].to_vec());
+            }
+        }
+
+        assert_eq!(tags.len(), 10);
+        assert!(tags.iter().all(|tag| *tag == tags[0]), "connection {} hit both backends", connection_id);
+    }
+}
+
+#[tokio::test]
+async fn test_sticky_sessions_follow_connection_id() {
+    use jsp_core::types::connection_id::ConnectionId;
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_core::types::header::{Header, FRAME_TYPE_DATA};
+
+    // 1. Two backends that tag their echoes
+    let mut backend_addrs = Vec::new();
+    for tag in [b'A', b'B'] {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        backend_addrs.push(socket.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            loop {
+                let (_len, src) = socket.recv_from(&mut buf).await.unwrap();
+                socket.send_to(&[tag], src).await.unwrap();
+            }
+        });
+    }
+
+    // 2. Gateway with sticky sessions
+    let strategy = Strategy::Sticky { idle_timeout: Duration::from_secs(60) };
+    let balancer = Arc::new(LoadBalancer::new(backend_addrs.clone(), strategy));
+    let proxy = Arc::new(Proxy::new("127.0.0.1:0", balancer.clone()).await.unwrap());
+    let gateway_addr = proxy.socket.local_addr().unwrap();
+    let proxy_task = proxy.clone();
+    tokio::spawn(async move {
+        proxy_task.run().await.unwrap();
+    });
+
+    let header = Header {
+        connection_id: Some(ConnectionId::from_u64(42)),
+        ..Header::new(1, FRAME_TYPE_DATA, 0, 1, 0, 0, DeliveryMode::Reliable, None, Some(4))
+    };
+    let header_bytes = serde_cbor::to_vec(&header).unwrap();
+    let mut packet = (header_bytes.len() as u16).to_be_bytes().to_vec();
+    packet.extend_from_slice(&header_bytes);
+    packet.extend_from_slice(b"ping");
+
+    async fn exchange(socket: &UdpSocket, packet: &[u8]) -> u8 {
+        socket.send(packet).await.unwrap();
+        let mut buf = [0u8; 16];
+        tokio::time::timeout(Duration::from_secs(1), socket.recv(&mut buf))
+            .await
+            .expect("Timeout waiting for response")
+            .unwrap();
+        buf[0]
+    }
+
+    // 3. A handshake without a header, then data for connection 42
+    let first_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    first_socket.connect(gateway_addr).await.unwrap();
+    let pinned = exchange(&first_socket, b"hello").await;
+    for _ in 0..5 {
+        assert_eq!(exchange(&first_socket, &packet).await, pinned);
+    }
+
+    // 4. The same connection from new addresses stays on its backend
+    for _ in 0..3 {
+        let migrated_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        migrated_socket.connect(gateway_addr).await.unwrap();
+        for _ in 0..5 {
+            assert_eq!(exchange(&migrated_socket, &packet).await, pinned, "connection 42 moved backends");
+        }
+    }
+
+    // 5. When its backend goes down, the session moves once and sticks again
+    let pinned_addr = backend_addrs[(pinned - b'A') as usize];
+    balancer.set_healthy(pinned_addr, false).await;
+    let moved = exchange(&first_socket, &packet).await;
+    assert_ne!(moved, pinned);
+    for _ in 0..5 {
+        assert_eq!(exchange(&first_socket, &packet).await, moved);
+    }
+    let migrated_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    migrated_socket.connect(gateway_addr).await.unwrap();
+    assert_eq!(exchange(&migrated_socket, &packet).await, moved);
+}
+
+#[tokio::test]
+async fn test_graceful_drain_completes_transfer() {
+    use jsp_core::types::header::{Header, FRAME_TYPE_CLOSE};
+
+    // 1. Echo backend
+    let backend_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let backend_addr = backend_socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 1024];
+        loop {
+            let (len, src) = backend_socket.recv_from(&mut buf).await.unwrap();
+            backend_socket.send_to(&buf[..len], src).await.unwrap();
+        }
+    });
+
+    // 2. Gateway
+    let balancer = Arc::new(LoadBalancer::new(vec![backend_addr], Strategy::RoundRobin));
+    let proxy = Arc::new(Proxy::new("127.0.0.1:0", balancer).await.unwrap());
+    let gateway_addr = proxy.socket.local_addr().unwrap();
+    let proxy_task = proxy.clone();
+    let run = tokio::spawn(async move { proxy_task.run().await });
+
+    // 3. Transfer 40 chunks, starting shutdown a quarter of the way in
+    let client_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client_socket.connect(gateway_addr).await.unwrap();
+    let mut buf = [0u8; 1024];
+    for i in 0..40u32 {
+        if i == 10 {
+            assert_eq!(proxy.active_flows(), 1);
+            proxy.shutdown(Duration::from_secs(2));
+            assert!(proxy.is_draining());
+
+            // New flows are refused while draining
+            let late_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            late_socket.connect(gateway_addr).await.unwrap();
+            late_socket.send(b"late").await.unwrap();
+            let late = tokio::time::timeout(Duration::from_millis(200), late_socket.recv(&mut buf)).await;
+            assert!(late.is_err(), "new client was served while draining");
+            assert_eq!(proxy.active_flows(), 1);
+        }
+
+        client_socket.send(&i.to_be_bytes()).await.unwrap();
+        let len = tokio::time::timeout(Duration::from_secs(1), client_socket.recv(&mut buf))
+            .await
+            .expect("Transfer stalled while draining")
+            .unwrap();
+        assert_eq!(&buf[..len], &i.to_be_bytes());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(!run.is_finished());
+
+    // 4. Grace expiry closes the flow and ends run()
+    let len = tokio::time::timeout(Duration::from_secs(3), client_socket.recv(&mut buf))
+        .await
+        .expect("No close frame after the grace period")
+        .unwrap();
+    let header_len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+    let header: Header = serde_cbor::from_slice(&buf[2..2 + header_len]).unwrap();
+    assert_eq!(header.msg_type, FRAME_TYPE_CLOSE);
+    assert!(len > 2 + header_len);
+
+    tokio::time::timeout(Duration::from_secs(1), run)
+        .await
+        .expect("run() didn't return after draining")
+        .unwrap()
+        .unwrap();
+    assert_eq!(proxy.active_flows(), 0);
+}
+
+#[tokio::test]
+async fn test_http_ingress_round_trip() {
+    use hyper::{Body, Client, Method, Request, StatusCode};
+    use jsp_gateway::ingress::HttpIngress;
+    use jsp_transport::config::ConnectionConfig;
+    use jsp_transport::events::ServerEvent;
+    use jsp_transport::pool::{ConnectionPool, PoolConfig};
+    use jsp_transport::server::Server;
+
+    // 1. JetStream backend echoing every message on its stream
+    let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+    let backend_addr = server.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            if let Ok(ServerEvent::DataReceived { addr, stream_id, data }) = server.next_event().await {
+                server.send_on_stream(addr, stream_id, &data).await.unwrap();
+            }
+        }
+    });
+
+    // 2. Ingress in front of it; a second one points at a backend that is down
+    let pool = ConnectionPool::new(PoolConfig::default());
+    let balancer = Arc::new(LoadBalancer::new(vec![backend_addr], Strategy::RoundRobin).with_pool(pool));
+    let ingress = HttpIngress::new(balancer).serve("127.0.0.1:0".parse().unwrap()).await.unwrap();
+
+    let dead_backend = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let dead_pool = ConnectionPool::new(PoolConfig {
+        connection: ConnectionConfig::builder()
+            .handshake_retry_interval(Duration::from_millis(50))
+            .handshake_max_retries(1)
+            .build(),
+        ..PoolConfig::default()
+    });
+    let dead_balancer = LoadBalancer::new(vec![dead_backend.local_addr().unwrap()], Strategy::RoundRobin).with_pool(dead_pool);
+    let dead_ingress = HttpIngress::new(Arc::new(dead_balancer)).serve("127.0.0.1:0".parse().unwrap()).await.unwrap();
+
+    // 3. A message posted over HTTP comes back on the same stream
+    let client = Client::new();
+    let post = |addr: std::net::SocketAddr, body: &'static str| {
+        Request::builder()
+            .method(Method::POST)
+            .uri(format!("http://{}/streams/7", addr))
+            .body(Body::from(body))
+            .unwrap()
+    };
+    let response = client.request(post(ingress.local_addr(), "hello ingress")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    let uri = format!("http://{}/streams/7?timeout_ms=5000", ingress.local_addr()).parse().unwrap();
+    let response = client.get(uri).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&body[..], b"hello ingress");
+
+    // 4. Nothing else is waiting
+    let uri = format!("http://{}/streams/7?timeout_ms=100", ingress.local_addr()).parse().unwrap();
+    let response = client.get(uri).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // 5. An unreachable backend is a 502 with a JSON error
+    let response = client.request(post(dead_ingress.local_addr(), "lost")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(response.headers()["content-type"], "application/json");
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error["status"], 502);
+    assert!(error["error"].as_str().unwrap().contains("unreachable"));
+
+    ingress.shutdown().await.unwrap();
+    dead_ingress.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_gateway_shutdown_refuses_new_sessions_while_draining() {
+    use hyper::{Client, StatusCode};
+    use jsp_core::types::control::CloseReason;
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_gateway::config::GatewayConfig;
+    use jsp_gateway::gateway::Gateway;
+    use jsp_transport::config::ConnectionConfig;
+    use jsp_transport::connection::Connection;
+    use jsp_transport::events::ServerEvent;
+    use jsp_transport::server::Server;
+
+    async fn echo(conn: &mut Connection, stream_id: u32, msg: &[u8]) {
+        conn.send_on_stream(stream_id, msg).await.unwrap();
+        loop {
+            let packets = tokio::time::timeout(Duration::from_secs(2), conn.recv())
+                .await
+                .expect("No echo through the gateway")
+                .unwrap();
+            if let Some((_, data)) = packets.into_iter().next() {
+                assert_eq!(&data[..], msg);
+                return;
+            }
+        }
+    }
+
+    // 1. JetStream backend echoing every message on its stream
+    let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+    let backend_addr = server.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            if let Ok(ServerEvent::DataReceived { addr, stream_id, data }) = server.next_event().await {
+                let _ = server.send_on_stream(addr, stream_id, &data).await;
+            }
+        }
+    });
+
+    // 2. Gateway with UDP proxy and HTTP ingress, stopped through a channel
+    let config = GatewayConfig {
+        bind_addr: "127.0.0.1:0".to_string(),
+        backends: vec![backend_addr.to_string()],
+        http_ingress: Some("127.0.0.1:0".to_string()),
+        drain_grace_secs: 10,
+        ..GatewayConfig::default()
+    };
+    let gateway = Gateway::bind(&config).await.unwrap();
+    let gateway_addr = gateway.local_addr().unwrap();
+    let ingress_addr = gateway.ingress_addr().unwrap();
+    let proxy = gateway.proxy().clone();
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let run = tokio::spawn(gateway.run(async { let _ = shutdown_rx.await; }));
+
+    // 3. A session over the proxy and a long poll over the ingress
+    let mut client = Connection::connect_with_config(&gateway_addr.to_string(), ConnectionConfig::default()).await.unwrap();
+    client.handshake().await.unwrap();
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable).unwrap();
+    echo(&mut client, stream_id, b"before shutdown").await;
+
+    let poll_uri = format!("http://{}/streams/3?timeout_ms=500", ingress_addr).parse().unwrap();
+    let long_poll = tokio::spawn(async move { Client::new().get(poll_uri).await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // 4. Shut down
+    shutdown_tx.send(()).unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(proxy.is_draining());
+
+    // New sessions are refused on both fronts
+    let late_config = ConnectionConfig::builder()
+        .handshake_retry_interval(Duration::from_millis(100))
+        .handshake_max_retries(1)
+        .build();
+    let mut late = Connection::connect_with_config(&gateway_addr.to_string(), late_config).await.unwrap();
+    assert!(late.handshake().await.is_err(), "new session handshaked while draining");
+    let uri = format!("http://{}/streams/4", ingress_addr).parse().unwrap();
+    assert!(Client::new().get(uri).await.is_err(), "new HTTP connection accepted while draining");
+    assert_eq!(proxy.active_flows(), 1);
+
+    // Existing ones complete
+    echo(&mut client, stream_id, b"while draining").await;
+    let response = long_poll.await.unwrap().unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(!run.is_finished());
+
+    // 5. Once the last session closes, run() returns well before the grace period
+    client.close(CloseReason::Normal, None).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(2), run)
+        .await
+        .expect("run() kept waiting after every session closed")
+        .unwrap()
+        .unwrap();
+    assert_eq!(proxy.active_flows(), 0);
+}
+
+
+#[tokio::test]
+async fn test_gateway_streams_flow_stats() {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_gateway::config::GatewayConfig;
+    use jsp_gateway::gateway::Gateway;
+    use jsp_transport::config::ConnectionConfig;
+    use jsp_transport::connection::Connection;
+    use jsp_transport::stats_stream::{StatsFrame, STATS_STREAM_ID};
+
+    // 1. Echoing backend behind a gateway that serves stats
+    let backend_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let backend_addr = backend_socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 1024];
+        loop {
+            let (len, src) = backend_socket.recv_from(&mut buf).await.unwrap();
+            backend_socket.send_to(&buf[..len], src).await.unwrap();
+        }
+    });
+
+    let config = GatewayConfig {
+        bind_addr: "127.0.0.1:0".to_string(),
+        backends: vec![backend_addr.to_string()],
+        stats_addr: Some("127.0.0.1:0".to_string()),
+        stats_interval_ms: 100,
+        ..GatewayConfig::default()
+    };
+    let gateway = Gateway::bind(&config).await.unwrap();
+    let gateway_addr = gateway.local_addr().unwrap();
+    let stats_addr = gateway.stats_addr().unwrap();
+    let run = tokio::spawn(gateway.run(std::future::pending()));
+
+    // 2. One flow through the proxy
+    let client_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client_socket.connect(gateway_addr).await.unwrap();
+    client_socket.send(b"hello gateway").await.unwrap();
+    let mut buf = [0u8; 1024];
+    tokio::time::timeout(Duration::from_secs(1), client_socket.recv(&mut buf)).await.unwrap().unwrap();
+
+    // 3. A monitor sees it
+    let mut monitor = Connection::connect_with_config(&stats_addr.to_string(), ConnectionConfig::default()).await.unwrap();
+    monitor.handshake().await.unwrap();
+    monitor.open_reserved_stream(STATS_STREAM_ID, 0, DeliveryMode::Reliable).unwrap();
+    monitor.send_on_stream(STATS_STREAM_ID, b"subscribe").await.unwrap();
+    let frame = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            for (stream_id, data) in monitor.recv().await.unwrap() {
+                if stream_id == STATS_STREAM_ID {
+                    return StatsFrame::from_bytes(&data).unwrap();
+                }
+            }
+        }
+    }).await.unwrap();
+
+    assert_eq!(frame.sessions.len(), 1);
+    let flow = &frame.sessions[0];
+    assert_eq!(flow.peer, client_socket.local_addr().unwrap());
+    assert_eq!(flow.backend, Some(backend_addr));
+    assert_eq!(flow.metrics.packets_received, 1);
+    assert_eq!(flow.metrics.packets_sent, 1);
+    assert_eq!(frame.metrics.bytes_received, b"hello gateway".len() as u64);
+
+    run.abort();
+}
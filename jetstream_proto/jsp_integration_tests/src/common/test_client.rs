@@ -39,7 +39,7 @@ impl TestClient {
     
     /// Send data on a stream
     pub async fn send(&mut self, stream_id: u32, data: &[u8]) -> anyhow::Result<()> {
-        self.connection.send_on_stream(stream_id, data).await
+        Ok(self.connection.send_on_stream(stream_id, data).await?)
     }
     
     /// Receive data from a stream
@@ -65,7 +65,7 @@ impl TestClient {
     
     /// Open a new stream with specified delivery mode
     pub fn open_stream(&mut self, priority: u8, mode: DeliveryMode) -> anyhow::Result<u32> {
-        self.connection.open_stream(priority, mode)
+        Ok(self.connection.open_stream(priority, mode)?)
     }
     
     /// Close a stream
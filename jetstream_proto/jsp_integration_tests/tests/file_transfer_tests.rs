@@ -1,4 +1,4 @@
-use jsp_integration_tests::common::{TestClient, TestServer, create_test_file};
+use jsp_integration_tests::common::{TestClient, TestServer, create_test_file, assert_files_equal};
 use jsp_core::types::delivery::DeliveryMode;
 use std::time::Duration;
 
@@ -119,3 +119,87 @@ async fn test_large_file_transfer_chunked() {
     // Cleanup
     server.stop().await;
 }
+
+#[tokio::test]
+async fn test_file_transfer_resumes_after_disconnect() {
+    use jsp_transport::config::ConnectionConfig;
+    use jsp_transport::connection::Connection;
+    use jsp_transport::file_transfer::{FileTransferOptions, TransferProgress};
+    use tokio::sync::watch;
+    use tokio::time::timeout;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    // The default rate limits would stretch 5 MB over most of a minute
+    fn config() -> ConnectionConfig {
+        ConnectionConfig::builder()
+            .rate_limit_messages(1_000_000)
+            .rate_limit_bytes(1 << 30)
+            .build()
+    }
+
+    let (_temp_dir, file_path, _content) = create_test_file(5 * 1024 * 1024);
+    let out_dir = tempfile::tempdir().expect("Failed to create output dir");
+
+    // First attempt: cut both ends off once half the chunks are confirmed
+    let dir = out_dir.path().to_path_buf();
+    let receiver = tokio::spawn(async move {
+        let mut server = Connection::listen_with_config("127.0.0.1:9130", config()).await.unwrap();
+        server.recv_file(&dir).await
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (progress_tx, mut progress_rx) = watch::channel(TransferProgress::default());
+    let path = file_path.clone();
+    let sender = tokio::spawn(async move {
+        let mut client = Connection::connect_with_config("127.0.0.1:9130", config()).await.unwrap();
+        client.handshake().await.unwrap();
+        let stream_id = client.open_stream(1, DeliveryMode::Reliable).unwrap();
+        let options = FileTransferOptions { progress: Some(progress_tx), ..FileTransferOptions::default() };
+        client.send_file(stream_id, &path, options).await
+    });
+
+    let halfway = timeout(Duration::from_secs(30), progress_rx.wait_for(|p| p.chunk_count > 0 && p.chunks_confirmed * 2 >= p.chunk_count))
+        .await
+        .expect("Transfer stalled before halfway")
+        .expect("Sender stopped before halfway")
+        .clone();
+    sender.abort();
+    receiver.abort();
+    let _ = sender.await;
+    let _ = receiver.await;
+
+    let token = halfway.resume_token();
+    assert!(token.next_chunk < halfway.chunk_count, "transfer finished before it was cut off");
+    assert!(!out_dir.path().join("test_file.bin").exists());
+
+    // Second attempt on a new connection picks up from the partial file
+    let dir = out_dir.path().to_path_buf();
+    let receiver = tokio::spawn(async move {
+        let mut server = Connection::listen_with_config("127.0.0.1:9131", config()).await.unwrap();
+        let received = server.recv_file(&dir).await;
+        (received, server)
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9131", config()).await.expect("Failed to connect");
+    client.handshake().await.expect("Handshake failed");
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable).expect("Failed to open stream");
+    let options = FileTransferOptions { resume: Some(token.clone()), ..FileTransferOptions::default() };
+    let progress = timeout(Duration::from_secs(30), client.send_file(stream_id, &file_path, options))
+        .await
+        .expect("Resumed transfer timed out")
+        .expect("Resumed transfer failed");
+
+    assert!(progress.is_complete());
+    assert_eq!(progress.transfer_id, token.transfer_id);
+    assert!(progress.resumed_from >= token.next_chunk, "resumed from chunk {} before confirmed chunk {}", progress.resumed_from, token.next_chunk);
+
+    let (received, _server) = timeout(Duration::from_secs(5), receiver).await.unwrap().unwrap();
+    let received = received.expect("Receiver failed");
+    assert_eq!(received, out_dir.path().join("test_file.bin"));
+    assert_files_equal(&file_path, &received);
+
+    // The partial file was renamed into place
+    assert_eq!(std::fs::read_dir(out_dir.path()).unwrap().count(), 1);
+}
@@ -1,11 +1,14 @@
 use anyhow::Result;
+use jsp_core::types::delivery::DeliveryMode;
 use jsp_transport::{
     connection::Connection,
     server::Server,
     config::ConnectionConfig,
+    events::ServerEvent,
 };
+use std::collections::HashSet;
 use std::time::Duration;
-use tokio::time::sleep;
+use tokio::time::{sleep, timeout, Instant};
 
 #[tokio::test]
 async fn test_connection_mobility() -> Result<()> {
@@ -74,3 +77,63 @@ async fn test_connection_mobility() -> Result<()> {
     
     Ok(())
 }
+
+#[tokio::test]
+async fn test_migration_mid_transfer_loses_no_reliable_messages() -> Result<()> {
+    const MESSAGES: usize = 200;
+    
+    let mut server = Server::bind("127.0.0.1:9132").await?;
+    let server_task = tokio::spawn(async move {
+        let mut received = HashSet::new();
+        let mut migrations = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while received.len() < MESSAGES || migrations.is_empty() {
+            match tokio::time::timeout_at(deadline, server.next_event()).await {
+                Ok(Ok(ServerEvent::DataReceived { data, .. })) => {
+                    received.insert(String::from_utf8(data.to_vec()).unwrap());
+                }
+                Ok(Ok(ServerEvent::PeerMigrated { old, new })) => migrations.push((old, new)),
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        (received, migrations)
+    });
+    sleep(Duration::from_millis(100)).await;
+    
+    let mut client = Connection::connect_with_config("127.0.0.1:9132", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    let old_port = client.local_addr()?.port();
+    
+    for i in 0..MESSAGES {
+        if i == MESSAGES / 2 {
+            // Messages still in flight are ACKed on the old path while the new one is probed
+            client.migrate("127.0.0.1:0").await?;
+            while client.is_migrating() {
+                let _ = timeout(Duration::from_millis(50), client.next_event()).await;
+            }
+        }
+        let message = format!("message {}", i);
+        client.send_on_stream_wait(stream_id, message.as_bytes(), Some(Duration::from_secs(5))).await?;
+    }
+    let new_port = client.local_addr()?.port();
+    
+    // Keep reading so the client answers the server's path challenge
+    while !server_task.is_finished() {
+        let _ = timeout(Duration::from_millis(50), client.next_event()).await;
+    }
+    let (received, migrations) = server_task.await?;
+    
+    let missing: Vec<usize> = (0..MESSAGES)
+        .filter(|i| !received.contains(&format!("message {}", i)))
+        .collect();
+    assert!(missing.is_empty(), "lost messages {:?}", missing);
+    let ports: Vec<(u16, u16)> = migrations.iter().map(|(old, new)| (old.port(), new.port())).collect();
+    assert_eq!(ports, vec![(old_port, new_port)]);
+    
+    // The second half went out on the validated path and was ACKed there
+    assert!(client.metrics().migration_duration.is_some());
+    
+    Ok(())
+}
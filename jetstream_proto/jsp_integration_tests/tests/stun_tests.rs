@@ -25,7 +25,7 @@ async fn test_stun_discovery() -> anyhow::Result<()> {
     let mut client = Connection::bind_with_config("127.0.0.1:0", config).await?;
     
     // 4. Discover public address
-    let public_addr = client.discover_public_address().await?;
+    let public_addr = client.discover_public_address(false).await?;
     
     // 5. Verify
     assert!(public_addr.is_some());
@@ -0,0 +1,40 @@
+package com.jetstream;
+
+/**
+ * Why a connection was closed.
+ */
+public enum CloseReason {
+    NORMAL(0),
+    GOING_AWAY(1),
+    PROTOCOL_ERROR(2),
+    TIMEOUT(3),
+    RATE_LIMIT_EXCEEDED(4),
+    INTERNAL_ERROR(5),
+    AUTHENTICATION_FAILED(6),
+    TOO_MANY_STREAMS(7);
+
+    private final int code;
+
+    CloseReason(int code) {
+        this.code = code;
+    }
+
+    /**
+     * Code sent on the wire.
+     */
+    public int code() {
+        return code;
+    }
+
+    /**
+     * Reason with the given code, or null if there is none.
+     */
+    public static CloseReason fromCode(int code) {
+        for (CloseReason reason : values()) {
+            if (reason.code == code) {
+                return reason;
+            }
+        }
+        return null;
+    }
+}
@@ -24,12 +24,32 @@ public class Connection implements AutoCloseable {
         return nativeRead(this.nativeHandle, streamId);
     }
 
-    @Override
-    public void close() {
+    /**
+     * Close the connection, telling the peer why.
+     *
+     * @param message human-readable message, or null for none
+     */
+    public void close(CloseReason reason, String message) {
         if (this.nativeHandle != 0) {
-            nativeDisconnect(this.nativeHandle);
+            long handle = this.nativeHandle;
             this.nativeHandle = 0;
+            nativeClose(handle, reason.code(), message);
+        }
+    }
+
+    @Override
+    public void close() {
+        close(CloseReason.NORMAL, null);
+    }
+
+    /**
+     * Why the peer closed the connection, or null while it has not.
+     */
+    public CloseReason getPeerCloseReason() {
+        if (this.nativeHandle == 0) {
+            return null;
         }
+        return CloseReason.fromCode(nativePeerCloseReason(this.nativeHandle));
     }
 
     // Native methods
@@ -53,6 +73,8 @@ public class Connection implements AutoCloseable {
 
     private native long nativeConnect(String address);
     private native void nativeDisconnect(long handle);
+    private native void nativeClose(long handle, int reason, String message);
+    private native int nativePeerCloseReason(long handle);
     private native boolean nativeSend(long handle, int streamId, byte[] data);
     private native void nativeSetDataListener(Connection connection);
 }
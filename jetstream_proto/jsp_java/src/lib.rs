@@ -1,6 +1,7 @@
 use jni::JNIEnv;
 use jni::objects::{JClass, JString, JObject, JByteArray};
 use jni::sys::{jint, jlong, jboolean, jbyteArray};
+use jsp_core::types::control::CloseReason;
 use jsp_transport::connection::Connection;
 use jsp_transport::config::ConnectionConfig;
 use std::sync::Arc;
@@ -78,6 +79,52 @@ pub unsafe extern "system" fn Java_com_jetstream_Connection_nativeDisconnect(
     // native_conn dropped here, connection closed
 }
 
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_jetstream_Connection_nativeClose(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    reason: jint,
+    message: JString,
+) {
+    if handle == 0 {
+        return;
+    }
+    let native_conn = Box::from_raw(handle as *mut NativeConnection);
+    native_conn.running.store(false, Ordering::SeqCst);
+
+    let reason = CloseReason::from_code(reason as u32).unwrap_or(CloseReason::Normal);
+    let message: Option<String> = if message.is_null() {
+        None
+    } else {
+        env.get_string(&message).ok().map(Into::into)
+    };
+    let result = RUNTIME.block_on(async {
+        let mut conn = native_conn.conn.lock().await;
+        conn.close(reason, message).await
+    });
+    if let Err(e) = result {
+        set_java_error(env, format!("Close failed: {}", e));
+    }
+}
+
+/// Code of the reason the peer closed with, or -1 while it has not
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_jetstream_Connection_nativePeerCloseReason(
+    _env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) -> jint {
+    if handle == 0 {
+        return -1;
+    }
+    let native_conn = &*(handle as *mut NativeConnection);
+    RUNTIME.block_on(async {
+        let conn = native_conn.conn.lock().await;
+        conn.peer_close().map_or(-1, |close| close.reason_code.code() as jint)
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "system" fn Java_com_jetstream_Connection_nativeSend(
     mut env: JNIEnv,
@@ -1,115 +1,517 @@
-use crate::crd::JetStreamServer;
+use crate::crd::{Condition, JetStreamServer, JetStreamServerStatus};
+use async_trait::async_trait;
 use kube::{
-    api::{Api, Patch, PatchParams, ResourceExt},
+    api::{Api, DeleteParams, ListParams, Patch, PatchParams, ResourceExt},
     client::Client,
     runtime::controller::{Action, Controller},
-    Error,
+    runtime::finalizer::{self, finalizer},
+    runtime::reflector::ObjectRef,
+    runtime::watcher,
+    Error, Resource,
 };
 use k8s_openapi::api::apps::v1::Deployment;
-use k8s_openapi::api::core::v1::Service;
+use k8s_openapi::api::core::v1::{ConfigMap, Service};
 use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
 use std::sync::Arc;
 use tokio::time::Duration;
 use futures::StreamExt;
 
-pub struct Context {
-    pub client: Client,
+/// Field manager for server-side apply
+const MANAGER: &str = "jsp-controller";
+
+/// Label on every object the controller creates, naming the owning JetStreamServer
+const SERVER_LABEL: &str = "jetstream.io/server";
+
+/// Label telling backends from the gateway
+const ROLE_LABEL: &str = "jetstream.io/role";
+
+/// Keeps a deleted JetStreamServer around until `cleanup` has run
+const FINALIZER: &str = "jetstream.io/cleanup";
+
+/// Pod template annotation with the SHA-256 of the server config
+const CHECKSUM_ANNOTATION: &str = "jetstream.io/config-checksum";
+
+/// Key of the server config in its ConfigMap, and where backends mount it
+const CONFIG_KEY: &str = "server.yaml";
+const CONFIG_DIR: &str = "/etc/jetstream";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReconcileError {
+    #[error("Kubernetes API error: {0}")]
+    Kube(#[from] Error),
+    #[error("Invalid spec: {0}")]
+    InvalidSpec(String),
+    #[error("Finalizer error: {0}")]
+    Finalizer(#[source] Box<finalizer::Error<ReconcileError>>),
 }
 
-async fn reconcile(server: Arc<JetStreamServer>, ctx: Arc<Context>) -> Result<Action, Error> {
-    let client = ctx.client.clone();
+/// The cluster operations `reconcile` needs
+///
+/// Implemented for `kube::Client`; tests substitute a recorder.
+#[async_trait]
+pub trait Cluster: Send + Sync {
+    async fn list_deployments(&self, namespace: &str, selector: &str) -> Result<Vec<Deployment>, Error>;
+    /// Create or update; returns the object as stored, status included
+    async fn apply_deployment(&self, namespace: &str, deployment: &Deployment) -> Result<Deployment, Error>;
+    async fn delete_deployment(&self, namespace: &str, name: &str) -> Result<(), Error>;
+    async fn list_services(&self, namespace: &str, selector: &str) -> Result<Vec<Service>, Error>;
+    /// Create or update; returns the object as stored, cluster IP included
+    async fn apply_service(&self, namespace: &str, service: &Service) -> Result<Service, Error>;
+    async fn delete_service(&self, namespace: &str, name: &str) -> Result<(), Error>;
+    async fn get_config_map(&self, namespace: &str, name: &str) -> Result<Option<ConfigMap>, Error>;
+    async fn apply_config_map(&self, namespace: &str, config_map: &ConfigMap) -> Result<ConfigMap, Error>;
+    async fn patch_status(&self, namespace: &str, name: &str, status: &JetStreamServerStatus) -> Result<(), Error>;
+}
+
+#[async_trait]
+impl Cluster for Client {
+    async fn list_deployments(&self, namespace: &str, selector: &str) -> Result<Vec<Deployment>, Error> {
+        let api: Api<Deployment> = Api::namespaced(self.clone(), namespace);
+        Ok(api.list(&ListParams::default().labels(selector)).await?.items)
+    }
+
+    async fn apply_deployment(&self, namespace: &str, deployment: &Deployment) -> Result<Deployment, Error> {
+        let api: Api<Deployment> = Api::namespaced(self.clone(), namespace);
+        api.patch(&deployment.name_any(), &PatchParams::apply(MANAGER).force(), &Patch::Apply(deployment)).await
+    }
+
+    async fn delete_deployment(&self, namespace: &str, name: &str) -> Result<(), Error> {
+        let api: Api<Deployment> = Api::namespaced(self.clone(), namespace);
+        ignore_not_found(api.delete(name, &DeleteParams::default()).await.map(|_| ()))
+    }
+
+    async fn list_services(&self, namespace: &str, selector: &str) -> Result<Vec<Service>, Error> {
+        let api: Api<Service> = Api::namespaced(self.clone(), namespace);
+        Ok(api.list(&ListParams::default().labels(selector)).await?.items)
+    }
+
+    async fn apply_service(&self, namespace: &str, service: &Service) -> Result<Service, Error> {
+        let api: Api<Service> = Api::namespaced(self.clone(), namespace);
+        api.patch(&service.name_any(), &PatchParams::apply(MANAGER).force(), &Patch::Apply(service)).await
+    }
+
+    async fn delete_service(&self, namespace: &str, name: &str) -> Result<(), Error> {
+        let api: Api<Service> = Api::namespaced(self.clone(), namespace);
+        ignore_not_found(api.delete(name, &DeleteParams::default()).await.map(|_| ()))
+    }
+
+    async fn get_config_map(&self, namespace: &str, name: &str) -> Result<Option<ConfigMap>, Error> {
+        let api: Api<ConfigMap> = Api::namespaced(self.clone(), namespace);
+        api.get_opt(name).await
+    }
+
+    async fn apply_config_map(&self, namespace: &str, config_map: &ConfigMap) -> Result<ConfigMap, Error> {
+        let api: Api<ConfigMap> = Api::namespaced(self.clone(), namespace);
+        api.patch(&config_map.name_any(), &PatchParams::apply(MANAGER).force(), &Patch::Apply(config_map)).await
+    }
+
+    async fn patch_status(&self, namespace: &str, name: &str, status: &JetStreamServerStatus) -> Result<(), Error> {
+        let api: Api<JetStreamServer> = Api::namespaced(self.clone(), namespace);
+        api.patch_status(name, &PatchParams::default(), &Patch::Merge(json!({ "status": status }))).await?;
+        Ok(())
+    }
+}
+
+/// Deleting something already gone is success
+fn ignore_not_found(result: Result<(), Error>) -> Result<(), Error> {
+    match result {
+        Err(Error::Api(response)) if response.code == 404 => Ok(()),
+        other => other,
+    }
+}
+
+pub struct Context<C> {
+    pub cluster: C,
+}
+
+/// Reconcile through the finalizer: a new JetStreamServer gets it first,
+/// a live one is applied and a deleted one is cleaned up before it comes off
+async fn reconcile(server: Arc<JetStreamServer>, ctx: Arc<Context<Client>>) -> Result<Action, ReconcileError> {
+    let namespace = server.namespace().unwrap_or("default".into());
+    let servers: Api<JetStreamServer> = Api::namespaced(ctx.cluster.clone(), &namespace);
+    let cluster = &ctx.cluster;
+    finalizer(&servers, FINALIZER, server, |event| async move {
+        match event {
+            finalizer::Event::Apply(server) => apply(server, cluster).await,
+            finalizer::Event::Cleanup(server) => cleanup(server, cluster).await,
+        }
+    })
+    .await
+    .map_err(|e| ReconcileError::Finalizer(Box::new(e)))
+}
+
+/// Bring the cluster in line with a JetStreamServer
+///
+/// Each replica is a backend with its own single-pod Deployment and a
+/// ClusterIP Service; the gateway Deployment balances across the Service
+/// addresses and a LoadBalancer Service named after the resource exposes
+/// it. Everything is applied every time, so a backend deleted out-of-band
+/// is recreated; backends past `spec.replicas` are deleted once the
+/// gateway no longer lists them.
+async fn apply<C: Cluster>(server: Arc<JetStreamServer>, cluster: &C) -> Result<Action, ReconcileError> {
+    let name = server.name_any();
+    let namespace = server.namespace().unwrap_or("default".into());
+    let replicas = server.spec.replicas.max(0);
+    let selector = backend_selector(&name);
+
+    let config = mounted_config(&server, cluster, &namespace).await?;
+    let existing_deployments = cluster.list_deployments(&namespace, &selector).await?;
+    let existing_services = cluster.list_services(&namespace, &selector).await?;
+
+    // Create/Update backends
+    let mut wanted = HashSet::new();
+    let mut backends = Vec::new();
+    let mut ready_replicas = 0;
+    for index in 0..replicas {
+        let backend_name = backend_name(&name, index);
+        let deployment = cluster.apply_deployment(&namespace, &backend_deployment(&server, index, config.as_ref())).await?;
+        ready_replicas += deployment.status.and_then(|status| status.ready_replicas).unwrap_or(0);
+        let service = cluster.apply_service(&namespace, &backend_service(&server, index)).await?;
+        // A Service gets its cluster IP when created, so this only misses if the API server is odd
+        match service.spec.and_then(|spec| spec.cluster_ip) {
+            Some(ip) => backends.push(format!("{}:{}", ip, server.spec.port)),
+            None => tracing::warn!("Service {} has no cluster IP yet", backend_name),
+        }
+        wanted.insert(backend_name);
+    }
+
+    // Point the gateway at the current backends before removing any
+    let gateway = cluster.apply_deployment(&namespace, &gateway_deployment(&server, &backends)).await?;
+    let gateway_ready = gateway.status.and_then(|status| status.ready_replicas).unwrap_or(0) > 0;
+    cluster.apply_service(&namespace, &gateway_service(&server)).await?;
+
+    // Delete backends past the replica count
+    for deployment in &existing_deployments {
+        let deployment_name = deployment.name_any();
+        if !wanted.contains(&deployment_name) {
+            tracing::info!("Scaling {} down: deleting deployment {}", name, deployment_name);
+            cluster.delete_deployment(&namespace, &deployment_name).await?;
+        }
+    }
+    for service in &existing_services {
+        let service_name = service.name_any();
+        if !wanted.contains(&service_name) {
+            cluster.delete_service(&namespace, &service_name).await?;
+        }
+    }
+
+    let status = server_status(server.status.as_ref(), replicas, ready_replicas, gateway_ready, backends);
+    let all_ready = status.conditions.iter().any(|condition| condition.type_ == "Ready" && condition.status == "True");
+    cluster.patch_status(&namespace, &name, &status).await?;
+
+    // Owned objects changing trigger a reconcile anyway; this is a backstop
+    if all_ready {
+        Ok(Action::requeue(Duration::from_secs(300)))
+    } else {
+        Ok(Action::requeue(Duration::from_secs(10)))
+    }
+}
+
+/// Tear down what `apply` created once the JetStreamServer is deleted
+///
+/// The garbage collector would remove the owned objects anyway, in no
+/// particular order; this takes the gateway down first so no client is
+/// balanced onto a backend that is already gone. The ConfigMap of an
+/// inline config is left to the garbage collector.
+async fn cleanup<C: Cluster>(server: Arc<JetStreamServer>, cluster: &C) -> Result<Action, ReconcileError> {
     let name = server.name_any();
     let namespace = server.namespace().unwrap_or("default".into());
-    let spec = &server.spec;
+    let selector = backend_selector(&name);
+    tracing::info!("Deleting {}: removing its gateway, then its backends", name);
+
+    cluster.delete_service(&namespace, &name).await?;
+    cluster.delete_deployment(&namespace, &gateway_name(&name)).await?;
+    for deployment in cluster.list_deployments(&namespace, &selector).await? {
+        cluster.delete_deployment(&namespace, &deployment.name_any()).await?;
+    }
+    for service in cluster.list_services(&namespace, &selector).await? {
+        cluster.delete_service(&namespace, &service.name_any()).await?;
+    }
+    Ok(Action::await_change())
+}
 
-    // Create/Update Deployment
-    let deployment_json = json!({
+/// The ConfigMap backends mount their config from, and the config's checksum
+struct MountedConfig {
+    config_map: String,
+    checksum: String,
+}
+
+/// Config of the backends, if any; an inline one is written to a ConfigMap
+/// of the server's own
+async fn mounted_config<C: Cluster>(server: &JetStreamServer, cluster: &C, namespace: &str) -> Result<Option<MountedConfig>, ReconcileError> {
+    match (&server.spec.config, &server.spec.config_map) {
+        (Some(_), Some(_)) => Err(ReconcileError::InvalidSpec("set config or config_map, not both".to_string())),
+        (Some(config), None) => {
+            let config_map = cluster.apply_config_map(namespace, &inline_config_map(server, config)).await?;
+            Ok(Some(MountedConfig { config_map: config_map.name_any(), checksum: checksum(config) }))
+        }
+        (None, Some(name)) => {
+            let config_map = cluster.get_config_map(namespace, name).await?
+                .ok_or_else(|| ReconcileError::InvalidSpec(format!("ConfigMap {} not found", name)))?;
+            let config = config_map.data.as_ref().and_then(|data| data.get(CONFIG_KEY))
+                .ok_or_else(|| ReconcileError::InvalidSpec(format!("ConfigMap {} has no {}", name, CONFIG_KEY)))?;
+            Ok(Some(MountedConfig { config_map: name.clone(), checksum: checksum(config) }))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+fn checksum(config: &str) -> String {
+    format!("{:x}", Sha256::digest(config.as_bytes()))
+}
+
+fn backend_name(server_name: &str, index: i32) -> String {
+    format!("{}-backend-{}", server_name, index)
+}
+
+fn gateway_name(server_name: &str) -> String {
+    format!("{}-gateway", server_name)
+}
+
+/// Selects the backend Deployments and Services of a server
+fn backend_selector(server_name: &str) -> String {
+    format!("{}={},{}=backend", SERVER_LABEL, server_name, ROLE_LABEL)
+}
+
+/// Labels of one object the controller manages for `server`
+fn labels(server: &JetStreamServer, role: &str) -> serde_json::Value {
+    json!({
+        "app": server.name_any(),
+        SERVER_LABEL: server.name_any(),
+        ROLE_LABEL: role
+    })
+}
+
+/// Metadata shared by every owned object; the owner reference lets the
+/// controller watch them and the garbage collector remove them with the resource
+fn metadata(server: &JetStreamServer, name: &str, labels: &serde_json::Value) -> serde_json::Value {
+    json!({
+        "name": name,
+        "namespace": server.namespace().unwrap_or("default".into()),
+        "labels": labels,
+        "ownerReferences": server.controller_owner_ref(&()).into_iter().collect::<Vec<_>>()
+    })
+}
+
+fn inline_config_map(server: &JetStreamServer, config: &str) -> ConfigMap {
+    let name = format!("{}-config", server.name_any());
+    serde_json::from_value(json!({
+        "apiVersion": "v1",
+        "kind": "ConfigMap",
+        "metadata": metadata(server, &name, &labels(server, "config")),
+        "data": {
+            CONFIG_KEY: config
+        }
+    })).unwrap()
+}
+
+/// A backend is ready once its server answers `/healthz`, so `readyReplicas`
+/// counts healthy servers
+fn backend_deployment(server: &JetStreamServer, index: i32, config: Option<&MountedConfig>) -> Deployment {
+    let name = backend_name(&server.name_any(), index);
+    let mut labels = labels(server, "backend");
+    labels["jetstream.io/backend"] = json!(name);
+    let mut args = Vec::new();
+    let mut annotations = json!({});
+    let mut volumes = json!([]);
+    let mut volume_mounts = json!([]);
+    if let Some(config) = config {
+        args = vec!["--config".to_string(), format!("{}/{}", CONFIG_DIR, CONFIG_KEY)];
+        // A new checksum changes the pod template, which rolls the backend
+        annotations[CHECKSUM_ANNOTATION] = json!(config.checksum);
+        volumes = json!([{ "name": "config", "configMap": { "name": config.config_map } }]);
+        volume_mounts = json!([{ "name": "config", "mountPath": CONFIG_DIR, "readOnly": true }]);
+    }
+    serde_json::from_value(json!({
         "apiVersion": "apps/v1",
         "kind": "Deployment",
-        "metadata": {
-            "name": name,
-            "namespace": namespace,
-            "labels": {
-                "app": name
-            }
-        },
+        "metadata": metadata(server, &name, &labels),
         "spec": {
-            "replicas": spec.replicas,
+            "replicas": 1,
             "selector": {
-                "matchLabels": {
-                    "app": name
-                }
+                "matchLabels": labels
             },
             "template": {
                 "metadata": {
-                    "labels": {
-                        "app": name
-                    }
+                    "labels": labels,
+                    "annotations": annotations
                 },
                 "spec": {
                     "containers": [{
                         "name": "server",
-                        "image": spec.image,
+                        "image": server.spec.image,
+                        "args": args,
                         "ports": [{
-                            "containerPort": spec.port
-                        }]
-                    }]
+                            "containerPort": server.spec.port,
+                            "protocol": "UDP"
+                        }, {
+                            "name": "health",
+                            "containerPort": server.spec.health_port,
+                            "protocol": "TCP"
+                        }],
+                        "readinessProbe": {
+                            "httpGet": {
+                                "path": "/healthz",
+                                "port": server.spec.health_port
+                            },
+                            "periodSeconds": 5
+                        },
+                        "resources": server.spec.resources,
+                        "volumeMounts": volume_mounts
+                    }],
+                    "volumes": volumes
                 }
             }
         }
-    });
-    let deployment: Deployment = serde_json::from_value(deployment_json).unwrap();
-
-    let deployments: Api<Deployment> = Api::namespaced(client.clone(), &namespace);
-    deployments.patch(
-        &name,
-        &PatchParams::apply("jsp-controller"),
-        &Patch::Apply(&deployment),
-    ).await?;
+    })).unwrap()
+}
 
-    // Create/Update Service
-    let service: k8s_openapi::api::core::v1::Service = serde_json::from_value(json!({
+fn backend_service(server: &JetStreamServer, index: i32) -> Service {
+    let name = backend_name(&server.name_any(), index);
+    let mut labels = labels(server, "backend");
+    labels["jetstream.io/backend"] = json!(name);
+    serde_json::from_value(json!({
         "apiVersion": "v1",
         "kind": "Service",
-        "metadata": {
-            "name": name,
-            "namespace": namespace,
-            "labels": {
-                "app": name
-            }
-        },
+        "metadata": metadata(server, &name, &labels),
+        "spec": {
+            "selector": labels,
+            "ports": [{
+                "protocol": "UDP",
+                "port": server.spec.port,
+                "targetPort": server.spec.port
+            }],
+            "type": "ClusterIP"
+        }
+    })).unwrap()
+}
+
+/// The gateway takes its backends on the command line, so a new list rolls
+/// its pods; they drain existing flows on SIGTERM
+fn gateway_deployment(server: &JetStreamServer, backends: &[String]) -> Deployment {
+    let name = gateway_name(&server.name_any());
+    let labels = labels(server, "gateway");
+    serde_json::from_value(json!({
+        "apiVersion": "apps/v1",
+        "kind": "Deployment",
+        "metadata": metadata(server, &name, &labels),
         "spec": {
+            "replicas": 1,
             "selector": {
-                "app": name
+                "matchLabels": labels
             },
+            "template": {
+                "metadata": {
+                    "labels": labels
+                },
+                "spec": {
+                    "containers": [{
+                        "name": "gateway",
+                        "image": server.spec.gateway_image,
+                        "args": [
+                            "--bind", format!("0.0.0.0:{}", server.spec.port),
+                            "--backends", backends.join(",")
+                        ],
+                        "ports": [{
+                            "containerPort": server.spec.port,
+                            "protocol": "UDP"
+                        }]
+                    }]
+                }
+            }
+        }
+    })).unwrap()
+}
+
+fn gateway_service(server: &JetStreamServer) -> Service {
+    let labels = labels(server, "gateway");
+    serde_json::from_value(json!({
+        "apiVersion": "v1",
+        "kind": "Service",
+        "metadata": metadata(server, &server.name_any(), &labels),
+        "spec": {
+            "selector": labels,
             "ports": [{
                 "protocol": "UDP",
-                "port": spec.port,
-                "targetPort": spec.port
+                "port": server.spec.port,
+                "targetPort": server.spec.port
             }],
             "type": "LoadBalancer"
         }
-    })).unwrap();
+    })).unwrap()
+}
 
-    let services: Api<Service> = Api::namespaced(client.clone(), &namespace);
-    services.patch(
-        &name,
-        &PatchParams::apply("jsp-controller"),
-        &Patch::Apply(&service),
-    ).await?;
+/// Status with a Ready condition; the transition time only moves when the condition flips
+///
+/// Backends count as ready once their `/healthz` answers; the gateway has
+/// no health endpoint, so it counts once its pod runs.
+fn server_status(
+    previous: Option<&JetStreamServerStatus>,
+    replicas: i32,
+    ready_replicas: i32,
+    gateway_ready: bool,
+    backends: Vec<String>,
+) -> JetStreamServerStatus {
+    let backends_ready = ready_replicas >= replicas && backends.len() == replicas as usize;
+    let ready = backends_ready && gateway_ready;
+    let status = if ready { "True" } else { "False" };
+    let last_transition_time = previous
+        .and_then(|previous| previous.conditions.iter().find(|c| c.type_ == "Ready"))
+        .filter(|condition| condition.status == status)
+        .map(|condition| condition.last_transition_time.clone())
+        .unwrap_or_else(|| k8s_openapi::chrono::Utc::now().to_rfc3339());
 
-    Ok(Action::requeue(Duration::from_secs(300)))
+    JetStreamServerStatus {
+        ready_replicas,
+        conditions: vec![Condition {
+            type_: "Ready".to_string(),
+            status: status.to_string(),
+            last_transition_time,
+            reason: match (backends_ready, gateway_ready) {
+                (false, _) => "BackendsPending",
+                (true, false) => "GatewayPending",
+                (true, true) => "BackendsReady",
+            }.to_string(),
+            message: format!(
+                "{}/{} backends ready{}",
+                ready_replicas,
+                replicas,
+                if gateway_ready { "" } else { ", gateway not ready" }
+            ),
+        }],
+        backends,
+    }
 }
 
-fn error_policy(_server: Arc<JetStreamServer>, _error: &Error, _ctx: Arc<Context>) -> Action {
+fn error_policy<C>(_server: Arc<JetStreamServer>, _error: &ReconcileError, _ctx: Arc<Context<C>>) -> Action {
     Action::requeue(Duration::from_secs(30))
 }
 
 pub async fn run(client: Client) {
     let servers = Api::<JetStreamServer>::all(client.clone());
-    let context = Arc::new(Context { client });
+    // Owned objects changing or going away (a pod deleted out-of-band shows
+    // up as a Deployment status change) reconcile their JetStreamServer
+    let owned = watcher::Config::default().labels(SERVER_LABEL);
+    let deployments = Api::<Deployment>::all(client.clone());
+    let services = Api::<Service>::all(client.clone());
+    let config_maps = Api::<ConfigMap>::all(client.clone());
+    let context = Arc::new(Context { cluster: client });
 
-    Controller::new(servers, kube::runtime::watcher::Config::default())
+    let controller = Controller::new(servers, watcher::Config::default());
+    // A ConfigMap referenced by `config_map` changing rolls the backends using it
+    let store = controller.store();
+    controller
+        .owns(deployments, owned.clone())
+        .owns(services, owned)
+        .watches(config_maps, watcher::Config::default(), move |config_map| {
+            store.state().into_iter()
+                .filter(|server| server.namespace() == config_map.namespace()
+                    && server.spec.config_map.as_deref() == Some(config_map.name_any().as_str()))
+                .map(|server| ObjectRef::from_obj(&*server))
+                .collect::<Vec<_>>()
+        })
         .run(reconcile, error_policy, context)
         .for_each(|res| async move {
             match res {
@@ -119,3 +521,405 @@ pub async fn run(client: Client) {
         })
         .await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crd::JetStreamServerSpec;
+    use http::{Request, Response};
+    use hyper::Body;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    /// Records calls and answers as an API server would
+    struct MockCluster {
+        deployments: Vec<String>,
+        services: Vec<String>,
+        /// ConfigMap name to the server config it holds
+        config_maps: BTreeMap<String, String>,
+        calls: Mutex<Vec<String>>,
+        applied: Mutex<Vec<Deployment>>,
+        status: Mutex<Option<JetStreamServerStatus>>,
+        gateway_args: Mutex<Vec<String>>,
+    }
+
+    impl MockCluster {
+        /// A cluster already holding backends `existing` of server `web`
+        fn with_backends(existing: &[i32]) -> Self {
+            let names: Vec<String> = existing.iter().map(|&index| backend_name("web", index)).collect();
+            Self {
+                deployments: names.clone(),
+                services: names,
+                config_maps: BTreeMap::new(),
+                calls: Mutex::new(Vec::new()),
+                applied: Mutex::new(Vec::new()),
+                status: Mutex::new(None),
+                gateway_args: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+
+        fn record(&self, call: String) {
+            self.calls.lock().unwrap().push(call);
+        }
+    }
+
+    /// A listed object with nothing but its name
+    fn named<K: Resource<DynamicType = ()> + serde::de::DeserializeOwned>(name: &str) -> K {
+        serde_json::from_value(json!({
+            "apiVersion": K::api_version(&()),
+            "kind": K::kind(&()),
+            "metadata": { "name": name }
+        })).unwrap()
+    }
+
+    #[async_trait]
+    impl Cluster for MockCluster {
+        async fn list_deployments(&self, _namespace: &str, _selector: &str) -> Result<Vec<Deployment>, Error> {
+            Ok(self.deployments.iter().map(|name| named(name)).collect())
+        }
+
+        async fn apply_deployment(&self, _namespace: &str, deployment: &Deployment) -> Result<Deployment, Error> {
+            self.record(format!("apply deployment {}", deployment.name_any()));
+            self.applied.lock().unwrap().push(deployment.clone());
+            let container = &deployment.spec.as_ref().unwrap().template.spec.as_ref().unwrap().containers[0];
+            if container.name == "gateway" {
+                *self.gateway_args.lock().unwrap() = container.args.clone().unwrap_or_default();
+            }
+            let mut applied = deployment.clone();
+            applied.status = Some(k8s_openapi::api::apps::v1::DeploymentStatus { ready_replicas: Some(1), ..Default::default() });
+            Ok(applied)
+        }
+
+        async fn delete_deployment(&self, _namespace: &str, name: &str) -> Result<(), Error> {
+            self.record(format!("delete deployment {}", name));
+            Ok(())
+        }
+
+        async fn list_services(&self, _namespace: &str, _selector: &str) -> Result<Vec<Service>, Error> {
+            Ok(self.services.iter().map(|name| named(name)).collect())
+        }
+
+        async fn apply_service(&self, _namespace: &str, service: &Service) -> Result<Service, Error> {
+            self.record(format!("apply service {}", service.name_any()));
+            // Stable made-up cluster IPs: 10.0.0.<index + 1>
+            let index = service.name_any().rsplit('-').next().and_then(|i| i.parse::<u8>().ok()).unwrap_or(99);
+            let mut applied = service.clone();
+            applied.spec.as_mut().unwrap().cluster_ip = Some(format!("10.0.0.{}", index + 1));
+            Ok(applied)
+        }
+
+        async fn delete_service(&self, _namespace: &str, name: &str) -> Result<(), Error> {
+            self.record(format!("delete service {}", name));
+            Ok(())
+        }
+
+        async fn get_config_map(&self, _namespace: &str, name: &str) -> Result<Option<ConfigMap>, Error> {
+            Ok(self.config_maps.get(name).map(|config| serde_json::from_value(json!({
+                "apiVersion": "v1",
+                "kind": "ConfigMap",
+                "metadata": { "name": name },
+                "data": { CONFIG_KEY: config }
+            })).unwrap()))
+        }
+
+        async fn apply_config_map(&self, _namespace: &str, config_map: &ConfigMap) -> Result<ConfigMap, Error> {
+            self.record(format!("apply config map {}", config_map.name_any()));
+            Ok(config_map.clone())
+        }
+
+        async fn patch_status(&self, _namespace: &str, name: &str, status: &JetStreamServerStatus) -> Result<(), Error> {
+            self.record(format!("patch status {}", name));
+            *self.status.lock().unwrap() = Some(status.clone());
+            Ok(())
+        }
+    }
+
+    fn server(replicas: i32) -> Arc<JetStreamServer> {
+        let mut server = JetStreamServer::new("web", JetStreamServerSpec {
+            replicas,
+            image: "jetstream:latest".to_string(),
+            port: 8080,
+            health_port: 9090,
+            config: None,
+            config_map: None,
+            resources: None,
+            gateway_image: "jetstream-gateway:latest".to_string(),
+        });
+        server.metadata.namespace = Some("default".to_string());
+        server.metadata.uid = Some("uid-web".to_string());
+        Arc::new(server)
+    }
+
+    /// `server` with `edit` applied
+    fn edited(server: Arc<JetStreamServer>, edit: impl FnOnce(&mut JetStreamServer)) -> Arc<JetStreamServer> {
+        let mut server = (*server).clone();
+        edit(&mut server);
+        Arc::new(server)
+    }
+
+    async fn reconciled_calls(existing: &[i32], replicas: i32) -> (Vec<String>, Arc<Context<MockCluster>>) {
+        let ctx = Arc::new(Context { cluster: MockCluster::with_backends(existing) });
+        apply(server(replicas), &ctx.cluster).await.unwrap();
+        (ctx.cluster.calls(), ctx)
+    }
+
+    /// Pod template of the backend Deployment applied first
+    fn backend_template(cluster: &MockCluster) -> k8s_openapi::api::core::v1::PodTemplateSpec {
+        cluster.applied.lock().unwrap()[0].spec.clone().unwrap().template
+    }
+
+    #[tokio::test]
+    async fn test_scale_up_creates_backends() {
+        let (calls, ctx) = reconciled_calls(&[0], 3).await;
+        assert_eq!(calls, vec![
+            "apply deployment web-backend-0", "apply service web-backend-0",
+            "apply deployment web-backend-1", "apply service web-backend-1",
+            "apply deployment web-backend-2", "apply service web-backend-2",
+            "apply deployment web-gateway", "apply service web",
+            "patch status web",
+        ]);
+
+        let args = ctx.cluster.gateway_args.lock().unwrap().clone();
+        assert_eq!(args, vec!["--bind", "0.0.0.0:8080", "--backends", "10.0.0.1:8080,10.0.0.2:8080,10.0.0.3:8080"]);
+
+        let status = ctx.cluster.status.lock().unwrap().clone().unwrap();
+        assert_eq!(status.ready_replicas, 3);
+        assert_eq!(status.backends.len(), 3);
+        assert_eq!((status.conditions[0].type_.as_str(), status.conditions[0].status.as_str()), ("Ready", "True"));
+    }
+
+    #[tokio::test]
+    async fn test_scale_down_deletes_backends_after_gateway_update() {
+        let (calls, ctx) = reconciled_calls(&[0, 1, 2], 1).await;
+        assert_eq!(calls, vec![
+            "apply deployment web-backend-0", "apply service web-backend-0",
+            "apply deployment web-gateway", "apply service web",
+            "delete deployment web-backend-1", "delete deployment web-backend-2",
+            "delete service web-backend-1", "delete service web-backend-2",
+            "patch status web",
+        ]);
+
+        let args = ctx.cluster.gateway_args.lock().unwrap().clone();
+        assert_eq!(args[3], "10.0.0.1:8080");
+        assert_eq!(ctx.cluster.status.lock().unwrap().as_ref().unwrap().ready_replicas, 1);
+    }
+
+    #[tokio::test]
+    async fn test_backend_deleted_out_of_band_is_recreated() {
+        let (calls, _ctx) = reconciled_calls(&[0, 2], 3).await;
+        assert!(calls.contains(&"apply deployment web-backend-1".to_string()), "{:?}", calls);
+        assert!(calls.contains(&"apply service web-backend-1".to_string()), "{:?}", calls);
+        assert!(!calls.iter().any(|call| call.starts_with("delete")), "{:?}", calls);
+    }
+
+    #[tokio::test]
+    async fn test_config_map_change_rolls_backends() {
+        let server = edited(server(1), |server| server.spec.config_map = Some("web-settings".to_string()));
+        let mut cluster = MockCluster::with_backends(&[]);
+        cluster.config_maps.insert("web-settings".to_string(), "max_sessions: 10\n".to_string());
+        apply(server.clone(), &cluster).await.unwrap();
+
+        let template = backend_template(&cluster);
+        let checksum_before = template.metadata.unwrap().annotations.unwrap()[CHECKSUM_ANNOTATION].clone();
+        assert_eq!(checksum_before, checksum("max_sessions: 10\n"));
+        let pod = template.spec.unwrap();
+        assert_eq!(pod.containers[0].args.as_ref().unwrap(), &vec!["--config".to_string(), "/etc/jetstream/server.yaml".to_string()]);
+        assert_eq!(pod.volumes.unwrap()[0].config_map.as_ref().unwrap().name.as_deref(), Some("web-settings"));
+
+        // Only the pod template annotation tells the new config apart
+        cluster.config_maps.insert("web-settings".to_string(), "max_sessions: 20\n".to_string());
+        cluster.applied.lock().unwrap().clear();
+        apply(server, &cluster).await.unwrap();
+        let checksum_after = backend_template(&cluster).metadata.unwrap().annotations.unwrap()[CHECKSUM_ANNOTATION].clone();
+        assert_ne!(checksum_before, checksum_after);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_config_is_rejected_before_any_change() {
+        let both = edited(server(1), |server| {
+            server.spec.config = Some("max_sessions: 10\n".to_string());
+            server.spec.config_map = Some("web-settings".to_string());
+        });
+        let missing = edited(server(1), |server| server.spec.config_map = Some("web-settings".to_string()));
+        for server in [both, missing] {
+            let cluster = MockCluster::with_backends(&[0]);
+            let result = apply(server, &cluster).await;
+            assert!(matches!(result, Err(ReconcileError::InvalidSpec(_))), "{:?}", result);
+            assert!(cluster.calls().is_empty(), "{:?}", cluster.calls());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_removes_gateway_before_backends() {
+        let cluster = MockCluster::with_backends(&[0, 1]);
+        cleanup(server(2), &cluster).await.unwrap();
+        assert_eq!(cluster.calls(), vec![
+            "delete service web", "delete deployment web-gateway",
+            "delete deployment web-backend-0", "delete deployment web-backend-1",
+            "delete service web-backend-0", "delete service web-backend-1",
+        ]);
+    }
+
+    #[test]
+    fn test_owned_objects_reference_the_server() {
+        let server = server(1);
+        let deployment = backend_deployment(&server, 0, None);
+        let owner = &deployment.metadata.owner_references.as_ref().unwrap()[0];
+        assert_eq!((owner.kind.as_str(), owner.name.as_str(), owner.controller), ("JetStreamServer", "web", Some(true)));
+        assert_eq!(deployment.metadata.labels.as_ref().unwrap()[SERVER_LABEL], "web");
+    }
+
+    #[test]
+    fn test_ready_condition_keeps_transition_time() {
+        let first = server_status(None, 2, 2, true, vec!["a".into(), "b".into()]);
+        let again = server_status(Some(&first), 2, 2, true, vec!["a".into(), "b".into()]);
+        assert_eq!(first.conditions[0].last_transition_time, again.conditions[0].last_transition_time);
+
+        let degraded = server_status(Some(&first), 2, 1, true, vec!["a".into(), "b".into()]);
+        assert_eq!(degraded.conditions[0].status, "False");
+        assert_eq!(degraded.conditions[0].message, "1/2 backends ready");
+
+        let no_gateway = server_status(Some(&first), 2, 2, false, vec!["a".into(), "b".into()]);
+        assert_eq!((no_gateway.conditions[0].status.as_str(), no_gateway.conditions[0].reason.as_str()), ("False", "GatewayPending"));
+    }
+
+    /// A request the mocked API server answered
+    #[derive(Debug)]
+    struct ApiCall {
+        method: String,
+        path: String,
+        body: serde_json::Value,
+    }
+
+    fn summaries(calls: &[ApiCall]) -> Vec<String> {
+        calls.iter().map(|call| format!("{} {}", call.method, call.path)).collect()
+    }
+
+    /// `reconcile` against a `kube::Client` whose API server holds `server`
+    /// and its backends `existing`; returns the requests it answered
+    async fn reconciled_by_client(server: Arc<JetStreamServer>, existing: &[i32]) -> Vec<ApiCall> {
+        let (service, mut handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        let stored = serde_json::to_value(&*server).unwrap();
+        let existing: Vec<String> = existing.iter().map(|&index| backend_name("web", index)).collect();
+        let api_server = tokio::spawn(async move {
+            while let Some((request, send)) = handle.next_request().await {
+                let method = request.method().to_string();
+                let path = request.uri().path().to_string();
+                let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                let body = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+                let response = api_response(&method, &path, &body, &stored, &existing);
+                recorded.lock().unwrap().push(ApiCall { method, path, body });
+                send.send_response(Response::new(Body::from(response.to_string())));
+            }
+        });
+
+        let ctx = Arc::new(Context { cluster: Client::new(service, "default") });
+        reconcile(server, ctx).await.unwrap();
+        api_server.abort();
+        let mut calls = calls.lock().unwrap();
+        std::mem::take(&mut *calls)
+    }
+
+    /// What an API server would answer, for the requests the controller makes
+    fn api_response(method: &str, path: &str, body: &serde_json::Value, stored: &serde_json::Value, existing: &[String]) -> serde_json::Value {
+        let resource: Vec<&str> = path.split_once("/namespaces/default/")
+            .map(|(_, resource)| resource.split('/').collect())
+            .unwrap_or_default();
+        match (method, resource.as_slice()) {
+            ("GET", ["deployments"]) => list::<Deployment>(existing),
+            ("GET", ["services"]) => list::<Service>(existing),
+            ("PATCH", ["deployments", _]) => {
+                let mut deployment = body.clone();
+                deployment["status"] = json!({ "readyReplicas": 1 });
+                deployment
+            }
+            ("PATCH", ["services", name]) => {
+                let index = name.rsplit('-').next().and_then(|i| i.parse::<u8>().ok()).unwrap_or(99);
+                let mut service = body.clone();
+                service["spec"]["clusterIP"] = json!(format!("10.0.0.{}", index + 1));
+                service
+            }
+            ("PATCH", ["configmaps", _]) => body.clone(),
+            ("PATCH", ["jetstreamservers", ..]) => stored.clone(),
+            ("DELETE", _) => json!({ "apiVersion": "v1", "kind": "Status", "status": "Success", "code": 200 }),
+            _ => panic!("unexpected request {} {}", method, path),
+        }
+    }
+
+    fn list<K: Resource<DynamicType = ()> + serde::de::DeserializeOwned + serde::Serialize>(names: &[String]) -> serde_json::Value {
+        json!({
+            "apiVersion": K::api_version(&()),
+            "kind": format!("{}List", K::kind(&())),
+            "metadata": {},
+            "items": names.iter().map(|name| serde_json::to_value(named::<K>(name)).unwrap()).collect::<Vec<_>>()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_client_create_adds_finalizer_first() {
+        let calls = reconciled_by_client(server(2), &[]).await;
+        // Nothing is created until the finalizer is on; adding it triggers the next reconcile
+        assert_eq!(summaries(&calls), vec!["PATCH /apis/jetstream.io/v1/namespaces/default/jetstreamservers/web"]);
+        assert!(calls[0].body.to_string().contains(FINALIZER), "{}", calls[0].body);
+    }
+
+    #[tokio::test]
+    async fn test_client_update_applies_config_and_status() {
+        let server = edited(server(1), |server| {
+            server.metadata.finalizers = Some(vec![FINALIZER.to_string()]);
+            server.spec.config = Some("max_sessions: 10\n".to_string());
+        });
+        let calls = reconciled_by_client(server.clone(), &[]).await;
+        assert_eq!(summaries(&calls), vec![
+            "PATCH /api/v1/namespaces/default/configmaps/web-config",
+            "GET /apis/apps/v1/namespaces/default/deployments",
+            "GET /api/v1/namespaces/default/services",
+            "PATCH /apis/apps/v1/namespaces/default/deployments/web-backend-0",
+            "PATCH /api/v1/namespaces/default/services/web-backend-0",
+            "PATCH /apis/apps/v1/namespaces/default/deployments/web-gateway",
+            "PATCH /api/v1/namespaces/default/services/web",
+            "PATCH /apis/jetstream.io/v1/namespaces/default/jetstreamservers/web/status",
+        ]);
+        assert_eq!(calls[0].body["data"][CONFIG_KEY], "max_sessions: 10\n");
+        let template = &calls[3].body["spec"]["template"];
+        assert_eq!(template["metadata"]["annotations"][CHECKSUM_ANNOTATION], checksum("max_sessions: 10\n"));
+        assert_eq!(template["spec"]["containers"][0]["readinessProbe"]["httpGet"]["path"], "/healthz");
+        let status = &calls[7].body["status"];
+        assert_eq!(status["ready_replicas"], 1);
+        assert_eq!(status["conditions"][0]["status"], "True");
+
+        // Editing the inline config rolls the backend through its pod template
+        let updated = edited(server, |server| server.spec.config = Some("max_sessions: 20\n".to_string()));
+        let calls = reconciled_by_client(updated, &[0]).await;
+        assert_eq!(calls[3].body["spec"]["template"]["metadata"]["annotations"][CHECKSUM_ANNOTATION], checksum("max_sessions: 20\n"));
+    }
+
+    #[tokio::test]
+    async fn test_client_delete_cleans_up_before_removing_finalizer() {
+        let server = edited(server(2), |server| {
+            server.metadata.finalizers = Some(vec![FINALIZER.to_string()]);
+            server.metadata.deletion_timestamp = Some(Time(k8s_openapi::chrono::Utc::now()));
+        });
+        let calls = reconciled_by_client(server, &[0, 1]).await;
+        assert_eq!(summaries(&calls), vec![
+            "DELETE /api/v1/namespaces/default/services/web",
+            "DELETE /apis/apps/v1/namespaces/default/deployments/web-gateway",
+            "GET /apis/apps/v1/namespaces/default/deployments",
+            "DELETE /apis/apps/v1/namespaces/default/deployments/web-backend-0",
+            "DELETE /apis/apps/v1/namespaces/default/deployments/web-backend-1",
+            "GET /api/v1/namespaces/default/services",
+            "DELETE /api/v1/namespaces/default/services/web-backend-0",
+            "DELETE /api/v1/namespaces/default/services/web-backend-1",
+            "PATCH /apis/jetstream.io/v1/namespaces/default/jetstreamservers/web",
+        ]);
+        let removal = calls.last().unwrap().body.to_string();
+        assert!(removal.contains("remove") && removal.contains("/metadata/finalizers/0"), "{}", removal);
+    }
+}
@@ -1,3 +1,4 @@
+use k8s_openapi::api::core::v1::ResourceRequirements;
 use kube::CustomResource;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -7,21 +8,48 @@ use serde::{Deserialize, Serialize};
 #[kube(group = "jetstream.io", version = "v1", kind = "JetStreamServer", namespaced)]
 #[kube(status = "JetStreamServerStatus")]
 pub struct JetStreamServerSpec {
+    /// Number of backend servers, each with its own Deployment and Service
     pub replicas: i32,
+    /// Server image; with a config set, `--config <file>` is appended to its
+    /// entrypoint, as `jsp-cli serve` takes it
     pub image: String,
     pub port: i32,
+    /// Port the server answers `/healthz` on (`jsp-cli serve --metrics-addr`);
+    /// a backend is ready once it does
+    #[serde(default = "default_health_port")]
+    pub health_port: i32,
+    /// Server config (YAML, as `jsp-cli config generate --server` writes it)
+    pub config: Option<String>,
+    /// ConfigMap holding the server config under `server.yaml`, instead of `config`
     pub config_map: Option<String>,
+    /// CPU and memory requests and limits of each backend
+    pub resources: Option<ResourceRequirements>,
+    /// Image of the gateway that balances clients across the backends
+    #[serde(default = "default_gateway_image")]
+    pub gateway_image: String,
+}
+
+fn default_health_port() -> i32 {
+    9090
+}
+
+fn default_gateway_image() -> String {
+    "jetstream-gateway:latest".to_string()
 }
 
 /// Status of JetStreamServer
-#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
 pub struct JetStreamServerStatus {
     pub ready_replicas: i32,
     pub conditions: Vec<Condition>,
+    /// Backend addresses the gateway currently balances across
+    #[serde(default)]
+    pub backends: Vec<String>,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Condition {
+    #[serde(rename = "type")]
     pub type_: String,
     pub status: String,
     pub last_transition_time: String,
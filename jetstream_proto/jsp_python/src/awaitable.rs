@@ -0,0 +1,62 @@
+//! Awaitables for the `*_async` methods
+//!
+//! The Rust future runs on the shared runtime and settles an
+//! `asyncio.Future` of the caller's event loop through
+//! `call_soon_threadsafe`. Cancelling the awaiting task aborts the Rust
+//! future.
+
+use std::future::Future;
+use pyo3::prelude::*;
+use tokio::runtime::Runtime;
+use tokio::task::AbortHandle;
+
+/// Run `future` on `runtime`, returning an awaitable of its result
+///
+/// Must be called from a coroutine, with the event loop running.
+pub fn future_into_py<'py, F, T>(py: Python<'py>, runtime: &Runtime, future: F) -> PyResult<&'py PyAny>
+where
+    F: Future<Output = PyResult<T>> + Send + 'static,
+    T: IntoPy<PyObject>,
+{
+    let event_loop = py.import("asyncio")?.call_method0("get_running_loop")?;
+    let py_future = event_loop.call_method0("create_future")?;
+    let (loop_ref, future_ref): (PyObject, PyObject) = (event_loop.into(), py_future.into());
+
+    let task = runtime.spawn(async move {
+        let result = future.await;
+        Python::with_gil(|py| {
+            let (method, value) = match result {
+                Ok(value) => ("set_result", value.into_py(py)),
+                Err(e) => ("set_exception", e.into_value(py).into_py(py)),
+            };
+            // Only fails once the event loop is closed, with nobody left to tell
+            let _ = wrap_pyfunction!(settle, py)
+                .and_then(|settle| loop_ref.call_method1(py, "call_soon_threadsafe", (settle, future_ref, method, value)));
+        });
+    });
+    py_future.call_method1("add_done_callback", (AbortOnCancel(task.abort_handle()),))?;
+    Ok(py_future)
+}
+
+/// Set the result or exception of `future`, unless it was cancelled meanwhile
+#[pyfunction]
+fn settle(future: &PyAny, method: &str, value: PyObject) -> PyResult<()> {
+    if !future.call_method0("done")?.is_true()? {
+        future.call_method1(method, (value,))?;
+    }
+    Ok(())
+}
+
+/// Done callback aborting the Rust future when its `asyncio.Future` is cancelled
+#[pyclass]
+struct AbortOnCancel(AbortHandle);
+
+#[pymethods]
+impl AbortOnCancel {
+    fn __call__(&self, future: &PyAny) -> PyResult<()> {
+        if future.call_method0("cancelled")?.is_true()? {
+            self.0.abort();
+        }
+        Ok(())
+    }
+}
@@ -1,68 +1,412 @@
 use pyo3::prelude::*;
+use pyo3::create_exception;
 use pyo3::exceptions::PyRuntimeError;
+use pyo3::types::{PyBytes, PyDict};
+use jsp_core::types::control::{CloseFrame, CloseReason};
+use jsp_core::types::delivery::DeliveryMode;
+use jsp_transport::compression::adaptive::CompressionDecision;
+use jsp_transport::error::TransportError;
+use jsp_transport::events::ConnectionEvent;
+use jsp_transport::metrics::StreamStats;
+use std::collections::VecDeque;
 use std::sync::Arc;
 use tokio::runtime::Runtime;
 
+mod awaitable;
+
+create_exception!(
+    jetstream_proto,
+    JetStreamError,
+    PyRuntimeError,
+    "A connection or server call failed"
+);
+create_exception!(
+    jetstream_proto,
+    ConnectionClosed,
+    JetStreamError,
+    "The connection was closed; `reason` and `message` say why"
+);
+create_exception!(jetstream_proto, NotConnected, JetStreamError, "The handshake has not completed");
+create_exception!(jetstream_proto, HandshakeTimeout, JetStreamError, "The server never answered the handshake");
+create_exception!(jetstream_proto, RateLimited, JetStreamError, "The rate limit was hit; the send can be retried later");
+create_exception!(jetstream_proto, CongestionLimited, JetStreamError, "The congestion window is full; the send can be retried once ACKs arrive");
+create_exception!(jetstream_proto, CircuitOpen, JetStreamError, "Sends are rejected after repeated failures until the circuit breaker probes again");
+create_exception!(jetstream_proto, CryptoError, JetStreamError, "The key exchange or message encryption failed");
+
+/// Shared runtime every connection and server runs on
+fn runtime() -> PyResult<&'static Runtime> {
+    jsp_transport::runtime::shared()
+        .map_err(|e| PyRuntimeError::new_err(format!("Failed to create runtime: {}", e)))
+}
+
+/// Set the number of worker threads all connections share (0 = one per
+/// CPU core, the default)
+///
+/// Raises `RuntimeError` once a `Connection` or `Server` was created.
+#[pyfunction]
+fn set_worker_threads(threads: usize) -> PyResult<()> {
+    if !jsp_transport::runtime::set_worker_threads(threads) {
+        return Err(PyRuntimeError::new_err("Runtime already started; set worker threads before creating connections"));
+    }
+    Ok(())
+}
+
+/// Why a connection was closed
+#[pyclass(name = "CloseReason")]
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PyCloseReason {
+    Normal = 0,
+    GoingAway = 1,
+    ProtocolError = 2,
+    Timeout = 3,
+    RateLimitExceeded = 4,
+    InternalError = 5,
+    AuthenticationFailed = 6,
+    TooManyStreams = 7,
+}
+
+impl From<PyCloseReason> for CloseReason {
+    fn from(reason: PyCloseReason) -> Self {
+        match reason {
+            PyCloseReason::Normal => CloseReason::Normal,
+            PyCloseReason::GoingAway => CloseReason::GoingAway,
+            PyCloseReason::ProtocolError => CloseReason::ProtocolError,
+            PyCloseReason::Timeout => CloseReason::Timeout,
+            PyCloseReason::RateLimitExceeded => CloseReason::RateLimitExceeded,
+            PyCloseReason::InternalError => CloseReason::InternalError,
+            PyCloseReason::AuthenticationFailed => CloseReason::AuthenticationFailed,
+            PyCloseReason::TooManyStreams => CloseReason::TooManyStreams,
+        }
+    }
+}
+
+impl From<CloseReason> for PyCloseReason {
+    fn from(reason: CloseReason) -> Self {
+        match reason {
+            CloseReason::Normal => PyCloseReason::Normal,
+            CloseReason::GoingAway => PyCloseReason::GoingAway,
+            CloseReason::ProtocolError => PyCloseReason::ProtocolError,
+            CloseReason::Timeout => PyCloseReason::Timeout,
+            CloseReason::RateLimitExceeded => PyCloseReason::RateLimitExceeded,
+            CloseReason::InternalError => PyCloseReason::InternalError,
+            CloseReason::AuthenticationFailed => PyCloseReason::AuthenticationFailed,
+            CloseReason::TooManyStreams => PyCloseReason::TooManyStreams,
+        }
+    }
+}
+
+/// `ConnectionClosed` carrying the peer's reason and message
+fn connection_closed(py: Python<'_>, close: CloseFrame) -> PyErr {
+    closed_error(py, format!("Connection closed by peer: {:?}", close.reason_code), close.reason_code, close.message)
+}
+
+/// `ConnectionClosed` with `text` as its message and `reason` and `message`
+/// as attributes
+fn closed_error(py: Python<'_>, text: String, reason: CloseReason, message: Option<String>) -> PyErr {
+    let err = ConnectionClosed::new_err(text);
+    let value = err.value(py);
+    // Setting attributes on a fresh exception instance does not fail
+    let _ = value.setattr("reason", PyCloseReason::from(reason).into_py(py));
+    let _ = value.setattr("message", message);
+    err
+}
+
+/// The exception for a failed call, `what` naming it as in "Send failed: ..."
+///
+/// Failures callers handle differently get a class of their own; all of
+/// them derive from `JetStreamError`, itself a `RuntimeError`.
+fn transport_error(what: &str, error: TransportError) -> PyErr {
+    let text = format!("{} failed: {}", what, error);
+    match error {
+        TransportError::ConnectionClosed { reason } => Python::with_gil(|py| closed_error(py, text, reason, None)),
+        TransportError::NotConnected => NotConnected::new_err(text),
+        TransportError::HandshakeTimeout => HandshakeTimeout::new_err(text),
+        TransportError::RateLimited => RateLimited::new_err(text),
+        TransportError::CongestionLimited => CongestionLimited::new_err(text),
+        TransportError::CircuitOpen => CircuitOpen::new_err(text),
+        TransportError::Crypto(_) => CryptoError::new_err(text),
+        _ => JetStreamError::new_err(text),
+    }
+}
+
+/// Receive a batch of in-order data, releasing the GIL while waiting
+///
+/// Once the peer closed and its last data was returned, raises `ConnectionClosed`.
+fn recv_packets(
+    py: Python<'_>,
+    runtime: &Runtime,
+    inner: &tokio::sync::Mutex<jsp_transport::connection::Connection>,
+) -> PyResult<Vec<(u32, Vec<u8>)>> {
+    let received = py.allow_threads(|| runtime.block_on(recv_batch(inner)));
+    packets_or_closed(py, received)
+}
+
+/// A batch of data along with the peer's close frame, if it closed
+async fn recv_batch(
+    inner: &tokio::sync::Mutex<jsp_transport::connection::Connection>,
+) -> Result<(Vec<(u32, Vec<u8>)>, Option<CloseFrame>), TransportError> {
+    let mut conn = inner.lock().await;
+    // Nothing arrives after the close frame, so don't wait for it
+    if let Some(close) = conn.peer_close() {
+        return Ok((Vec::new(), Some(close.clone())));
+    }
+    let packets = conn.recv().await?.into_iter()
+        .map(|(stream_id, data)| (stream_id, data.to_vec()))
+        .collect();
+    Ok((packets, conn.peer_close().cloned()))
+}
+
+/// What `recv` returns for a batch from `recv_batch`
+fn packets_or_closed(
+    py: Python<'_>,
+    received: Result<(Vec<(u32, Vec<u8>)>, Option<CloseFrame>), TransportError>,
+) -> PyResult<Vec<(u32, Vec<u8>)>> {
+    let (packets, peer_close) = received
+        .map_err(|e| transport_error("Recv", e))?;
+
+    match peer_close {
+        Some(close) if packets.is_empty() => Err(connection_closed(py, close)),
+        _ => Ok(packets),
+    }
+}
+
+/// Open a stream with a delivery mode named as in Python
+fn open_stream(
+    runtime: &Runtime,
+    inner: &tokio::sync::Mutex<jsp_transport::connection::Connection>,
+    priority: u8,
+    delivery_mode: &str,
+) -> PyResult<u32> {
+    let mode = match delivery_mode {
+        "reliable" => DeliveryMode::Reliable,
+        "best_effort" => DeliveryMode::BestEffort,
+        _ => return Err(PyRuntimeError::new_err("Invalid delivery mode. Use 'reliable' or 'best_effort'")),
+    };
+    
+    runtime.block_on(async {
+        let mut conn = inner.lock().await;
+        conn.open_stream(priority, mode)
+    }).map_err(|e| transport_error("Open stream", e))
+}
+
+/// Convert a connection event to a dict with a "type" key plus its fields
+fn event_to_py(py: Python<'_>, event: ConnectionEvent) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    match event {
+        ConnectionEvent::DataReceived { stream_id, data } => {
+            dict.set_item("type", "data")?;
+            dict.set_item("stream_id", stream_id)?;
+            dict.set_item("data", PyBytes::new(py, &data))?;
+        }
+        ConnectionEvent::PeerMigrated { old, new } => {
+            dict.set_item("type", "peer_migrated")?;
+            dict.set_item("old", old.to_string())?;
+            dict.set_item("new", new.to_string())?;
+        }
+        ConnectionEvent::PublicAddressDiscovered(addr) => {
+            dict.set_item("type", "public_address")?;
+            dict.set_item("addr", addr.to_string())?;
+        }
+        ConnectionEvent::Closed { reason, message } => {
+            dict.set_item("type", "closed")?;
+            dict.set_item("reason", format!("{:?}", reason))?;
+            dict.set_item("reason_code", reason.code())?;
+            dict.set_item("message", message)?;
+        }
+        ConnectionEvent::StreamFinished(stream_id) => {
+            dict.set_item("type", "stream_finished")?;
+            dict.set_item("stream_id", stream_id)?;
+        }
+        ConnectionEvent::Rebound { old_local, new_local } => {
+            dict.set_item("type", "rebound")?;
+            dict.set_item("old_local", old_local.to_string())?;
+            dict.set_item("new_local", new_local.to_string())?;
+        }
+        ConnectionEvent::NetworkChanged { old, new } => {
+            dict.set_item("type", "network_changed")?;
+            dict.set_item("old", old.as_str())?;
+            dict.set_item("new", new.as_str())?;
+        }
+        ConnectionEvent::CircuitStateChanged { old, new } => {
+            dict.set_item("type", "circuit_state_changed")?;
+            dict.set_item("old", old.as_str())?;
+            dict.set_item("new", new.as_str())?;
+        }
+    }
+    Ok(dict.into())
+}
+
+/// Convert stream statistics to a dict keyed by field name
+fn stream_stats_to_py(py: Python<'_>, stats: StreamStats) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    dict.set_item("stream_id", stats.stream_id)?;
+    dict.set_item("bytes_sent", stats.bytes_sent)?;
+    dict.set_item("bytes_received", stats.bytes_received)?;
+    dict.set_item("messages_sent", stats.messages_sent)?;
+    dict.set_item("messages_received", stats.messages_received)?;
+    dict.set_item("retransmits", stats.retransmits)?;
+    let delivery_mode = match stats.delivery_mode {
+        DeliveryMode::Reliable => "reliable",
+        DeliveryMode::PartiallyReliable { .. } => "partially_reliable",
+        DeliveryMode::BestEffort => "best_effort",
+    };
+    dict.set_item("delivery_mode", delivery_mode)?;
+    dict.set_item("priority", stats.priority)?;
+    Ok(dict.into())
+}
+
+/// Convert a compression decision to a dict; `algorithm` is None when not compressing
+fn compression_decision_to_py(py: Python<'_>, decision: CompressionDecision) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    let algorithm = decision.algorithm.map(|algorithm| format!("{:?}", algorithm).to_lowercase());
+    dict.set_item("algorithm", algorithm)?;
+    dict.set_item("level", decision.level)?;
+    dict.set_item("reason", decision.reason.as_str())?;
+    Ok(dict.into())
+}
+
+/// Iterator over `(stream_id, data)` as messages arrive, from `messages()`
+///
+/// Stops once the peer closed the connection and its last data was
+/// yielded; `close_reason` then says why.
+#[pyclass]
+struct Messages {
+    inner: Arc<tokio::sync::Mutex<jsp_transport::connection::Connection>>,
+    runtime: &'static Runtime,
+    // Rest of the last batch received
+    pending: VecDeque<(u32, Vec<u8>)>,
+    close: Option<CloseFrame>,
+}
+
+impl Messages {
+    fn new(runtime: &'static Runtime, inner: &Arc<tokio::sync::Mutex<jsp_transport::connection::Connection>>) -> Self {
+        Self {
+            inner: inner.clone(),
+            runtime,
+            pending: VecDeque::new(),
+            close: None,
+        }
+    }
+}
+
+#[pymethods]
+impl Messages {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<(u32, Py<PyBytes>)>> {
+        while self.pending.is_empty() && self.close.is_none() {
+            let (runtime, inner) = (self.runtime, &self.inner);
+            let (packets, close) = py.allow_threads(|| runtime.block_on(recv_batch(inner)))
+                .map_err(|e| transport_error("Recv", e))?;
+            self.pending.extend(packets);
+            self.close = close;
+        }
+        Ok(self.pending.pop_front()
+            .map(|(stream_id, data)| (stream_id, PyBytes::new(py, &data).into())))
+    }
+
+    /// Why the peer closed the connection, once iteration stopped
+    #[getter]
+    fn close_reason(&self) -> Option<PyCloseReason> {
+        self.close.as_ref().map(|close| close.reason_code.into())
+    }
+
+    /// The peer's close message, if it sent one
+    #[getter]
+    fn close_message(&self) -> Option<String> {
+        self.close.as_ref().and_then(|close| close.message.clone())
+    }
+}
+
 /// Python wrapper for JetStream Connection
 #[pyclass]
 struct Connection {
     inner: Option<Arc<tokio::sync::Mutex<jsp_transport::connection::Connection>>>,
-    runtime: Arc<Runtime>,
+    runtime: &'static Runtime,
 }
 
 #[pymethods]
 impl Connection {
     #[new]
     fn new() -> PyResult<Self> {
-        let runtime = Arc::new(
-            Runtime::new()
-                .map_err(|e| PyRuntimeError::new_err(format!("Failed to create runtime: {}", e)))?
-        );
-        
         Ok(Self {
             inner: None,
-            runtime,
+            runtime: runtime()?,
         })
     }
 
     /// Connect to a server
-    fn connect(&mut self, addr: String) -> PyResult<()> {
-        let runtime = self.runtime.clone();
+    fn connect(&mut self, py: Python<'_>, addr: String) -> PyResult<()> {
+        let runtime = self.runtime;
         
-        let conn = runtime.block_on(async {
+        let conn = py.allow_threads(|| runtime.block_on(async {
             jsp_transport::connection::Connection::connect_with_config(
                 &addr,
                 jsp_transport::config::ConnectionConfig::default()
             ).await
-        }).map_err(|e| PyRuntimeError::new_err(format!("Connection failed: {}", e)))?;
+        })).map_err(|e| transport_error("Connection", e))?;
         
         self.inner = Some(Arc::new(tokio::sync::Mutex::new(conn)));
         Ok(())
     }
 
+    /// Connect to a server, as a coroutine
+    ///
+    /// `await conn.connect_async(addr)` leaves the event loop running.
+    fn connect_async<'py>(slf: &PyCell<Self>, py: Python<'py>, addr: String) -> PyResult<&'py PyAny> {
+        let runtime = slf.borrow().runtime;
+        let slf: Py<Self> = slf.into();
+        awaitable::future_into_py(py, runtime, async move {
+            let conn = jsp_transport::connection::Connection::connect_with_config(
+                &addr,
+                jsp_transport::config::ConnectionConfig::default()
+            ).await.map_err(|e| transport_error("Connection", e))?;
+
+            Python::with_gil(|py| -> PyResult<()> {
+                slf.try_borrow_mut(py)?.inner = Some(Arc::new(tokio::sync::Mutex::new(conn)));
+                Ok(())
+            })
+        })
+    }
+
     /// Perform handshake
-    fn handshake(&self) -> PyResult<()> {
+    fn handshake(&self, py: Python<'_>) -> PyResult<()> {
         let inner = self.inner.as_ref()
-            .ok_or_else(|| PyRuntimeError::new_err("Not connected"))?;
+            .ok_or_else(|| NotConnected::new_err("Not connected"))?;
         
         let inner_clone = inner.clone();
-        let runtime = self.runtime.clone();
+        let runtime = self.runtime;
         
-        runtime.block_on(async move {
+        py.allow_threads(move || runtime.block_on(async move {
             let mut conn = inner_clone.lock().await;
             conn.handshake().await
-        }).map_err(|e| PyRuntimeError::new_err(format!("Handshake failed: {}", e)))?;
+        })).map_err(|e| transport_error("Handshake", e))?;
         
         Ok(())
     }
 
+    /// Perform handshake, as a coroutine
+    fn handshake_async<'py>(&self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let inner = self.inner.as_ref()
+            .ok_or_else(|| NotConnected::new_err("Not connected"))?
+            .clone();
+        
+        awaitable::future_into_py(py, self.runtime, async move {
+            let mut conn = inner.lock().await;
+            conn.handshake().await
+                .map_err(|e| transport_error("Handshake", e))
+        })
+    }
+
     /// Get session ID
     fn session_id(&self) -> PyResult<u64> {
         let inner = self.inner.as_ref()
-            .ok_or_else(|| PyRuntimeError::new_err("Not connected"))?;
+            .ok_or_else(|| NotConnected::new_err("Not connected"))?;
         
         let inner_clone = inner.clone();
-        let runtime = self.runtime.clone();
+        let runtime = self.runtime;
         
         let session_id = runtime.block_on(async move {
             let conn = inner_clone.lock().await;
@@ -75,70 +419,133 @@ impl Connection {
     /// Open a new stream
     fn open_stream(&self, priority: u8, delivery_mode: String) -> PyResult<u32> {
         let inner = self.inner.as_ref()
-            .ok_or_else(|| PyRuntimeError::new_err("Not connected"))?;
+            .ok_or_else(|| NotConnected::new_err("Not connected"))?;
         
-        let mode = match delivery_mode.as_str() {
-            "reliable" => jsp_core::types::delivery::DeliveryMode::Reliable,
-            "best_effort" => jsp_core::types::delivery::DeliveryMode::BestEffort,
-            _ => return Err(PyRuntimeError::new_err("Invalid delivery mode. Use 'reliable' or 'best_effort'")),
-        };
-        
-        let inner_clone = inner.clone();
-        let runtime = self.runtime.clone();
-        
-        let stream_id = runtime.block_on(async move {
-            let mut conn = inner_clone.lock().await;
-            conn.open_stream(priority, mode)
-        }).map_err(|e| PyRuntimeError::new_err(format!("Open stream failed: {}", e)))?;
-        
-        Ok(stream_id)
+        open_stream(self.runtime, inner, priority, &delivery_mode)
     }
 
     /// Send data on a stream
-    fn send(&self, stream_id: u32, data: Vec<u8>) -> PyResult<()> {
+    fn send(&self, py: Python<'_>, stream_id: u32, data: Vec<u8>) -> PyResult<()> {
         let inner = self.inner.as_ref()
-            .ok_or_else(|| PyRuntimeError::new_err("Not connected"))?;
+            .ok_or_else(|| NotConnected::new_err("Not connected"))?;
         
         let inner_clone = inner.clone();
-        let runtime = self.runtime.clone();
+        let runtime = self.runtime;
         
-        runtime.block_on(async move {
+        py.allow_threads(move || runtime.block_on(async move {
             let mut conn = inner_clone.lock().await;
             conn.send_on_stream(stream_id, &data).await
-        }).map_err(|e| PyRuntimeError::new_err(format!("Send failed: {}", e)))?;
+        })).map_err(|e| transport_error("Send", e))?;
         
         Ok(())
     }
 
+    /// Send data on a stream, as a coroutine
+    fn send_async<'py>(&self, py: Python<'py>, stream_id: u32, data: Vec<u8>) -> PyResult<&'py PyAny> {
+        let inner = self.inner.as_ref()
+            .ok_or_else(|| NotConnected::new_err("Not connected"))?
+            .clone();
+        
+        awaitable::future_into_py(py, self.runtime, async move {
+            let mut conn = inner.lock().await;
+            conn.send_on_stream(stream_id, &data).await
+                .map_err(|e| transport_error("Send", e))
+        })
+    }
+
     /// Receive data
-    fn recv(&self) -> PyResult<Vec<(u32, Vec<u8>)>> {
+    ///
+    /// Raises `ConnectionClosed` once the peer closed the connection.
+    fn recv(&self, py: Python<'_>) -> PyResult<Vec<(u32, Vec<u8>)>> {
+        let inner = self.inner.as_ref()
+            .ok_or_else(|| NotConnected::new_err("Not connected"))?;
+        
+        recv_packets(py, self.runtime, inner)
+    }
+
+    /// Receive data, as a coroutine
+    ///
+    /// Raises `ConnectionClosed` once the peer closed the connection.
+    fn recv_async<'py>(&self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let inner = self.inner.as_ref()
+            .ok_or_else(|| NotConnected::new_err("Not connected"))?
+            .clone();
+        
+        awaitable::future_into_py(py, self.runtime, async move {
+            let received = recv_batch(&inner).await;
+            Python::with_gil(|py| packets_or_closed(py, received))
+        })
+    }
+
+    /// Iterate over `(stream_id, data)` as messages arrive, until the peer
+    /// closes the connection
+    fn messages(&self) -> PyResult<Messages> {
+        let inner = self.inner.as_ref()
+            .ok_or_else(|| NotConnected::new_err("Not connected"))?;
+        
+        Ok(Messages::new(self.runtime, inner))
+    }
+
+    /// Wait for the next connection event (data, migration, close, ...)
+    ///
+    /// Returns a dict whose "type" key names the event.
+    fn next_event(&self, py: Python<'_>) -> PyResult<PyObject> {
         let inner = self.inner.as_ref()
-            .ok_or_else(|| PyRuntimeError::new_err("Not connected"))?;
+            .ok_or_else(|| NotConnected::new_err("Not connected"))?;
         
         let inner_clone = inner.clone();
-        let runtime = self.runtime.clone();
+        let runtime = self.runtime;
         
-        let packets = runtime.block_on(async move {
+        let event = py.allow_threads(move || runtime.block_on(async move {
             let mut conn = inner_clone.lock().await;
-            conn.recv().await
-        }).map_err(|e| PyRuntimeError::new_err(format!("Recv failed: {}", e)))?;
+            conn.next_event().await
+        })).map_err(|e| transport_error("Recv", e))?;
         
-        Ok(packets.into_iter()
-            .map(|(stream_id, data)| (stream_id, data.to_vec()))
-            .collect())
+        event_to_py(py, event)
     }
 
-    /// Close connection
-    fn close(&mut self) -> PyResult<()> {
+    /// Traffic of one stream as a dict, or None for an unknown stream
+    fn stream_stats(&self, py: Python<'_>, stream_id: u32) -> PyResult<Option<PyObject>> {
+        let inner = self.inner.as_ref()
+            .ok_or_else(|| NotConnected::new_err("Not connected"))?;
+        
+        let inner_clone = inner.clone();
+        let runtime = self.runtime;
+        
+        let stats = runtime.block_on(async move {
+            let conn = inner_clone.lock().await;
+            conn.stream_stats(stream_id)
+        });
+        
+        stats.map(|stats| stream_stats_to_py(py, stats)).transpose()
+    }
+
+    /// Adaptive compression's current decision as a dict
+    fn compression_decision(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let inner = self.inner.as_ref()
+            .ok_or_else(|| NotConnected::new_err("Not connected"))?;
+        
+        let inner_clone = inner.clone();
+        let runtime = self.runtime;
+        
+        let decision = runtime.block_on(async move {
+            let conn = inner_clone.lock().await;
+            conn.compression_decision()
+        });
+        
+        compression_decision_to_py(py, decision)
+    }
+
+    /// Close connection, telling the peer why
+    #[pyo3(signature = (reason = PyCloseReason::Normal, message = None))]
+    fn close(&mut self, py: Python<'_>, reason: PyCloseReason, message: Option<String>) -> PyResult<()> {
         if let Some(inner) = self.inner.take() {
-            let runtime = self.runtime.clone();
-            runtime.block_on(async move {
+            let runtime = self.runtime;
+            let message = message.unwrap_or_else(|| "Connection closed by Python SDK".to_string());
+            py.allow_threads(move || runtime.block_on(async move {
                 let mut conn = inner.lock().await;
-                conn.close(
-                    jsp_core::types::control::CloseReason::Normal,
-                    Some("Connection closed by Python SDK".to_string())
-                ).await
-            }).map_err(|e| PyRuntimeError::new_err(format!("Close failed: {}", e)))?;
+                conn.close(reason.into(), Some(message)).await
+            })).map_err(|e| transport_error("Close", e))?;
         }
         Ok(())
     }
@@ -148,69 +555,93 @@ impl Connection {
 #[pyclass]
 struct Server {
     inner: Option<Arc<tokio::sync::Mutex<jsp_transport::connection::Connection>>>,
-    runtime: Arc<Runtime>,
+    runtime: &'static Runtime,
 }
 
 #[pymethods]
 impl Server {
     #[new]
     fn new() -> PyResult<Self> {
-        let runtime = Arc::new(
-            Runtime::new()
-                .map_err(|e| PyRuntimeError::new_err(format!("Failed to create runtime: {}", e)))?
-        );
-        
         Ok(Self {
             inner: None,
-            runtime,
+            runtime: runtime()?,
         })
     }
 
     /// Start listening on an address
-    fn listen(&mut self, addr: String) -> PyResult<()> {
-        let runtime = self.runtime.clone();
+    ///
+    /// Returns once a client completed the handshake.
+    fn listen(&mut self, py: Python<'_>, addr: String) -> PyResult<()> {
+        let runtime = self.runtime;
         
-        let conn = runtime.block_on(async {
+        let conn = py.allow_threads(|| runtime.block_on(async {
             jsp_transport::connection::Connection::listen_with_config(
                 &addr,
                 jsp_transport::config::ConnectionConfig::default()
             ).await
-        }).map_err(|e| PyRuntimeError::new_err(format!("Listen failed: {}", e)))?;
+        })).map_err(|e| transport_error("Listen", e))?;
         
         self.inner = Some(Arc::new(tokio::sync::Mutex::new(conn)));
         Ok(())
     }
 
     /// Receive data
-    fn recv(&self) -> PyResult<Vec<(u32, Vec<u8>)>> {
+    ///
+    /// Raises `ConnectionClosed` once the peer closed the connection.
+    fn recv(&self, py: Python<'_>) -> PyResult<Vec<(u32, Vec<u8>)>> {
+        let inner = self.inner.as_ref()
+            .ok_or_else(|| PyRuntimeError::new_err("Not listening"))?;
+        
+        recv_packets(py, self.runtime, inner)
+    }
+
+    /// Iterate over `(stream_id, data)` as messages arrive, until the peer
+    /// closes the connection
+    fn messages(&self) -> PyResult<Messages> {
+        let inner = self.inner.as_ref()
+            .ok_or_else(|| PyRuntimeError::new_err("Not listening"))?;
+        
+        Ok(Messages::new(self.runtime, inner))
+    }
+
+    /// Open a new stream to send on
+    fn open_stream(&self, priority: u8, delivery_mode: String) -> PyResult<u32> {
+        let inner = self.inner.as_ref()
+            .ok_or_else(|| PyRuntimeError::new_err("Not listening"))?;
+        
+        open_stream(self.runtime, inner, priority, &delivery_mode)
+    }
+
+    /// Wait for the next connection event (data, migration, close, ...)
+    ///
+    /// Returns a dict whose "type" key names the event.
+    fn next_event(&self, py: Python<'_>) -> PyResult<PyObject> {
         let inner = self.inner.as_ref()
             .ok_or_else(|| PyRuntimeError::new_err("Not listening"))?;
         
         let inner_clone = inner.clone();
-        let runtime = self.runtime.clone();
+        let runtime = self.runtime;
         
-        let packets = runtime.block_on(async move {
+        let event = py.allow_threads(move || runtime.block_on(async move {
             let mut conn = inner_clone.lock().await;
-            conn.recv().await
-        }).map_err(|e| PyRuntimeError::new_err(format!("Recv failed: {}", e)))?;
+            conn.next_event().await
+        })).map_err(|e| transport_error("Recv", e))?;
         
-        Ok(packets.into_iter()
-            .map(|(stream_id, data)| (stream_id, data.to_vec()))
-            .collect())
+        event_to_py(py, event)
     }
 
     /// Send data on a stream
-    fn send(&self, stream_id: u32, data: Vec<u8>) -> PyResult<()> {
+    fn send(&self, py: Python<'_>, stream_id: u32, data: Vec<u8>) -> PyResult<()> {
         let inner = self.inner.as_ref()
             .ok_or_else(|| PyRuntimeError::new_err("Not listening"))?;
         
         let inner_clone = inner.clone();
-        let runtime = self.runtime.clone();
+        let runtime = self.runtime;
         
-        runtime.block_on(async move {
+        py.allow_threads(move || runtime.block_on(async move {
             let mut conn = inner_clone.lock().await;
             conn.send_on_stream(stream_id, &data).await
-        }).map_err(|e| PyRuntimeError::new_err(format!("Send failed: {}", e)))?;
+        })).map_err(|e| transport_error("Send", e))?;
         
         Ok(())
     }
@@ -218,8 +649,19 @@ impl Server {
 
 /// JetStreamProto Python module
 #[pymodule]
-fn jetstream_proto(_py: Python, m: &PyModule) -> PyResult<()> {
+fn jetstream_proto(py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<Connection>()?;
     m.add_class::<Server>()?;
+    m.add_class::<Messages>()?;
+    m.add_class::<PyCloseReason>()?;
+    m.add_function(wrap_pyfunction!(set_worker_threads, m)?)?;
+    m.add("JetStreamError", py.get_type::<JetStreamError>())?;
+    m.add("ConnectionClosed", py.get_type::<ConnectionClosed>())?;
+    m.add("NotConnected", py.get_type::<NotConnected>())?;
+    m.add("HandshakeTimeout", py.get_type::<HandshakeTimeout>())?;
+    m.add("RateLimited", py.get_type::<RateLimited>())?;
+    m.add("CongestionLimited", py.get_type::<CongestionLimited>())?;
+    m.add("CircuitOpen", py.get_type::<CircuitOpen>())?;
+    m.add("CryptoError", py.get_type::<CryptoError>())?;
     Ok(())
 }
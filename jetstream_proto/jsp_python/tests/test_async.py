@@ -0,0 +1,56 @@
+"""The *_async methods are awaited from an asyncio event loop."""
+
+import asyncio
+import threading
+import time
+
+import jetstream_proto
+
+
+def test_async_round_trip():
+    server = jetstream_proto.Server()
+    # listen() returns once the client completed the handshake
+    listening = threading.Thread(target=server.listen, args=("127.0.0.1:9497",))
+    listening.start()
+    time.sleep(0.1)
+
+    def echo_once():
+        received = []
+        while not received:
+            received = server.recv()
+        reply_stream = server.open_stream(1, "reliable")
+        for _, data in received:
+            server.send(reply_stream, bytes(data))
+
+    async def main():
+        client = jetstream_proto.Connection()
+        await client.connect_async("127.0.0.1:9497")
+        await client.handshake_async()
+        await asyncio.to_thread(listening.join, 5)
+        assert not listening.is_alive()
+
+        echo = asyncio.create_task(asyncio.to_thread(echo_once))
+        stream_id = client.open_stream(1, "reliable")
+        # A timer on the same loop keeps firing while the round trip is awaited
+        ticks = 0
+
+        async def tick():
+            nonlocal ticks
+            while True:
+                await asyncio.sleep(0.01)
+                ticks += 1
+
+        ticker = asyncio.create_task(tick())
+        await client.send_async(stream_id, b"ping")
+        received = []
+        while not received:
+            received = await asyncio.wait_for(client.recv_async(), timeout=5)
+        await echo
+        ticker.cancel()
+        client.close()
+        return stream_id, received, ticks
+
+    stream_id, received, ticks = asyncio.run(main())
+    # The server's first stream has the same ID as the client's
+    assert [(id, bytes(data)) for id, data in received] == [(stream_id, b"ping")]
+    assert ticks > 0
@@ -0,0 +1,62 @@
+"""Close reasons travel from one peer's close() to the other's recv()."""
+
+import threading
+import time
+
+import pytest
+
+import jetstream_proto
+from jetstream_proto import CloseReason, ConnectionClosed
+
+
+def test_recv_raises_peer_close_reason():
+    server = jetstream_proto.Server()
+    # listen() returns once the client completed the handshake
+    listening = threading.Thread(target=server.listen, args=("127.0.0.1:9471",))
+    listening.start()
+    time.sleep(0.1)
+
+    client = jetstream_proto.Connection()
+    client.connect("127.0.0.1:9471")
+    client.handshake()
+    listening.join(timeout=5)
+    assert not listening.is_alive()
+
+    stream_id = client.open_stream(1, "reliable")
+    client.send(stream_id, b"hello")
+    received = []
+    while not received:
+        received = server.recv()
+    assert received == [(stream_id, b"hello")]
+
+    client.close(CloseReason.ProtocolError, "unexpected frame")
+
+    with pytest.raises(ConnectionClosed) as closed:
+        while True:
+            server.recv()
+    assert closed.value.reason == CloseReason.ProtocolError
+    assert closed.value.message == "unexpected frame"
+
+    # Later calls keep reporting the close instead of waiting for data
+    with pytest.raises(ConnectionClosed):
+        server.recv()
+
+
+def test_close_defaults_to_normal():
+    assert int(CloseReason.Normal) == 0
+    assert int(CloseReason.TooManyStreams) == 7
+
+    server = jetstream_proto.Server()
+    listening = threading.Thread(target=server.listen, args=("127.0.0.1:9472",))
+    listening.start()
+    time.sleep(0.1)
+
+    client = jetstream_proto.Connection()
+    client.connect("127.0.0.1:9472")
+    client.handshake()
+    listening.join(timeout=5)
+    client.close()
+
+    event = server.next_event()
+    assert event["type"] == "closed"
+    assert event["reason_code"] == int(CloseReason.Normal)
@@ -0,0 +1,23 @@
+"""compression_decision() reports what adaptive compression chose."""
+
+import threading
+import time
+
+import jetstream_proto
+
+
+def test_compression_decision():
+    server = jetstream_proto.Server()
+    listening = threading.Thread(target=server.listen, args=("127.0.0.1:9499",))
+    listening.start()
+    time.sleep(0.1)
+
+    client = jetstream_proto.Connection()
+    client.connect("127.0.0.1:9499")
+    client.handshake()
+    listening.join(timeout=5)
+
+    # No RTT measured yet: Zstd at the middle of the default 0-9 range
+    decision = client.compression_decision()
+    assert decision == {"algorithm": "zstd", "level": 4, "reason": "initial"}
+    client.close()
@@ -0,0 +1,33 @@
+"""messages() yields data as it arrives and stops when the peer closes."""
+
+import threading
+import time
+
+import jetstream_proto
+from jetstream_proto import CloseReason
+
+
+def test_messages_until_peer_closes():
+    server = jetstream_proto.Server()
+    # listen() returns once the client completed the handshake
+    listening = threading.Thread(target=server.listen, args=("127.0.0.1:9498",))
+    listening.start()
+    time.sleep(0.1)
+
+    client = jetstream_proto.Connection()
+    client.connect("127.0.0.1:9498")
+    client.handshake()
+    listening.join(timeout=5)
+    assert not listening.is_alive()
+
+    stream_id = client.open_stream(1, "reliable")
+    for message in (b"one", b"two", b"three"):
+        client.send(stream_id, message)
+    client.close(CloseReason.GoingAway, "done")
+
+    messages = server.messages()
+    received = [(sid, data) for sid, data in messages]
+    assert received == [(stream_id, b"one"), (stream_id, b"two"), (stream_id, b"three")]
+    assert all(isinstance(data, bytes) for _, data in received)
+    assert messages.close_reason == CloseReason.GoingAway
+    assert messages.close_message == "done"
@@ -0,0 +1,69 @@
+"""Connections share one runtime, so opening more of them adds no threads."""
+
+import os
+import threading
+import time
+
+import pytest
+
+import jetstream_proto
+
+PAIRS = 24
+FIRST_PORT = 9473
+
+pytestmark = pytest.mark.skipif(not os.path.isdir("/proc/self/task"), reason="counts threads through /proc")
+
+
+def threads():
+    """Names of this process's threads"""
+    names = []
+    for task in os.listdir("/proc/self/task"):
+        with open(f"/proc/self/task/{task}/comm") as comm:
+            names.append(comm.read().strip())
+    return names
+
+
+def open_pair(port):
+    server = jetstream_proto.Server()
+    listening = threading.Thread(target=server.listen, args=(f"127.0.0.1:{port}",))
+    listening.start()
+    time.sleep(0.1)
+
+    client = jetstream_proto.Connection()
+    client.connect(f"127.0.0.1:{port}")
+    client.handshake()
+    listening.join(timeout=5)
+    assert not listening.is_alive()
+    return server, client
+
+
+def test_many_connections_keep_thread_count_bounded():
+    pairs = [open_pair(FIRST_PORT)]
+    baseline = len(threads())
+
+    for port in range(FIRST_PORT + 1, FIRST_PORT + PAIRS):
+        pairs.append(open_pair(port))
+
+    # Every side of every pair works on the shared workers
+    for server, client in pairs:
+        stream_id = client.open_stream(1, "reliable")
+        client.send(stream_id, b"ping")
+        received = []
+        while not received:
+            received = server.recv()
+        assert received == [(stream_id, b"ping")]
+
+    names = threads()
+    assert len(names) <= baseline + 2, f"{len(names)} threads for {2 * PAIRS} connections, {baseline} for 2"
+    workers = [name for name in names if name.startswith("jsp-worker")]
+    assert 0 < len(workers) <= os.cpu_count()
+
+    for _, client in pairs:
+        client.close()
+
+
+def test_worker_threads_fixed_once_running():
+    # Any connection, in this test or an earlier one, started the runtime
+    jetstream_proto.Connection()
+    with pytest.raises(RuntimeError):
+        jetstream_proto.set_worker_threads(2)
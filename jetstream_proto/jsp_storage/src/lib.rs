@@ -1,8 +1,15 @@
 pub mod object_store;
+pub mod object_exchange;
 pub mod message_queue;
+pub mod queue_log;
+pub mod queue_bridge;
 pub mod delivery;
 pub mod replication;
 pub mod merkle_tree;
 
 pub use object_store::ObjectStore;
-pub use message_queue::MessageQueue;
+pub use object_exchange::{FetchStats, ServeStats, OBJECT_STREAM_ID};
+pub use message_queue::{MessageQueue, MessageId, QueueConfig};
+pub use queue_log::{FsyncPolicy, LogConfig};
+pub use queue_bridge::{QueuedData, ServerQueueBridge};
+pub use replication::{GossipPeer, Replicator, ReplicatorConfig};
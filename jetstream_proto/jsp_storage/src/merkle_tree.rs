@@ -2,6 +2,89 @@ use sha2::{Sha256, Digest};
 use sled::Db;
 use anyhow::Result;
 
+/// Hash of a leaf holding `data`
+pub fn leaf_hash(data: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(&[0x00]); // Leaf prefix
+    hasher.update(data);
+    hasher.finalize().to_vec()
+}
+
+/// Hash of an inner node over its two children
+pub fn node_hash(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(&[0x01]); // Node prefix
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().to_vec()
+}
+
+/// Root of a complete tree over `leaf_hashes`
+///
+/// A node without a right sibling is paired with zero padding, as in `MerkleTree`.
+pub fn root_of(leaf_hashes: &[Vec<u8>]) -> Vec<u8> {
+    if leaf_hashes.is_empty() {
+        return vec![0u8; 32];
+    }
+
+    let mut level = leaf_hashes.to_vec();
+    while level.len() > 1 {
+        let padding = vec![0u8; 32];
+        level = level.chunks(2)
+            .map(|pair| node_hash(&pair[0], pair.get(1).unwrap_or(&padding)))
+            .collect();
+    }
+    level.remove(0)
+}
+
+/// Every level of the tree over `leaf_hashes`, from the leaves up to the root
+///
+/// Lets a sender build proofs for many leaves with `proof_from_levels`
+/// without rehashing the tree each time.
+pub fn levels_of(leaf_hashes: &[Vec<u8>]) -> Vec<Vec<Vec<u8>>> {
+    let padding = vec![0u8; 32];
+    let mut levels = vec![leaf_hashes.to_vec()];
+    while levels[levels.len() - 1].len() > 1 {
+        let next = levels[levels.len() - 1].chunks(2)
+            .map(|pair| node_hash(&pair[0], pair.get(1).unwrap_or(&padding)))
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+/// Sibling hashes from leaf `index` up to the root, out of `levels_of`
+pub fn proof_from_levels(levels: &[Vec<Vec<u8>>], index: usize) -> Vec<Vec<u8>> {
+    let padding = vec![0u8; 32];
+    let mut index = index;
+    let mut proof = Vec::new();
+    for level in &levels[..levels.len().saturating_sub(1)] {
+        proof.push(level.get(index ^ 1).unwrap_or(&padding).clone());
+        index /= 2;
+    }
+    proof
+}
+
+/// Sibling hashes from leaf `index` up to the root of `root_of(leaf_hashes)`
+pub fn proof_of(leaf_hashes: &[Vec<u8>], index: usize) -> Vec<Vec<u8>> {
+    proof_from_levels(&levels_of(leaf_hashes), index)
+}
+
+/// Check that `leaf_hash` is leaf `index` of the tree with root `root`
+pub fn verify_proof(leaf_hash: &[u8], index: usize, proof: &[Vec<u8>], root: &[u8]) -> bool {
+    let mut hash = leaf_hash.to_vec();
+    let mut index = index;
+    for sibling in proof {
+        hash = if index & 1 == 0 {
+            node_hash(&hash, sibling)
+        } else {
+            node_hash(sibling, &hash)
+        };
+        index /= 2;
+    }
+    index == 0 && hash == root
+}
+
 #[derive(Debug, Clone)]
 pub struct MerkleTree {
     db: Db,
@@ -26,10 +109,7 @@ impl MerkleTree {
         }).unwrap_or(0);
 
         // Hash leaf
-        let mut hasher = Sha256::new();
-        hasher.update(&[0x00]); // Leaf prefix
-        hasher.update(data);
-        let leaf_hash = hasher.finalize().to_vec();
+        let leaf_hash = leaf_hash(data);
 
         // Store leaf
         self.store_node(&tree, 0, count, &leaf_hash)?;
@@ -137,4 +217,41 @@ mod tests {
         
         Ok(())
     }
+
+    #[test]
+    fn test_root_of_matches_tree() -> Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let tree = MerkleTree::new(db);
+        tree.append(b"data1")?;
+        let root = tree.append(b"data2")?;
+
+        assert_eq!(root_of(&[leaf_hash(b"data1"), leaf_hash(b"data2")]), root);
+        assert_eq!(root_of(&[leaf_hash(b"data1")]), leaf_hash(b"data1"));
+        assert_eq!(root_of(&[]), vec![0u8; 32]);
+
+        // Changing any leaf changes the root
+        let leaves: Vec<Vec<u8>> = (0..5u8).map(|i| leaf_hash(&[i])).collect();
+        let mut tampered = leaves.clone();
+        tampered[4] = leaf_hash(b"other");
+        assert_ne!(root_of(&leaves), root_of(&tampered));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_proofs_verify_against_root() {
+        for count in [1usize, 2, 5, 8, 13] {
+            let leaves: Vec<Vec<u8>> = (0..count).map(|i| leaf_hash(&i.to_be_bytes())).collect();
+            let root = root_of(&leaves);
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = proof_of(&leaves, index);
+                assert!(verify_proof(leaf, index, &proof, &root), "leaf {} of {}", index, count);
+                // The proof is bound to the position and the content
+                assert!(!verify_proof(&leaf_hash(b"other"), index, &proof, &root));
+                if count > 1 {
+                    assert!(!verify_proof(leaf, (index + 1) % count, &proof, &root));
+                }
+            }
+        }
+    }
 }
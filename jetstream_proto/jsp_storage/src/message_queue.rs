@@ -1,21 +1,104 @@
 use sled::Db;
 use anyhow::Result;
 use serde::{Serialize, Deserialize};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::queue_log::{LogConfig, QueueLog};
+
+/// Identifies a polled message for `ack`/`nack` (its position in the topic)
+pub type MessageId = u64;
+
+/// Consumer acknowledgement settings
+#[derive(Debug, Clone)]
+pub struct QueueConfig {
+    /// How long a polled message stays hidden before it is redelivered
+    pub visibility_timeout: Duration,
+    /// Deliveries after which an unacked message goes to the dead-letter queue
+    pub max_deliveries: u32,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            visibility_timeout: Duration::from_secs(30),
+            max_deliveries: 5,
+        }
+    }
+}
+
+/// Polled message waiting for its ack
+#[derive(Debug, Serialize, Deserialize)]
+struct InFlight {
+    payload: Vec<u8>,
+    deliveries: u32,
+    /// Unix time (ms) at which the message becomes visible again
+    visible_at_ms: u64,
+}
 
 #[derive(Debug, Clone)]
 pub struct MessageQueue {
-    db: Db,
+    storage: Storage,
+    config: QueueConfig,
+}
+
+#[derive(Debug, Clone)]
+enum Storage {
+    Sled(Db),
+    /// Append-only segment log, see `open_durable`
+    Log(Arc<Mutex<QueueLog>>),
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+fn inflight_tree(topic: &str) -> String {
+    format!("{}:inflight", topic)
+}
+
+fn dead_letter_tree(topic: &str) -> String {
+    format!("{}:dead", topic)
 }
 
 impl MessageQueue {
     pub fn new(db: Db) -> Self {
-        Self { db }
+        Self::with_config(db, QueueConfig::default())
+    }
+
+    pub fn with_config(db: Db, config: QueueConfig) -> Self {
+        Self { storage: Storage::Sled(db), config }
+    }
+
+    /// Open a queue persisted in an append-only log under `dir`
+    ///
+    /// Every topic's messages, deliveries and acks are replayed on open, so a
+    /// process can drain after a restart what an earlier one enqueued; messages
+    /// that were in flight are redelivered once their visibility timeout passes.
+    pub fn open_durable(dir: impl AsRef<Path>, config: QueueConfig, log_config: LogConfig) -> Result<Self> {
+        let log = QueueLog::open(dir.as_ref(), log_config)?;
+        Ok(Self { storage: Storage::Log(Arc::new(Mutex::new(log))), config })
+    }
+
+    /// Flush the durable log to disk regardless of its fsync policy
+    pub fn sync(&self) -> Result<()> {
+        match &self.storage {
+            Storage::Sled(db) => {
+                db.flush()?;
+                Ok(())
+            }
+            Storage::Log(log) => log.lock().unwrap().sync(),
+        }
     }
 
     /// Enqueue a message to a specific topic
     pub fn enqueue<T: Serialize>(&self, topic: &str, message: &T) -> Result<u64> {
-        let tree = self.db.open_tree(topic)?;
-        let _id = self.db.generate_id()?; // Global ID, but we can use it for unique ordering if we want, or manage our own counters
+        let db = match &self.storage {
+            Storage::Sled(db) => db,
+            Storage::Log(log) => return log.lock().unwrap().enqueue(topic, bincode::serialize(message)?),
+        };
+        let tree = db.open_tree(topic)?;
+        let _id = db.generate_id()?; // Global ID, but we can use it for unique ordering if we want, or manage our own counters
         
         // Better approach for FIFO: use a counter for tail
         let tail_key = b"meta:tail";
@@ -39,8 +122,18 @@ impl MessageQueue {
     }
 
     /// Dequeue a message from a specific topic
+    ///
+    /// The message is removed right away (at-most-once); use `poll` and `ack`
+    /// for at-least-once delivery.
     pub fn dequeue<T: for<'a> Deserialize<'a>>(&self, topic: &str) -> Result<Option<T>> {
-        let tree = self.db.open_tree(topic)?;
+        let db = match &self.storage {
+            Storage::Sled(db) => db,
+            Storage::Log(log) => {
+                let payload = log.lock().unwrap().dequeue(topic)?;
+                return Ok(payload.map(|bytes| bincode::deserialize(&bytes)).transpose()?);
+            }
+        };
+        let tree = db.open_tree(topic)?;
         
         // Get head index
         let head_key = b"meta:head";
@@ -69,9 +162,134 @@ impl MessageQueue {
         }
     }
 
+    /// Take the next message for processing
+    ///
+    /// The message stays hidden for the visibility timeout and is delivered
+    /// again unless it is `ack`ed in time. Messages delivered `max_deliveries`
+    /// times without an ack move to the dead-letter queue.
+    pub fn poll<T: for<'a> Deserialize<'a>>(&self, topic: &str) -> Result<Option<(MessageId, T)>> {
+        let now = now_ms();
+        let db = match &self.storage {
+            Storage::Sled(db) => db,
+            Storage::Log(log) => {
+                let message = log.lock().unwrap().poll(topic, now, &self.config)?;
+                return match message {
+                    Some((id, bytes)) => Ok(Some((id, bincode::deserialize(&bytes)?))),
+                    None => Ok(None),
+                };
+            }
+        };
+        let inflight = db.open_tree(inflight_tree(topic))?;
+        let hidden_until = now + self.config.visibility_timeout.as_millis() as u64;
+
+        // Redeliver timed out messages first, oldest first
+        for entry in inflight.iter() {
+            let (key, bytes) = entry?;
+            let mut message: InFlight = bincode::deserialize(&bytes)?;
+            if message.visible_at_ms > now {
+                continue;
+            }
+
+            if message.deliveries >= self.config.max_deliveries {
+                let dead = db.open_tree(dead_letter_tree(topic))?;
+                dead.insert(&key, message.payload)?;
+                inflight.remove(&key)?;
+                continue;
+            }
+
+            message.deliveries += 1;
+            message.visible_at_ms = hidden_until;
+            inflight.insert(&key, bincode::serialize(&message)?)?;
+
+            let id = MessageId::from_be_bytes(key[..].try_into()?);
+            return Ok(Some((id, bincode::deserialize(&message.payload)?)));
+        }
+
+        // Then the head of the queue
+        let tree = db.open_tree(topic)?;
+        let head_key = b"meta:head";
+        let head_idx = tree.get(head_key)?.map(|b| {
+            let mut arr = [0u8; 8];
+            arr.copy_from_slice(&b);
+            u64::from_be_bytes(arr)
+        }).unwrap_or(0);
+        let target_idx = head_idx + 1;
+
+        let payload = match tree.get(target_idx.to_be_bytes())? {
+            Some(bytes) => bytes.to_vec(),
+            None => return Ok(None),
+        };
+
+        // Track before removing, so a crash in between redelivers rather than loses it
+        let message = InFlight {
+            payload,
+            deliveries: 1,
+            visible_at_ms: hidden_until,
+        };
+        inflight.insert(target_idx.to_be_bytes(), bincode::serialize(&message)?)?;
+        tree.remove(target_idx.to_be_bytes())?;
+        tree.insert(head_key, &target_idx.to_be_bytes())?;
+
+        Ok(Some((target_idx, bincode::deserialize(&message.payload)?)))
+    }
+
+    /// Confirm a polled message was processed; returns false if it wasn't in flight
+    pub fn ack(&self, topic: &str, id: MessageId) -> Result<bool> {
+        let db = match &self.storage {
+            Storage::Sled(db) => db,
+            Storage::Log(log) => return log.lock().unwrap().ack(topic, id),
+        };
+        let inflight = db.open_tree(inflight_tree(topic))?;
+        Ok(inflight.remove(id.to_be_bytes())?.is_some())
+    }
+
+    /// Give a polled message back for immediate redelivery
+    pub fn nack(&self, topic: &str, id: MessageId) -> Result<bool> {
+        let db = match &self.storage {
+            Storage::Sled(db) => db,
+            Storage::Log(log) => return log.lock().unwrap().nack(topic, id),
+        };
+        let inflight = db.open_tree(inflight_tree(topic))?;
+        let mut message: InFlight = match inflight.get(id.to_be_bytes())? {
+            Some(bytes) => bincode::deserialize(&bytes)?,
+            None => return Ok(false),
+        };
+        message.visible_at_ms = 0;
+        inflight.insert(id.to_be_bytes(), bincode::serialize(&message)?)?;
+        Ok(true)
+    }
+
+    /// Messages that exceeded `max_deliveries`, oldest first
+    pub fn dead_letters<T: for<'a> Deserialize<'a>>(&self, topic: &str) -> Result<Vec<(MessageId, T)>> {
+        let db = match &self.storage {
+            Storage::Sled(db) => db,
+            Storage::Log(log) => {
+                let dead = log.lock().unwrap().dead_letters(topic);
+                return dead.into_iter()
+                    .map(|(id, bytes)| Ok((id, bincode::deserialize(&bytes)?)))
+                    .collect();
+            }
+        };
+        let dead = db.open_tree(dead_letter_tree(topic))?;
+        dead.iter()
+            .map(|entry| -> Result<(MessageId, T)> {
+                let (key, bytes) = entry?;
+                let id = MessageId::from_be_bytes(key[..].try_into()?);
+                Ok((id, bincode::deserialize(&bytes)?))
+            })
+            .collect()
+    }
+
     /// Peek at the next message without removing it
     pub fn peek<T: for<'a> Deserialize<'a>>(&self, topic: &str) -> Result<Option<T>> {
-        let tree = self.db.open_tree(topic)?;
+        let db = match &self.storage {
+            Storage::Sled(db) => db,
+            Storage::Log(log) => {
+                let payload = log.lock().unwrap().peek(topic);
+                return Ok(payload.map(|bytes| bincode::deserialize(&bytes)).transpose()?);
+            }
+        };
+        let tree = db.open_tree(topic)?;
         let head_key = b"meta:head";
         let head_idx = tree.get(head_key)?.map(|b| {
             let mut arr = [0u8; 8];
@@ -91,7 +309,11 @@ impl MessageQueue {
     
     /// Get queue length
     pub fn len(&self, topic: &str) -> Result<u64> {
-        let tree = self.db.open_tree(topic)?;
+        let db = match &self.storage {
+            Storage::Sled(db) => db,
+            Storage::Log(log) => return Ok(log.lock().unwrap().len(topic)),
+        };
+        let tree = db.open_tree(topic)?;
         let head_key = b"meta:head";
         let tail_key = b"meta:tail";
         
@@ -143,4 +365,105 @@ mod tests {
         
         Ok(())
     }
+
+    fn queue_with(visibility_timeout: Duration, max_deliveries: u32) -> Result<MessageQueue> {
+        let db = sled::Config::new().temporary(true).open()?;
+        Ok(MessageQueue::with_config(db, QueueConfig { visibility_timeout, max_deliveries }))
+    }
+
+    #[test]
+    fn test_redelivery_after_visibility_timeout() -> Result<()> {
+        let queue = queue_with(Duration::from_millis(100), 5)?;
+        queue.enqueue("jobs", &"job1".to_string())?;
+        queue.enqueue("jobs", &"job2".to_string())?;
+
+        let (id1, job1) = queue.poll::<String>("jobs")?.unwrap();
+        assert_eq!(job1, "job1");
+        let (id2, job2) = queue.poll::<String>("jobs")?.unwrap();
+        assert_eq!(job2, "job2");
+        assert_eq!(queue.len("jobs")?, 0);
+
+        // Nothing is visible until the timeout passes
+        assert!(queue.poll::<String>("jobs")?.is_none());
+        assert!(queue.ack("jobs", id2)?);
+
+        std::thread::sleep(Duration::from_millis(150));
+
+        // The unacked message comes back with the same ID; the acked one doesn't
+        let (again, job) = queue.poll::<String>("jobs")?.unwrap();
+        assert_eq!((again, job.as_str()), (id1, "job1"));
+        assert!(queue.poll::<String>("jobs")?.is_none());
+
+        assert!(queue.ack("jobs", id1)?);
+        assert!(!queue.ack("jobs", id1)?);
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(queue.poll::<String>("jobs")?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dead_letter_after_max_deliveries() -> Result<()> {
+        let queue = queue_with(Duration::from_secs(60), 3)?;
+        queue.enqueue("jobs", &"poison".to_string())?;
+        queue.enqueue("jobs", &"good".to_string())?;
+
+        // A nack makes the message visible again right away
+        let (id, _) = queue.poll::<String>("jobs")?.unwrap();
+        for _ in 1..3 {
+            assert!(queue.nack("jobs", id)?);
+            let (again, _) = queue.poll::<String>("jobs")?.unwrap();
+            assert_eq!(again, id);
+        }
+
+        // Third delivery failed too: dead-lettered, and the queue moves on
+        assert!(queue.nack("jobs", id)?);
+        let (next, job) = queue.poll::<String>("jobs")?.unwrap();
+        assert_ne!(next, id);
+        assert_eq!(job, "good");
+
+        let dead: Vec<(MessageId, String)> = queue.dead_letters("jobs")?;
+        assert_eq!(dead, vec![(id, "poison".to_string())]);
+        assert!(!queue.ack("jobs", id)?);
+
+        Ok(())
+    }
+    #[test]
+    fn test_durable_queue_redelivers_after_reopen() -> Result<()> {
+        use crate::queue_log::{FsyncPolicy, LogConfig};
+
+        let dir = tempfile::tempdir()?;
+        let config = QueueConfig { visibility_timeout: Duration::from_millis(100), max_deliveries: 5 };
+        let log_config = LogConfig { segment_size: 8 * 1024, fsync: FsyncPolicy::Interval(Duration::from_millis(50)) };
+
+        let queue = MessageQueue::open_durable(dir.path(), config.clone(), log_config.clone())?;
+        for i in 0..1000u32 {
+            queue.enqueue("jobs", &i)?;
+        }
+        // Half are taken but never acked
+        for _ in 0..500 {
+            queue.poll::<u32>("jobs")?.unwrap();
+        }
+        drop(queue);
+
+        let queue = MessageQueue::open_durable(dir.path(), config, log_config)?;
+        assert_eq!(queue.len("jobs")?, 500);
+        std::thread::sleep(Duration::from_millis(150));
+
+        let mut seen = std::collections::HashSet::new();
+        while let Some((id, value)) = queue.poll::<u32>("jobs")? {
+            assert!(seen.insert(value), "{} delivered twice", value);
+            assert!(queue.ack("jobs", id)?);
+        }
+        assert_eq!(seen, (0..1000).collect());
+
+        // Acks are durable too
+        drop(queue);
+        let queue = MessageQueue::open_durable(dir.path(), QueueConfig::default(), LogConfig::default())?;
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(queue.poll::<u32>("jobs")?.is_none());
+        assert_eq!(queue.len("jobs")?, 0);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,355 @@
+use anyhow::{bail, Context, Result};
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::time::Instant;
+use jsp_core::stream::RESERVED_STREAM_ID_START;
+use jsp_core::types::delivery::DeliveryMode;
+use jsp_transport::connection::Connection;
+use jsp_transport::events::ConnectionEvent;
+use crate::merkle_tree::{leaf_hash, levels_of, proof_from_levels, verify_proof};
+use crate::object_store::{ObjectManifest, ObjectStore};
+
+/// Reserved stream carrying object requests and manifests
+///
+/// Chunks travel as datagrams instead, so a lost or damaged chunk is
+/// re-requested by the fetcher rather than retransmitted by the transport.
+pub const OBJECT_STREAM_ID: u32 = RESERVED_STREAM_ID_START + 0x100;
+
+/// Chunks requested but not received yet, per fetch
+const REQUEST_WINDOW: usize = 64;
+/// Time after which an outstanding chunk is requested again
+const CHUNK_TIMEOUT: Duration = Duration::from_millis(500);
+/// Requests for a single chunk before the fetch gives up
+const MAX_CHUNK_ATTEMPTS: u32 = 10;
+const MANIFEST_TIMEOUT: Duration = Duration::from_secs(10);
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum ObjectFrame {
+    Get { root: Vec<u8> },
+    Manifest { root: Vec<u8>, size: u64, chunk_size: u32, chunk_count: u64 },
+    NotFound { root: Vec<u8> },
+    /// The object exists but cannot be served, e.g. its chunks don't fit in a datagram
+    Unavailable { root: Vec<u8>, message: String },
+    Request { root: Vec<u8>, indices: Vec<u64> },
+    Chunk { root: Vec<u8>, index: u64, data: Vec<u8>, proof: Vec<Vec<u8>> },
+}
+
+/// Counters of one `serve` run
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServeStats {
+    pub objects_requested: u64,
+    pub chunks_sent: u64,
+}
+
+/// Outcome of a successful `fetch`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchStats {
+    /// Manifest of the object as committed locally, under its root hash
+    pub manifest: ObjectManifest,
+    /// Chunk requests sent again after a timeout or a rejected chunk
+    pub re_requests: u64,
+    /// Received chunks whose merkle proof did not verify
+    pub rejected_chunks: u64,
+}
+
+/// Object being served, kept between requests for its chunks
+struct ServedObject {
+    key: Vec<u8>,
+    manifest: ObjectManifest,
+    levels: Vec<Vec<Vec<u8>>>,
+}
+
+fn open_object_stream(conn: &mut Connection) -> Result<()> {
+    conn.open_reserved_stream(OBJECT_STREAM_ID, 0, DeliveryMode::Reliable)?;
+    Ok(())
+}
+
+async fn send_frame(conn: &mut Connection, frame: &ObjectFrame) -> Result<()> {
+    let bytes = bincode::serialize(frame).context("Failed to serialize object frame")?;
+    Ok(conn.send_on_stream_wait(OBJECT_STREAM_ID, &bytes, Some(SEND_TIMEOUT)).await?)
+}
+
+/// Merkle proof length for an object of `chunk_count` chunks
+fn proof_depth(chunk_count: u64) -> usize {
+    chunk_count.next_power_of_two().trailing_zeros() as usize
+}
+
+impl ObjectStore {
+    /// Answer object requests from the peer of `conn` until it closes the connection
+    ///
+    /// Objects are looked up by root hash. Each chunk is sent as a datagram
+    /// with its merkle proof; chunks the rate limiter refuses are skipped and
+    /// left for the fetcher to re-request. Events other than object requests
+    /// are dropped while serving.
+    pub async fn serve(&self, conn: &mut Connection) -> Result<ServeStats> {
+        open_object_stream(conn)?;
+        let mut stats = ServeStats::default();
+        let mut served: Option<ServedObject> = None;
+
+        loop {
+            let data = match conn.next_event().await? {
+                ConnectionEvent::DataReceived { stream_id: OBJECT_STREAM_ID, data } => data,
+                ConnectionEvent::Closed { .. } => return Ok(stats),
+                event => {
+                    tracing::trace!(?event, "Ignoring event while serving objects");
+                    continue;
+                }
+            };
+            conn.flush_acks().await?;
+
+            let frame: ObjectFrame = match bincode::deserialize(&data) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    tracing::warn!("Dropping malformed object frame: {}", e);
+                    continue;
+                }
+            };
+
+            match frame {
+                ObjectFrame::Get { root } => {
+                    stats.objects_requested += 1;
+                    served = self.served_object(&root)?;
+                    let reply = match &served {
+                        Some(object) => match self.datagram_fit_error(conn, object)? {
+                            None => ObjectFrame::Manifest {
+                                root,
+                                size: object.manifest.size,
+                                chunk_size: object.manifest.chunk_size,
+                                chunk_count: object.manifest.chunk_hashes.len() as u64,
+                            },
+                            Some(message) => ObjectFrame::Unavailable { root, message },
+                        },
+                        None => ObjectFrame::NotFound { root },
+                    };
+                    send_frame(conn, &reply).await?;
+                }
+                ObjectFrame::Request { root, indices } => {
+                    if served.as_ref().is_none_or(|object| object.manifest.root != root) {
+                        served = self.served_object(&root)?;
+                    }
+                    let object = match &served {
+                        Some(object) => object,
+                        None => {
+                            send_frame(conn, &ObjectFrame::NotFound { root }).await?;
+                            continue;
+                        }
+                    };
+                    for index in indices {
+                        let frame = match self.chunk_frame(object, index)? {
+                            Some(frame) => frame,
+                            None => continue,
+                        };
+                        match conn.send_datagram(&frame).await {
+                            Ok(()) => stats.chunks_sent += 1,
+                            Err(e) => tracing::debug!(index, "Skipping chunk: {}", e),
+                        }
+                    }
+                }
+                frame => tracing::debug!(?frame, "Unexpected object frame"),
+            }
+        }
+    }
+
+    fn served_object(&self, root: &[u8]) -> Result<Option<ServedObject>> {
+        Ok(self.find_by_root(root)?.map(|(key, manifest)| ServedObject {
+            key,
+            levels: levels_of(&manifest.chunk_hashes),
+            manifest,
+        }))
+    }
+
+    /// Encoded chunk `index` of `object` with its proof, if it exists
+    fn chunk_frame(&self, object: &ServedObject, index: u64) -> Result<Option<Vec<u8>>> {
+        if index >= object.manifest.chunk_hashes.len() as u64 {
+            return Ok(None);
+        }
+        let data = match self.read_chunk(&object.key, index)? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        let frame = ObjectFrame::Chunk {
+            root: object.manifest.root.clone(),
+            index,
+            data,
+            proof: proof_from_levels(&object.levels, index as usize),
+        };
+        Ok(Some(bincode::serialize(&frame)?))
+    }
+
+    /// Why chunk frames of `object` can't be sent as datagrams on `conn`, if they can't
+    fn datagram_fit_error(&self, conn: &Connection, object: &ServedObject) -> Result<Option<String>> {
+        // Every proof has the same length and the first chunk is a full one
+        let len = self.chunk_frame(object, 0)?.map_or(0, |frame| frame.len());
+        let max = conn.config().max_fragment_size;
+        if len > max {
+            return Ok(Some(format!(
+                "Chunk frames of {} bytes exceed max_fragment_size ({}); store the object with a smaller chunk size",
+                len, max
+            )));
+        }
+        Ok(None)
+    }
+
+    /// Retrieve the object with root hash `root` from a peer running `serve`
+    ///
+    /// Every chunk is checked against `root` with its merkle proof; chunks that
+    /// fail the check or don't arrive in time are requested again. The object
+    /// is stored under its root hash once all chunks verified. Events that
+    /// arrive meanwhile are kept for the caller.
+    pub async fn fetch(&self, conn: &mut Connection, root: &[u8]) -> Result<FetchStats> {
+        open_object_stream(conn)?;
+        send_frame(conn, &ObjectFrame::Get { root: root.to_vec() }).await?;
+
+        let (chunk_size, chunk_count) = wait_for_manifest(conn, root).await?;
+        let depth = proof_depth(chunk_count);
+        let mut chunks: Vec<Option<Vec<u8>>> = vec![None; chunk_count as usize];
+        let mut attempts = vec![0u32; chunk_count as usize];
+        let mut missing: VecDeque<u64> = (0..chunk_count).collect();
+        let mut outstanding: HashMap<u64, Instant> = HashMap::new();
+        let mut received = 0u64;
+        let mut re_requests = 0;
+        let mut rejected_chunks = 0;
+
+        while received < chunk_count {
+            let mut indices = Vec::new();
+            while outstanding.len() < REQUEST_WINDOW {
+                let index = match missing.pop_front() {
+                    Some(index) => index,
+                    None => break,
+                };
+                if chunks[index as usize].is_some() {
+                    continue; // Arrived after it timed out
+                }
+                attempts[index as usize] += 1;
+                if attempts[index as usize] > MAX_CHUNK_ATTEMPTS {
+                    bail!("Chunk {} of object not received after {} requests", index, MAX_CHUNK_ATTEMPTS);
+                }
+                if attempts[index as usize] > 1 {
+                    re_requests += 1;
+                }
+                outstanding.insert(index, Instant::now() + CHUNK_TIMEOUT);
+                indices.push(index);
+            }
+            if !indices.is_empty() {
+                send_frame(conn, &ObjectFrame::Request { root: root.to_vec(), indices }).await?;
+            }
+
+            let deadline = outstanding.values().min().copied().unwrap_or_else(|| Instant::now() + CHUNK_TIMEOUT);
+            if let Ok(datagram) = tokio::time::timeout_at(deadline, conn.recv_datagram()).await {
+                match bincode::deserialize(&datagram?) {
+                    Ok(ObjectFrame::Chunk { root: chunk_root, index, data, proof }) if chunk_root == root && index < chunk_count => {
+                        if chunks[index as usize].is_some() {
+                            // Duplicate of a chunk that was re-requested
+                        } else if proof.len() == depth && verify_proof(&leaf_hash(&data), index as usize, &proof, root) {
+                            chunks[index as usize] = Some(data);
+                            outstanding.remove(&index);
+                            received += 1;
+                        } else {
+                            rejected_chunks += 1;
+                            tracing::debug!(index, "Chunk failed its merkle proof, requesting it again");
+                            if outstanding.remove(&index).is_some() {
+                                missing.push_front(index);
+                            }
+                        }
+                    }
+                    _ => tracing::debug!("Dropping datagram that is not a chunk of the object"),
+                }
+            }
+
+            let now = Instant::now();
+            let expired: Vec<u64> = outstanding.iter()
+                .filter(|(_, deadline)| **deadline <= now)
+                .map(|(index, _)| *index)
+                .collect();
+            for index in expired {
+                outstanding.remove(&index);
+                missing.push_back(index);
+            }
+        }
+
+        let chunks: Vec<Vec<u8>> = chunks.into_iter().flatten().collect();
+        let manifest = self.commit_object(root, chunk_size, &chunks)?;
+        Ok(FetchStats { manifest, re_requests, rejected_chunks })
+    }
+}
+
+/// Wait for the answer to a `Get`, returning the chunk size and count
+async fn wait_for_manifest(conn: &mut Connection, root: &[u8]) -> Result<(u32, u64)> {
+    let deadline = Instant::now() + MANIFEST_TIMEOUT;
+    let mut unrelated = Vec::new();
+
+    let result = loop {
+        let event = match tokio::time::timeout_at(deadline, conn.next_event()).await {
+            Ok(event) => event?,
+            Err(_) => break Err(anyhow::anyhow!("Timed out waiting for the object manifest")),
+        };
+        let data = match event {
+            ConnectionEvent::DataReceived { stream_id: OBJECT_STREAM_ID, data } => data,
+            ConnectionEvent::Closed { reason, message } => {
+                unrelated.push(ConnectionEvent::Closed { reason, message });
+                break Err(anyhow::anyhow!("Connection closed before the object manifest arrived"));
+            }
+            event => {
+                unrelated.push(event);
+                continue;
+            }
+        };
+        conn.flush_acks().await?;
+
+        match bincode::deserialize(&data) {
+            Ok(ObjectFrame::Manifest { root: manifest_root, chunk_size, chunk_count, .. }) if manifest_root == root => {
+                break Ok((chunk_size, chunk_count));
+            }
+            Ok(ObjectFrame::NotFound { root: missing_root }) if missing_root == root => {
+                break Err(anyhow::anyhow!("Object not found on peer"));
+            }
+            Ok(ObjectFrame::Unavailable { root: unavailable_root, message }) if unavailable_root == root => {
+                break Err(anyhow::anyhow!("Object unavailable on peer: {}", message));
+            }
+            _ => tracing::debug!("Ignoring object frame while waiting for the manifest"),
+        }
+    };
+
+    conn.requeue_events(unrelated);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proof_depth() {
+        assert_eq!(proof_depth(0), 0);
+        assert_eq!(proof_depth(1), 0);
+        assert_eq!(proof_depth(2), 1);
+        assert_eq!(proof_depth(5), 3);
+        assert_eq!(proof_depth(4096), 12);
+    }
+
+    #[test]
+    fn test_chunk_frames_verify() -> Result<()> {
+        let store = ObjectStore::open_temporary()?.with_chunk_size(512);
+        let object: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        let manifest = store.put_object("obj", &object[..])?;
+        let served = store.served_object(&manifest.root)?.unwrap();
+        let count = manifest.chunk_hashes.len() as u64;
+
+        for index in 0..count {
+            let frame: ObjectFrame = bincode::deserialize(&store.chunk_frame(&served, index)?.unwrap())?;
+            let (data, proof) = match frame {
+                ObjectFrame::Chunk { data, proof, .. } => (data, proof),
+                frame => panic!("unexpected frame {:?}", frame),
+            };
+            assert_eq!(proof.len(), proof_depth(count));
+            assert!(verify_proof(&leaf_hash(&data), index as usize, &proof, &manifest.root));
+
+            // A truncated chunk no longer matches its proof
+            assert!(!verify_proof(&leaf_hash(&data[..data.len() - 1]), index as usize, &proof, &manifest.root));
+        }
+        assert!(store.chunk_frame(&served, count)?.is_none());
+        Ok(())
+    }
+}
@@ -1,11 +1,35 @@
 use sled::Db;
 use anyhow::{Result, Context};
 use serde::{Serialize, Deserialize};
+use std::io::{self, Read};
 use std::path::Path;
+use crate::merkle_tree::{leaf_hash, root_of};
+
+/// Default chunk size; matches the transport's default `max_fragment_size`,
+/// so each chunk fits in one packet
+pub const DEFAULT_CHUNK_SIZE: usize = 1200;
+
+const MANIFESTS_TREE: &str = "object_manifests";
+const CHUNKS_TREE: &str = "object_chunks";
+// Root hash -> key, for looking objects up by content
+const ROOTS_TREE: &str = "object_roots";
 
 #[derive(Debug, Clone)]
 pub struct ObjectStore {
     db: Db,
+    chunk_size: usize,
+}
+
+/// Layout of a chunked object
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectManifest {
+    /// Object size in bytes
+    pub size: u64,
+    pub chunk_size: u32,
+    /// Merkle leaf hash of each chunk, in order
+    pub chunk_hashes: Vec<Vec<u8>>,
+    /// Merkle root over `chunk_hashes`, verifying the whole object
+    pub root: Vec<u8>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -19,13 +43,19 @@ impl ObjectStore {
     /// Open or create a new ObjectStore at the given path
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
         let db = sled::open(path).context("Failed to open sled database")?;
-        Ok(Self { db })
+        Ok(Self { db, chunk_size: DEFAULT_CHUNK_SIZE })
     }
 
     /// Open an in-memory ObjectStore (useful for tests)
     pub fn open_temporary() -> Result<Self> {
         let db = sled::Config::new().temporary(true).open().context("Failed to open temporary sled database")?;
-        Ok(Self { db })
+        Ok(Self { db, chunk_size: DEFAULT_CHUNK_SIZE })
+    }
+
+    /// Set the chunk size used by `put_object`
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
     }
 
     /// Store a value
@@ -96,6 +126,221 @@ impl ObjectStore {
             None => Ok(None),
         }
     }
+
+    /// Store an object of any size, read from `reader`, as fixed-size chunks
+    ///
+    /// Replaces an existing object under the same key.
+    pub fn put_object<K: AsRef<[u8]>, R: Read>(&self, key: K, mut reader: R) -> Result<ObjectManifest> {
+        let key = key.as_ref();
+        self.delete_object(key)?;
+
+        let chunks = self.db.open_tree(CHUNKS_TREE)?;
+        let mut chunk_hashes = Vec::new();
+        let mut size = 0u64;
+        let mut buf = vec![0u8; self.chunk_size];
+
+        loop {
+            let len = read_full(&mut reader, &mut buf).context("Failed to read object")?;
+            if len == 0 {
+                break;
+            }
+            let chunk = &buf[..len];
+            chunks.insert(chunk_key(key, chunk_hashes.len() as u64), chunk).context("Failed to insert chunk")?;
+            chunk_hashes.push(leaf_hash(chunk));
+            size += len as u64;
+            if len < buf.len() {
+                break;
+            }
+        }
+
+        let manifest = ObjectManifest {
+            size,
+            chunk_size: self.chunk_size as u32,
+            root: root_of(&chunk_hashes),
+            chunk_hashes,
+        };
+        self.insert_manifest(key, &manifest)?;
+        Ok(manifest)
+    }
+
+    /// Store an object received chunk by chunk under its root hash
+    ///
+    /// The caller has verified every chunk against `root`.
+    pub(crate) fn commit_object(&self, root: &[u8], chunk_size: u32, chunks: &[Vec<u8>]) -> Result<ObjectManifest> {
+        self.delete_object(root)?;
+
+        let tree = self.db.open_tree(CHUNKS_TREE)?;
+        let mut chunk_hashes = Vec::with_capacity(chunks.len());
+        for (index, chunk) in chunks.iter().enumerate() {
+            tree.insert(chunk_key(root, index as u64), chunk.as_slice()).context("Failed to insert chunk")?;
+            chunk_hashes.push(leaf_hash(chunk));
+        }
+
+        let manifest = ObjectManifest {
+            size: chunks.iter().map(|chunk| chunk.len() as u64).sum(),
+            chunk_size,
+            root: root_of(&chunk_hashes),
+            chunk_hashes,
+        };
+        if manifest.root != root {
+            anyhow::bail!("Chunks do not add up to the requested root hash");
+        }
+        self.insert_manifest(root, &manifest)?;
+        Ok(manifest)
+    }
+
+    fn insert_manifest(&self, key: &[u8], manifest: &ObjectManifest) -> Result<()> {
+        let manifests = self.db.open_tree(MANIFESTS_TREE)?;
+        manifests.insert(key, bincode::serialize(manifest).context("Failed to serialize manifest")?)
+            .context("Failed to insert manifest")?;
+        self.db.open_tree(ROOTS_TREE)?.insert(&manifest.root, key).context("Failed to index root hash")?;
+        Ok(())
+    }
+
+    /// Key and manifest of the object whose root hash is `root`
+    pub fn find_by_root(&self, root: &[u8]) -> Result<Option<(Vec<u8>, ObjectManifest)>> {
+        let key = match self.db.open_tree(ROOTS_TREE)?.get(root).context("Failed to look up root hash")? {
+            Some(key) => key.to_vec(),
+            None => return Ok(None),
+        };
+        // The index may be stale if the key was overwritten
+        Ok(self.object_manifest(&key)?
+            .filter(|manifest| manifest.root == root)
+            .map(|manifest| (key, manifest)))
+    }
+
+    /// Raw bytes of one chunk, unverified
+    pub(crate) fn read_chunk(&self, key: &[u8], index: u64) -> Result<Option<Vec<u8>>> {
+        let chunks = self.db.open_tree(CHUNKS_TREE)?;
+        Ok(chunks.get(chunk_key(key, index)).context("Failed to get chunk")?.map(|chunk| chunk.to_vec()))
+    }
+
+    /// Manifest of a chunked object
+    pub fn object_manifest<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<ObjectManifest>> {
+        let manifests = self.db.open_tree(MANIFESTS_TREE)?;
+        match manifests.get(key).context("Failed to get manifest")? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes).context("Failed to deserialize manifest")?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Stream a chunked object back
+    ///
+    /// Each chunk is checked against the manifest as it is read; a mismatch
+    /// fails the read with `io::ErrorKind::InvalidData`.
+    pub fn get_object<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<ObjectReader>> {
+        let key = key.as_ref();
+        let manifest = match self.object_manifest(key)? {
+            Some(manifest) => manifest,
+            None => return Ok(None),
+        };
+
+        if root_of(&manifest.chunk_hashes) != manifest.root {
+            anyhow::bail!("Manifest of object {} does not match its root hash", String::from_utf8_lossy(key));
+        }
+
+        Ok(Some(ObjectReader {
+            chunks: self.db.open_tree(CHUNKS_TREE)?,
+            key: key.to_vec(),
+            manifest,
+            next_chunk: 0,
+            current: Vec::new(),
+            pos: 0,
+        }))
+    }
+
+    /// Delete a chunked object and its chunks
+    pub fn delete_object<K: AsRef<[u8]>>(&self, key: K) -> Result<()> {
+        let key = key.as_ref();
+        if let Some(manifest) = self.object_manifest(key)? {
+            let chunks = self.db.open_tree(CHUNKS_TREE)?;
+            for index in 0..manifest.chunk_hashes.len() as u64 {
+                chunks.remove(chunk_key(key, index)).context("Failed to remove chunk")?;
+            }
+            self.db.open_tree(MANIFESTS_TREE)?.remove(key).context("Failed to remove manifest")?;
+            let roots = self.db.open_tree(ROOTS_TREE)?;
+            if roots.get(&manifest.root)?.is_some_and(|indexed| indexed == key) {
+                roots.remove(&manifest.root).context("Failed to remove root index")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Chunk key: length-prefixed object key, then the chunk index
+fn chunk_key(key: &[u8], index: u64) -> Vec<u8> {
+    let mut chunk_key = Vec::with_capacity(4 + key.len() + 8);
+    chunk_key.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    chunk_key.extend_from_slice(key);
+    chunk_key.extend_from_slice(&index.to_be_bytes());
+    chunk_key
+}
+
+/// Read until `buf` is full or the reader is exhausted
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Verified reader over a chunked object, returned by `ObjectStore::get_object`
+pub struct ObjectReader {
+    chunks: sled::Tree,
+    key: Vec<u8>,
+    manifest: ObjectManifest,
+    next_chunk: usize,
+    current: Vec<u8>,
+    pos: usize,
+}
+
+impl ObjectReader {
+    pub fn manifest(&self) -> &ObjectManifest {
+        &self.manifest
+    }
+
+    /// Load and verify the next chunk; false at the end of the object
+    fn load_next_chunk(&mut self) -> io::Result<bool> {
+        let index = self.next_chunk;
+        let expected = match self.manifest.chunk_hashes.get(index) {
+            Some(hash) => hash,
+            None => return Ok(false),
+        };
+
+        let chunk = self.chunks.get(chunk_key(&self.key, index as u64))
+            .map_err(io::Error::other)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Chunk {} is missing", index)))?;
+
+        if leaf_hash(&chunk) != *expected {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Chunk {} failed verification", index)));
+        }
+
+        self.current = chunk.to_vec();
+        self.pos = 0;
+        self.next_chunk += 1;
+        Ok(true)
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.current.len() {
+            if !self.load_next_chunk()? {
+                return Ok(0);
+            }
+        }
+
+        let n = buf.len().min(self.current.len() - self.pos);
+        buf[..n].copy_from_slice(&self.current[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
 }
 
 #[cfg(test)]
@@ -135,4 +380,57 @@ mod tests {
         
         Ok(())
     }
+
+    fn test_object(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_chunked_object_roundtrip() -> Result<()> {
+        let store = ObjectStore::open_temporary()?;
+        let object = test_object(5 * 1024 * 1024);
+
+        let manifest = store.put_object("big", &object[..])?;
+        assert_eq!(manifest.size, object.len() as u64);
+        assert_eq!(manifest.chunk_hashes.len(), object.len().div_ceil(DEFAULT_CHUNK_SIZE));
+
+        let mut read_back = Vec::new();
+        store.get_object("big")?.unwrap().read_to_end(&mut read_back)?;
+        assert!(read_back == object, "object differs after roundtrip");
+
+        // The root hash verifies the whole object
+        let hashes: Vec<Vec<u8>> = object.chunks(DEFAULT_CHUNK_SIZE).map(leaf_hash).collect();
+        assert_eq!(root_of(&hashes), manifest.root);
+
+        let (key, found) = store.find_by_root(&manifest.root)?.unwrap();
+        assert_eq!(key, b"big");
+        assert_eq!(found, manifest);
+
+        store.delete_object("big")?;
+        assert!(store.get_object("big")?.is_none());
+        assert!(store.find_by_root(&manifest.root)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted_chunk_detected() -> Result<()> {
+        let store = ObjectStore::open_temporary()?.with_chunk_size(1024);
+        let object = test_object(10 * 1024);
+        store.put_object("doc", &object[..])?;
+
+        // Flip a byte in the fourth chunk
+        let chunks = store.db.open_tree(CHUNKS_TREE)?;
+        let mut chunk = chunks.get(chunk_key(b"doc", 3))?.unwrap().to_vec();
+        chunk[10] ^= 0xFF;
+        chunks.insert(chunk_key(b"doc", 3), chunk)?;
+
+        let mut reader = store.get_object("doc")?.unwrap();
+        let mut read_back = Vec::new();
+        let err = reader.read_to_end(&mut read_back).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("Chunk 3"));
+        // Chunks before the corrupted one were delivered
+        assert_eq!(read_back, object[..3 * 1024]);
+        Ok(())
+    }
 }
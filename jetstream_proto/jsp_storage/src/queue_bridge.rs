@@ -0,0 +1,128 @@
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+use std::future::Future;
+use std::net::SocketAddr;
+use jsp_transport::events::ServerEvent;
+use jsp_transport::server::Server;
+use crate::message_queue::{MessageId, MessageQueue};
+
+/// Stream data received by a server, as stored in the queue
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuedData {
+    pub peer: SocketAddr,
+    pub stream_id: u32,
+    pub data: Vec<u8>,
+}
+
+/// Enqueues the stream data a `Server` receives into a named topic
+///
+/// With a durable queue (`MessageQueue::open_durable`) a consumer can drain
+/// the topic with `poll`/`ack` after the server or itself restarted.
+#[derive(Debug, Clone)]
+pub struct ServerQueueBridge {
+    queue: MessageQueue,
+    topic: String,
+}
+
+impl ServerQueueBridge {
+    pub fn new(queue: MessageQueue, topic: impl Into<String>) -> Self {
+        Self { queue, topic: topic.into() }
+    }
+
+    pub fn queue(&self) -> &MessageQueue {
+        &self.queue
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Enqueue the payload of a `DataReceived` event; other events are ignored
+    pub fn handle(&self, event: &ServerEvent) -> Result<Option<MessageId>> {
+        match event {
+            ServerEvent::DataReceived { addr, stream_id, data } => {
+                let message = QueuedData { peer: *addr, stream_id: *stream_id, data: data.to_vec() };
+                Ok(Some(self.queue.enqueue(&self.topic, &message)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Drive `server` until `shutdown` resolves, returning how many messages were enqueued
+    pub async fn run(&self, server: &mut Server, shutdown: impl Future<Output = ()>) -> Result<u64> {
+        let mut enqueued = 0;
+        tokio::pin!(shutdown);
+
+        loop {
+            let event = tokio::select! {
+                _ = &mut shutdown => break,
+                event = server.next_event() => event,
+            };
+
+            match event {
+                Ok(event) => {
+                    if self.handle(&event)?.is_some() {
+                        enqueued += 1;
+                    }
+                }
+                Err(e) => tracing::warn!(topic = %self.topic, "Receive error: {}", e),
+            }
+        }
+
+        self.queue.sync()?;
+        Ok(enqueued)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_queue::QueueConfig;
+    use crate::queue_log::LogConfig;
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::config::ConnectionConfig;
+    use jsp_transport::connection::Connection;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_server_data_survives_restart() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let queue = MessageQueue::open_durable(dir.path(), QueueConfig::default(), LogConfig::default())?;
+        let bridge = ServerQueueBridge::new(queue.clone(), "inbox");
+
+        let mut server = Server::bind("127.0.0.1:0").await?;
+        let addr = server.local_addr()?.to_string();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let bridge_task = tokio::spawn(async move {
+            bridge.run(&mut server, async { let _ = stop_rx.await; }).await
+        });
+
+        let mut client = Connection::connect_with_config(&addr, ConnectionConfig::default()).await?;
+        client.handshake().await?;
+        let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+        for message in [&b"one"[..], b"two", b"three"] {
+            client.send_on_stream(stream_id, message).await?;
+        }
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while queue.len("inbox").unwrap() < 3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await?;
+        stop_tx.send(()).unwrap();
+        assert_eq!(bridge_task.await??, 3);
+        drop(queue);
+
+        // A consumer started later finds everything in order
+        let queue = MessageQueue::open_durable(dir.path(), QueueConfig::default(), LogConfig::default())?;
+        let mut received = Vec::new();
+        while let Some((id, message)) = queue.poll::<QueuedData>("inbox")? {
+            assert_eq!(message.stream_id, stream_id);
+            assert_eq!(message.peer.port(), client.local_addr()?.port());
+            received.push(message.data);
+            assert!(queue.ack("inbox", id)?);
+        }
+        assert_eq!(received, vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]);
+        Ok(())
+    }
+}
@@ -0,0 +1,448 @@
+use anyhow::{bail, Context, Result};
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use crate::message_queue::{MessageId, QueueConfig};
+
+/// Bytes of the length and CRC in front of every record
+const RECORD_HEADER_LEN: usize = 8;
+const SEGMENT_EXTENSION: &str = "log";
+
+/// When appended records are flushed to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// After every record; nothing acknowledged is lost on power failure
+    Always,
+    /// On the first write at least this long after the previous sync
+    Interval(Duration),
+    /// Left to the OS; survives process crashes but not power failure
+    Never,
+}
+
+/// Layout and flushing of a durable queue's log
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    /// Size at which the active segment is sealed and a new one started
+    pub segment_size: u64,
+    pub fsync: FsyncPolicy,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            segment_size: 16 * 1024 * 1024,
+            fsync: FsyncPolicy::Always,
+        }
+    }
+}
+
+/// One change to a queue, replayed in order on open
+#[derive(Debug, Serialize, Deserialize)]
+enum LogRecord {
+    Enqueue { topic: String, id: MessageId, payload: Vec<u8> },
+    Deliver { topic: String, id: MessageId, deliveries: u32, visible_at_ms: u64 },
+    Nack { topic: String, id: MessageId },
+    /// Acked, or taken by an at-most-once `dequeue`
+    Remove { topic: String, id: MessageId },
+    DeadLetter { topic: String, id: MessageId },
+}
+
+#[derive(Debug)]
+struct InFlight {
+    payload: Vec<u8>,
+    deliveries: u32,
+    visible_at_ms: u64,
+}
+
+#[derive(Debug, Default)]
+struct Topic {
+    ready: BTreeMap<MessageId, Vec<u8>>,
+    inflight: BTreeMap<MessageId, InFlight>,
+    dead: BTreeMap<MessageId, Vec<u8>>,
+    last_id: MessageId,
+}
+
+#[derive(Debug)]
+struct Segment {
+    index: u64,
+    path: PathBuf,
+    /// Messages enqueued in this segment that are not removed yet
+    live: usize,
+}
+
+/// Append-only segment log holding the state of every topic of a durable queue
+///
+/// Records are `[u32 length][u32 CRC32][bincode record]`. A torn record at
+/// the end of the last segment (a crash mid-write) is truncated on open.
+/// Sealed segments are deleted once every message enqueued in them and in
+/// all older segments has been removed.
+#[derive(Debug)]
+pub(crate) struct QueueLog {
+    dir: PathBuf,
+    config: LogConfig,
+    topics: HashMap<String, Topic>,
+    segments: Vec<Segment>,
+    // Segment each live message was enqueued in
+    locations: HashMap<(String, MessageId), u64>,
+    active: File,
+    active_len: u64,
+    last_sync: Instant,
+}
+
+fn segment_path(dir: &Path, index: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", index, SEGMENT_EXTENSION))
+}
+
+/// Decode the records of one segment, stopping at the first damaged one
+///
+/// Returns the records, the length of the intact prefix and whether that
+/// prefix is the whole file.
+fn read_segment(path: &Path) -> Result<(Vec<LogRecord>, u64, bool)> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        if bytes.len() - offset < RECORD_HEADER_LEN {
+            return Ok((records, offset as u64, false));
+        }
+        let len = u32::from_be_bytes(bytes[offset..offset + 4].try_into()?) as usize;
+        let crc = u32::from_be_bytes(bytes[offset + 4..offset + 8].try_into()?);
+        let start = offset + RECORD_HEADER_LEN;
+        if bytes.len() - start < len {
+            return Ok((records, offset as u64, false));
+        }
+        let payload = &bytes[start..start + len];
+        if crc32fast::hash(payload) != crc {
+            return Ok((records, offset as u64, false));
+        }
+        match bincode::deserialize(payload) {
+            Ok(record) => records.push(record),
+            Err(_) => return Ok((records, offset as u64, false)),
+        }
+        offset = start + len;
+    }
+    Ok((records, offset as u64, true))
+}
+
+impl QueueLog {
+    /// Open the log in `dir`, creating it if needed, and replay it
+    pub(crate) fn open(dir: &Path, config: LogConfig) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create queue directory {}", dir.display()))?;
+
+        let mut indexes: Vec<u64> = fs::read_dir(dir)?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()?.to_str()? != SEGMENT_EXTENSION {
+                    return None;
+                }
+                path.file_stem()?.to_str()?.parse().ok()
+            })
+            .collect();
+        indexes.sort_unstable();
+        if indexes.is_empty() {
+            indexes.push(0);
+        }
+
+        let last_index = indexes[indexes.len() - 1];
+        let mut topics: HashMap<String, Topic> = HashMap::new();
+        let mut segments = Vec::new();
+        let mut locations = HashMap::new();
+        let mut active_len = 0;
+
+        for &index in &indexes {
+            let path = segment_path(dir, index);
+            if !path.exists() {
+                File::create(&path)?;
+            }
+            let (records, intact_len, complete) = read_segment(&path)?;
+            if !complete {
+                if index != last_index {
+                    bail!("Corrupt record in sealed segment {} at offset {}", path.display(), intact_len);
+                }
+                // Torn write at the tail: drop it
+                tracing::warn!(segment = %path.display(), offset = intact_len, "Truncating damaged queue log tail");
+                OpenOptions::new().write(true).open(&path)?.set_len(intact_len)?;
+            }
+            if index == last_index {
+                active_len = intact_len;
+            }
+
+            segments.push(Segment { index, path, live: 0 });
+            for record in records {
+                apply(&mut topics, &mut locations, &mut segments, record);
+            }
+        }
+
+        let active = OpenOptions::new()
+            .append(true)
+            .open(segment_path(dir, last_index))?;
+
+        let mut log = Self {
+            dir: dir.to_path_buf(),
+            config,
+            topics,
+            segments,
+            locations,
+            active,
+            active_len,
+            last_sync: Instant::now(),
+        };
+        log.compact()?;
+        Ok(log)
+    }
+
+    fn append(&mut self, record: LogRecord) -> Result<()> {
+        let payload = bincode::serialize(&record)?;
+        let record_len = (RECORD_HEADER_LEN + payload.len()) as u64;
+
+        if self.active_len > 0 && self.active_len + record_len > self.config.segment_size {
+            self.roll()?;
+        }
+
+        let mut bytes = Vec::with_capacity(record_len as usize);
+        bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&crc32fast::hash(&payload).to_be_bytes());
+        bytes.extend_from_slice(&payload);
+        self.active.write_all(&bytes)?;
+        self.active_len += record_len;
+
+        let sync = match self.config.fsync {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::Interval(interval) => self.last_sync.elapsed() >= interval,
+            FsyncPolicy::Never => false,
+        };
+        if sync {
+            self.active.sync_data()?;
+            self.last_sync = Instant::now();
+        }
+
+        apply(&mut self.topics, &mut self.locations, &mut self.segments, record);
+        Ok(())
+    }
+
+    /// Seal the active segment and start the next one
+    fn roll(&mut self) -> Result<()> {
+        self.active.sync_data()?;
+        let index = self.segments.last().map_or(0, |segment| segment.index + 1);
+        let path = segment_path(&self.dir, index);
+        self.active = OpenOptions::new().create(true).append(true).open(&path)?;
+        self.active_len = 0;
+        self.segments.push(Segment { index, path, live: 0 });
+        self.compact()
+    }
+
+    /// Delete sealed segments from the front that hold no live messages
+    fn compact(&mut self) -> Result<()> {
+        while self.segments.len() > 1 && self.segments[0].live == 0 {
+            let segment = self.segments.remove(0);
+            fs::remove_file(&segment.path)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn enqueue(&mut self, topic: &str, payload: Vec<u8>) -> Result<MessageId> {
+        let id = self.topics.get(topic).map_or(0, |t| t.last_id) + 1;
+        self.append(LogRecord::Enqueue { topic: topic.to_string(), id, payload })?;
+        Ok(id)
+    }
+
+    pub(crate) fn dequeue(&mut self, topic: &str) -> Result<Option<Vec<u8>>> {
+        let (id, payload) = match self.topics.get(topic).and_then(|t| t.ready.first_key_value()) {
+            Some((id, payload)) => (*id, payload.clone()),
+            None => return Ok(None),
+        };
+        self.append(LogRecord::Remove { topic: topic.to_string(), id })?;
+        self.compact()?;
+        Ok(Some(payload))
+    }
+
+    pub(crate) fn peek(&self, topic: &str) -> Option<Vec<u8>> {
+        self.topics.get(topic)?.ready.first_key_value().map(|(_, payload)| payload.clone())
+    }
+
+    pub(crate) fn len(&self, topic: &str) -> u64 {
+        self.topics.get(topic).map_or(0, |t| t.ready.len() as u64)
+    }
+
+    /// Same semantics as the sled-backed `MessageQueue::poll`
+    pub(crate) fn poll(&mut self, topic: &str, now_ms: u64, config: &QueueConfig) -> Result<Option<(MessageId, Vec<u8>)>> {
+        let hidden_until = now_ms + config.visibility_timeout.as_millis() as u64;
+
+        // Redeliver timed out messages first, oldest first
+        loop {
+            let due = self.topics.get(topic).and_then(|t| {
+                t.inflight.iter()
+                    .find(|(_, message)| message.visible_at_ms <= now_ms)
+                    .map(|(id, message)| (*id, message.deliveries, message.payload.clone()))
+            });
+            let (id, deliveries, payload) = match due {
+                Some(due) => due,
+                None => break,
+            };
+            if deliveries >= config.max_deliveries {
+                self.append(LogRecord::DeadLetter { topic: topic.to_string(), id })?;
+                continue;
+            }
+            self.append(LogRecord::Deliver { topic: topic.to_string(), id, deliveries: deliveries + 1, visible_at_ms: hidden_until })?;
+            return Ok(Some((id, payload)));
+        }
+
+        // Then the head of the queue
+        let (id, payload) = match self.topics.get(topic).and_then(|t| t.ready.first_key_value()) {
+            Some((id, payload)) => (*id, payload.clone()),
+            None => return Ok(None),
+        };
+        self.append(LogRecord::Deliver { topic: topic.to_string(), id, deliveries: 1, visible_at_ms: hidden_until })?;
+        Ok(Some((id, payload)))
+    }
+
+    pub(crate) fn ack(&mut self, topic: &str, id: MessageId) -> Result<bool> {
+        if !self.topics.get(topic).is_some_and(|t| t.inflight.contains_key(&id)) {
+            return Ok(false);
+        }
+        self.append(LogRecord::Remove { topic: topic.to_string(), id })?;
+        self.compact()?;
+        Ok(true)
+    }
+
+    pub(crate) fn nack(&mut self, topic: &str, id: MessageId) -> Result<bool> {
+        if !self.topics.get(topic).is_some_and(|t| t.inflight.contains_key(&id)) {
+            return Ok(false);
+        }
+        self.append(LogRecord::Nack { topic: topic.to_string(), id })?;
+        Ok(true)
+    }
+
+    pub(crate) fn dead_letters(&self, topic: &str) -> Vec<(MessageId, Vec<u8>)> {
+        self.topics.get(topic)
+            .map(|t| t.dead.iter().map(|(id, payload)| (*id, payload.clone())).collect())
+            .unwrap_or_default()
+    }
+
+    /// Flush everything written so far, whatever the fsync policy
+    pub(crate) fn sync(&mut self) -> Result<()> {
+        self.active.sync_data()?;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+}
+
+impl Drop for QueueLog {
+    fn drop(&mut self) {
+        if let Err(e) = self.sync() {
+            tracing::warn!("Failed to sync queue log on close: {}", e);
+        }
+    }
+}
+
+/// Apply one record to the in-memory state
+///
+/// Records about messages that are already gone are ignored, as their
+/// segment may have been deleted.
+fn apply(topics: &mut HashMap<String, Topic>, locations: &mut HashMap<(String, MessageId), u64>, segments: &mut [Segment], record: LogRecord) {
+    match record {
+        LogRecord::Enqueue { topic, id, payload } => {
+            if let Some(segment) = segments.last_mut() {
+                segment.live += 1;
+                locations.insert((topic.clone(), id), segment.index);
+            }
+            let state = topics.entry(topic).or_default();
+            state.last_id = state.last_id.max(id);
+            state.ready.insert(id, payload);
+        }
+        LogRecord::Deliver { topic, id, deliveries, visible_at_ms } => {
+            let state = topics.entry(topic).or_default();
+            let payload = match state.ready.remove(&id) {
+                Some(payload) => payload,
+                None => match state.inflight.remove(&id) {
+                    Some(message) => message.payload,
+                    None => return,
+                },
+            };
+            state.inflight.insert(id, InFlight { payload, deliveries, visible_at_ms });
+        }
+        LogRecord::Nack { topic, id } => {
+            if let Some(message) = topics.get_mut(&topic).and_then(|t| t.inflight.get_mut(&id)) {
+                message.visible_at_ms = 0;
+            }
+        }
+        LogRecord::DeadLetter { topic, id } => {
+            let state = topics.entry(topic).or_default();
+            if let Some(message) = state.inflight.remove(&id) {
+                state.dead.insert(id, message.payload);
+            }
+        }
+        LogRecord::Remove { topic, id } => {
+            if let Some(state) = topics.get_mut(&topic) {
+                let removed = state.ready.remove(&id).is_some() || state.inflight.remove(&id).is_some();
+                if !removed {
+                    return;
+                }
+            }
+            let segment = locations.remove(&(topic, id))
+                .and_then(|index| segments.iter_mut().find(|s| s.index == index));
+            if let Some(segment) = segment {
+                segment.live = segment.live.saturating_sub(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_torn_tail_is_truncated() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = LogConfig { fsync: FsyncPolicy::Never, ..LogConfig::default() };
+        {
+            let mut log = QueueLog::open(dir.path(), config.clone())?;
+            log.enqueue("t", b"one".to_vec())?;
+            log.enqueue("t", b"two".to_vec())?;
+        }
+
+        // Half of a third record made it to disk
+        let path = segment_path(dir.path(), 0);
+        let mut file = OpenOptions::new().append(true).open(&path)?;
+        file.write_all(&[0, 0, 0, 40, 1, 2, 3])?;
+        drop(file);
+
+        let mut log = QueueLog::open(dir.path(), config)?;
+        assert_eq!(log.len("t"), 2);
+        assert_eq!(log.dequeue("t")?, Some(b"one".to_vec()));
+        // Appends after the repair are readable again
+        log.enqueue("t", b"three".to_vec())?;
+        drop(log);
+        let (records, _, complete) = read_segment(&path)?;
+        assert!(complete);
+        assert_eq!(records.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_acked_segments_are_deleted() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = LogConfig { segment_size: 256, fsync: FsyncPolicy::Never };
+        let queue_config = QueueConfig::default();
+        let mut log = QueueLog::open(dir.path(), config)?;
+        for i in 0..50u32 {
+            log.enqueue("t", i.to_be_bytes().repeat(8))?;
+        }
+        let segments = fs::read_dir(dir.path())?.count();
+        assert!(segments > 2);
+
+        while let Some((id, _)) = log.poll("t", 0, &queue_config)? {
+            assert!(log.ack("t", id)?);
+        }
+        // Only segments written after the last live message remain
+        assert!(fs::read_dir(dir.path())?.count() < segments);
+        assert_eq!(log.poll("t", u64::MAX / 2, &queue_config)?, None);
+        Ok(())
+    }
+}
@@ -1,6 +1,18 @@
 use sled::Db;
-use anyhow::Result;
+use anyhow::{bail, Result};
+use async_trait::async_trait;
 use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use crate::merkle_tree::{leaf_hash, node_hash, root_of};
+
+/// Keys are spread over this many buckets, the leaves of the gossip tree
+pub const BUCKET_COUNT: usize = 256;
+/// Levels above the buckets; the root is at this level
+const TREE_DEPTH: u32 = 8;
+const ENTRIES_TREE: &str = "replicated_entries";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReplicaInfo {
@@ -64,6 +76,319 @@ impl ReplicationManager {
     }
 }
 
+/// Anti-entropy configuration
+#[derive(Debug, Clone)]
+pub struct ReplicatorConfig {
+    /// Time between gossip rounds started by `spawn_gossip`
+    pub gossip_interval: Duration,
+}
+
+impl Default for ReplicatorConfig {
+    fn default() -> Self {
+        Self {
+            gossip_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A replicated value; of two entries for a key the higher version wins
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionedEntry {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    pub version: u64,
+}
+
+impl VersionedEntry {
+    /// Whether this entry replaces `other`; equal versions are ordered by value
+    /// so every replica picks the same winner
+    fn supersedes(&self, other: &VersionedEntry) -> bool {
+        (self.version, &self.value) > (other.version, &other.value)
+    }
+}
+
+/// Node of the gossip tree; level 0 holds the bucket hashes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NodeId {
+    pub level: u32,
+    pub index: u64,
+}
+
+impl NodeId {
+    pub const ROOT: NodeId = NodeId { level: TREE_DEPTH, index: 0 };
+
+    fn children(&self) -> [NodeId; 2] {
+        [
+            NodeId { level: self.level - 1, index: self.index * 2 },
+            NodeId { level: self.level - 1, index: self.index * 2 + 1 },
+        ]
+    }
+}
+
+/// Anti-entropy request sent to a peer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum GossipRequest {
+    /// Hashes of these tree nodes
+    Nodes(Vec<NodeId>),
+    /// All entries in these buckets
+    Buckets(Vec<u64>),
+    /// Entries for the peer to merge
+    Push(Vec<VersionedEntry>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum GossipResponse {
+    Nodes(Vec<(NodeId, Vec<u8>)>),
+    Entries(Vec<VersionedEntry>),
+    /// Number of pushed entries that were newer than the peer's
+    Merged(usize),
+}
+
+/// A replica reachable for gossip, locally or over a connection
+#[async_trait]
+pub trait GossipPeer: Send + Sync {
+    async fn exchange(&self, request: GossipRequest) -> Result<GossipResponse>;
+}
+
+/// Outcome of one gossip round
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncStats {
+    /// Requests sent to the peer
+    pub round_trips: usize,
+    /// Buckets whose hashes differed
+    pub divergent_buckets: usize,
+    /// Entries taken from the peer
+    pub pulled: usize,
+    /// Entries the peer took from us
+    pub pushed: usize,
+}
+
+/// Key-value store kept convergent with peers by Merkle anti-entropy
+///
+/// Keys are hashed into `BUCKET_COUNT` buckets forming the leaves of a Merkle
+/// tree. A gossip round compares trees top-down, descending only into
+/// subtrees whose hashes differ, and then exchanges just the entries of the
+/// differing buckets. Deletes are not replicated.
+#[derive(Debug, Clone)]
+pub struct Replicator {
+    db: Db,
+    config: ReplicatorConfig,
+}
+
+impl Replicator {
+    pub fn new(db: Db) -> Self {
+        Self::with_config(db, ReplicatorConfig::default())
+    }
+
+    pub fn with_config(db: Db, config: ReplicatorConfig) -> Self {
+        Self { db, config }
+    }
+
+    pub fn config(&self) -> &ReplicatorConfig {
+        &self.config
+    }
+
+    /// Write a value locally, returning its new version
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<u64> {
+        let version = self.entry(key)?.map_or(0, |entry| entry.version) + 1;
+        self.store(&VersionedEntry { key: key.to_vec(), value: value.to_vec(), version })?;
+        Ok(version)
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.entry(key)?.map(|entry| entry.value))
+    }
+
+    pub fn entry(&self, key: &[u8]) -> Result<Option<VersionedEntry>> {
+        let tree = self.db.open_tree(ENTRIES_TREE)?;
+        match tree.get(storage_key(key))? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Apply an entry from a peer if it supersedes ours
+    pub fn merge(&self, entry: VersionedEntry) -> Result<bool> {
+        let newer = match self.entry(&entry.key)? {
+            Some(local) => entry.supersedes(&local),
+            None => true,
+        };
+        if !newer {
+            return Ok(false);
+        }
+        self.store(&entry)?;
+        Ok(true)
+    }
+
+    /// Root hash over all entries
+    pub fn root(&self) -> Result<Vec<u8>> {
+        let levels = self.tree_levels()?;
+        Ok(levels[TREE_DEPTH as usize][0].clone())
+    }
+
+    /// Run one anti-entropy round with `peer`, leaving both replicas equal
+    pub async fn sync_with<P: GossipPeer + ?Sized>(&self, peer: &P) -> Result<SyncStats> {
+        let levels = self.tree_levels()?;
+        let mut stats = SyncStats::default();
+
+        // Walk down the tree, following only nodes whose hashes differ
+        let mut frontier = vec![NodeId::ROOT];
+        let mut divergent = Vec::new();
+        while !frontier.is_empty() {
+            stats.round_trips += 1;
+            let remote = match peer.exchange(GossipRequest::Nodes(frontier)).await? {
+                GossipResponse::Nodes(nodes) => nodes,
+                other => bail!("Unexpected gossip response: {:?}", other),
+            };
+
+            frontier = Vec::new();
+            for (node, hash) in remote {
+                let local = levels.get(node.level as usize).and_then(|level| level.get(node.index as usize));
+                match local {
+                    Some(local) if *local == hash => {}
+                    Some(_) if node.level == 0 => divergent.push(node.index),
+                    Some(_) => frontier.extend(node.children()),
+                    None => bail!("Peer sent unknown tree node {:?}", node),
+                }
+            }
+        }
+
+        stats.divergent_buckets = divergent.len();
+        if divergent.is_empty() {
+            return Ok(stats);
+        }
+
+        // Pull the peer's side of the differing buckets
+        stats.round_trips += 1;
+        let remote_entries = match peer.exchange(GossipRequest::Buckets(divergent.clone())).await? {
+            GossipResponse::Entries(entries) => entries,
+            other => bail!("Unexpected gossip response: {:?}", other),
+        };
+        let mut remote_versions = std::collections::HashMap::new();
+        for entry in remote_entries {
+            remote_versions.insert(entry.key.clone(), entry.clone());
+            if self.merge(entry)? {
+                stats.pulled += 1;
+            }
+        }
+
+        // Push what the peer is missing or has older
+        let push: Vec<VersionedEntry> = self.bucket_entries(&divergent)?
+            .into_iter()
+            .filter(|entry| remote_versions.get(&entry.key).is_none_or(|remote| entry.supersedes(remote)))
+            .collect();
+        if !push.is_empty() {
+            stats.round_trips += 1;
+            stats.pushed = match peer.exchange(GossipRequest::Push(push)).await? {
+                GossipResponse::Merged(merged) => merged,
+                other => bail!("Unexpected gossip response: {:?}", other),
+            };
+        }
+
+        Ok(stats)
+    }
+
+    /// Answer a peer's gossip request
+    pub fn handle(&self, request: GossipRequest) -> Result<GossipResponse> {
+        match request {
+            GossipRequest::Nodes(nodes) => {
+                let levels = self.tree_levels()?;
+                let hashes = nodes.into_iter()
+                    .filter_map(|node| {
+                        let hash = levels.get(node.level as usize)?.get(node.index as usize)?;
+                        Some((node, hash.clone()))
+                    })
+                    .collect();
+                Ok(GossipResponse::Nodes(hashes))
+            }
+            GossipRequest::Buckets(buckets) => Ok(GossipResponse::Entries(self.bucket_entries(&buckets)?)),
+            GossipRequest::Push(entries) => {
+                let mut merged = 0;
+                for entry in entries {
+                    if self.merge(entry)? {
+                        merged += 1;
+                    }
+                }
+                Ok(GossipResponse::Merged(merged))
+            }
+        }
+    }
+
+    /// Gossip with `peer` every `gossip_interval` until the task is aborted
+    pub fn spawn_gossip(&self, peer: Arc<dyn GossipPeer>) -> JoinHandle<()> {
+        let replicator = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(replicator.config.gossip_interval);
+            loop {
+                interval.tick().await;
+                match replicator.sync_with(peer.as_ref()).await {
+                    Ok(stats) if stats.divergent_buckets > 0 => tracing::debug!(?stats, "Gossip round repaired divergence"),
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!("Gossip round failed: {}", e),
+                }
+            }
+        })
+    }
+
+    fn store(&self, entry: &VersionedEntry) -> Result<()> {
+        let tree = self.db.open_tree(ENTRIES_TREE)?;
+        tree.insert(storage_key(&entry.key), bincode::serialize(entry)?)?;
+        Ok(())
+    }
+
+    fn bucket_entries(&self, buckets: &[u64]) -> Result<Vec<VersionedEntry>> {
+        let tree = self.db.open_tree(ENTRIES_TREE)?;
+        let mut entries = Vec::new();
+        for &bucket in buckets {
+            if bucket >= BUCKET_COUNT as u64 {
+                bail!("Bucket {} out of range", bucket);
+            }
+            for item in tree.scan_prefix([bucket as u8]) {
+                let (_, value) = item?;
+                entries.push(bincode::deserialize(&value)?);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Hashes of every tree level, buckets first and the root last
+    fn tree_levels(&self) -> Result<Vec<Vec<Vec<u8>>>> {
+        let tree = self.db.open_tree(ENTRIES_TREE)?;
+        let mut buckets: Vec<Vec<Vec<u8>>> = vec![Vec::new(); BUCKET_COUNT];
+        // Entries are stored by bucket, then key, so each bucket is in key order
+        for item in tree.iter() {
+            let (key, value) = item?;
+            buckets[key[0] as usize].push(leaf_hash(&value));
+        }
+
+        let mut levels = vec![buckets.iter().map(|leaves| root_of(leaves)).collect::<Vec<_>>()];
+        while levels.last().map_or(0, Vec::len) > 1 {
+            let next = levels.last().unwrap()
+                .chunks(2)
+                .map(|pair| node_hash(&pair[0], &pair[1]))
+                .collect();
+            levels.push(next);
+        }
+        Ok(levels)
+    }
+}
+
+#[async_trait]
+impl GossipPeer for Replicator {
+    async fn exchange(&self, request: GossipRequest) -> Result<GossipResponse> {
+        self.handle(request)
+    }
+}
+
+/// `[bucket][key]`, grouping the entries of a bucket for prefix scans
+fn storage_key(key: &[u8]) -> Vec<u8> {
+    let bucket = Sha256::digest(key)[0];
+    let mut storage_key = Vec::with_capacity(1 + key.len());
+    storage_key.push(bucket);
+    storage_key.extend_from_slice(key);
+    storage_key
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -84,4 +409,71 @@ mod tests {
         
         Ok(())
     }
+
+    fn replicator() -> Result<Replicator> {
+        Ok(Replicator::new(sled::Config::new().temporary(true).open()?))
+    }
+
+    #[tokio::test]
+    async fn test_diverged_stores_converge_after_one_round() -> Result<()> {
+        let a = replicator()?;
+        let b = replicator()?;
+        for i in 0..200u32 {
+            let key = format!("key-{}", i);
+            a.put(key.as_bytes(), b"shared")?;
+            b.merge(a.entry(key.as_bytes())?.unwrap())?;
+        }
+        assert_eq!(a.root()?, b.root()?);
+
+        // Each side changes a few keys
+        a.put(b"key-1", b"from a")?;
+        a.put(b"only-a", b"new")?;
+        b.put(b"key-2", b"from b")?;
+        b.put(b"key-3", b"from b")?;
+        b.put(b"only-b", b"new")?;
+        assert_ne!(a.root()?, b.root()?);
+
+        let stats = a.sync_with(&b).await?;
+        assert_eq!(a.root()?, b.root()?);
+        assert_eq!(stats.pulled, 3);
+        assert_eq!(stats.pushed, 2);
+        assert!(stats.divergent_buckets <= 5);
+
+        for store in [&a, &b] {
+            assert_eq!(store.get(b"key-1")?.unwrap(), b"from a");
+            assert_eq!(store.get(b"key-2")?.unwrap(), b"from b");
+            assert_eq!(store.get(b"only-a")?.unwrap(), b"new");
+            assert_eq!(store.get(b"only-b")?.unwrap(), b"new");
+            assert_eq!(store.get(b"key-100")?.unwrap(), b"shared");
+        }
+
+        // Nothing left to repair: only the roots are compared
+        let stats = a.sync_with(&b).await?;
+        assert_eq!(stats, SyncStats { round_trips: 1, ..Default::default() });
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_periodic_gossip() -> Result<()> {
+        let config = ReplicatorConfig { gossip_interval: Duration::from_millis(20) };
+        let a = Replicator::with_config(sled::Config::new().temporary(true).open()?, config);
+        let b = Arc::new(replicator()?);
+        let gossip = a.spawn_gossip(b.clone());
+
+        b.put(b"late", b"value")?;
+        let mut converged = false;
+        for _ in 0..50 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            if a.get(b"late")?.is_some() {
+                converged = true;
+                break;
+            }
+        }
+        gossip.abort();
+
+        assert!(converged);
+        assert_eq!(a.root()?, b.root()?);
+        Ok(())
+    }
 }
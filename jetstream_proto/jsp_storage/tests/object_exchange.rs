@@ -0,0 +1,130 @@
+use anyhow::Result;
+use jsp_core::types::control::CloseReason;
+use jsp_storage::ObjectStore;
+use jsp_transport::config::ConnectionConfig;
+use jsp_transport::connection::Connection;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+// The default rate limits would stretch 2 MB of chunks over most of a minute
+fn config() -> ConnectionConfig {
+    ConnectionConfig::builder()
+        .rate_limit_messages(1_000_000)
+        .rate_limit_bytes(1 << 30)
+        .build()
+}
+
+/// Relay UDP between a client on `listen` and `upstream`, passing every
+/// server-to-client datagram through `mangle` (which returns false to drop it)
+async fn spawn_lossy_proxy<F>(listen: &str, upstream: std::net::SocketAddr, mangle: F) -> Result<()>
+where
+    F: Fn(&mut Vec<u8>) -> bool + Send + 'static,
+{
+    let front = Arc::new(UdpSocket::bind(listen).await?);
+    let back = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let client = Arc::new(Mutex::new(None));
+
+    let (front_rx, back_tx, client_rx) = (Arc::clone(&front), Arc::clone(&back), Arc::clone(&client));
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 65536];
+        while let Ok((len, src)) = front_rx.recv_from(&mut buf).await {
+            *client_rx.lock().unwrap() = Some(src);
+            let _ = back_tx.send_to(&buf[..len], upstream).await;
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 65536];
+        while let Ok((len, _)) = back.recv_from(&mut buf).await {
+            let mut data = buf[..len].to_vec();
+            if !mangle(&mut data) {
+                continue;
+            }
+            let dest = *client.lock().unwrap();
+            if let Some(dest) = dest {
+                let _ = front.send_to(&data, dest).await;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Test that a fetched object survives dropped, corrupted and truncated chunks
+#[tokio::test]
+async fn test_fetch_over_lossy_path() -> Result<()> {
+    const OBJECT_SIZE: usize = 2 * 1024 * 1024;
+
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let object: Vec<u8> = (0..OBJECT_SIZE)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+
+    // Chunks of 512 bytes leave room for the proof within one datagram
+    let server_store = ObjectStore::open_temporary()?.with_chunk_size(512);
+    let manifest = server_store.put_object("big", &object[..])?;
+    let root = manifest.root.clone();
+
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen_with_config("127.0.0.1:9140", config()).await.unwrap();
+        server_store.serve(&mut server).await
+    });
+
+    // Chunk frames start with the variant index and the length-prefixed root
+    let mut chunk_prefix = vec![5, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0];
+    chunk_prefix.extend_from_slice(&root);
+    let chunks_seen = Arc::new(AtomicU64::new(0));
+    let seen = Arc::clone(&chunks_seen);
+    spawn_lossy_proxy("127.0.0.1:9141", "127.0.0.1:9140".parse()?, move |data| {
+        if !data.windows(chunk_prefix.len()).any(|window| window == chunk_prefix.as_slice()) {
+            return true;
+        }
+        match seen.fetch_add(1, Ordering::Relaxed) + 1 {
+            // Flip a byte of the proof
+            3 => {
+                let last = data.len() - 1;
+                data[last] ^= 0xFF;
+                true
+            }
+            // Cut the chunk short
+            7 => {
+                data.truncate(data.len() - 40);
+                true
+            }
+            n => n % 97 != 0,
+        }
+    }).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let client_store = ObjectStore::open_temporary()?;
+    let mut client = Connection::connect_with_config("127.0.0.1:9141", config()).await?;
+    client.handshake().await?;
+
+    let stats = timeout(Duration::from_secs(60), client_store.fetch(&mut client, &root)).await??;
+    assert_eq!(stats.manifest.root, root);
+    assert_eq!(stats.manifest.size, OBJECT_SIZE as u64);
+    assert!(stats.rejected_chunks >= 1, "corrupted chunk was not rejected");
+    assert!(stats.re_requests >= 2, "only {} chunks were re-requested", stats.re_requests);
+    assert!(chunks_seen.load(Ordering::Relaxed) > manifest.chunk_hashes.len() as u64);
+
+    // Committed under its root hash, byte for byte
+    let mut fetched = Vec::new();
+    std::io::Read::read_to_end(&mut client_store.get_object(&root)?.unwrap(), &mut fetched)?;
+    assert!(fetched == object, "fetched object differs");
+    assert_eq!(client_store.find_by_root(&root)?.unwrap().0, root);
+
+    client.close(CloseReason::Normal, None).await?;
+    let served = timeout(Duration::from_secs(5), server_task).await??;
+    let served = served?;
+    assert_eq!(served.objects_requested, 1);
+    assert!(served.chunks_sent > manifest.chunk_hashes.len() as u64);
+    Ok(())
+}
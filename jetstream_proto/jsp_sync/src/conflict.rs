@@ -1,3 +1,6 @@
+use serde::{Serialize, Deserialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use crate::crdt::LWWRegister;
 
 pub trait ConflictResolver<T> {
@@ -35,3 +38,165 @@ where F: Fn(&LWWRegister<String>, &LWWRegister<String>) -> LWWRegister<String>
         (self.resolve_fn)(local, remote)
     }
 }
+
+/// Happens-before relation between two vector clocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Causality {
+    /// One clock happened before the other, or they are equal
+    Ordered(Ordering),
+    /// Neither saw the other's updates
+    Concurrent,
+}
+
+/// Vector Clock
+///
+/// Counts the updates seen from each replica, so ordering doesn't depend on
+/// wall clocks agreeing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct VectorClock {
+    counters: HashMap<String, u64>,
+}
+
+impl VectorClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a local update on `replica_id`
+    pub fn increment(&mut self, replica_id: &str) {
+        *self.counters.entry(replica_id.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn get(&self, replica_id: &str) -> u64 {
+        self.counters.get(replica_id).copied().unwrap_or(0)
+    }
+
+    /// Pointwise maximum, i.e. everything either clock has seen
+    pub fn merge(&mut self, other: &VectorClock) {
+        for (replica_id, &count) in &other.counters {
+            let local = self.counters.entry(replica_id.clone()).or_insert(0);
+            *local = (*local).max(count);
+        }
+    }
+
+    pub fn compare(&self, other: &VectorClock) -> Causality {
+        let mut less = false;
+        let mut greater = false;
+        for replica_id in self.counters.keys().chain(other.counters.keys()) {
+            match self.get(replica_id).cmp(&other.get(replica_id)) {
+                Ordering::Less => less = true,
+                Ordering::Greater => greater = true,
+                Ordering::Equal => {}
+            }
+        }
+
+        match (less, greater) {
+            (true, true) => Causality::Concurrent,
+            (true, false) => Causality::Ordered(Ordering::Less),
+            (false, true) => Causality::Ordered(Ordering::Greater),
+            (false, false) => Causality::Ordered(Ordering::Equal),
+        }
+    }
+
+    pub fn happened_before(&self, other: &VectorClock) -> bool {
+        self.compare(other) == Causality::Ordered(Ordering::Less)
+    }
+}
+
+/// A value tagged with the vector clock of the write that produced it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Versioned<T> {
+    pub value: T,
+    pub clock: VectorClock,
+}
+
+impl<T> Versioned<T> {
+    pub fn new(value: T, clock: VectorClock) -> Self {
+        Self { value, clock }
+    }
+}
+
+/// Two versions of the same key held by different replicas
+#[derive(Debug, Clone)]
+pub struct Conflict<T> {
+    pub local: Versioned<T>,
+    pub remote: Versioned<T>,
+}
+
+impl<T> Conflict<T> {
+    pub fn new(local: Versioned<T>, remote: Versioned<T>) -> Self {
+        Self { local, remote }
+    }
+
+    /// Resolve by happens-before ordering
+    ///
+    /// The causally later version wins. Only truly concurrent writes are passed
+    /// to `tiebreak` as `(local, remote)`. The result carries the merged clock,
+    /// so it supersedes both versions.
+    pub fn resolve_causal<F>(self, tiebreak: F) -> Versioned<T>
+    where
+        F: FnOnce(T, T) -> T,
+    {
+        let mut clock = self.local.clock.clone();
+        clock.merge(&self.remote.clock);
+
+        let value = match self.local.clock.compare(&self.remote.clock) {
+            Causality::Ordered(Ordering::Less) => self.remote.value,
+            Causality::Ordered(_) => self.local.value,
+            Causality::Concurrent => tiebreak(self.local.value, self.remote.value),
+        };
+
+        Versioned { value, clock }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrent_writes_use_tiebreaker() {
+        let mut base = VectorClock::new();
+        base.increment("A");
+
+        // Both replicas write on top of the same state without syncing
+        let mut clock_a = base.clone();
+        clock_a.increment("A");
+        let mut clock_b = base.clone();
+        clock_b.increment("B");
+        assert_eq!(clock_a.compare(&clock_b), Causality::Concurrent);
+
+        let conflict = Conflict::new(
+            Versioned::new("from A".to_string(), clock_a.clone()),
+            Versioned::new("from B".to_string(), clock_b.clone()),
+        );
+        let resolved = conflict.resolve_causal(|local, remote| format!("{}+{}", local, remote));
+        assert_eq!(resolved.value, "from A+from B");
+
+        // The resolution descends from both writes
+        assert!(clock_a.happened_before(&resolved.clock));
+        assert!(clock_b.happened_before(&resolved.clock));
+    }
+
+    #[test]
+    fn test_causal_chain_needs_no_tiebreaker() {
+        let mut clock = VectorClock::new();
+        clock.increment("A");
+        let first = Versioned::new(1, clock.clone());
+
+        // B saw A's write before writing, then A saw B's
+        clock.increment("B");
+        let second = Versioned::new(2, clock.clone());
+        clock.increment("A");
+        let third = Versioned::new(3, clock.clone());
+
+        assert_eq!(first.clock.compare(&second.clock), Causality::Ordered(Ordering::Less));
+        assert_eq!(third.clock.compare(&second.clock), Causality::Ordered(Ordering::Greater));
+        assert_eq!(second.clock.compare(&second.clock), Causality::Ordered(Ordering::Equal));
+
+        let no_tiebreak = |_: i32, _: i32| -> i32 { panic!("ordered writes are not concurrent") };
+        assert_eq!(Conflict::new(first.clone(), third.clone()).resolve_causal(no_tiebreak).value, 3);
+        assert_eq!(Conflict::new(third.clone(), first).resolve_causal(no_tiebreak).value, 3);
+        assert_eq!(Conflict::new(second, third.clone()).resolve_causal(no_tiebreak).clock, third.clock);
+    }
+}
@@ -1,7 +1,34 @@
 use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
 use std::collections::{HashSet, HashMap};
 use std::hash::Hash;
 
+/// State-based CRDT that replicas exchange and merge, e.g. over a `SyncSession`
+///
+/// `merge` must be commutative, associative and idempotent, and `digest` the
+/// same for equal states whatever order their updates were applied in.
+pub trait Crdt: Clone + Serialize + DeserializeOwned {
+    fn merge(&mut self, other: Self);
+
+    fn digest(&self) -> [u8; 32];
+}
+
+/// Hash of a set of encoded entries, independent of their order
+fn digest_entries(mut entries: Vec<Vec<u8>>) -> [u8; 32] {
+    entries.sort_unstable();
+    let mut hasher = Sha256::new();
+    for entry in entries {
+        hasher.update((entry.len() as u64).to_be_bytes());
+        hasher.update(entry);
+    }
+    hasher.finalize().into()
+}
+
+fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+    bincode::serialize(value).expect("CRDT state is serializable")
+}
+
 /// Last-Write-Wins Register
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct LWWRegister<T> {
@@ -40,32 +67,43 @@ impl<T: Clone> LWWRegister<T> {
 }
 
 /// Observed-Remove Set (Add-Wins)
+///
+/// Removing an element drops the add tags observed so far, so a concurrent
+/// add with a new tag wins. Removed tags are kept as tombstones so merging
+/// with a replica that still has them doesn't bring the element back.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ORSet<T: Eq + Hash> {
     // Element -> Set of unique add tags (timestamps/UUIDs)
     elements: HashMap<T, HashSet<String>>,
+    #[serde(default)]
+    tombstones: HashSet<String>,
 }
 
 impl<T: Clone + Eq + Hash + Serialize + for<'a> Deserialize<'a>> ORSet<T> {
     pub fn new() -> Self {
         Self {
             elements: HashMap::new(),
+            tombstones: HashSet::new(),
         }
     }
 
+    /// Add `element` under `tag`, which must be unique; removed tags are ignored
     pub fn add(&mut self, element: T, tag: String) {
-        self.elements.entry(element).or_default().insert(tag);
+        if !self.tombstones.contains(&tag) {
+            self.elements.entry(element).or_default().insert(tag);
+        }
     }
 
     pub fn remove(&mut self, element: &T, tags: HashSet<String>) {
         if let Some(current_tags) = self.elements.get_mut(element) {
-            for tag in tags {
-                current_tags.remove(&tag);
+            for tag in &tags {
+                current_tags.remove(tag);
             }
             if current_tags.is_empty() {
                 self.elements.remove(element);
             }
         }
+        self.tombstones.extend(tags);
     }
     
     pub fn contains(&self, element: &T) -> bool {
@@ -76,35 +114,53 @@ impl<T: Clone + Eq + Hash + Serialize + for<'a> Deserialize<'a>> ORSet<T> {
         self.elements.keys().cloned().collect()
     }
 
+    /// Add tags of `element` observed by this replica
+    pub fn tags(&self, element: &T) -> HashSet<String> {
+        self.elements.get(element).cloned().unwrap_or_default()
+    }
+
+    /// Delta adding `element` under `tag`, to be merged into replicas
+    pub fn add_delta(element: T, tag: String) -> Self {
+        let mut delta = Self::new();
+        delta.add(element, tag);
+        delta
+    }
+
+    /// Delta removing `element` as observed by this replica
+    pub fn remove_delta(&self, element: &T) -> Self {
+        let mut delta = Self::new();
+        delta.tombstones = self.tags(element);
+        delta
+    }
+
     pub fn merge(&mut self, other: ORSet<T>) {
+        self.tombstones.extend(other.tombstones);
         for (elem, other_tags) in other.elements {
             let my_tags = self.elements.entry(elem).or_default();
             for tag in other_tags {
                 my_tags.insert(tag);
             }
         }
+
+        let tombstones = &self.tombstones;
+        self.elements.retain(|_, tags| {
+            tags.retain(|tag| !tombstones.contains(tag));
+            !tags.is_empty()
+        });
+    }
+}
+
+impl<T: Clone + Eq + Hash + Serialize + DeserializeOwned> Crdt for ORSet<T> {
+    fn merge(&mut self, other: Self) {
+        ORSet::merge(self, other);
+    }
+
+    fn digest(&self) -> [u8; 32] {
+        let adds = self.elements.iter()
+            .flat_map(|(elem, tags)| tags.iter().map(move |tag| encode(&(0u8, elem, tag))));
+        let removes = self.tombstones.iter().map(|tag| encode(&(1u8, tag)));
+        digest_entries(adds.chain(removes).collect())
     }
-    
-    // For OR-Set, true merge logic usually involves comparing add sets and remove sets if tracked separately.
-    // But for a simple state-based OR-Set where we just exchange the full state of "elements and their tags":
-    // The merge of two OR-Sets is the union of their (element, tag) pairs.
-    // However, this implementation is strictly additive. To support removal, we need to know what was removed.
-    // A proper state-based OR-Set usually keeps tombstones or we assume we are merging "Add" operations.
-    // If we want to support "Remove", we typically use a Dot Kernel or similar.
-    // For simplicity here, let's implement a standard "Add-Wins" set where we merge by taking the union of tags.
-    // Removal is done by generating a "Remove" operation that lists the tags observed at the source.
-    // But `merge` here takes another state. If we just union, we can never remove.
-    // 
-    // Let's stick to a simpler G-Set (Grow-only Set) behavior for `merge` if we don't have tombstones, 
-    // OR implement the "observed" part: `remove` takes the tags it sees.
-    // But `merge` between two replicas needs to know what was removed.
-    // 
-    // Actually, standard OR-Set merge:
-    // E = E1 U E2
-    // But this doesn't account for removal if we just sync states.
-    // If we want state-based sync with removal, we need tombstones.
-    // Let's add tombstones.
-    
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -113,6 +169,23 @@ pub struct ORSetWithTombstones<T: Eq + Hash> {
     remove_set: HashMap<T, HashSet<u64>>, // Element -> Timestamps
 }
 
+impl<T: Clone + Eq + Hash + Serialize + DeserializeOwned> Crdt for ORSetWithTombstones<T> {
+    fn merge(&mut self, other: Self) {
+        ORSetWithTombstones::merge(self, other);
+    }
+
+    fn digest(&self) -> [u8; 32] {
+        let entries = |kind: u8, set: &HashMap<T, HashSet<u64>>| -> Vec<Vec<u8>> {
+            set.iter()
+                .flat_map(|(elem, timestamps)| timestamps.iter().map(move |ts| encode(&(kind, elem, ts))))
+                .collect()
+        };
+        let mut all = entries(0, &self.add_set);
+        all.extend(entries(1, &self.remove_set));
+        digest_entries(all)
+    }
+}
+
 impl<T: Clone + Eq + Hash + Serialize + for<'a> Deserialize<'a>> ORSetWithTombstones<T> {
     pub fn new() -> Self {
         Self {
@@ -154,6 +227,102 @@ impl<T: Clone + Eq + Hash + Serialize + for<'a> Deserialize<'a>> ORSetWithTombst
     }
 }
 
+/// Grow-only Counter
+///
+/// Each replica only increments its own entry; merging takes the maximum per replica.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct GCounter {
+    counts: HashMap<String, u64>,
+}
+
+impl GCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn increment(&mut self, replica_id: &str, n: u64) {
+        let count = self.counts.entry(replica_id.to_string()).or_insert(0);
+        *count = count.saturating_add(n);
+    }
+
+    pub fn value(&self) -> u64 {
+        self.counts.values().fold(0u64, |sum, count| sum.saturating_add(*count))
+    }
+
+    pub fn merge(&mut self, other: GCounter) {
+        for (replica_id, other_count) in other.counts {
+            let count = self.counts.entry(replica_id).or_insert(0);
+            *count = (*count).max(other_count);
+        }
+    }
+}
+
+impl Crdt for GCounter {
+    fn merge(&mut self, other: Self) {
+        GCounter::merge(self, other);
+    }
+
+    fn digest(&self) -> [u8; 32] {
+        digest_entries(self.counts.iter().map(|entry| encode(&entry)).collect())
+    }
+}
+
+/// Positive-Negative Counter
+///
+/// A pair of G-Counters, one for increments and one for decrements.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct PNCounter {
+    increments: GCounter,
+    decrements: GCounter,
+}
+
+impl PNCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn increment(&mut self, replica_id: &str, n: u64) {
+        self.increments.increment(replica_id, n);
+    }
+
+    pub fn decrement(&mut self, replica_id: &str, n: u64) {
+        self.decrements.increment(replica_id, n);
+    }
+
+    pub fn value(&self) -> i64 {
+        (self.increments.value() as i128 - self.decrements.value() as i128)
+            .clamp(i64::MIN as i128, i64::MAX as i128) as i64
+    }
+
+    pub fn merge(&mut self, other: PNCounter) {
+        self.increments.merge(other.increments);
+        self.decrements.merge(other.decrements);
+    }
+}
+
+impl Crdt for PNCounter {
+    fn merge(&mut self, other: Self) {
+        PNCounter::merge(self, other);
+    }
+
+    fn digest(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.increments.digest());
+        hasher.update(self.decrements.digest());
+        hasher.finalize().into()
+    }
+}
+
+impl<T: Clone + Serialize + DeserializeOwned> Crdt for LWWRegister<T> {
+    fn merge(&mut self, other: Self) {
+        LWWRegister::merge(self, other);
+    }
+
+    fn digest(&self) -> [u8; 32] {
+        Sha256::digest(encode(self)).into()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -187,4 +356,134 @@ mod tests {
         assert!(s1.contains(&String::from("B")));  // Kept
         assert!(s1.contains(&String::from("C")));  // Added
     }
+
+    #[test]
+    fn test_orset_remove_survives_merge() {
+        let mut a = ORSet::new();
+        a.add("x".to_string(), "a1".to_string());
+        let mut b = a.clone();
+
+        // A removes x while B concurrently adds it again
+        a.merge(a.remove_delta(&"x".to_string()));
+        b.merge(ORSet::add_delta("x".to_string(), "b1".to_string()));
+        b.add("y".to_string(), "b2".to_string());
+
+        let mut merged_a = a.clone();
+        merged_a.merge(b.clone());
+        let mut merged_b = b;
+        merged_b.merge(a);
+
+        // Add wins over the concurrent remove, the removed tag stays gone
+        assert!(merged_a.contains(&"x".to_string()));
+        assert_eq!(merged_a.tags(&"x".to_string()), HashSet::from(["b1".to_string()]));
+        assert_eq!(merged_a, merged_b);
+        assert_eq!(Crdt::digest(&merged_a), Crdt::digest(&merged_b));
+
+        // A stale replica can't resurrect the removed tag
+        merged_a.merge(ORSet::add_delta("x".to_string(), "a1".to_string()));
+        assert_eq!(merged_a, merged_b);
+    }
+
+    /// Deterministic pseudo-random sequence for shuffling merge orders
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> usize {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 33) as usize
+        }
+
+        fn shuffle<T>(&mut self, items: &mut [T]) {
+            for i in (1..items.len()).rev() {
+                items.swap(i, self.next() % (i + 1));
+            }
+        }
+    }
+
+    /// Replicas that each applied their own updates
+    fn pn_replicas(rng: &mut Lcg) -> Vec<PNCounter> {
+        (0..5)
+            .map(|replica| {
+                let id = format!("node-{}", replica);
+                let mut counter = PNCounter::new();
+                for _ in 0..20 {
+                    let n = (rng.next() % 10) as u64;
+                    if rng.next() % 3 == 0 {
+                        counter.decrement(&id, n);
+                    } else {
+                        counter.increment(&id, n);
+                    }
+                }
+                counter
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_gcounter() {
+        let mut a = GCounter::new();
+        a.increment("A", 3);
+        let mut b = GCounter::new();
+        b.increment("B", 4);
+        b.increment("A", 1); // Stale view of A
+
+        a.merge(b.clone());
+        assert_eq!(a.value(), 7);
+
+        // Idempotent
+        a.merge(b);
+        assert_eq!(a.value(), 7);
+    }
+
+    #[test]
+    fn test_pncounter() {
+        let mut a = PNCounter::new();
+        a.increment("A", 10);
+        a.decrement("A", 3);
+        let mut b = PNCounter::new();
+        b.decrement("B", 12);
+
+        a.merge(b);
+        assert_eq!(a.value(), -5);
+    }
+
+    #[test]
+    fn test_counters_converge_in_any_merge_order() {
+        let mut rng = Lcg(42);
+
+        for _ in 0..50 {
+            let replicas = pn_replicas(&mut rng);
+            let expected: i64 = replicas.iter().map(PNCounter::value).sum();
+
+            // Every replica receives all states, in its own order and with duplicates
+            let mut results = Vec::new();
+            for start in 0..replicas.len() {
+                let mut incoming = replicas.clone();
+                incoming.push(replicas[rng.next() % replicas.len()].clone());
+                rng.shuffle(&mut incoming);
+
+                let mut merged = replicas[start].clone();
+                for state in incoming {
+                    merged.merge(state);
+                }
+                results.push(merged);
+            }
+
+            for merged in &results {
+                assert_eq!(merged.value(), expected);
+                assert_eq!(merged, &results[0]);
+            }
+
+            // Associative: (a + b) + c == a + (b + c)
+            let (a, b, c) = (replicas[0].clone(), replicas[1].clone(), replicas[2].clone());
+            let mut left = a.clone();
+            left.merge(b.clone());
+            left.merge(c.clone());
+            let mut right = b;
+            right.merge(c);
+            let mut right_total = a;
+            right_total.merge(right);
+            assert_eq!(left, right_total);
+        }
+    }
 }
@@ -1,7 +1,12 @@
 use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use anyhow::{bail, Result};
 use crate::crdt::LWWRegister;
 
+/// Block size used by `DeltaSync::diff`
+pub const DEFAULT_BLOCK_SIZE: usize = 2048;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct State {
     pub registers: HashMap<String, LWWRegister<Vec<u8>>>,
@@ -17,7 +22,174 @@ pub struct DeltaSync {
     state: State,
 }
 
+/// One step of rebuilding a buffer from an older version of it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeltaOp {
+    /// Copy `len` bytes of the old buffer starting at `offset`
+    Copy { offset: u64, len: u64 },
+    /// Bytes that don't occur in the old buffer
+    Insert(Vec<u8>),
+}
+
+/// rsync-style weak checksum over a window, cheap to slide one byte forward
+struct RollingChecksum {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl RollingChecksum {
+    fn new(window: &[u8]) -> Self {
+        let len = window.len() as u32;
+        let mut a = 0u32;
+        let mut b = 0u32;
+        for (i, &byte) in window.iter().enumerate() {
+            a = a.wrapping_add(byte as u32);
+            b = b.wrapping_add((len - i as u32).wrapping_mul(byte as u32));
+        }
+        Self { a, b, len }
+    }
+
+    /// Drop `out` from the front of the window and append `inp`
+    fn roll(&mut self, out: u8, inp: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(inp as u32);
+        self.b = self.b.wrapping_sub(self.len.wrapping_mul(out as u32)).wrapping_add(self.a);
+    }
+
+    fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+/// Checksums of the blocks of a buffer, all a sender needs to diff against it
+#[derive(Debug, Clone)]
+pub struct Signature {
+    block_size: usize,
+    /// Weak checksum -> indices of the blocks having it
+    weak: HashMap<u32, Vec<usize>>,
+    strong: Vec<[u8; 32]>,
+}
+
+impl Signature {
+    /// Checksum every full block of `data`; a trailing partial block is left out
+    pub fn new(data: &[u8], block_size: usize) -> Self {
+        let block_size = block_size.max(1);
+        let mut weak: HashMap<u32, Vec<usize>> = HashMap::new();
+        let mut strong = Vec::with_capacity(data.len() / block_size);
+
+        for (index, block) in data.chunks_exact(block_size).enumerate() {
+            weak.entry(RollingChecksum::new(block).digest()).or_default().push(index);
+            strong.push(Sha256::digest(block).into());
+        }
+
+        Self { block_size, weak, strong }
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Index of a block equal to `window`, confirmed by its strong hash
+    fn find(&self, weak: u32, window: &[u8]) -> Option<usize> {
+        let candidates = self.weak.get(&weak)?;
+        let strong: [u8; 32] = Sha256::digest(window).into();
+        candidates.iter().copied().find(|&index| self.strong[index] == strong)
+    }
+
+    /// Operations turning the buffer this signature was taken of into `new`
+    ///
+    /// Slides a window over `new` one byte at a time, so blocks are found
+    /// again after bytes are inserted or deleted in front of them.
+    pub fn delta(&self, new: &[u8]) -> Vec<DeltaOp> {
+        let block_size = self.block_size;
+        let mut ops = Vec::new();
+        if self.strong.is_empty() || new.len() < block_size {
+            push_insert(&mut ops, new);
+            return ops;
+        }
+
+        // Start of bytes not covered by an op yet
+        let mut literal_start = 0;
+        let mut pos = 0;
+        let mut checksum = RollingChecksum::new(&new[..block_size]);
+
+        while pos + block_size <= new.len() {
+            let window = &new[pos..pos + block_size];
+            match self.find(checksum.digest(), window) {
+                Some(index) => {
+                    push_insert(&mut ops, &new[literal_start..pos]);
+                    push_copy(&mut ops, (index * block_size) as u64, block_size as u64);
+                    pos += block_size;
+                    literal_start = pos;
+                    if pos + block_size <= new.len() {
+                        checksum = RollingChecksum::new(&new[pos..pos + block_size]);
+                    }
+                }
+                None => {
+                    if pos + block_size < new.len() {
+                        checksum.roll(new[pos], new[pos + block_size]);
+                    }
+                    pos += 1;
+                }
+            }
+        }
+
+        push_insert(&mut ops, &new[literal_start..]);
+        ops
+    }
+}
+
+fn push_insert(ops: &mut Vec<DeltaOp>, bytes: &[u8]) {
+    if !bytes.is_empty() {
+        ops.push(DeltaOp::Insert(bytes.to_vec()));
+    }
+}
+
+/// Append a copy, extending the previous one when the ranges are adjacent
+fn push_copy(ops: &mut Vec<DeltaOp>, offset: u64, len: u64) {
+    if let Some(DeltaOp::Copy { offset: last_offset, len: last_len }) = ops.last_mut() {
+        if *last_offset + *last_len == offset {
+            *last_len += len;
+            return;
+        }
+    }
+    ops.push(DeltaOp::Copy { offset, len });
+}
+
 impl DeltaSync {
+    /// Operations rebuilding `new` from `old`, see `diff_with_block_size`
+    pub fn diff(old: &[u8], new: &[u8]) -> Vec<DeltaOp> {
+        Self::diff_with_block_size(old, new, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// Operations rebuilding `new` from `old`, matching `old` in blocks of `block_size`
+    ///
+    /// Only ranges of `new` that aren't found among the blocks of `old` are
+    /// carried as bytes. Smaller blocks find more matches around edits but
+    /// cost more checksums.
+    pub fn diff_with_block_size(old: &[u8], new: &[u8], block_size: usize) -> Vec<DeltaOp> {
+        Signature::new(old, block_size).delta(new)
+    }
+
+    /// Rebuild the new buffer from `old` and the operations `diff` produced
+    pub fn apply(old: &[u8], ops: &[DeltaOp]) -> Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(old.len());
+        for op in ops {
+            match op {
+                DeltaOp::Copy { offset, len } => {
+                    let start = *offset as usize;
+                    let end = match start.checked_add(*len as usize) {
+                        Some(end) if end <= old.len() => end,
+                        _ => bail!("Copy of {}..+{} is outside the {} byte base", offset, len, old.len()),
+                    };
+                    out.extend_from_slice(&old[start..end]);
+                }
+                DeltaOp::Insert(bytes) => out.extend_from_slice(bytes),
+            }
+        }
+        Ok(out)
+    }
+
     pub fn new() -> Self {
         Self {
             state: State {
@@ -55,3 +227,66 @@ impl DeltaSync {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic pseudo-random bytes
+    fn buffer(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                (state >> 33) as u8
+            })
+            .collect()
+    }
+
+    fn encoded_len(ops: &[DeltaOp]) -> usize {
+        bincode::serialize(ops).unwrap().len()
+    }
+
+    #[test]
+    fn test_diff_small_edits_in_large_buffer() {
+        let old = buffer(1024 * 1024, 7);
+
+        // In-place edits
+        let mut new = old.clone();
+        new[100] ^= 0xff;
+        new[500_000] ^= 0xff;
+        new[1_000_000] ^= 0xff;
+
+        let ops = DeltaSync::diff(&old, &new);
+        assert_eq!(DeltaSync::apply(&old, &ops).unwrap(), new);
+        assert!(encoded_len(&ops) < old.len() / 50, "delta is {} bytes", encoded_len(&ops));
+
+        // Insert and delete, shifting everything after them
+        let mut new = old.clone();
+        new.splice(300_000..300_000, b"inserted bytes".iter().copied());
+        new.drain(700_000..700_123);
+
+        let ops = DeltaSync::diff(&old, &new);
+        assert_eq!(DeltaSync::apply(&old, &ops).unwrap(), new);
+        assert!(encoded_len(&ops) < old.len() / 50, "delta is {} bytes", encoded_len(&ops));
+    }
+
+    #[test]
+    fn test_diff_edge_cases() {
+        let old = buffer(10_000, 3);
+
+        let ops = DeltaSync::diff_with_block_size(&old, &old, 512);
+        assert_eq!(DeltaSync::apply(&old, &ops).unwrap(), old);
+        assert!(ops.iter().all(|op| matches!(op, DeltaOp::Copy { .. }) || op == &DeltaOp::Insert(old[9728..].to_vec())));
+
+        let ops = DeltaSync::diff(&[], &old);
+        assert_eq!(ops, vec![DeltaOp::Insert(old.clone())]);
+        assert_eq!(DeltaSync::apply(&[], &ops).unwrap(), old);
+
+        let ops = DeltaSync::diff(&old, &[]);
+        assert!(ops.is_empty());
+
+        let bad = [DeltaOp::Copy { offset: 9_000, len: 2_000 }];
+        assert!(DeltaSync::apply(&old, &bad).is_err());
+    }
+}
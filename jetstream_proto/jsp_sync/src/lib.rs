@@ -2,7 +2,10 @@ pub mod crdt;
 pub mod delta;
 pub mod snapshot;
 pub mod conflict;
+pub mod session;
 
-pub use crdt::{LWWRegister, ORSet};
-pub use delta::DeltaSync;
-pub use snapshot::SnapshotSync;
+pub use crdt::{Crdt, GCounter, LWWRegister, ORSet, PNCounter};
+pub use delta::{DeltaOp, DeltaSync, Signature};
+pub use snapshot::{ReplicaSnapshot, SnapshotSync};
+pub use conflict::{Causality, Conflict, VectorClock, Versioned};
+pub use session::{SyncSession, SyncStats, SYNC_STREAM_ID};
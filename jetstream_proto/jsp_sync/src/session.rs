@@ -0,0 +1,273 @@
+use anyhow::{Context, Result};
+use serde::{Serialize, Deserialize};
+use std::time::Duration;
+use tokio::time::Instant;
+use jsp_core::stream::RESERVED_STREAM_ID_START;
+use jsp_core::types::delivery::DeliveryMode;
+use jsp_transport::connection::Connection;
+use jsp_transport::events::ConnectionEvent;
+use crate::crdt::Crdt;
+use crate::snapshot::{ReplicaSnapshot, SnapshotSync};
+
+/// Reserved stream `SyncSession::open` binds to
+pub const SYNC_STREAM_ID: u32 = RESERVED_STREAM_ID_START + 0x200;
+pub const DEFAULT_DIGEST_INTERVAL: Duration = Duration::from_secs(1);
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize, Deserialize)]
+enum SyncFrame<C> {
+    /// Local updates, merged into the peer's replica as they are
+    Delta(C),
+    /// Digest of the sender's replica, to detect divergence
+    Digest([u8; 32]),
+    /// Full state sent to repair a peer whose digest differed
+    Snapshot(ReplicaSnapshot<C>),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncStats {
+    pub deltas_sent: u64,
+    pub deltas_received: u64,
+    pub digests_sent: u64,
+    /// Snapshots sent because the peer's digest or snapshot differed
+    pub repairs_sent: u64,
+    pub snapshots_applied: u64,
+}
+
+/// Keeps a CRDT replica in sync with the peer of a connection
+///
+/// Local updates go out as CBOR encoded deltas on a reserved stream. Digests
+/// of the replica are exchanged periodically; when they differ, for example
+/// because deltas were lost on a BestEffort stream, the replicas swap full
+/// snapshots until they converge.
+pub struct SyncSession<C: Crdt> {
+    replica: C,
+    stream_id: u32,
+    digest_interval: Duration,
+    next_digest: Instant,
+    stats: SyncStats,
+}
+
+impl<C: Crdt> SyncSession<C> {
+    /// Bind `replica` to `SYNC_STREAM_ID` on `conn`, opened with `mode`
+    ///
+    /// The peer opens its session the same way.
+    pub fn open(conn: &mut Connection, replica: C, mode: DeliveryMode) -> Result<Self> {
+        Self::open_on(conn, SYNC_STREAM_ID, replica, mode)
+    }
+
+    /// Bind `replica` to another reserved stream, to sync several replicas over one connection
+    pub fn open_on(conn: &mut Connection, stream_id: u32, replica: C, mode: DeliveryMode) -> Result<Self> {
+        conn.open_reserved_stream(stream_id, 0, mode)?;
+        Ok(Self {
+            replica,
+            stream_id,
+            digest_interval: DEFAULT_DIGEST_INTERVAL,
+            next_digest: Instant::now() + DEFAULT_DIGEST_INTERVAL,
+            stats: SyncStats::default(),
+        })
+    }
+
+    pub fn with_digest_interval(mut self, interval: Duration) -> Self {
+        self.digest_interval = interval;
+        self.next_digest = Instant::now() + interval;
+        self
+    }
+
+    pub fn replica(&self) -> &C {
+        &self.replica
+    }
+
+    /// Mutable access to the replica; changes made here reach the peer only
+    /// through digest repair, use `update` to send them right away
+    pub fn replica_mut(&mut self) -> &mut C {
+        &mut self.replica
+    }
+
+    pub fn into_replica(self) -> C {
+        self.replica
+    }
+
+    pub fn stream_id(&self) -> u32 {
+        self.stream_id
+    }
+
+    pub fn stats(&self) -> &SyncStats {
+        &self.stats
+    }
+
+    /// Merge `delta` into the local replica and send it to the peer
+    pub async fn update(&mut self, conn: &mut Connection, delta: C) -> Result<()> {
+        self.replica.merge(delta.clone());
+        self.send(conn, &SyncFrame::Delta(delta)).await?;
+        self.stats.deltas_sent += 1;
+        Ok(())
+    }
+
+    /// Send a digest of the replica now
+    pub async fn send_digest(&mut self, conn: &mut Connection) -> Result<()> {
+        self.send(conn, &SyncFrame::Digest(self.replica.digest())).await?;
+        self.stats.digests_sent += 1;
+        self.next_digest = Instant::now() + self.digest_interval;
+        Ok(())
+    }
+
+    /// Apply sync traffic in `event`; any other event is handed back
+    pub async fn handle_event(&mut self, conn: &mut Connection, event: ConnectionEvent) -> Result<Option<ConnectionEvent>> {
+        let data = match event {
+            ConnectionEvent::DataReceived { stream_id, data } if stream_id == self.stream_id => data,
+            event => return Ok(Some(event)),
+        };
+        conn.flush_acks().await?;
+
+        let frame: SyncFrame<C> = match serde_cbor::from_slice(&data) {
+            Ok(frame) => frame,
+            Err(e) => {
+                tracing::warn!(stream_id = self.stream_id, "Dropping malformed sync frame: {}", e);
+                return Ok(None);
+            }
+        };
+
+        match frame {
+            SyncFrame::Delta(delta) => {
+                self.replica.merge(delta);
+                self.stats.deltas_received += 1;
+            }
+            SyncFrame::Digest(digest) => {
+                if digest != self.replica.digest() {
+                    tracing::debug!(stream_id = self.stream_id, "Replica digests differ, sending snapshot");
+                    self.send_snapshot(conn).await?;
+                }
+            }
+            SyncFrame::Snapshot(snapshot) => {
+                self.stats.snapshots_applied += 1;
+                if SnapshotSync::apply_replica_snapshot(&mut self.replica, snapshot) {
+                    self.send_snapshot(conn).await?;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Process sync traffic for `wait`, sending digests when they are due
+    ///
+    /// Other events read meanwhile are put back on `conn` for the caller.
+    pub async fn poll(&mut self, conn: &mut Connection, wait: Duration) -> Result<()> {
+        let deadline = Instant::now() + wait;
+        let mut unrelated = Vec::new();
+
+        let result: Result<()> = async {
+            loop {
+                if Instant::now() >= self.next_digest {
+                    self.send_digest(conn).await?;
+                }
+                match tokio::time::timeout_at(deadline.min(self.next_digest), conn.next_event()).await {
+                    Ok(event) => {
+                        if let Some(event) = self.handle_event(conn, event?).await? {
+                            unrelated.push(event);
+                        }
+                    }
+                    Err(_) if Instant::now() >= deadline => return Ok(()),
+                    Err(_) => {}
+                }
+            }
+        }.await;
+
+        conn.requeue_events(unrelated);
+        result
+    }
+
+    async fn send_snapshot(&mut self, conn: &mut Connection) -> Result<()> {
+        let snapshot = SnapshotSync::snapshot_replica(&self.replica);
+        self.send(conn, &SyncFrame::Snapshot(snapshot)).await?;
+        self.stats.repairs_sent += 1;
+        Ok(())
+    }
+
+    async fn send(&self, conn: &mut Connection, frame: &SyncFrame<C>) -> Result<()> {
+        let bytes = serde_cbor::to_vec(frame).context("Failed to encode sync frame")?;
+        Ok(conn.send_on_stream_wait(self.stream_id, &bytes, Some(SEND_TIMEOUT)).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::ORSet;
+    use std::sync::Arc;
+    use tokio::sync::Barrier;
+
+    fn add(set: &mut ORSet<String>, element: &str, tag: &str) {
+        set.merge(ORSet::add_delta(element.to_string(), tag.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_orset_replicas_converge_after_partition() -> Result<()> {
+        let step = Duration::from_millis(300);
+        let barrier = Arc::new(Barrier::new(2));
+
+        let server_barrier = Arc::clone(&barrier);
+        let server = tokio::spawn(async move {
+            let mut conn = Connection::listen("127.0.0.1:9150").await?;
+            let mut session = SyncSession::open(&mut conn, ORSet::new(), DeliveryMode::Reliable)?
+                .with_digest_interval(Duration::from_millis(200));
+
+            session.update(&mut conn, ORSet::add_delta("apple".to_string(), "a1".to_string())).await?;
+            session.poll(&mut conn, step).await?;
+            server_barrier.wait().await;
+
+            // Remove what the client added
+            let delta = session.replica().remove_delta(&"banana".to_string());
+            session.update(&mut conn, delta).await?;
+            session.poll(&mut conn, step).await?;
+            server_barrier.wait().await;
+
+            // Partition: updates pile up unread on both ends
+            session.update(&mut conn, ORSet::add_delta("date".to_string(), "a2".to_string())).await?;
+            let delta = session.replica().remove_delta(&"cherry".to_string());
+            session.update(&mut conn, delta).await?;
+            add(session.replica_mut(), "elder", "a3");
+            tokio::time::sleep(step).await;
+            server_barrier.wait().await;
+
+            session.poll(&mut conn, Duration::from_millis(1500)).await?;
+            anyhow::Ok((session.into_replica(), conn))
+        });
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut conn = Connection::connect_with_config("127.0.0.1:9150", Default::default()).await?;
+        conn.handshake().await?;
+        let mut session = SyncSession::open(&mut conn, ORSet::new(), DeliveryMode::Reliable)?
+            .with_digest_interval(Duration::from_millis(200));
+
+        session.update(&mut conn, ORSet::add_delta("banana".to_string(), "b1".to_string())).await?;
+        session.poll(&mut conn, step).await?;
+        barrier.wait().await;
+
+        session.update(&mut conn, ORSet::add_delta("cherry".to_string(), "b2".to_string())).await?;
+        session.poll(&mut conn, step).await?;
+        barrier.wait().await;
+
+        // Re-add cherry concurrently with the server removing it, and remove apple
+        session.update(&mut conn, ORSet::add_delta("cherry".to_string(), "b3".to_string())).await?;
+        let delta = session.replica().remove_delta(&"apple".to_string());
+        session.update(&mut conn, delta).await?;
+        add(session.replica_mut(), "fig", "b4");
+        tokio::time::sleep(step).await;
+        barrier.wait().await;
+
+        session.poll(&mut conn, Duration::from_millis(1500)).await?;
+        let stats = session.stats().clone();
+        let client_set = session.into_replica();
+        let (server_set, _server_conn) = server.await??;
+
+        let mut elements = client_set.elements();
+        elements.sort();
+        assert_eq!(elements, vec!["cherry", "date", "elder", "fig"]);
+        assert_eq!(client_set, server_set);
+        assert_eq!(client_set.tags(&"cherry".to_string()).len(), 1);
+        // The silent updates only arrived through snapshot repair
+        assert!(stats.repairs_sent + stats.snapshots_applied > 0, "{:?}", stats);
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Serialize, Deserialize};
+use crate::crdt::Crdt;
 use crate::delta::State;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -7,6 +8,12 @@ pub struct Snapshot {
     pub version: u64,
 }
 
+/// Full state of a CRDT replica, sent to repair a peer that diverged
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicaSnapshot<C> {
+    pub state: C,
+}
+
 pub struct SnapshotSync {
     // In a real system, this would likely wrap the same state storage as DeltaSync
     // or interact with the storage layer.
@@ -34,4 +41,18 @@ impl SnapshotSync {
             current_state.last_modified = snapshot.version;
         }
     }
+
+    pub fn snapshot_replica<C: Crdt>(replica: &C) -> ReplicaSnapshot<C> {
+        ReplicaSnapshot { state: replica.clone() }
+    }
+
+    /// Merge a peer's snapshot into `replica`
+    ///
+    /// Returns true if `replica` still differs from the snapshot afterwards,
+    /// i.e. the peer lacks updates this replica has.
+    pub fn apply_replica_snapshot<C: Crdt>(replica: &mut C, snapshot: ReplicaSnapshot<C>) -> bool {
+        let peer_digest = snapshot.state.digest();
+        replica.merge(snapshot.state);
+        replica.digest() != peer_digest
+    }
 }
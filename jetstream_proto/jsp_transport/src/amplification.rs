@@ -0,0 +1,85 @@
+//! Anti-amplification limit for unvalidated addresses
+//!
+//! A ClientHello's source address can be spoofed. Until the peer proves it
+//! receives our packets, the server sends at most `AMPLIFICATION_FACTOR`
+//! times the bytes it received from the address, so it cannot be used to
+//! reflect a small request as a much larger flood at someone else.
+
+/// Bytes the server may send per byte received from an unvalidated address
+pub const AMPLIFICATION_FACTOR: u64 = 3;
+
+/// Send budget for one peer address
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AmplificationLimit {
+    received: u64,
+    sent: u64,
+    validated: bool,
+}
+
+impl AmplificationLimit {
+    /// Budget for an address that already proved it is reachable
+    pub fn validated() -> Self {
+        Self { validated: true, ..Self::default() }
+    }
+
+    /// Count a datagram received from the address
+    pub fn on_received(&mut self, bytes: usize) {
+        self.received = self.received.saturating_add(bytes as u64);
+    }
+
+    /// Reserve `bytes` for sending; false (nothing reserved) if they exceed the budget
+    pub fn try_send(&mut self, bytes: usize) -> bool {
+        if self.validated {
+            return true;
+        }
+        let sent = self.sent.saturating_add(bytes as u64);
+        if sent > self.received.saturating_mul(AMPLIFICATION_FACTOR) {
+            return false;
+        }
+        self.sent = sent;
+        true
+    }
+
+    /// The address completed a round trip; lift the limit
+    pub fn validate(&mut self) {
+        self.validated = true;
+    }
+
+    pub fn is_validated(&self) -> bool {
+        self.validated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_is_three_times_received() {
+        let mut limit = AmplificationLimit::default();
+        assert!(!limit.try_send(1));
+
+        limit.on_received(100);
+        assert!(!limit.try_send(301));
+        assert!(limit.try_send(200));
+        assert!(limit.try_send(100));
+        assert!(!limit.try_send(1));
+
+        // More bytes from the peer extend the budget
+        limit.on_received(10);
+        assert!(limit.try_send(30));
+        assert!(!limit.try_send(1));
+    }
+
+    #[test]
+    fn test_validated_address_is_unlimited() {
+        let mut limit = AmplificationLimit::default();
+        limit.on_received(10);
+        assert!(!limit.try_send(1000));
+
+        limit.validate();
+        assert!(limit.is_validated());
+        assert!(limit.try_send(1_000_000));
+        assert!(AmplificationLimit::validated().try_send(1_000_000));
+    }
+}
@@ -36,6 +36,9 @@ pub struct BbrCongestionControl {
     /// Congestion window (bytes)
     cwnd: usize,
     
+    /// Bytes sent and not yet acknowledged or lost
+    inflight: usize,
+    
     /// Maximum segment size
     mss: usize,
     
@@ -46,6 +49,7 @@ pub struct BbrCongestionControl {
     /// Round trip counter
     round_count: u64,
     round_start: bool,
+    round_start_time: Instant,
     
     /// Startup parameters
     #[allow(dead_code)]
@@ -67,8 +71,14 @@ pub struct BbrCongestionControl {
     cwnd_gain: f64,
     
     /// Full pipe detection
+    full_bw: u64,
     full_pipe_count: u32,
     filled_pipe: bool,
+    
+    /// Upper bound on inflight learned from ECN marks, if any
+    inflight_hi: Option<usize>,
+    /// Round in which inflight_hi was last lowered
+    ecn_round: Option<u64>,
 }
 
 impl BbrCongestionControl {
@@ -82,11 +92,13 @@ impl BbrCongestionControl {
             min_rtt_timestamp: now,
             pacing_rate: 0,
             cwnd: 10 * mss, // Initial window
+            inflight: 0,
             mss,
             delivered_bytes: 0,
             delivered_time: now,
             round_count: 0,
             round_start: false,
+            round_start_time: now,
             startup_gain: 2.77, // BBRv2 startup gain
             drain_gain: 1.0 / 2.77,
             probe_bw_cycle_idx: 0,
@@ -95,13 +107,18 @@ impl BbrCongestionControl {
             probe_rtt_start: None,
             pacing_gain: 2.77, // Start with startup gain
             cwnd_gain: 2.77,
+            full_bw: 0,
             full_pipe_count: 0,
             filled_pipe: false,
+            inflight_hi: None,
+            ecn_round: None,
         }
     }
     
     /// Update on packet acknowledgment
     pub fn on_ack(&mut self, acked_bytes: usize, rtt: Duration, now: Instant) {
+        self.inflight = self.inflight.saturating_sub(acked_bytes);
+        
         // Update delivery rate
         self.delivered_bytes += acked_bytes as u64;
         let elapsed = now.duration_since(self.delivered_time);
@@ -124,8 +141,17 @@ impl BbrCongestionControl {
             self.min_rtt_timestamp = now;
         }
         
+        // A round trip ends once min_rtt has passed since it started
+        self.round_start = now.duration_since(self.round_start_time) >= self.min_rtt;
+        if self.round_start {
+            self.round_count += 1;
+            self.round_start_time = now;
+        }
+        
         // Check if we need to probe RTT
-        if now.duration_since(self.min_rtt_timestamp) > Duration::from_secs(10) {
+        if self.state != BbrState::ProbeRTT
+            && now.duration_since(self.min_rtt_timestamp) > Duration::from_secs(10)
+        {
             self.enter_probe_rtt(now);
         }
         
@@ -135,19 +161,49 @@ impl BbrCongestionControl {
         // Update pacing rate and cwnd
         self.update_pacing_rate();
         self.update_cwnd();
-        
-        // Simple round counting approximation
-        if rtt > Duration::from_micros(1) {
-             self.round_start = true; // Simplified
-        }
+    }
+    
+    /// Forget the bandwidth and RTT of the old path and re-enter Startup
+    ///
+    /// Bytes in flight are still in flight, on whichever path.
+    pub fn on_path_change(&mut self) {
+        let inflight = self.inflight;
+        *self = Self::new(self.mss);
+        self.inflight = inflight;
+    }
+    
+    /// Update on packet sent
+    pub fn on_send(&mut self, sent_bytes: usize) {
+        self.inflight += sent_bytes;
     }
     
     /// Update on packet loss
-    pub fn on_loss(&mut self, _lost_bytes: usize) {
+    pub fn on_loss(&mut self, lost_bytes: usize) {
+        self.inflight = self.inflight.saturating_sub(lost_bytes);
+
         // BBR is not loss-based, but we can use loss as a signal
         // In BBRv2, we might reduce pacing slightly on persistent loss
     }
     
+    /// Update on packets the peer saw marked Congestion Experienced
+    ///
+    /// Like BBRv2, a mark means the path is queueing: Startup stops and
+    /// inflight is capped at 70% of the window, lowered at most once a round.
+    pub fn on_ecn(&mut self, marked_packets: u64) {
+        if marked_packets == 0 {
+            return;
+        }
+        self.filled_pipe = true;
+        if self.ecn_round == Some(self.round_count) {
+            return;
+        }
+        self.ecn_round = Some(self.round_count);
+        
+        let inflight_hi = cmp::max((self.cwnd as f64 * 0.7) as usize, 4 * self.mss);
+        self.inflight_hi = Some(inflight_hi);
+        self.cwnd = cmp::min(self.cwnd, inflight_hi);
+    }
+    
     /// Enter ProbeRTT state
     fn enter_probe_rtt(&mut self, now: Instant) {
         self.state = BbrState::ProbeRTT;
@@ -172,8 +228,8 @@ impl BbrCongestionControl {
             
             BbrState::Drain => {
                 // Exit drain when inflight <= BDP
-                let bdp = self.btlbw * self.min_rtt.as_secs_f64() as u64;
-                if self.cwnd as u64 <= bdp {
+                let bdp = (self.btlbw as f64 * self.min_rtt.as_secs_f64()) as usize;
+                if self.inflight <= bdp {
                     self.state = BbrState::ProbeBW;
                     self.probe_bw_cycle_idx = 0;
                     self.probe_bw_cycle_start = now;
@@ -187,9 +243,10 @@ impl BbrCongestionControl {
                     self.probe_bw_cycle_idx = (self.probe_bw_cycle_idx + 1) % 8;
                     self.probe_bw_cycle_start = now;
                     self.update_probe_bw_gain();
-                    
-                    // Track rounds for potential mode switching
-                    self.round_count += 1;
+                    // Probing up also probes past the ECN bound
+                    if self.probe_bw_cycle_idx == 0 {
+                        self.inflight_hi = self.inflight_hi.map(|hi| hi + hi / 4);
+                    }
                 }
             }
             
@@ -198,6 +255,8 @@ impl BbrCongestionControl {
                     if now.duration_since(start) > self.probe_rtt_min_duration {
                         self.state = BbrState::ProbeBW;
                         self.probe_rtt_start = None;
+                        // The current min_rtt stays valid for another 10s
+                        self.min_rtt_timestamp = now;
                         self.probe_bw_cycle_idx = 0;
                         self.update_probe_bw_gain();
                     }
@@ -208,23 +267,27 @@ impl BbrCongestionControl {
 
     /// Check if pipe is full (bandwidth plateaued)
     fn check_full_pipe(&mut self) -> bool {
-        // Simplified check: if we've been in startup for a while and bandwidth isn't growing fast enough
-        // In a real implementation, we'd track max bandwidth over a window
         if self.filled_pipe {
             return true;
         }
         
-        // For simulation/simplification, we assume pipe fills after some rounds
-        // This logic would normally check if btlbw increased by < 25% over the last round
-        if self.btlbw > 0 && self.round_count > 3 {
-            self.full_pipe_count += 1;
-            if self.full_pipe_count >= 3 {
-                self.filled_pipe = true;
-                return true;
-            }
+        // Judged once per round, once there is a bandwidth estimate
+        if !self.round_start || self.btlbw == 0 {
+            return false;
+        }
+        
+        // The pipe is full when btlbw grew by less than 25% for 3 rounds in a row
+        if self.btlbw as f64 >= self.full_bw as f64 * 1.25 {
+            self.full_bw = self.btlbw;
+            self.full_pipe_count = 0;
+            return false;
+        }
+        self.full_pipe_count += 1;
+        if self.full_pipe_count >= 3 {
+            self.filled_pipe = true;
         }
         
-        false
+        self.filled_pipe
     }
     
     /// Update ProbeBW pacing gain based on cycle
@@ -247,6 +310,12 @@ impl BbrCongestionControl {
     
     /// Update congestion window
     fn update_cwnd(&mut self) {
+        // ProbeRTT holds cwnd at 4 MSS until it ends
+        if self.state == BbrState::ProbeRTT {
+            self.cwnd = 4 * self.mss;
+            return;
+        }
+        
         let bdp = if self.btlbw > 0 {
             (self.btlbw as f64 * self.min_rtt.as_secs_f64() * self.cwnd_gain) as usize
         } else {
@@ -255,6 +324,9 @@ impl BbrCongestionControl {
         
         // Ensure minimum cwnd
         self.cwnd = cmp::max(bdp, 4 * self.mss);
+        if let Some(inflight_hi) = self.inflight_hi {
+            self.cwnd = cmp::min(self.cwnd, inflight_hi);
+        }
     }
     
     /// Get current congestion window
@@ -281,8 +353,8 @@ impl BbrCongestionControl {
 use crate::congestion::{CongestionController, CongestionState};
 
 impl CongestionController for BbrCongestionControl {
-    fn on_packet_sent(&mut self, _sent_bytes: usize) {
-        // BBR doesn't track inflight internally in this implementation
+    fn on_packet_sent(&mut self, sent_bytes: usize) {
+        self.on_send(sent_bytes);
     }
 
     fn on_packet_acked(&mut self, acked_bytes: usize, rtt: Duration) {
@@ -293,6 +365,19 @@ impl CongestionController for BbrCongestionControl {
         self.on_loss(lost_bytes);
     }
 
+    fn on_ecn_marked(&mut self, marked_packets: u64) {
+        self.on_ecn(marked_packets);
+    }
+
+    fn on_path_changed(&mut self) {
+        self.on_path_change();
+    }
+
+    fn set_mss(&mut self, mss: usize) {
+        self.mss = mss;
+        self.cwnd = cmp::max(self.cwnd, 4 * mss);
+    }
+
     fn congestion_window(&self) -> usize {
         self.cwnd
     }
@@ -344,4 +429,66 @@ mod tests {
         // Should have updated delivery rate
         assert!(bbr.pacing_rate() > 0);
     }
+
+    #[test]
+    fn test_bbr_progresses_to_probe_bw() {
+        let mut bbr = BbrCongestionControl::new(1000);
+        let start = Instant::now();
+        let rtt = Duration::from_millis(20);
+        let mut states = vec![bbr.state()];
+
+        // A steady 1 MB/s path: the bandwidth stops growing after the first round
+        for round in 1..=20 {
+            bbr.on_send(20_000);
+            bbr.on_ack(20_000, rtt, start + rtt * round);
+            if states.last() != Some(&bbr.state()) {
+                states.push(bbr.state());
+            }
+        }
+
+        assert_eq!(states, [BbrState::Startup, BbrState::Drain, BbrState::ProbeBW]);
+    }
+
+    #[test]
+    fn test_bbr_ecn_ends_startup_and_caps_cwnd() {
+        let mut bbr = BbrCongestionControl::new(1000);
+        let start = Instant::now();
+        let rtt = Duration::from_millis(20);
+
+        bbr.on_send(20_000);
+        bbr.on_ack(20_000, rtt, start + rtt);
+        assert_eq!(bbr.state(), BbrState::Startup);
+        let cwnd = bbr.congestion_window();
+
+        bbr.on_ecn(2);
+        assert_eq!(bbr.congestion_window(), cwnd * 7 / 10);
+        // The same round does not lower the bound again
+        bbr.on_ecn(5);
+        assert_eq!(bbr.congestion_window(), cwnd * 7 / 10);
+
+        bbr.on_send(10_000);
+        bbr.on_ack(10_000, rtt, start + rtt * 2);
+        assert_eq!(bbr.state(), BbrState::Drain);
+        assert!(bbr.congestion_window() <= cwnd * 7 / 10);
+    }
+
+    #[test]
+    fn test_bbr_path_change_reenters_startup() {
+        let mut bbr = BbrCongestionControl::new(1000);
+        let start = Instant::now();
+        let rtt = Duration::from_millis(20);
+        for round in 1..=20 {
+            bbr.on_send(20_000);
+            bbr.on_ack(20_000, rtt, start + rtt * round);
+        }
+        assert_eq!(bbr.state(), BbrState::ProbeBW);
+        bbr.on_send(5_000);
+
+        bbr.on_path_change();
+        assert_eq!(bbr.state(), BbrState::Startup);
+        assert_eq!(bbr.btlbw, 0);
+        assert_eq!(bbr.congestion_window(), 10_000);
+        assert_eq!(bbr.inflight, 5_000);
+        assert!(!bbr.can_send(10_000));
+    }
 }
@@ -1,9 +1,11 @@
-use std::time::Duration;
-use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
-use std::sync::Arc;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
 
 /// Circuit Breaker states
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum State {
     /// Normal operation, requests allowed
     Closed,
@@ -13,15 +15,31 @@ pub enum State {
     HalfOpen,
 }
 
+impl State {
+    /// Lowercase name, as used in events of the language bindings
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            State::Closed => "closed",
+            State::Open => "open",
+            State::HalfOpen => "half_open",
+        }
+    }
+}
+
 /// Circuit Breaker configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CircuitBreakerConfig {
     /// Number of failures before opening the circuit
     pub failure_threshold: u32,
     /// Duration to wait before switching from Open to HalfOpen
+    #[serde(with = "crate::duration_format")]
     pub reset_timeout: Duration,
     /// Number of successful requests in HalfOpen to switch back to Closed
     pub success_threshold: u32,
+    /// Requests let through in HalfOpen; further ones are refused until
+    /// the probes decide, or until `reset_timeout` passes without a verdict
+    pub half_open_max_probes: u32,
 }
 
 impl Default for CircuitBreakerConfig {
@@ -30,116 +48,178 @@ impl Default for CircuitBreakerConfig {
             failure_threshold: 5,
             reset_timeout: Duration::from_secs(10),
             success_threshold: 2,
+            half_open_max_probes: 2,
         }
     }
 }
 
-/// Circuit Breaker implementation
+/// Called with the old and new state on every transition
+pub type StateListener = Box<dyn Fn(State, State) + Send + Sync>;
+
 #[derive(Debug)]
+struct Inner {
+    state: State,
+    failures: u32,
+    successes: u32,
+    /// Probes let through in the current HalfOpen round
+    probes: u32,
+    /// Last failure while Open, or the start of the HalfOpen round
+    since: Instant,
+}
+
+/// Circuit Breaker implementation
+///
+/// Closed counts consecutive failures and opens at `failure_threshold`.
+/// Open refuses every request until `reset_timeout` has passed since the
+/// last failure, then lets the next one through as the first probe of
+/// HalfOpen. HalfOpen lets exactly `half_open_max_probes` requests through:
+/// `success_threshold` successes close the circuit, any failure opens it
+/// again.
 pub struct CircuitBreaker {
     config: CircuitBreakerConfig,
-    state: Arc<AtomicU32>, // 0: Closed, 1: Open, 2: HalfOpen
-    failures: Arc<AtomicU32>,
-    successes: Arc<AtomicU32>,
-    last_failure_time: Arc<AtomicU64>, // Milliseconds since epoch
+    inner: Mutex<Inner>,
+    listener: Option<StateListener>,
+}
+
+impl fmt::Debug for CircuitBreaker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CircuitBreaker")
+            .field("config", &self.config)
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
 }
 
 impl CircuitBreaker {
     pub fn new(config: CircuitBreakerConfig) -> Self {
         Self {
             config,
-            state: Arc::new(AtomicU32::new(0)),
-            failures: Arc::new(AtomicU32::new(0)),
-            successes: Arc::new(AtomicU32::new(0)),
-            last_failure_time: Arc::new(AtomicU64::new(0)),
+            inner: Mutex::new(Inner {
+                state: State::Closed,
+                failures: 0,
+                successes: 0,
+                probes: 0,
+                since: Instant::now(),
+            }),
+            listener: None,
         }
     }
 
+    /// Call `listener` on every state change, outside the breaker's lock
+    pub fn with_listener(mut self, listener: impl Fn(State, State) + Send + Sync + 'static) -> Self {
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    pub fn config(&self) -> &CircuitBreakerConfig {
+        &self.config
+    }
+
     /// Check if a request is allowed
+    ///
+    /// In HalfOpen an allowed request is one of the probes, so call this
+    /// only for requests whose outcome will be recorded.
     pub fn allow_request(&self) -> bool {
-        let state = self.state.load(Ordering::Relaxed);
-        
-        match state {
-            0 => true, // Closed
-            1 => { // Open
-                // Check if reset timeout has passed
-                let last_failure = self.last_failure_time.load(Ordering::Relaxed);
-                let now = self.now_ms();
-                
-                if now.saturating_sub(last_failure) >= self.config.reset_timeout.as_millis() as u64 {
-                    // Switch to HalfOpen
-                    if self.state.compare_exchange(1, 2, Ordering::SeqCst, Ordering::Relaxed).is_ok() {
-                        self.successes.store(0, Ordering::Relaxed);
-                        return true;
-                    }
+        let mut inner = self.inner.lock().unwrap();
+        match inner.state {
+            State::Closed => true,
+            State::Open => {
+                if inner.since.elapsed() < self.config.reset_timeout {
+                    return false;
                 }
-                false
-            },
-            2 => true, // HalfOpen (allow probing)
-            _ => true,
+                inner.state = State::HalfOpen;
+                inner.successes = 0;
+                inner.probes = 1;
+                inner.since = Instant::now();
+                drop(inner);
+                self.notify(State::Open, State::HalfOpen);
+                true
+            }
+            State::HalfOpen => {
+                if inner.probes < self.config.half_open_max_probes {
+                    inner.probes += 1;
+                    true
+                } else if inner.since.elapsed() >= self.config.reset_timeout {
+                    // The probes' outcomes never came; start another round
+                    inner.successes = 0;
+                    inner.probes = 1;
+                    inner.since = Instant::now();
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    /// Give back the HalfOpen probe slot `allow_request` took for a request
+    /// that was not made after all
+    pub fn release_probe(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.state == State::HalfOpen {
+            inner.probes = inner.probes.saturating_sub(1);
         }
     }
 
     /// Record a successful request
     pub fn record_success(&self) {
-        let state = self.state.load(Ordering::Relaxed);
-        
-        if state == 2 { // HalfOpen
-            let successes = self.successes.fetch_add(1, Ordering::Relaxed) + 1;
-            if successes >= self.config.success_threshold {
-                // Reset to Closed
-                self.state.store(0, Ordering::SeqCst);
-                self.failures.store(0, Ordering::Relaxed);
-                self.successes.store(0, Ordering::Relaxed);
+        let mut inner = self.inner.lock().unwrap();
+        match inner.state {
+            State::HalfOpen => {
+                inner.successes += 1;
+                if inner.successes >= self.config.success_threshold {
+                    inner.state = State::Closed;
+                    inner.failures = 0;
+                    inner.successes = 0;
+                    drop(inner);
+                    self.notify(State::HalfOpen, State::Closed);
+                }
             }
-        } else if state == 0 { // Closed
-            // Reset failure count on success in Closed state (optional, depends on strategy)
-            self.failures.store(0, Ordering::Relaxed);
+            // Only consecutive failures open the circuit
+            State::Closed => inner.failures = 0,
+            State::Open => {}
         }
     }
 
     /// Record a failed request
     pub fn record_failure(&self) {
-        let state = self.state.load(Ordering::Relaxed);
-        
-        if state == 0 { // Closed
-            let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
-            if failures >= self.config.failure_threshold {
-                // Trip to Open
-                self.trip_to_open();
+        let mut inner = self.inner.lock().unwrap();
+        let old = inner.state;
+        match old {
+            State::Closed => {
+                inner.failures += 1;
+                if inner.failures < self.config.failure_threshold {
+                    return;
+                }
+                inner.state = State::Open;
             }
-        } else if state == 2 { // HalfOpen
             // Immediate trip back to Open on failure in HalfOpen
-            self.trip_to_open();
+            State::HalfOpen => inner.state = State::Open,
+            // Requests sent before the circuit opened keep it open longer
+            State::Open => {}
+        }
+        inner.since = Instant::now();
+        drop(inner);
+        if old != State::Open {
+            self.notify(old, State::Open);
         }
-        
-        self.last_failure_time.store(self.now_ms(), Ordering::Relaxed);
-    }
-    
-    fn trip_to_open(&self) {
-        self.state.store(1, Ordering::SeqCst);
     }
-    
-    fn now_ms(&self) -> u64 {
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_millis() as u64
+
+    fn notify(&self, old: State, new: State) {
+        if let Some(listener) = &self.listener {
+            listener(old, new);
+        }
     }
     
     pub fn state(&self) -> State {
-        match self.state.load(Ordering::Relaxed) {
-            0 => State::Closed,
-            1 => State::Open,
-            2 => State::HalfOpen,
-            _ => State::Closed,
-        }
+        self.inner.lock().unwrap().state
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::Arc;
     use std::thread;
 
     #[test]
@@ -171,6 +251,7 @@ mod tests {
             failure_threshold: 1,
             reset_timeout: Duration::from_millis(100),
             success_threshold: 2,
+            ..Default::default()
         };
         let cb = CircuitBreaker::new(config);
 
@@ -200,6 +281,7 @@ mod tests {
             failure_threshold: 1,
             reset_timeout: Duration::from_millis(100),
             success_threshold: 2,
+            ..Default::default()
         };
         let cb = CircuitBreaker::new(config);
 
@@ -216,4 +298,94 @@ mod tests {
         assert_eq!(cb.state(), State::Open);
         assert!(!cb.allow_request());
     }
+
+    #[test]
+    fn test_half_open_allows_exactly_max_probes() {
+        let config = CircuitBreakerConfig {
+            failure_threshold: 1,
+            reset_timeout: Duration::from_millis(50),
+            success_threshold: 3,
+            half_open_max_probes: 3,
+        };
+        let cb = CircuitBreaker::new(config);
+        cb.record_failure();
+        thread::sleep(Duration::from_millis(60));
+
+        let allowed = (0..10).filter(|_| cb.allow_request()).count();
+        assert_eq!(allowed, 3);
+        assert_eq!(cb.state(), State::HalfOpen);
+
+        cb.record_success();
+        cb.record_success();
+        assert_eq!(cb.state(), State::HalfOpen);
+        assert!(!cb.allow_request());
+        cb.record_success();
+        assert_eq!(cb.state(), State::Closed);
+        assert!(cb.allow_request());
+
+        // Probes whose outcome never comes don't hold the circuit half-open forever
+        cb.record_failure();
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!((0..10).filter(|_| cb.allow_request()).count(), 3);
+        thread::sleep(Duration::from_millis(60));
+        assert!(cb.allow_request());
+        assert_eq!(cb.state(), State::HalfOpen);
+    }
+
+    #[test]
+    fn test_released_probe_can_be_taken_again() {
+        let config = CircuitBreakerConfig {
+            failure_threshold: 1,
+            reset_timeout: Duration::from_millis(50),
+            success_threshold: 1,
+            half_open_max_probes: 1,
+        };
+        let cb = CircuitBreaker::new(config);
+        cb.record_failure();
+        thread::sleep(Duration::from_millis(60));
+
+        assert!(cb.allow_request());
+        assert!(!cb.allow_request());
+        cb.release_probe();
+        assert!(cb.allow_request());
+        assert_eq!(cb.state(), State::HalfOpen);
+
+        cb.record_success();
+        // Nothing to give back outside HalfOpen
+        cb.release_probe();
+        assert_eq!(cb.state(), State::Closed);
+    }
+
+    #[test]
+    fn test_listener_sees_every_transition() {
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&transitions);
+        let config = CircuitBreakerConfig {
+            failure_threshold: 2,
+            reset_timeout: Duration::from_millis(50),
+            success_threshold: 1,
+            half_open_max_probes: 1,
+        };
+        let cb = CircuitBreaker::new(config)
+            .with_listener(move |old, new| seen.lock().unwrap().push((old, new)));
+
+        cb.record_failure();
+        cb.record_failure();
+        // Failures while open change nothing
+        cb.record_failure();
+        thread::sleep(Duration::from_millis(60));
+        assert!(cb.allow_request());
+        cb.record_failure();
+        thread::sleep(Duration::from_millis(60));
+        assert!(cb.allow_request());
+        cb.record_success();
+
+        assert_eq!(*transitions.lock().unwrap(), vec![
+            (State::Closed, State::Open),
+            (State::Open, State::HalfOpen),
+            (State::HalfOpen, State::Open),
+            (State::Open, State::HalfOpen),
+            (State::HalfOpen, State::Closed),
+        ]);
+    }
 }
@@ -0,0 +1,209 @@
+//! Frame boundaries in coalesced datagrams
+//!
+//! With `coalescing_window_ms` set, the sender task packs queued packets
+//! back to back into one datagram. Each frame is `[header_len: u16]
+//! [header][payload]`, and only the header's `payload_len` says where the
+//! payload ends and the next frame starts; a frame without it swallows
+//! every frame behind it. Frames are therefore checked with `frame_len`
+//! before they are queued.
+
+use anyhow::{anyhow, bail, Result};
+use jsp_core::codec::{decode_any_header, SerializationFormat};
+use jsp_core::compression::header_compression::peek_payload_len;
+
+/// Length of the first frame in `packet`, as the receiver will delimit it
+///
+/// Fails if the header has no `payload_len` or the payload runs past the
+/// end of `packet`. Headers below 0x80 are compressed on CBOR sessions and
+/// FlatBuffers otherwise, as in the receive loop.
+pub fn frame_len(packet: &[u8], format: SerializationFormat) -> Result<usize> {
+    let header_len = match packet {
+        [high, low, ..] => u16::from_be_bytes([*high, *low]) as usize,
+        _ => bail!("Frame too short for its header length"),
+    };
+    let header_bytes = packet.get(2..2 + header_len)
+        .ok_or_else(|| anyhow!("Header runs past the end of the frame"))?;
+    let compressed = header_bytes.first().is_some_and(|&first| first < 0x80)
+        && format == SerializationFormat::Cbor;
+    let payload_len = if compressed {
+        peek_payload_len(header_bytes)
+    } else {
+        decode_any_header(header_bytes)?.payload_len
+    };
+    let payload_len = payload_len
+        .ok_or_else(|| anyhow!("Frame has no payload_len, so frames coalesced behind it would be lost"))?;
+    let len = 2 + header_len + payload_len as usize;
+    if len > packet.len() {
+        bail!("Payload of {} bytes runs past the end of the frame", payload_len);
+    }
+    Ok(len)
+}
+
+/// Check that `packet` is exactly one frame that says where it ends
+pub fn check_frame(packet: &[u8], format: SerializationFormat) -> Result<()> {
+    let len = frame_len(packet, format)?;
+    if len != packet.len() {
+        bail!("Frame of {} bytes has {} bytes behind it", len, packet.len() - len);
+    }
+    Ok(())
+}
+
+/// The frames of a coalesced datagram, in order
+pub fn split_frames(datagram: &[u8], format: SerializationFormat) -> Result<Vec<&[u8]>> {
+    let mut frames = Vec::new();
+    let mut rest = datagram;
+    while !rest.is_empty() {
+        let len = frame_len(rest, format)?;
+        let (frame, tail) = rest.split_at(len);
+        frames.push(frame);
+        rest = tail;
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsp_core::codec::Codec;
+    use jsp_core::compression::header_compression::HeaderCompressor;
+    use jsp_core::types::connection_id::ConnectionId;
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_core::types::header::{
+        Header, FRAME_TYPE_ACK, FRAME_TYPE_CLOSE, FRAME_TYPE_DATA, FRAME_TYPE_DATAGRAM,
+        FRAME_TYPE_KEY_UPDATE, FRAME_TYPE_PATH_CHALLENGE, FRAME_TYPE_STREAM_FIN,
+    };
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const MSG_TYPES: [u8; 7] = [
+        FRAME_TYPE_DATA,
+        FRAME_TYPE_ACK,
+        FRAME_TYPE_CLOSE,
+        FRAME_TYPE_STREAM_FIN,
+        FRAME_TYPE_DATAGRAM,
+        FRAME_TYPE_KEY_UPDATE,
+        FRAME_TYPE_PATH_CHALLENGE,
+    ];
+
+    /// Datagram size the sender task coalesces up to by default
+    const MAX_DATAGRAM: usize = 1400;
+
+    fn frame(header_bytes: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut frame = (header_bytes.len() as u16).to_be_bytes().to_vec();
+        frame.extend_from_slice(header_bytes);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn random_header(rng: &mut StdRng, payload_len: usize) -> Header {
+        let msg_type = MSG_TYPES[rng.gen_range(0..MSG_TYPES.len())];
+        let delivery_mode = match rng.gen_range(0..3) {
+            0 => DeliveryMode::Reliable,
+            1 => DeliveryMode::PartiallyReliable { ttl_ms: rng.gen_range(1..10_000) },
+            _ => DeliveryMode::BestEffort,
+        };
+        Header::new(
+            if msg_type == FRAME_TYPE_DATA { rng.gen_range(1..8) } else { 0 },
+            msg_type,
+            rng.gen(),
+            rng.gen_range(0..1 << 20),
+            rng.gen_range(1_700_000_000_000..1_800_000_000_000),
+            rng.gen(),
+            delivery_mode,
+            if rng.gen_bool(0.3) { Some(rng.gen_range(0..1 << 20)) } else { None },
+            Some(payload_len as u32),
+        )
+    }
+
+    /// A random frame encoded the way the sender does: compressed headers
+    /// on CBOR sessions, with the odd uncompressed one carrying the
+    /// connection ID as during a migration
+    fn random_frame(rng: &mut StdRng, format: SerializationFormat, compressor: &mut HeaderCompressor) -> (Vec<u8>, Header, Vec<u8>) {
+        let payload: Vec<u8> = (0..rng.gen_range(0..300)).map(|_| rng.gen()).collect();
+        let mut header = random_header(rng, payload.len());
+        let header_bytes = if format == SerializationFormat::Cbor && rng.gen_bool(0.8) {
+            compressor.compress(&header)
+        } else {
+            header.connection_id = Some(ConnectionId::from_u64(rng.gen()));
+            Codec::new(format).encode_header(&header).unwrap()
+        };
+        (frame(&header_bytes, &payload), header, payload)
+    }
+
+    #[test]
+    fn test_random_coalesced_frames_split_exactly() {
+        for format in [SerializationFormat::Cbor, SerializationFormat::FlatBuffers] {
+            let mut rng = StdRng::seed_from_u64(0x5EED);
+            let mut compressor = HeaderCompressor::new();
+            let mut decompressor = HeaderCompressor::new();
+            let mut next = random_frame(&mut rng, format, &mut compressor);
+            for _ in 0..200 {
+                // Packed as the sender task packs them; a frame that doesn't
+                // fit starts the next datagram, keeping the compressor in step
+                let mut datagram = Vec::new();
+                let mut sent = Vec::new();
+                while datagram.is_empty() || datagram.len() + next.0.len() <= MAX_DATAGRAM {
+                    datagram.extend_from_slice(&next.0);
+                    sent.push((next.1, next.2));
+                    next = random_frame(&mut rng, format, &mut compressor);
+                }
+                let frames = split_frames(&datagram, format).unwrap();
+                assert_eq!(frames.len(), sent.len());
+
+                // Decoded as the receive loop does, decompressor state and all
+                for (frame, (header, payload)) in frames.iter().zip(&sent) {
+                    let header_len = u16::from_be_bytes([frame[0], frame[1]]) as usize;
+                    let header_bytes = &frame[2..2 + header_len];
+                    let decoded = if header_bytes[0] < 0x80 && format == SerializationFormat::Cbor {
+                        decompressor.decompress(header_bytes).unwrap()
+                    } else {
+                        decode_any_header(header_bytes).unwrap()
+                    };
+                    assert_eq!(&decoded, header);
+                    assert_eq!(&frame[2 + header_len..], &payload[..]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_frame_without_payload_len_rejected() {
+        // The mis-split case: an ACK without payload_len ahead of a data
+        // frame, which the receiver would read as part of the ACK
+        let ack = serde_cbor::to_vec(&Header::new(0, FRAME_TYPE_ACK, 0, 0, 0, 0, DeliveryMode::BestEffort, None, None)).unwrap();
+        let data = serde_cbor::to_vec(&Header::new(1, FRAME_TYPE_DATA, 0, 1, 0, 0, DeliveryMode::Reliable, None, Some(5))).unwrap();
+        let mut datagram = frame(&ack, b"\xa1\x00\x01");
+        datagram.extend(frame(&data, b"hello"));
+
+        assert!(frame_len(&datagram, SerializationFormat::Cbor).is_err());
+        assert!(split_frames(&datagram, SerializationFormat::Cbor).is_err());
+
+        // Compressed, the flags byte says the length is missing
+        let compressed = HeaderCompressor::new()
+            .compress(&Header::new(0, FRAME_TYPE_ACK, 0, 0, 0, 0, DeliveryMode::BestEffort, None, None));
+        assert!(frame_len(&frame(&compressed, b"\x00"), SerializationFormat::Cbor).is_err());
+
+        // With the length, both frames come back
+        let ack = serde_cbor::to_vec(&Header::new(0, FRAME_TYPE_ACK, 0, 0, 0, 0, DeliveryMode::BestEffort, None, Some(3))).unwrap();
+        let mut datagram = frame(&ack, b"\xa1\x00\x01");
+        datagram.extend(frame(&data, b"hello"));
+        let frames = split_frames(&datagram, SerializationFormat::Cbor).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert!(frames[1].ends_with(b"hello"));
+    }
+
+    #[test]
+    fn test_truncated_frame_rejected() {
+        let data = serde_cbor::to_vec(&Header::new(1, FRAME_TYPE_DATA, 0, 1, 0, 0, DeliveryMode::Reliable, None, Some(5))).unwrap();
+        let whole = frame(&data, b"hello");
+        assert_eq!(frame_len(&whole, SerializationFormat::Cbor).unwrap(), whole.len());
+        assert!(frame_len(&whole[..whole.len() - 1], SerializationFormat::Cbor).is_err());
+        assert!(frame_len(&whole[..4], SerializationFormat::Cbor).is_err());
+        assert!(frame_len(&[0x00], SerializationFormat::Cbor).is_err());
+
+        check_frame(&whole, SerializationFormat::Cbor).unwrap();
+        let mut trailing = whole.clone();
+        trailing.push(0);
+        assert!(check_frame(&trailing, SerializationFormat::Cbor).is_err());
+    }
+}
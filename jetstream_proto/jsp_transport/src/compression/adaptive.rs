@@ -1,19 +1,33 @@
 use std::time::Duration;
 use std::cmp::{max, min};
+use serde::{Deserialize, Serialize};
+use jsp_core::compression::payload_compression::CompressionAlgorithm;
 
 /// Configuration for adaptive compression
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AdaptiveCompressionConfig {
-    /// Minimum compression level (e.g., 0 for no compression or low level)
+    /// Minimum compression level; at level 0 payloads go uncompressed
     pub min_level: i32,
     /// Maximum compression level
     pub max_level: i32,
     /// RTT threshold above which to decrease compression (latency bottleneck)
+    #[serde(with = "crate::duration_format")]
     pub high_rtt_threshold: Duration,
     /// RTT threshold below which to increase compression (bandwidth optimization)
+    #[serde(with = "crate::duration_format")]
     pub low_rtt_threshold: Duration,
+    /// RTT below which the peer is taken to be on the local network and
+    /// payloads go uncompressed, as bandwidth is cheaper than CPU there
+    #[serde(with = "crate::duration_format")]
+    pub local_rtt_threshold: Duration,
     /// Packet loss threshold above which to decrease compression
     pub packet_loss_threshold: f64,
+    /// Levels moved per adjustment (high packet loss moves twice as far)
+    pub aggressiveness: i32,
+    /// Minimum time between level adjustments
+    #[serde(with = "crate::duration_format")]
+    pub update_interval: Duration,
 }
 
 impl Default for AdaptiveCompressionConfig {
@@ -23,27 +37,88 @@ impl Default for AdaptiveCompressionConfig {
             max_level: 9, // Standard max for many algos
             high_rtt_threshold: Duration::from_millis(200),
             low_rtt_threshold: Duration::from_millis(50),
+            local_rtt_threshold: Duration::from_millis(10),
             packet_loss_threshold: 0.05, // 5%
+            aggressiveness: 1,
+            update_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Why adaptive compression made its current decision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompressionReason {
+    /// No RTT measured yet
+    Initial,
+    /// RTT below `local_rtt_threshold`
+    LocalLink,
+    /// The application is in the background, saving battery
+    PowerSaving,
+    /// Loss above `packet_loss_threshold`
+    HighLoss,
+    /// RTT above `high_rtt_threshold`
+    HighRtt,
+    /// RTT below `low_rtt_threshold`
+    LowRtt,
+    /// RTT between the thresholds
+    Steady,
+}
+
+impl CompressionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompressionReason::Initial => "initial",
+            CompressionReason::LocalLink => "local_link",
+            CompressionReason::PowerSaving => "power_saving",
+            CompressionReason::HighLoss => "high_loss",
+            CompressionReason::HighRtt => "high_rtt",
+            CompressionReason::LowRtt => "low_rtt",
+            CompressionReason::Steady => "steady",
         }
     }
 }
 
+/// How payloads are compressed right now, and why
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompressionDecision {
+    /// None sends payloads as they are
+    pub algorithm: Option<CompressionAlgorithm>,
+    /// Level passed to the algorithm (0 without one)
+    pub level: i32,
+    pub reason: CompressionReason,
+}
+
 /// Adaptive compression manager
+///
+/// Steps a level between `min_level` and `max_level` with RTT and loss,
+/// and turns it into a `CompressionDecision`: nothing on local links,
+/// LZ4 while saving power, Zstd at the level otherwise.
 pub struct AdaptiveCompression {
     current_level: i32,
     config: AdaptiveCompressionConfig,
     last_update: std::time::Instant,
     update_interval: Duration,
+    power_saving: bool,
+    last_rtt: Duration,
+    last_loss: f64,
+    decision: CompressionDecision,
 }
 
 impl AdaptiveCompression {
     pub fn new(config: AdaptiveCompressionConfig) -> Self {
-        Self {
-            current_level: (config.min_level + config.max_level) / 2,
+        let current_level = (config.min_level + config.max_level) / 2;
+        let mut adaptive = Self {
+            current_level,
+            update_interval: config.update_interval,
             config,
             last_update: std::time::Instant::now(),
-            update_interval: Duration::from_secs(5),
-        }
+            power_saving: false,
+            last_rtt: Duration::ZERO,
+            last_loss: 0.0,
+            decision: CompressionDecision { algorithm: None, level: 0, reason: CompressionReason::Initial },
+        };
+        adaptive.decide();
+        adaptive
     }
 
     pub fn default_config() -> Self {
@@ -64,12 +139,13 @@ impl AdaptiveCompression {
         // 2. High RTT -> Reduce compression (latency bottleneck, CPU might add to it)
         // 3. Low RTT -> Increase compression (optimize bandwidth)
         
+        let step = self.config.aggressiveness.max(1);
         if packet_loss > self.config.packet_loss_threshold {
-            self.current_level = max(self.config.min_level, self.current_level - 2);
+            self.current_level = max(self.config.min_level, self.current_level - 2 * step);
         } else if rtt > self.config.high_rtt_threshold {
-            self.current_level = max(self.config.min_level, self.current_level - 1);
+            self.current_level = max(self.config.min_level, self.current_level - step);
         } else if rtt < self.config.low_rtt_threshold {
-            self.current_level = min(self.config.max_level, self.current_level + 1);
+            self.current_level = min(self.config.max_level, self.current_level + step);
         }
 
         if self.current_level != old_level {
@@ -81,12 +157,61 @@ impl AdaptiveCompression {
                 "Adaptive compression level updated"
             );
         }
+
+        self.last_rtt = rtt;
+        self.last_loss = packet_loss;
+        self.decide();
+    }
+
+    /// Favour cheap compression, e.g. while the application is in the background
+    pub fn set_power_saving(&mut self, power_saving: bool) {
+        self.power_saving = power_saving;
+        self.decide();
     }
 
     /// Get current compression level
     pub fn get_level(&self) -> i32 {
         self.current_level
     }
+
+    /// Algorithm and level payloads are compressed with, and why
+    pub fn decision(&self) -> CompressionDecision {
+        self.decision
+    }
+
+    /// Re-derive the decision from the last metrics; the first matching row wins
+    fn decide(&mut self) {
+        let rtt = self.last_rtt;
+        let reason = if self.power_saving {
+            CompressionReason::PowerSaving
+        } else if rtt.is_zero() {
+            CompressionReason::Initial
+        } else if rtt < self.config.local_rtt_threshold {
+            CompressionReason::LocalLink
+        } else if self.last_loss > self.config.packet_loss_threshold {
+            CompressionReason::HighLoss
+        } else if rtt > self.config.high_rtt_threshold {
+            CompressionReason::HighRtt
+        } else if rtt < self.config.low_rtt_threshold {
+            CompressionReason::LowRtt
+        } else {
+            CompressionReason::Steady
+        };
+
+        let (algorithm, level) = match reason {
+            CompressionReason::LocalLink => (None, 0),
+            // LZ4 costs the least CPU, and has no levels
+            CompressionReason::PowerSaving => (Some(CompressionAlgorithm::Lz4), 0),
+            _ if self.current_level <= 0 => (None, 0),
+            _ => (Some(CompressionAlgorithm::Zstd), self.current_level),
+        };
+        let decision = CompressionDecision { algorithm, level, reason };
+
+        if decision != self.decision {
+            tracing::debug!(?decision, "Adaptive compression decision changed");
+            self.decision = decision;
+        }
+    }
 }
 
 #[cfg(test)]
@@ -101,6 +226,7 @@ mod tests {
             high_rtt_threshold: Duration::from_millis(100),
             low_rtt_threshold: Duration::from_millis(20),
             packet_loss_threshold: 0.1,
+            ..Default::default()
         };
         let mut adaptive = AdaptiveCompression::new(config);
         
@@ -122,4 +248,75 @@ mod tests {
         adaptive.update_metrics(Duration::from_millis(150), 0.0);
         assert_eq!(adaptive.get_level(), 1);
     }
+
+    fn decide(adaptive: &mut AdaptiveCompression, rtt_ms: u64, loss: f64) -> CompressionDecision {
+        adaptive.update_metrics(Duration::from_millis(rtt_ms), loss);
+        adaptive.decision()
+    }
+
+    #[test]
+    fn test_decision_table() {
+        let config = AdaptiveCompressionConfig {
+            update_interval: Duration::ZERO,
+            ..Default::default()
+        };
+        let mut adaptive = AdaptiveCompression::new(config);
+        let zstd = Some(CompressionAlgorithm::Zstd);
+
+        // Before any RTT sample: Zstd at the middle level
+        assert_eq!(adaptive.decision(), CompressionDecision { algorithm: zstd, level: 4, reason: CompressionReason::Initial });
+
+        // Local link: not worth the CPU, whatever the loss
+        assert_eq!(decide(&mut adaptive, 2, 0.0), CompressionDecision { algorithm: None, level: 0, reason: CompressionReason::LocalLink });
+        assert_eq!(decide(&mut adaptive, 2, 0.5).reason, CompressionReason::LocalLink);
+        // The level still moved (up on low RTT, down twice on loss)
+        assert_eq!(adaptive.get_level(), 3);
+
+        // Loss beats RTT
+        assert_eq!(decide(&mut adaptive, 30, 0.2), CompressionDecision { algorithm: zstd, level: 1, reason: CompressionReason::HighLoss });
+        assert_eq!(decide(&mut adaptive, 30, 0.0), CompressionDecision { algorithm: zstd, level: 2, reason: CompressionReason::LowRtt });
+        assert_eq!(decide(&mut adaptive, 100, 0.0), CompressionDecision { algorithm: zstd, level: 2, reason: CompressionReason::Steady });
+        assert_eq!(decide(&mut adaptive, 300, 0.0), CompressionDecision { algorithm: zstd, level: 1, reason: CompressionReason::HighRtt });
+
+        // Down at level 0 nothing is compressed
+        assert_eq!(decide(&mut adaptive, 300, 0.0), CompressionDecision { algorithm: None, level: 0, reason: CompressionReason::HighRtt });
+    }
+
+    #[test]
+    fn test_power_saving_prefers_lz4() {
+        let mut adaptive = AdaptiveCompression::new(AdaptiveCompressionConfig {
+            update_interval: Duration::ZERO,
+            ..Default::default()
+        });
+        decide(&mut adaptive, 100, 0.0);
+
+        // Takes effect at once, without waiting for the next update
+        adaptive.set_power_saving(true);
+        let expected = CompressionDecision { algorithm: Some(CompressionAlgorithm::Lz4), level: 0, reason: CompressionReason::PowerSaving };
+        assert_eq!(adaptive.decision(), expected);
+        // Even on a local link
+        assert_eq!(decide(&mut adaptive, 2, 0.0), expected);
+
+        adaptive.set_power_saving(false);
+        assert_eq!(adaptive.decision().reason, CompressionReason::LocalLink);
+    }
+
+    #[test]
+    fn test_high_loss_drops_level() {
+        let config = AdaptiveCompressionConfig {
+            aggressiveness: 2,
+            update_interval: Duration::ZERO,
+            ..Default::default()
+        };
+        let mut adaptive = AdaptiveCompression::new(config);
+        assert_eq!(adaptive.get_level(), 4);
+
+        // Low RTT but 20% loss: loss wins and moves 2 * aggressiveness levels
+        adaptive.update_metrics(Duration::from_millis(10), 0.2);
+        assert_eq!(adaptive.get_level(), 0);
+
+        // Clamped at min_level
+        adaptive.update_metrics(Duration::from_millis(10), 0.2);
+        assert_eq!(adaptive.get_level(), 0);
+    }
 }
@@ -1 +1,60 @@
 pub mod adaptive;
+
+use anyhow::Result;
+use jsp_core::compression::payload_compression::{CompressionAlgorithm, PayloadCompressor};
+use adaptive::CompressionDecision;
+
+/// `data` compressed as `decision` says, behind a byte naming the
+/// algorithm; None if the decision is not to compress or it would not make
+/// the message smaller. Sent with `FLAG_COMPRESSED`.
+pub(crate) fn compress_payload(decision: CompressionDecision, data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let algorithm = match decision.algorithm {
+        Some(algorithm) => algorithm,
+        None => return Ok(None),
+    };
+    let mut compressor = PayloadCompressor::default();
+    compressor.set_algorithm(algorithm);
+    compressor.set_level(decision.level);
+    Ok(compressor.compress(data)?.map(|compressed| {
+        let mut payload = Vec::with_capacity(1 + compressed.len());
+        payload.push(algorithm.id());
+        payload.extend_from_slice(&compressed);
+        payload
+    }))
+}
+
+/// A message `compress_payload` compressed, restored
+pub(crate) fn decompress_payload(payload: &[u8]) -> Result<Vec<u8>> {
+    let (&id, compressed) = payload.split_first()
+        .ok_or_else(|| anyhow::anyhow!("Compressed message is empty"))?;
+    let algorithm = CompressionAlgorithm::from_id(id)
+        .ok_or_else(|| anyhow::anyhow!("Unknown compression algorithm {}", id))?;
+    PayloadCompressor::default().decompress(compressed, algorithm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use adaptive::CompressionReason;
+
+    #[test]
+    fn test_payload_round_trip() {
+        let data = b"compress me, compress me, compress me ".repeat(40);
+        for algorithm in [CompressionAlgorithm::Lz4, CompressionAlgorithm::Brotli, CompressionAlgorithm::Zstd] {
+            let decision = CompressionDecision { algorithm: Some(algorithm), level: 5, reason: CompressionReason::Steady };
+            let payload = compress_payload(decision, &data).unwrap().unwrap();
+            assert_eq!(payload[0], algorithm.id());
+            assert!(payload.len() < data.len());
+            assert_eq!(decompress_payload(&payload).unwrap(), data);
+        }
+
+        // Nothing to do when the decision is off, or for small messages
+        let off = CompressionDecision { algorithm: None, level: 0, reason: CompressionReason::LocalLink };
+        assert!(compress_payload(off, &data).unwrap().is_none());
+        let zstd = CompressionDecision { algorithm: Some(CompressionAlgorithm::Zstd), level: 3, reason: CompressionReason::Steady };
+        assert!(compress_payload(zstd, b"short").unwrap().is_none());
+
+        assert!(decompress_payload(&[]).is_err());
+        assert!(decompress_payload(&[0xFF, 1, 2]).is_err());
+    }
+}
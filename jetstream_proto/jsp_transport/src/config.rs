@@ -1,21 +1,74 @@
+use std::path::Path;
 use std::time::Duration;
+use anyhow::{bail, Context, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use crate::circuit_breaker::CircuitBreakerConfig;
+use crate::mtu_discovery::{MtuDiscoveryConfig, PACKET_OVERHEAD};
+use crate::memory_pool::RECV_BUFFER_SIZE;
 use crate::ddos_protection::DdosConfig;
+use crate::ticket_store::TicketStore;
+use jsp_core::types::control::SessionTicket;
+use crate::compression::adaptive::AdaptiveCompressionConfig;
+use crate::congestion::CongestionAlgorithm;
+use crate::mptcp::MptcpConfig;
+use crate::webrtc::WebRTCConfig;
+use crate::duration_format;
+use jsp_core::codec::SerializationFormat;
+use jsp_core::qos::QosWeights;
+
+/// Strategy used by `Connection::connect_with_config` to pick a transport
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectStrategy {
+    /// Only attempt UDP (handshake is performed separately)
+    #[default]
+    UdpOnly,
+    /// Race UDP, QUIC and TCP in parallel and keep the first completed handshake
+    Race,
+    /// Try UDP, then QUIC, then TCP, one after another
+    Ordered,
+    /// Open a WebRTC data channel to the peer ID passed as the address,
+    /// through `webrtc.signaling_server` (handshake is performed separately)
+    #[serde(rename = "webrtc")]
+    WebRtc,
+}
 
 /// Connection configuration
-#[derive(Debug, Clone)]
+///
+/// Serializable to JSON or YAML; missing fields take their defaults and
+/// durations are written like "5s" or "250ms".
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ConnectionConfig {
     /// Session timeout duration
+    #[serde(with = "crate::duration_format")]
     pub session_timeout: Duration,
     /// Heartbeat interval
+    #[serde(with = "crate::duration_format")]
     pub heartbeat_interval: Duration,
+    /// Heartbeat interval while the app is in the background
+    #[serde(with = "crate::duration_format")]
+    pub heartbeat_background_interval: Duration,
+    /// Heartbeat interval in the foreground on a cellular network
+    #[serde(with = "crate::duration_format")]
+    pub heartbeat_cellular_interval: Duration,
     /// Number of missed heartbeats before timeout
     pub heartbeat_timeout_count: u32,
     /// Maximum concurrent streams per connection
     pub max_streams: u32,
+    /// Streams of a connection exported with their own `stream_id` label in
+    /// Prometheus; streams opened after that share the `other` series
+    pub max_stream_metric_labels: usize,
     /// Per-connection message rate limit (messages/second)
     pub rate_limit_messages: u32,
     /// Per-connection byte rate limit (bytes/second)
     pub rate_limit_bytes: u64,
+    /// Messages that may be sent at once above the sustained rate
+    /// (None = one second's worth)
+    pub rate_limit_burst_messages: Option<u32>,
+    /// Bytes that may be sent at once above the sustained rate
+    /// (None = one second's worth)
+    pub rate_limit_burst_bytes: Option<u64>,
     /// Optional local address to bind to (e.g. "0.0.0.0:0" or "127.0.0.1:8081")
     pub bind_addr: Option<String>,
     /// Memory pool capacity (number of buffers to keep)
@@ -28,16 +81,106 @@ pub struct ConnectionConfig {
     pub ack_batch_timeout_ms: u64,
     /// Maximum time to wait for message coalescing (in milliseconds, 0 = disabled)
     pub coalescing_window_ms: u64,
+    /// UDP datagrams sent or received per syscall where the platform
+    /// batches them (Linux sendmmsg/recvmmsg, at most `udp::MAX_BATCH`; 1 = one at a time)
+    pub io_batch_size: usize,
+    /// Use UDP segmentation and receive offload (GSO/GRO) where the kernel
+    /// supports them; receive buffers grow to 64 KB each
+    pub enable_gso: bool,
     /// STUN servers for NAT discovery (e.g., ["stun.l.google.com:19302"])
     pub stun_servers: Vec<String>,
     /// STUN request timeout
+    #[serde(with = "crate::duration_format")]
     pub stun_timeout: Duration,
     /// STUN cache TTL (how long to cache discovered address)
+    #[serde(with = "crate::duration_format")]
     pub stun_cache_ttl: Duration,
-    /// Enable header compression (default: true)
+    /// Enable header compression (default: true); only used by sessions
+    /// that negotiate CBOR headers
     pub enable_header_compression: bool,
+    /// Compress messages as `adaptive_compression_config` decides (default:
+    /// false); both peers must run a version that understands compressed
+    /// messages
+    pub enable_payload_compression: bool,
+    /// Header formats to offer (client) or accept (server), most preferred
+    /// first; CBOR is the fallback when the peers share none. Enable
+    /// FlatBuffers only against peers on this version, as older ones offer
+    /// it without speaking it
+    pub serialization_formats: Vec<SerializationFormat>,
     /// Multi-hop tunnel configuration (optional)
     pub multihop_config: Option<crate::multihop::MultiHopConfig>,
+    /// Transport selection strategy on connect
+    pub connect_strategy: ConnectStrategy,
+    /// Handshake timeout for racing/ordered connects (per attempt when ordered)
+    #[serde(with = "crate::duration_format")]
+    pub connect_timeout: Duration,
+    /// Payloads larger than this are split into fragments (must fit in one
+    /// datagram); with `mtu_discovery` on, the discovered path MTU sizes them
+    pub max_fragment_size: usize,
+    /// How long incomplete BestEffort messages are kept for reassembly
+    #[serde(with = "crate::duration_format")]
+    pub reassembly_timeout: Duration,
+    /// Received bytes held for the application before the peer must stop
+    /// sending; advertised to it as the receive window in every ACK, and the
+    /// cap on fragments held for reassembly
+    pub max_receive_buffer: usize,
+    /// Packets the send queue holds before shedding BestEffort data and
+    /// pushing back on other sends
+    pub send_queue_max_packets: usize,
+    /// Stream payload bytes the send queue holds, with the same policy
+    pub send_queue_max_bytes: usize,
+    /// Share of send opportunities per stream priority while the send queue
+    /// is backed up; every level gets at least its share, so bulk streams
+    /// keep moving under a flood of higher-priority data
+    pub qos_weights: QosWeights,
+    /// Wait in the send queue that raises a packet one priority level, so
+    /// it goes out within a few intervals under any flood of higher-priority
+    /// data (None = weights alone decide)
+    #[serde(with = "crate::duration_format::option")]
+    pub priority_aging_interval: Option<Duration>,
+    /// When repeated send failures make the connection refuse sends with
+    /// `TransportError::CircuitOpen`, and how it probes its way back
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// Probing the path for the largest datagram it carries, which then
+    /// sizes fragments, coalesced datagrams and the congestion window's MSS
+    pub mtu_discovery: MtuDiscoveryConfig,
+    /// Congestion controller driving the send window
+    pub congestion_algorithm: CongestionAlgorithm,
+    /// Maximum segment size the congestion controller counts windows in (bytes)
+    pub mss: usize,
+    /// Server-side store of issued session tickets (None = resumption disabled)
+    #[serde(skip)]
+    pub ticket_store: Option<TicketStore>,
+    /// Ticket from an earlier connection's `session_ticket()`; `connect_with_config`
+    /// resumes with it over UDP instead of a full handshake
+    #[serde(skip)]
+    pub session_ticket: Option<SessionTicket>,
+    /// Thresholds and aggressiveness for adaptive compression
+    pub adaptive_compression_config: AdaptiveCompressionConfig,
+    /// Multi-path subflows used by the sender (disabled by default)
+    pub mptcp_config: MptcpConfig,
+    /// Encrypt stream data with a Double Ratchet negotiated in the handshake
+    /// (both sides must enable it; not used for resumed sessions)
+    pub enable_double_ratchet: bool,
+    /// Seal stream data with the session key and rotate it with KEY_UPDATE
+    /// frames (both sides must enable it)
+    pub enable_key_updates: bool,
+    /// Rotate the session key after this long in one epoch (None = no time limit)
+    #[serde(with = "crate::duration_format::option")]
+    pub key_update_interval: Option<Duration>,
+    /// Rotate the session key after sealing this many bytes in one epoch
+    /// (None = no volume limit)
+    pub key_update_bytes: Option<u64>,
+    /// How long the client waits for a ServerHello before resending its ClientHello
+    #[serde(with = "crate::duration_format")]
+    pub handshake_retry_interval: Duration,
+    /// ClientHello retransmissions before the handshake fails
+    pub handshake_max_retries: u32,
+    /// Move a client to a new local socket, and migrate, when the current
+    /// one dies or its interface goes away
+    pub auto_rebind: bool,
+    /// Signaling, ICE and data channel settings for `ConnectStrategy::WebRtc`
+    pub webrtc: WebRTCConfig,
 }
 
 impl Default for ConnectionConfig {
@@ -45,21 +188,55 @@ impl Default for ConnectionConfig {
         Self {
             session_timeout: Duration::from_secs(30),
             heartbeat_interval: Duration::from_secs(5),
+            heartbeat_background_interval: Duration::from_secs(30),
+            heartbeat_cellular_interval: Duration::from_secs(15),
             heartbeat_timeout_count: 3,
             max_streams: 100,
+            max_stream_metric_labels: 16,
             rate_limit_messages: 100,
             rate_limit_bytes: 1_048_576, // 1 MB/s
+            rate_limit_burst_messages: None,
+            rate_limit_burst_bytes: None,
             bind_addr: None,
             pool_capacity: 100, // Keep up to 100 buffers
             pool_max_packet_size: 65536, // 64 KB max packet size
             ack_batch_size: 10, // Batch up to 10 ACKs
             ack_batch_timeout_ms: 10, // Wait max 10ms
             coalescing_window_ms: 0, // Disabled by default
+            io_batch_size: 32,
+            enable_gso: false,
             stun_servers: vec![], // No STUN servers by default
             stun_timeout: Duration::from_secs(5),
             stun_cache_ttl: Duration::from_secs(300), // 5 minutes
             enable_header_compression: true,
+            enable_payload_compression: false,
+            serialization_formats: vec![SerializationFormat::Cbor],
             multihop_config: None, // Multi-hop disabled by default
+            connect_strategy: ConnectStrategy::UdpOnly,
+            connect_timeout: Duration::from_secs(5),
+            max_fragment_size: 1200, // Safe for typical 1500 byte MTU paths
+            reassembly_timeout: Duration::from_secs(5),
+            max_receive_buffer: 4 * 1024 * 1024, // 4 MB
+            send_queue_max_packets: 8192,
+            send_queue_max_bytes: 8 * 1024 * 1024, // 8 MB
+            qos_weights: QosWeights::default(), // 8:4:2:1
+            priority_aging_interval: Some(Duration::from_millis(100)),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            mtu_discovery: MtuDiscoveryConfig::default(), // Off: fragments are `max_fragment_size`
+            congestion_algorithm: CongestionAlgorithm::NewReno,
+            mss: 1200,
+            ticket_store: None, // Session tickets disabled by default
+            session_ticket: None,
+            adaptive_compression_config: AdaptiveCompressionConfig::default(),
+            mptcp_config: MptcpConfig::default(),
+            enable_double_ratchet: false,
+            enable_key_updates: false,
+            key_update_interval: None, // Rotate only on demand
+            key_update_bytes: None,
+            handshake_retry_interval: Duration::from_millis(500),
+            handshake_max_retries: 5,
+            auto_rebind: true,
+            webrtc: WebRTCConfig::default(),
         }
     }
 }
@@ -68,6 +245,181 @@ impl ConnectionConfig {
     pub fn builder() -> ConnectionConfigBuilder {
         ConnectionConfigBuilder::default()
     }
+
+    /// Load and validate a config file, JSON or YAML by extension
+    ///
+    /// Runtime state (`ticket_store`, `session_ticket`) is not part of the file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let config: Self = read_config_file(path.as_ref())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Write the config as JSON or YAML, by extension
+    pub fn to_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        write_config_file(self, path.as_ref())
+    }
+
+    /// Check values that would make connections misbehave, naming every offending field
+    pub fn validate(&self) -> Result<()> {
+        into_result(self.problems())
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut require = |ok: bool, problem: String| {
+            if !ok {
+                problems.push(problem);
+            }
+        };
+
+        for (field, value) in [
+            ("session_timeout", self.session_timeout),
+            ("heartbeat_interval", self.heartbeat_interval),
+            ("heartbeat_background_interval", self.heartbeat_background_interval),
+            ("heartbeat_cellular_interval", self.heartbeat_cellular_interval),
+            ("connect_timeout", self.connect_timeout),
+            ("handshake_retry_interval", self.handshake_retry_interval),
+        ] {
+            require(!value.is_zero(), format!("`{}` must be greater than zero", field));
+        }
+        for (field, value) in [
+            ("heartbeat_timeout_count", self.heartbeat_timeout_count as usize),
+            ("max_streams", self.max_streams as usize),
+            ("pool_capacity", self.pool_capacity),
+            ("ack_batch_size", self.ack_batch_size),
+            ("io_batch_size", self.io_batch_size),
+            ("max_fragment_size", self.max_fragment_size),
+            ("max_receive_buffer", self.max_receive_buffer),
+            ("send_queue_max_packets", self.send_queue_max_packets),
+            ("send_queue_max_bytes", self.send_queue_max_bytes),
+            ("mss", self.mss),
+        ] {
+            require(value > 0, format!("`{}` must be greater than zero", field));
+        }
+
+        require(
+            self.heartbeat_interval < self.session_timeout,
+            format!(
+                "`heartbeat_interval` ({}) must be shorter than `session_timeout` ({})",
+                duration_format::format(self.heartbeat_interval),
+                duration_format::format(self.session_timeout)
+            ),
+        );
+        for (field, burst) in [
+            ("rate_limit_burst_messages", self.rate_limit_burst_messages.map(u64::from)),
+            ("rate_limit_burst_bytes", self.rate_limit_burst_bytes),
+        ] {
+            require(burst != Some(0), format!("`{}` must be greater than zero (or null for one second's worth)", field));
+        }
+
+        require(
+            Duration::from_millis(self.ack_batch_timeout_ms) <= self.heartbeat_interval,
+            format!(
+                "`ack_batch_timeout_ms` ({}ms) must not exceed `heartbeat_interval` ({})",
+                self.ack_batch_timeout_ms,
+                duration_format::format(self.heartbeat_interval)
+            ),
+        );
+        require(
+            self.pool_max_packet_size >= self.max_fragment_size,
+            format!(
+                "`pool_max_packet_size` ({}) must hold a full fragment (`max_fragment_size` = {})",
+                self.pool_max_packet_size, self.max_fragment_size
+            ),
+        );
+
+        let compression = &self.adaptive_compression_config;
+        require(
+            compression.min_level <= compression.max_level,
+            format!(
+                "`adaptive_compression_config.min_level` ({}) must not exceed `max_level` ({})",
+                compression.min_level, compression.max_level
+            ),
+        );
+        let weights = &self.qos_weights;
+        require(
+            weights.system > 0 && weights.media > 0 && weights.chat > 0 && weights.bulk > 0,
+            "`qos_weights` must all be greater than zero".to_string(),
+        );
+        require(
+            self.priority_aging_interval.is_none_or(|interval| !interval.is_zero()),
+            "`priority_aging_interval` must be greater than zero".to_string(),
+        );
+        let breaker = &self.circuit_breaker;
+        require(
+            breaker.failure_threshold > 0,
+            "`circuit_breaker.failure_threshold` must be greater than zero".to_string(),
+        );
+        require(
+            !breaker.reset_timeout.is_zero(),
+            "`circuit_breaker.reset_timeout` must be greater than zero".to_string(),
+        );
+        require(
+            breaker.success_threshold > 0 && breaker.success_threshold <= breaker.half_open_max_probes,
+            format!(
+                "`circuit_breaker.success_threshold` ({}) must be between 1 and `half_open_max_probes` ({})",
+                breaker.success_threshold, breaker.half_open_max_probes
+            ),
+        );
+        let mtu = &self.mtu_discovery;
+        require(
+            mtu.base_mtu > PACKET_OVERHEAD && mtu.base_mtu <= mtu.max_mtu,
+            format!(
+                "`mtu_discovery.base_mtu` ({}) must be between {} and `max_mtu` ({})",
+                mtu.base_mtu, PACKET_OVERHEAD + 1, mtu.max_mtu
+            ),
+        );
+        require(
+            mtu.max_mtu <= RECV_BUFFER_SIZE.min(self.pool_max_packet_size),
+            format!(
+                "`mtu_discovery.max_mtu` ({}) must fit in a receive buffer ({} bytes) and `pool_max_packet_size` ({})",
+                mtu.max_mtu, RECV_BUFFER_SIZE, self.pool_max_packet_size
+            ),
+        );
+        require(
+            !mtu.raise_interval.is_zero(),
+            "`mtu_discovery.raise_interval` must be greater than zero".to_string(),
+        );
+        require(
+            !self.serialization_formats.is_empty(),
+            "`serialization_formats` must list at least one format".to_string(),
+        );
+        require(
+            !self.mptcp_config.enabled || self.mptcp_config.max_subflows > 0,
+            "`mptcp_config.max_subflows` must be greater than zero when MPTCP is enabled".to_string(),
+        );
+        require(
+            self.key_update_interval.is_none_or(|interval| !interval.is_zero()),
+            "`key_update_interval` must be greater than zero".to_string(),
+        );
+        require(
+            self.key_update_bytes != Some(0),
+            "`key_update_bytes` must be greater than zero".to_string(),
+        );
+        require(
+            self.enable_key_updates || (self.key_update_interval.is_none() && self.key_update_bytes.is_none()),
+            "`key_update_interval` and `key_update_bytes` require `enable_key_updates`".to_string(),
+        );
+
+        if self.connect_strategy == ConnectStrategy::WebRtc {
+            require(
+                self.webrtc.signaling_server.is_some(),
+                "`webrtc.signaling_server` is required by the `webrtc` connect strategy".to_string(),
+            );
+            if let Err(e) = self.webrtc.validate() {
+                problems.push(format!("`webrtc`: {}", e));
+            }
+        }
+
+        if let Some(multihop) = &self.multihop_config {
+            if let Err(e) = multihop.validate() {
+                problems.push(format!("`multihop_config`: {}", e));
+            }
+        }
+
+        problems
+    }
 }
 
 /// Builder for ConnectionConfig
@@ -75,21 +427,55 @@ impl ConnectionConfig {
 pub struct ConnectionConfigBuilder {
     session_timeout: Option<Duration>,
     heartbeat_interval: Option<Duration>,
+    heartbeat_background_interval: Option<Duration>,
+    heartbeat_cellular_interval: Option<Duration>,
     heartbeat_timeout_count: Option<u32>,
     max_streams: Option<u32>,
+    max_stream_metric_labels: Option<usize>,
     rate_limit_messages: Option<u32>,
     rate_limit_bytes: Option<u64>,
+    rate_limit_burst_messages: Option<u32>,
+    rate_limit_burst_bytes: Option<u64>,
     bind_addr: Option<String>,
     pool_capacity: Option<usize>,
     pool_max_packet_size: Option<usize>,
     ack_batch_size: Option<usize>,
     ack_batch_timeout_ms: Option<u64>,
     coalescing_window_ms: Option<u64>,
+    io_batch_size: Option<usize>,
+    enable_gso: Option<bool>,
     stun_servers: Option<Vec<String>>,
     stun_timeout: Option<Duration>,
     stun_cache_ttl: Option<Duration>,
     enable_header_compression: Option<bool>,
+    enable_payload_compression: Option<bool>,
+    serialization_formats: Option<Vec<SerializationFormat>>,
     multihop_config: Option<Option<crate::multihop::MultiHopConfig>>,
+    connect_strategy: Option<ConnectStrategy>,
+    connect_timeout: Option<Duration>,
+    max_fragment_size: Option<usize>,
+    reassembly_timeout: Option<Duration>,
+    max_receive_buffer: Option<usize>,
+    send_queue_max_packets: Option<usize>,
+    send_queue_max_bytes: Option<usize>,
+    qos_weights: Option<QosWeights>,
+    priority_aging_interval: Option<Option<Duration>>,
+    circuit_breaker: Option<CircuitBreakerConfig>,
+    mtu_discovery: Option<MtuDiscoveryConfig>,
+    congestion_algorithm: Option<CongestionAlgorithm>,
+    mss: Option<usize>,
+    ticket_store: Option<TicketStore>,
+    session_ticket: Option<SessionTicket>,
+    adaptive_compression_config: Option<AdaptiveCompressionConfig>,
+    mptcp_config: Option<MptcpConfig>,
+    enable_double_ratchet: Option<bool>,
+    enable_key_updates: Option<bool>,
+    key_update_interval: Option<Duration>,
+    key_update_bytes: Option<u64>,
+    handshake_retry_interval: Option<Duration>,
+    handshake_max_retries: Option<u32>,
+    auto_rebind: Option<bool>,
+    webrtc: Option<WebRTCConfig>,
 }
 
 impl ConnectionConfigBuilder {
@@ -103,6 +489,16 @@ impl ConnectionConfigBuilder {
         self
     }
 
+    pub fn heartbeat_background_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_background_interval = Some(interval);
+        self
+    }
+
+    pub fn heartbeat_cellular_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_cellular_interval = Some(interval);
+        self
+    }
+
     pub fn heartbeat_timeout_count(mut self, count: u32) -> Self {
         self.heartbeat_timeout_count = Some(count);
         self
@@ -113,6 +509,11 @@ impl ConnectionConfigBuilder {
         self
     }
 
+    pub fn max_stream_metric_labels(mut self, max: usize) -> Self {
+        self.max_stream_metric_labels = Some(max);
+        self
+    }
+
     pub fn rate_limit_messages(mut self, limit: u32) -> Self {
         self.rate_limit_messages = Some(limit);
         self
@@ -123,6 +524,16 @@ impl ConnectionConfigBuilder {
         self
     }
 
+    pub fn rate_limit_burst_messages(mut self, burst: u32) -> Self {
+        self.rate_limit_burst_messages = Some(burst);
+        self
+    }
+
+    pub fn rate_limit_burst_bytes(mut self, burst: u64) -> Self {
+        self.rate_limit_burst_bytes = Some(burst);
+        self
+    }
+
     pub fn bind_addr(mut self, addr: String) -> Self {
         self.bind_addr = Some(addr);
         self
@@ -153,6 +564,16 @@ impl ConnectionConfigBuilder {
         self
     }
 
+    pub fn io_batch_size(mut self, size: usize) -> Self {
+        self.io_batch_size = Some(size);
+        self
+    }
+
+    pub fn enable_gso(mut self, enable: bool) -> Self {
+        self.enable_gso = Some(enable);
+        self
+    }
+
     pub fn stun_servers(mut self, servers: Vec<String>) -> Self {
         self.stun_servers = Some(servers);
         self
@@ -173,37 +594,211 @@ impl ConnectionConfigBuilder {
         self
     }
 
+    pub fn enable_payload_compression(mut self, enable: bool) -> Self {
+        self.enable_payload_compression = Some(enable);
+        self
+    }
+
+    pub fn serialization_formats(mut self, formats: Vec<SerializationFormat>) -> Self {
+        self.serialization_formats = Some(formats);
+        self
+    }
+
     pub fn multihop_config(mut self, config: Option<crate::multihop::MultiHopConfig>) -> Self {
         self.multihop_config = Some(config);
         self
     }
 
+    pub fn connect_strategy(mut self, strategy: ConnectStrategy) -> Self {
+        self.connect_strategy = Some(strategy);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn max_fragment_size(mut self, size: usize) -> Self {
+        self.max_fragment_size = Some(size);
+        self
+    }
+
+    pub fn reassembly_timeout(mut self, timeout: Duration) -> Self {
+        self.reassembly_timeout = Some(timeout);
+        self
+    }
+
+    pub fn max_receive_buffer(mut self, bytes: usize) -> Self {
+        self.max_receive_buffer = Some(bytes);
+        self
+    }
+
+    pub fn send_queue_max_packets(mut self, packets: usize) -> Self {
+        self.send_queue_max_packets = Some(packets);
+        self
+    }
+
+    pub fn send_queue_max_bytes(mut self, bytes: usize) -> Self {
+        self.send_queue_max_bytes = Some(bytes);
+        self
+    }
+
+    /// Set the send queue's share of send opportunities per stream priority
+    pub fn qos_weights(mut self, weights: QosWeights) -> Self {
+        self.qos_weights = Some(weights);
+        self
+    }
+
+    /// Set the wait that raises a queued packet one priority level (None = no aging)
+    pub fn priority_aging_interval(mut self, interval: Option<Duration>) -> Self {
+        self.priority_aging_interval = Some(interval);
+        self
+    }
+
+    /// Set when the circuit breaker opens and how many probes it sends half-open
+    pub fn circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.circuit_breaker = Some(config);
+        self
+    }
+
+    /// Set whether and how far the path MTU is probed
+    pub fn mtu_discovery(mut self, config: MtuDiscoveryConfig) -> Self {
+        self.mtu_discovery = Some(config);
+        self
+    }
+
+    pub fn congestion_algorithm(mut self, algorithm: CongestionAlgorithm) -> Self {
+        self.congestion_algorithm = Some(algorithm);
+        self
+    }
+
+    pub fn mss(mut self, mss: usize) -> Self {
+        self.mss = Some(mss);
+        self
+    }
+
+    pub fn ticket_store(mut self, store: TicketStore) -> Self {
+        self.ticket_store = Some(store);
+        self
+    }
+
+    pub fn session_ticket(mut self, ticket: SessionTicket) -> Self {
+        self.session_ticket = Some(ticket);
+        self
+    }
+
+    pub fn adaptive_compression_config(mut self, config: AdaptiveCompressionConfig) -> Self {
+        self.adaptive_compression_config = Some(config);
+        self
+    }
+
+    pub fn mptcp_config(mut self, config: MptcpConfig) -> Self {
+        self.mptcp_config = Some(config);
+        self
+    }
+
+    pub fn enable_double_ratchet(mut self, enabled: bool) -> Self {
+        self.enable_double_ratchet = Some(enabled);
+        self
+    }
+
+    pub fn enable_key_updates(mut self, enabled: bool) -> Self {
+        self.enable_key_updates = Some(enabled);
+        self
+    }
+
+    pub fn key_update_interval(mut self, interval: Duration) -> Self {
+        self.key_update_interval = Some(interval);
+        self
+    }
+
+    pub fn key_update_bytes(mut self, bytes: u64) -> Self {
+        self.key_update_bytes = Some(bytes);
+        self
+    }
+
+    pub fn handshake_retry_interval(mut self, interval: Duration) -> Self {
+        self.handshake_retry_interval = Some(interval);
+        self
+    }
+
+    pub fn handshake_max_retries(mut self, retries: u32) -> Self {
+        self.handshake_max_retries = Some(retries);
+        self
+    }
+
+    pub fn auto_rebind(mut self, enabled: bool) -> Self {
+        self.auto_rebind = Some(enabled);
+        self
+    }
+
+    pub fn webrtc(mut self, config: WebRTCConfig) -> Self {
+        self.webrtc = Some(config);
+        self
+    }
+
     pub fn build(self) -> ConnectionConfig {
         let default = ConnectionConfig::default();
         ConnectionConfig {
             session_timeout: self.session_timeout.unwrap_or(default.session_timeout),
             heartbeat_interval: self.heartbeat_interval.unwrap_or(default.heartbeat_interval),
+            heartbeat_background_interval: self.heartbeat_background_interval.unwrap_or(default.heartbeat_background_interval),
+            heartbeat_cellular_interval: self.heartbeat_cellular_interval.unwrap_or(default.heartbeat_cellular_interval),
             heartbeat_timeout_count: self.heartbeat_timeout_count.unwrap_or(default.heartbeat_timeout_count),
             max_streams: self.max_streams.unwrap_or(default.max_streams),
+            max_stream_metric_labels: self.max_stream_metric_labels.unwrap_or(default.max_stream_metric_labels),
             rate_limit_messages: self.rate_limit_messages.unwrap_or(default.rate_limit_messages),
             rate_limit_bytes: self.rate_limit_bytes.unwrap_or(default.rate_limit_bytes),
+            rate_limit_burst_messages: self.rate_limit_burst_messages.or(default.rate_limit_burst_messages),
+            rate_limit_burst_bytes: self.rate_limit_burst_bytes.or(default.rate_limit_burst_bytes),
             bind_addr: self.bind_addr.or(default.bind_addr),
             pool_capacity: self.pool_capacity.unwrap_or(default.pool_capacity),
             pool_max_packet_size: self.pool_max_packet_size.unwrap_or(default.pool_max_packet_size),
             ack_batch_size: self.ack_batch_size.unwrap_or(default.ack_batch_size),
             ack_batch_timeout_ms: self.ack_batch_timeout_ms.unwrap_or(default.ack_batch_timeout_ms),
             coalescing_window_ms: self.coalescing_window_ms.unwrap_or(default.coalescing_window_ms),
+            io_batch_size: self.io_batch_size.unwrap_or(default.io_batch_size),
+            enable_gso: self.enable_gso.unwrap_or(default.enable_gso),
             stun_servers: self.stun_servers.unwrap_or(default.stun_servers),
             stun_timeout: self.stun_timeout.unwrap_or(default.stun_timeout),
             stun_cache_ttl: self.stun_cache_ttl.unwrap_or(default.stun_cache_ttl),
             enable_header_compression: self.enable_header_compression.unwrap_or(default.enable_header_compression),
+            enable_payload_compression: self.enable_payload_compression.unwrap_or(default.enable_payload_compression),
+            serialization_formats: self.serialization_formats.unwrap_or(default.serialization_formats),
             multihop_config: self.multihop_config.unwrap_or(default.multihop_config),
+            connect_strategy: self.connect_strategy.unwrap_or(default.connect_strategy),
+            connect_timeout: self.connect_timeout.unwrap_or(default.connect_timeout),
+            max_fragment_size: self.max_fragment_size.unwrap_or(default.max_fragment_size),
+            reassembly_timeout: self.reassembly_timeout.unwrap_or(default.reassembly_timeout),
+            max_receive_buffer: self.max_receive_buffer.unwrap_or(default.max_receive_buffer),
+            send_queue_max_packets: self.send_queue_max_packets.unwrap_or(default.send_queue_max_packets),
+            send_queue_max_bytes: self.send_queue_max_bytes.unwrap_or(default.send_queue_max_bytes),
+            qos_weights: self.qos_weights.unwrap_or(default.qos_weights),
+            priority_aging_interval: self.priority_aging_interval.unwrap_or(default.priority_aging_interval),
+            circuit_breaker: self.circuit_breaker.unwrap_or(default.circuit_breaker),
+            mtu_discovery: self.mtu_discovery.unwrap_or(default.mtu_discovery),
+            congestion_algorithm: self.congestion_algorithm.unwrap_or(default.congestion_algorithm),
+            mss: self.mss.unwrap_or(default.mss),
+            ticket_store: self.ticket_store.or(default.ticket_store),
+            session_ticket: self.session_ticket.or(default.session_ticket),
+            adaptive_compression_config: self.adaptive_compression_config.unwrap_or(default.adaptive_compression_config),
+            mptcp_config: self.mptcp_config.unwrap_or(default.mptcp_config),
+            enable_double_ratchet: self.enable_double_ratchet.unwrap_or(default.enable_double_ratchet),
+            enable_key_updates: self.enable_key_updates.unwrap_or(default.enable_key_updates),
+            key_update_interval: self.key_update_interval.or(default.key_update_interval),
+            key_update_bytes: self.key_update_bytes.or(default.key_update_bytes),
+            handshake_retry_interval: self.handshake_retry_interval.unwrap_or(default.handshake_retry_interval),
+            handshake_max_retries: self.handshake_max_retries.unwrap_or(default.handshake_max_retries),
+            auto_rebind: self.auto_rebind.unwrap_or(default.auto_rebind),
+            webrtc: self.webrtc.unwrap_or(default.webrtc),
         }
     }
 }
 
 /// Server configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ServerConfig {
     /// Connection configuration (applies to all connections)
     pub connection: ConnectionConfig,
@@ -214,7 +809,14 @@ pub struct ServerConfig {
     /// DDoS protection configuration
     pub ddos_config: DdosConfig,
     /// Session cleanup interval
+    #[serde(with = "crate::duration_format")]
     pub cleanup_interval: Duration,
+    /// Concurrent sessions allowed from one source IP (None = unlimited)
+    pub max_sessions_per_ip: Option<u32>,
+    /// Send stats frames to monitors subscribed on `STATS_STREAM_ID` this
+    /// often (None = the stats stream is off)
+    #[serde(with = "crate::duration_format::option")]
+    pub stats_stream_interval: Option<Duration>,
 }
 
 impl Default for ServerConfig {
@@ -225,6 +827,8 @@ impl Default for ServerConfig {
             global_rate_limit_bytes: Some(100_000_000), // 100 MB/s
             ddos_config: DdosConfig::default(),
             cleanup_interval: Duration::from_secs(10),
+            max_sessions_per_ip: None,
+            stats_stream_interval: None,
         }
     }
 }
@@ -233,6 +837,102 @@ impl ServerConfig {
     pub fn builder() -> ServerConfigBuilder {
         ServerConfigBuilder::default()
     }
+
+    /// Load and validate a config file, JSON or YAML by extension
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let config: Self = read_config_file(path.as_ref())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Write the config as JSON or YAML, by extension
+    pub fn to_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        write_config_file(self, path.as_ref())
+    }
+
+    /// Check the server settings and the per-connection ones
+    pub fn validate(&self) -> Result<()> {
+        let mut problems: Vec<String> = self.connection.problems()
+            .into_iter()
+            .map(|problem| format!("connection: {}", problem))
+            .collect();
+
+        for (field, limit) in [
+            ("global_rate_limit_messages", self.global_rate_limit_messages.map(u64::from)),
+            ("global_rate_limit_bytes", self.global_rate_limit_bytes),
+            ("max_sessions_per_ip", self.max_sessions_per_ip.map(u64::from)),
+        ] {
+            if limit == Some(0) {
+                problems.push(format!("`{}` must be greater than zero (or null for no limit)", field));
+            }
+        }
+        let ddos = &self.ddos_config;
+        if ddos.max_packets_per_ip == 0 || ddos.max_handshakes_per_ip == 0
+            || ddos.max_packets_per_subnet == 0 || ddos.max_handshakes_per_subnet == 0
+        {
+            problems.push("`ddos_config` limits must be greater than zero".to_string());
+        }
+        if ddos.ipv4_subnet_prefix.is_some_and(|prefix| prefix > 32) {
+            problems.push("`ddos_config.ipv4_subnet_prefix` must be at most 32".to_string());
+        }
+        if ddos.ipv6_subnet_prefix.is_some_and(|prefix| prefix > 128) {
+            problems.push("`ddos_config.ipv6_subnet_prefix` must be at most 128".to_string());
+        }
+        if self.cleanup_interval.is_zero() || ddos.cleanup_interval.is_zero() {
+            problems.push("`cleanup_interval` must be greater than zero".to_string());
+        }
+        if ddos.retry_token_lifetime.is_zero() {
+            problems.push("`ddos_config.retry_token_lifetime` must be greater than zero".to_string());
+        }
+        if self.stats_stream_interval.is_some_and(|interval| interval.is_zero()) {
+            problems.push("`stats_stream_interval` must be greater than zero (or null to turn the stats stream off)".to_string());
+        }
+
+        into_result(problems)
+    }
+}
+
+/// Config file formats, picked by extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConfigFormat {
+    Json,
+    Yaml,
+}
+
+impl ConfigFormat {
+    fn of(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Ok(ConfigFormat::Json),
+            Some("yaml") | Some("yml") => Ok(ConfigFormat::Yaml),
+            _ => bail!("Unsupported config file {}: expected a .json, .yaml or .yml extension", path.display()),
+        }
+    }
+}
+
+fn read_config_file<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let format = ConfigFormat::of(path)?;
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    let config = match format {
+        ConfigFormat::Json => serde_json::from_str(&content).map_err(anyhow::Error::from),
+        ConfigFormat::Yaml => serde_yaml::from_str(&content).map_err(anyhow::Error::from),
+    };
+    config.with_context(|| format!("Failed to parse config file {}", path.display()))
+}
+
+fn write_config_file<T: Serialize>(config: &T, path: &Path) -> Result<()> {
+    let content = match ConfigFormat::of(path)? {
+        ConfigFormat::Json => serde_json::to_string_pretty(config)? + "\n",
+        ConfigFormat::Yaml => serde_yaml::to_string(config)?,
+    };
+    std::fs::write(path, content).with_context(|| format!("Failed to write config file {}", path.display()))
+}
+
+fn into_result(problems: Vec<String>) -> Result<()> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+    bail!("Invalid configuration:\n  - {}", problems.join("\n  - "))
 }
 
 /// Builder for ServerConfig
@@ -243,6 +943,8 @@ pub struct ServerConfigBuilder {
     global_rate_limit_bytes: Option<Option<u64>>,
     ddos_config: Option<DdosConfig>,
     cleanup_interval: Option<Duration>,
+    max_sessions_per_ip: Option<Option<u32>>,
+    stats_stream_interval: Option<Option<Duration>>,
 }
 
 impl ServerConfigBuilder {
@@ -271,6 +973,16 @@ impl ServerConfigBuilder {
         self
     }
 
+    pub fn max_sessions_per_ip(mut self, limit: Option<u32>) -> Self {
+        self.max_sessions_per_ip = Some(limit);
+        self
+    }
+
+    pub fn stats_stream_interval(mut self, interval: Option<Duration>) -> Self {
+        self.stats_stream_interval = Some(interval);
+        self
+    }
+
     pub fn build(self) -> ServerConfig {
         let default = ServerConfig::default();
         ServerConfig {
@@ -279,6 +991,8 @@ impl ServerConfigBuilder {
             global_rate_limit_bytes: self.global_rate_limit_bytes.unwrap_or(default.global_rate_limit_bytes),
             ddos_config: self.ddos_config.unwrap_or(default.ddos_config),
             cleanup_interval: self.cleanup_interval.unwrap_or(default.cleanup_interval),
+            max_sessions_per_ip: self.max_sessions_per_ip.unwrap_or(default.max_sessions_per_ip),
+            stats_stream_interval: self.stats_stream_interval.unwrap_or(default.stats_stream_interval),
         }
     }
 }
@@ -318,4 +1032,147 @@ mod tests {
         assert_eq!(config.global_rate_limit_messages, Some(5000));
         assert_eq!(config.cleanup_interval, Duration::from_secs(5));
     }
+
+    #[test]
+    fn test_config_golden_files_round_trip() {
+        let yaml = include_str!("../tests/fixtures/connection_config.yaml");
+        let json = include_str!("../tests/fixtures/connection_config.json");
+
+        let from_yaml: ConnectionConfig = serde_yaml::from_str(yaml).unwrap();
+        let from_json: ConnectionConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(from_yaml.stun_cache_ttl, Duration::from_secs(300));
+        assert_eq!(from_json.handshake_retry_interval, Duration::from_millis(500));
+
+        assert_eq!(serde_yaml::to_string(&from_yaml).unwrap(), yaml);
+        assert_eq!(serde_json::to_string_pretty(&from_json).unwrap() + "\n", json);
+        assert_eq!(serde_yaml::to_string(&ConnectionConfig::default()).unwrap(), yaml);
+    }
+
+    #[test]
+    fn test_partial_config_uses_defaults() {
+        let config: ConnectionConfig = serde_yaml::from_str("heartbeat_interval: 250ms\nconnect_strategy: race\n").unwrap();
+        assert_eq!(config.heartbeat_interval, Duration::from_millis(250));
+        assert_eq!(config.connect_strategy, ConnectStrategy::Race);
+        assert_eq!(config.session_timeout, Duration::from_secs(30));
+        assert!(config.validate().is_ok());
+
+        let config: ConnectionConfig = serde_yaml::from_str("congestion_algorithm: bbr\nmss: 1400\n").unwrap();
+        assert_eq!(config.congestion_algorithm, CongestionAlgorithm::Bbr);
+        assert_eq!(config.mss, 1400);
+
+        let config: ConnectionConfig = serde_yaml::from_str("priority_aging_interval: null\n").unwrap();
+        assert_eq!(config.priority_aging_interval, None);
+        assert!(config.validate().is_ok());
+        let config = ConnectionConfig::builder().priority_aging_interval(Some(Duration::ZERO)).build();
+        assert!(config.validate().unwrap_err().to_string().contains("`priority_aging_interval`"));
+
+        let config: ConnectionConfig = serde_yaml::from_str("circuit_breaker:\n  half_open_max_probes: 1\n").unwrap();
+        assert_eq!(config.circuit_breaker.failure_threshold, 5);
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("`circuit_breaker.success_threshold` (2) must be between 1 and `half_open_max_probes` (1)"), "{}", err);
+
+        let config: ConnectionConfig = serde_yaml::from_str("mtu_discovery:\n  enabled: true\n  max_mtu: 4000\n").unwrap();
+        assert_eq!(config.mtu_discovery.base_mtu, 1200);
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("`mtu_discovery.max_mtu` (4000) must fit in a receive buffer"), "{}", err);
+
+        let err = serde_yaml::from_str::<ConnectionConfig>("session_timeout: 30\n").unwrap_err();
+        assert!(err.to_string().contains("missing unit"), "{}", err);
+    }
+
+    #[test]
+    fn test_config_file_round_trip() {
+        let dir = std::env::temp_dir().join(format!("jsp-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = ConnectionConfig::builder()
+            .session_timeout(Duration::from_secs(90))
+            .pool_capacity(8)
+            .build();
+
+        for name in ["config.yaml", "config.yml", "config.json"] {
+            let path = dir.join(name);
+            config.to_file(&path).unwrap();
+            let loaded = ConnectionConfig::from_file(&path).unwrap();
+            assert_eq!(loaded.session_timeout, Duration::from_secs(90));
+            assert_eq!(loaded.pool_capacity, 8);
+        }
+
+        let err = config.to_file(dir.join("config.toml")).unwrap_err();
+        assert!(err.to_string().contains("Unsupported config file"), "{}", err);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_server_config_round_trip() {
+        let config: ServerConfig = serde_yaml::from_str(
+            "cleanup_interval: 1m\nmax_sessions_per_ip: 4\nstats_stream_interval: 500ms\nconnection:\n  heartbeat_interval: 2s\n",
+        ).unwrap();
+        assert_eq!(config.cleanup_interval, Duration::from_secs(60));
+        assert_eq!(config.stats_stream_interval, Some(Duration::from_millis(500)));
+        assert_eq!(config.max_sessions_per_ip, Some(4));
+        assert_eq!(config.connection.heartbeat_interval, Duration::from_secs(2));
+        assert_eq!(config.connection.session_timeout, Duration::from_secs(30));
+        assert_eq!(config.global_rate_limit_messages, Some(10_000));
+        assert!(config.validate().is_ok());
+        assert!(ServerConfig { stats_stream_interval: Some(Duration::ZERO), ..config.clone() }.validate().is_err());
+
+        let yaml = serde_yaml::to_string(&config).unwrap();
+        assert!(yaml.contains("cleanup_interval: 1m\n"), "{}", yaml);
+        assert!(yaml.contains("stats_stream_interval: 500ms\n"), "{}", yaml);
+        let json = serde_json::to_string(&config).unwrap();
+        for reloaded in [serde_yaml::from_str::<ServerConfig>(&yaml).unwrap(), serde_json::from_str(&json).unwrap()] {
+            assert_eq!(serde_yaml::to_string(&reloaded).unwrap(), yaml);
+        }
+    }
+
+    #[test]
+    fn test_validation_names_the_field() {
+        let config = ConnectionConfig {
+            ack_batch_timeout_ms: 10_000,
+            pool_capacity: 0,
+            ..ConnectionConfig::default()
+        };
+
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("`ack_batch_timeout_ms`"), "{}", err);
+        assert!(err.contains("`pool_capacity`"), "{}", err);
+
+        let server = ServerConfig {
+            connection: config,
+            ..ServerConfig::default()
+        };
+        let err = server.validate().unwrap_err().to_string();
+        assert!(err.contains("connection: `pool_capacity`"), "{}", err);
+    }
+
+    #[test]
+    fn test_key_update_limits_need_key_updates() {
+        let config = ConnectionConfig::builder().key_update_bytes(1 << 20).build();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("`enable_key_updates`"), "{}", err);
+
+        let config = ConnectionConfig::builder()
+            .enable_key_updates(true)
+            .key_update_interval(Duration::ZERO)
+            .key_update_bytes(1 << 20)
+            .build();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("`key_update_interval` must be greater than zero"), "{}", err);
+        assert!(!err.contains("`key_update_bytes`"), "{}", err);
+    }
+
+    #[test]
+    fn test_rate_limit_burst() {
+        let config = ConnectionConfig::builder()
+            .rate_limit_burst_messages(500)
+            .rate_limit_burst_bytes(8 << 20)
+            .build();
+        assert_eq!(config.rate_limit_burst_messages, Some(500));
+        assert_eq!(config.rate_limit_burst_bytes, Some(8 << 20));
+        assert!(config.validate().is_ok());
+
+        let config = ConnectionConfig::builder().rate_limit_burst_bytes(0).build();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("`rate_limit_burst_bytes` must be greater than zero"), "{}", err);
+    }
 }
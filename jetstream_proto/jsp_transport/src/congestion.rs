@@ -1,7 +1,8 @@
 use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
 
 /// State of the congestion controller
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CongestionState {
     SlowStart,
     CongestionAvoidance,
@@ -13,6 +14,27 @@ pub enum CongestionState {
     ProbeRTT,
 }
 
+/// Congestion control algorithm used by a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CongestionAlgorithm {
+    /// Loss-based NewReno
+    #[default]
+    NewReno,
+    /// Model-based BBR (`crate::bbr`)
+    Bbr,
+}
+
+impl CongestionAlgorithm {
+    /// Create a controller for segments of `mss` bytes
+    pub fn controller(self, mss: usize) -> Box<dyn CongestionController + Send + Sync> {
+        match self {
+            CongestionAlgorithm::NewReno => Box::new(NewReno::new(mss)),
+            CongestionAlgorithm::Bbr => Box::new(crate::bbr::BbrCongestionControl::new(mss)),
+        }
+    }
+}
+
 /// Trait for congestion control algorithms
 pub trait CongestionController: Send + Sync + std::fmt::Debug {
     /// Called when a packet is sent
@@ -24,6 +46,17 @@ pub trait CongestionController: Send + Sync + std::fmt::Debug {
     /// Called when a packet is declared lost
     fn on_packet_lost(&mut self, lost_bytes: usize);
     
+    /// Called when the peer reports newly CE-marked packets in an ACK
+    fn on_ecn_marked(&mut self, marked_packets: u64);
+    
+    /// Called when traffic moved to another network path; what was learned
+    /// about the old one no longer applies, so probing starts over
+    fn on_path_changed(&mut self);
+    
+    /// Called when path MTU discovery changed the segment size; windows
+    /// counted in segments are recomputed, the current one is kept
+    fn set_mss(&mut self, mss: usize);
+    
     /// Get current congestion window in bytes
     fn congestion_window(&self) -> usize;
     
@@ -34,7 +67,12 @@ pub trait CongestionController: Send + Sync + std::fmt::Debug {
     fn state(&self) -> CongestionState;
 }
 
-/// NewReno congestion control implementation
+/// NewReno congestion control implementation (RFC 5681)
+///
+/// Slow start grows the window by up to one MSS per ACK, doubling it every
+/// RTT, until it reaches `ssthresh`. Congestion avoidance then adds about one
+/// MSS per RTT. A loss halves the window and sets `ssthresh` to the result;
+/// `cwnd / 2` stands in for half the flight size, which is not known here.
 #[derive(Debug)]
 pub struct NewReno {
     /// Congestion Window in bytes
@@ -43,11 +81,16 @@ pub struct NewReno {
     ssthresh: usize,
     /// Current state
     state: CongestionState,
+    /// Sender Maximum Segment Size
+    mss: usize,
     /// Initial Window (IW)
-    #[allow(dead_code)]
     initial_window: usize,
     /// Minimum Window
     min_window: usize,
+    /// Latest RTT sample, the length of a congestion event
+    last_rtt: Duration,
+    /// When the window was last reduced for a loss or ECN mark
+    last_reduction: Option<Instant>,
 }
 
 impl NewReno {
@@ -58,9 +101,40 @@ impl NewReno {
             cwnd: initial_window,
             ssthresh: usize::MAX,
             state: CongestionState::SlowStart,
+            mss,
             initial_window,
             min_window: 2 * mss,
+            last_rtt: Duration::ZERO,
+            last_reduction: None,
+        }
+    }
+
+    /// Congestion window in bytes
+    pub fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+
+    /// Slow start threshold in bytes; `usize::MAX` until the first loss
+    pub fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+
+    /// Whether the last reduction was less than an RTT ago
+    fn in_congestion_event(&self) -> bool {
+        self.last_reduction.is_some_and(|at| at.elapsed() < self.last_rtt)
+    }
+
+    /// Multiplicative decrease: halve the window, once per congestion event
+    ///
+    /// Returns false if the window was already reduced within the last RTT.
+    fn reduce_window(&mut self) -> bool {
+        if self.in_congestion_event() {
+            return false;
         }
+        self.last_reduction = Some(Instant::now());
+        self.ssthresh = std::cmp::max(self.cwnd / 2, self.min_window);
+        self.cwnd = self.ssthresh;
+        true
     }
 }
 
@@ -69,45 +143,61 @@ impl CongestionController for NewReno {
         // NewReno doesn't change state on send, just tracks inflight (handled externally)
     }
 
-    fn on_packet_acked(&mut self, acked_bytes: usize, _rtt: Duration) {
+    fn on_packet_acked(&mut self, acked_bytes: usize, rtt: Duration) {
+        self.last_rtt = rtt;
         match self.state {
             CongestionState::SlowStart => {
-                // In Slow Start, cwnd increases by acked_bytes for each ACK
-                self.cwnd += acked_bytes;
+                // cwnd += min(N, SMSS) per ACK (RFC 5681, section 3.1)
+                self.cwnd += std::cmp::min(acked_bytes, self.mss);
                 
                 if self.cwnd >= self.ssthresh {
                     self.state = CongestionState::CongestionAvoidance;
                 }
             }
             CongestionState::CongestionAvoidance => {
-                // In Congestion Avoidance, cwnd increases by MSS * MSS / cwnd for each ACK
-                // This approximates +1 MSS per RTT
-                // We use a simplified additive increase: cwnd += acked_bytes * acked_bytes / cwnd
-                // Or more standard: cwnd += MSS * acked_bytes / cwnd
-                // Let's use a safe integer approximation
-                if self.cwnd > 0 {
-                    let increase = (acked_bytes * acked_bytes) / self.cwnd;
-                    self.cwnd += std::cmp::max(1, increase);
-                }
+                // cwnd += SMSS * SMSS / cwnd per full segment ACKed, about +1 MSS per RTT
+                let increase = (self.mss * acked_bytes) / self.cwnd.max(1);
+                self.cwnd += std::cmp::max(1, increase);
             }
-            CongestionState::Recovery => {
-                // In Recovery (Fast Recovery), we stay here until all lost packets are recovered
-                // For simple NewReno here, we exit recovery on new ACK (simplified)
-                // Real NewReno is more complex with partial ACKs.
-                // For now, let's assume if we get a good ACK, we go back to CA
-                self.cwnd = self.ssthresh;
+            // The window stays at ssthresh until the RTT of the loss is over
+            CongestionState::Recovery if !self.in_congestion_event() => {
                 self.state = CongestionState::CongestionAvoidance;
             }
-            // Ignore BBR states for NewReno
+            // Still recovering, or a BBR state NewReno never enters
             _ => {}
         }
     }
 
     fn on_packet_lost(&mut self, _lost_bytes: usize) {
-        // Multiplicative Decrease
-        self.ssthresh = std::cmp::max(self.cwnd / 2, self.min_window);
-        self.cwnd = self.min_window; // Timeout usually resets to 1 MSS (or min window)
+        // Further losses from the same window are part of the same event
+        if self.reduce_window() {
+            self.state = CongestionState::Recovery;
+        }
+    }
+
+    fn on_ecn_marked(&mut self, marked_packets: u64) {
+        if marked_packets == 0 {
+            return;
+        }
+        // Mild loss: halve the window, but nothing was dropped, so nothing to recover
+        if self.reduce_window() {
+            self.state = CongestionState::CongestionAvoidance;
+        }
+    }
+
+    fn on_path_changed(&mut self) {
+        self.cwnd = self.initial_window;
+        self.ssthresh = usize::MAX;
         self.state = CongestionState::SlowStart;
+        self.last_rtt = Duration::ZERO;
+        self.last_reduction = None;
+    }
+
+    fn set_mss(&mut self, mss: usize) {
+        self.mss = mss;
+        self.initial_window = 10 * mss;
+        self.min_window = 2 * mss;
+        self.cwnd = self.cwnd.max(self.min_window);
     }
 
     fn congestion_window(&self) -> usize {
@@ -212,9 +302,104 @@ mod tests {
         // Simulate loss
         cc.on_packet_lost(mss);
         
+        assert_eq!(cc.state(), CongestionState::Recovery);
+        assert_eq!(cc.ssthresh(), initial_cwnd / 2);
+        assert_eq!(cc.cwnd(), initial_cwnd / 2);
+    }
+
+    #[test]
+    fn test_slow_start_grows_exponentially() {
+        let mss = 1000;
+        let mut cc = NewReno::new(mss);
+        let rtt = Duration::from_millis(50);
+
+        // Every RTT, each segment of the window is ACKed and the window doubles
+        for round in 0..4 {
+            let cwnd = cc.cwnd();
+            assert_eq!(cwnd, (10 * mss) << round);
+            for _ in 0..cwnd / mss {
+                cc.on_packet_acked(mss, rtt);
+            }
+            assert_eq!(cc.cwnd(), 2 * cwnd);
+            assert_eq!(cc.state(), CongestionState::SlowStart);
+        }
+
+        // An ACK for several segments still adds at most one MSS
+        let cwnd = cc.cwnd();
+        cc.on_packet_acked(4 * mss, rtt);
+        assert_eq!(cc.cwnd(), cwnd + mss);
+    }
+
+    #[test]
+    fn test_loss_halves_window_then_additive_increase() {
+        let mss = 1000;
+        let mut cc = NewReno::new(mss);
+        let rtt = Duration::from_millis(20);
+        for _ in 0..30 {
+            cc.on_packet_acked(mss, rtt);
+        }
+        assert_eq!(cc.cwnd(), 40 * mss);
+        assert_eq!(cc.ssthresh(), usize::MAX);
+
+        // Multiplicative decrease, once for all losses of the same window
+        cc.on_packet_lost(mss);
+        assert_eq!(cc.ssthresh(), 20 * mss);
+        assert_eq!(cc.cwnd(), 20 * mss);
+        assert_eq!(cc.state(), CongestionState::Recovery);
+        cc.on_packet_lost(mss);
+        assert_eq!(cc.cwnd(), 20 * mss);
+
+        // Recovery ends after an RTT; then about one MSS per window of ACKs
+        std::thread::sleep(rtt);
+        cc.on_packet_acked(mss, rtt);
+        assert_eq!(cc.state(), CongestionState::CongestionAvoidance);
+        for _ in 0..20 {
+            cc.on_packet_acked(mss, rtt);
+        }
+        assert!(cc.cwnd() > 20 * mss && cc.cwnd() <= 21 * mss, "cwnd {}", cc.cwnd());
+
+        // A later loss halves the grown window again
+        std::thread::sleep(rtt);
+        let cwnd = cc.cwnd();
+        cc.on_packet_lost(mss);
+        assert_eq!(cc.ssthresh(), cwnd / 2);
+        assert_eq!(cc.cwnd(), cwnd / 2);
+    }
+
+    #[test]
+    fn test_ecn_marked_halves_window_once_per_rtt() {
+        let mss = 1000;
+        let mut cc = NewReno::new(mss);
+        cc.on_packet_acked(mss, Duration::from_secs(10));
+        let cwnd = cc.congestion_window();
+
+        cc.on_ecn_marked(1);
+        assert_eq!(cc.congestion_window(), cwnd / 2);
+        assert_eq!(cc.ssthresh(), cwnd / 2);
+        assert_eq!(cc.state(), CongestionState::CongestionAvoidance);
+
+        // More marks within the same RTT are the same event
+        cc.on_ecn_marked(3);
+        assert_eq!(cc.congestion_window(), cwnd / 2);
+    }
+
+    #[test]
+    fn test_path_change_restarts_slow_start() {
+        let mss = 1000;
+        let mut cc = NewReno::new(mss);
+        for _ in 0..30 {
+            cc.on_packet_acked(mss, Duration::from_millis(20));
+        }
+        cc.on_packet_lost(mss);
+        assert_eq!(cc.state(), CongestionState::Recovery);
+
+        cc.on_path_changed();
         assert_eq!(cc.state(), CongestionState::SlowStart);
-        assert_eq!(cc.ssthresh, initial_cwnd / 2);
-        assert_eq!(cc.congestion_window(), cc.min_window);
+        assert_eq!(cc.cwnd(), 10 * mss);
+        assert_eq!(cc.ssthresh(), usize::MAX);
+        // No congestion event carries over, so a loss on the new path counts
+        cc.on_packet_lost(mss);
+        assert_eq!(cc.cwnd(), 5 * mss);
     }
 
     #[test]
@@ -232,4 +417,32 @@ mod tests {
         // Let's just check it compiles and runs without panic.
         let _bw = estimator.bandwidth();
     }
+
+    #[test]
+    fn test_algorithm_controller() {
+        let reno = CongestionAlgorithm::default().controller(1000);
+        assert_eq!(reno.state(), CongestionState::SlowStart);
+        assert_eq!(reno.congestion_window(), 10_000);
+
+        let bbr = CongestionAlgorithm::Bbr.controller(1400);
+        assert_eq!(bbr.state(), CongestionState::Startup);
+        assert_eq!(bbr.congestion_window(), 14_000);
+    }
+
+    #[test]
+    fn test_set_mss_rescales_window_limits() {
+        let mut cc = NewReno::new(1000);
+        cc.set_mss(1400);
+        // The current window stays; growth and the floor follow the new size
+        assert_eq!(cc.cwnd(), 10_000);
+        cc.on_packet_acked(1400, Duration::from_millis(50));
+        assert_eq!(cc.cwnd(), 11_400);
+        for _ in 0..20 {
+            cc.on_packet_lost(1400);
+            cc.last_reduction = None;
+        }
+        assert_eq!(cc.cwnd(), 2 * 1400);
+        cc.on_path_changed();
+        assert_eq!(cc.cwnd(), 14_000);
+    }
 }
@@ -1,35 +1,196 @@
-use crate::udp::UdpTransport;
-use jsp_core::session::Session;
-use jsp_core::types::control::{HeartbeatFrame, CloseFrame, CloseReason, AckFrame};
-use jsp_core::types::header::{Header, FRAME_TYPE_DATA, FRAME_TYPE_ACK, FRAME_TYPE_STUN, FRAME_TYPE_PATH_CHALLENGE, FRAME_TYPE_PATH_RESPONSE};
-use jsp_core::types::stun::{StunMessage, StunMessageType, StunAttribute};
+use crate::udp::{EcnCodepoint, RecvMeta, UdpTransport, GRO_BUFFER_SIZE, MAX_BATCH};
+use crate::transport::ConnectionTransport;
+use crate::transport_selector::TransportType;
+use jsp_core::session::{Session, SessionState};
+use jsp_core::types::control::{HeartbeatFrame, CloseFrame, CloseReason, AckFrame, SessionTicket, KeyUpdateFrame, MtuProbeAckFrame};
+use jsp_core::types::header::{Header, FRAME_TYPE_DATA, FRAME_TYPE_HEARTBEAT, FRAME_TYPE_CLOSE, FRAME_TYPE_ACK, FRAME_TYPE_STUN, FRAME_TYPE_PATH_CHALLENGE, FRAME_TYPE_PATH_RESPONSE, FRAME_TYPE_SESSION_TICKET, FRAME_TYPE_STREAM_FIN, FRAME_TYPE_DATAGRAM, FRAME_TYPE_KEY_UPDATE, FRAME_TYPE_MTU_PROBE, FRAME_TYPE_MTU_PROBE_ACK, FLAG_FRAGMENT, FLAG_EARLY_DATA, FLAG_HALF_CLOSE, FLAG_COMPRESSED, key_phase_flags};
+use jsp_core::types::fragment::{FragmentHeader, FRAGMENT_HEADER_LEN};
+use jsp_core::types::delivery::DeliveryMode;
+use jsp_core::double_ratchet::EncryptedMessage;
+use jsp_core::types::stun::{StunMessage, StunMessageType};
 use jsp_core::types::path_validation::{PathChallenge, PathResponse};
+use jsp_core::types::connection_id::ConnectionId;
+use jsp_core::crypto::{header_aad, AEAD_TAG_LEN};
+use jsp_core::codec::{decode_any_header, Codec, SerializationFormat};
+use jsp_core::compression::header_compression::HeaderCompressor;
 use anyhow::Result;
+use std::collections::{hash_map, HashMap, HashSet, VecDeque};
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Mutex;
 use std::time::Duration;
 use bytes::{Bytes, BytesMut};
-use tracing::{info, warn};
+use tracing::info;
 
 use crate::reliability::ReliabilityLayer;
+use crate::circuit_breaker::{CircuitBreaker, State as CircuitState};
+use crate::events::ConnectionEvent;
+use crate::fragmentation::Reassembler;
 use crate::heartbeat::HeartbeatManager;
 use crate::rate_limit::RateLimiter;
-use crate::memory_pool::PacketPool;
-use crate::ice::IceAgent;
-use crate::config::ConnectionConfig;
-use crate::priority_queue::PriorityQueue;
+use crate::memory_pool::{PacketPool, RECV_BUFFER_SIZE};
+use crate::coalescing;
+use crate::mtu_discovery::{MtuDiscovery, MTU_PROBE_ATTEMPTS, PACKET_OVERHEAD};
+use crate::compression::{self, adaptive::CompressionDecision};
+use crate::ice::{stun_packet, IceAgent};
+use crate::nat::NatType;
+use crate::config::{ConnectionConfig, ConnectStrategy};
+use crate::error::TransportError;
+use crate::priority_queue::{PriorityCounters, PriorityQueue};
+use crate::metrics::StreamStats;
+use crate::mptcp::{MptcpManager, DedupWindow, InterfaceWatcher, SubflowHandshake};
+use crate::path_validator::PathValidator;
+use crate::mptcp::watcher::NetworkInterface;
+use crate::webrtc::WebRTCTransport;
 use jsp_core::qos::QosPriority;
 
+/// FIN exchange for a closing stream
+#[derive(Debug, Default, Clone, Copy)]
+struct StreamFin {
+    /// We sent our FIN
+    sent: bool,
+    /// The peer's FIN was delivered
+    received: bool,
+}
+
+/// Application data of one stream, for `stream_stats`
+#[derive(Debug, Default, Clone, Copy)]
+struct StreamCounters {
+    bytes_sent: u64,
+    bytes_received: u64,
+    messages_sent: u64,
+    messages_received: u64,
+}
+
+/// Local socket that `migrate` is validating before it carries any data
+struct PathProbe {
+    transport: ConnectionTransport,
+    token: [u8; 8],
+    /// PATH_CHALLENGE packet, resent until the peer answers
+    challenge: Vec<u8>,
+    /// Challenges sent so far
+    sent: u32,
+    /// When to resend the challenge, or give up after the last one
+    deadline: tokio::time::Instant,
+}
+
+/// PATH_CHALLENGEs sent on a new path before falling back to the old one
+const PATH_PROBE_ATTEMPTS: u32 = 3;
+
+/// Peer address we sent a PATH_CHALLENGE to and take nothing from until it is echoed
+struct PendingPath {
+    validator: PathValidator,
+    /// The address sent a PATH_CHALLENGE of its own: the peer is migrating there
+    migrate: bool,
+    /// When the challenge was last sent
+    sent_at: Option<std::time::Instant>,
+}
+
+/// How long an address has to echo our PATH_CHALLENGE
+const PATH_VALIDATION_TIMEOUT: Duration = Duration::from_secs(3);
+/// Addresses challenged at once, and validated subflow addresses kept, so
+/// spoofed sources can't grow either without bound
+const MAX_PATH_VALIDATIONS: usize = 16;
+
+/// MTU probe awaiting its MTU_PROBE_ACK
+#[derive(Debug, Clone, Copy)]
+struct MtuProbe {
+    /// Datagram size being tested
+    size: usize,
+    /// Probes of this size sent so far
+    sent: u32,
+}
+
+/// Where the sender task puts packets: MPTCP subflows when any are open,
+/// otherwise (or once they all failed) the primary transport
+#[derive(Clone)]
+struct SendPath {
+    transport: ConnectionTransport,
+    peer_addr: SocketAddr,
+    mptcp: Option<Arc<MptcpManager>>,
+}
+
+impl SendPath {
+    async fn send(&self, data: &[u8]) -> Result<()> {
+        if let Some(mptcp) = &self.mptcp {
+            if mptcp.usable_subflow_count() > 0 {
+                match mptcp.send_sequenced(&sequences_in(data), data).await {
+                    Ok(()) => return Ok(()),
+                    Err(e) => tracing::debug!(peer = %self.peer_addr, "No usable subflow, using the primary path: {}", e),
+                }
+            }
+        }
+        self.transport.send_to(data, self.peer_addr).await?;
+        Ok(())
+    }
+
+    /// Send packets in order, in as few syscalls as possible on the primary path
+    async fn send_batch(&self, packets: &[Vec<u8>]) -> Result<()> {
+        if self.mptcp.as_ref().is_some_and(|mptcp| mptcp.usable_subflow_count() > 0) {
+            for packet in packets {
+                self.send(packet).await?;
+            }
+            return Ok(());
+        }
+        let packets: Vec<(&[u8], SocketAddr)> = packets.iter()
+            .map(|packet| (packet.as_slice(), self.peer_addr))
+            .collect();
+        self.transport.send_batch(&packets).await
+    }
+}
+
+/// Sequence numbers of the sequenced frames in a (possibly coalesced) packet
+fn sequences_in(mut packet: &[u8]) -> Vec<u64> {
+    let mut seqs = Vec::new();
+    while packet.len() >= 2 {
+        let header_len = u16::from_be_bytes([packet[0], packet[1]]) as usize;
+        // Compressed headers can't be read without the connection's decompressor
+        let header = match packet.get(2..2 + header_len).and_then(|bytes| decode_any_header(bytes).ok()) {
+            Some(header) => header,
+            None => break,
+        };
+        if header.msg_type == FRAME_TYPE_DATA || header.msg_type == FRAME_TYPE_STREAM_FIN {
+            seqs.push(header.sequence);
+        }
+        let end = match header.payload_len {
+            Some(len) => 2 + header_len + len as usize,
+            None => packet.len(),
+        };
+        packet = packet.get(end..).unwrap_or_default();
+    }
+    seqs
+}
+
 pub struct Connection {
-    pub(crate) transport: UdpTransport,
+    pub(crate) transport: ConnectionTransport,
     session: Session,
     reliability: ReliabilityLayer,
     pub peer_addr: SocketAddr,
     pub public_addr: Option<SocketAddr>,
+    // When `public_addr` was last confirmed by a STUN server
+    public_addr_at: Option<std::time::Instant>,
     stun_server_addrs: Vec<SocketAddr>,
+    // Binding requests awaiting an answer: transaction ID -> server asked
+    stun_transactions: HashMap<[u8; 16], SocketAddr>,
+    // Address each server answered with in the latest round
+    stun_mappings: HashMap<SocketAddr, SocketAddr>,
+    nat_type: NatType,
     migration_start: Option<std::time::Instant>,
+    // Last migration, until the first ACK on the new path
+    migrated_at: Option<std::time::Instant>,
+    // Set when switching paths until the peer validated the new one
+    awaiting_path_validation: bool,
+    // New local path being probed by `migrate`; data stays on the old one meanwhile
+    path_probe: Option<PathProbe>,
+    // Peer addresses other than `peer_addr` we challenged, and those that
+    // answered, whose frames are taken as MPTCP subflows
+    path_validations: HashMap<SocketAddr, PendingPath>,
+    validated_subflows: HashSet<SocketAddr>,
+    // Clients with `auto_rebind`: local interfaces, to notice ours going away
+    _interface_watcher: Option<InterfaceWatcher>,
+    interfaces: Option<tokio::sync::watch::Receiver<Vec<NetworkInterface>>>,
+    last_rebind: Option<std::time::Instant>,
     
     // Heartbeat management
     heartbeat: Arc<HeartbeatManager>,
@@ -40,9 +201,21 @@ pub struct Connection {
     
     // Memory pool
     packet_pool: PacketPool,
+    // Receive buffers, handed out as `Bytes` slices
+    recv_pool: PacketPool,
+    // Buffers ready for the next batched receive
+    recv_bufs: Vec<Vec<u8>>,
+    // Datagrams read in a batch and not processed yet
+    recv_backlog: VecDeque<(Bytes, SocketAddr, EcnCodepoint)>,
+    // Frames of the current datagram not handled yet
+    recv_remainder: Option<(Bytes, SocketAddr)>,
     
     // Graceful shutdown
     closing: Arc<AtomicBool>,
+    // Why the peer closed the connection, once its close frame was read
+    peer_close: Option<CloseFrame>,
+    // Reason passed to `close`, if this side closed first
+    local_close: Option<CloseReason>,
     
     // Configuration
     config: ConnectionConfig,
@@ -60,18 +233,23 @@ pub struct Connection {
 
     // Metrics
     metrics: Arc<crate::metrics::Metrics>,
+    // Labeled series in the global Prometheus registry, once established
+    labeled_metrics: Option<crate::prometheus::ConnectionSeries>,
 
     // Sender Task
     sender_task: Option<tokio::task::JoinHandle<()>>,
     sender_notify: Arc<tokio::sync::Notify>,
     priority_queue: Arc<Mutex<PriorityQueue<Vec<u8>>>>,
+    send_queue_locks: AtomicU64,
 
     // Circuit Breaker
-    circuit_breaker: Arc<crate::circuit_breaker::CircuitBreaker>,
+    circuit_breaker: Arc<CircuitBreaker>,
+    // Transitions not yet queued as events
+    circuit_transitions: Arc<Mutex<Vec<(CircuitState, CircuitState)>>>,
 
     // Header Compression
-    header_compressor: Option<jsp_core::compression::header_compression::HeaderCompressor>,
-    header_decompressor: Option<jsp_core::compression::header_compression::HeaderCompressor>,
+    header_compressor: Option<HeaderCompressor>,
+    header_decompressor: Option<HeaderCompressor>,
 
     // DDoS Protection
     _ddos_protection: Option<crate::ddos_protection::DdosProtection>,
@@ -79,33 +257,191 @@ pub struct Connection {
     // Mobile Optimizations
     pub adaptive_compression: Arc<Mutex<crate::compression::adaptive::AdaptiveCompression>>,
     pub network_status: Arc<crate::network_status::NetworkStatus>,
+
+    // Fragmentation
+    next_message_id: u32,
+    reassembler: Reassembler,
+    remote_stream_modes: HashMap<u32, DeliveryMode>,
+
+    // Events ready for the application (also filled while waiting for send capacity)
+    pending_events: VecDeque<ConnectionEvent>,
+
+    // Received datagrams, returned by `recv_datagram`
+    datagrams: VecDeque<Bytes>,
+
+    // Streams with a FIN in either direction; the id is released once both sides sent one
+    stream_fins: HashMap<u32, StreamFin>,
+    
+    // Traffic per stream, forgotten with the stream once FINs went both ways
+    stream_counters: HashMap<u32, StreamCounters>,
+
+    // 0-RTT resumption: early data kept until the server accepts or rejects it
+    early_data: Vec<(u32, Vec<u8>)>,
+    handshake_round_trips: u32,
+
+    // Client: ClientHello kept for retransmission. Server: the accepted
+    // ClientHello's nonce and our ServerHello, resent if the hello repeats
+    client_hello: Option<Vec<u8>>,
+    server_hello: Option<(u64, Vec<u8>)>,
+
+    // Multi-path subflows, created after the handshake when enabled
+    mptcp: Option<Arc<MptcpManager>>,
+    // Drops copies of sequenced frames sent on several subflows
+    dedup: DedupWindow,
+
+    // Key updates: start of the current epoch, bytes sealed in it, our
+    // KEY_UPDATE until the peer ACKs it, and when the previous epoch's key goes
+    key_epoch_started: std::time::Instant,
+    key_epoch_bytes: u64,
+    // AEAD nonce of the next sealed packet, carried in its header
+    next_packet_nonce: u64,
+    key_update_seq: Option<u64>,
+    retire_previous_epoch_at: Option<std::time::Instant>,
+
+    // Receive window in our last ACK
+    advertised_window: u64,
+
+    // Path MTU discovery, shared with the sender task, which coalesces up
+    // to the MTU and reports datagrams the socket refused as too big
+    mtu_discovery: Arc<Mutex<MtuDiscovery>>,
+    mtu_probe: Option<MtuProbe>,
+    // When to resend the probe in flight or send the next one
+    mtu_probe_at: Option<tokio::time::Instant>,
+    // Segment size last handed to the congestion controller
+    path_mss: usize,
+
+    // Root of this connection's trace, started with the first traced operation
+    trace_span: Option<crate::otel::Span>,
 }
 
 impl Connection {
-    pub async fn connect_with_config(addr: &str, config: ConnectionConfig) -> Result<Self> {
+    /// Connect to `addr` with the transport picked by `config.connect_strategy`
+    ///
+    /// With `ConnectStrategy::WebRtc`, `addr` is the peer ID registered at the
+    /// signaling server and the data channel is open when this returns.
+    pub async fn connect_with_config(addr: &str, config: ConnectionConfig) -> Result<Self, TransportError> {
+        if config.connect_strategy == ConnectStrategy::WebRtc {
+            let (transport, peer_addr) = Self::webrtc_transport(Some(addr), config.bind_addr.as_deref(), &config).await?;
+            return Ok(Self::new_from_transport(transport, peer_addr, config, false).await?);
+        }
+
+        // Resumption is UDP only; the ClientHello goes out right away
+        if let Some(ticket) = config.session_ticket.clone() {
+            return Self::resume_with_ticket_and_config(addr, ticket, config).await;
+        }
+
+        let peer_addr: SocketAddr = addr.parse()?;
+
+        if config.connect_strategy == ConnectStrategy::UdpOnly {
+            let bind_addr = config.bind_addr.as_deref().unwrap_or("0.0.0.0:0");
+            let transport = UdpTransport::bind(bind_addr).await?;
+            return Ok(Self::new_from_transport(transport.into(), peer_addr, config, false).await?);
+        }
+
+        // Race/ordered connects complete the handshake while picking a transport
+        let outcome = crate::transport_race::connect(peer_addr, &config).await?;
+        let mut connection = Self::new_from_transport(outcome.transport, peer_addr, config, false).await?;
+        connection.session = outcome.session;
+
+        tracing::info!(
+            peer = %peer_addr,
+            transport = ?connection.active_transport(),
+            session_id = connection.session.session_id,
+            "Handshake completed"
+        );
+
+        connection.start_background_tasks();
+        Ok(connection)
+    }
+
+    /// Resume a previous session from a ticket obtained with `session_ticket()`
+    ///
+    /// The ClientHello carrying the ticket is sent immediately and data passed
+    /// to `send_on_stream` before `handshake()` completes goes out as 0-RTT
+    /// early data. If the ticket is expired or rejected by the server, a full
+    /// handshake is performed and any early data is sent again.
+    pub async fn resume_with_ticket(addr: &str, ticket: SessionTicket) -> Result<Self, TransportError> {
+        Self::resume_with_ticket_and_config(addr, ticket, ConnectionConfig::default()).await
+    }
+
+    pub async fn resume_with_ticket_and_config(addr: &str, ticket: SessionTicket, config: ConnectionConfig) -> Result<Self, TransportError> {
+        let peer_addr: SocketAddr = addr.parse()?;
         let bind_addr = config.bind_addr.as_deref().unwrap_or("0.0.0.0:0");
         let transport = UdpTransport::bind(bind_addr).await?;
-        let peer_addr: SocketAddr = addr.parse()?;
-        Self::new_from_transport(transport, peer_addr, config, false).await
+        let mut connection = Self::new_from_transport(transport.into(), peer_addr, config, false).await?;
+
+        let (hello, early_data_allowed) = match connection.session.generate_resumption_hello(&ticket) {
+            Ok(hello) => (hello, true),
+            Err(e) => {
+                tracing::debug!(peer = %peer_addr, "Session ticket unusable, using full handshake: {}", e);
+                (connection.session.generate_client_hello()?, false)
+            }
+        };
+        connection.transport.send_to(&hello, peer_addr).await?;
+        connection.client_hello = Some(hello);
+        tracing::info!(peer = %peer_addr, resumption = early_data_allowed, "Handshake initiated");
+
+        // Early data is only sent once the server can tell it apart; without
+        // a usable ticket, queued data waits for the handshake to complete
+        if early_data_allowed {
+            connection.start_sender_task();
+        }
+
+        Ok(connection)
     }
 
-    pub async fn bind_with_config(bind_addr: &str, config: ConnectionConfig) -> Result<Self> {
+    /// Bind the server side of a connection
+    ///
+    /// With `ConnectStrategy::WebRtc` this waits for an offer through the
+    /// signaling server and answers it before returning.
+    pub async fn bind_with_config(bind_addr: &str, config: ConnectionConfig) -> Result<Self, TransportError> {
+        if config.connect_strategy == ConnectStrategy::WebRtc {
+            let (transport, _) = Self::webrtc_transport(None, Some(bind_addr), &config).await?;
+            let peer_addr: SocketAddr = "0.0.0.0:0".parse()?;
+            return Ok(Self::new_from_transport(transport, peer_addr, config, true).await?);
+        }
+
         let transport = UdpTransport::bind(bind_addr).await?;
         let peer_addr: SocketAddr = "0.0.0.0:0".parse()?;
-        Self::new_from_transport(transport, peer_addr, config, true).await
+        Ok(Self::new_from_transport(transport.into(), peer_addr, config, true).await?)
     }
 
-    async fn new_from_transport(transport: UdpTransport, peer_addr: SocketAddr, config: ConnectionConfig, is_server: bool) -> Result<Self> {
+    /// Open a WebRTC data channel, offering it to `remote_peer` or answering
+    /// the first offer when `None`, and return it with the peer's address
+    async fn webrtc_transport(remote_peer: Option<&str>, bind_addr: Option<&str>, config: &ConnectionConfig) -> Result<(ConnectionTransport, SocketAddr)> {
+        let mut webrtc_config = config.webrtc.clone();
+        if let Some(bind_addr) = bind_addr {
+            webrtc_config.bind_addr = bind_addr.to_string();
+        }
+        let transport = WebRTCTransport::new(webrtc_config)
+            .map_err(|e| anyhow::anyhow!("Invalid WebRTC config: {}", e))?;
+
+        // Candidates are trickled during the signaling exchange, and the
+        // handshake only starts once the data channel is open
+        transport.initialize().await
+            .map_err(|e| anyhow::anyhow!("WebRTC initialization failed: {}", e))?;
+        let peer_addr = match remote_peer {
+            Some(peer) => transport.connect(peer).await?,
+            None => transport.accept().await?,
+        };
+
+        Ok((ConnectionTransport::WebRtc(transport), peer_addr))
+    }
+
+    async fn new_from_transport(transport: ConnectionTransport, peer_addr: SocketAddr, config: ConnectionConfig, is_server: bool) -> Result<Self> {
         let heartbeat_config = crate::heartbeat::HeartbeatConfig {
             foreground_interval: config.heartbeat_interval,
-            background_interval: Duration::from_secs(30), // Default background interval
+            background_interval: config.heartbeat_background_interval,
+            cellular_interval: config.heartbeat_cellular_interval,
             timeout_count: config.heartbeat_timeout_count,
         };
         let heartbeat = Arc::new(HeartbeatManager::new(heartbeat_config));
         
-        let rate_limiter = RateLimiter::new(
+        let rate_limiter = RateLimiter::with_burst(
             config.rate_limit_messages,
             config.rate_limit_bytes,
+            config.rate_limit_burst_messages.unwrap_or(config.rate_limit_messages),
+            config.rate_limit_burst_bytes.unwrap_or(config.rate_limit_bytes),
         );
         
         let packet_pool = PacketPool::new(
@@ -121,68 +457,118 @@ impl Connection {
         let peer_id = transport.local_addr()?.to_string();
         let mut agent = IceAgent::new(peer_id);
 
+        // State changes come from the sender task as well as from sends;
+        // they are queued as events the next time the application looks
+        let metrics = Arc::new(crate::metrics::Metrics::new());
+        let circuit_transitions = Arc::new(Mutex::new(Vec::new()));
+        let circuit_breaker = {
+            let (metrics, transitions) = (Arc::clone(&metrics), Arc::clone(&circuit_transitions));
+            CircuitBreaker::new(config.circuit_breaker.clone()).with_listener(move |old, new| {
+                if new == CircuitState::Open {
+                    metrics.record_circuit_breaker_trip();
+                }
+                transitions.lock().unwrap().push((old, new));
+            })
+        };
+
         let mut connection = Self {
             transport,
             session: Session::new(),
-            reliability: ReliabilityLayer::new(),
+            reliability: ReliabilityLayer::with_congestion(config.congestion_algorithm.controller(config.mss)),
             peer_addr,
             public_addr: None,
+            public_addr_at: None,
             stun_server_addrs,
+            stun_transactions: HashMap::new(),
+            stun_mappings: HashMap::new(),
+            nat_type: NatType::Unknown,
             migration_start: None,
+            migrated_at: None,
+            awaiting_path_validation: false,
+            path_probe: None,
+            path_validations: HashMap::new(),
+            validated_subflows: HashSet::new(),
+            _interface_watcher: None,
+            interfaces: None,
+            last_rebind: None,
             heartbeat,
             heartbeat_task: None,
             rate_limiter,
             packet_pool,
+            recv_pool: PacketPool::new(config.pool_capacity, if config.enable_gso { GRO_BUFFER_SIZE } else { RECV_BUFFER_SIZE }),
+            recv_bufs: Vec::new(),
+            recv_backlog: VecDeque::new(),
+            recv_remainder: None,
             closing: Arc::new(AtomicBool::new(false)),
+            peer_close: None,
+            local_close: None,
             config: config.clone(),
             is_server,
             coalescing_buffer: Arc::new(Mutex::new(BytesMut::with_capacity(1500))),
             last_coalesce_flush: Arc::new(Mutex::new(std::time::Instant::now())),
             ice_agent: None, // Set later
             flush_task: None,
-            metrics: Arc::new(crate::metrics::Metrics::new()),
+            metrics,
+            labeled_metrics: None,
             sender_task: None,
             sender_notify: Arc::new(tokio::sync::Notify::new()),
-            priority_queue: Arc::new(Mutex::new(PriorityQueue::new())),
-            circuit_breaker: Arc::new(crate::circuit_breaker::CircuitBreaker::new(Default::default())),
+            priority_queue: Arc::new(Mutex::new(PriorityQueue::with_capacity(config.send_queue_max_packets, config.send_queue_max_bytes).with_weights(&config.qos_weights).with_aging(config.priority_aging_interval))),
+            send_queue_locks: AtomicU64::new(0),
+            circuit_breaker: Arc::new(circuit_breaker),
+            circuit_transitions,
             header_compressor: None,
             header_decompressor: None,
             _ddos_protection: None,
-            adaptive_compression: Arc::new(Mutex::new(crate::compression::adaptive::AdaptiveCompression::default_config())),
+            adaptive_compression: Arc::new(Mutex::new(crate::compression::adaptive::AdaptiveCompression::new(config.adaptive_compression_config.clone()))),
             network_status: Arc::new(crate::network_status::NetworkStatus::new()),
+            next_message_id: 0,
+            reassembler: Reassembler::new(config.reassembly_timeout).with_max_buffered(config.max_receive_buffer),
+            remote_stream_modes: HashMap::new(),
+            pending_events: VecDeque::new(),
+            datagrams: VecDeque::new(),
+            stream_fins: HashMap::new(),
+            stream_counters: HashMap::new(),
+            early_data: Vec::new(),
+            handshake_round_trips: 0,
+            client_hello: None,
+            server_hello: None,
+            mptcp: None,
+            dedup: DedupWindow::new(),
+            key_epoch_started: std::time::Instant::now(),
+            key_epoch_bytes: 0,
+            next_packet_nonce: 0,
+            key_update_seq: None,
+            retire_previous_epoch_at: None,
+            advertised_window: config.max_receive_buffer as u64,
+            mtu_discovery: Arc::new(Mutex::new(MtuDiscovery::from_config(&config.mtu_discovery))),
+            mtu_probe: None,
+            mtu_probe_at: None,
+            path_mss: config.mss,
+            trace_span: None,
         };
+        connection.sync_path_mss();
+        
+        // Until the peer advertises its receive window, assume it matches ours
+        connection.reliability.on_receive_window(config.max_receive_buffer as u64);
+        
+        if config.enable_double_ratchet {
+            connection.session.enable_double_ratchet();
+        }
+        connection.session.set_serialization_formats(&config.serialization_formats);
+        connection.enable_offload();
 
-        if !is_server {
-            // Gather candidates (STUN)
-            agent.gather_candidates(&mut connection).await?;
-            
-            // Wait for remote candidates (with timeout)
-            let wait_timeout = Duration::from_secs(5);
-            let start = std::time::Instant::now();
-            
-            while start.elapsed() < wait_timeout {
-                if let Some(sig) = &mut agent.signaling {
-                    match tokio::time::timeout(Duration::from_millis(500), sig.recv()).await {
-                        Ok(Ok(msg)) => {
-                            agent.process_signaling_message(msg).await?;
-                            
-                            // If we have at least one remote candidate, try connectivity checks
-                            if agent.remote_candidates_count() > 0 {
-                                info!("Received remote candidates, performing connectivity checks...");
-                                if let Some(selected) = agent.perform_connectivity_checks(&mut connection).await? {
-                                    info!("P2P connection established via {}", selected);
-                                    // Update peer_addr to use the selected candidate
-                                    connection.peer_addr = selected;
-                                    break;
-                                }
-                            }
-                        }
-                        Ok(Err(e)) => {
-                            warn!("Error receiving from signaling: {}", e);
-                        }
-                        Err(_) => continue, // timeout, keep waiting
-                    }
+        // ICE only applies to datagram transports
+        if !is_server && connection.transport.as_udp().is_some() {
+            if agent.signaling.is_some() {
+                // Trickle candidates and check remote ones as they arrive
+                if let Some(selected) = agent.trickle(&mut connection, Duration::from_secs(5)).await? {
+                    info!("P2P connection established via {}", selected);
+                    // Update peer_addr to use the selected candidate
+                    connection.peer_addr = selected;
                 }
+            } else {
+                // Gather candidates (STUN)
+                agent.gather_candidates(&mut connection).await?;
             }
         }
         
@@ -190,114 +576,428 @@ impl Connection {
         Ok(connection)
     }
 
-    pub fn local_addr(&self) -> Result<SocketAddr> {
-        self.transport.local_addr()
+    pub fn local_addr(&self) -> Result<SocketAddr, TransportError> {
+        Ok(self.transport.local_addr()?)
+    }
+
+    /// Keep `agent`, which ran `trickle` on this connection, so `close`
+    /// releases the TURN relay it may have fallen back to
+    pub fn set_ice_agent(&mut self, agent: IceAgent) {
+        self.ice_agent = Some(agent);
+    }
+
+    /// Transport carrying this connection (the race winner when racing)
+    pub fn active_transport(&self) -> TransportType {
+        self.transport.transport_type()
     }
 
     /// Migrate connection to a new local address
-    pub async fn migrate(&mut self, new_bind_addr: &str) -> Result<()> {
-        let new_transport = UdpTransport::bind(new_bind_addr).await?;
-        self.transport = new_transport;
-        tracing::info!("Connection migrated to local address: {}", new_bind_addr);
-        self.migration_start = Some(std::time::Instant::now());
-        
-        // Send a probe packet (PathChallenge) to peer to update their view of our address
-        // Or just send next data packet. 
-        // For now, let's send a PathChallenge to be proactive
+    ///
+    /// The new socket is probed with a PATH_CHALLENGE while data keeps
+    /// flowing on the current one, and the connection switches once the
+    /// peer answers on the new path (see `is_migrating`). Answers arrive
+    /// through `recv` and friends. If the peer never answers, the probe is
+    /// given up and the connection stays on the old path.
+    pub async fn migrate(&mut self, new_bind_addr: &str) -> Result<(), TransportError> {
+        let transport: ConnectionTransport = UdpTransport::bind(new_bind_addr).await?.into();
         let mut token = [0u8; 8];
         getrandom::getrandom(&mut token).expect("Failed to generate random token");
-        let challenge = PathChallenge { token };
-        let payload = serde_cbor::to_vec(&challenge)?;
-        
-        let mut header = Header::new(
-            0,
-            FRAME_TYPE_PATH_CHALLENGE,
-            0,
-            0,
-            0,
-            0,
-            jsp_core::types::delivery::DeliveryMode::Reliable,
-            None,
-            Some(payload.len() as u32)
-        );
-        header.connection_id = Some(jsp_core::types::connection_id::ConnectionId::from_u64(self.session.session_id));
+        let challenge = self.path_challenge_packet(token)?;
+        transport.send_to(&challenge, self.peer_addr).await?;
+        tracing::info!(peer = %self.peer_addr, local = %transport.local_addr()?, "Probing new local path");
         
-        // Do NOT compress migration packet so server can identify connection from new address
-        let header_bytes = serde_cbor::to_vec(&header)?;
-        let header_len = header_bytes.len() as u16;
+        let deadline = tokio::time::Instant::now() + self.path_probe_timeout();
+        self.path_probe = Some(PathProbe { transport, token, challenge, sent: 1, deadline });
+        Ok(())
+    }
+
+    /// Whether a `migrate` is still waiting for its new path to be validated
+    pub fn is_migrating(&self) -> bool {
+        self.path_probe.is_some()
+    }
+
+    /// Largest datagram the path is known to carry, in UDP payload bytes
+    ///
+    /// Found by probing with `mtu_discovery` enabled, and `base_mtu` until
+    /// a probe is answered or while discovery is off.
+    pub fn path_mtu(&self) -> usize {
+        self.mtu_discovery.lock().unwrap().current_mtu()
+    }
+
+    /// Move to `transport` at once and have the peer validate it
+    ///
+    /// For when the current socket is gone, so there is no old path to keep
+    /// using meanwhile.
+    async fn switch_path(&mut self, transport: ConnectionTransport) -> Result<()> {
+        self.path_probe = None;
+        self.commit_path(transport);
+        let mut token = [0u8; 8];
+        getrandom::getrandom(&mut token).expect("Failed to generate random token");
+        let challenge = self.path_challenge_packet(token)?;
+        self.transport.send_to(&challenge, self.peer_addr).await?;
+        Ok(())
+    }
+
+    /// Turn on GSO and GRO for the active socket, when configured and the kernel has them
+    fn enable_offload(&self) {
+        if let (true, Some(udp)) = (self.config.enable_gso, self.transport.as_udp()) {
+            let (gso, gro) = (udp.enable_gso(), udp.enable_gro());
+            tracing::debug!(gso, gro, "UDP offload");
+        }
+    }
+
+    /// Codec for headers in the format negotiated in the handshake
+    fn header_codec(&self) -> Codec {
+        Codec::new(self.session.serialization_format())
+    }
+
+    /// Header compressor, if compression is on and the session uses CBOR
+    ///
+    /// FlatBuffers headers are always sent whole: compressed headers are
+    /// told apart from CBOR ones by their first byte, which doesn't work
+    /// against FlatBuffers.
+    fn header_compressor(&mut self) -> Option<&mut HeaderCompressor> {
+        match self.session.serialization_format() {
+            SerializationFormat::Cbor => self.header_compressor.as_mut(),
+            SerializationFormat::FlatBuffers => None,
+        }
+    }
+
+    /// Receive buffer size for the active socket
+    fn recv_buffer_size(&self) -> usize {
+        if self.transport.as_udp().is_some_and(UdpTransport::gro_enabled) {
+            GRO_BUFFER_SIZE
+        } else {
+            RECV_BUFFER_SIZE
+        }
+    }
+
+    /// Make `transport` the active path
+    fn commit_path(&mut self, transport: ConnectionTransport) {
+        self.transport = transport;
+        self.enable_offload();
+        // The NAT mapping belongs to the old socket
+        self.public_addr_at = None;
+        self.stun_mappings.clear();
+        self.nat_type = NatType::Unknown;
+        self.restart_path_tasks();
+        self.restart_mtu_discovery();
+        tracing::info!(peer = %self.peer_addr, local = ?self.transport.local_addr().ok(), "Connection migrated to new local address");
+        self.migration_start = Some(std::time::Instant::now());
+        self.migrated_at = self.migration_start;
+        // The peer validates the new path before accepting data from it;
+        // whatever it dropped meanwhile is resent once we answer
+        self.awaiting_path_validation = true;
+    }
+
+    /// How long each PATH_CHALLENGE on a new path, or MTU probe, is given
+    /// to be answered
+    fn path_probe_timeout(&self) -> Duration {
+        (self.reliability.smoothed_rtt() + 4 * self.reliability.rtt_var()).max(Duration::from_millis(200))
+    }
+
+    /// Whether a datagram on the probed socket proves the new path works:
+    /// the peer's PATH_RESPONSE to our challenge, or its own PATH_CHALLENGE
+    fn validates_path_probe(&self, packet: &[u8], src: SocketAddr) -> bool {
+        let probe = match &self.path_probe {
+            Some(probe) if src == self.peer_addr && packet.len() >= 2 => probe,
+            _ => return false,
+        };
+        let header_len = u16::from_be_bytes([packet[0], packet[1]]) as usize;
+        let header = match packet.get(2..2 + header_len).and_then(|bytes| decode_any_header(bytes).ok()) {
+            Some(header) => header,
+            None => return false,
+        };
+        let end = header.payload_len.map_or(packet.len(), |len| 2 + header_len + len as usize);
+        let payload = packet.get(2 + header_len..end).unwrap_or_default();
+        match header.msg_type {
+            FRAME_TYPE_PATH_RESPONSE => serde_cbor::from_slice::<PathResponse>(payload)
+                .is_ok_and(|response| response.token == probe.token),
+            FRAME_TYPE_PATH_CHALLENGE => true,
+            _ => false,
+        }
+    }
+
+    /// Resend the PATH_CHALLENGE on the probed path, or give the path up
+    async fn on_path_probe_timeout(&mut self) -> Result<()> {
+        let timeout = self.path_probe_timeout();
+        let probe = match &mut self.path_probe {
+            Some(probe) => probe,
+            None => return Ok(()),
+        };
+        if probe.sent >= PATH_PROBE_ATTEMPTS {
+            tracing::warn!(
+                peer = %self.peer_addr,
+                local = ?probe.transport.local_addr().ok(),
+                "New path was never validated, staying on the current one"
+            );
+            self.path_probe = None;
+            return Ok(());
+        }
+        probe.sent += 1;
+        probe.deadline = tokio::time::Instant::now() + timeout;
+        tracing::debug!(peer = %self.peer_addr, attempt = probe.sent, "Resending PATH_CHALLENGE on the new path");
+        if let Err(e) = probe.transport.send_to(&probe.challenge, self.peer_addr).await {
+            tracing::warn!(peer = %self.peer_addr, "New path failed, staying on the current one: {}", e);
+            self.path_probe = None;
+        }
+        Ok(())
+    }
+
+    /// PATH_CHALLENGE carrying `token`, with an uncompressed header naming
+    /// our connection so the peer can tell who the new address belongs to
+    fn path_challenge_packet(&self, token: [u8; 8]) -> Result<Vec<u8>> {
+        let payload = serde_cbor::to_vec(&PathChallenge { token })?;
+        Ok(path_frame(self.session.session_id, FRAME_TYPE_PATH_CHALLENGE, &payload))
+    }
+
+    /// Echo a PATH_CHALLENGE's `token` back to `dst`
+    async fn send_path_response(&mut self, token: [u8; 8], dst: SocketAddr) -> Result<()> {
+        let payload = serde_cbor::to_vec(&PathResponse { token })?;
+        let packet = path_frame(self.session.session_id, FRAME_TYPE_PATH_RESPONSE, &payload);
+        self.transport.send_to(&packet, dst).await?;
+        Ok(())
+    }
+
+    /// Hello and PATH_CHALLENGE answers for our MPTCP subflows, so the peer
+    /// validates their addresses before taking frames from them
+    fn subflow_handshake(&self) -> SubflowHandshake {
+        let session_id = self.session.session_id;
+        SubflowHandshake {
+            hello: path_frame(session_id, FRAME_TYPE_HEARTBEAT, &[]),
+            respond: Box::new(move |packet: &[u8]| answer_path_challenge(session_id, packet)),
+        }
+    }
+
+    /// Start path MTU discovery over from `base_mtu`, for a new path
+    fn restart_mtu_discovery(&mut self) {
+        if !self.config.mtu_discovery.enabled {
+            return;
+        }
+        self.mtu_discovery.lock().unwrap().reset();
+        self.mtu_probe = None;
+        self.mtu_probe_at = Some(tokio::time::Instant::now());
+        self.sync_path_mss();
+    }
+
+    /// Largest payload of one packet: what the path MTU leaves room for
+    /// with discovery on, `max_fragment_size` otherwise
+    fn max_fragment(&self) -> usize {
+        if self.config.mtu_discovery.enabled {
+            self.path_mtu() - PACKET_OVERHEAD
+        } else {
+            self.config.max_fragment_size.max(1)
+        }
+    }
+
+    /// Hand the congestion controller the packet size, if discovery changed it
+    ///
+    /// The sender task lowers the MTU on its own when the socket refuses a
+    /// packet, so this also restarts a search that had converged.
+    fn sync_path_mss(&mut self) {
+        let mss = self.max_fragment();
+        if !self.config.mtu_discovery.enabled || mss == self.path_mss {
+            return;
+        }
+        tracing::debug!(peer = %self.peer_addr, path_mtu = self.path_mtu(), mss, "Path MTU changed");
+        self.path_mss = mss;
+        self.reliability.set_mss(mss);
+        if self.mtu_probe.is_none() && self.mtu_probe_at.is_some() {
+            self.mtu_probe_at = Some(tokio::time::Instant::now());
+        }
+    }
+
+    /// Resend the MTU probe in flight, give its size up after the last
+    /// attempt, or send the next probe the search calls for
+    async fn on_mtu_probe_timer(&mut self) -> Result<()> {
+        let next = match self.mtu_probe.take() {
+            Some(probe) if probe.sent < MTU_PROBE_ATTEMPTS => Some(MtuProbe { sent: probe.sent + 1, ..probe }),
+            lost => {
+                let mut discovery = self.mtu_discovery.lock().unwrap();
+                if let Some(probe) = lost {
+                    tracing::debug!(peer = %self.peer_addr, size = probe.size, "MTU probes unanswered");
+                    discovery.report_failure(probe.size);
+                }
+                discovery.due_probe().map(|size| MtuProbe { size, sent: 1 })
+            }
+        };
+        self.sync_path_mss();
+        let now = tokio::time::Instant::now();
+        let probe = match next {
+            Some(probe) => probe,
+            None => {
+                // Converged; look for a larger MTU once the raise interval passed
+                self.mtu_probe_at = Some(now + self.config.mtu_discovery.raise_interval);
+                return Ok(());
+            }
+        };
+        self.mtu_probe_at = Some(now + self.path_probe_timeout());
+        let packet = self.mtu_probe_packet(probe.size)?;
+        match self.transport.send_to(&packet, self.peer_addr).await {
+            Ok(_) => self.mtu_probe = Some(probe),
+            Err(e) if self.mtu_discovery.lock().unwrap().on_send_error(&e, probe.size) => {
+                // The kernel already knows it is too big; no need to wait
+                tracing::debug!(peer = %self.peer_addr, size = probe.size, "MTU probe refused by the socket");
+                self.mtu_probe_at = Some(now);
+            }
+            Err(e) => {
+                tracing::debug!(peer = %self.peer_addr, size = probe.size, "Sending MTU probe failed: {}", e);
+                self.mtu_probe = Some(probe);
+            }
+        }
+        Ok(())
+    }
+
+    /// The peer's MTU_PROBE_ACK: a datagram of `size` bytes got through
+    fn on_mtu_probe_acked(&mut self, size: usize) {
+        if !self.config.mtu_discovery.enabled {
+            return;
+        }
+        self.mtu_discovery.lock().unwrap().report_success(size);
+        if self.mtu_probe.is_some_and(|probe| probe.size == size) {
+            self.mtu_probe = None;
+            self.mtu_probe_at = Some(tokio::time::Instant::now());
+        }
+        self.sync_path_mss();
+    }
+
+    /// MTU probe of exactly `size` bytes: zero padding behind a header
+    /// that is never compressed, so a lost probe leaves the peer's
+    /// decompressor in step
+    fn mtu_probe_packet(&self, size: usize) -> Result<Vec<u8>> {
+        let mut header = Header::new(0, FRAME_TYPE_MTU_PROBE, 0, 0, 0, 0, DeliveryMode::BestEffort, None, Some(0));
+        header.connection_id = Some(ConnectionId::from_u64(self.session.session_id));
+        // The payload length's encoding changes the header's size, so settle it first
+        let mut header_bytes = self.header_codec().encode_header(&header)?;
+        for _ in 0..2 {
+            header.payload_len = Some(size.saturating_sub(2 + header_bytes.len()) as u32);
+            header_bytes = self.header_codec().encode_header(&header)?;
+        }
+        let mut packet = Vec::with_capacity(size);
+        packet.extend_from_slice(&(header_bytes.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&header_bytes);
+        packet.resize(packet.len() + header.payload_len.unwrap_or(0) as usize, 0);
+        Ok(packet)
+    }
+
+    /// Answer an MTU probe of `size` bytes from `src`
+    async fn send_mtu_probe_ack(&mut self, size: usize, src: SocketAddr) -> Result<()> {
+        let payload = serde_cbor::to_vec(&MtuProbeAckFrame { size: size as u32 })?;
+        let mut header = Header::new(0, FRAME_TYPE_MTU_PROBE_ACK, 0, 0, 0, 0, DeliveryMode::BestEffort, None, Some(payload.len() as u32));
+        header.connection_id = Some(ConnectionId::from_u64(self.session.session_id));
+        let header_bytes = self.header_codec().encode_header(&header)?;
         
         let mut packet = self.packet_pool.acquire();
         packet.reserve(2 + header_bytes.len() + payload.len());
-        packet.extend_from_slice(&header_len.to_be_bytes());
+        packet.extend_from_slice(&(header_bytes.len() as u16).to_be_bytes());
         packet.extend_from_slice(&header_bytes);
         packet.extend_from_slice(&payload);
-        
-        self.transport.send_to(&packet, self.peer_addr).await?;
+        let result = self.transport.send_to(&packet, src).await;
         self.packet_pool.release(packet);
-        
+        result?;
         Ok(())
     }
 
-    pub async fn discover_public_address(&mut self) -> Result<Option<SocketAddr>> {
+    /// Ask the STUN servers for our public address
+    ///
+    /// An address discovered within `stun_cache_ttl` is returned without
+    /// querying again, unless `force` is set.
+    pub async fn discover_public_address(&mut self, force: bool) -> Result<Option<SocketAddr>, TransportError> {
         if self.stun_server_addrs.is_empty() {
             return Ok(None);
         }
 
-        let servers = self.stun_server_addrs.clone();
+        let ttl = self.config.stun_cache_ttl;
+        if !force && self.public_addr_at.is_some_and(|at| at.elapsed() < ttl) {
+            return Ok(self.public_addr);
+        }
 
-        for server_addr in servers {
-            let req = StunMessage::binding_request();
-            let payload = req.to_bytes();
-            
-            // We need to wrap this in a STUN frame
-            #[allow(clippy::too_many_arguments)]
-            let header = Header::new(
-                0,
-                FRAME_TYPE_STUN,
-                0,
-                0,
-                0,
-                0,
-                Default::default(),
-                None,
-                Some(payload.len() as u32)
-            );
-            
-            let header_bytes = serde_cbor::to_vec(&header)?;
-            let header_len = header_bytes.len() as u16;
-            
-            let mut packet = BytesMut::with_capacity(2 + header_bytes.len() + payload.len());
-            packet.extend_from_slice(&header_len.to_be_bytes());
-            packet.extend_from_slice(&header_bytes);
-            packet.extend_from_slice(&payload);
-            
-            self.transport.send_to(&packet, server_addr).await?;
-            
-            // Wait for response with timeout
-            let timeout = self.config.stun_timeout;
-            let start = std::time::Instant::now();
-            
-            while start.elapsed() < timeout {
-                match tokio::time::timeout(Duration::from_millis(100), self.recv()).await {
-                    Ok(Ok(_)) => {
-                        if let Some(addr) = self.public_addr {
-                            return Ok(Some(addr));
-                        }
-                    }
-                    Ok(Err(e)) => tracing::warn!("Error receiving during STUN: {}", e),
-                    Err(_) => continue, // timeout
-                }
+        // The first valid answer from any server wins
+        self.query_stun_servers(|connection| connection.public_addr_at.is_some()).await?;
+        Ok(self.public_addr_at.and(self.public_addr))
+    }
+
+    /// Ask every STUN server for our public address and compare the answers
+    ///
+    /// Waits up to `stun_timeout` for all servers. With fewer than two
+    /// answers a translated address can't be told apart from a symmetric
+    /// NAT, and the result is `NatType::Unknown`.
+    pub async fn detect_nat_type(&mut self) -> Result<NatType, TransportError> {
+        self.public_addr_at = None;
+        self.query_stun_servers(|_| false).await?;
+        Ok(self.nat_type)
+    }
+
+    /// NAT type found by the last `detect_nat_type` (or discovery that
+    /// heard from several servers)
+    pub fn nat_type(&self) -> NatType {
+        self.nat_type
+    }
+
+    /// Send a binding request to every STUN server at once, then take
+    /// answers until `done`, until all servers answered, or until
+    /// `stun_timeout`
+    async fn query_stun_servers(&mut self, done: fn(&Self) -> bool) -> Result<()> {
+        self.stun_transactions.clear();
+        self.stun_mappings.clear();
+        for server_addr in self.stun_server_addrs.clone() {
+            let request = StunMessage::binding_request();
+            let packet = stun_packet(&request)?;
+            // A server we can't reach is as good as one that doesn't answer
+            if let Err(e) = self.transport.send_to(&packet, server_addr).await {
+                tracing::warn!(server = %server_addr, "Failed to send STUN binding request: {}", e);
+                continue;
             }
+            self.stun_transactions.insert(request.transaction_id, server_addr);
+        }
+
+        let deadline = tokio::time::Instant::now() + self.config.stun_timeout;
+        while !done(self) && !self.stun_transactions.is_empty() {
+            match tokio::time::timeout_at(deadline, self.process_incoming()).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => tracing::warn!("Error receiving during STUN: {}", e),
+                Err(_) => break,
+            }
+        }
+        // Late answers belong to a round nobody waits for anymore
+        self.stun_transactions.clear();
+        Ok(())
+    }
+
+    /// A binding response from `src`, accepted only if it answers a request
+    /// we sent to that very server
+    fn on_stun_response(&mut self, msg: &StunMessage, src: SocketAddr) {
+        if self.stun_transactions.get(&msg.transaction_id) != Some(&src) {
+            tracing::debug!(%src, "Ignoring STUN response to no request of ours");
+            return;
+        }
+        self.stun_transactions.remove(&msg.transaction_id);
+        let addr = match msg.get_mapped_address() {
+            Some(addr) => addr,
+            None => return,
+        };
+        self.stun_mappings.insert(src, addr);
+
+        if self.public_addr_at.is_none() {
+            if self.public_addr != Some(addr) {
+                self.pending_events.push_back(ConnectionEvent::PublicAddressDiscovered(addr));
+            }
+            self.public_addr = Some(addr);
+            self.public_addr_at = Some(std::time::Instant::now());
+            tracing::info!("Discovered public address: {}", addr);
+        }
+
+        if let Ok(local) = self.transport.local_addr() {
+            let mapped: Vec<SocketAddr> = self.stun_mappings.values().copied().collect();
+            self.nat_type = NatType::classify(local, &mapped);
         }
-        
-        Ok(None)
     }
 
-    pub async fn listen(bind_addr: &str) -> Result<Self> {
+    pub async fn listen(bind_addr: &str) -> Result<Self, TransportError> {
         Self::listen_with_config(bind_addr, ConnectionConfig::default()).await
     }
 
-    pub async fn listen_with_config(bind_addr: &str, config: ConnectionConfig) -> Result<Self> {
+    pub async fn listen_with_config(bind_addr: &str, config: ConnectionConfig) -> Result<Self, TransportError> {
         let mut connection = Self::bind_with_config(bind_addr, config).await?;
         tracing::info!(bind_addr, "Listening for incoming connection");
         
@@ -306,7 +1006,25 @@ impl Connection {
         Ok(connection)
     }
 
-    pub async fn handshake(&mut self) -> Result<()> {
+    pub async fn handshake(&mut self) -> Result<(), TransportError> {
+        let mut span = self.child_span("handshake");
+        let result = self.perform_handshake().await.map_err(TransportError::from);
+        if let Some(span) = &mut span {
+            span.set_attribute("session.id", self.session.session_id.to_string());
+            span.set_attribute("round_trips", self.handshake_round_trips.to_string());
+            if let Err(e) = &result {
+                span.record_error(e);
+            }
+        }
+        result
+    }
+
+    async fn perform_handshake(&mut self) -> Result<()> {
+        if !self.is_server && self.session.state == SessionState::Established {
+            // Already completed by a racing/ordered connect
+            return Ok(());
+        }
+
         if self.is_server {
             // Server side handshake
             tracing::info!("Waiting for incoming handshake...");
@@ -318,7 +1036,8 @@ impl Connection {
             // Update peer address
             self.peer_addr = peer_addr;
             
-            let client_hello = self.session.process_client_hello(&buf[..len])?;
+            let client_hello = self.session.process_client_hello(&buf[..len])
+                .map_err(TransportError::crypto)?;
             
             // Select cipher suite
             let cipher_suite = client_hello.cipher_suites
@@ -329,65 +1048,270 @@ impl Connection {
             // Generate ServerHello
             // For simple Connection, we use session_id 1 or random
             let session_id = 1;
-            let (server_hello, kyber_shared) = self.session.generate_server_hello(
-                session_id,
-                cipher_suite,
-                &client_hello.kyber_public_key,
-                &client_hello.supported_formats  // Pass client's supported formats
-            )?;
             
-            // Derive keys
-            self.session.derive_keys_from_client_hello(&client_hello.public_key, Some(&kyber_shared));
-            
-            // Send ServerHello
-            self.transport.send_to(&server_hello, peer_addr).await?;
+            // A valid ticket restores the previous session and skips the key exchange.
+            // Replayed ClientHellos are refused before the ticket is looked at.
+            let resumption_state = match (&client_hello.session_ticket, &self.config.ticket_store) {
+                (Some(ticket), Some(store)) => match store.check_replay(client_hello.nonce, client_hello.timestamp) {
+                    Ok(()) => store.redeem(ticket),
+                    Err(e) => {
+                        tracing::warn!(peer = %peer_addr, "Refusing resumption: {}", e);
+                        None
+                    }
+                },
+                _ => None,
+            };
             
-            tracing::info!(
-                peer = %peer_addr,
+            let server_hello = match resumption_state {
+                Some(state) => self.session.generate_resumed_server_hello(
+                    session_id,
+                    cipher_suite,
+                    &state,
+                    &client_hello.supported_formats
+                )?,
+                None => {
+                    if client_hello.session_ticket.is_some() {
+                        tracing::debug!(peer = %peer_addr, "Session ticket rejected, falling back to full handshake");
+                    }
+                    
+                    let (server_hello, kyber_shared) = self.session.generate_server_hello(
+                        session_id,
+                        cipher_suite,
+                        &client_hello.kyber_public_key,
+                        &client_hello.supported_formats  // Pass client's supported formats
+                    ).map_err(TransportError::crypto)?;
+                    
+                    // Derive keys
+                    self.session.derive_keys_from_client_hello(&client_hello.public_key, Some(&kyber_shared));
+                    server_hello
+                }
+            };
+            
+            // Send ServerHello, kept in case the client retransmits its hello
+            self.transport.send_to(&server_hello, peer_addr).await?;
+            self.server_hello = Some((client_hello.nonce, server_hello));
+            
+            tracing::info!(
+                peer = %peer_addr,
                 session_id,
+                resumed = self.session.is_resumed(),
                 "Handshake accepted"
             );
-        } else {
-            // Client side handshake
-            let hello = self.session.generate_client_hello()?;
-            self.transport.send_to(&hello, self.peer_addr).await?;
             
-            tracing::info!(peer = %self.peer_addr, "Handshake initiated");
+            // Hand the client a ticket for its next connection
+            self.send_session_ticket().await?;
+        } else {
+            // Client side handshake (already initiated when resuming from a ticket)
+            let hello = match self.client_hello.take() {
+                Some(hello) if self.session.state == SessionState::HelloSent => hello,
+                _ => {
+                    let hello = self.session.generate_client_hello()?;
+                    self.transport.send_to(&hello, self.peer_addr).await?;
+                    
+                    tracing::info!(peer = %self.peer_addr, "Handshake initiated");
+                    hello
+                }
+            };
             
-            // Simple wait for response (blocking for now, should be loop)
-            let mut buf = [0u8; 2048];
-            let (len, _src) = self.transport.recv_from(&mut buf).await?;
+            // Resends the hello until the ServerHello arrives
+            let server_hello = crate::transport_race::await_server_hello(&self.transport, self.peer_addr, &hello, &self.config).await?;
+            self.session.process_server_hello(&server_hello)
+                .map_err(TransportError::crypto)?;
             
-            self.session.process_server_hello(&buf[..len])?;
+            // Early data saved the round trip only if the server accepted it
+            let early_data = std::mem::take(&mut self.early_data);
+            self.handshake_round_trips = if self.session.is_resumed() && !early_data.is_empty() { 0 } else { 1 };
             
             tracing::info!(
                 peer = %self.peer_addr,
                 session_id = self.session.session_id,
+                resumed = self.session.is_resumed(),
                 "Handshake completed"
             );
+            
+            if !self.session.is_resumed() && !early_data.is_empty() {
+                tracing::debug!(
+                    peer = %self.peer_addr,
+                    messages = early_data.len(),
+                    "Early data rejected, resending"
+                );
+                self.start_background_tasks();
+                for (stream_id, data) in early_data {
+                    self.send_on_stream(stream_id, &data).await?;
+                }
+                return Ok(());
+            }
         }
         
+        self.start_background_tasks();
+        
+        Ok(())
+    }
+
+    /// Start background tasks after a successful handshake
+    fn start_background_tasks(&mut self) {
         // Start heartbeat after successful handshake
         self.start_heartbeat();
         
+        // Subflows need the peer address, which servers only learn in the handshake.
+        // A sender task started early for 0-RTT data keeps using the primary path.
+        if self.config.mptcp_config.enabled && self.mptcp.is_none() {
+            let manager = MptcpManager::new(self.config.mptcp_config.clone(), self.peer_addr)
+                .with_handshake(self.subflow_handshake());
+            let manager = Arc::new(manager);
+            let watcher = Arc::clone(&manager);
+            tokio::spawn(async move { watcher.start().await });
+            self.mptcp = Some(manager);
+        }
+        
         // Start flush task if coalescing is enabled
         self.start_flush_task();
         
-        // Start sender task for QoS
-        self.start_sender_task();
+        if self.config.auto_rebind && !self.is_server && self.transport.as_udp().is_some() {
+            let watcher = InterfaceWatcher::new();
+            self.interfaces = Some(watcher.subscribe());
+            self._interface_watcher = Some(watcher);
+        }
+        
+        // Start sender task for QoS (already running if early data was allowed)
+        if self.sender_task.is_none() {
+            self.start_sender_task();
+        }
+        
+        self.restart_mtu_discovery();
+    }
+
+    /// Restart the running tasks that send on the path, after the transport or peer address changed
+    fn restart_path_tasks(&mut self) {
+        if let Some(task) = self.heartbeat_task.take() {
+            task.abort();
+            self.start_heartbeat();
+        }
+        if let Some(task) = self.flush_task.take() {
+            task.abort();
+            self.start_flush_task();
+        }
+        if let Some(task) = self.sender_task.take() {
+            task.abort();
+            self.start_sender_task();
+            // Packets still queued go out on the new path
+            self.sender_notify.notify_one();
+        }
+    }
+
+    /// Whether a broken local path may be replaced automatically now
+    fn can_rebind(&self) -> bool {
+        self.config.auto_rebind
+            && !self.is_server
+            && self.transport.as_udp().is_some()
+            // A socket that fails again right away is not fixed by rebinding
+            && self.last_rebind.is_none_or(|at| at.elapsed() >= Duration::from_secs(1))
+    }
+
+    /// Whether the address our socket is bound to no longer belongs to any interface
+    fn local_interface_lost(&self) -> bool {
+        let (interfaces, local) = match (&self.interfaces, self.transport.local_addr()) {
+            (Some(interfaces), Ok(local)) => (interfaces.borrow(), local),
+            _ => return false,
+        };
+        // Wildcard sockets follow the routing table; an empty list is a failed scan
+        if local.ip().is_unspecified() || local.ip().is_loopback() || interfaces.is_empty() {
+            return false;
+        }
+        !interfaces.iter().any(|interface| interface.ip == local.ip())
+    }
+
+    /// Move to a new local socket on whatever interface is available and migrate to it
+    async fn rebind(&mut self) -> Result<()> {
+        self.last_rebind = Some(std::time::Instant::now());
+        let old_local = self.transport.local_addr()?;
+        let ip: std::net::IpAddr = match old_local {
+            addr if addr.ip().is_loopback() => addr.ip(),
+            SocketAddr::V4(_) => std::net::Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => std::net::Ipv6Addr::UNSPECIFIED.into(),
+        };
+        
+        let transport = UdpTransport::bind(&SocketAddr::new(ip, 0).to_string()).await?;
+        self.switch_path(transport.into()).await?;
+        let new_local = self.transport.local_addr()?;
+        tracing::info!(%old_local, %new_local, "Rebound to a new local socket");
+        self.pending_events.push_back(ConnectionEvent::Rebound { old_local, new_local });
+        Ok(())
+    }
+
+    /// Issue a session ticket the client can use to resume with 0-RTT
+    ///
+    /// The ticket is sealed with the session key like stream data, whether
+    /// or not key updates are on, so only the client can read it.
+    async fn send_session_ticket(&mut self) -> Result<()> {
+        let store = match &self.config.ticket_store {
+            Some(store) => store.clone(),
+            None => return Ok(()),
+        };
+        
+        let ticket = store.issue(&self.session)?;
+        let nonce = self.next_packet_nonce;
+        self.next_packet_nonce += 1;
+        
+        let plaintext = serde_cbor::to_vec(&ticket)?;
+        let header = Header::new(
+            0,
+            FRAME_TYPE_SESSION_TICKET,
+            key_phase_flags(self.session.crypto.key_phase()),
+            0,
+            std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_millis() as u64,
+            nonce,
+            DeliveryMode::Reliable,
+            None,
+            Some((plaintext.len() + AEAD_TAG_LEN) as u32),
+        );
+        let payload = self.session.crypto.encrypt_packet(self.is_server as u32, nonce, &plaintext, &header_aad(&header))?;
+        
+        let header_bytes = self.header_codec().encode_header(&header)?;
+        let header_len = header_bytes.len() as u16;
+        
+        let mut packet = self.packet_pool.acquire();
+        packet.reserve(2 + header_bytes.len() + payload.len());
+        packet.extend_from_slice(&header_len.to_be_bytes());
+        packet.extend_from_slice(&header_bytes);
+        packet.extend_from_slice(&payload);
+        
+        self.transport.send_to(&packet, self.peer_addr).await?;
+        self.packet_pool.release(packet);
         
         Ok(())
     }
 
     /// Update application state (Foreground/Background)
+    ///
+    /// In the background adaptive compression falls back to cheap LZ4.
     pub async fn set_app_state(&self, state: crate::heartbeat::AppState) {
         self.heartbeat.set_app_state(state).await;
+        self.adaptive_compression.lock().unwrap()
+            .set_power_saving(state == crate::heartbeat::AppState::Background);
     }
 
     /// Update network type
-    pub async fn set_network_type(&self, net_type: crate::network_status::NetworkType) {
-        self.network_status.set_network_type(net_type).await;
+    ///
+    /// Heartbeats follow the new network's interval (longer on cellular).
+    /// Switching from one known network to another also means a new path,
+    /// so RTT and bandwidth estimates start over in slow start (or BBR
+    /// Startup). Any change is reported as `ConnectionEvent::NetworkChanged`.
+    pub async fn set_network_type(&mut self, net_type: crate::network_status::NetworkType) {
+        use crate::network_status::NetworkType;
+        let old = match self.network_status.set_network_type(net_type).await {
+            Some(old) => old,
+            None => return,
+        };
+        self.heartbeat.set_network_type(net_type).await;
+        // Learning the first network type is no switch
+        if old != NetworkType::Unknown {
+            self.reliability.on_path_changed();
+            tracing::info!(peer = %self.peer_addr, ?old, new = ?net_type, "Network switched, restarting congestion control");
+        }
+        self.pending_events.push_back(ConnectionEvent::NetworkChanged { old, new: net_type });
     }
+
     /// Get reference to heartbeat manager (for testing/monitoring)
     pub fn heartbeat(&self) -> &HeartbeatManager {
         &self.heartbeat
@@ -446,8 +1370,11 @@ impl Connection {
         
         let buffer = Arc::clone(&self.coalescing_buffer);
         let last_flush = Arc::clone(&self.last_coalesce_flush);
-        let transport = self.transport.clone();
-        let peer_addr = self.peer_addr;
+        let path = SendPath {
+            transport: self.transport.clone(),
+            peer_addr: self.peer_addr,
+            mptcp: self.mptcp.clone(),
+        };
         let window_ms = self.config.coalescing_window_ms;
         let closing = Arc::clone(&self.closing);
         
@@ -481,7 +1408,7 @@ impl Connection {
                         data
                     };
                     
-                    if let Err(e) = transport.send_to(&data, peer_addr).await {
+                    if let Err(e) = path.send(&data).await {
                         tracing::warn!("Background flush failed: {}", e);
                     } else {
                         *last_flush.lock().unwrap() = std::time::Instant::now();
@@ -499,8 +1426,13 @@ impl Connection {
         let priority_queue: Arc<Mutex<PriorityQueue<Vec<u8>>>> = Arc::clone(&self.priority_queue);
         let metrics = Arc::clone(&self.metrics);
         let sender_notify = Arc::clone(&self.sender_notify);
-        let transport = self.transport.clone();
-        let peer_addr = self.peer_addr;
+        // Senders waiting for room in the queue wait on the capacity signal
+        let capacity = self.reliability.capacity_notify();
+        let path = SendPath {
+            transport: self.transport.clone(),
+            peer_addr: self.peer_addr,
+            mptcp: self.mptcp.clone(),
+        };
         let closing = Arc::clone(&self.closing);
         let circuit_breaker = Arc::clone(&self.circuit_breaker);
         
@@ -508,6 +1440,9 @@ impl Connection {
         let coalescing_buffer = Arc::clone(&self.coalescing_buffer);
         let last_flush = Arc::clone(&self.last_coalesce_flush);
         let config = self.config.clone();
+        // Coalesced datagrams stay within the path MTU, and packets the
+        // socket refuses as too big lower it
+        let mtu_discovery = config.mtu_discovery.enabled.then(|| Arc::clone(&self.mtu_discovery));
         
         let task = tokio::spawn(async move {
             loop {
@@ -527,6 +1462,8 @@ impl Connection {
                     
                     match packet {
                         Some(data) => {
+                            capacity.notify_one();
+                            
                             // Check if coalescing is enabled
                             if config.coalescing_window_ms > 0 {
                                 let should_flush = {
@@ -535,7 +1472,7 @@ impl Connection {
                                     let now = std::time::Instant::now();
                                     
                                     // If adding this packet would exceed max size, or time window passed
-                                    if buf.len() + data.len() > config.pool_max_packet_size {
+                                    if buf.len() + data.len() > coalesce_limit(&config, mtu_discovery.as_deref()) {
                                         true
                                     } else if !buf.is_empty() && now.duration_since(*last).as_millis() as u64 >= config.coalescing_window_ms {
                                         true
@@ -561,7 +1498,7 @@ impl Connection {
                                     
                                     if let Some(d) = flush_data {
                                         let len = d.len();
-                                        match transport.send_to(&d, peer_addr).await {
+                                        match path.send(&d).await {
                                             Ok(_) => {
                                                 circuit_breaker.record_success();
                                                 metrics.record_packet_sent(len);
@@ -570,6 +1507,7 @@ impl Connection {
                                             Err(e) => {
                                                 circuit_breaker.record_failure();
                                                 metrics.record_error();
+                                                report_too_big(mtu_discovery.as_deref(), &e, len);
                                                 tracing::warn!("Coalesced flush failed: {}", e);
                                             }
                                         }
@@ -577,26 +1515,52 @@ impl Connection {
                                     
                                     // Now handle current packet
                                     // If it fits in empty buffer, add it. Else send directly.
-                                    if data.len() <= config.pool_max_packet_size {
+                                    if data.len() <= coalesce_limit(&config, mtu_discovery.as_deref()) {
                                         let mut buf = coalescing_buffer.lock().unwrap();
                                         buf.extend_from_slice(&data);
                                     } else {
                                         // Too big for coalescing, send directly
-                                        match transport.send_to(&data, peer_addr).await {
-                                            Ok(_) => circuit_breaker.record_success(),
+                                        match path.send(&data).await {
+                                            Ok(_) => {
+                                                circuit_breaker.record_success();
+                                                metrics.record_packet_sent(data.len());
+                                            }
                                             Err(e) => {
                                                 circuit_breaker.record_failure();
+                                                report_too_big(mtu_discovery.as_deref(), &e, data.len());
                                                 tracing::warn!("Direct send failed: {}", e);
                                             }
                                         }
                                     }
                                 }
                             } else {
-                                // No coalescing, send directly
-                                match transport.send_to(&data, peer_addr).await {
-                                    Ok(_) => circuit_breaker.record_success(),
+                                // No coalescing: send it along with whatever else is
+                                // queued, up to a batch per syscall
+                                let mut batch = vec![data];
+                                {
+                                    let mut queue = priority_queue.lock().unwrap();
+                                    while batch.len() < config.io_batch_size {
+                                        match queue.dequeue() {
+                                            Some(packet) => {
+                                                capacity.notify_one();
+                                                batch.push(packet);
+                                            }
+                                            None => break,
+                                        }
+                                    }
+                                }
+                                match path.send_batch(&batch).await {
+                                    Ok(()) => {
+                                        circuit_breaker.record_success();
+                                        for packet in &batch {
+                                            metrics.record_packet_sent(packet.len());
+                                        }
+                                    }
                                     Err(e) => {
                                         circuit_breaker.record_failure();
+                                        // Which packet was refused isn't known; blame the largest
+                                        let largest = batch.iter().map(Vec::len).max().unwrap_or(0);
+                                        report_too_big(mtu_discovery.as_deref(), &e, largest);
                                         tracing::warn!("Sender task failed to send: {}", e);
                                     }
                                 }
@@ -612,19 +1576,217 @@ impl Connection {
     }
 
     /// Send data on a specific stream
-    pub async fn send_on_stream(&mut self, stream_id: u32, data: &[u8]) -> Result<()> {
+    ///
+    /// Fails fast when the rate limiter or congestion window has no capacity;
+    /// see `send_on_stream_wait` for a variant that waits instead.
+    pub async fn send_on_stream(&mut self, stream_id: u32, data: &[u8]) -> Result<(), TransportError> {
+        let mut span = self.child_span("send_on_stream");
+        let result = self.try_send_on_stream(stream_id, data).await;
+        if let Some(span) = &mut span {
+            span.set_attribute("stream.id", stream_id.to_string());
+            span.set_attribute("bytes", data.len().to_string());
+            if let Err(e) = &result {
+                span.record_error(e);
+            }
+        }
+        result
+    }
+
+    /// Send data on a stream, waiting for rate limiter and congestion window capacity
+    ///
+    /// While waiting, incoming packets are processed so ACKs can open the window;
+    /// any data they deliver is returned by the next `recv()`. Returns an error if
+    /// `timeout` elapses before the data could be sent.
+    pub async fn send_on_stream_wait(&mut self, stream_id: u32, data: &[u8], timeout: Option<Duration>) -> Result<(), TransportError> {
+        let deadline = timeout.map(|t| tokio::time::Instant::now() + t);
+        
+        loop {
+            if self.closing.load(Ordering::Relaxed) {
+                return Err(self.closed_error());
+            }
+            
+            let rate_wait = self.rate_limiter.time_until_available(data.len())
+                .ok_or(TransportError::ExceedsRateLimitBurst(data.len()))?;
+            
+            if rate_wait.is_zero() && self.reliability.can_send() && self.reliability.window_allows(data.len())
+                && self.send_queue_admits(stream_id, data.len())
+            {
+                return self.try_send_on_stream(stream_id, data).await;
+            }
+            
+            // Re-check at least every RTO-ish interval in case no ACK ever arrives
+            let wait = if rate_wait.is_zero() { Duration::from_millis(200) } else { rate_wait };
+            let capacity = self.reliability.capacity_notify();
+            let deadline_sleep = async {
+                match deadline {
+                    Some(d) => tokio::time::sleep_until(d).await,
+                    None => std::future::pending().await,
+                }
+            };
+            
+            tokio::select! {
+                _ = capacity.notified() => {}
+                _ = tokio::time::sleep(wait) => {}
+                _ = deadline_sleep => {
+                    return Err(TransportError::SendTimeout);
+                }
+                res = self.process_incoming() => {
+                    if let Err(e) = res {
+                        tracing::debug!(peer = %self.peer_addr, "Receive while waiting for capacity failed: {}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether the send queue has room for a message of `len` bytes on the stream
+    ///
+    /// Makes room by shedding expired and BestEffort packets. BestEffort data
+    /// is always admitted, the queue drops it when full.
+    fn send_queue_admits(&self, stream_id: u32, len: usize) -> bool {
+        let best_effort = self.session.streams().get_stream(stream_id)
+            .is_some_and(|stream| stream.delivery_mode == DeliveryMode::BestEffort);
+        best_effort || self.lock_send_queue().make_room(self.packets_for(len), len)
+    }
+
+    /// Send data on a specific stream, failing fast when there is no capacity
+    ///
+    /// Payloads larger than `max_fragment_size` are split into fragments
+    /// that the peer reassembles before delivery.
+    pub async fn try_send_on_stream(&mut self, stream_id: u32, data: &[u8]) -> Result<(), TransportError> {
+        let (delivery_mode, priority) = self.check_send(stream_id, data.len())?;
+        
+        // Check the send queue; BestEffort data is shed by the queue instead
+        if !self.send_queue_admits(stream_id, data.len()) {
+            tracing::debug!(
+                peer = %self.peer_addr,
+                stream_id,
+                "Send queue full"
+            );
+            return Err(TransportError::SendQueueFull);
+        }
+        
+        // Update session activity
+        self.session.update_activity();
+        self.maybe_update_keys().await?;
+        self.update_adaptive_compression();
+        
+        self.check_circuit(stream_id)?;
+        let packets = self.build_message(stream_id, delivery_mode, data)
+            .map_err(|e| {
+                self.circuit_breaker.release_probe();
+                e
+            })?;
+        let fragment_count = packets.len();
+        Self::enqueue_message(&mut self.lock_send_queue(), packets, priority, delivery_mode);
+        
+        // Notify sender
+        self.sender_notify.notify_one();
+        self.count_message_sent(stream_id, data.len());
+        
+        tracing::trace!(
+            peer = %self.peer_addr,
+            stream_id,
+            fragments = fragment_count,
+            ?delivery_mode,
+            bytes = data.len(),
+            "Data sent on stream"
+        );
+        
+        Ok(())
+    }
+
+    /// Send several messages, taking the send queue lock and waking the
+    /// sender task once for all of them
+    ///
+    /// Each item is checked and framed as `try_send_on_stream` would, with
+    /// its stream's delivery mode and priority, so items on one stream go
+    /// out in order. Sending stops at the first item that can't be sent:
+    /// the items before it are still sent, and the error names the item.
+    /// Whether keys are due for an update is checked once per batch.
+    pub async fn send_batch(&mut self, items: &[(u32, &[u8])]) -> Result<(), TransportError> {
+        if items.is_empty() {
+            return Ok(());
+        }
+        let mut span = self.child_span("send_batch");
+        
+        self.session.update_activity();
+        self.maybe_update_keys().await?;
+        self.update_adaptive_compression();
+        
+        // Framing happens under the lock, so the queue has room for every
+        // packet that gets a sequence number
+        let mut sent = 0;
+        let mut result = Ok(());
+        {
+            let send_queue = Arc::clone(&self.priority_queue);
+            self.send_queue_locks.fetch_add(1, Ordering::Relaxed);
+            let mut queue = send_queue.lock().unwrap();
+            for &(stream_id, data) in items {
+                if let Err(e) = self.enqueue_batch_item(&mut queue, stream_id, data) {
+                    result = Err(TransportError::BatchItemFailed { index: sent, stream_id, source: Box::new(e) });
+                    break;
+                }
+                sent += 1;
+            }
+        }
+        if sent > 0 {
+            self.sender_notify.notify_one();
+        }
+        
+        tracing::trace!(peer = %self.peer_addr, messages = sent, "Batch sent");
+        if let Some(span) = &mut span {
+            span.set_attribute("messages", sent.to_string());
+            if let Err(e) = &result {
+                span.record_error(e);
+            }
+        }
+        result
+    }
+
+    /// One `send_batch` item, with the send queue locked
+    fn enqueue_batch_item(&mut self, queue: &mut PriorityQueue<Vec<u8>>, stream_id: u32, data: &[u8]) -> Result<(), TransportError> {
+        let (delivery_mode, priority) = self.check_send(stream_id, data.len())?;
+        if delivery_mode != DeliveryMode::BestEffort && !queue.make_room(self.packets_for(data.len()), data.len()) {
+            return Err(TransportError::SendQueueFull);
+        }
+        self.check_circuit(stream_id)?;
+        let packets = self.build_message(stream_id, delivery_mode, data)
+            .map_err(|e| {
+                self.circuit_breaker.release_probe();
+                e
+            })?;
+        Self::enqueue_message(queue, packets, priority, delivery_mode);
+        self.count_message_sent(stream_id, data.len());
+        Ok(())
+    }
+
+    /// Checks a send of `len` bytes must pass before it is queued, consuming
+    /// rate limiter tokens; returns the stream's delivery mode and priority
+    ///
+    /// The circuit breaker is left to `check_circuit`, once the send queue
+    /// is known to have room.
+    fn check_send(&mut self, stream_id: u32, len: usize) -> Result<(DeliveryMode, QosPriority), TransportError> {
         if self.closing.load(Ordering::Relaxed) {
-            return Err(anyhow::anyhow!("Connection is closing"));
+            return Err(self.closed_error());
+        }
+        
+        if self.stream_fins.get(&stream_id).is_some_and(|fin| fin.sent) {
+            return Err(TransportError::StreamClosed(stream_id));
+        }
+        
+        if self.packets_for(len) > u16::MAX as usize {
+            return Err(TransportError::MessageTooLarge(len));
         }
         
         // Check rate limit
-        if !self.rate_limiter.check_and_consume(data.len()) {
+        if !self.rate_limiter.check_and_consume(len) {
             tracing::warn!(
                 peer = %self.peer_addr,
                 stream_id,
                 "Rate limit exceeded"
             );
-            return Err(anyhow::anyhow!("Rate limit exceeded"));
+            return Err(TransportError::RateLimited);
         }
         
         // Check congestion window
@@ -634,104 +1796,507 @@ impl Connection {
                 stream_id,
                 "Congestion window full"
             );
-            return Err(anyhow::anyhow!("Congestion window full"));
+            return Err(TransportError::CongestionLimited);
         }
         
-        // Check circuit breaker
-        if !self.circuit_breaker.allow_request() {
-             tracing::warn!(
+        // Check the peer's receive window
+        if !self.reliability.window_allows(len) {
+             tracing::debug!(
                 peer = %self.peer_addr,
                 stream_id,
-                "Circuit breaker open, request rejected"
+                "Peer receive window full"
             );
-            return Err(anyhow::anyhow!("Circuit breaker open"));
+            return Err(TransportError::ReceiveWindowFull);
         }
         
         // Get stream to determine delivery mode and priority
-        let (delivery_mode, priority) = {
-            let stream = self.session.streams()
-                .get_stream(stream_id)
-                .ok_or_else(|| anyhow::anyhow!("Stream not found"))?;
-            (stream.delivery_mode, stream.priority)
+        let stream = self.session.streams()
+            .get_stream(stream_id)
+            .ok_or(TransportError::StreamNotFound(stream_id))?;
+        Ok((stream.delivery_mode, QosPriority::from_value(stream.priority).unwrap_or_default()))
+    }
+
+    /// Let a send through the circuit breaker
+    ///
+    /// Half-open, an allowed send is a probe and must actually be queued, so
+    /// this comes after every other check, right before framing.
+    fn check_circuit(&mut self, stream_id: u32) -> Result<(), TransportError> {
+        let allowed = self.circuit_breaker.allow_request();
+        self.queue_circuit_events();
+        if !allowed {
+             tracing::warn!(
+                peer = %self.peer_addr,
+                stream_id,
+                "Circuit breaker open, request rejected"
+            );
+            return Err(TransportError::CircuitOpen);
+        }
+        Ok(())
+    }
+
+    /// State of the circuit breaker guarding sends
+    ///
+    /// Open after `circuit_breaker.failure_threshold` consecutive failed
+    /// socket sends; sends then fail with `TransportError::CircuitOpen`
+    /// until `reset_timeout` has passed and enough half-open probes succeed.
+    pub fn circuit_state(&self) -> CircuitState {
+        self.circuit_breaker.state()
+    }
+
+    /// Queue circuit breaker transitions as `CircuitStateChanged` events
+    fn queue_circuit_events(&mut self) {
+        let transitions = std::mem::take(&mut *self.circuit_transitions.lock().unwrap());
+        self.pending_events.extend(transitions.into_iter()
+            .map(|(old, new)| ConnectionEvent::CircuitStateChanged { old, new }));
+    }
+
+    /// Packets a message of `len` bytes takes; an empty one still takes one
+    fn packets_for(&self, len: usize) -> usize {
+        len.div_ceil(self.max_fragment()).max(1)
+    }
+
+    /// Feed the current RTT and loss to adaptive compression
+    fn update_adaptive_compression(&self) {
+        let rtt = self.metrics.get_avg_rtt();
+        // Simple packet loss estimation: (retransmits / total_sent)
+        let loss = self.reliability.loss_rate();
+        
+        let mut adaptive = self.adaptive_compression.lock().unwrap();
+        adaptive.update_metrics(rtt, loss);
+    }
+
+    /// Frame one message, split into fragments past `max_fragment_size`
+    /// (or what the path MTU leaves room for, with discovery on)
+    ///
+    /// Returns its packets, each with the payload bytes it carries.
+    fn build_message(&mut self, stream_id: u32, delivery_mode: DeliveryMode, data: &[u8]) -> Result<Vec<(Vec<u8>, usize)>> {
+        self.sync_path_mss();
+        let original = data;
+        // Compressed before sealing, as ciphertext doesn't compress
+        let compressed = if self.config.enable_payload_compression {
+            compression::compress_payload(self.compression_decision(), data)?
+        } else {
+            None
         };
+        let data = compressed.as_deref().unwrap_or(data);
+        // With a Double Ratchet every message gets its own key
+        let sealed = match self.session.double_ratchet_mut() {
+            Some(ratchet) => Some(ratchet.encrypt(data).map_err(TransportError::crypto)?.to_bytes()),
+            None => None,
+        };
+        let data = sealed.as_deref().unwrap_or(data);
+        let max_fragment = self.max_fragment();
+        let fragment_count = self.packets_for(data.len());
+        if fragment_count > u16::MAX as usize {
+            return Err(anyhow::anyhow!("Message too large: {} bytes", data.len()));
+        }
         
-        // Update session activity
-        self.session.update_activity();
-        
-        // Get next sequence number
-        let seq = self.reliability.next_sequence();
+        // Data sent before a resumed handshake completes is 0-RTT early data
+        let early = self.session.state == SessionState::HelloSent && self.session.session_ticket.is_some();
+        let mut base_flags = if early { FLAG_EARLY_DATA } else { 0 };
+        if compressed.is_some() {
+            base_flags |= FLAG_COMPRESSED;
+        }
+        if early {
+            // Resent through `send_on_stream` if rejected, which compresses and seals it again
+            self.early_data.push((stream_id, original.to_vec()));
+        }
         
-        // Track packet if needed (Reliable or PartiallyReliable)
-        if delivery_mode.requires_retransmit() {
-            self.reliability.track_sent_packet(seq, Bytes::copy_from_slice(data), delivery_mode);
+        if fragment_count == 1 {
+            return Ok(vec![(self.build_data_packet(stream_id, delivery_mode, base_flags, data)?, data.len())]);
         }
         
-        // Check for piggybacked ACK
-        let piggyback = if self.reliability.has_pending_acks() {
-            let (ack, ranges) = self.reliability.get_ack_info();
-            if ranges.is_empty() {
-                 self.reliability.on_ack_sent();
-                 Some(ack)
+        let message_id = self.next_message_id;
+        self.next_message_id = self.next_message_id.wrapping_add(1);
+        
+        let mut packets = Vec::with_capacity(fragment_count);
+        for (index, chunk) in data.chunks(max_fragment).enumerate() {
+            let fragment = FragmentHeader::new(message_id, index as u16, fragment_count as u16);
+            let mut payload = Vec::with_capacity(FRAGMENT_HEADER_LEN + chunk.len());
+            payload.extend_from_slice(&fragment.to_bytes());
+            payload.extend_from_slice(chunk);
+            packets.push((self.build_data_packet(stream_id, delivery_mode, base_flags | FLAG_FRAGMENT, &payload)?, chunk.len()));
+        }
+        Ok(packets)
+    }
+
+    /// Queue a message's packets; room for anything but BestEffort was made beforehand
+    fn enqueue_message(queue: &mut PriorityQueue<Vec<u8>>, packets: Vec<(Vec<u8>, usize)>, priority: QosPriority, delivery_mode: DeliveryMode) {
+        for (packet, len) in packets {
+            if delivery_mode == DeliveryMode::BestEffort {
+                // Dropped and counted by the queue if there is no room
+                let _ = queue.try_enqueue(packet, priority, len, delivery_mode);
             } else {
-                None
+                queue.enqueue_sized(packet, priority, len, delivery_mode);
             }
-        } else {
-            None
-        };
+        }
+    }
 
-        // Create Header
+    fn count_message_sent(&mut self, stream_id: u32, len: usize) {
+        let counters = self.stream_counters.entry(stream_id).or_default();
+        counters.bytes_sent += len as u64;
+        counters.messages_sent += 1;
+    }
+
+    /// Lock the send queue, counting it for `send_queue_locks`
+    fn lock_send_queue(&self) -> std::sync::MutexGuard<'_, PriorityQueue<Vec<u8>>> {
+        self.send_queue_locks.fetch_add(1, Ordering::Relaxed);
+        self.priority_queue.lock().unwrap()
+    }
+
+    /// Send an unreliable datagram outside any stream
+    ///
+    /// Like QUIC DATAGRAM frames, datagrams are never sequenced, ACKed or
+    /// retransmitted and skip the congestion window. They are not fragmented,
+    /// so `data` must fit in `max_fragment_size` (or, with MTU discovery on,
+    /// in `path_mtu()` less the packet overhead).
+    pub async fn send_datagram(&mut self, data: &[u8]) -> Result<(), TransportError> {
+        if self.closing.load(Ordering::Relaxed) {
+            return Err(self.closed_error());
+        }
+        
+        if data.len() > self.max_fragment() {
+            return Err(TransportError::MessageTooLarge(data.len()));
+        }
+        
+        if !self.rate_limiter.check_and_consume(data.len()) {
+            tracing::warn!(peer = %self.peer_addr, "Rate limit exceeded");
+            return Err(TransportError::RateLimited);
+        }
+        
         let mut header = Header::new(
-            stream_id,
-            FRAME_TYPE_DATA,
-            0, // flags
-            seq,
-            std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_millis() as u64,
-            0, // nonce (TODO: Encryption)
-            delivery_mode,
-            piggyback,
+            0,
+            FRAME_TYPE_DATAGRAM,
+            0,
+            0, // Not sequenced
+            std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_err(anyhow::Error::from)?.as_millis() as u64,
+            0,
+            DeliveryMode::BestEffort,
+            None,
             Some(data.len() as u32),
         );
         
-        // Determine if we should compress
-        let use_compression = if let Some(start) = self.migration_start {
-            if start.elapsed() < Duration::from_secs(5) {
-                false
-            } else {
-                self.migration_start = None;
-                self.header_compressor.is_some()
-            }
+        let header_bytes = if let Some(compressor) = self.header_compressor() {
+            compressor.compress(&header)
         } else {
-            self.header_compressor.is_some()
+            header.connection_id = Some(ConnectionId::from_u64(self.session.session_id));
+            self.header_codec().encode_header(&header)?
         };
-
-        if !use_compression {
-             header.connection_id = Some(jsp_core::types::connection_id::ConnectionId::from_u64(self.session.session_id));
-        }
+        let header_len = header_bytes.len() as u16;
         
-        // Update adaptive compression metrics
-        // In a real implementation, we would get RTT from reliability layer and packet loss from metrics
-        // For now, we just simulate or use placeholder values if not available
-        {
-            let rtt = self.metrics.get_avg_rtt();
-            // Simple packet loss estimation: (retransmits / total_sent)
-            // This is a rough approximation
-            let loss = 0.0; // TODO: Get from reliability layer
-            
-            let mut adaptive = self.adaptive_compression.lock().unwrap();
-            adaptive.update_metrics(rtt, loss);
+        let mut packet = self.packet_pool.acquire();
+        packet.reserve(2 + header_bytes.len() + data.len());
+        packet.extend_from_slice(&header_len.to_be_bytes());
+        packet.extend_from_slice(&header_bytes);
+        packet.extend_from_slice(data);
+        
+        let result = self.transport.send_to(&packet, self.peer_addr).await;
+        if result.is_ok() {
+            self.metrics.record_packet_sent(packet.len());
         }
+        self.packet_pool.release(packet);
+        result?;
+        
+        Ok(())
+    }
+
+    /// Wait for the next datagram sent with `send_datagram`
+    ///
+    /// Stream data and events received meanwhile stay queued for `recv()`.
+    pub async fn recv_datagram(&mut self) -> Result<Bytes, TransportError> {
+        loop {
+            if let Some(datagram) = self.datagrams.pop_front() {
+                return Ok(datagram);
+            }
+            self.process_incoming().await?;
+        }
+    }
+
+    /// Close a stream and tell the peer
+    ///
+    /// The FIN is sequenced after data already sent on the stream, so the peer
+    /// receives all of it before `ConnectionEvent::StreamFinished`. Further sends
+    /// on the stream fail; the id is released once the peer answers with its own FIN.
+    /// Use `finish_stream` to keep receiving on the stream.
+    pub async fn close_stream(&mut self, stream_id: u32) -> Result<(), TransportError> {
+        if self.closing.load(Ordering::Relaxed) {
+            return Err(self.closed_error());
+        }
+        
+        if self.stream_fins.get(&stream_id).is_some_and(|fin| fin.sent) {
+            return Err(TransportError::StreamClosed(stream_id));
+        }
+        
+        Ok(self.send_stream_fin(stream_id, 0)?)
+    }
+
+    /// Finish sending on a stream while still receiving on it
+    ///
+    /// Sends a FIN flagged `FLAG_HALF_CLOSE` behind the data already queued on
+    /// the stream; the peer gets `ConnectionEvent::StreamFinished` after that
+    /// data and may keep sending until it finishes or closes the stream too.
+    /// Further sends on the stream fail.
+    pub async fn finish_stream(&mut self, stream_id: u32) -> Result<(), TransportError> {
+        if self.closing.load(Ordering::Relaxed) {
+            return Err(self.closed_error());
+        }
+        
+        if self.stream_fins.get(&stream_id).is_some_and(|fin| fin.sent) {
+            return Err(TransportError::StreamClosed(stream_id));
+        }
+        
+        if self.session.streams().get_stream(stream_id).is_none() {
+            return Err(TransportError::StreamNotFound(stream_id));
+        }
+        
+        Ok(self.send_stream_fin(stream_id, FLAG_HALF_CLOSE)?)
+    }
+
+    /// Queue a FIN for the stream behind any data already queued on it
+    fn send_stream_fin(&mut self, stream_id: u32, flags: u8) -> Result<()> {
+        // Same priority as the stream's data so the FIN is not sent ahead of it
+        let priority = self.session.streams().get_stream(stream_id)
+            .and_then(|stream| QosPriority::from_value(stream.priority))
+            .unwrap_or_default();
+        
+        // Reliable even on BestEffort streams, a lost FIN would leave the peer waiting
+        let packet = self.build_packet(FRAME_TYPE_STREAM_FIN, stream_id, DeliveryMode::Reliable, flags, &[])?;
+        self.lock_send_queue().enqueue(packet, priority);
+        self.sender_notify.notify_one();
+        
+        // Stop local sends; the peer may still have data in flight to us
+        if self.session.streams().get_stream(stream_id).is_some() {
+            self.session.close_stream(stream_id)?;
+        }
+        self.stream_fins.entry(stream_id).or_default().sent = true;
+        self.release_stream_if_closed(stream_id);
+        
+        tracing::debug!(peer = %self.peer_addr, stream_id, "Stream FIN sent");
+        Ok(())
+    }
+
+    /// Handle a FIN delivered in order from the peer
+    fn on_stream_fin(&mut self, stream_id: u32) -> Result<()> {
+        tracing::debug!(peer = %self.peer_addr, stream_id, "Stream FIN received");
+        self.pending_events.push_back(ConnectionEvent::StreamFinished(stream_id));
+        
+        let fin = self.stream_fins.entry(stream_id).or_default();
+        fin.received = true;
+        if fin.sent {
+            self.release_stream_if_closed(stream_id);
+        } else {
+            // Answer with our own FIN so both sides agree the id is done
+            self.send_stream_fin(stream_id, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Handle a FIN flagged `FLAG_HALF_CLOSE`; unlike a full FIN it is not answered
+    fn on_stream_finished(&mut self, stream_id: u32) {
+        tracing::debug!(peer = %self.peer_addr, stream_id, "Peer finished stream");
+        self.pending_events.push_back(ConnectionEvent::StreamFinished(stream_id));
+        
+        self.stream_fins.entry(stream_id).or_default().received = true;
+        self.release_stream_if_closed(stream_id);
+    }
+
+    /// Forget a stream once FINs went both ways, allowing the id to be reused
+    fn release_stream_if_closed(&mut self, stream_id: u32) {
+        if let Some(fin) = self.stream_fins.get(&stream_id) {
+            if fin.sent && fin.received {
+                self.stream_fins.remove(&stream_id);
+                self.remote_stream_modes.remove(&stream_id);
+                self.stream_counters.remove(&stream_id);
+                if let Some(stream) = self.session.streams_mut().get_stream_mut(stream_id) {
+                    stream.finalize_close();
+                }
+                self.session.streams_mut().cleanup_closed_streams();
+            }
+        }
+    }
+
+    /// Handle a frame for this session from an address that hasn't echoed a
+    /// PATH_CHALLENGE yet
+    ///
+    /// The address is challenged, and the peer's own PATH_CHALLENGE answered
+    /// so its `migrate` can validate the new path. Once our token comes back
+    /// the peer migrates there if it sent a PATH_CHALLENGE, and otherwise
+    /// the address is taken as an MPTCP subflow.
+    async fn on_unvalidated_path(&mut self, src: SocketAddr, header: &Header, payload: &[u8]) -> Result<()> {
+        match header.msg_type {
+            FRAME_TYPE_PATH_RESPONSE => {
+                let response = match serde_cbor::from_slice::<PathResponse>(payload) {
+                    Ok(response) => response,
+                    Err(_) => return Ok(()),
+                };
+                let answered = self.path_validations.get(&src).is_some_and(|pending| {
+                    !pending.validator.is_expired() && pending.validator.verify_response(&response)
+                });
+                if !answered {
+                    return Ok(());
+                }
+                let pending = self.path_validations.remove(&src).expect("checked above");
+                if pending.migrate {
+                    self.on_peer_migrated(src);
+                } else if self.validated_subflows.len() < MAX_PATH_VALIDATIONS {
+                    tracing::debug!(peer = %self.peer_addr, subflow = %src, "Subflow address validated");
+                    self.validated_subflows.insert(src);
+                }
+            }
+            FRAME_TYPE_PATH_CHALLENGE => {
+                // Our challenge goes first, so it is there when the peer switches
+                self.challenge_path(src, true).await?;
+                if let Ok(challenge) = serde_cbor::from_slice::<PathChallenge>(payload) {
+                    self.send_path_response(challenge.token, src).await?;
+                }
+            }
+            _ => self.challenge_path(src, false).await?,
+        }
+        Ok(())
+    }
+
+    /// Send `src` a PATH_CHALLENGE, at most once per probe timeout
+    async fn challenge_path(&mut self, src: SocketAddr, migrate: bool) -> Result<()> {
+        self.path_validations.retain(|_, pending| !pending.validator.is_expired());
+        if self.path_validations.len() >= MAX_PATH_VALIDATIONS && !self.path_validations.contains_key(&src) {
+            tracing::debug!(%src, "Too many addresses being validated, ignoring");
+            return Ok(());
+        }
+        let timeout = self.path_probe_timeout();
+        let pending = match self.path_validations.entry(src) {
+            hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            hash_map::Entry::Vacant(entry) => entry.insert(PendingPath {
+                validator: PathValidator::new(src, PATH_VALIDATION_TIMEOUT),
+                migrate: false,
+                sent_at: None,
+            }),
+        };
+        pending.migrate |= migrate;
+        if pending.sent_at.is_some_and(|sent_at| sent_at.elapsed() < timeout) {
+            return Ok(());
+        }
+        pending.sent_at = Some(std::time::Instant::now());
+        let token = pending.validator.get_challenge().token;
+
+        tracing::debug!(peer = %self.peer_addr, %src, migrate, "Challenging new peer address");
+        let challenge = self.path_challenge_packet(token)?;
+        self.transport.send_to(&challenge, src).await?;
+        Ok(())
+    }
+
+    /// Switch to the peer's new address once it echoed our PATH_CHALLENGE there
+    fn on_peer_migrated(&mut self, new: SocketAddr) {
+        let old = self.peer_addr;
+        self.peer_addr = new;
+        self.restart_path_tasks();
+        self.restart_mtu_discovery();
+        tracing::info!(%old, %new, "Peer migrated");
+        self.pending_events.push_back(ConnectionEvent::PeerMigrated { old, new });
+    }
+
+    /// Build a single data packet: [Header Len (2)] [Header] [Payload]
+    fn build_data_packet(&mut self, stream_id: u32, delivery_mode: DeliveryMode, flags: u8, data: &[u8]) -> Result<Vec<u8>> {
+        crate::prometheus::global_registry().observe_packet_size(data.len());
+        self.build_packet(FRAME_TYPE_DATA, stream_id, delivery_mode, flags, data)
+    }
+
+    /// Build a sequenced packet (data or stream FIN)
+    fn build_packet(&mut self, msg_type: u8, stream_id: u32, delivery_mode: DeliveryMode, flags: u8, data: &[u8]) -> Result<Vec<u8>> {
+        // Get next sequence number
+        let seq = self.reliability.next_sequence();
+        
+        // Track packet if needed (Reliable or PartiallyReliable)
+        if delivery_mode.requires_retransmit() {
+            self.reliability.track_sent_frame(seq, stream_id, msg_type, flags, Bytes::copy_from_slice(data), delivery_mode);
+        }
+        
+        self.encode_sequenced(seq, msg_type, stream_id, delivery_mode, flags, data)
+    }
+
+    /// Frame a sequenced packet under `seq`, for its first transmission or a resend
+    ///
+    /// With key updates, stream data is sealed with the current epoch's key
+    /// each time it is framed, so a resend after an update uses the new key.
+    /// 0-RTT early data is always sealed with the early data key instead.
+    /// The header is authenticated along with the payload (see `header_aad`).
+    fn encode_sequenced(&mut self, seq: u64, msg_type: u8, stream_id: u32, delivery_mode: DeliveryMode, flags: u8, data: &[u8]) -> Result<Vec<u8>> {
+        let seal = msg_type == FRAME_TYPE_DATA && self.config.enable_key_updates;
+        let (flags, nonce, payload_len) = if seal {
+            // A resend gets a new header (timestamp, piggybacked ACK), so it
+            // must not reuse the first transmission's nonce
+            let nonce = self.next_packet_nonce;
+            self.next_packet_nonce += 1;
+            (flags | key_phase_flags(self.session.crypto.key_phase()), nonce, data.len() + AEAD_TAG_LEN)
+        } else {
+            (flags, 0, data.len())
+        };
+        
+        // Check for piggybacked ACK
+        let piggyback = if self.reliability.has_pending_acks() {
+            let (ack, ranges) = self.reliability.get_ack_info();
+            if ranges.is_empty() {
+                 self.reliability.on_ack_sent();
+                 Some(ack)
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+
+        // Create Header
+        let mut header = Header::new(
+            stream_id,
+            msg_type,
+            flags,
+            seq,
+            std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_millis() as u64,
+            nonce,
+            delivery_mode,
+            piggyback,
+            Some(payload_len as u32),
+        );
+        
+        // Determine if we should compress
+        let use_compression = if let Some(start) = self.migration_start {
+            if start.elapsed() < Duration::from_secs(5) {
+                false
+            } else {
+                self.migration_start = None;
+                self.header_compressor().is_some()
+            }
+        } else {
+            self.header_compressor().is_some()
+        };
+
+        if !use_compression {
+             header.connection_id = Some(jsp_core::types::connection_id::ConnectionId::from_u64(self.session.session_id));
+        }
+        
+        // Seal against the finished header, so tampering with any field fails authentication
+        let sealed;
+        let data = if seal {
+            let aad = header_aad(&header);
+            sealed = if flags & FLAG_EARLY_DATA != 0 {
+                self.session.crypto.encrypt_early_packet(self.is_server as u32, nonce, data, &aad)?
+            } else {
+                self.session.crypto.encrypt_packet(self.is_server as u32, nonce, data, &aad)?
+            };
+            self.key_epoch_bytes += data.len() as u64;
+            sealed.as_slice()
+        } else {
+            data
+        };
         
         // Serialize Header
         let header_bytes = if use_compression {
-            if let Some(compressor) = &mut self.header_compressor {
-                // TODO: Pass compression level to compressor if supported
-                // For now, adaptive compression just tracks the level
+            if let Some(compressor) = self.header_compressor() {
                 compressor.compress(&header)
             } else {
-                serde_cbor::to_vec(&header)?
+                self.header_codec().encode_header(&header)?
             }
         } else {
-            serde_cbor::to_vec(&header)?
+            self.header_codec().encode_header(&header)?
         };
         let header_len = header_bytes.len() as u16;
         
@@ -743,36 +2308,41 @@ impl Connection {
         packet.extend_from_slice(&header_bytes);
         packet.extend_from_slice(data);
         
-        // Determine priority
-        let priority = QosPriority::from_value(priority).unwrap_or_default();
-        
-        // Enqueue
-        {
-            let mut queue = self.priority_queue.lock().unwrap();
-            queue.enqueue(packet, priority);
+        // Queued packets may be coalesced, where only payload_len tells
+        // the receiver where this one ends and the next begins
+        if self.config.coalescing_window_ms > 0 {
+            coalescing::check_frame(&packet, self.session.serialization_format())?;
         }
         
-        // Notify sender
-        self.sender_notify.notify_one();
-        
-        tracing::trace!(
-            peer = %self.peer_addr,
-            stream_id,
-            seq,
-            ?delivery_mode,
-            bytes = data.len(),
-            "Data sent on stream"
-        );
-        
+        Ok(packet)
+    }
+
+    /// Resend every frame the peer has not acknowledged, after the path changed
+    async fn resend_unacked(&mut self) -> Result<()> {
+        let frames = self.reliability.unacked_frames();
+        if frames.is_empty() {
+            return Ok(());
+        }
+        tracing::debug!(peer = %self.peer_addr, frames = frames.len(), "Resending unacknowledged frames on the new path");
+        for frame in frames {
+            let packet = self.encode_sequenced(frame.seq, frame.msg_type, frame.stream_id, frame.mode, frame.flags, &frame.data)?;
+            self.transport.send_to(&packet, self.peer_addr).await?;
+            self.metrics.record_packet_sent(packet.len());
+        }
+        let loss = self.reliability.loss_stats();
+        self.metrics.set_retransmits(loss.packets_retransmitted, loss.spurious_retransmits);
         Ok(())
     }
     
     async fn send_ack(&mut self) -> Result<()> {
         let (ack, sack_ranges) = self.reliability.get_ack_info();
         
+        let receive_window = self.receive_window();
         let ack_frame = AckFrame {
             cumulative_ack: ack,
             sack_ranges,
+            ecn_counts: self.reliability.ecn_counts(),
+            receive_window: Some(receive_window),
         };
         
         let payload = serde_cbor::to_vec(&ack_frame)?;
@@ -790,10 +2360,10 @@ impl Connection {
             Some(payload.len() as u32),
         );
         
-        let header_bytes = if let Some(compressor) = &mut self.header_compressor {
+        let header_bytes = if let Some(compressor) = self.header_compressor() {
             compressor.compress(&header)
         } else {
-            serde_cbor::to_vec(&header)?
+            self.header_codec().encode_header(&header)?
         };
         let header_len = header_bytes.len() as u16;
         
@@ -809,45 +2379,263 @@ impl Connection {
         
         // Reset batching state
         self.reliability.on_ack_sent();
+        self.advertised_window = receive_window;
         
         Ok(())
     }
 
+    /// Bytes we can still take before the application reads more
+    fn receive_window(&self) -> u64 {
+        let undelivered: usize = self.pending_events.iter()
+            .map(|event| match event {
+                ConnectionEvent::DataReceived { data, .. } => data.len(),
+                _ => 0,
+            })
+            .sum();
+        let buffered = undelivered + self.reliability.buffered_bytes() + self.reassembler.buffered_bytes();
+        self.config.max_receive_buffer.saturating_sub(buffered) as u64
+    }
+
+    /// Advertise the window again once the application has drained enough of it
+    ///
+    /// A sender that filled our window waits for an ACK to reopen it, and
+    /// sends nothing that would make us ACK in the meantime.
+    async fn reopen_receive_window(&mut self) {
+        let half = self.config.max_receive_buffer as u64 / 2;
+        if self.advertised_window < half && self.receive_window() >= half {
+            if let Err(e) = self.send_ack().await {
+                tracing::debug!(peer = %self.peer_addr, "Sending window update failed: {}", e);
+            }
+        }
+    }
+
     /// Receive packets from the connection
     /// Returns a list of (stream_id, data) tuples that are ready (in-order)
-    pub async fn recv(&mut self) -> Result<Vec<(u32, Bytes)>> {
-        let mut buf = BytesMut::with_capacity(2048);
-        buf.resize(2048, 0);
-        let (len, src) = self.transport.recv_from(&mut buf).await?;
-        buf.truncate(len);
-        
-        self.metrics.record_packet_received(len);
-        
-        if src != self.peer_addr {
-            // Check if it's a STUN response from one of our servers
-            let is_stun = self.stun_server_addrs.contains(&src);
-            if !is_stun {
-                // Ignore packets from other peers (for now)
-                return Ok(Vec::new());
+    ///
+    /// Events other than data are dropped; use `recv_events` to observe them,
+    /// or `peer_close` for the reason the peer closed the connection.
+    /// Allocates the returned `Vec` on every call; loops receiving at high
+    /// rates should use `recv_into`.
+    pub async fn recv(&mut self) -> Result<Vec<(u32, Bytes)>, TransportError> {
+        let mut packets = Vec::new();
+        self.recv_into(&mut packets).await?;
+        Ok(packets)
+    }
+
+    /// Like `recv`, appending to `bufs` instead of returning a new `Vec`
+    ///
+    /// Returns the number of messages appended. Clearing `bufs` between
+    /// calls keeps its allocation, so a receive loop allocates nothing per
+    /// call once `bufs` has grown to the largest batch.
+    ///
+    /// Payloads are never copied on their way to `bufs`: each `Bytes` is a
+    /// slice of the pooled buffer the datagram was read into, and that
+    /// buffer goes back to the pool once every slice of it is dropped, so
+    /// holding on to one message keeps its whole datagram out of the pool.
+    /// Three cases deliver a fresh buffer instead: messages sent in
+    /// fragments (reassembled into one copy), payloads sealed with
+    /// `enable_key_updates`, and messages under a Double Ratchet (one copy
+    /// each for decryption).
+    pub async fn recv_into(&mut self, bufs: &mut Vec<(u32, Bytes)>) -> Result<usize, TransportError> {
+        let mut span = self.child_span("recv");
+        self.queue_circuit_events();
+        // Events received while a send was waiting for capacity are returned first
+        if self.pending_events.is_empty() {
+            if let Err(e) = self.process_incoming().await {
+                if let Some(span) = &mut span {
+                    span.record_error(&e);
+                }
+                return Err(e.into());
+            }
+        }
+        let start = bufs.len();
+        bufs.extend(self.pending_events.drain(..).filter_map(|event| match event {
+            ConnectionEvent::DataReceived { stream_id, data } => Some((stream_id, data)),
+            _ => None,
+        }));
+        self.reopen_receive_window().await;
+        
+        let received = &bufs[start..];
+        if let Some(span) = &mut span {
+            span.set_attribute("messages", received.len().to_string());
+            span.set_attribute("bytes", received.iter().map(|(_, data)| data.len()).sum::<usize>().to_string());
+        }
+        Ok(received.len())
+    }
+
+    /// Start a span under this connection's trace, when a global tracer is installed
+    fn child_span(&mut self, name: &str) -> Option<crate::otel::Span> {
+        let tracer = crate::otel::try_global_tracer()?;
+        let (peer, is_server) = (self.peer_addr, self.is_server);
+        let root = self.trace_span.get_or_insert_with(|| {
+            let mut span = tracer.start_span("connection");
+            span.set_attribute("peer", peer.to_string());
+            span.set_attribute("role", if is_server { "server" } else { "client" });
+            span
+        });
+        Some(tracer.start_span_with_parent(name, Some(&root.context())))
+    }
+
+    /// Trace this connection's spans belong to, once one was started
+    pub fn trace_context(&self) -> Option<crate::otel::SpanContext> {
+        self.trace_span.as_ref().map(|span| span.context())
+    }
+
+    /// Receive data and stream events in the order they occurred
+    pub async fn recv_events(&mut self) -> Result<Vec<ConnectionEvent>, TransportError> {
+        self.queue_circuit_events();
+        // Events received while a send was waiting for capacity are returned first
+        if self.pending_events.is_empty() {
+            self.process_incoming().await?;
+        }
+        let events = self.pending_events.drain(..).collect();
+        self.reopen_receive_window().await;
+        Ok(events)
+    }
+
+    /// Put back events that were read while waiting for something else,
+    /// ahead of any that arrived after them
+    pub fn requeue_events(&mut self, events: Vec<ConnectionEvent>) {
+        for event in events.into_iter().rev() {
+            self.pending_events.push_front(event);
+        }
+    }
+
+    /// Wait for the next connection event
+    ///
+    /// Unlike `recv_events`, this keeps reading until something happens, so
+    /// datagrams that only carry ACKs or heartbeats never return early.
+    pub async fn next_event(&mut self) -> Result<ConnectionEvent, TransportError> {
+        loop {
+            self.queue_circuit_events();
+            if let Some(event) = self.pending_events.pop_front() {
+                self.reopen_receive_window().await;
+                return Ok(event);
             }
+            self.process_incoming().await?;
+        }
+    }
+
+    /// Handle one datagram and queue any in-order events it completes
+    ///
+    /// Reads up to `io_batch_size` datagrams when none are left from the
+    /// last read. Events are pushed to `pending_events` as soon as they are
+    /// ready, the rest of a batch waits in `recv_backlog` and the frames of
+    /// a datagram not handled yet in `recv_remainder`, so cancelling this
+    /// future loses at most the control frame being answered.
+    async fn process_incoming(&mut self) -> Result<()> {
+        if self.recv_remainder.is_some() {
+            return self.process_frames(None).await;
+        }
+        if let Some((buf, src, ecn)) = self.recv_backlog.pop_front() {
+            return self.process_datagram(buf, src, ecn).await;
         }
         
-        let data = buf.freeze();
+        // Buffers the last batch didn't fill are reused
+        let batch = self.config.io_batch_size.clamp(1, MAX_BATCH);
+        while self.recv_bufs.len() < batch {
+            self.recv_bufs.push(self.recv_pool.acquire());
+        }
+        // Sized on every read, as GRO comes and goes with the path
+        let size = self.recv_buffer_size();
+        for buf in &mut self.recv_bufs[..batch] {
+            buf.resize(size, 0);
+        }
+        let mut meta = [RecvMeta::default(); MAX_BATCH];
+        let mut probe_buf = [0u8; RECV_BUFFER_SIZE];
+        let received = tokio::select! {
+            res = self.transport.recv_batch(&mut self.recv_bufs[..batch], &mut meta[..batch]) => Some(res),
+            Ok(()) = interfaces_changed(&mut self.interfaces) => None,
+            res = recv_on_probe(self.path_probe.as_ref(), &mut probe_buf) => match res {
+                Ok((len, src, ecn)) if self.validates_path_probe(&probe_buf[..len], src) => {
+                    // Handled below like any packet, now that it arrived on the active path
+                    let probe = self.path_probe.take().expect("datagram came from the probe");
+                    self.commit_path(probe.transport);
+                    self.recv_bufs[0][..len].copy_from_slice(&probe_buf[..len]);
+                    meta[0] = RecvMeta::single(len, src, ecn);
+                    Some(Ok(1))
+                }
+                Ok((len, src, _)) => {
+                    tracing::trace!(%src, len, "Ignoring packet on a path that is not validated yet");
+                    return Ok(());
+                }
+                Err(e) => {
+                    tracing::warn!(peer = %self.peer_addr, "New path failed, staying on the current one: {}", e);
+                    self.path_probe = None;
+                    return Ok(());
+                }
+            },
+            () = probe_deadline(self.path_probe.as_ref()) => return self.on_path_probe_timeout().await,
+            () = mtu_probe_deadline(self.mtu_probe_at) => return self.on_mtu_probe_timer().await,
+        };
+        let count = match received {
+            Some(Ok(count)) => count,
+            Some(Err(e)) if self.can_rebind() && is_path_error(&e) => {
+                tracing::warn!(peer = %self.peer_addr, "Local socket failed: {}", e);
+                return self.rebind().await;
+            }
+            Some(Err(e)) => return Err(e),
+            None => {
+                if self.local_interface_lost() && self.can_rebind() {
+                    return self.rebind().await;
+                }
+                return Ok(());
+            }
+        };
+        for (mut buf, meta) in self.recv_bufs.drain(..count).zip(meta) {
+            buf.truncate(meta.len);
+            // A GRO buffer holds several datagrams, each handled on its own
+            let data = self.recv_pool.freeze(buf);
+            for segment in meta.segments() {
+                self.recv_backlog.push_back((data.slice(segment), meta.addr, meta.ecn));
+            }
+        }
+        match self.recv_backlog.pop_front() {
+            Some((buf, src, ecn)) => self.process_datagram(buf, src, ecn).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Handle a datagram received from `src`
+    ///
+    /// Frames and their payloads are slices of `data`.
+    async fn process_datagram(&mut self, data: Bytes, src: SocketAddr, ecn: EcnCodepoint) -> Result<()> {
+        self.metrics.record_packet_received(data.len());
+        self.refresh_labeled_metrics();
         
         // Parse Header Length
         if data.len() < 2 {
-            return Ok(Vec::new());
+            return Ok(());
         }
         
         let header_len = u16::from_be_bytes([data[0], data[1]]) as usize;
         if data.len() < 2 + header_len {
-            return Ok(Vec::new());
+            // Not a framed packet; maybe a ClientHello whose ServerHello was lost
+            if self.is_repeated_client_hello(src, &data) {
+                if let Some((_, server_hello)) = &self.server_hello {
+                    tracing::debug!(peer = %src, "Duplicate ClientHello, resending ServerHello");
+                    self.transport.send_to(server_hello, src).await?;
+                }
+            }
+            return Ok(());
         }
         
-        let mut result = Vec::new();
-        let mut current_data = data;
-        
-        while !current_data.is_empty() {
+        self.recv_remainder = Some((data, src));
+        self.process_frames(Some(ecn)).await
+    }
+
+    /// Handle the frames in `recv_remainder`
+    ///
+    /// Each frame is taken off the remainder before it is handled, so the
+    /// frames after it survive a cancelled await. `ecn` is the datagram's
+    /// ECN codepoint, counted when its first frame is accepted.
+    async fn process_frames(&mut self, mut ecn: Option<EcnCodepoint>) -> Result<()> {
+        let mut alive = false;
+        
+        while let Some((current_data, src)) = self.recv_remainder.take() {
+            // Packets from an unknown address must carry our connection ID and
+            // are only trusted once the address echoed our PATH_CHALLENGE
+            let new_path = src != self.peer_addr && !self.stun_server_addrs.contains(&src);
             if current_data.len() < 2 {
                 break; // Malformed or empty
             }
@@ -859,16 +2647,15 @@ impl Connection {
             
             let header_bytes = &current_data[2..2+header_len];
             
-            // Try to decompress or deserialize
-            let header_result = if let Some(decompressor) = &mut self.header_decompressor {
-                // Check first byte to see if it's compressed (flags < 0x80) or CBOR (map/array >= 0x80)
-                if !header_bytes.is_empty() && header_bytes[0] < 0x80 {
-                    decompressor.decompress(header_bytes).map_err(|e| anyhow::anyhow!("Decompression failed: {}", e))
-                } else {
-                    serde_cbor::from_slice(header_bytes).map_err(|e| anyhow::anyhow!("Deserialization failed: {}", e))
-                }
-            } else {
-                serde_cbor::from_slice(header_bytes).map_err(|e| anyhow::anyhow!("Deserialization failed: {}", e))
+            // Try to decompress or deserialize. Compressed headers start below 0x80
+            // like FlatBuffers ones, but only sessions using CBOR send them.
+            let compressed = header_bytes.first().is_some_and(|&first| first < 0x80)
+                && self.session.serialization_format() == SerializationFormat::Cbor;
+            let header_result = match (&mut self.header_decompressor, compressed) {
+                (Some(decompressor), true) => decompressor.decompress(header_bytes)
+                    .map_err(|e| anyhow::anyhow!("Decompression failed: {}", e)),
+                _ => decode_any_header(header_bytes)
+                    .map_err(|e| anyhow::anyhow!("Deserialization failed: {}", e)),
             };
 
             let header: Header = match header_result {
@@ -893,81 +2680,200 @@ impl Connection {
             
             let payload = current_data.slice(2+header_len..2+header_len+payload_len);
             
+            if new_path {
+                if header.connection_id != Some(ConnectionId::from_u64(self.session.session_id)) {
+                    // Ignore packets from other peers
+                    return Ok(());
+                }
+                if !self.validated_subflows.contains(&src) {
+                    // Nothing in the datagram is acted on before that
+                    return self.on_unvalidated_path(src, &header, &payload).await;
+                }
+                if header.msg_type == FRAME_TYPE_PATH_CHALLENGE {
+                    self.validated_subflows.remove(&src);
+                    self.on_peer_migrated(src);
+                }
+                // Other frames for this session come from additional MPTCP subflows
+                // and feed the same reliability layer
+            }
+            
             // Advance buffer for next packet
             let next_start = 2 + header_len + payload_len;
-            let remaining = current_data.slice(next_start..);
-            current_data = remaining;
+            if next_start < current_data.len() {
+                self.recv_remainder = Some((current_data.slice(next_start..), src));
+            }
             
             // Process packet
             
+            // Authenticate sealed frames before acting on any header field
+            let sealed = (header.msg_type == FRAME_TYPE_DATA && self.config.enable_key_updates)
+                || header.msg_type == FRAME_TYPE_SESSION_TICKET;
+            let payload = if sealed {
+                match self.open_payload(&header, &payload) {
+                    Some(plaintext) => plaintext,
+                    None => continue,
+                }
+            } else {
+                payload
+            };
+            
             // Process piggybacked ACK if present
             if let Some(ack) = header.piggybacked_ack {
                  self.reliability.on_ack(ack, &[]);
+                 self.on_acks_processed();
             }
             
-            // Update activity
+            // Update activity; any packet from the peer proves it is alive
             self.session.update_activity();
+            alive = true;
+            if let Some(ecn) = ecn.take() {
+                self.reliability.on_ecn_received(ecn);
+            }
             
-            // Handle Control Frames
-            if header.is_control_frame() {
+            // Handle Control Frames (stream FINs and key updates are sequenced with data below)
+            if header.is_control_frame() && header.msg_type != FRAME_TYPE_STREAM_FIN && header.msg_type != FRAME_TYPE_KEY_UPDATE {
                 if header.msg_type == FRAME_TYPE_ACK {
                     if let Ok(ack_frame) = serde_cbor::from_slice::<AckFrame>(&payload) {
                         self.reliability.on_ack(ack_frame.cumulative_ack, &ack_frame.sack_ranges);
+                        if let Some(counts) = &ack_frame.ecn_counts {
+                            self.reliability.on_ecn_counts(counts);
+                        }
+                        if let Some(window) = ack_frame.receive_window {
+                            self.reliability.on_receive_window(window);
+                        }
+                        self.on_acks_processed();
                     }
                 } else if header.msg_type == FRAME_TYPE_STUN {
                      if let Ok(msg) = StunMessage::from_bytes(&payload) {
                          if let StunMessageType::BindingResponse = msg.msg_type {
-                             for attr in msg.attributes {
-                                 if let StunAttribute::MappedAddress(addr) = attr {
-                                     self.public_addr = Some(addr);
-                                     tracing::info!("Discovered public address: {}", addr);
-                                 }
-                             }
+                             self.on_stun_response(&msg, src);
                          }
                      }
+                } else if header.msg_type == FRAME_TYPE_DATAGRAM {
+                    // Not tracked by the reliability layer, so never ACKed
+                    self.datagrams.push_back(payload);
+                } else if header.msg_type == FRAME_TYPE_CLOSE {
+                    if let Ok(close) = serde_cbor::from_slice::<CloseFrame>(&payload) {
+                        tracing::info!(peer = %self.peer_addr, reason = ?close.reason_code, "Peer closed connection");
+                        self.closing.store(true, Ordering::Relaxed);
+                        self.pending_events.push_back(ConnectionEvent::Closed {
+                            reason: close.reason_code,
+                            message: close.message.clone(),
+                        });
+                        self.peer_close = Some(close);
+                    }
+                } else if header.msg_type == FRAME_TYPE_SESSION_TICKET {
+                    if let Ok(ticket) = serde_cbor::from_slice::<SessionTicket>(&payload) {
+                        tracing::debug!(peer = %self.peer_addr, "Received session ticket");
+                        self.session.receive_session_ticket(ticket)?;
+                    }
                 } else if header.msg_type == FRAME_TYPE_PATH_CHALLENGE {
                     if let Ok(challenge) = serde_cbor::from_slice::<PathChallenge>(&payload) {
                         tracing::debug!("Received PathChallenge, sending response");
+                        // With our connection ID: the peer may not know the new address yet
+                        self.send_path_response(challenge.token, src).await?;
                         
-                        // Send PathResponse
-                        let response = PathResponse { token: challenge.token };
-                        let resp_payload = serde_cbor::to_vec(&response)?;
-                        
-                        let resp_header = Header::new(
-                            0,
-                            FRAME_TYPE_PATH_RESPONSE,
-                            0,
-                            0,
-                            0,
-                            0,
-                            jsp_core::types::delivery::DeliveryMode::Reliable,
-                            None,
-                            Some(resp_payload.len() as u32)
-                        );
-                        
-                        let header_bytes = if let Some(compressor) = &mut self.header_compressor {
-                            compressor.compress(&resp_header)
-                        } else {
-                            serde_cbor::to_vec(&resp_header)?
-                        };
-                        let header_len = header_bytes.len() as u16;
-                        
-                        let mut packet = self.packet_pool.acquire();
-                        packet.reserve(2 + header_bytes.len() + resp_payload.len());
-                        packet.extend_from_slice(&header_len.to_be_bytes());
-                        packet.extend_from_slice(&header_bytes);
-                        packet.extend_from_slice(&resp_payload);
-                        
-                        self.transport.send_to(&packet, src).await?;
-                        self.packet_pool.release(packet);
+                        if self.awaiting_path_validation && src == self.peer_addr {
+                            // The peer is validating our new address and will
+                            // accept data from it once this response arrives
+                            self.awaiting_path_validation = false;
+                            self.resend_unacked().await?;
+                        }
+                    }
+                } else if header.msg_type == FRAME_TYPE_MTU_PROBE {
+                    // Answered even with discovery off here, so the peer can probe
+                    self.send_mtu_probe_ack(2 + header_len + payload_len, src).await?;
+                } else if header.msg_type == FRAME_TYPE_MTU_PROBE_ACK {
+                    if let Ok(ack) = serde_cbor::from_slice::<MtuProbeAckFrame>(&payload) {
+                        self.on_mtu_probe_acked(ack.size as usize);
+                    }
+                } else if header.msg_type == FRAME_TYPE_PATH_RESPONSE {
+                    // A peer that switches without challenging us just answers
+                    // our PathChallenge; it already accepts the new path
+                    if self.awaiting_path_validation && src == self.peer_addr {
+                        self.awaiting_path_validation = false;
+                        self.resend_unacked().await?;
                     }
                 }
                 continue;
             }
             
             // Handle Data Frame
+            if header.msg_type == FRAME_TYPE_DATA {
+                self.remote_stream_modes.insert(header.stream_id, header.delivery_mode);
+            }
+            
+            // Redundant subflows deliver the same sequence more than once
+            if !self.dedup.insert(header.sequence) {
+                self.metrics.record_duplicate();
+                // A resend of something we ACKed before; that ACK may have been lost
+                self.reliability.on_duplicate_received();
+                if self.reliability.should_send_ack(
+                    self.config.ack_batch_size,
+                    Duration::from_millis(self.config.ack_batch_timeout_ms)
+                ) {
+                    self.send_ack().await?;
+                }
+                continue;
+            }
+            
             // Track received packet for reliability
-            self.reliability.track_received_packet(header.sequence, header.stream_id, payload);
+            self.reliability.track_received_frame(header.sequence, header.stream_id, header.msg_type, header.flags, payload);
+            
+            // Check for in-order packets; they are delivered before the ACK
+            // is awaited so a cancelled send can't strand them
+            let packets = self.reliability.pop_received_frames();
+            
+            for frame in packets {
+                let (stream_id, flags, p_data) = (frame.stream_id, frame.flags, frame.data);
+                
+                if frame.msg_type == FRAME_TYPE_STREAM_FIN {
+                    if flags & FLAG_HALF_CLOSE != 0 {
+                        self.on_stream_finished(stream_id);
+                    } else {
+                        self.on_stream_fin(stream_id)?;
+                    }
+                    continue;
+                }
+                
+                if frame.msg_type == FRAME_TYPE_KEY_UPDATE {
+                    match serde_cbor::from_slice::<KeyUpdateFrame>(&p_data) {
+                        Ok(update) => self.follow_key_update(update.epoch)?,
+                        Err(e) => tracing::warn!(peer = %self.peer_addr, "Malformed key update: {}", e),
+                    }
+                    continue;
+                }
+                
+                if self.stream_fins.get(&stream_id).is_some_and(|fin| fin.received) {
+                    tracing::warn!(stream_id, "Dropping data received after stream FIN");
+                    continue;
+                }
+                
+                if flags & FLAG_EARLY_DATA != 0 && !self.session.is_resumed() {
+                    // Rejected early data; the client resends it after the full handshake
+                    tracing::debug!(stream_id, "Dropping early data from non-resumed session");
+                    continue;
+                }
+                
+                let compressed = flags & FLAG_COMPRESSED != 0;
+                if flags & FLAG_FRAGMENT == 0 {
+                    self.deliver(stream_id, p_data, compressed);
+                    continue;
+                }
+                
+                let fragment = match FragmentHeader::from_bytes(&p_data) {
+                    Some(f) => f,
+                    None => {
+                        tracing::warn!(stream_id, "Malformed fragment header");
+                        continue;
+                    }
+                };
+                let mode = self.remote_stream_modes.get(&stream_id).copied().unwrap_or_default();
+                let body = p_data.slice(FRAGMENT_HEADER_LEN..);
+                if let Some(message) = self.reassembler.insert(stream_id, mode, fragment, body) {
+                    self.deliver(stream_id, message, compressed);
+                }
+            }
             
             // Check if ACK should be sent
             if self.reliability.should_send_ack(
@@ -976,33 +2882,165 @@ impl Connection {
             ) {
                 self.send_ack().await?;
             }
-            
-            // Check for in-order packets
-            let packets = self.reliability.pop_received_packets();
-            
-            for (_seq, stream_id, p_data) in packets {
-                result.push((stream_id, p_data));
-            }
         }
         
-        Ok(result)
-    }
-
-    /// Manually flush pending ACKs
-    pub async fn flush_acks(&mut self) -> Result<()> {
-        if self.reliability.has_pending_acks() {
-            self.send_ack().await?;
+        if alive {
+            self.heartbeat.mark_received().await;
+        }
+        
+        // Drop BestEffort messages whose fragments never arrived
+        let expired = self.reassembler.expire();
+        if expired > 0 {
+            tracing::debug!(peer = %self.peer_addr, expired, "Discarded incomplete messages");
         }
+        
         Ok(())
     }
 
-    /// Cleanup expired packets from reliability layer
-    pub fn cleanup_expired_packets(&mut self) {
-        self.reliability.cleanup_expired();
-    }
+    /// Open a sealed data payload, or None (logged and counted as an auth
+    /// failure) if it does not authenticate together with its header
+    ///
+    /// A payload from the next epoch moves us there too: the peer updated
+    /// its keys and its KEY_UPDATE is still on the way. Early data opens
+    /// with the early data key.
+    fn open_payload(&mut self, header: &Header, payload: &[u8]) -> Option<Bytes> {
+        let peer = (!self.is_server) as u32;
+        let opened = if header.flags & FLAG_EARLY_DATA != 0 {
+            self.session.crypto.decrypt_early_packet(peer, header.nonce, payload, &header_aad(header))
+                .map(|plaintext| (self.session.crypto.key_epoch(), plaintext))
+        } else {
+            self.session.crypto.decrypt_packet(header.key_phase(), peer, header.nonce, payload, &header_aad(header))
+        };
+        let (epoch, plaintext) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                // Forged or tampered with, or sealed under a key we no longer hold
+                tracing::debug!(peer = %self.peer_addr, seq = header.sequence, "Dropping packet that failed to decrypt: {}", e);
+                self.metrics.record_auth_failure();
+                return None;
+            }
+        };
+        
+        if epoch == self.session.crypto.key_epoch().wrapping_add(1) {
+            if let Err(e) = self.follow_key_update(epoch) {
+                tracing::warn!(peer = %self.peer_addr, "Following key update failed: {}", e);
+            }
+        }
+        self.retire_stale_epoch();
+        Some(Bytes::from(plaintext))
+    }
+
+    /// Hand a complete message to the application, opening it with the
+    /// Double Ratchet if one is active and decompressing it if `compressed`
+    fn deliver(&mut self, stream_id: u32, data: Bytes, compressed: bool) {
+        let data = match self.session.double_ratchet_mut() {
+            Some(ratchet) => {
+                let opened = EncryptedMessage::from_bytes(&data)
+                    .ok_or_else(|| anyhow::anyhow!("Message shorter than a ratchet header"))
+                    .and_then(|message| ratchet.decrypt(&message));
+                match opened {
+                    Ok(plaintext) => Bytes::from(plaintext),
+                    Err(e) => {
+                        tracing::warn!(peer = %self.peer_addr, stream_id, "Dropping message that failed to decrypt: {}", e);
+                        return;
+                    }
+                }
+            }
+            None => data,
+        };
+        let data = if compressed {
+            match compression::decompress_payload(&data) {
+                Ok(decompressed) => Bytes::from(decompressed),
+                Err(e) => {
+                    tracing::warn!(peer = %self.peer_addr, stream_id, "Dropping message that failed to decompress: {}", e);
+                    return;
+                }
+            }
+        } else {
+            data
+        };
+        let counters = self.stream_counters.entry(stream_id).or_default();
+        counters.bytes_received += data.len() as u64;
+        counters.messages_received += 1;
+        self.pending_events.push_back(ConnectionEvent::DataReceived { stream_id, data });
+    }
+
+    /// Feed RTT samples and loss counters into the shared connection metrics
+    fn on_acks_processed(&mut self) {
+        if let Some(migrated_at) = self.migrated_at.take() {
+            let duration = migrated_at.elapsed();
+            tracing::info!(peer = %self.peer_addr, ?duration, "First ACK after migration");
+            self.metrics.record_migration(duration);
+        }
+        if self.key_update_seq.is_some_and(|seq| !self.reliability.is_unacked(seq)) {
+            // The peer has our KEY_UPDATE; what it sealed before may still be in flight
+            self.key_update_seq = None;
+            self.retire_previous_epoch_at = Some(std::time::Instant::now() + self.key_retire_delay());
+        }
+        let samples = self.reliability.take_rtt_samples();
+        for &(seq, rtt) in &samples {
+            self.metrics.record_rtt_sample(rtt);
+            if let Some(mptcp) = &self.mptcp {
+                mptcp.on_rtt_sample(seq, rtt);
+            }
+        }
+        if !samples.is_empty() {
+            crate::prometheus::global_registry().observe_rtt(self.reliability.smoothed_rtt());
+        }
+        let loss = self.reliability.loss_stats();
+        self.metrics.set_retransmits(loss.packets_retransmitted, loss.spurious_retransmits);
+        self.metrics.update_cwnd(self.reliability.congestion_window() as u64);
+        self.refresh_labeled_metrics();
+    }
+
+    /// Whether `data` is the ClientHello this server connection already answered
+    fn is_repeated_client_hello(&self, src: SocketAddr, data: &[u8]) -> bool {
+        let nonce = match &self.server_hello {
+            Some((nonce, _)) if self.is_server && src == self.peer_addr => *nonce,
+            _ => return false,
+        };
+        serde_cbor::from_slice::<jsp_core::types::handshake::ClientHello>(data)
+            .map(|hello| hello.nonce == nonce)
+            .unwrap_or(false)
+    }
+
+    /// Mirror this connection's counters into its labeled Prometheus series
+    ///
+    /// Runs on every received datagram, so bytes sent lag by at most an RTT.
+    fn refresh_labeled_metrics(&mut self) {
+        if self.session.state != SessionState::Established {
+            return;
+        }
+        let (session_id, peer) = (self.session.session_id, self.peer_addr);
+        let streams = self.all_stream_stats();
+        let series = self.labeled_metrics.get_or_insert_with(|| {
+            crate::prometheus::global_registry().connection_metrics(session_id, peer)
+        });
+        let snapshot = self.metrics.snapshot();
+        series.update(
+            snapshot.bytes_sent,
+            snapshot.bytes_received,
+            self.reliability.loss_stats().packets_retransmitted,
+            self.reliability.congestion_window() as u64,
+        );
+        series.update_streams(&streams, self.config.max_stream_metric_labels);
+    }
+
+    /// Manually flush pending ACKs
+    pub async fn flush_acks(&mut self) -> Result<(), TransportError> {
+        if self.reliability.has_pending_acks() {
+            self.send_ack().await?;
+        }
+        Ok(())
+    }
+
+    /// Cleanup expired packets from reliability layer
+    pub fn cleanup_expired_packets(&mut self) {
+        self.reliability.cleanup_expired();
+    }
 
     /// Flush coalesced packets
-    pub async fn flush_coalesced(&mut self) -> Result<()> {
+    pub async fn flush_coalesced(&mut self) -> Result<(), TransportError> {
         let data = {
             let mut buf = self.coalescing_buffer.lock().unwrap();
             if buf.is_empty() {
@@ -1024,8 +3062,182 @@ impl Connection {
         self.session.session_id
     }
 
+    /// Header encoding agreed in the handshake
+    pub fn serialization_format(&self) -> SerializationFormat {
+        self.session.serialization_format()
+    }
+
+    /// Latest session ticket issued by the server, for `resume_with_ticket`
+    pub fn session_ticket(&self) -> Option<SessionTicket> {
+        self.session.session_ticket.clone()
+    }
+
+    /// Compression level currently recommended by adaptive compression
+    pub fn current_compression_level(&self) -> i32 {
+        self.adaptive_compression.lock().unwrap().get_level()
+    }
+
+    /// Algorithm and level messages are compressed with, and why; applied
+    /// when `enable_payload_compression` is set
+    pub fn compression_decision(&self) -> CompressionDecision {
+        self.adaptive_compression.lock().unwrap().decision()
+    }
+
+    /// MPTCP manager, present after the handshake when `mptcp_config.enabled`
+    pub fn mptcp(&self) -> Option<&MptcpManager> {
+        self.mptcp.as_deref()
+    }
+
+    /// Whether the handshake resumed a previous session from a ticket
+    pub fn is_resumed(&self) -> bool {
+        self.session.is_resumed()
+    }
+
+    /// DH ratchet steps taken so far, or None if no Double Ratchet was negotiated
+    pub fn ratchet_steps(&self) -> Option<u64> {
+        self.session.double_ratchet().map(|ratchet| ratchet.dh_ratchet_steps())
+    }
+
+    /// Stream bytes sent and not yet acknowledged by the peer
+    pub fn bytes_in_flight(&self) -> usize {
+        self.reliability.inflight_bytes()
+    }
+
+    /// Receive window the peer last advertised, in bytes
+    pub fn peer_receive_window(&self) -> Option<u64> {
+        self.reliability.peer_receive_window()
+    }
+
+    /// Number of key updates since the handshake
+    pub fn key_epoch(&self) -> u32 {
+        self.session.crypto.key_epoch()
+    }
+
+    /// Move to the next key epoch and tell the peer with a KEY_UPDATE
+    ///
+    /// Data sealed from here on uses a key derived from the current one;
+    /// packets already sealed with the old key still open at the peer until
+    /// it retires that epoch. Only one earlier epoch is kept, so right after
+    /// an update this first waits (processing incoming packets) until the
+    /// previous epoch is retired. Requires `enable_key_updates` on both sides.
+    /// Returns the new epoch.
+    pub async fn update_keys(&mut self) -> Result<u32, TransportError> {
+        if !self.config.enable_key_updates {
+            return Err(anyhow::anyhow!("Key updates are not enabled").into());
+        }
+        if self.session.state != SessionState::Established {
+            return Err(TransportError::NotConnected);
+        }
+        
+        loop {
+            if self.closing.load(Ordering::Relaxed) {
+                return Err(self.closed_error());
+            }
+            self.retire_stale_epoch();
+            if !self.session.crypto.has_previous_epoch() {
+                break;
+            }
+            // Until the peer ACKs our last KEY_UPDATE there is no retirement time yet
+            let wait = self.retire_previous_epoch_at
+                .map_or(Duration::from_millis(200), |at| at.saturating_duration_since(std::time::Instant::now()));
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                res = self.process_incoming() => {
+                    if let Err(e) = res {
+                        tracing::debug!(peer = %self.peer_addr, "Receive while waiting to update keys failed: {}", e);
+                    }
+                }
+            }
+        }
+        
+        let epoch = self.session.crypto.update_keys()?;
+        self.start_key_epoch();
+        
+        let payload = serde_cbor::to_vec(&KeyUpdateFrame { epoch }).map_err(anyhow::Error::from)?;
+        let seq = self.reliability.next_sequence();
+        self.reliability.track_sent_frame(seq, 0, FRAME_TYPE_KEY_UPDATE, 0, Bytes::copy_from_slice(&payload), DeliveryMode::Reliable);
+        let packet = self.encode_sequenced(seq, FRAME_TYPE_KEY_UPDATE, 0, DeliveryMode::Reliable, 0, &payload)?;
+        self.key_update_seq = Some(seq);
+        self.lock_send_queue().enqueue(packet, QosPriority::System);
+        self.sender_notify.notify_one();
+        
+        tracing::debug!(peer = %self.peer_addr, epoch, "Updated session keys");
+        Ok(epoch)
+    }
+
+    /// Start an update when the current epoch reached `key_update_interval`
+    /// or `key_update_bytes`
+    ///
+    /// Waits until the previous epoch is retired, so the peer is never more
+    /// than one epoch behind.
+    async fn maybe_update_keys(&mut self) -> Result<()> {
+        if !self.config.enable_key_updates || self.session.state != SessionState::Established {
+            return Ok(());
+        }
+        self.retire_stale_epoch();
+        if self.session.crypto.has_previous_epoch() {
+            return Ok(());
+        }
+        
+        let expired = self.config.key_update_interval.is_some_and(|interval| self.key_epoch_started.elapsed() >= interval);
+        let exhausted = self.config.key_update_bytes.is_some_and(|bytes| self.key_epoch_bytes >= bytes);
+        if expired || exhausted {
+            self.update_keys().await?;
+        }
+        Ok(())
+    }
+
+    /// Catch up with a key update the peer made, up to `epoch`
+    fn follow_key_update(&mut self, epoch: u32) -> Result<()> {
+        if !self.config.enable_key_updates {
+            tracing::warn!(peer = %self.peer_addr, epoch, "Ignoring key update, key updates are not enabled");
+            return Ok(());
+        }
+        // Epochs wrap; anything up to half the space ahead is newer
+        if epoch.wrapping_sub(self.session.crypto.key_epoch()) as i32 <= 0 {
+            return Ok(());
+        }
+        while self.session.crypto.key_epoch() != epoch {
+            self.session.crypto.update_keys()?;
+        }
+        self.start_key_epoch();
+        // The peer already seals with this epoch
+        self.retire_previous_epoch_at = Some(std::time::Instant::now() + self.key_retire_delay());
+        
+        tracing::debug!(peer = %self.peer_addr, epoch, "Followed peer key update");
+        Ok(())
+    }
+
+    fn start_key_epoch(&mut self) {
+        self.key_epoch_started = std::time::Instant::now();
+        self.key_epoch_bytes = 0;
+        self.key_update_seq = None;
+        self.retire_previous_epoch_at = None;
+    }
+
+    /// Drop the previous epoch's key once its packets had time to arrive
+    fn retire_stale_epoch(&mut self) {
+        if self.retire_previous_epoch_at.is_some_and(|at| std::time::Instant::now() >= at) {
+            self.session.crypto.retire_previous_epoch();
+            self.retire_previous_epoch_at = None;
+        }
+    }
+
+    /// How long the previous epoch's key stays after the peer switched:
+    /// three probe timeouts, enough for reordered and resent packets
+    fn key_retire_delay(&self) -> Duration {
+        3 * self.path_probe_timeout()
+    }
+
+    /// Round trips the client waited before the server accepted application data
+    ///
+    /// 1 for a full handshake, 0 when early data was accepted on resumption.
+    pub fn handshake_round_trips(&self) -> u32 {
+        self.handshake_round_trips
+    }
+
     /// Open a new stream with specified delivery mode
-    pub fn open_stream(&mut self, priority: u8, mode: jsp_core::types::delivery::DeliveryMode) -> Result<u32> {
+    pub fn open_stream(&mut self, priority: u8, mode: jsp_core::types::delivery::DeliveryMode) -> Result<u32, TransportError> {
         use jsp_core::types::delivery::DeliveryMode;
         let stream_id = match mode {
             DeliveryMode::Reliable => self.session.open_reliable_stream(priority)?,
@@ -1037,9 +3249,20 @@ impl Connection {
         Ok(stream_id)
     }
 
+    /// Open a stream with an ID from `RESERVED_STREAM_ID_START` up
+    ///
+    /// Both peers open the same ID, so either can send on it without the other
+    /// having to learn a dynamically assigned one. Already open streams are kept.
+    pub fn open_reserved_stream(&mut self, stream_id: u32, priority: u8, mode: jsp_core::types::delivery::DeliveryMode) -> Result<u32, TransportError> {
+        self.session.streams_mut().open_reserved_stream(stream_id, priority, mode)
+            .map_err(|e| anyhow::anyhow!("Cannot open reserved stream {}: {}", stream_id, e))?;
+        Ok(stream_id)
+    }
+
     /// Gracefully close the connection
-    pub async fn close(&mut self, reason: CloseReason, message: Option<String>) -> Result<()> {
+    pub async fn close(&mut self, reason: CloseReason, message: Option<String>) -> Result<(), TransportError> {
         self.closing.store(true, Ordering::Relaxed);
+        self.local_close.get_or_insert(reason);
         
         tracing::info!(
             peer = %self.peer_addr,
@@ -1054,8 +3277,40 @@ impl Connection {
             CloseFrame { reason_code: reason, message: None }
         };
         
-        if let Ok(data) = serde_cbor::to_vec(&close_frame) {
-            let _ = self.transport.send_to(&data, self.peer_addr).await;
+        if let Ok(payload) = serde_cbor::to_vec(&close_frame) {
+            let header = Header::new(
+                0,
+                FRAME_TYPE_CLOSE,
+                0,
+                0,
+                0,
+                0,
+                DeliveryMode::BestEffort,
+                None,
+                Some(payload.len() as u32)
+            );
+            let header_bytes = if let Some(compressor) = self.header_compressor() {
+                compressor.compress(&header)
+            } else {
+                self.header_codec().encode_header(&header)?
+            };
+            let header_len = header_bytes.len() as u16;
+            
+            let mut packet = self.packet_pool.acquire();
+            packet.reserve(2 + header_bytes.len() + payload.len());
+            packet.extend_from_slice(&header_len.to_be_bytes());
+            packet.extend_from_slice(&header_bytes);
+            packet.extend_from_slice(&payload);
+            
+            let _ = self.transport.send_to(&packet, self.peer_addr).await;
+            self.packet_pool.release(packet);
+        }
+
+        // After the close frame, which may have gone through the relay
+        if let Some(agent) = &mut self.ice_agent {
+            if let Err(e) = agent.release_relay().await {
+                tracing::debug!("Failed to release the TURN relay: {}", e);
+            }
         }
         
         // Stop heartbeat task
@@ -1063,6 +3318,11 @@ impl Connection {
             task.abort();
         }
         
+        if let Some(mut span) = self.trace_span.take() {
+            span.set_attribute("close.reason", format!("{:?}", reason));
+            span.end();
+        }
+        
         tracing::info!(peer = %self.peer_addr, "Connection closed");
         
         Ok(())
@@ -1073,6 +3333,24 @@ impl Connection {
         self.closing.load(Ordering::Relaxed)
     }
 
+    /// What calls on a closing connection fail with: the peer's reason if
+    /// its close frame was read, else the one passed to `close`
+    fn closed_error(&self) -> TransportError {
+        let reason = self.peer_close.as_ref()
+            .map(|close| close.reason_code)
+            .or(self.local_close)
+            .unwrap_or(CloseReason::Normal);
+        TransportError::ConnectionClosed { reason }
+    }
+
+    /// The peer's close frame, once one was read
+    ///
+    /// `recv` drops the `Closed` event, so this is how its callers learn
+    /// why the peer went away.
+    pub fn peer_close(&self) -> Option<&CloseFrame> {
+        self.peer_close.as_ref()
+    }
+
     /// Get connection configuration
     pub fn config(&self) -> &ConnectionConfig {
         &self.config
@@ -1090,7 +3368,85 @@ impl Connection {
 
     /// Get connection metrics
     pub fn metrics(&self) -> crate::metrics::MetricsSnapshot {
-        self.metrics.snapshot()
+        let mut snapshot = self.metrics.snapshot();
+        
+        // Loss accounting lives in the reliability layer
+        let loss = self.reliability.loss_stats();
+        snapshot.packets_retransmitted = loss.packets_retransmitted;
+        snapshot.spurious_retransmits = loss.spurious_retransmits;
+        snapshot.loss_rate = self.reliability.loss_rate();
+        snapshot.congestion_window = self.reliability.congestion_window() as u64;
+        snapshot.congestion_state = Some(self.reliability.congestion_state());
+        snapshot.compression = Some(self.compression_decision());
+        snapshot.circuit_state = Some(self.circuit_breaker.state());
+        
+        // So does the send queue
+        let queue = self.lock_send_queue();
+        snapshot.send_queue_packets = queue.len() as u64;
+        snapshot.send_queue_bytes = queue.bytes() as u64;
+        snapshot.send_queue_dropped = queue.dropped_best_effort();
+        snapshot.send_queue_expired = queue.dropped_expired();
+        
+        snapshot
+    }
+
+    /// Send queue counters of streams with `priority`, including how long
+    /// their packets waited to be sent
+    pub fn send_queue_counters(&self, priority: QosPriority) -> PriorityCounters {
+        self.lock_send_queue().counters(priority)
+    }
+
+    /// Times this connection took the send queue lock, which its sender
+    /// task contends for on every packet; `send_batch` takes it once
+    pub fn send_queue_locks(&self) -> u64 {
+        self.send_queue_locks.load(Ordering::Relaxed)
+    }
+
+    /// Traffic, retransmits, delivery mode and priority of one stream
+    ///
+    /// None for a stream that neither side opened, or that was released
+    /// after FINs went both ways.
+    pub fn stream_stats(&self, stream_id: u32) -> Option<StreamStats> {
+        let stream = self.session.streams().get_stream(stream_id);
+        let counters = self.stream_counters.get(&stream_id);
+        if stream.is_none() && counters.is_none() {
+            return None;
+        }
+        let counters = counters.copied().unwrap_or_default();
+        let delivery_mode = stream.map(|stream| stream.delivery_mode)
+            .or_else(|| self.remote_stream_modes.get(&stream_id).copied())
+            .unwrap_or_default();
+        Some(StreamStats {
+            stream_id,
+            bytes_sent: counters.bytes_sent,
+            bytes_received: counters.bytes_received,
+            messages_sent: counters.messages_sent,
+            messages_received: counters.messages_received,
+            retransmits: self.reliability.stream_retransmits(stream_id),
+            delivery_mode,
+            priority: stream.map(|stream| stream.priority),
+        })
+    }
+
+    /// `stream_stats` of every stream, by stream ID
+    pub fn all_stream_stats(&self) -> Vec<StreamStats> {
+        let mut ids: Vec<u32> = self.session.streams().streams()
+            .map(|stream| stream.id)
+            .chain(self.stream_counters.keys().copied())
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids.into_iter().filter_map(|id| self.stream_stats(id)).collect()
+    }
+
+    /// Export this connection's metrics through `prometheus::export_metrics()`
+    ///
+    /// Values are refreshed on every scrape until the connection is dropped.
+    /// Labels should identify the connection, e.g. `[("connection_id", "42")]`.
+    pub fn register_metrics(&self, labels: &[(&str, &str)]) -> Result<()> {
+        let live = crate::prometheus::LiveMetrics::new(&self.metrics, labels)?;
+        crate::prometheus::global_registry().add_source(Box::new(live));
+        Ok(())
     }
 
     /// Process received heartbeat
@@ -1129,5 +3485,532 @@ impl Drop for Connection {
         if let Some(task) = self.flush_task.take() {
             task.abort();
         }
+        
+        // Abort sender task on drop
+        if let Some(task) = self.sender_task.take() {
+            task.abort();
+        }
+    }
+}
+
+/// Resolves when the watched interface list changes; never without a watcher
+async fn interfaces_changed(
+    interfaces: &mut Option<tokio::sync::watch::Receiver<Vec<NetworkInterface>>>,
+) -> std::result::Result<(), tokio::sync::watch::error::RecvError> {
+    match interfaces {
+        Some(interfaces) => interfaces.changed().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Next datagram on the path being probed; never while not probing
+async fn recv_on_probe(probe: Option<&PathProbe>, buf: &mut [u8]) -> Result<(usize, SocketAddr, EcnCodepoint)> {
+    match probe {
+        Some(probe) => probe.transport.recv_from_ecn(buf).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Fires when the current PATH_CHALLENGE on the probed path goes unanswered
+async fn probe_deadline(probe: Option<&PathProbe>) {
+    match probe {
+        Some(probe) => tokio::time::sleep_until(probe.deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Largest datagram the sender task coalesces: `pool_max_packet_size`,
+/// and no more than the path MTU when `mtu_discovery` is given
+fn coalesce_limit(config: &ConnectionConfig, mtu_discovery: Option<&Mutex<MtuDiscovery>>) -> usize {
+    match mtu_discovery {
+        Some(discovery) => config.pool_max_packet_size.min(discovery.lock().unwrap().current_mtu()),
+        None => config.pool_max_packet_size,
+    }
+}
+
+/// Lower the path MTU if the socket refused a datagram of `len` bytes as too big
+fn report_too_big(mtu_discovery: Option<&Mutex<MtuDiscovery>>, e: &anyhow::Error, len: usize) {
+    if let Some(discovery) = mtu_discovery {
+        discovery.lock().unwrap().on_send_error(e, len);
+    }
+}
+
+/// Fires when the MTU probe in flight times out or the next one is due;
+/// never while discovery is off
+async fn mtu_probe_deadline(at: Option<tokio::time::Instant>) {
+    match at {
+        Some(at) => tokio::time::sleep_until(at).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// `msg_type` frame naming connection `session_id`, for a peer address
+/// that doesn't know the connection yet
+///
+/// The header is never compressed, and CBOR whatever the session
+/// negotiated, so the peer can tell which connection it belongs to.
+fn path_frame(session_id: u64, msg_type: u8, payload: &[u8]) -> Vec<u8> {
+    let mut header = Header::new(0, msg_type, 0, 0, 0, 0, DeliveryMode::Reliable, None, Some(payload.len() as u32));
+    header.connection_id = Some(ConnectionId::from_u64(session_id));
+    let header_bytes = serde_cbor::to_vec(&header).expect("Failed to serialize Header");
+
+    let mut packet = Vec::with_capacity(2 + header_bytes.len() + payload.len());
+    packet.extend_from_slice(&(header_bytes.len() as u16).to_be_bytes());
+    packet.extend_from_slice(&header_bytes);
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// PATH_RESPONSE to `packet` if it is a PATH_CHALLENGE for connection `session_id`
+fn answer_path_challenge(session_id: u64, packet: &[u8]) -> Option<Vec<u8>> {
+    let header_len = u16::from_be_bytes([*packet.first()?, *packet.get(1)?]) as usize;
+    let header = decode_any_header(packet.get(2..2 + header_len)?).ok()?;
+    if header.msg_type != FRAME_TYPE_PATH_CHALLENGE || header.connection_id != Some(ConnectionId::from_u64(session_id)) {
+        return None;
+    }
+    let end = header.payload_len.map_or(packet.len(), |len| 2 + header_len + len as usize);
+    let challenge = serde_cbor::from_slice::<PathChallenge>(packet.get(2 + header_len..end)?).ok()?;
+    let payload = serde_cbor::to_vec(&PathResponse { token: challenge.token }).ok()?;
+    Some(path_frame(session_id, FRAME_TYPE_PATH_RESPONSE, &payload))
+}
+
+/// Whether `e` means the local socket or its interface is gone
+fn is_path_error(e: &anyhow::Error) -> bool {
+    use std::io::ErrorKind;
+    e.downcast_ref::<std::io::Error>().is_some_and(|e| matches!(
+        e.kind(),
+        ErrorKind::NotConnected | ErrorKind::AddrNotAvailable | ErrorKind::NetworkDown | ErrorKind::NetworkUnreachable
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConnectionConfigBuilder;
+    use crate::stun_server::StunServer;
+    use std::sync::atomic::AtomicUsize;
+    use tokio::time::timeout;
+
+    /// A STUN server behind a relay that counts the requests passing through
+    async fn spawn_counting_stun_server() -> Result<(SocketAddr, Arc<AtomicUsize>)> {
+        let mut stun_server = StunServer::new("127.0.0.1:0").await?;
+        let stun_addr = stun_server.local_addr()?;
+        stun_server.start();
+
+        let relay = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let relay_addr = relay.local_addr()?;
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        tokio::spawn(async move {
+            let _stun_server = stun_server;
+            let mut client = None;
+            let mut buf = vec![0u8; 2048];
+            while let Ok((len, src)) = relay.recv_from(&mut buf).await {
+                if src == stun_addr {
+                    if let Some(client) = client {
+                        let _ = relay.send_to(&buf[..len], client).await;
+                    }
+                } else {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    client = Some(src);
+                    let _ = relay.send_to(&buf[..len], stun_addr).await;
+                }
+            }
+        });
+
+        Ok((relay_addr, requests))
+    }
+
+    #[tokio::test]
+    async fn test_public_address_cached_for_stun_cache_ttl() -> Result<()> {
+        let (stun_addr, requests) = spawn_counting_stun_server().await?;
+        let config = ConnectionConfigBuilder::default()
+            .stun_servers(vec![stun_addr.to_string()])
+            .stun_timeout(Duration::from_secs(1))
+            .build();
+        let mut client = Connection::bind_with_config("127.0.0.1:0", config).await?;
+
+        let public_addr = client.discover_public_address(false).await?;
+        assert!(public_addr.is_some());
+        assert_eq!(client.discover_public_address(false).await?, public_addr);
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+
+        // Forcing skips the cache
+        assert_eq!(client.discover_public_address(true).await?, public_addr);
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_public_address_requeried_after_ttl() -> Result<()> {
+        let (stun_addr, requests) = spawn_counting_stun_server().await?;
+        let config = ConnectionConfigBuilder::default()
+            .stun_servers(vec![stun_addr.to_string()])
+            .stun_timeout(Duration::from_secs(1))
+            .stun_cache_ttl(Duration::from_millis(50))
+            .build();
+        let mut client = Connection::bind_with_config("127.0.0.1:0", config).await?;
+
+        assert!(client.discover_public_address(false).await?.is_some());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(client.discover_public_address(false).await?.is_some());
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+
+        Ok(())
+    }
+
+    /// A STUN responder that answers every binding request with `mapped`
+    ///
+    /// With `spoof_first`, each real answer is preceded by one carrying
+    /// another transaction ID and address, as an off-path attacker would send.
+    async fn spawn_mock_stun_server(mapped: SocketAddr, spoof_first: bool) -> Result<SocketAddr> {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = socket.local_addr()?;
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 2048];
+            while let Ok((len, src)) = socket.recv_from(&mut buf).await {
+                let request = match crate::ice::parse_stun_packet(&buf[..len]) {
+                    Some(request) => request,
+                    None => continue,
+                };
+                if spoof_first {
+                    let mut transaction_id = request.transaction_id;
+                    transaction_id[0] ^= 0xFF;
+                    let spoofed = StunMessage::binding_response(transaction_id, "198.51.100.66:6666".parse().unwrap());
+                    let _ = socket.send_to(&stun_packet(&spoofed).unwrap(), src).await;
+                }
+                let response = StunMessage::binding_response(request.transaction_id, mapped);
+                let _ = socket.send_to(&stun_packet(&response).unwrap(), src).await;
+            }
+        });
+        Ok(addr)
+    }
+
+    fn stun_config(servers: &[SocketAddr]) -> ConnectionConfig {
+        ConnectionConfigBuilder::default()
+            .stun_servers(servers.iter().map(|server| server.to_string()).collect())
+            .stun_timeout(Duration::from_secs(2))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_stun_response_with_wrong_transaction_id_ignored() -> Result<()> {
+        let mapped: SocketAddr = "203.0.113.7:41000".parse().unwrap();
+        let stun_addr = spawn_mock_stun_server(mapped, true).await?;
+        let mut client = Connection::bind_with_config("127.0.0.1:0", stun_config(&[stun_addr])).await?;
+
+        assert_eq!(client.discover_public_address(false).await?, Some(mapped));
+        assert_eq!(client.public_addr, Some(mapped));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stun_servers_queried_concurrently() -> Result<()> {
+        // The first server never answers; waiting on it would use up the whole timeout
+        let dead = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let mapped: SocketAddr = "203.0.113.7:41000".parse().unwrap();
+        let live = spawn_mock_stun_server(mapped, false).await?;
+        let mut client = Connection::bind_with_config("127.0.0.1:0", stun_config(&[dead.local_addr()?, live])).await?;
+
+        let start = std::time::Instant::now();
+        assert_eq!(client.discover_public_address(false).await?, Some(mapped));
+        assert!(start.elapsed() < Duration::from_secs(1), "took {:?}", start.elapsed());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_nat_type_from_two_servers() -> Result<()> {
+        let public: SocketAddr = "203.0.113.7:41000".parse().unwrap();
+        let other_port: SocketAddr = "203.0.113.7:41001".parse().unwrap();
+
+        // Both servers see the same mapping
+        let first = spawn_mock_stun_server(public, false).await?;
+        let second = spawn_mock_stun_server(public, false).await?;
+        let mut client = Connection::bind_with_config("127.0.0.1:0", stun_config(&[first, second])).await?;
+        assert_eq!(client.nat_type(), NatType::Unknown);
+        assert_eq!(client.detect_nat_type().await?, NatType::Cone);
+        assert_eq!(client.nat_type(), NatType::Cone);
+        assert_eq!(client.public_addr, Some(public));
+
+        // Each server sees another port
+        let first = spawn_mock_stun_server(public, false).await?;
+        let second = spawn_mock_stun_server(other_port, true).await?;
+        let mut client = Connection::bind_with_config("127.0.0.1:0", stun_config(&[first, second])).await?;
+        assert_eq!(client.detect_nat_type().await?, NatType::Symmetric);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rebind_when_local_socket_dies() -> Result<()> {
+        let mut server = Connection::bind_with_config("127.0.0.1:0", ConnectionConfig::default()).await?;
+        let server_addr = server.local_addr()?;
+        let server_task = tokio::spawn(async move {
+            server.handshake().await.unwrap();
+            let mut events = Vec::new();
+            loop {
+                let event = server.next_event().await.unwrap();
+                let done = matches!(event, ConnectionEvent::DataReceived { .. });
+                events.push(event);
+                if done {
+                    break;
+                }
+            }
+            events
+        });
+
+        let mut client = Connection::connect_with_config(&server_addr.to_string(), ConnectionConfig::default()).await?;
+        client.handshake().await?;
+        let old_local = client.local_addr()?;
+
+        // What the connection sees once the interface behind its socket is gone
+        client.transport.as_udp().unwrap().close();
+
+        let new_local = match timeout(Duration::from_secs(2), client.next_event()).await?? {
+            ConnectionEvent::Rebound { old_local: old, new_local } => {
+                assert_eq!(old, old_local);
+                new_local
+            }
+            other => panic!("expected Rebound, got {:?}", other),
+        };
+        assert_ne!(new_local.port(), old_local.port());
+        assert_eq!(client.local_addr()?, new_local);
+
+        // The peer follows the client to the new port and data keeps flowing
+        let stream_id = client.open_stream(0, DeliveryMode::Reliable)?;
+        client.send_on_stream(stream_id, b"after rebind").await?;
+
+        let events = timeout(Duration::from_secs(5), server_task).await??;
+        match &events[0] {
+            ConnectionEvent::PeerMigrated { old, new } => {
+                assert_eq!(old.port(), old_local.port());
+                assert_eq!(new.port(), new_local.port());
+            }
+            other => panic!("expected PeerMigrated, got {:?}", other),
+        }
+        assert_eq!(
+            events.last(),
+            Some(&ConnectionEvent::DataReceived { stream_id, data: bytes::Bytes::from_static(b"after rebind") })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stalled_send_queue_sheds_best_effort_and_blocks_reliable() -> Result<()> {
+        let config = ConnectionConfigBuilder::default()
+            .send_queue_max_packets(8)
+            .rate_limit_messages(1000)
+            .build();
+        let mut server = Connection::bind_with_config("127.0.0.1:0", config.clone()).await?;
+        let server_addr = server.local_addr()?;
+        let server_task = tokio::spawn(async move {
+            server.handshake().await.unwrap();
+            server
+        });
+
+        let mut client = Connection::connect_with_config(&server_addr.to_string(), config).await?;
+        client.handshake().await?;
+        let _server = timeout(Duration::from_secs(5), server_task).await??;
+
+        // Nothing leaves the queue any more, as if send_to never resolved
+        client.sender_task.take().unwrap().abort();
+
+        let best_effort = client.open_stream(0, DeliveryMode::BestEffort)?;
+        for i in 0..100u8 {
+            client.send_on_stream(best_effort, &[i; 100]).await?;
+        }
+        let metrics = client.metrics();
+        assert_eq!(metrics.send_queue_packets, 8);
+        assert_eq!(metrics.send_queue_bytes, 800);
+        assert_eq!(metrics.send_queue_dropped, 92);
+
+        // Reliable data displaces what is left of it, then has to wait
+        let reliable = client.open_stream(1, DeliveryMode::Reliable)?;
+        for i in 0..8u8 {
+            client.send_on_stream(reliable, &[i; 100]).await?;
+        }
+        assert!(client.send_on_stream(reliable, b"no room").await.is_err());
+        assert!(client.send_on_stream_wait(reliable, b"no room", Some(Duration::from_millis(300))).await.is_err());
+
+        client.send_on_stream(best_effort, b"shed").await?;
+        let metrics = client.metrics();
+        assert_eq!(metrics.send_queue_packets, 8);
+        assert_eq!(metrics.send_queue_dropped, 101);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_half_open_probe_not_taken_by_send_rejected_for_full_queue() -> Result<()> {
+        let breaker = crate::circuit_breaker::CircuitBreakerConfig {
+            failure_threshold: 1,
+            reset_timeout: Duration::from_millis(300),
+            success_threshold: 1,
+            half_open_max_probes: 1,
+        };
+        let config = ConnectionConfigBuilder::default()
+            .circuit_breaker(breaker)
+            .send_queue_max_packets(4)
+            .rate_limit_messages(1000)
+            .build();
+        let mut server = Connection::bind_with_config("127.0.0.1:0", config.clone()).await?;
+        let server_addr = server.local_addr()?;
+        let server_task = tokio::spawn(async move {
+            server.handshake().await.unwrap();
+            server
+        });
+
+        let mut client = Connection::connect_with_config(&server_addr.to_string(), config).await?;
+        client.handshake().await?;
+        let _server = timeout(Duration::from_secs(5), server_task).await??;
+
+        // A stalled queue full of reliable data, then an open circuit
+        client.sender_task.take().unwrap().abort();
+        let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+        for i in 0..4u8 {
+            client.send_on_stream(stream_id, &[i; 100]).await?;
+        }
+        client.circuit_breaker.record_failure();
+        assert_eq!(client.circuit_state(), CircuitState::Open);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        // Refused for the queue, before the breaker hands out its only probe
+        let err = client.send_on_stream(stream_id, b"no room").await.unwrap_err();
+        assert!(matches!(err, TransportError::SendQueueFull), "{:?}", err);
+        assert_eq!(client.circuit_state(), CircuitState::Open);
+
+        // Once the queue drains, the next send is that probe
+        client.start_sender_task();
+        timeout(Duration::from_secs(2), async {
+            while client.metrics().send_queue_packets > 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await?;
+        client.send_on_stream(stream_id, b"probe").await?;
+        assert_eq!(client.circuit_state(), CircuitState::HalfOpen);
+        timeout(Duration::from_secs(2), async {
+            while client.circuit_state() != CircuitState::Closed {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_opens_on_failing_path_and_probes_back() -> Result<()> {
+        let breaker = crate::circuit_breaker::CircuitBreakerConfig {
+            failure_threshold: 3,
+            reset_timeout: Duration::from_millis(300),
+            success_threshold: 2,
+            half_open_max_probes: 2,
+        };
+        let config = ConnectionConfigBuilder::default()
+            .circuit_breaker(breaker)
+            .rate_limit_messages(1000)
+            .build();
+        let mut server = Connection::bind_with_config("127.0.0.1:0", config.clone()).await?;
+        let server_addr = server.local_addr()?;
+        let server_task = tokio::spawn(async move {
+            server.handshake().await.unwrap();
+            server
+        });
+
+        let mut client = Connection::connect_with_config(&server_addr.to_string(), config).await?;
+        client.handshake().await?;
+        let _server = timeout(Duration::from_secs(5), server_task).await??;
+        // BestEffort, so neither the congestion window nor ACKs get in the way
+        let stream_id = client.open_stream(0, DeliveryMode::BestEffort)?;
+        let udp = client.transport.as_udp().unwrap().clone();
+        // Each send is its own socket write, and its outcome is recorded
+        // before the next one
+        let settle = || tokio::time::sleep(Duration::from_millis(50));
+
+        // closed -> open after three failed writes
+        udp.set_send_fault(true);
+        for i in 0..3u8 {
+            assert_eq!(client.circuit_state(), CircuitState::Closed);
+            client.send_on_stream(stream_id, &[i; 10]).await?;
+            settle().await;
+        }
+        assert_eq!(client.circuit_state(), CircuitState::Open);
+        assert_eq!(client.metrics().circuit_breaker_trips, 1);
+        assert_eq!(client.metrics().circuit_state, Some(CircuitState::Open));
+
+        // Sends fail fast with their own error while open, path fixed or not
+        udp.set_send_fault(false);
+        let started = std::time::Instant::now();
+        let err = client.send_on_stream(stream_id, b"rejected").await.unwrap_err();
+        assert!(matches!(err, TransportError::CircuitOpen), "{:?}", err);
+        assert!(err.is_transient());
+        assert!(started.elapsed() < Duration::from_millis(50));
+
+        // open -> half-open once the timeout passes; two probes, then refusal
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        client.send_on_stream(stream_id, b"probe 1").await?;
+        assert_eq!(client.circuit_state(), CircuitState::HalfOpen);
+        client.send_on_stream(stream_id, b"probe 2").await?;
+        assert!(matches!(client.send_on_stream(stream_id, b"third").await, Err(TransportError::CircuitOpen)));
+
+        // half-open -> closed once both probes went out
+        settle().await;
+        assert_eq!(client.circuit_state(), CircuitState::Closed);
+        client.send_on_stream(stream_id, b"closed again").await?;
+
+        let events = timeout(Duration::from_secs(1), client.recv_events()).await??;
+        let transitions: Vec<_> = events.into_iter()
+            .filter_map(|event| match event {
+                ConnectionEvent::CircuitStateChanged { old, new } => Some((old, new)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(transitions, vec![
+            (CircuitState::Closed, CircuitState::Open),
+            (CircuitState::Open, CircuitState::HalfOpen),
+            (CircuitState::HalfOpen, CircuitState::Closed),
+        ]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multi_frame_datagram_delivered_as_slices() -> Result<()> {
+        let mut server = Connection::bind_with_config("127.0.0.1:0", ConnectionConfig::default()).await?;
+        let server_addr = server.local_addr()?;
+        let server_task = tokio::spawn(async move {
+            server.handshake().await.unwrap();
+            server
+        });
+
+        let mut client = Connection::connect_with_config(&server_addr.to_string(), ConnectionConfig::default()).await?;
+        client.handshake().await?;
+        let mut server = timeout(Duration::from_secs(5), server_task).await??;
+
+        // Two frames in one datagram, as the coalescing sender produces them
+        let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+        let mut datagram = client.build_data_packet(stream_id, DeliveryMode::Reliable, 0, b"first")?;
+        datagram.extend(client.build_data_packet(stream_id, DeliveryMode::Reliable, 0, b"second")?);
+        client.transport.send_to(&datagram, server_addr).await?;
+
+        let mut received = Vec::new();
+        while received.len() < 2 {
+            received.extend(timeout(Duration::from_secs(5), server.recv()).await??);
+        }
+        assert_eq!(received, vec![
+            (stream_id, Bytes::from_static(b"first")),
+            (stream_id, Bytes::from_static(b"second")),
+        ]);
+
+        // Both payloads point into the one receive buffer
+        let offset = received[1].1.as_ptr() as usize - received[0].1.as_ptr() as usize;
+        assert!(offset < datagram.len(), "payloads {} bytes apart", offset);
+
+        // Which goes back to the pool once the application lets go of them
+        let released = server.recv_pool.metrics().total_released;
+        drop(received);
+        assert_eq!(server.recv_pool.metrics().total_released, released + 1);
+
+        Ok(())
     }
 }
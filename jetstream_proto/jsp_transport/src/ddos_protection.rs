@@ -1,11 +1,16 @@
 use std::collections::HashMap;
-use std::net::IpAddr;
-use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::RwLock;
 use crate::rate_limit::RateLimiter;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct DdosConfig {
     /// Max packets per second per IP
     pub max_packets_per_ip: u32,
@@ -13,10 +18,33 @@ pub struct DdosConfig {
     pub max_bytes_per_ip: u64,
     /// Max handshakes (ClientHello) per second per IP
     pub max_handshakes_per_ip: u32,
-    /// Duration to ban an IP if it exceeds limits significantly (optional)
+    /// Prefix length IPv4 sources are grouped by for the per-subnet limits
+    /// (None = per-IP limits only). Subnets are rate limited but never
+    /// banned, as spoofed hellos from a few addresses could ban the rest.
+    pub ipv4_subnet_prefix: Option<u8>,
+    /// Prefix length IPv6 sources are grouped by for the per-subnet limits
+    /// (None = per-IP limits only)
+    pub ipv6_subnet_prefix: Option<u8>,
+    /// Max packets per second per subnet, on top of the per-IP limit
+    pub max_packets_per_subnet: u32,
+    /// Max bytes per second per subnet
+    pub max_bytes_per_subnet: u64,
+    /// Max handshakes per second per subnet
+    pub max_handshakes_per_subnet: u32,
+    /// Duration to ban an IP if it exceeds the handshake limit (optional)
+    #[serde(with = "crate::duration_format::option")]
     pub ban_duration: Option<Duration>,
     /// Cleanup interval for removing stale IP records
+    #[serde(with = "crate::duration_format")]
     pub cleanup_interval: Duration,
+    /// ClientHellos per second, across all sources, above which new clients
+    /// must prove their address with a retry token first (None = never).
+    /// `Some(0)` asks on every first contact, so no session state exists for
+    /// a source address until it has echoed a token.
+    pub retry_threshold: Option<u32>,
+    /// How long a retry token stays valid
+    #[serde(with = "crate::duration_format")]
+    pub retry_token_lifetime: Duration,
 }
 
 impl Default for DdosConfig {
@@ -25,8 +53,41 @@ impl Default for DdosConfig {
             max_packets_per_ip: 1000,
             max_bytes_per_ip: 1024 * 1024, // 1 MB/s
             max_handshakes_per_ip: 5,
+            ipv4_subnet_prefix: None,
+            ipv6_subnet_prefix: None,
+            max_packets_per_subnet: 10_000,
+            max_bytes_per_subnet: 10 * 1024 * 1024, // 10 MB/s
+            max_handshakes_per_subnet: 50,
             ban_duration: Some(Duration::from_secs(60)),
             cleanup_interval: Duration::from_secs(60),
+            retry_threshold: Some(1000),
+            retry_token_lifetime: Duration::from_secs(10),
+        }
+    }
+}
+
+/// What a set of limits applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Source {
+    Ip(IpAddr),
+    /// Network address and prefix length
+    Subnet(IpAddr, u8),
+}
+
+impl Source {
+    /// The subnet `ip` is in, if subnet limits are enabled for its family
+    fn subnet_of(ip: IpAddr, config: &DdosConfig) -> Option<Self> {
+        match ip {
+            IpAddr::V4(ip) => {
+                let prefix = config.ipv4_subnet_prefix?.min(32);
+                let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+                Some(Source::Subnet(IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask)), prefix))
+            }
+            IpAddr::V6(ip) => {
+                let prefix = config.ipv6_subnet_prefix?.min(128);
+                let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+                Some(Source::Subnet(IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask)), prefix))
+            }
         }
     }
 }
@@ -39,21 +100,103 @@ struct IpState {
 }
 
 impl IpState {
-    fn new(config: &DdosConfig) -> Self {
+    fn new(config: &DdosConfig, source: Source) -> Self {
+        let (packets, bytes, handshakes) = match source {
+            Source::Ip(_) => (config.max_packets_per_ip, config.max_bytes_per_ip, config.max_handshakes_per_ip),
+            Source::Subnet(..) => (config.max_packets_per_subnet, config.max_bytes_per_subnet, config.max_handshakes_per_subnet),
+        };
         Self {
-            packet_limiter: RateLimiter::new(config.max_packets_per_ip, config.max_bytes_per_ip),
+            packet_limiter: RateLimiter::new(packets, bytes),
             // For handshakes, we only care about count, so use a large byte limit
-            handshake_limiter: RateLimiter::new(config.max_handshakes_per_ip, u64::MAX),
+            handshake_limiter: RateLimiter::new(handshakes, u64::MAX),
             last_activity: Instant::now(),
             banned_until: None,
         }
     }
+
+    /// Whether a ban is in force, lifting it once it has run out
+    fn is_banned(&mut self) -> bool {
+        match self.banned_until {
+            Some(banned_until) if Instant::now() < banned_until => true,
+            _ => {
+                self.banned_until = None;
+                false
+            }
+        }
+    }
+}
+
+/// Stateless address-validation tokens, SYN-cookie style
+///
+/// A token is `timestamp (u64 BE seconds) || HMAC-SHA256(key, ip || port ||
+/// client random || timestamp)`, so the server can check it without having
+/// kept anything for the client.
+#[derive(Clone)]
+pub struct RetryTokens {
+    key: [u8; 32],
+    lifetime: Duration,
+}
+
+impl RetryTokens {
+    /// Tokens signed with a fresh random key
+    pub fn new(lifetime: Duration) -> Self {
+        let mut key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        Self { key, lifetime }
+    }
+
+    fn mac(&self, addr: SocketAddr, client_random: &[u8; 32], timestamp: u64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+        match addr.ip() {
+            IpAddr::V4(ip) => mac.update(&ip.octets()),
+            IpAddr::V6(ip) => mac.update(&ip.octets()),
+        }
+        mac.update(&addr.port().to_be_bytes());
+        mac.update(client_random);
+        mac.update(&timestamp.to_be_bytes());
+        mac
+    }
+
+    /// Token for a ClientHello carrying `client_random` from `addr`
+    pub fn issue(&self, addr: SocketAddr, client_random: &[u8; 32]) -> Vec<u8> {
+        let timestamp = unix_secs();
+        let mut token = timestamp.to_be_bytes().to_vec();
+        token.extend_from_slice(&self.mac(addr, client_random, timestamp).finalize().into_bytes());
+        token
+    }
+
+    /// Whether `token` was issued by us to `addr` for `client_random` and hasn't expired
+    pub fn validate(&self, token: &[u8], addr: SocketAddr, client_random: &[u8; 32]) -> bool {
+        if token.len() != 8 + 32 {
+            return false;
+        }
+        let (timestamp, tag) = token.split_at(8);
+        let timestamp = u64::from_be_bytes(timestamp.try_into().expect("8 bytes"));
+        let now = unix_secs();
+        if timestamp > now || now - timestamp > self.lifetime.as_secs() {
+            return false;
+        }
+        self.mac(addr, client_random, timestamp).verify_slice(tag).is_ok()
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// ClientHellos seen in the current one-second window
+struct HandshakeWindow {
+    start: Instant,
+    count: u32,
 }
 
 #[derive(Clone)]
 pub struct DdosProtection {
     config: DdosConfig,
-    ip_states: Arc<RwLock<HashMap<IpAddr, IpState>>>,
+    /// Per-IP and per-subnet limits
+    ip_states: Arc<RwLock<HashMap<Source, IpState>>>,
+    retry_tokens: RetryTokens,
+    handshakes: Arc<Mutex<HandshakeWindow>>,
     // cleanup_task is spawned detached, so we don't hold a handle here to simplify cloning
     // In a real app we might want to control it better
 }
@@ -63,6 +206,8 @@ impl DdosProtection {
         let protection = Self {
             config: config.clone(),
             ip_states: Arc::new(RwLock::new(HashMap::new())),
+            retry_tokens: RetryTokens::new(config.retry_token_lifetime),
+            handshakes: Arc::new(Mutex::new(HandshakeWindow { start: Instant::now(), count: 0 })),
         };
         
         // Start cleanup task
@@ -86,65 +231,119 @@ impl DdosProtection {
         protection
     }
 
+    /// The IP itself and, with subnet limits, the subnet it is in
+    fn sources(&self, ip: IpAddr) -> impl Iterator<Item = Source> {
+        std::iter::once(Source::Ip(ip)).chain(Source::subnet_of(ip, &self.config))
+    }
+
     /// Check if a packet from the given IP is allowed
+    ///
+    /// It must pass both the IP's and its subnet's limits, and is only
+    /// counted against them if it does.
     pub async fn check_packet(&self, ip: IpAddr, len: usize) -> bool {
         let mut states = self.ip_states.write().await;
         
-        let state = states.entry(ip).or_insert_with(|| IpState::new(&self.config));
-        state.last_activity = Instant::now();
-        
-        // Check ban
-        if let Some(banned_until) = state.banned_until {
-            if Instant::now() < banned_until {
-                return false;
-            } else {
-                state.banned_until = None;
+        let mut allowed = true;
+        for source in self.sources(ip) {
+            let state = states.entry(source).or_insert_with(|| IpState::new(&self.config, source));
+            state.last_activity = Instant::now();
+            
+            if state.is_banned() || state.packet_limiter.time_until_available(len) != Some(Duration::ZERO) {
+                // If limit exceeded, potentially ban
+                // For now, just reject.
+                allowed = false;
             }
         }
         
-        if !state.packet_limiter.check_and_consume(len) {
-            // If limit exceeded, potentially ban
-            // For now, just reject.
-            return false;
+        if allowed {
+            for source in self.sources(ip) {
+                if let Some(state) = states.get_mut(&source) {
+                    state.packet_limiter.check_and_consume(len);
+                }
+            }
         }
-        
-        true
+        allowed
+    }
+
+    /// Count a ClientHello; true if clients must now present a retry token
+    ///
+    /// This is checked before any crypto, so under a flood of spoofed hellos
+    /// the server only ever computes HMACs.
+    pub fn requires_retry(&self) -> bool {
+        let threshold = match self.config.retry_threshold {
+            Some(threshold) => threshold,
+            None => return false,
+        };
+        let mut window = self.handshakes.lock().unwrap();
+        if window.start.elapsed() >= Duration::from_secs(1) {
+            window.start = Instant::now();
+            window.count = 0;
+        }
+        window.count = window.count.saturating_add(1);
+        window.count > threshold
+    }
+
+    pub fn retry_tokens(&self) -> &RetryTokens {
+        &self.retry_tokens
     }
 
     /// Check if a handshake attempt from the given IP is allowed
+    ///
+    /// Like packets, it must pass both the IP's and its subnet's limits. A
+    /// flood bans the IP; a subnet over its limit only turns hellos away
+    /// until it has budget again. Hellos are checked before their source
+    /// address is validated, so banning a subnet would let anyone lock out
+    /// a whole /24 or /64 by spoofing a few addresses in it.
     pub async fn check_handshake(&self, ip: IpAddr) -> bool {
         let mut states = self.ip_states.write().await;
         
-        let state = states.entry(ip).or_insert_with(|| IpState::new(&self.config));
-        state.last_activity = Instant::now();
-        
-        // Check ban
-        if let Some(banned_until) = state.banned_until {
-            if Instant::now() < banned_until {
-                return false;
-            } else {
-                state.banned_until = None;
-            }
+        let mut banned = false;
+        for source in self.sources(ip) {
+            let state = states.entry(source).or_insert_with(|| IpState::new(&self.config, source));
+            state.last_activity = Instant::now();
+            banned |= state.is_banned();
+        }
+        if banned {
+            return false;
         }
         
         // Handshake limit check (size 0 because we only count messages)
-        if !state.handshake_limiter.check_and_consume(0) {
-            // Ban on handshake flood
-            if let Some(ban_duration) = self.config.ban_duration {
-                state.banned_until = Some(Instant::now() + ban_duration);
-                tracing::warn!(%ip, "IP banned due to handshake flood");
+        let mut allowed = true;
+        for source in self.sources(ip) {
+            let state = match states.get_mut(&source) {
+                Some(state) => state,
+                None => continue,
+            };
+            if state.handshake_limiter.time_until_available(0) == Some(Duration::ZERO) {
+                continue;
+            }
+            allowed = false;
+            match (source, self.config.ban_duration) {
+                (Source::Ip(ip), Some(ban_duration)) => {
+                    state.banned_until = Some(Instant::now() + ban_duration);
+                    tracing::warn!(%ip, "IP banned due to handshake flood");
+                }
+                (Source::Subnet(network, prefix), _) => {
+                    tracing::debug!(%ip, subnet = %format!("{}/{}", network, prefix), "Subnet over its handshake limit");
+                }
+                _ => {}
             }
-            return false;
         }
         
-        true
+        if allowed {
+            for source in self.sources(ip) {
+                if let Some(state) = states.get_mut(&source) {
+                    state.handshake_limiter.check_and_consume(0);
+                }
+            }
+        }
+        allowed
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::net::Ipv4Addr;
 
     #[tokio::test]
     async fn test_packet_limit() {
@@ -181,4 +380,151 @@ mod tests {
         // Should still be banned even if packet limit is fine
         assert!(!ddos.check_packet(ip, 100).await);
     }
+
+    #[tokio::test]
+    async fn test_subnet_packet_limit() {
+        let ddos = DdosProtection::new(DdosConfig {
+            ipv6_subnet_prefix: Some(64),
+            max_packets_per_subnet: 10,
+            ..Default::default()
+        });
+        let in_prefix = |host: u16| IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, host, 0, 0, 1));
+        
+        // Rotating addresses within one /64 doesn't escape the limit
+        for host in 0..10 {
+            assert!(ddos.check_packet(in_prefix(host), 100).await);
+        }
+        assert!(!ddos.check_packet(in_prefix(10), 100).await);
+        
+        // Another /64 has its own budget
+        assert!(ddos.check_packet(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 0, 0, 0, 1)), 100).await);
+    }
+
+    #[tokio::test]
+    async fn test_stricter_limit_applies() {
+        let ddos = DdosProtection::new(DdosConfig {
+            max_packets_per_ip: 3,
+            ipv4_subnet_prefix: Some(24),
+            max_packets_per_subnet: 5,
+            ..Default::default()
+        });
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let neighbour = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+        
+        for _ in 0..3 {
+            assert!(ddos.check_packet(ip, 100).await);
+        }
+        // The IP's own limit is hit first
+        assert!(!ddos.check_packet(ip, 100).await);
+        
+        // Packets rejected for the IP didn't use up the subnet's budget
+        assert!(ddos.check_packet(neighbour, 100).await);
+        assert!(ddos.check_packet(neighbour, 100).await);
+        assert!(!ddos.check_packet(neighbour, 100).await);
+        
+        // Without aggregation addresses are independent
+        let exact = DdosProtection::new(DdosConfig {
+            max_packets_per_ip: 3,
+            max_packets_per_subnet: 5,
+            ipv4_subnet_prefix: None,
+            ..Default::default()
+        });
+        for host in 1..=10 {
+            assert!(exact.check_packet(IpAddr::V4(Ipv4Addr::new(192, 0, 2, host)), 100).await);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subnet_handshake_limit_never_bans() {
+        let ddos = DdosProtection::new(DdosConfig {
+            ipv6_subnet_prefix: Some(64),
+            max_handshakes_per_subnet: 3,
+            ban_duration: Some(Duration::from_secs(60)),
+            ..Default::default()
+        });
+        let in_prefix = |host: u16| IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, host));
+        
+        for host in 1..=3 {
+            assert!(ddos.check_handshake(in_prefix(host)).await);
+        }
+        // The fourth address is over the /64's limit...
+        assert!(!ddos.check_handshake(in_prefix(4)).await);
+        assert!(!ddos.check_handshake(in_prefix(5)).await);
+        // ...but nothing in it is banned: other traffic passes, and
+        // hellos do again once the limit has refilled
+        assert!(ddos.check_packet(in_prefix(6), 100).await);
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(ddos.check_handshake(in_prefix(6)).await);
+        
+        assert!(ddos.check_handshake(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 0, 0, 0, 1))).await);
+    }
+
+    #[test]
+    fn test_subnet_limits_are_opt_in() {
+        let config = DdosConfig::default();
+        assert_eq!(Source::subnet_of("192.0.2.77".parse().unwrap(), &config), None);
+        assert_eq!(Source::subnet_of("2001:db8::1".parse().unwrap(), &config), None);
+    }
+
+    #[test]
+    fn test_subnet_of() {
+        let config = DdosConfig { ipv4_subnet_prefix: Some(24), ipv6_subnet_prefix: Some(64), ..Default::default() };
+        assert_eq!(
+            Source::subnet_of("192.0.2.77".parse().unwrap(), &config),
+            Some(Source::Subnet("192.0.2.0".parse().unwrap(), 24))
+        );
+        assert_eq!(
+            Source::subnet_of("2001:db8::1:2:3:4".parse().unwrap(), &config),
+            Some(Source::Subnet("2001:db8::".parse().unwrap(), 64))
+        );
+        
+        let config = DdosConfig { ipv4_subnet_prefix: Some(0), ipv6_subnet_prefix: None, ..Default::default() };
+        assert_eq!(
+            Source::subnet_of("192.0.2.77".parse().unwrap(), &config),
+            Some(Source::Subnet("0.0.0.0".parse().unwrap(), 0))
+        );
+        assert_eq!(Source::subnet_of("2001:db8::1".parse().unwrap(), &config), None);
+    }
+
+    #[test]
+    fn test_retry_token_binding() {
+        let tokens = RetryTokens::new(Duration::from_secs(10));
+        let addr: SocketAddr = "192.0.2.1:4000".parse().unwrap();
+        let random = [7u8; 32];
+
+        let token = tokens.issue(addr, &random);
+        assert!(tokens.validate(&token, addr, &random));
+
+        // Bound to the address, the hello and the issuing server
+        assert!(!tokens.validate(&token, "192.0.2.1:4001".parse().unwrap(), &random));
+        assert!(!tokens.validate(&token, "192.0.2.2:4000".parse().unwrap(), &random));
+        assert!(!tokens.validate(&token, addr, &[8u8; 32]));
+        assert!(!RetryTokens::new(Duration::from_secs(10)).validate(&token, addr, &random));
+
+        let mut tampered = token.clone();
+        tampered[20] ^= 1;
+        assert!(!tokens.validate(&tampered, addr, &random));
+        assert!(!tokens.validate(&token[..20], addr, &random));
+
+        // Expired once older than the lifetime
+        let mut old = (unix_secs() - 11).to_be_bytes().to_vec();
+        old.extend_from_slice(&tokens.mac(addr, &random, unix_secs() - 11).finalize().into_bytes());
+        assert!(!tokens.validate(&old, addr, &random));
+    }
+
+    #[tokio::test]
+    async fn test_retry_above_handshake_threshold() {
+        let ddos = DdosProtection::new(DdosConfig {
+            retry_threshold: Some(3),
+            ..Default::default()
+        });
+        let required: Vec<bool> = (0..5).map(|_| ddos.requires_retry()).collect();
+        assert_eq!(required, [false, false, false, true, true]);
+
+        let off = DdosProtection::new(DdosConfig {
+            retry_threshold: None,
+            ..Default::default()
+        });
+        assert!((0..5000).all(|_| !off.requires_retry()));
+    }
 }
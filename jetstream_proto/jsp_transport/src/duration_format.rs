@@ -0,0 +1,128 @@
+//! Human-friendly `Duration` fields for config files
+//!
+//! Durations are written as an integer and a unit ("250ms", "5s", "2m"),
+//! optionally combined ("1m 30s"). Use with `#[serde(with = "crate::duration_format")]`,
+//! or `duration_format::option` for `Option<Duration>`.
+
+use serde::{Deserialize, Deserializer, Serializer};
+use std::time::Duration;
+
+const UNITS: [(&str, u64); 8] = [
+    ("ns", 1),
+    ("us", 1_000),
+    ("µs", 1_000),
+    ("ms", 1_000_000),
+    ("s", 1_000_000_000),
+    ("m", 60 * 1_000_000_000),
+    ("h", 3_600 * 1_000_000_000),
+    ("d", 86_400 * 1_000_000_000),
+];
+
+/// Parse "250ms", "5s", "1h 30m" and the like
+pub fn parse(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err("empty duration".to_string());
+    }
+
+    let mut total: u128 = 0;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(format!("invalid duration {:?}: expected a number", text));
+        }
+        let value: u128 = rest[..digits].parse().map_err(|_| format!("invalid duration {:?}", text))?;
+        rest = rest[digits..].trim_start();
+
+        let unit_len = rest.find(|c: char| c.is_ascii_digit() || c.is_whitespace()).unwrap_or(rest.len());
+        let unit = &rest[..unit_len];
+        let nanos = match UNITS.iter().find(|(name, _)| *name == unit) {
+            Some((_, nanos)) => *nanos,
+            None if unit.is_empty() => {
+                return Err(format!("invalid duration {:?}: missing unit (e.g. \"{}s\" or \"{}ms\")", text, value, value));
+            }
+            None => return Err(format!("invalid duration {:?}: unknown unit {:?}", text, unit)),
+        };
+        total += value * nanos as u128;
+        rest = rest[unit_len..].trim_start();
+    }
+
+    let secs = u64::try_from(total / 1_000_000_000).map_err(|_| format!("duration {:?} is too large", text))?;
+    Ok(Duration::new(secs, (total % 1_000_000_000) as u32))
+}
+
+/// Format with the largest unit that represents the duration exactly
+pub fn format(duration: Duration) -> String {
+    let nanos = duration.as_nanos();
+    if nanos == 0 {
+        return "0s".to_string();
+    }
+
+    // Largest unit first, skipping the "µs" alias
+    for (name, unit) in UNITS.iter().rev().filter(|(name, _)| *name != "µs") {
+        let unit = *unit as u128;
+        if nanos.is_multiple_of(unit) {
+            return format!("{}{}", nanos / unit, name);
+        }
+    }
+    unreachable!("every duration is a whole number of nanoseconds")
+}
+
+pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format(*duration))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    parse(&text).map_err(serde::de::Error::custom)
+}
+
+/// `Option<Duration>`, with `null` for `None`
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_some(&format(*duration)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(text) => parse(&text).map(Some).map_err(serde::de::Error::custom),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() {
+        assert_eq!(parse("5s").unwrap(), Duration::from_secs(5));
+        assert_eq!(parse("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse("1m 30s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse("1s500ms").unwrap(), Duration::from_millis(1500));
+
+        assert_eq!(format(Duration::from_secs(300)), "5m");
+        assert_eq!(format(Duration::from_millis(1500)), "1500ms");
+        assert_eq!(format(Duration::from_micros(7)), "7us");
+        assert_eq!(format(Duration::ZERO), "0s");
+        for text in ["5m", "1500ms", "7us", "3d", "0s"] {
+            assert_eq!(format(parse(text).unwrap()), text);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("").is_err());
+        assert!(parse("ms").unwrap_err().contains("expected a number"));
+        assert!(parse("30").unwrap_err().contains("missing unit"));
+        assert!(parse("5 fortnights").unwrap_err().contains("unknown unit"));
+    }
+}
@@ -0,0 +1,210 @@
+//! Errors returned by the public `Connection` and `Server` APIs
+//!
+//! Sends fail for reasons a caller handles differently: a rate limited
+//! send can be retried once tokens refill, a full congestion window once
+//! ACKs arrive, an open circuit breaker only after its timeout, and a
+//! closed connection never. Matching on `TransportError` tells them apart
+//! without parsing messages. Failures from lower layers (codec, transport)
+//! that have no variant of their own stay wrapped in `Other`.
+
+use jsp_core::types::control::CloseReason;
+use std::net::AddrParseError;
+
+/// Why a `Connection` or `Server` call failed
+#[derive(Debug, thiserror::Error)]
+pub enum TransportError {
+    /// The handshake has not completed yet, or the server has no session
+    /// with the address
+    #[error("Handshake not completed")]
+    NotConnected,
+
+    /// `close` was called, or the peer closed the connection; `reason` is
+    /// the peer's if it closed first
+    #[error("Connection closed: {reason:?}")]
+    ConnectionClosed { reason: CloseReason },
+
+    /// No ServerHello arrived before the handshake retries ran out
+    #[error("Handshake timed out")]
+    HandshakeTimeout,
+
+    /// The rate limiter has no tokens for the message
+    #[error("Rate limit exceeded")]
+    RateLimited,
+
+    /// The message exceeds the rate limiter's burst, so waiting never helps
+    #[error("Message of {0} bytes exceeds the rate limit burst")]
+    ExceedsRateLimitBurst(usize),
+
+    /// The congestion window has no room until ACKs arrive
+    #[error("Congestion window full")]
+    CongestionLimited,
+
+    /// The peer advertised no room for more data
+    #[error("Peer receive window full")]
+    ReceiveWindowFull,
+
+    /// The circuit breaker rejects sends after repeated failures
+    #[error("Circuit breaker open")]
+    CircuitOpen,
+
+    /// The send queue has no room for the message
+    #[error("Send queue full")]
+    SendQueueFull,
+
+    /// `send_on_stream_wait` gave up before there was capacity
+    #[error("Timed out waiting for send capacity")]
+    SendTimeout,
+
+    /// The stream was never opened, or has been released
+    #[error("Stream {0} not found")]
+    StreamNotFound(u32),
+
+    /// Sending on the stream was closed or finished
+    #[error("Stream {0} is closed")]
+    StreamClosed(u32),
+
+    /// The message takes more fragments than a fragment header counts,
+    /// or a datagram exceeds `max_fragment_size`
+    #[error("Message too large: {0} bytes")]
+    MessageTooLarge(usize),
+
+    /// An item of `send_batch` failed; the ones before it were sent
+    #[error("Batch item {index} (stream {stream_id}) not sent; the {index} before it were")]
+    BatchItemFailed {
+        index: usize,
+        stream_id: u32,
+        #[source]
+        source: Box<TransportError>,
+    },
+
+    /// An address that doesn't parse
+    #[error("Invalid address: {0}")]
+    InvalidAddress(#[from] AddrParseError),
+
+    /// The socket failed
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    /// The key exchange or message encryption failed, such as a hello
+    /// that doesn't verify or a ratchet that can't seal
+    #[error("Crypto failure: {0}")]
+    Crypto(String),
+
+    /// Any other failure, such as a rejected handshake or a malformed packet
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+/// The name `TransportError` had before the `Server` API returned it too
+pub type ConnectionError = TransportError;
+
+impl TransportError {
+    /// Whether the same send may succeed later without any change on the
+    /// caller's side
+    pub fn is_transient(&self) -> bool {
+        match self {
+            TransportError::RateLimited
+            | TransportError::CongestionLimited
+            | TransportError::ReceiveWindowFull
+            | TransportError::CircuitOpen
+            | TransportError::SendQueueFull
+            | TransportError::SendTimeout => true,
+            TransportError::BatchItemFailed { source, .. } => source.is_transient(),
+            _ => false,
+        }
+    }
+
+    /// `Crypto` for a failure from the session's key exchange or ratchet,
+    /// keeping its whole context chain as the message
+    pub(crate) fn crypto(error: anyhow::Error) -> Self {
+        TransportError::Crypto(format!("{:#}", error))
+    }
+}
+
+impl From<anyhow::Error> for TransportError {
+    /// Unwraps errors that are a `TransportError`, with or without context
+    /// added, or a bare I/O error, so they keep their variant
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<TransportError>() {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+        if error.chain().count() > 1 {
+            return TransportError::Other(error);
+        }
+        match error.downcast::<std::io::Error>() {
+            Ok(error) => TransportError::Io(error),
+            Err(error) => TransportError::Other(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_anyhow_keeps_variant() {
+        let error = TransportError::from(anyhow::Error::new(TransportError::RateLimited));
+        assert!(matches!(error, TransportError::RateLimited));
+
+        let io = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+        assert!(matches!(TransportError::from(anyhow::Error::new(io)), TransportError::Io(_)));
+
+        // Added context doesn't hide the variant underneath
+        let wrapped = anyhow::Error::new(TransportError::RateLimited).context("while flushing");
+        assert!(matches!(TransportError::from(wrapped), TransportError::RateLimited));
+
+        // An I/O error with context keeps the context instead
+        let io = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+        let error = TransportError::from(anyhow::Error::new(io).context("while connecting"));
+        assert!(matches!(error, TransportError::Other(_)));
+        assert_eq!(error.to_string(), "while connecting");
+
+        let other = TransportError::from(anyhow::anyhow!("Decompression failed"));
+        assert_eq!(other.to_string(), "Decompression failed");
+    }
+
+    #[test]
+    fn test_variants_survive_anyhow_round_trip() {
+        let closed = TransportError::ConnectionClosed { reason: CloseReason::GoingAway };
+        let error = TransportError::from(anyhow::Error::from(closed));
+        assert!(matches!(error, TransportError::ConnectionClosed { reason: CloseReason::GoingAway }));
+        assert_eq!(error.to_string(), "Connection closed: GoingAway");
+
+        let error = TransportError::from(anyhow::Error::from(TransportError::HandshakeTimeout));
+        assert!(matches!(error, TransportError::HandshakeTimeout));
+
+        // A crypto failure's context chain ends up in one message
+        let crypto = TransportError::crypto(anyhow::anyhow!("bad tag").context("Opening ServerHello"));
+        let error = TransportError::from(anyhow::Error::from(crypto));
+        assert_eq!(error.to_string(), "Crypto failure: Opening ServerHello: bad tag");
+
+        // Variants with a source have a longer chain but still come back whole
+        let batch = TransportError::BatchItemFailed {
+            index: 2,
+            stream_id: 7,
+            source: Box::new(TransportError::SendQueueFull),
+        };
+        let error = TransportError::from(anyhow::Error::from(batch));
+        assert!(matches!(
+            error,
+            TransportError::BatchItemFailed { index: 2, stream_id: 7, ref source }
+                if matches!(**source, TransportError::SendQueueFull)
+        ));
+        assert!(error.is_transient());
+    }
+
+    #[test]
+    fn test_batch_item_message() {
+        let error = TransportError::BatchItemFailed {
+            index: 1,
+            stream_id: 999,
+            source: Box::new(TransportError::StreamNotFound(999)),
+        };
+        assert_eq!(error.to_string(), "Batch item 1 (stream 999) not sent; the 1 before it were");
+        assert!(!error.is_transient());
+        assert!(TransportError::RateLimited.is_transient());
+        assert!(!TransportError::ConnectionClosed { reason: CloseReason::Normal }.is_transient());
+    }
+}
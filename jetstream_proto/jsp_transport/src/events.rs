@@ -0,0 +1,39 @@
+use bytes::Bytes;
+use crate::circuit_breaker::State as CircuitState;
+use crate::network_status::NetworkType;
+use jsp_core::types::control::CloseReason;
+use std::net::SocketAddr;
+
+/// Event surfaced to the application by `Connection::recv_events` and `Connection::next_event`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// In-order data on a stream
+    DataReceived { stream_id: u32, data: Bytes },
+    /// The peer moved to a new address (e.g. after a client-side `migrate`)
+    PeerMigrated { old: SocketAddr, new: SocketAddr },
+    /// A STUN server reported our public address
+    PublicAddressDiscovered(SocketAddr),
+    /// The peer closed the connection
+    Closed { reason: CloseReason, message: Option<String> },
+    /// The peer closed the stream; no more data will arrive on it
+    StreamFinished(u32),
+    /// Our local socket died and the connection moved to a new one
+    Rebound { old_local: SocketAddr, new_local: SocketAddr },
+    /// `set_network_type` reported a different network
+    NetworkChanged { old: NetworkType, new: NetworkType },
+    /// The circuit breaker guarding sends changed state
+    CircuitStateChanged { old: CircuitState, new: CircuitState },
+}
+
+/// Event surfaced to the application by `Server::next_event`, tagged with the client's address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+    /// A client completed the handshake
+    SessionEstablished { addr: SocketAddr, session_id: u64 },
+    /// Data on one of the client's streams
+    DataReceived { addr: SocketAddr, stream_id: u32, data: Bytes },
+    /// A client proved it owns a session from a new address
+    PeerMigrated { old: SocketAddr, new: SocketAddr },
+    /// A client closed its session
+    Closed { addr: SocketAddr, reason: CloseReason, message: Option<String> },
+}
@@ -0,0 +1,278 @@
+//! File transfer over a connection
+//!
+//! The sender writes a manifest (`FileTransferFrame::Metadata`) and then the
+//! file's chunks on one reliable stream. The receiver answers on a stream of
+//! its own: `Resume` says which chunk to start from, `Ack`s confirm chunks as
+//! they are written and `Complete` reports whether the BLAKE3 hash matched.
+//!
+//! Chunks are written to `<name>.<hash>.part` in the target directory, so a
+//! transfer of the same file that was cut off resumes where the file ends.
+
+use crate::connection::Connection;
+use crate::events::ConnectionEvent;
+use anyhow::Result;
+use jsp_core::transfer::{FileReceiver, FileSender, FileTransferFrame, ResumeToken};
+use jsp_core::types::delivery::DeliveryMode;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Room left in each fragment for the CBOR framing around a chunk
+pub const CHUNK_FRAME_OVERHEAD: usize = 128;
+
+/// Chunks the receiver writes between two `Ack` frames
+pub const ACK_INTERVAL: u64 = 32;
+
+/// Options for `Connection::send_file`
+#[derive(Debug, Default)]
+pub struct FileTransferOptions {
+    /// Bytes per chunk; defaults to what fits in one `max_fragment_size` packet
+    pub chunk_size: Option<usize>,
+    /// Continue an earlier transfer of the same file
+    pub resume: Option<ResumeToken>,
+    /// Receives an update whenever the receiver confirms more chunks
+    pub progress: Option<watch::Sender<TransferProgress>>,
+    /// How long to wait for the receiver; defaults to the session timeout
+    pub timeout: Option<Duration>,
+}
+
+/// Progress of a transfer as confirmed by the receiver
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransferProgress {
+    pub transfer_id: u64,
+    /// Chunks the receiver has written
+    pub chunks_confirmed: u64,
+    pub chunk_count: u64,
+    /// First chunk sent by this attempt; non-zero when resuming
+    pub resumed_from: u64,
+    pub bytes_confirmed: u64,
+    pub file_size: u64,
+    /// BLAKE3 hash of the file
+    pub checksum: Vec<u8>,
+    pub chunk_size: u32,
+}
+
+impl TransferProgress {
+    pub fn is_complete(&self) -> bool {
+        self.chunks_confirmed == self.chunk_count
+    }
+
+    /// Token for `FileTransferOptions::resume` after this attempt failed
+    pub fn resume_token(&self) -> ResumeToken {
+        ResumeToken {
+            transfer_id: self.transfer_id,
+            checksum: self.checksum.clone(),
+            next_chunk: self.chunks_confirmed,
+        }
+    }
+
+    fn confirm(&mut self, next_chunk: u64) {
+        self.chunks_confirmed = next_chunk.min(self.chunk_count).max(self.chunks_confirmed);
+        self.bytes_confirmed = (self.chunks_confirmed * self.chunk_size as u64).min(self.file_size);
+    }
+}
+
+impl Connection {
+    /// Send a file on `stream_id`, which should be a reliable stream
+    ///
+    /// Returns once the receiver has verified the file's hash. If the transfer
+    /// fails, pass the last progress' `resume_token()` to a new attempt to
+    /// skip the chunks the receiver already has. Data that arrives on other
+    /// streams meanwhile is returned by the next `recv()`.
+    pub async fn send_file(&mut self, stream_id: u32, path: impl AsRef<Path>, options: FileTransferOptions) -> Result<TransferProgress> {
+        let mut deferred = Vec::new();
+        let result = self.send_file_inner(stream_id, path.as_ref(), &options, &mut deferred).await;
+        self.requeue_events(deferred);
+        result
+    }
+
+    /// Receive a file sent with `send_file` into `dir`
+    ///
+    /// Waits for the sender's manifest, resumes from a partial file left by an
+    /// earlier attempt and returns the path of the verified file.
+    pub async fn recv_file(&mut self, dir: impl AsRef<Path>) -> Result<PathBuf> {
+        let mut deferred = Vec::new();
+        let result = self.recv_file_inner(dir.as_ref(), &mut deferred).await;
+        self.requeue_events(deferred);
+        result
+    }
+
+    async fn send_file_inner(&mut self, stream_id: u32, path: &Path, options: &FileTransferOptions, deferred: &mut Vec<ConnectionEvent>) -> Result<TransferProgress> {
+        let timeout = options.timeout.unwrap_or(self.config().session_timeout);
+        let chunk_size = options.chunk_size
+            .unwrap_or_else(|| self.config().max_fragment_size.saturating_sub(CHUNK_FRAME_OVERHEAD))
+            .clamp(1, u32::MAX as usize) as u32;
+        let transfer_id = match &options.resume {
+            Some(token) => token.transfer_id,
+            None => {
+                let mut id = [0u8; 8];
+                getrandom::getrandom(&mut id).expect("Failed to generate transfer ID");
+                u64::from_be_bytes(id)
+            }
+        };
+
+        let mut sender = FileSender::new(transfer_id, path.to_path_buf(), chunk_size)?;
+        if let Some(token) = &options.resume {
+            if token.checksum != sender.checksum() {
+                return Err(anyhow::anyhow!("{} changed since the resume token was issued", path.display()));
+            }
+        }
+
+        let mut progress = TransferProgress {
+            transfer_id,
+            chunk_count: sender.total_chunks(),
+            file_size: sender.file_size(),
+            checksum: sender.checksum().to_vec(),
+            chunk_size,
+            ..TransferProgress::default()
+        };
+        let publish = |progress: &TransferProgress| {
+            if let Some(tx) = &options.progress {
+                tx.send_replace(progress.clone());
+            }
+        };
+
+        self.send_transfer_frame(stream_id, &sender.get_metadata_frame(), timeout).await?;
+        let next_chunk = loop {
+            match self.next_transfer_frame(Some(transfer_id), deferred, Some(timeout)).await? {
+                Some(FileTransferFrame::Resume { next_chunk, .. }) => break next_chunk.min(progress.chunk_count),
+                Some(_) => {}
+                None => return Err(anyhow::anyhow!("Timed out waiting for the receiver to accept transfer {}", transfer_id)),
+            }
+        };
+        progress.resumed_from = next_chunk;
+        progress.confirm(next_chunk);
+        publish(&progress);
+
+        for chunk_id in next_chunk..progress.chunk_count {
+            let frame = sender.read_chunk(chunk_id)?
+                .ok_or_else(|| anyhow::anyhow!("{} shrank during the transfer", path.display()))?;
+            self.send_transfer_frame(stream_id, &frame, timeout).await?;
+
+            // Pick up ACKs that already arrived without waiting for more
+            while let Some(frame) = self.next_transfer_frame(Some(transfer_id), deferred, Some(Duration::ZERO)).await? {
+                if let FileTransferFrame::Ack { next_chunk, .. } = frame {
+                    progress.confirm(next_chunk);
+                    publish(&progress);
+                }
+            }
+        }
+
+        loop {
+            match self.next_transfer_frame(Some(transfer_id), deferred, Some(timeout)).await? {
+                Some(FileTransferFrame::Ack { next_chunk, .. }) => {
+                    progress.confirm(next_chunk);
+                    publish(&progress);
+                }
+                Some(FileTransferFrame::Complete { verified: true, .. }) => {
+                    progress.confirm(progress.chunk_count);
+                    publish(&progress);
+                    return Ok(progress);
+                }
+                Some(FileTransferFrame::Complete { verified: false, .. }) => {
+                    return Err(anyhow::anyhow!("Receiver reported a checksum mismatch for transfer {}", transfer_id));
+                }
+                Some(_) => {}
+                None => return Err(anyhow::anyhow!("Timed out waiting for transfer {} to complete", transfer_id)),
+            }
+        }
+    }
+
+    async fn recv_file_inner(&mut self, dir: &Path, deferred: &mut Vec<ConnectionEvent>) -> Result<PathBuf> {
+        let timeout = self.config().session_timeout;
+        let (transfer_id, metadata) = loop {
+            // Anything else is left over from an earlier transfer
+            if let Some(FileTransferFrame::Metadata { transfer_id, metadata }) = self.next_transfer_frame(None, deferred, None).await? {
+                break (transfer_id, metadata);
+            }
+        };
+
+        // Only the name is used; the sender doesn't get to pick the directory
+        let name = match Path::new(&metadata.filename).file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => return Err(anyhow::anyhow!("Invalid file name {:?}", metadata.filename)),
+        };
+        let checksum = metadata.checksum.clone()
+            .ok_or_else(|| anyhow::anyhow!("Manifest for {} has no checksum", name))?;
+        let hash_prefix: String = checksum.iter().take(8).map(|b| format!("{:02x}", b)).collect();
+        let partial_path = dir.join(format!("{}.{}.part", name, hash_prefix));
+        let chunk_count = metadata.chunk_count;
+
+        let mut receiver = FileReceiver::new(transfer_id, partial_path.clone());
+        receiver.process_frame(FileTransferFrame::Metadata { transfer_id, metadata })?;
+
+        let reply_stream = self.open_stream(1, DeliveryMode::Reliable)?;
+        let resume = FileTransferFrame::Resume { transfer_id, next_chunk: receiver.next_chunk() };
+        self.send_transfer_frame(reply_stream, &resume, timeout).await?;
+
+        let mut unacked = 0;
+        while !receiver.is_complete(chunk_count) {
+            match self.next_transfer_frame(Some(transfer_id), deferred, Some(timeout)).await? {
+                Some(frame @ FileTransferFrame::Chunk { .. }) => {
+                    receiver.process_frame(frame)?;
+                    unacked += 1;
+                    if unacked >= ACK_INTERVAL {
+                        let ack = FileTransferFrame::Ack { transfer_id, next_chunk: receiver.next_chunk() };
+                        self.send_transfer_frame(reply_stream, &ack, timeout).await?;
+                        unacked = 0;
+                    }
+                }
+                Some(_) => {}
+                None => return Err(anyhow::anyhow!("Timed out waiting for chunks of transfer {}", transfer_id)),
+            }
+        }
+
+        let verified = receiver.verify()?;
+        drop(receiver);
+        self.send_transfer_frame(reply_stream, &FileTransferFrame::Complete { transfer_id, verified }, timeout).await?;
+        if !verified {
+            std::fs::remove_file(&partial_path)?;
+            return Err(anyhow::anyhow!("Checksum mismatch for {}", name));
+        }
+
+        let output_path = dir.join(&name);
+        std::fs::rename(&partial_path, &output_path)?;
+        Ok(output_path)
+    }
+
+    async fn send_transfer_frame(&mut self, stream_id: u32, frame: &FileTransferFrame, timeout: Duration) -> Result<()> {
+        let bytes = serde_cbor::to_vec(frame)?;
+        Ok(self.send_on_stream_wait(stream_id, &bytes, Some(timeout)).await?)
+    }
+
+    /// Next transfer frame, optionally only for `transfer_id`
+    ///
+    /// Other events are collected in `deferred`. Returns `None` once `wait`
+    /// elapses; `None` waits forever.
+    async fn next_transfer_frame(&mut self, transfer_id: Option<u64>, deferred: &mut Vec<ConnectionEvent>, wait: Option<Duration>) -> Result<Option<FileTransferFrame>> {
+        let deadline = wait.map(|wait| tokio::time::Instant::now() + wait);
+        loop {
+            let event = match deadline {
+                Some(deadline) => match tokio::time::timeout_at(deadline, self.next_event()).await {
+                    Ok(event) => event?,
+                    Err(_) => return Ok(None),
+                },
+                None => self.next_event().await?,
+            };
+
+            match event {
+                ConnectionEvent::DataReceived { stream_id, data } => {
+                    match serde_cbor::from_slice::<FileTransferFrame>(&data) {
+                        Ok(frame) if transfer_id.is_none_or(|id| id == frame.transfer_id()) => return Ok(Some(frame)),
+                        Ok(_) => {}
+                        Err(_) => deferred.push(ConnectionEvent::DataReceived { stream_id, data }),
+                    }
+                }
+                ConnectionEvent::Closed { reason, message } => {
+                    deferred.push(ConnectionEvent::Closed { reason, message: message.clone() });
+                    return Err(anyhow::anyhow!(
+                        "Peer closed the connection during the transfer: {:?}{}",
+                        reason,
+                        message.map(|m| format!(" ({})", m)).unwrap_or_default()
+                    ));
+                }
+                other => deferred.push(other),
+            }
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+use bytes::{Bytes, BytesMut};
+use jsp_core::types::delivery::DeliveryMode;
+use jsp_core::types::fragment::FragmentHeader;
+
+/// Most messages reassembled at once per connection
+pub const MAX_PENDING_MESSAGES: usize = 1024;
+
+/// Default cap on bytes held by incomplete messages
+pub const DEFAULT_MAX_BUFFERED: usize = 4 * 1024 * 1024;
+
+/// Most fragments held at once, so a flood of tiny fragments stays bounded
+/// even while under the byte cap
+pub const MAX_HELD_FRAGMENTS: usize = 65_536;
+
+/// Message being reassembled from its fragments
+#[derive(Debug)]
+struct PartialMessage {
+    /// Fragments received so far, by index; slots are only allocated for
+    /// fragments that arrive, whatever count the sender claims
+    fragments: BTreeMap<u16, Bytes>,
+    count: u16,
+    total_len: usize,
+    started: Instant,
+    delivery_mode: DeliveryMode,
+}
+
+/// Reassembles fragmented messages on the receive path
+#[derive(Debug)]
+pub struct Reassembler {
+    /// (stream_id, message_id) -> partial message
+    partial: HashMap<(u32, u32), PartialMessage>,
+    /// How long an incomplete BestEffort message is kept
+    timeout: Duration,
+    /// Cap on payload bytes held across all partial messages
+    max_buffered: usize,
+    /// Payload bytes held in partial messages
+    buffered: usize,
+    /// Fragments held in partial messages
+    held: usize,
+}
+
+impl Reassembler {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            partial: HashMap::new(),
+            timeout,
+            max_buffered: DEFAULT_MAX_BUFFERED,
+            buffered: 0,
+            held: 0,
+        }
+    }
+
+    /// Cap the bytes held by incomplete messages
+    ///
+    /// Fragments that would take the total past the cap are dropped, as are
+    /// fragments beyond `MAX_HELD_FRAGMENTS` and first fragments once
+    /// `MAX_PENDING_MESSAGES` messages are in progress.
+    pub fn with_max_buffered(mut self, max_buffered: usize) -> Self {
+        self.max_buffered = max_buffered;
+        self
+    }
+
+    /// Add a fragment, returning the full message once every fragment has arrived
+    pub fn insert(
+        &mut self,
+        stream_id: u32,
+        delivery_mode: DeliveryMode,
+        fragment: FragmentHeader,
+        data: Bytes,
+    ) -> Option<Bytes> {
+        if fragment.index >= fragment.count {
+            tracing::warn!(
+                stream_id,
+                message_id = fragment.message_id,
+                index = fragment.index,
+                count = fragment.count,
+                "Fragment index out of range, dropping fragment"
+            );
+            return None;
+        }
+
+        let key = (stream_id, fragment.message_id);
+        if self.buffered + data.len() > self.max_buffered
+            || self.held >= MAX_HELD_FRAGMENTS
+            || (!self.partial.contains_key(&key) && self.partial.len() >= MAX_PENDING_MESSAGES)
+        {
+            tracing::debug!(
+                stream_id,
+                message_id = fragment.message_id,
+                pending = self.partial.len(),
+                buffered = self.buffered,
+                "Reassembly buffer full, dropping fragment"
+            );
+            return None;
+        }
+
+        let entry = self.partial.entry(key).or_insert_with(|| PartialMessage {
+            fragments: BTreeMap::new(),
+            count: fragment.count,
+            total_len: 0,
+            started: Instant::now(),
+            delivery_mode,
+        });
+
+        // Fragment count must agree with the first fragment seen
+        if entry.count != fragment.count {
+            tracing::warn!(
+                stream_id,
+                message_id = fragment.message_id,
+                "Fragment count mismatch, dropping fragment"
+            );
+            return None;
+        }
+
+        if entry.fragments.contains_key(&fragment.index) {
+            // Duplicate fragment
+            return None;
+        }
+        let len = data.len();
+        entry.total_len += len;
+        entry.fragments.insert(fragment.index, data);
+        self.buffered += len;
+        self.held += 1;
+
+        if entry.fragments.len() < entry.count as usize {
+            return None;
+        }
+
+        let message = self.remove(&key)?;
+        let mut buf = BytesMut::with_capacity(message.total_len);
+        for part in message.fragments.into_values() {
+            buf.extend_from_slice(&part);
+        }
+        Some(buf.freeze())
+    }
+
+    fn remove(&mut self, key: &(u32, u32)) -> Option<PartialMessage> {
+        let message = self.partial.remove(key)?;
+        self.buffered -= message.total_len;
+        self.held -= message.fragments.len();
+        Some(message)
+    }
+
+    /// Discard incomplete BestEffort messages older than the timeout
+    ///
+    /// Returns the number of discarded messages.
+    pub fn expire(&mut self) -> usize {
+        let timeout = self.timeout;
+        let expired: Vec<_> = self.partial.iter()
+            .filter(|(_, message)| {
+                message.delivery_mode == DeliveryMode::BestEffort && message.started.elapsed() > timeout
+            })
+            .map(|(key, _)| *key)
+            .collect();
+        for key in &expired {
+            self.remove(key);
+        }
+        expired.len()
+    }
+
+    /// Number of messages currently being reassembled
+    pub fn pending(&self) -> usize {
+        self.partial.len()
+    }
+
+    /// Bytes held in fragments of incomplete messages
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fragments(data: &[u8], size: usize, message_id: u32) -> Vec<(FragmentHeader, Bytes)> {
+        let count = data.len().div_ceil(size) as u16;
+        data.chunks(size)
+            .enumerate()
+            .map(|(i, chunk)| (FragmentHeader::new(message_id, i as u16, count), Bytes::copy_from_slice(chunk)))
+            .collect()
+    }
+
+    #[test]
+    fn test_reassemble_in_order() {
+        let data: Vec<u8> = (0..1_048_576u32).map(|i| (i % 251) as u8).collect();
+        let mut reassembler = Reassembler::new(Duration::from_secs(5));
+
+        let mut result = None;
+        for (header, chunk) in fragments(&data, 1200, 1) {
+            result = reassembler.insert(1, DeliveryMode::Reliable, header, chunk);
+        }
+
+        assert_eq!(result.unwrap(), Bytes::from(data));
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn test_reassemble_out_of_order_with_duplicates() {
+        let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        let mut reassembler = Reassembler::new(Duration::from_secs(5));
+
+        let mut parts = fragments(&data, 1000, 7);
+        parts.reverse();
+        let duplicate = parts[0].clone();
+        parts.insert(1, duplicate);
+
+        let mut results = Vec::new();
+        for (header, chunk) in parts {
+            if let Some(message) = reassembler.insert(3, DeliveryMode::Reliable, header, chunk) {
+                results.push(message);
+            }
+        }
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0], Bytes::from(data));
+    }
+
+    #[test]
+    fn test_best_effort_timeout() {
+        let mut reassembler = Reassembler::new(Duration::from_millis(10));
+        reassembler.insert(1, DeliveryMode::BestEffort, FragmentHeader::new(1, 0, 2), Bytes::from_static(b"a"));
+        reassembler.insert(2, DeliveryMode::Reliable, FragmentHeader::new(1, 0, 2), Bytes::from_static(b"a"));
+
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(reassembler.expire(), 1);
+        assert_eq!(reassembler.pending(), 1);
+    }
+
+    #[test]
+    fn test_first_fragment_flood_is_bounded() {
+        let mut reassembler = Reassembler::new(Duration::from_secs(5)).with_max_buffered(64 * 1024);
+        let chunk = Bytes::from(vec![0u8; 1000]);
+
+        // Reliable first fragments of messages that never complete
+        for message_id in 0..10_000 {
+            let header = FragmentHeader::new(message_id, 0, u16::MAX);
+            assert!(reassembler.insert(1, DeliveryMode::Reliable, header, chunk.clone()).is_none());
+        }
+        assert!(reassembler.buffered_bytes() <= 64 * 1024);
+        assert_eq!(reassembler.pending(), 64 * 1024 / 1000);
+
+        // Tiny fragments hit the message cap instead of the byte cap
+        let mut reassembler = Reassembler::new(Duration::from_secs(5));
+        for message_id in 0..10_000 {
+            let header = FragmentHeader::new(message_id, 0, u16::MAX);
+            reassembler.insert(1, DeliveryMode::Reliable, header, Bytes::from_static(b"x"));
+        }
+        assert_eq!(reassembler.pending(), MAX_PENDING_MESSAGES);
+    }
+
+    #[test]
+    fn test_completed_and_expired_messages_release_budget() {
+        let mut reassembler = Reassembler::new(Duration::from_millis(10)).with_max_buffered(2000);
+        let data: Vec<u8> = (0..2000u32).map(|i| i as u8).collect();
+
+        for round in 0..3 {
+            let mut result = None;
+            for (header, chunk) in fragments(&data, 1000, round) {
+                result = reassembler.insert(1, DeliveryMode::Reliable, header, chunk);
+            }
+            assert_eq!(result.unwrap(), Bytes::from(data.clone()));
+            assert_eq!(reassembler.buffered_bytes(), 0);
+        }
+
+        reassembler.insert(2, DeliveryMode::BestEffort, FragmentHeader::new(1, 0, 3), Bytes::from(vec![0u8; 1500]));
+        assert!(reassembler
+            .insert(2, DeliveryMode::BestEffort, FragmentHeader::new(1, 1, 3), Bytes::from(vec![0u8; 1000]))
+            .is_none());
+        assert_eq!(reassembler.buffered_bytes(), 1500);
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(reassembler.expire(), 1);
+        assert_eq!(reassembler.buffered_bytes(), 0);
+    }
+}
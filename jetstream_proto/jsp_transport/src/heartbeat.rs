@@ -3,6 +3,8 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 use tokio::time::interval;
 use anyhow::Result;
+use crate::clock::{Clock, SystemClock};
+use crate::network_status::NetworkType;
 
 /// Application state for battery optimization
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -16,6 +18,9 @@ pub enum AppState {
 pub struct HeartbeatConfig {
     pub foreground_interval: Duration,
     pub background_interval: Duration,
+    /// Interval in the foreground on a cellular network, where each wakeup
+    /// of the radio costs battery
+    pub cellular_interval: Duration,
     pub timeout_count: u32,
 }
 
@@ -24,6 +29,7 @@ impl Default for HeartbeatConfig {
         Self {
             foreground_interval: Duration::from_secs(5),
             background_interval: Duration::from_secs(30),
+            cellular_interval: Duration::from_secs(15),
             timeout_count: 3,
         }
     }
@@ -42,17 +48,28 @@ pub struct HeartbeatManager {
     config: HeartbeatConfig,
     /// Current application state
     app_state: Arc<RwLock<AppState>>,
+    /// Network the connection currently runs over
+    network_type: Arc<RwLock<NetworkType>>,
+    /// Time source for intervals and timeouts
+    clock: Arc<dyn Clock>,
 }
 
 impl HeartbeatManager {
     pub fn new(config: HeartbeatConfig) -> Self {
-        let now = Instant::now();
+        Self::with_clock(config, Arc::new(SystemClock))
+    }
+
+    /// Create a manager whose intervals and timeouts read the given clock
+    pub fn with_clock(config: HeartbeatConfig, clock: Arc<dyn Clock>) -> Self {
+        let now = clock.now();
         Self {
             sequence: Arc::new(RwLock::new(0)),
             last_sent: Arc::new(RwLock::new(now)),
             last_received: Arc::new(RwLock::new(now)),
             config,
             app_state: Arc::new(RwLock::new(AppState::Foreground)),
+            network_type: Arc::new(RwLock::new(NetworkType::Unknown)),
+            clock,
         }
     }
 
@@ -65,12 +82,23 @@ impl HeartbeatManager {
         }
     }
 
-    /// Get current heartbeat interval based on app state
+    /// Set the network heartbeats go over
+    pub async fn set_network_type(&self, network_type: NetworkType) {
+        let mut current = self.network_type.write().await;
+        if *current != network_type {
+            *current = network_type;
+            tracing::info!(network_type = ?network_type, "Network type changed, adjusting heartbeat interval");
+        }
+    }
+
+    /// Get current heartbeat interval based on app state and network type
     pub async fn current_interval(&self) -> Duration {
-        let state = self.app_state.read().await;
-        match *state {
-            AppState::Foreground => self.config.foreground_interval,
-            AppState::Background => self.config.background_interval,
+        let state = *self.app_state.read().await;
+        let network_type = *self.network_type.read().await;
+        match (state, network_type) {
+            (AppState::Background, _) => self.config.background_interval,
+            (AppState::Foreground, NetworkType::Cellular) => self.config.cellular_interval,
+            (AppState::Foreground, _) => self.config.foreground_interval,
         }
     }
 
@@ -84,19 +112,19 @@ impl HeartbeatManager {
     /// Update last sent timestamp
     pub async fn mark_sent(&self) {
         let mut last = self.last_sent.write().await;
-        *last = Instant::now();
+        *last = self.clock.now();
     }
 
     /// Update last received timestamp
     pub async fn mark_received(&self) {
         let mut last = self.last_received.write().await;
-        *last = Instant::now();
+        *last = self.clock.now();
     }
 
     /// Check if heartbeat should be sent
     pub async fn should_send(&self) -> bool {
         let last = self.last_sent.read().await;
-        last.elapsed() >= self.current_interval().await
+        self.clock.now().duration_since(*last) >= self.current_interval().await
     }
 
     /// Check if connection has timed out
@@ -104,13 +132,13 @@ impl HeartbeatManager {
         let last = self.last_received.read().await;
         let interval = self.current_interval().await;
         let timeout_duration = interval * self.config.timeout_count;
-        last.elapsed() >= timeout_duration
+        self.clock.now().duration_since(*last) >= timeout_duration
     }
 
     /// Get time since last heartbeat received
     pub async fn time_since_last_received(&self) -> Duration {
         let last = self.last_received.read().await;
-        last.elapsed()
+        self.clock.now().duration_since(*last)
     }
 
     /// Start heartbeat monitoring task
@@ -152,13 +180,14 @@ impl HeartbeatManager {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tokio::time::sleep;
+    use crate::clock::MockClock;
 
     #[tokio::test]
     async fn test_heartbeat_sequence() {
         let config = HeartbeatConfig {
             foreground_interval: Duration::from_secs(5),
             background_interval: Duration::from_secs(30),
+            cellular_interval: Duration::from_secs(15),
             timeout_count: 3,
         };
         let manager = HeartbeatManager::new(config);
@@ -175,15 +204,17 @@ mod tests {
         let config = HeartbeatConfig {
             foreground_interval: Duration::from_secs(1),
             background_interval: Duration::from_secs(5),
+            cellular_interval: Duration::from_secs(3),
             timeout_count: 3,
         };
-        let manager = HeartbeatManager::new(config);
+        let clock = MockClock::new();
+        let manager = HeartbeatManager::with_clock(config, Arc::new(clock.clone()));
         
         // Initially should not send
         assert!(!manager.should_send().await);
         
         // Wait for interval
-        sleep(Duration::from_millis(1100)).await;
+        clock.advance(Duration::from_millis(1100));
         
         // Now should send
         assert!(manager.should_send().await);
@@ -194,15 +225,17 @@ mod tests {
         let config = HeartbeatConfig {
             foreground_interval: Duration::from_secs(1),
             background_interval: Duration::from_secs(5),
+            cellular_interval: Duration::from_secs(3),
             timeout_count: 2,
         };
-        let manager = HeartbeatManager::new(config);
+        let clock = MockClock::new();
+        let manager = HeartbeatManager::with_clock(config, Arc::new(clock.clone()));
         
         // Initially not timed out
         assert!(!manager.is_timed_out().await);
         
         // Wait for timeout (2 intervals)
-        sleep(Duration::from_millis(2100)).await;
+        clock.advance(Duration::from_millis(2100));
         
         // Should be timed out
         assert!(manager.is_timed_out().await);
@@ -213,17 +246,22 @@ mod tests {
         let config = HeartbeatConfig {
             foreground_interval: Duration::from_secs(1),
             background_interval: Duration::from_secs(5),
+            cellular_interval: Duration::from_secs(3),
             timeout_count: 2,
         };
-        let manager = HeartbeatManager::new(config);
+        let clock = MockClock::new();
+        let manager = HeartbeatManager::with_clock(config, Arc::new(clock.clone()));
         
-        sleep(Duration::from_millis(1500)).await;
+        clock.advance(Duration::from_millis(1500));
         
         // Mark as received
         manager.mark_received().await;
         
         // Should not be timed out
         assert!(!manager.is_timed_out().await);
+        clock.advance(Duration::from_millis(1500));
+        assert!(!manager.is_timed_out().await);
+        assert_eq!(manager.time_since_last_received().await, Duration::from_millis(1500));
     }
 
     #[tokio::test]
@@ -231,6 +269,7 @@ mod tests {
         let config = HeartbeatConfig {
             foreground_interval: Duration::from_secs(1),
             background_interval: Duration::from_secs(5),
+            cellular_interval: Duration::from_secs(3),
             timeout_count: 3,
         };
         let manager = HeartbeatManager::new(config);
@@ -242,4 +281,35 @@ mod tests {
         manager.set_app_state(AppState::Background).await;
         assert_eq!(manager.current_interval().await, Duration::from_secs(5));
     }
+
+    #[tokio::test]
+    async fn test_network_type_change() {
+        let config = HeartbeatConfig {
+            foreground_interval: Duration::from_secs(1),
+            background_interval: Duration::from_secs(5),
+            cellular_interval: Duration::from_secs(3),
+            timeout_count: 2,
+        };
+        let clock = MockClock::new();
+        let manager = HeartbeatManager::with_clock(config, Arc::new(clock.clone()));
+
+        manager.set_network_type(NetworkType::Wifi).await;
+        assert_eq!(manager.current_interval().await, Duration::from_secs(1));
+
+        // Longer on cellular, and so is the timeout
+        manager.set_network_type(NetworkType::Cellular).await;
+        assert_eq!(manager.current_interval().await, Duration::from_secs(3));
+        clock.advance(Duration::from_millis(2100));
+        assert!(!manager.should_send().await);
+        assert!(!manager.is_timed_out().await);
+        clock.advance(Duration::from_secs(1));
+        assert!(manager.should_send().await);
+
+        // The background interval wins over the network's
+        manager.set_app_state(AppState::Background).await;
+        assert_eq!(manager.current_interval().await, Duration::from_secs(5));
+        manager.set_app_state(AppState::Foreground).await;
+        manager.set_network_type(NetworkType::Ethernet).await;
+        assert_eq!(manager.current_interval().await, Duration::from_secs(1));
+    }
 }
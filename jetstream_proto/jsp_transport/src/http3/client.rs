@@ -0,0 +1,49 @@
+//! HTTP/3 Client
+
+use super::message::{self, DEFAULT_MAX_BODY_SIZE};
+use super::{Request, Response};
+use crate::quic_transport::QuicTransport;
+use std::net::SocketAddr;
+
+/// HTTP/3 client sending each request on its own bidirectional stream
+pub struct Http3Client {
+    transport: QuicTransport,
+    max_body_size: usize,
+}
+
+impl Http3Client {
+    /// Connect to an HTTP/3 server
+    pub async fn connect(addr: SocketAddr) -> anyhow::Result<Self> {
+        Ok(Self::new(QuicTransport::connect(addr).await?))
+    }
+
+    /// Use an established QUIC connection
+    pub fn new(transport: QuicTransport) -> Self {
+        Self {
+            transport,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    /// Reject response bodies larger than `size` bytes
+    pub fn with_max_body_size(mut self, size: usize) -> Self {
+        self.max_body_size = size;
+        self
+    }
+
+    /// Send `request` and wait for the response
+    pub async fn send(&self, request: Request) -> anyhow::Result<Response> {
+        let (mut send, mut recv) = self.transport.open_bi().await?;
+        let authority = self.transport.peer_addr().to_string();
+        message::write_message(&mut send, &request.to_fields(&authority), &request.body).await?;
+        send.finish()?;
+
+        let (fields, body) = message::read_message(&mut recv, self.max_body_size).await?
+            .ok_or_else(|| anyhow::anyhow!("Stream ended without a response"))?;
+        Ok(Response::from_fields(fields, body)?)
+    }
+
+    pub fn transport(&self) -> &QuicTransport {
+        &self.transport
+    }
+}
@@ -1,6 +1,7 @@
 //! HTTP/3 Frames
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 /// HTTP/3 frame types
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -97,6 +98,51 @@ impl Frame {
         Ok(Self { frame_type, payload })
     }
 
+    /// Read the next frame from a stream, `None` at a clean end of stream
+    ///
+    /// Frames of unknown types, including reserved ones, are skipped as
+    /// RFC 9114 requires. Payloads above `max_payload` are rejected.
+    pub async fn read_from<R: AsyncRead + Unpin>(reader: &mut R, max_payload: usize) -> super::Result<Option<Self>> {
+        loop {
+            let frame_type_val = match Self::read_varint(reader).await? {
+                Some(value) => value,
+                None => return Ok(None),
+            };
+            let length = Self::read_varint(reader).await?
+                .ok_or_else(|| super::Http3Error::InvalidFrame("Stream ended before frame length".to_string()))?;
+
+            if length > max_payload as u64 {
+                return Err(super::Http3Error::InvalidFrame(format!("Frame payload of {} bytes exceeds limit", length)));
+            }
+            let mut payload = vec![0u8; length as usize];
+            reader.read_exact(&mut payload).await?;
+
+            if let Some(frame_type) = FrameType::from_u64(frame_type_val) {
+                return Ok(Some(Self::new(frame_type, Bytes::from(payload))));
+            }
+            tracing::trace!(frame_type = frame_type_val, "Skipping unknown HTTP/3 frame");
+        }
+    }
+
+    /// Read a variable-length integer, `None` if the stream ends before its first byte
+    async fn read_varint<R: AsyncRead + Unpin>(reader: &mut R) -> super::Result<Option<u64>> {
+        let mut first = [0u8; 1];
+        if reader.read(&mut first).await? == 0 {
+            return Ok(None);
+        }
+
+        let extra = (1usize << (first[0] >> 6)) - 1;
+        let mut value = (first[0] & 0x3F) as u64;
+        if extra > 0 {
+            let mut rest = [0u8; 7];
+            reader.read_exact(&mut rest[..extra]).await?;
+            for byte in &rest[..extra] {
+                value = (value << 8) | *byte as u64;
+            }
+        }
+        Ok(Some(value))
+    }
+
     /// Encode variable-length integer
     fn encode_varint(buf: &mut BytesMut, value: u64) {
         if value < 64 {
@@ -166,6 +212,26 @@ mod tests {
         assert_eq!(decoded.payload, Bytes::from("Hello HTTP/3"));
     }
 
+    #[tokio::test]
+    async fn test_read_frames_from_stream() {
+        let mut wire = BytesMut::new();
+        wire.put(Frame::headers(Bytes::from_static(b"\x00\x00\xd1")).encode());
+        // Reserved frame type 0x21 with a two-byte payload
+        wire.put_slice(&[0x21, 0x02, 0xAB, 0xCD]);
+        wire.put(Frame::data(Bytes::from(vec![7u8; 100])).encode());
+        let mut reader = &wire[..];
+
+        let headers = Frame::read_from(&mut reader, 1024).await.unwrap().unwrap();
+        assert_eq!(headers.frame_type, FrameType::Headers);
+        let data = Frame::read_from(&mut reader, 1024).await.unwrap().unwrap();
+        assert_eq!(data.frame_type, FrameType::Data);
+        assert_eq!(data.payload.len(), 100);
+        assert!(Frame::read_from(&mut reader, 1024).await.unwrap().is_none());
+
+        let mut reader = &wire[..];
+        assert!(Frame::read_from(&mut reader, 2).await.is_err());
+    }
+
     #[test]
     fn test_varint_encoding() {
         let mut buf = BytesMut::new();
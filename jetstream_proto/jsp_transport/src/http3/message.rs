@@ -0,0 +1,157 @@
+//! HTTP/3 message framing
+//!
+//! A message is one HEADERS frame carrying a QPACK field section, followed
+//! by DATA frames with the body, on a single request stream.
+
+use super::qpack::{self, Field};
+use super::{Frame, FrameType, Http3Error, Request, Response};
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+/// Default cap on a message body read from a stream
+pub const DEFAULT_MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+/// Cap on a single encoded field section
+const MAX_FIELD_SECTION_SIZE: usize = 64 * 1024;
+
+/// Read the header fields and body of one message, `None` if the stream ends before it starts
+pub async fn read_message<R: AsyncRead + Unpin>(reader: &mut R, max_body_size: usize) -> super::Result<Option<(Vec<Field>, Vec<u8>)>> {
+    let headers = match Frame::read_from(reader, MAX_FIELD_SECTION_SIZE).await? {
+        Some(frame) if frame.frame_type == FrameType::Headers => frame,
+        Some(frame) => {
+            return Err(Http3Error::ProtocolError(format!("Expected HEADERS frame, got {:?}", frame.frame_type)));
+        }
+        None => return Ok(None),
+    };
+    let fields = qpack::decode(&headers.payload)?;
+
+    let mut body = Vec::new();
+    while let Some(frame) = Frame::read_from(reader, max_body_size.max(MAX_FIELD_SECTION_SIZE)).await? {
+        match frame.frame_type {
+            FrameType::Data => {
+                if body.len() + frame.payload.len() > max_body_size {
+                    return Err(Http3Error::ProtocolError(format!("Message body exceeds {} bytes", max_body_size)));
+                }
+                body.extend_from_slice(&frame.payload);
+            }
+            // Trailers are accepted and dropped
+            FrameType::Headers => {}
+            other => {
+                return Err(Http3Error::ProtocolError(format!("Unexpected {:?} frame on request stream", other)));
+            }
+        }
+    }
+
+    Ok(Some((fields, body)))
+}
+
+/// Write the header fields and body of one message; the caller finishes the stream
+pub async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, fields: &[Field], body: &[u8]) -> super::Result<()> {
+    writer.write_all(&Frame::headers(qpack::encode(fields)).encode()).await?;
+    if !body.is_empty() {
+        writer.write_all(&Frame::data(Bytes::copy_from_slice(body)).encode()).await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+impl Request {
+    /// Header fields of the request, pseudo-headers first
+    pub fn to_fields(&self, authority: &str) -> Vec<Field> {
+        let mut fields = vec![
+            (":method".to_string(), self.method.clone()),
+            (":scheme".to_string(), "https".to_string()),
+            (":authority".to_string(), authority.to_string()),
+            (":path".to_string(), self.path.clone()),
+        ];
+        fields.extend(self.headers.iter().map(|(k, v)| (k.clone(), v.clone())));
+        fields
+    }
+
+    /// Build a request from decoded header fields and a body
+    pub fn from_fields(fields: Vec<Field>, body: Vec<u8>) -> super::Result<Self> {
+        let mut method = None;
+        let mut path = None;
+        let mut request = Request::default();
+
+        for (name, value) in fields {
+            match name.as_str() {
+                ":method" => method = Some(value),
+                ":path" => path = Some(value),
+                ":scheme" | ":authority" => {}
+                pseudo if pseudo.starts_with(':') => {
+                    return Err(Http3Error::ProtocolError(format!("Unknown request pseudo-header {}", pseudo)));
+                }
+                _ => request = request.header(name, value),
+            }
+        }
+
+        request.method = method.ok_or_else(|| Http3Error::ProtocolError("Request without :method".to_string()))?;
+        request.path = path.ok_or_else(|| Http3Error::ProtocolError("Request without :path".to_string()))?;
+        Ok(request.body(body))
+    }
+}
+
+impl Response {
+    /// Header fields of the response, `:status` first
+    pub fn to_fields(&self) -> Vec<Field> {
+        let mut fields = vec![(":status".to_string(), self.status.to_string())];
+        fields.extend(self.headers.iter().map(|(k, v)| (k.clone(), v.clone())));
+        fields
+    }
+
+    /// Build a response from decoded header fields and a body
+    pub fn from_fields(fields: Vec<Field>, body: Vec<u8>) -> super::Result<Self> {
+        let mut status = None;
+        let mut response = Response::new(0);
+
+        for (name, value) in fields {
+            match name.as_str() {
+                ":status" => {
+                    status = Some(value.parse::<u16>()
+                        .map_err(|_| Http3Error::ProtocolError(format!("Invalid :status {}", value)))?);
+                }
+                pseudo if pseudo.starts_with(':') => {
+                    return Err(Http3Error::ProtocolError(format!("Unknown response pseudo-header {}", pseudo)));
+                }
+                _ => response = response.header(name, value),
+            }
+        }
+
+        response.status = status.ok_or_else(|| Http3Error::ProtocolError("Response without :status".to_string()))?;
+        Ok(response.body(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_request_roundtrip() {
+        let request = Request::new("POST", "/api/echo")
+            .header("Content-Type", "text/plain")
+            .body(b"ping".to_vec());
+
+        let mut wire = Vec::new();
+        write_message(&mut wire, &request.to_fields("localhost"), &request.body).await.unwrap();
+
+        let (fields, body) = read_message(&mut &wire[..], 1024).await.unwrap().unwrap();
+        let decoded = Request::from_fields(fields, body).unwrap();
+        assert_eq!(decoded.method, "POST");
+        assert_eq!(decoded.path, "/api/echo");
+        assert_eq!(decoded.get_header("content-type").unwrap(), "text/plain");
+        assert_eq!(decoded.body, b"ping");
+
+        assert!(read_message(&mut &wire[..], 2).await.is_err());
+        assert!(read_message(&mut &[][..], 1024).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_response_requires_status() {
+        let fields = vec![("content-type".to_string(), "text/plain".to_string())];
+        assert!(Response::from_fields(fields, Vec::new()).is_err());
+
+        let response = Response::from_fields(Response::not_found().to_fields(), Vec::new()).unwrap();
+        assert_eq!(response.status, 404);
+    }
+}
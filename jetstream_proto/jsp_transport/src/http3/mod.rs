@@ -1,12 +1,17 @@
 //! HTTP/3 Compatibility Layer
 //! 
-//! Provides HTTP/3 support over QUIC transport.
+//! Provides HTTP/3 support over QUIC transport: request streams carrying
+//! HEADERS and DATA frames, with QPACK field sections limited to the static table.
 
+pub mod client;
 pub mod frame;
+pub mod message;
+pub mod qpack;
 pub mod request;
 pub mod response;
 pub mod server;
 
+pub use client::Http3Client;
 pub use frame::{Frame, FrameType};
 pub use request::Request;
 pub use response::Response;
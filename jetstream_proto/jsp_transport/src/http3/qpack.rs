@@ -0,0 +1,297 @@
+//! QPACK field sections (RFC 9204) using the static table only
+//!
+//! The encoder never inserts into the dynamic table or Huffman-codes
+//! strings, so every field section is self-contained. The decoder rejects
+//! sections that need the dynamic table or Huffman decoding.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use super::Http3Error;
+
+/// Header field as it appears on the wire: a lowercase name and a value
+pub type Field = (String, String);
+
+/// Static table of RFC 9204, Appendix A
+const STATIC_TABLE: [(&str, &str); 99] = [
+    (":authority", ""),
+    (":path", "/"),
+    ("age", "0"),
+    ("content-disposition", ""),
+    ("content-length", "0"),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("referer", ""),
+    ("set-cookie", ""),
+    (":method", "CONNECT"),
+    (":method", "DELETE"),
+    (":method", "GET"),
+    (":method", "HEAD"),
+    (":method", "OPTIONS"),
+    (":method", "POST"),
+    (":method", "PUT"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "103"),
+    (":status", "200"),
+    (":status", "304"),
+    (":status", "404"),
+    (":status", "503"),
+    ("accept", "*/*"),
+    ("accept", "application/dns-message"),
+    ("accept-encoding", "gzip, deflate, br"),
+    ("accept-ranges", "bytes"),
+    ("access-control-allow-headers", "cache-control"),
+    ("access-control-allow-headers", "content-type"),
+    ("access-control-allow-origin", "*"),
+    ("cache-control", "max-age=0"),
+    ("cache-control", "max-age=2592000"),
+    ("cache-control", "max-age=604800"),
+    ("cache-control", "no-cache"),
+    ("cache-control", "no-store"),
+    ("cache-control", "public, max-age=31536000"),
+    ("content-encoding", "br"),
+    ("content-encoding", "gzip"),
+    ("content-type", "application/dns-message"),
+    ("content-type", "application/javascript"),
+    ("content-type", "application/json"),
+    ("content-type", "application/x-www-form-urlencoded"),
+    ("content-type", "image/gif"),
+    ("content-type", "image/jpeg"),
+    ("content-type", "image/png"),
+    ("content-type", "text/css"),
+    ("content-type", "text/html; charset=utf-8"),
+    ("content-type", "text/plain"),
+    ("content-type", "text/plain;charset=utf-8"),
+    ("range", "bytes=0-"),
+    ("strict-transport-security", "max-age=31536000"),
+    ("strict-transport-security", "max-age=31536000; includesubdomains"),
+    ("strict-transport-security", "max-age=31536000; includesubdomains; preload"),
+    ("vary", "accept-encoding"),
+    ("vary", "origin"),
+    ("x-content-type-options", "nosniff"),
+    ("x-xss-protection", "1; mode=block"),
+    (":status", "100"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "302"),
+    (":status", "400"),
+    (":status", "403"),
+    (":status", "421"),
+    (":status", "425"),
+    (":status", "500"),
+    ("accept-language", ""),
+    ("access-control-allow-credentials", "FALSE"),
+    ("access-control-allow-credentials", "TRUE"),
+    ("access-control-allow-headers", "*"),
+    ("access-control-allow-methods", "get"),
+    ("access-control-allow-methods", "get, post, options"),
+    ("access-control-allow-methods", "options"),
+    ("access-control-expose-headers", "content-length"),
+    ("access-control-request-headers", "content-type"),
+    ("access-control-request-method", "get"),
+    ("access-control-request-method", "post"),
+    ("alt-svc", "clear"),
+    ("authorization", ""),
+    ("content-security-policy", "script-src 'none'; object-src 'none'; base-uri 'none'"),
+    ("early-data", "1"),
+    ("expect-ct", ""),
+    ("forwarded", ""),
+    ("if-range", ""),
+    ("origin", ""),
+    ("purpose", "prefetch"),
+    ("server", ""),
+    ("timing-allow-origin", "*"),
+    ("upgrade-insecure-requests", "1"),
+    ("user-agent", ""),
+    ("x-forwarded-for", ""),
+    ("x-frame-options", "deny"),
+    ("x-frame-options", "sameorigin"),
+];
+
+/// Encode `fields` as a field section; names are lowercased
+pub fn encode(fields: &[Field]) -> Bytes {
+    let mut buf = BytesMut::new();
+    // Required Insert Count and Delta Base, both zero without a dynamic table
+    buf.put_u8(0);
+    buf.put_u8(0);
+
+    for (name, value) in fields {
+        let name = name.to_ascii_lowercase();
+        if let Some(index) = STATIC_TABLE.iter().position(|(n, v)| *n == name && v == value) {
+            // Indexed field line, static table
+            encode_int(&mut buf, 0b1100_0000, 6, index as u64);
+        } else if let Some(index) = STATIC_TABLE.iter().position(|(n, _)| *n == name) {
+            // Literal field line with a static name reference
+            encode_int(&mut buf, 0b0101_0000, 4, index as u64);
+            encode_string(&mut buf, 0, 7, value.as_bytes());
+        } else {
+            // Literal field line with a literal name
+            encode_string(&mut buf, 0b0010_0000, 3, name.as_bytes());
+            encode_string(&mut buf, 0, 7, value.as_bytes());
+        }
+    }
+
+    buf.freeze()
+}
+
+/// Decode a field section produced by `encode` or any encoder that sticks to the static table
+pub fn decode(data: &[u8]) -> super::Result<Vec<Field>> {
+    let mut pos = 0;
+    let required_insert_count = decode_int(data, &mut pos, 8)?;
+    let _delta_base = decode_int(data, &mut pos, 7)?;
+    if required_insert_count != 0 {
+        return Err(Http3Error::ProtocolError("QPACK dynamic table references are not supported".to_string()));
+    }
+
+    let mut fields = Vec::new();
+    while pos < data.len() {
+        let first = data[pos];
+        if first & 0b1000_0000 != 0 {
+            // Indexed field line
+            if first & 0b0100_0000 == 0 {
+                return Err(Http3Error::ProtocolError("QPACK dynamic table references are not supported".to_string()));
+            }
+            let (name, value) = static_entry(decode_int(data, &mut pos, 6)?)?;
+            fields.push((name.to_string(), value.to_string()));
+        } else if first & 0b0100_0000 != 0 {
+            // Literal field line with name reference
+            if first & 0b0001_0000 == 0 {
+                return Err(Http3Error::ProtocolError("QPACK dynamic table references are not supported".to_string()));
+            }
+            let (name, _) = static_entry(decode_int(data, &mut pos, 4)?)?;
+            let value = decode_string(data, &mut pos, 7)?;
+            fields.push((name.to_string(), value));
+        } else if first & 0b0010_0000 != 0 {
+            // Literal field line with literal name
+            let name = decode_string(data, &mut pos, 3)?;
+            let value = decode_string(data, &mut pos, 7)?;
+            fields.push((name, value));
+        } else {
+            return Err(Http3Error::ProtocolError("QPACK post-base references are not supported".to_string()));
+        }
+    }
+
+    Ok(fields)
+}
+
+fn static_entry(index: u64) -> super::Result<(&'static str, &'static str)> {
+    STATIC_TABLE.get(index as usize)
+        .copied()
+        .ok_or_else(|| Http3Error::ProtocolError(format!("QPACK static index {} out of range", index)))
+}
+
+/// Prefixed integer (RFC 7541, 5.1) whose first byte carries `flags` above the prefix
+fn encode_int(buf: &mut BytesMut, flags: u8, prefix_bits: u8, value: u64) {
+    let max = (1u64 << prefix_bits) - 1;
+    if value < max {
+        buf.put_u8(flags | value as u8);
+        return;
+    }
+    buf.put_u8(flags | max as u8);
+    let mut rest = value - max;
+    while rest >= 0x80 {
+        buf.put_u8((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    buf.put_u8(rest as u8);
+}
+
+fn decode_int(data: &[u8], pos: &mut usize, prefix_bits: u8) -> super::Result<u64> {
+    let truncated = || Http3Error::ProtocolError("Truncated QPACK integer".to_string());
+    let max = (1u64 << prefix_bits) - 1;
+    let mut value = *data.get(*pos).ok_or_else(truncated)? as u64 & max;
+    *pos += 1;
+    if value < max {
+        return Ok(value);
+    }
+
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*pos).ok_or_else(truncated)?;
+        *pos += 1;
+        if shift > 56 {
+            return Err(Http3Error::ProtocolError("QPACK integer overflow".to_string()));
+        }
+        value += ((byte & 0x7F) as u64) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+/// String literal without Huffman coding; the H bit sits just above the length prefix
+fn encode_string(buf: &mut BytesMut, flags: u8, prefix_bits: u8, bytes: &[u8]) {
+    encode_int(buf, flags, prefix_bits, bytes.len() as u64);
+    buf.put_slice(bytes);
+}
+
+fn decode_string(data: &[u8], pos: &mut usize, prefix_bits: u8) -> super::Result<String> {
+    let huffman = data.get(*pos).is_some_and(|byte| byte & (1 << prefix_bits) != 0);
+    if huffman {
+        return Err(Http3Error::ProtocolError("Huffman-coded QPACK strings are not supported".to_string()));
+    }
+    let len = decode_int(data, pos, prefix_bits)? as usize;
+    let end = pos.checked_add(len)
+        .filter(|end| *end <= data.len())
+        .ok_or_else(|| Http3Error::ProtocolError("Truncated QPACK string".to_string()))?;
+    let value = String::from_utf8(data[*pos..end].to_vec())
+        .map_err(|_| Http3Error::ProtocolError("QPACK string is not UTF-8".to_string()))?;
+    *pos = end;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<Field> {
+        pairs.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_static_table_indexes() {
+        // Fully indexed: :method GET (17), :path / (1)
+        let encoded = encode(&fields(&[(":method", "GET"), (":path", "/")]));
+        assert_eq!(&encoded[..], &[0x00, 0x00, 0xC0 | 17, 0xC0 | 1]);
+
+        // :status 500 is index 71, beyond the 6-bit prefix
+        let encoded = encode(&fields(&[(":status", "500")]));
+        assert_eq!(&encoded[..], &[0x00, 0x00, 0xFF, 71 - 63]);
+    }
+
+    #[test]
+    fn test_field_section_roundtrip() {
+        let long_value = "x".repeat(300);
+        let original = fields(&[
+            (":method", "POST"),
+            (":path", "/api/data?id=7"),
+            ("content-type", "application/json"),
+            ("Content-Length", "12"),
+            ("x-custom-header", &long_value),
+        ]);
+
+        let decoded = decode(&encode(&original)).unwrap();
+        assert_eq!(decoded.len(), original.len());
+        assert_eq!(decoded[1], (":path".to_string(), "/api/data?id=7".to_string()));
+        assert_eq!(decoded[3], ("content-length".to_string(), "12".to_string()));
+        assert_eq!(decoded[4].1, long_value);
+    }
+
+    #[test]
+    fn test_rejects_unsupported_sections() {
+        // Dynamic table reference
+        assert!(decode(&[0x01, 0x00, 0x80]).is_err());
+        // Huffman-coded literal name
+        assert!(decode(&[0x00, 0x00, 0b0010_1001, b'a']).is_err());
+        // Truncated string
+        assert!(decode(&[0x00, 0x00, 0x5F, 0x00, 0x05, b'a']).is_err());
+        // Static index past the table
+        assert!(decode(&[0x00, 0x00, 0xFF, 0x40]).is_err());
+    }
+}
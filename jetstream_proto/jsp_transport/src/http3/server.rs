@@ -1,85 +1,208 @@
 //! HTTP/3 Server
 
-use super::{Request, Response, Frame, FrameType};
-use bytes::Bytes;
+use super::message::{self, DEFAULT_MAX_BODY_SIZE};
+use super::{Request, Response};
+use crate::quic_transport::{QuicServer, QuicTransport};
 use std::sync::Arc;
 
 /// HTTP/3 request handler
 pub type RequestHandler = Arc<dyn Fn(Request) -> Response + Send + Sync>;
 
+/// H3_MESSAGE_ERROR, sent when a request stream carries a malformed message
+const H3_MESSAGE_ERROR: u32 = 0x10E;
+
+#[derive(Clone)]
+struct Route {
+    method: String,
+    path: String,
+    handler: RequestHandler,
+}
+
 /// HTTP/3 server
+///
+/// Requests are matched against the registered routes by method and path
+/// (ignoring the query string). Unmatched requests go to the fallback
+/// handler given to `new`, or get a 404.
+#[derive(Clone)]
 pub struct Http3Server {
-    handler: RequestHandler,
+    routes: Vec<Route>,
+    fallback: Option<RequestHandler>,
+    max_body_size: usize,
 }
 
 impl Http3Server {
-    /// Create a new HTTP/3 server
+    /// Create a new HTTP/3 server that passes unrouted requests to `handler`
     pub fn new(handler: RequestHandler) -> Self {
-        Self { handler }
+        Self {
+            fallback: Some(handler),
+            ..Self::default()
+        }
     }
 
-    /// Handle incoming frame
-    pub fn handle_frame(&self, frame: Frame) -> super::Result<Frame> {
-        match frame.frame_type {
-            FrameType::Headers => {
-                // Parse request from headers
-                let request = self.parse_request(frame.payload)?;
-                
-                // Handle request
-                let response = (self.handler)(request);
-                
-                // Encode response
-                Ok(self.encode_response(response))
-            }
-            FrameType::Data => {
-                // Handle data frame
-                Ok(Frame::data(Bytes::from("ACK")))
-            }
-            _ => {
-                // Other frame types
-                Ok(Frame::settings())
-            }
+    /// Serve `method` requests for `path` with `handler`
+    pub fn route<F>(mut self, method: impl Into<String>, path: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method: method.into(),
+            path: path.into(),
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    /// Reject request bodies larger than `size` bytes
+    pub fn with_max_body_size(mut self, size: usize) -> Self {
+        self.max_body_size = size;
+        self
+    }
+
+    /// Produce the response for `request`
+    pub fn dispatch(&self, request: Request) -> Response {
+        let path = request.path.split('?').next().unwrap_or("");
+        let route = self.routes.iter()
+            .find(|route| route.method == request.method && route.path == path);
+
+        match (route, &self.fallback) {
+            (Some(route), _) => (route.handler)(request),
+            (None, Some(fallback)) => fallback(request),
+            (None, None) => Response::not_found(),
+        }
+    }
+
+    /// Accept connections from `server` and serve each on its own task
+    pub async fn run(self, server: QuicServer) -> anyhow::Result<()> {
+        loop {
+            let conn = match server.accept().await {
+                Ok(conn) => conn,
+                Err(e) if e.downcast_ref::<quinn::ConnectionError>().is_some() => {
+                    tracing::warn!("HTTP/3 handshake failed: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let this = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = this.serve_connection(conn).await {
+                    tracing::warn!("HTTP/3 connection failed: {}", e);
+                }
+            });
         }
     }
 
-    /// Parse request from headers frame
-    fn parse_request(&self, _payload: Bytes) -> super::Result<Request> {
-        // Simplified: in production, parse QPACK headers
-        Ok(Request::new("GET", "/"))
+    /// Serve the request streams of one connection until the peer closes it
+    pub async fn serve_connection(&self, conn: QuicTransport) -> anyhow::Result<()> {
+        loop {
+            let (send, recv) = match conn.accept_bi().await {
+                Ok(streams) => streams,
+                Err(e) => {
+                    return match e.downcast_ref::<quinn::ConnectionError>() {
+                        Some(quinn::ConnectionError::ApplicationClosed(_))
+                        | Some(quinn::ConnectionError::LocallyClosed)
+                        | Some(quinn::ConnectionError::TimedOut) => Ok(()),
+                        _ => Err(e),
+                    };
+                }
+            };
+
+            let this = self.clone();
+            let peer = conn.peer_addr();
+            tokio::spawn(async move {
+                if let Err(e) = this.serve_stream(send, recv).await {
+                    tracing::debug!(%peer, "HTTP/3 request stream failed: {}", e);
+                }
+            });
+        }
+    }
+
+    /// Read one request from a stream and write the response back on it
+    pub async fn serve_stream(&self, mut send: quinn::SendStream, mut recv: quinn::RecvStream) -> super::Result<()> {
+        let request = match message::read_message(&mut recv, self.max_body_size).await {
+            Ok(Some((fields, body))) => Request::from_fields(fields, body),
+            Ok(None) => return Ok(()),
+            Err(e) => Err(e),
+        };
+        let request = match request {
+            Ok(request) => request,
+            Err(e) => {
+                let _ = send.reset(quinn::VarInt::from_u32(H3_MESSAGE_ERROR));
+                let _ = recv.stop(quinn::VarInt::from_u32(H3_MESSAGE_ERROR));
+                return Err(e);
+            }
+        };
+
+        tracing::debug!(method = %request.method, path = %request.path, "HTTP/3 request");
+        let response = self.dispatch(request);
+        message::write_message(&mut send, &response.to_fields(), &response.body).await?;
+        send.finish().map_err(|e| super::Http3Error::StreamError(e.to_string()))?;
+        Ok(())
     }
+}
 
-    /// Encode response to frame
-    fn encode_response(&self, response: Response) -> Frame {
-        // Simplified: in production, encode with QPACK
-        let mut payload = Vec::new();
-        payload.extend_from_slice(format!(":status: {}\r\n", response.status).as_bytes());
-        
-        for (key, value) in &response.headers {
-            payload.extend_from_slice(format!("{}: {}\r\n", key, value).as_bytes());
+impl Default for Http3Server {
+    /// A server without routes, answering every request with a 404
+    fn default() -> Self {
+        Self {
+            routes: Vec::new(),
+            fallback: None,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
         }
-        
-        payload.extend_from_slice(b"\r\n");
-        payload.extend_from_slice(&response.body);
-        
-        Frame::headers(Bytes::from(payload))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::http3::Http3Client;
+    use std::sync::Once;
 
-    #[test]
-    fn test_http3_server() {
-        let handler = Arc::new(|_req: Request| {
-            Response::ok().text("Hello HTTP/3")
+    static INIT: Once = Once::new();
+
+    fn init() {
+        INIT.call_once(|| {
+            let _ = rustls::crypto::ring::default_provider().install_default();
         });
+    }
+
+    #[test]
+    fn test_dispatch_routes() {
+        let server = Http3Server::default()
+            .route("GET", "/hello", |_req| Response::ok().text("hello"))
+            .route("POST", "/hello", |req| Response::new(201).body(req.body));
+
+        assert_eq!(server.dispatch(Request::new("GET", "/hello?lang=en")).body, b"hello");
+        assert_eq!(server.dispatch(Request::new("POST", "/hello").body(b"hi".to_vec())).status, 201);
+        assert_eq!(server.dispatch(Request::new("DELETE", "/hello")).status, 404);
+        assert_eq!(server.dispatch(Request::new("GET", "/missing")).status, 404);
+
+        let with_fallback = Http3Server::new(Arc::new(|_req| Response::ok().text("fallback")));
+        assert_eq!(with_fallback.dispatch(Request::new("GET", "/missing")).body, b"fallback");
+    }
+
+    #[tokio::test]
+    async fn test_http3_server() {
+        init();
+        let quic = QuicServer::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let addr = quic.local_addr().unwrap();
+
+        let server = Http3Server::default()
+            .route("GET", "/hello", |req| {
+                Response::ok().text(&format!("Hello {}", req.get_header("user-agent").unwrap()))
+            });
+        let server_task = tokio::spawn(server.run(quic));
+
+        let client = Http3Client::connect(addr).await.unwrap();
+        let response = client.send(Request::new("GET", "/hello").header("User-Agent", "test")).await.unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"Hello test");
+        assert_eq!(response.headers.get("content-type").unwrap(), "text/plain");
+
+        let response = client.send(Request::new("GET", "/missing")).await.unwrap();
+        assert_eq!(response.status, 404);
+        assert!(response.body.is_empty());
 
-        let server = Http3Server::new(handler);
-        
-        let request_frame = Frame::headers(Bytes::from("GET / HTTP/3"));
-        let response_frame = server.handle_frame(request_frame).unwrap();
-        
-        assert_eq!(response_frame.frame_type, FrameType::Headers);
+        server_task.abort();
     }
 }
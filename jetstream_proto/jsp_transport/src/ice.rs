@@ -1,12 +1,24 @@
 use std::net::SocketAddr;
 use std::collections::HashSet;
+use std::time::Duration;
 use anyhow::Result;
-use tracing::{info, warn};
+use bytes::BytesMut;
+use jsp_core::types::header::{Header, FRAME_TYPE_STUN};
+use jsp_core::types::stun::{StunMessage, StunMessageType};
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
 use crate::connection::Connection;
-use crate::signaling::{SignalingClient, SignalingMessage};
-use crate::turn_client::TurnClient;
+use crate::signaling::{SignalingChannel, SignalingMessage};
+use crate::turn_client::{TurnClient, TurnRefreshHandle};
+use crate::udp::UdpTransport;
+use crate::webrtc::IceTransportPolicy;
 use serde::Deserialize;
 
+/// How long a single candidate is checked before moving on
+const CHECK_TIMEOUT: Duration = Duration::from_millis(500);
+/// Binding requests are resent this often during a check
+const CHECK_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum CandidateType {
     Host,
@@ -22,14 +34,38 @@ pub struct Candidate {
     pub priority: u32,
 }
 
+/// TURN allocation the agent's transport relays through
+struct ActiveRelay {
+    transport: UdpTransport,
+    /// Keeps the allocation alive until stopped
+    refresh: TurnRefreshHandle,
+}
+
+/// ICE agent with trickle candidate exchange
+///
+/// Local candidates are sent over signaling as soon as they are gathered,
+/// and remote candidates are checked as they arrive, so a pair can be
+/// selected before either side has finished gathering.
+///
+/// With a TURN server set, a relay is only allocated once direct checks
+/// have failed (or up front under the `Relay` policy). From then on the
+/// transport sends everything through the relay.
 pub struct IceAgent {
     local_candidates: HashSet<Candidate>,
     remote_candidates: HashSet<Candidate>,
-    pub signaling: Option<SignalingClient>,
+    /// Remote candidates received but not checked yet
+    pending_checks: Vec<Candidate>,
+    pub signaling: Option<Box<dyn SignalingChannel>>,
     peer_id: String,
     target_peer_id: Option<String>,
     selected_candidate: Option<SocketAddr>,
     turn_client: Option<TurnClient>,
+    transport_policy: IceTransportPolicy,
+    /// A check failed, so the relay may be needed
+    direct_check_failed: bool,
+    /// Allocating was tried already, successfully or not
+    relay_attempted: bool,
+    relay: Option<ActiveRelay>,
 }
 
 impl IceAgent {
@@ -37,11 +73,16 @@ impl IceAgent {
         Self {
             local_candidates: HashSet::new(),
             remote_candidates: HashSet::new(),
+            pending_checks: Vec::new(),
             signaling: None,
             peer_id,
             target_peer_id: None,
             selected_candidate: None,
             turn_client: None,
+            transport_policy: IceTransportPolicy::All,
+            direct_check_failed: false,
+            relay_attempted: false,
+            relay: None,
         }
     }
 
@@ -49,16 +90,49 @@ impl IceAgent {
         self.turn_client = Some(TurnClient::new(turn_server_addr));
     }
 
+    /// Use `turn_client`, e.g. one with another lifetime, for the relay
+    pub fn set_turn_client(&mut self, turn_client: TurnClient) {
+        self.turn_client = Some(turn_client);
+    }
+
+    /// Local relayed address, while the transport relays through it
+    pub fn relay_addr(&self) -> Option<SocketAddr> {
+        self.relay.as_ref()?;
+        self.turn_client.as_ref()?.get_relay_addr()
+    }
+
     pub async fn connect_signaling(&mut self, url: &str) -> Result<()> {
-        let client = SignalingClient::connect(url, self.peer_id.clone()).await?;
-        self.signaling = Some(client);
+        let client = crate::signaling::SignalingClient::connect(url, self.peer_id.clone()).await?;
+        self.signaling = Some(Box::new(client));
         Ok(())
     }
 
+    /// Exchange candidates over `channel` instead of the signaling server
+    pub fn set_signaling(&mut self, channel: impl SignalingChannel + 'static) {
+        self.signaling = Some(Box::new(channel));
+    }
+
     pub fn set_target_peer(&mut self, target: String) {
         self.target_peer_id = Some(target);
     }
 
+    /// Restrict the candidates used; with `Relay` only relayed candidates
+    /// are advertised and only remote relayed candidates are checked
+    pub fn set_transport_policy(&mut self, policy: IceTransportPolicy) {
+        self.transport_policy = policy;
+    }
+
+    pub fn transport_policy(&self) -> IceTransportPolicy {
+        self.transport_policy
+    }
+
+    fn allows(&self, c_type: &CandidateType) -> bool {
+        match self.transport_policy {
+            IceTransportPolicy::All => true,
+            IceTransportPolicy::Relay => *c_type == CandidateType::Relayed,
+        }
+    }
+
     pub fn add_local_candidate(&mut self, addr: SocketAddr, c_type: CandidateType) {
         let priority = match c_type {
             CandidateType::Host => 100,
@@ -76,129 +150,259 @@ impl IceAgent {
         self.local_candidates.insert(candidate);
     }
 
+    /// Add a local candidate and trickle it to the target peer right away
+    ///
+    /// Candidates the transport policy excludes are dropped.
+    pub async fn emit_local_candidate(&mut self, addr: SocketAddr, c_type: CandidateType) -> Result<()> {
+        if !self.allows(&c_type) {
+            debug!("Transport policy excludes {:?} candidate {}", c_type, addr);
+            return Ok(());
+        }
+        self.add_local_candidate(addr, c_type.clone());
+
+        let candidate = match self.local_candidates.iter().find(|c| c.addr == addr && c.candidate_type == c_type) {
+            Some(candidate) => serde_json::to_string(candidate)?,
+            None => return Ok(()),
+        };
+        if let (Some(sig), Some(target)) = (&mut self.signaling, &self.target_peer_id) {
+            sig.send(SignalingMessage::Candidate {
+                target: target.clone(),
+                candidate,
+            }).await?;
+        }
+        Ok(())
+    }
+
+    /// Gather local candidates, trickling each one as it is found
     pub async fn gather_candidates(&mut self, connection: &mut Connection) -> Result<()> {
+        self.gather(connection, None).await.map(|_| ())
+    }
+
+    /// Gather and trickle local candidates while checking remote candidates
+    /// as they arrive, until a pair is selected or `timeout` passes
+    ///
+    /// Checks read the connection's socket directly, so this runs before
+    /// the handshake.
+    pub async fn trickle(&mut self, connection: &mut Connection, timeout: Duration) -> Result<Option<SocketAddr>> {
+        let deadline = Instant::now() + timeout;
+        let udp = match connection.transport.as_udp() {
+            Some(udp) => udp.clone(),
+            None => return Ok(None),
+        };
+
+        if let Some(selected) = self.gather(connection, Some(&udp)).await? {
+            return Ok(Some(selected));
+        }
+
+        while self.signaling.is_some() && Instant::now() < deadline {
+            if let Some(selected) = self.poll_remote(&udp, deadline - Instant::now()).await? {
+                return Ok(Some(selected));
+            }
+        }
+        self.check_pending(&udp).await
+    }
+
+    /// Gather host, server reflexive and relayed candidates in turn; with
+    /// `udp`, remote candidates that arrived meanwhile are checked between steps
+    async fn gather(&mut self, connection: &mut Connection, udp: Option<&UdpTransport>) -> Result<Option<SocketAddr>> {
         // 1. Host candidates (local interface)
         // For now, we just use the address we are bound to if possible, or 0.0.0.0
         // In a real implementation, we'd iterate interfaces.
         if let Ok(addr) = connection.local_addr() {
-             self.add_local_candidate(addr, CandidateType::Host);
+            self.emit_local_candidate(addr, CandidateType::Host).await?;
+        }
+        if let Some(udp) = udp {
+            if let Some(selected) = self.poll_remote(udp, Duration::ZERO).await? {
+                return Ok(Some(selected));
+            }
         }
 
         // 2. Server Reflexive (STUN)
-        if let Some(public_addr) = connection.discover_public_address().await? {
-            self.add_local_candidate(public_addr, CandidateType::ServerReflexive);
+        if let Some(public_addr) = connection.discover_public_address(false).await? {
+            self.emit_local_candidate(public_addr, CandidateType::ServerReflexive).await?;
         }
-        
-        // 3. Relayed (TURN)
-        if let Some(turn_client) = &mut self.turn_client {
-            match turn_client.allocate(&connection.transport).await {
-                Ok(relay_addr) => {
-                    info!("TURN relay allocated: {}", relay_addr);
-                    self.add_local_candidate(relay_addr, CandidateType::Relayed);
-                }
-                Err(e) => {
-                    warn!("TURN allocation failed: {}", e);
-                }
+        if let Some(udp) = udp {
+            if let Some(selected) = self.poll_remote(udp, Duration::ZERO).await? {
+                return Ok(Some(selected));
             }
         }
         
-        // Send candidates via signaling
-        if let (Some(sig), Some(target)) = (&mut self.signaling, &self.target_peer_id) {
-            for c in &self.local_candidates {
-                let json = serde_json::to_string(c)?;
-                let msg = SignalingMessage::Candidate {
-                    target: target.clone(),
-                    candidate: json,
-                };
-                sig.send(msg).await?;
+        // 3. Relayed (TURN), up front only when nothing else may be used;
+        // otherwise once direct checks fail
+        if self.transport_policy == IceTransportPolicy::Relay {
+            if let Some(transport) = connection.transport.as_udp() {
+                let transport = transport.clone();
+                self.start_relay(&transport).await?;
             }
         }
+        if let Some(udp) = udp {
+            return self.poll_remote(udp, Duration::ZERO).await;
+        }
 
-        Ok(())
+        Ok(None)
     }
 
+    /// Read signaling messages for up to `wait`, then drain those already
+    /// queued and check the remote candidates they carried
+    async fn poll_remote(&mut self, udp: &UdpTransport, wait: Duration) -> Result<Option<SocketAddr>> {
+        let mut wait = wait;
+        while let Some(sig) = &mut self.signaling {
+            match tokio::time::timeout(wait, sig.recv()).await {
+                Ok(Ok(msg)) => self.process_signaling_message(msg).await?,
+                Ok(Err(e)) => {
+                    warn!("Signaling channel failed, no more remote candidates: {}", e);
+                    self.signaling = None;
+                }
+                Err(_) => break,
+            }
+            wait = Duration::ZERO;
+        }
+
+        self.check_pending(udp).await
+    }
+
+    /// Take in a remote candidate, or the peer a signaling room paired
+    /// this agent with
+    ///
+    /// On being paired without a target, the paired peer becomes the target
+    /// and gets the candidates gathered so far.
     pub async fn process_signaling_message(&mut self, msg: SignalingMessage) -> Result<()> {
         match msg {
             SignalingMessage::Candidate { candidate, .. } => {
                 if let Ok(c) = serde_json::from_str::<Candidate>(&candidate) {
                     info!("Received remote candidate: {:?}", c);
-                    self.remote_candidates.insert(c);
+                    if !self.allows(&c.candidate_type) {
+                        debug!("Transport policy excludes remote candidate {}", c.addr);
+                    } else if self.remote_candidates.insert(c.clone()) {
+                        if let (Some(relay), Some(turn_client)) = (&self.relay, &self.turn_client) {
+                            if let Err(e) = turn_client.add_permission(&relay.transport, c.addr).await {
+                                warn!("TURN permission for {} failed: {}", c.addr, e);
+                            }
+                        }
+                        self.pending_checks.push(c);
+                    }
                 }
             }
+            SignalingMessage::PeerJoined { peer_id } if self.target_peer_id.is_none() => {
+                info!("Paired with peer {}", peer_id);
+                let candidates = self.local_candidates.iter()
+                    .filter(|c| self.allows(&c.candidate_type))
+                    .map(serde_json::to_string)
+                    .collect::<Result<Vec<_>, _>>()?;
+                if let Some(sig) = &mut self.signaling {
+                    for candidate in candidates {
+                        sig.send(SignalingMessage::Candidate { target: peer_id.clone(), candidate }).await?;
+                    }
+                }
+                self.target_peer_id = Some(peer_id);
+            }
+            SignalingMessage::PeerLeft { peer_id } if self.target_peer_id.as_ref() == Some(&peer_id) => {
+                warn!("Target peer {} left the signaling server", peer_id);
+                self.target_peer_id = None;
+            }
+            SignalingMessage::Error { message } => warn!("Signaling server error: {}", message),
             _ => {}
         }
         Ok(())
     }
+
+    /// Check unchecked remote candidates, highest priority first, falling
+    /// back to the TURN relay once they have all failed
+    async fn check_pending(&mut self, udp: &UdpTransport) -> Result<Option<SocketAddr>> {
+        loop {
+            self.pending_checks.sort_by_key(|c| std::cmp::Reverse(c.priority));
+            while !self.pending_checks.is_empty() {
+                let candidate = self.pending_checks.remove(0);
+                info!("Testing connectivity to {:?}", candidate.addr);
+                if check_candidate(udp, candidate.addr).await? {
+                    info!("Connectivity check succeeded for {:?}", candidate.addr);
+                    self.selected_candidate = Some(candidate.addr);
+                    return Ok(Some(candidate.addr));
+                }
+                info!("Connectivity check failed for {:?}", candidate.addr);
+                self.direct_check_failed = true;
+            }
+
+            if !self.direct_check_failed || self.relay_attempted || self.turn_client.is_none() {
+                return Ok(None);
+            }
+            info!("Direct connectivity checks failed, falling back to TURN");
+            self.start_relay(udp).await?;
+            if self.pending_checks.is_empty() {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Allocate a TURN relay and send everything through it from now on
+    ///
+    /// Permissions are created for the remote candidates known so far, the
+    /// relayed candidate is trickled, and the remote candidates are queued
+    /// to be checked again, through the relay. Only tried once; a failed
+    /// allocation leaves the agent without a relay.
+    async fn start_relay(&mut self, udp: &UdpTransport) -> Result<()> {
+        if self.relay_attempted {
+            return Ok(());
+        }
+        self.relay_attempted = true;
+        let turn_client = match &mut self.turn_client {
+            Some(turn_client) => turn_client,
+            None => return Ok(()),
+        };
+
+        let relay_addr = match turn_client.allocate(udp).await {
+            Ok(relay_addr) => relay_addr,
+            Err(e) => {
+                warn!("TURN allocation failed: {}", e);
+                return Ok(());
+            }
+        };
+        info!("TURN relay allocated: {}", relay_addr);
+        for candidate in &self.remote_candidates {
+            if let Err(e) = turn_client.create_permission(udp, candidate.addr).await {
+                warn!("TURN permission for {} failed: {}", candidate.addr, e);
+            }
+        }
+        let refresh = turn_client.start_relay(udp, |lost| {
+            warn!("TURN relay lost ({:?}), sending directly again", lost);
+        })?;
+        self.relay = Some(ActiveRelay { transport: udp.clone(), refresh });
+
+        self.emit_local_candidate(relay_addr, CandidateType::Relayed).await?;
+        self.pending_checks = self.remote_candidates.iter().cloned().collect();
+        Ok(())
+    }
+
+    /// Delete the TURN allocation, if the agent relays through one, and
+    /// send directly again
+    pub async fn release_relay(&mut self) -> Result<()> {
+        let relay = match self.relay.take() {
+            Some(relay) => relay,
+            None => return Ok(()),
+        };
+        relay.refresh.stop();
+        match &mut self.turn_client {
+            Some(turn_client) => turn_client.deallocate(&relay.transport).await,
+            None => Ok(()),
+        }
+    }
     
     pub async fn perform_connectivity_checks(&mut self, connection: &mut Connection) -> Result<Option<SocketAddr>> {
-        use jsp_core::types::stun::StunMessage;
-        use jsp_core::types::header::{Header, FRAME_TYPE_STUN};
-        use bytes::BytesMut;
-        use tokio::time::{timeout, Duration};
-        
         if self.remote_candidates.is_empty() {
             warn!("No remote candidates to check");
             return Ok(None);
         }
-        
-        // Sort candidates by priority
-        let mut candidates: Vec<_> = self.remote_candidates.iter().collect();
-        candidates.sort_by_key(|c| std::cmp::Reverse(c.priority));
-        
-        // Try each candidate
-        for candidate in candidates {
-            info!("Testing connectivity to {:?}", candidate.addr);
-            
-            // Send STUN Binding Request
-            let req = StunMessage::binding_request();
-            let payload = req.to_bytes();
-            
-            let header = Header::new(
-                0,
-                FRAME_TYPE_STUN,
-                0,
-                0,
-                0,
-                0,
-                Default::default(),
-                None,
-                Some(payload.len() as u32)
-            );
-            
-            let header_bytes = serde_cbor::to_vec(&header)?;
-            let header_len = header_bytes.len() as u16;
-            
-            let mut packet = BytesMut::with_capacity(2 + header_bytes.len() + payload.len());
-            packet.extend_from_slice(&header_len.to_be_bytes());
-            packet.extend_from_slice(&header_bytes);
-            packet.extend_from_slice(&payload);
-            
-            connection.transport.send_to(&packet, candidate.addr).await?;
-            
-            // Wait for response (short timeout)
-            let check_timeout = Duration::from_millis(500);
-            let start = std::time::Instant::now();
-            
-            while start.elapsed() < check_timeout {
-                match timeout(Duration::from_millis(100), connection.recv()).await {
-                    Ok(Ok(_)) => {
-                        // If we got any response, consider this candidate valid
-                        info!("Connectivity check succeeded for {:?}", candidate.addr);
-                        self.selected_candidate = Some(candidate.addr);
-                        return Ok(Some(candidate.addr));
-                    }
-                    Ok(Err(e)) => {
-                        warn!("Error during connectivity check: {}", e);
-                    }
-                    Err(_) => continue, // timeout, keep trying
-                }
-            }
-            
-            info!("Connectivity check failed for {:?}", candidate.addr);
+        let udp = match connection.transport.as_udp() {
+            Some(udp) => udp.clone(),
+            None => return Ok(None),
+        };
+
+        self.pending_checks = self.remote_candidates.iter().cloned().collect();
+        let selected = self.check_pending(&udp).await?;
+        if selected.is_none() {
+            warn!("All connectivity checks failed");
         }
-        
-        warn!("All connectivity checks failed");
-        Ok(None)
+        Ok(selected)
     }
     
     pub fn get_selected_candidate(&self) -> Option<SocketAddr> {
@@ -222,6 +426,84 @@ impl IceAgent {
     }
 }
 
+/// Send binding requests to `addr` until it answers or `CHECK_TIMEOUT` passes
+///
+/// Binding requests from the peer's own checks are answered meanwhile;
+/// anything else read from the socket is dropped.
+async fn check_candidate(udp: &UdpTransport, addr: SocketAddr) -> Result<bool> {
+    let request = StunMessage::binding_request();
+    let packet = stun_packet(&request)?;
+    let deadline = Instant::now() + CHECK_TIMEOUT;
+    let mut buf = vec![0u8; 2048];
+
+    while Instant::now() < deadline {
+        if let Err(e) = udp.send_to(&packet, addr).await {
+            debug!("Connectivity check to {} could not be sent: {}", addr, e);
+            return Ok(false);
+        }
+
+        let retry = (Instant::now() + CHECK_RETRY_INTERVAL).min(deadline);
+        while let Ok(received) = tokio::time::timeout_at(retry, udp.recv_from(&mut buf)).await {
+            let (len, from) = match received {
+                Ok(received) => received,
+                Err(e) => {
+                    debug!("Error during connectivity check: {}", e);
+                    continue;
+                }
+            };
+            let msg = match parse_stun_packet(&buf[..len]) {
+                Some(msg) => msg,
+                None => continue,
+            };
+            match msg.msg_type {
+                StunMessageType::BindingResponse if from == addr && msg.transaction_id == request.transaction_id => {
+                    return Ok(true);
+                }
+                StunMessageType::BindingRequest => {
+                    let response = StunMessage::binding_response(msg.transaction_id, from);
+                    let _ = udp.send_to(&stun_packet(&response)?, from).await;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+/// Frame a STUN message the way the connection and `StunServer` expect it
+pub(crate) fn stun_packet(msg: &StunMessage) -> Result<BytesMut> {
+    let payload = msg.to_bytes();
+    let header = Header::new(
+        0,
+        FRAME_TYPE_STUN,
+        0,
+        0,
+        0,
+        0,
+        Default::default(),
+        None,
+        Some(payload.len() as u32)
+    );
+
+    let header_bytes = serde_cbor::to_vec(&header)?;
+    let header_len = header_bytes.len() as u16;
+
+    let mut packet = BytesMut::with_capacity(2 + header_bytes.len() + payload.len());
+    packet.extend_from_slice(&header_len.to_be_bytes());
+    packet.extend_from_slice(&header_bytes);
+    packet.extend_from_slice(&payload);
+    Ok(packet)
+}
+
+pub(crate) fn parse_stun_packet(data: &[u8]) -> Option<StunMessage> {
+    let header_len = u16::from_be_bytes([*data.first()?, *data.get(1)?]) as usize;
+    let header: Header = serde_cbor::from_slice(data.get(2..2 + header_len)?).ok()?;
+    if header.msg_type != FRAME_TYPE_STUN {
+        return None;
+    }
+    StunMessage::from_bytes(&data[2 + header_len..]).ok()
+}
 
 // Need Serialize/Deserialize for Candidate to send over signaling
 impl serde::Serialize for Candidate {
@@ -291,3 +573,122 @@ impl<'de> serde::Deserialize<'de> for CandidateType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConnectionConfig;
+    use crate::stun_server::StunServer;
+    use std::collections::VecDeque;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tokio::net::UdpSocket;
+
+    /// Delivers remote candidates one at a time, 100 ms apart, and records what the agent trickles
+    struct MockSignaling {
+        remote: VecDeque<Candidate>,
+        delivered: Arc<AtomicUsize>,
+        sent: Arc<Mutex<Vec<Candidate>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl SignalingChannel for MockSignaling {
+        async fn send(&mut self, msg: SignalingMessage) -> Result<()> {
+            if let SignalingMessage::Candidate { candidate, .. } = msg {
+                self.sent.lock().unwrap().push(serde_json::from_str(&candidate)?);
+            }
+            Ok(())
+        }
+
+        async fn recv(&mut self) -> Result<SignalingMessage> {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            match self.remote.pop_front() {
+                Some(candidate) => {
+                    self.delivered.fetch_add(1, Ordering::SeqCst);
+                    Ok(SignalingMessage::Candidate {
+                        target: "local".to_string(),
+                        candidate: serde_json::to_string(&candidate)?,
+                    })
+                }
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    fn host(addr: SocketAddr) -> Candidate {
+        Candidate { addr, candidate_type: CandidateType::Host, priority: 100 }
+    }
+
+    async fn local_connection() -> Result<Connection> {
+        let config = ConnectionConfig::builder()
+            .bind_addr("127.0.0.1:0".to_string())
+            .build();
+        Ok(Connection::connect_with_config("127.0.0.1:9", config).await?)
+    }
+
+    fn agent_with(remote: Vec<Candidate>) -> (IceAgent, Arc<AtomicUsize>, Arc<Mutex<Vec<Candidate>>>) {
+        let delivered = Arc::new(AtomicUsize::new(0));
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut agent = IceAgent::new("local".to_string());
+        agent.set_target_peer("remote".to_string());
+        agent.set_signaling(MockSignaling {
+            remote: remote.into(),
+            delivered: Arc::clone(&delivered),
+            sent: Arc::clone(&sent),
+        });
+        (agent, delivered, sent)
+    }
+
+    #[tokio::test]
+    async fn test_trickle_selects_pair_before_all_candidates_arrive() -> Result<()> {
+        let mut responder = StunServer::new("127.0.0.1:0").await?;
+        responder.start();
+        // Bound but never answering
+        let mut silent = Vec::new();
+        for _ in 0..4 {
+            silent.push(UdpSocket::bind("127.0.0.1:0").await?);
+        }
+
+        let remote = vec![
+            host(silent[0].local_addr()?),
+            host(responder.local_addr()?),
+            host(silent[1].local_addr()?),
+            host(silent[2].local_addr()?),
+            host(silent[3].local_addr()?),
+        ];
+        let total = remote.len();
+        let (mut agent, delivered, sent) = agent_with(remote);
+
+        let mut conn = local_connection().await?;
+        let selected = agent.trickle(&mut conn, Duration::from_secs(5)).await?;
+
+        assert_eq!(selected, Some(responder.local_addr()?));
+        assert_eq!(agent.get_selected_candidate(), selected);
+        assert!(delivered.load(Ordering::SeqCst) < total, "waited for every candidate");
+        assert_eq!(sent.lock().unwrap().as_slice(), &[Candidate {
+            addr: conn.local_addr()?,
+            candidate_type: CandidateType::Host,
+            priority: 100,
+        }]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_relay_policy_skips_direct_candidates() -> Result<()> {
+        let mut responder = StunServer::new("127.0.0.1:0").await?;
+        responder.start();
+
+        let (mut agent, delivered, sent) = agent_with(vec![host(responder.local_addr()?)]);
+        agent.set_transport_policy(IceTransportPolicy::Relay);
+
+        let mut conn = local_connection().await?;
+        let selected = agent.trickle(&mut conn, Duration::from_millis(500)).await?;
+
+        // The reachable host candidate arrived but was never checked
+        assert_eq!(selected, None);
+        assert_eq!(delivered.load(Ordering::SeqCst), 1);
+        assert_eq!(agent.remote_candidates_count(), 0);
+        assert!(sent.lock().unwrap().is_empty());
+        Ok(())
+    }
+}
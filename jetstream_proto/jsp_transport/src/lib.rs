@@ -1,14 +1,23 @@
 pub mod udp;
 pub mod connection;
+pub mod error;
+pub mod events;
+pub mod file_transfer;
 pub mod reliability;
+pub mod fragmentation;
 pub mod server;
+pub mod pool;
+pub mod ticket_store;
 pub mod heartbeat;
+pub mod clock;
 pub mod rate_limit;
 pub mod config;
+pub mod duration_format;
 pub mod logging;
 pub mod congestion;
 pub mod bbr;
 pub mod memory_pool;
+pub mod coalescing;
 pub mod stun_server;
 pub mod signaling;
 pub mod ice;
@@ -20,6 +29,7 @@ pub mod priority_queue;
 pub mod circuit_breaker;
 pub mod ddos_protection;
 pub mod metrics;
+pub mod stats_stream;
 pub mod tcp_transport;
 pub mod fallback_detector;
 pub mod transport;
@@ -27,11 +37,15 @@ pub mod quic_cert;
 pub mod quic_transport;
 pub mod compression;
 pub mod network_status;
+pub mod nat;
 pub mod pow;
 pub mod ip_blacklist;
+pub mod amplification;
 pub mod negotiation;
 pub mod transport_selector;
+pub mod transport_race;
 pub mod adaptive;
+pub mod runtime;
 
 // Multi-hop tunnel manager
 pub mod multihop;
@@ -1,8 +1,9 @@
 //! Load Balancing Algorithms
 
 use super::Backend;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::BTreeMap;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
@@ -110,14 +111,104 @@ impl BalancingAlgorithm for WeightedRoundRobin {
     }
 }
 
+/// Weighted least-response-time algorithm
+///
+/// Picks the backend with the lowest `response time * (connections + 1) / weight`,
+/// so slow backends get less traffic without starving while idle. Backends
+/// without a measurement yet are tried first.
+pub struct LeastResponseTime;
+
+impl LeastResponseTime {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn score(backend: &Backend) -> f64 {
+        let response_time = match backend.response_time() {
+            Some(response_time) => response_time.as_secs_f64(),
+            None => return 0.0,
+        };
+        response_time * (backend.connections() as f64 + 1.0) / backend.weight.max(1) as f64
+    }
+}
+
+impl Default for LeastResponseTime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BalancingAlgorithm for LeastResponseTime {
+    fn select(&self, backends: &[Arc<Backend>], _key: Option<&str>) -> Option<Arc<Backend>> {
+        backends.iter()
+            .min_by(|a, b| Self::score(a).total_cmp(&Self::score(b)))
+            .cloned()
+    }
+}
+
+/// Hash ring with virtual nodes
+///
+/// Each node owns the arcs ending at its virtual node points, so adding or
+/// removing a node only moves the keys on its arcs (about 1/n of all keys).
+#[derive(Debug, Clone, Default)]
+pub struct HashRing {
+    virtual_nodes: usize,
+    points: BTreeMap<u64, String>,
+}
+
+impl HashRing {
+    pub fn new(virtual_nodes: usize) -> Self {
+        Self {
+            virtual_nodes: virtual_nodes.max(1),
+            points: BTreeMap::new(),
+        }
+    }
+
+    /// Add a node; adding it again has no effect
+    pub fn add(&mut self, node: &str) {
+        for i in 0..self.virtual_nodes {
+            self.points.insert(hash_bytes(format!("{}#{}", node, i).as_bytes()), node.to_string());
+        }
+    }
+
+    pub fn remove(&mut self, node: &str) {
+        self.points.retain(|_, n| n != node);
+    }
+
+    /// Node owning `key`: the first point at or after the key's hash
+    pub fn get(&self, key: &[u8]) -> Option<&str> {
+        let hash = hash_bytes(key);
+        self.points.range(hash..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .map(|(_, node)| node.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+}
+
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
 /// Consistent hashing algorithm
+///
+/// Backends are placed on a `HashRing` by ID; requests without a key all
+/// go to the owner of "default".
 pub struct ConsistentHash {
-    virtual_nodes: usize,
+    /// Ring for the backend IDs it was built from
+    ring: Mutex<(Vec<String>, HashRing)>,
 }
 
 impl ConsistentHash {
     pub fn new(virtual_nodes: usize) -> Self {
-        Self { virtual_nodes }
+        Self {
+            ring: Mutex::new((Vec::new(), HashRing::new(virtual_nodes))),
+        }
     }
 }
 
@@ -134,29 +225,34 @@ impl BalancingAlgorithm for ConsistentHash {
         }
 
         let key = key.unwrap_or("default");
-        let hash = Self::hash_key(key);
-
-        // Find the backend with the closest hash
-        backends.iter()
-            .min_by_key(|b| {
-                let backend_hash = Self::hash_key(&format!("{}:{}", b.id, hash % (self.virtual_nodes as u64)));
-                hash.wrapping_sub(backend_hash)
-            })
-            .cloned()
-    }
-}
+        let mut ring = self.ring.lock().unwrap();
+
+        // Update the ring incrementally when the backend set changed
+        let (ids, hash_ring) = &mut *ring;
+        if ids.len() != backends.len() || ids.iter().zip(backends).any(|(id, b)| *id != b.id) {
+            for id in ids.iter() {
+                if !backends.iter().any(|b| b.id == *id) {
+                    hash_ring.remove(id);
+                }
+            }
+            for backend in backends {
+                if !ids.contains(&backend.id) {
+                    hash_ring.add(&backend.id);
+                }
+            }
+            *ids = backends.iter().map(|b| b.id.clone()).collect();
+        }
 
-impl ConsistentHash {
-    fn hash_key(key: &str) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        key.hash(&mut hasher);
-        hasher.finish()
+        let id = hash_ring.get(key.as_bytes())?;
+        backends.iter().find(|b| b.id == id).cloned()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
+    use std::time::Duration;
 
     fn create_backends() -> Vec<Arc<Backend>> {
         vec![
@@ -194,6 +290,41 @@ mod tests {
         assert!(selected.id == "backend1" || selected.id == "backend3");
     }
 
+    #[test]
+    fn test_least_response_time_avoids_slow_backend() {
+        let backends = create_backends();
+        let lrt = LeastResponseTime::new();
+        let latency = |id: &str| match id {
+            "backend2" => Duration::from_millis(200),
+            _ => Duration::from_millis(10),
+        };
+
+        // Unmeasured backends are tried first
+        let mut picks: HashMap<String, usize> = HashMap::new();
+        for _ in 0..300 {
+            let selected = lrt.select(&backends, None).unwrap();
+            selected.inc_connections();
+            selected.record_response_time(latency(&selected.id));
+            *picks.entry(selected.id.clone()).or_default() += 1;
+            if backends.iter().map(|b| b.connections()).sum::<u32>() >= 10 {
+                for backend in &backends {
+                    backend.active_connections.store(0, Ordering::Relaxed);
+                }
+            }
+        }
+
+        // Despite its double weight, the slow backend gets a small share
+        assert!(picks["backend2"] < 300 / 10, "slow backend got {:?}", picks);
+        assert!(picks["backend1"] > 100 && picks["backend3"] > 100, "{:?}", picks);
+
+        // Once it speeds up, its average recovers and traffic returns
+        for _ in 0..20 {
+            backends[1].record_response_time(Duration::from_millis(2));
+        }
+        assert!(backends[1].response_time().unwrap() < Duration::from_millis(10));
+        assert_eq!(lrt.select(&backends, None).unwrap().id, "backend2");
+    }
+
     #[test]
     fn test_consistent_hash() {
         let backends = create_backends();
@@ -205,4 +336,32 @@ mod tests {
         // Same key should always return same backend
         assert_eq!(b1.id, b2.id);
     }
+
+    #[test]
+    fn test_hash_ring_minimal_remap() {
+        let keys: Vec<String> = (0..1000).map(|i| format!("client{}", i)).collect();
+        let mut ring = HashRing::new(150);
+        for node in ["a", "b", "c"] {
+            ring.add(node);
+        }
+        let before: Vec<String> = keys.iter().map(|k| ring.get(k.as_bytes()).unwrap().to_string()).collect();
+
+        // Only keys taken over by the new node move
+        ring.add("d");
+        let mut moved = 0;
+        for (key, old) in keys.iter().zip(&before) {
+            let new = ring.get(key.as_bytes()).unwrap();
+            if new != old {
+                assert_eq!(new, "d");
+                moved += 1;
+            }
+        }
+        assert!(moved > 100 && moved < 400, "moved {} of 1000 keys", moved);
+
+        // Removing it again restores the original mapping
+        ring.remove("d");
+        for (key, old) in keys.iter().zip(&before) {
+            assert_eq!(ring.get(key.as_bytes()).unwrap(), old);
+        }
+    }
 }
@@ -1,16 +1,21 @@
 //! Load Balancer
 
 use super::{Backend, BalancingAlgorithm, HealthChecker, HealthStatus};
-use super::health::HealthCheck;
+use super::health::{BackendHealth, HealthCheck};
+use crate::circuit_breaker::{CircuitBreaker, CircuitBreakerConfig, State};
 use std::sync::{Arc, RwLock};
 use std::collections::HashMap;
+use std::time::Duration;
 
 /// Load balancer
 pub struct LoadBalancer {
     backends: Arc<RwLock<Vec<Arc<Backend>>>>,
     algorithm: Arc<dyn BalancingAlgorithm>,
     health_checker: HealthChecker,
-    health_status: Arc<RwLock<HashMap<String, HealthStatus>>>,
+    health_status: Arc<RwLock<HashMap<String, BackendHealth>>>,
+    /// Passive health: trips on failures reported in-band, ejecting the backend
+    breakers: RwLock<HashMap<String, Arc<CircuitBreaker>>>,
+    breaker_config: CircuitBreakerConfig,
 }
 
 impl LoadBalancer {
@@ -27,7 +32,7 @@ impl LoadBalancer {
         {
             let mut status = health_status.write().unwrap();
             for backend in &backends {
-                status.insert(backend.id.clone(), HealthStatus::Healthy);
+                status.insert(backend.id.clone(), BackendHealth::default());
             }
         }
 
@@ -36,18 +41,45 @@ impl LoadBalancer {
             algorithm,
             health_checker,
             health_status,
+            breakers: RwLock::new(HashMap::new()),
+            breaker_config: CircuitBreakerConfig::default(),
         }
     }
 
+    /// Eject a backend after `failure_threshold` consecutive reported failures
+    /// and let traffic back in after `reset_timeout`
+    pub fn with_passive_health(mut self, config: CircuitBreakerConfig) -> Self {
+        self.breaker_config = config;
+        self
+    }
+
     /// Start health checking
     pub async fn start_health_checks(&self) {
         let backends = self.backends.read().unwrap().clone();
         let health_status = self.health_status.clone();
+        let probed = backends.clone();
+        let unhealthy_threshold = self.health_checker.unhealthy_threshold();
+        let healthy_threshold = self.health_checker.healthy_threshold();
 
         self.health_checker.start_checking(backends, move |check: HealthCheck| {
             let mut status = health_status.write().unwrap();
-            status.insert(check.backend_id.clone(), check.status);
+            // Backends removed since the checks started are no longer tracked
+            let health = match status.get_mut(&check.backend_id) {
+                Some(health) => health,
+                None => return,
+            };
+            let previous = health.status;
+            health.record(&check, unhealthy_threshold, healthy_threshold);
+
+            // Probe round trips feed response-time based balancing
+            let responded = check.status != HealthStatus::Unhealthy;
+            if let Some(backend) = probed.iter().find(|b| responded && b.id == check.backend_id) {
+                backend.record_response_time(Duration::from_millis(check.latency_ms));
+            }
             
+            if health.status != previous {
+                tracing::info!("Backend {} is now {:?}", check.backend_id, health.status);
+            }
             tracing::debug!(
                 "Health check: {} = {:?} ({}ms)",
                 check.backend_id,
@@ -57,17 +89,29 @@ impl LoadBalancer {
         }).await;
     }
 
+    /// Health of every backend, in backend order
+    pub fn health_report(&self) -> Vec<(Arc<Backend>, BackendHealth)> {
+        let backends = self.backends.read().unwrap();
+        let health_status = self.health_status.read().unwrap();
+
+        backends.iter()
+            .map(|b| (b.clone(), health_status.get(&b.id).cloned().unwrap_or_default()))
+            .collect()
+    }
+
     /// Select a backend for a request
     pub fn select_backend(&self, key: Option<&str>) -> Option<Arc<Backend>> {
+        self.reinstate_ejected();
+
         let backends = self.backends.read().unwrap();
         let health_status = self.health_status.read().unwrap();
 
-        // Filter only healthy backends
+        // Filter out unhealthy backends, whatever the algorithm
         let healthy_backends: Vec<Arc<Backend>> = backends
             .iter()
             .filter(|b| {
                 health_status.get(&b.id)
-                    .map(|s| *s == HealthStatus::Healthy || *s == HealthStatus::Degraded)
+                    .map(|h| h.status != HealthStatus::Unhealthy)
                     .unwrap_or(false)
             })
             .cloned()
@@ -81,12 +125,77 @@ impl LoadBalancer {
         self.algorithm.select(&healthy_backends, key)
     }
 
+    /// Record how long a backend took to answer a request
+    pub fn record_response_time(&self, backend_id: &str, elapsed: Duration) {
+        let backends = self.backends.read().unwrap();
+        if let Some(backend) = backends.iter().find(|b| b.id == backend_id) {
+            backend.record_response_time(elapsed);
+        }
+    }
+
+    /// Report a failed send or receive on a backend
+    ///
+    /// Once the failures reach the ejection threshold the backend is marked
+    /// Unhealthy right away, without waiting for the next active probe.
+    pub fn report_failure(&self, backend_id: &str) {
+        let breaker = self.breaker(backend_id);
+        breaker.record_failure();
+        if breaker.state() != State::Open {
+            return;
+        }
+
+        let mut health_status = self.health_status.write().unwrap();
+        if let Some(health) = health_status.get_mut(backend_id) {
+            if health.status != HealthStatus::Unhealthy {
+                tracing::warn!("Backend {} ejected after repeated failures", backend_id);
+            }
+            health.status = HealthStatus::Unhealthy;
+        }
+    }
+
+    /// Report a successful exchange with a backend
+    pub fn report_success(&self, backend_id: &str) {
+        self.breaker(backend_id).record_success();
+    }
+
+    fn breaker(&self, backend_id: &str) -> Arc<CircuitBreaker> {
+        if let Some(breaker) = self.breakers.read().unwrap().get(backend_id) {
+            return breaker.clone();
+        }
+        self.breakers.write().unwrap()
+            .entry(backend_id.to_string())
+            .or_insert_with(|| Arc::new(CircuitBreaker::new(self.breaker_config.clone())))
+            .clone()
+    }
+
+    /// Give ejected backends whose cooldown passed a trial; another failure
+    /// ejects them again, enough successes close their breaker
+    fn reinstate_ejected(&self) {
+        let breakers = self.breakers.read().unwrap();
+        let cooled_down: Vec<&String> = breakers.iter()
+            .filter(|(_, breaker)| breaker.state() == State::Open && breaker.allow_request())
+            .map(|(id, _)| id)
+            .collect();
+        if cooled_down.is_empty() {
+            return;
+        }
+
+        let mut health_status = self.health_status.write().unwrap();
+        for id in cooled_down {
+            if let Some(health) = health_status.get_mut(id) {
+                tracing::info!("Backend {} reinstated after cooldown", id);
+                health.status = HealthStatus::Healthy;
+                health.consecutive_failures = 0;
+            }
+        }
+    }
+
     /// Add a backend
     pub fn add_backend(&self, backend: Backend) {
         let mut backends = self.backends.write().unwrap();
         let mut health_status = self.health_status.write().unwrap();
         
-        health_status.insert(backend.id.clone(), HealthStatus::Healthy);
+        health_status.insert(backend.id.clone(), BackendHealth::default());
         backends.push(Arc::new(backend));
     }
 
@@ -97,6 +206,7 @@ impl LoadBalancer {
         
         backends.retain(|b| b.id != backend_id);
         health_status.remove(backend_id);
+        self.breakers.write().unwrap().remove(backend_id);
     }
 
     /// Get backend count
@@ -112,7 +222,7 @@ impl LoadBalancer {
         backends.iter()
             .filter(|b| {
                 health_status.get(&b.id)
-                    .map(|s| *s == HealthStatus::Healthy)
+                    .map(|h| h.status == HealthStatus::Healthy)
                     .unwrap_or(false)
             })
             .count()
@@ -178,4 +288,125 @@ mod tests {
         lb.remove_backend("b1");
         assert_eq!(lb.backend_count(), 1);
     }
+
+    #[test]
+    fn test_response_times_shift_traffic() {
+        use crate::load_balancer::LeastResponseTime;
+
+        let lb = LoadBalancer::new(
+            vec![
+                Backend::new("fast", "127.0.0.1:8001", 1),
+                Backend::new("slow", "127.0.0.1:8002", 1),
+            ],
+            Arc::new(LeastResponseTime::new()),
+            HealthChecker::default(),
+        );
+
+        let mut slow_picks = 0;
+        for _ in 0..100 {
+            let backend = lb.select_backend(None).unwrap();
+            let elapsed = if backend.id == "slow" {
+                slow_picks += 1;
+                Duration::from_millis(250)
+            } else {
+                Duration::from_millis(5)
+            };
+            lb.record_response_time(&backend.id, elapsed);
+        }
+
+        assert_eq!(slow_picks, 1);
+        let report = lb.health_report();
+        assert_eq!(report[1].0.response_time(), Some(Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn test_passive_ejection_and_reinstatement() {
+        let lb = LoadBalancer::new(
+            vec![
+                Backend::new("b1", "127.0.0.1:8001", 1),
+                Backend::new("b2", "127.0.0.1:8002", 1),
+            ],
+            Arc::new(RoundRobin::new()),
+            HealthChecker::default(),
+        ).with_passive_health(CircuitBreakerConfig {
+            failure_threshold: 3,
+            reset_timeout: Duration::from_millis(100),
+            success_threshold: 1,
+            ..Default::default()
+        });
+
+        // A success in between resets the count
+        lb.report_failure("b2");
+        lb.report_failure("b2");
+        lb.report_success("b2");
+        lb.report_failure("b2");
+        lb.report_failure("b2");
+        assert_eq!(lb.healthy_backend_count(), 2);
+
+        lb.report_failure("b2");
+        assert_eq!(lb.healthy_backend_count(), 1);
+        assert_eq!(lb.health_report()[1].1.status, HealthStatus::Unhealthy);
+        for _ in 0..10 {
+            assert_eq!(lb.select_backend(None).unwrap().id, "b1");
+        }
+
+        // After the cooldown b2 is on trial; one failure ejects it again
+        std::thread::sleep(Duration::from_millis(150));
+        lb.select_backend(None);
+        assert_eq!(lb.healthy_backend_count(), 2);
+        lb.report_failure("b2");
+        assert_eq!(lb.healthy_backend_count(), 1);
+
+        // A successful trial reinstates it for good
+        std::thread::sleep(Duration::from_millis(150));
+        let picks: Vec<String> = (0..4).map(|_| lb.select_backend(None).unwrap().id.clone()).collect();
+        assert!(picks.iter().any(|id| id == "b2"), "{:?}", picks);
+        lb.report_success("b2");
+        lb.report_failure("b2");
+        assert_eq!(lb.healthy_backend_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_probes_route_around_dead_backend() {
+        use crate::server::Server;
+
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        let live_addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let _ = server.accept().await;
+            }
+        });
+
+        // Bound and released, so nothing answers on it
+        let dead_addr = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+
+        let lb = LoadBalancer::new(
+            vec![
+                Backend::new("live", live_addr.to_string(), 1),
+                Backend::new("dead", dead_addr.to_string(), 1),
+            ],
+            Arc::new(RoundRobin::new()),
+            HealthChecker::new(Duration::from_millis(100), Duration::from_millis(300)).with_thresholds(2, 1),
+        );
+        lb.start_health_checks().await;
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while lb.healthy_backend_count() != 1 {
+            assert!(tokio::time::Instant::now() < deadline, "dead backend never marked unhealthy");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let report = lb.health_report();
+        assert_eq!(report[0].0.id, "live");
+        assert_eq!(report[0].1.status, HealthStatus::Healthy);
+        assert!(report[0].1.latency_ms.is_some());
+        assert_eq!(report[1].0.id, "dead");
+        assert_eq!(report[1].1.status, HealthStatus::Unhealthy);
+        assert!(report[1].1.consecutive_failures >= 2);
+
+        for _ in 0..10 {
+            assert_eq!(lb.select_backend(None).unwrap().id, "live");
+        }
+    }
 }
@@ -1,6 +1,9 @@
 //! Health Checking
 
 use super::Backend;
+use crate::config::ConnectionConfig;
+use crate::connection::Connection;
+use jsp_core::types::control::CloseReason;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::time::interval;
@@ -22,10 +25,63 @@ pub struct HealthCheck {
     pub last_check: Instant,
 }
 
+/// Health of one backend, accumulated over probes
+#[derive(Debug, Clone)]
+pub struct BackendHealth {
+    pub status: HealthStatus,
+    /// Latency of the last successful probe
+    pub latency_ms: Option<u64>,
+    pub last_check: Option<Instant>,
+    pub consecutive_failures: u32,
+    pub consecutive_successes: u32,
+}
+
+impl Default for BackendHealth {
+    fn default() -> Self {
+        Self {
+            status: HealthStatus::Healthy,
+            latency_ms: None,
+            last_check: None,
+            consecutive_failures: 0,
+            consecutive_successes: 0,
+        }
+    }
+}
+
+impl BackendHealth {
+    /// Apply a probe result
+    ///
+    /// A backend turns Unhealthy after `unhealthy_threshold` failed probes in a
+    /// row and recovers after `healthy_threshold` successful ones.
+    pub fn record(&mut self, check: &HealthCheck, unhealthy_threshold: u32, healthy_threshold: u32) {
+        self.last_check = Some(check.last_check);
+
+        if check.status == HealthStatus::Unhealthy {
+            self.consecutive_failures += 1;
+            self.consecutive_successes = 0;
+            if self.consecutive_failures >= unhealthy_threshold {
+                self.status = HealthStatus::Unhealthy;
+            }
+            return;
+        }
+
+        self.latency_ms = Some(check.latency_ms);
+        self.consecutive_failures = 0;
+        self.consecutive_successes += 1;
+        if self.status != HealthStatus::Unhealthy || self.consecutive_successes >= healthy_threshold {
+            self.status = check.status;
+        }
+    }
+}
+
 /// Health checker
+///
+/// Probes each backend with a JetStream handshake.
 pub struct HealthChecker {
     check_interval: Duration,
     timeout: Duration,
+    unhealthy_threshold: u32,
+    healthy_threshold: u32,
 }
 
 impl HealthChecker {
@@ -34,9 +90,27 @@ impl HealthChecker {
         Self {
             check_interval,
             timeout,
+            unhealthy_threshold: 3,
+            healthy_threshold: 2,
         }
     }
 
+    /// Consecutive failed probes before a backend is Unhealthy, and
+    /// successful ones before it is healthy again
+    pub fn with_thresholds(mut self, unhealthy_threshold: u32, healthy_threshold: u32) -> Self {
+        self.unhealthy_threshold = unhealthy_threshold.max(1);
+        self.healthy_threshold = healthy_threshold.max(1);
+        self
+    }
+
+    pub fn unhealthy_threshold(&self) -> u32 {
+        self.unhealthy_threshold
+    }
+
+    pub fn healthy_threshold(&self) -> u32 {
+        self.healthy_threshold
+    }
+
     /// Start health checking for backends
     pub async fn start_checking(
         &self,
@@ -70,13 +144,7 @@ impl HealthChecker {
 
     /// Check a single backend
     async fn check_backend(backend: &Backend, timeout: Duration) -> HealthStatus {
-        // Simplified health check - in production, this would make actual TCP/HTTP requests
-        let check_result = tokio::time::timeout(timeout, async {
-            // Simulate health check
-            tokio::time::sleep(Duration::from_millis(10)).await;
-            Ok::<_, ()>(())
-        })
-        .await;
+        let check_result = tokio::time::timeout(timeout, Self::probe(&backend.address)).await;
 
         match check_result {
             Ok(Ok(_)) => {
@@ -87,10 +155,21 @@ impl HealthChecker {
                     HealthStatus::Healthy
                 }
             }
-            Ok(Err(_)) => HealthStatus::Unhealthy,
+            Ok(Err(e)) => {
+                tracing::debug!(backend = %backend.id, "Health probe failed: {}", e);
+                HealthStatus::Unhealthy
+            }
             Err(_) => HealthStatus::Unhealthy, // Timeout
         }
     }
+
+    /// Handshake with the backend and close the connection again
+    async fn probe(address: &str) -> anyhow::Result<()> {
+        let mut conn = Connection::connect_with_config(address, ConnectionConfig::default()).await?;
+        conn.handshake().await?;
+        conn.close(CloseReason::Normal, None).await?;
+        Ok(())
+    }
 }
 
 impl Default for HealthChecker {
@@ -102,26 +181,75 @@ impl Default for HealthChecker {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::server::Server;
+
+    /// Start a JetStream server that answers handshakes, returning its address
+    async fn spawn_live_backend() -> String {
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let _ = server.accept().await;
+            }
+        });
+        addr.to_string()
+    }
+
+    /// An address nothing listens on
+    async fn closed_port() -> String {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.local_addr().unwrap().to_string()
+    }
+
+    fn check(status: HealthStatus) -> HealthCheck {
+        HealthCheck {
+            backend_id: "test".to_string(),
+            status,
+            latency_ms: 1,
+            last_check: Instant::now(),
+        }
+    }
 
     #[tokio::test]
     async fn test_health_checker() {
-        let checker = HealthChecker::default();
-        let backend = Arc::new(Backend::new("test", "127.0.0.1:8000", 1));
-        
-        let status = HealthChecker::check_backend(&backend, Duration::from_secs(1)).await;
+        let backend = Arc::new(Backend::new("test", spawn_live_backend().await, 1));
+
+        let status = HealthChecker::check_backend(&backend, Duration::from_secs(2)).await;
         assert_eq!(status, HealthStatus::Healthy);
+
+        let backend = Arc::new(Backend::new("dead", closed_port().await, 1));
+        let status = HealthChecker::check_backend(&backend, Duration::from_millis(500)).await;
+        assert_eq!(status, HealthStatus::Unhealthy);
     }
 
     #[tokio::test]
     async fn test_degraded_status() {
-        let backend = Arc::new(Backend::new("test", "127.0.0.1:8000", 1));
-        
+        let backend = Arc::new(Backend::new("test", spawn_live_backend().await, 1));
+
         // Simulate high connection count
         for _ in 0..101 {
             backend.inc_connections();
         }
-        
-        let status = HealthChecker::check_backend(&backend, Duration::from_secs(1)).await;
+
+        let status = HealthChecker::check_backend(&backend, Duration::from_secs(2)).await;
         assert_eq!(status, HealthStatus::Degraded);
     }
+
+    #[test]
+    fn test_status_thresholds() {
+        let mut health = BackendHealth::default();
+
+        health.record(&check(HealthStatus::Unhealthy), 3, 2);
+        health.record(&check(HealthStatus::Unhealthy), 3, 2);
+        assert_eq!(health.status, HealthStatus::Healthy);
+        health.record(&check(HealthStatus::Unhealthy), 3, 2);
+        assert_eq!(health.status, HealthStatus::Unhealthy);
+
+        health.record(&check(HealthStatus::Healthy), 3, 2);
+        assert_eq!(health.status, HealthStatus::Unhealthy);
+        health.record(&check(HealthStatus::Healthy), 3, 2);
+        assert_eq!(health.status, HealthStatus::Healthy);
+        assert_eq!(health.consecutive_failures, 0);
+        assert_eq!(health.latency_ms, Some(1));
+    }
 }
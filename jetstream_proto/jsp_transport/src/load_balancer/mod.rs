@@ -7,8 +7,8 @@ pub mod health;
 pub mod balancer;
 
 pub use balancer::LoadBalancer;
-pub use algorithms::{BalancingAlgorithm, RoundRobin, LeastConnections, WeightedRoundRobin, ConsistentHash};
-pub use health::{HealthChecker, HealthStatus};
+pub use algorithms::{BalancingAlgorithm, RoundRobin, LeastConnections, WeightedRoundRobin, ConsistentHash, HashRing, LeastResponseTime};
+pub use health::{HealthChecker, HealthStatus, BackendHealth};
 
 /// Backend server
 #[derive(Debug, Clone)]
@@ -17,8 +17,13 @@ pub struct Backend {
     pub address: String,
     pub weight: u32,
     pub active_connections: std::sync::Arc<std::sync::atomic::AtomicU32>,
+    /// EWMA of response times in microseconds, 0 until the first sample
+    pub response_time_us: std::sync::Arc<std::sync::atomic::AtomicU64>,
 }
 
+/// Weight of a new sample in the response time EWMA
+const RESPONSE_TIME_ALPHA: f64 = 0.3;
+
 impl Backend {
     /// Create a new backend
     pub fn new(id: impl Into<String>, address: impl Into<String>, weight: u32) -> Self {
@@ -27,6 +32,7 @@ impl Backend {
             address: address.into(),
             weight,
             active_connections: std::sync::Arc::new(std::sync::atomic::AtomicU32::new(0)),
+            response_time_us: std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0)),
         }
     }
 
@@ -44,4 +50,29 @@ impl Backend {
     pub fn connections(&self) -> u32 {
         self.active_connections.load(std::sync::atomic::Ordering::Relaxed)
     }
+
+    /// Fold a response time sample into the moving average
+    pub fn record_response_time(&self, elapsed: std::time::Duration) {
+        // At least 1us, so a recorded backend never reads as unmeasured
+        let sample = (elapsed.as_micros() as u64).max(1);
+        let _ = self.response_time_us.fetch_update(
+            std::sync::atomic::Ordering::Relaxed,
+            std::sync::atomic::Ordering::Relaxed,
+            |current| {
+                if current == 0 {
+                    return Some(sample);
+                }
+                let average = RESPONSE_TIME_ALPHA * sample as f64 + (1.0 - RESPONSE_TIME_ALPHA) * current as f64;
+                Some((average as u64).max(1))
+            },
+        );
+    }
+
+    /// Average response time, if any was recorded
+    pub fn response_time(&self) -> Option<std::time::Duration> {
+        match self.response_time_us.load(std::sync::atomic::Ordering::Relaxed) {
+            0 => None,
+            us => Some(std::time::Duration::from_micros(us)),
+        }
+    }
 }
@@ -1,4 +1,8 @@
 use std::sync::{Arc, Mutex};
+use bytes::Bytes;
+
+/// Size of the buffers datagrams are received into
+pub const RECV_BUFFER_SIZE: usize = 2048;
 
 /// A simple memory pool for reusable byte buffers
 /// 
@@ -86,6 +90,14 @@ impl PacketPool {
         // Otherwise, buffer is dropped
     }
 
+    /// Hand out a filled buffer as `Bytes`
+    ///
+    /// Slices of it share the allocation; the buffer goes back to the pool
+    /// once the last of them is dropped.
+    pub fn freeze(&self, buffer: Vec<u8>) -> Bytes {
+        Bytes::from_owner(PooledBuffer { buffer, pool: self.clone() })
+    }
+
     /// Get current pool metrics
     pub fn metrics(&self) -> PoolMetrics {
         let inner = self.inner.lock().unwrap();
@@ -103,6 +115,24 @@ impl PacketPool {
     }
 }
 
+/// Buffer shared through `Bytes`, released to its pool on drop
+struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: PacketPool,
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.release(std::mem::take(&mut self.buffer));
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -188,6 +218,26 @@ mod tests {
         assert_eq!(pool.hit_rate(), 50.0); // 1 hit out of 2 acquires
     }
 
+    #[test]
+    fn test_frozen_buffer_returns_when_slices_drop() {
+        let pool = PacketPool::new(10, 1500);
+
+        let mut buffer = pool.acquire();
+        buffer.extend_from_slice(b"headerpayload");
+        let data = pool.freeze(buffer);
+        let payload = data.slice(6..);
+        drop(data);
+        assert_eq!(&payload[..], b"payload");
+        assert_eq!(pool.metrics().total_released, 0);
+
+        drop(payload);
+        assert_eq!(pool.metrics().total_released, 1);
+
+        // The next acquire reuses it
+        let _buffer = pool.acquire();
+        assert_eq!(pool.metrics().total_allocated, 1);
+    }
+
     #[test]
     fn test_concurrent_access() {
         use std::thread;
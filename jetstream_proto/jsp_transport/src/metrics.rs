@@ -1,5 +1,16 @@
+use std::collections::VecDeque;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
 use std::fmt;
+use serde::{Deserialize, Serialize};
+use jsp_core::types::delivery::DeliveryMode;
+use crate::circuit_breaker::State as CircuitState;
+use crate::congestion::CongestionState;
+use crate::compression::adaptive::CompressionDecision;
+
+/// Number of recent RTT samples kept for percentiles
+const RTT_WINDOW: usize = 1024;
 
 /// Collection of metrics for a connection or server
 #[derive(Debug, Default)]
@@ -13,16 +24,25 @@ pub struct Metrics {
     // Reliability
     pub packets_lost: AtomicU64,
     pub packets_retransmitted: AtomicU64,
+    pub spurious_retransmits: AtomicU64,
     pub duplicate_packets_received: AtomicU64,
     
     // Performance
     pub rtt_ms: AtomicU64, // Current RTT in milliseconds
+    smoothed_rtt_us: AtomicU64, // EWMA of RTT samples in microseconds
+    rtt_samples: Mutex<VecDeque<u64>>, // Recent RTT samples in microseconds
     pub congestion_window: AtomicU64, // Current cwnd in bytes
+    migration_duration_us: AtomicU64, // Last migration until its first ACK, 0 if none
     
     // Errors
     pub connection_errors: AtomicU64,
     pub timeouts: AtomicU64,
     pub circuit_breaker_trips: AtomicU64,
+    pub auth_failures: AtomicU64,
+    
+    // Admission (server side)
+    pub handshakes_rejected: AtomicU64,
+    pub packets_dropped: AtomicU64,
 }
 
 impl Metrics {
@@ -45,6 +65,16 @@ impl Metrics {
         self.bytes_sent.fetch_add(size as u64, Ordering::Relaxed); // Retransmits count as sent bytes
     }
 
+    pub fn record_spurious_retransmit(&self) {
+        self.spurious_retransmits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Set retransmission counters tracked elsewhere (e.g. by the reliability layer)
+    pub fn set_retransmits(&self, retransmitted: u64, spurious: u64) {
+        self.packets_retransmitted.store(retransmitted, Ordering::Relaxed);
+        self.spurious_retransmits.store(spurious, Ordering::Relaxed);
+    }
+
     pub fn record_loss(&self) {
         self.packets_lost.fetch_add(1, Ordering::Relaxed);
     }
@@ -57,10 +87,34 @@ impl Metrics {
         self.rtt_ms.store(rtt_ms, Ordering::Relaxed);
     }
 
+    /// Record an ACK-derived RTT sample
+    pub fn record_rtt_sample(&self, rtt: Duration) {
+        let sample_us = rtt.as_micros() as u64;
+        
+        let mut samples = self.rtt_samples.lock().unwrap();
+        let smoothed_us = if samples.is_empty() {
+            sample_us
+        } else {
+            (self.smoothed_rtt_us.load(Ordering::Relaxed) * 7 + sample_us) / 8
+        };
+        if samples.len() == RTT_WINDOW {
+            samples.pop_front();
+        }
+        samples.push_back(sample_us);
+        
+        self.smoothed_rtt_us.store(smoothed_us, Ordering::Relaxed);
+        self.rtt_ms.store(smoothed_us / 1000, Ordering::Relaxed);
+    }
+
     pub fn update_cwnd(&self, cwnd: u64) {
         self.congestion_window.store(cwnd, Ordering::Relaxed);
     }
 
+    /// Record how long the last migration took, from the switch to the first ACK on the new path
+    pub fn record_migration(&self, duration: Duration) {
+        self.migration_duration_us.store((duration.as_micros() as u64).max(1), Ordering::Relaxed);
+    }
+
     pub fn record_error(&self) {
         self.connection_errors.fetch_add(1, Ordering::Relaxed);
     }
@@ -73,31 +127,87 @@ impl Metrics {
         self.circuit_breaker_trips.fetch_add(1, Ordering::Relaxed);
     }
 
-    pub fn get_avg_rtt(&self) -> std::time::Duration {
-        std::time::Duration::from_millis(self.rtt_ms.load(Ordering::Relaxed))
+    /// A sealed packet whose payload or header failed authentication
+    pub fn record_auth_failure(&self) {
+        self.auth_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A ClientHello turned away by admission limits
+    pub fn record_handshake_rejected(&self) {
+        self.handshakes_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A datagram discarded before parsing (banned or flooding source)
+    pub fn record_packet_dropped(&self) {
+        self.packets_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_avg_rtt(&self) -> Duration {
+        match self.smoothed_rtt_us.load(Ordering::Relaxed) {
+            0 => Duration::from_millis(self.rtt_ms.load(Ordering::Relaxed)),
+            us => Duration::from_micros(us),
+        }
+    }
+
+    /// RTT percentile (0.0..=1.0) over recent samples, in milliseconds
+    pub fn rtt_percentile_ms(&self, percentile: f64) -> f64 {
+        let mut samples: Vec<u64> = self.rtt_samples.lock().unwrap().iter().copied().collect();
+        if samples.is_empty() {
+            return 0.0;
+        }
+        samples.sort_unstable();
+        let rank = ((samples.len() - 1) as f64 * percentile.clamp(0.0, 1.0)).round() as usize;
+        samples[rank] as f64 / 1000.0
     }
 
     /// Get a snapshot of the current metrics
     pub fn snapshot(&self) -> MetricsSnapshot {
+        let packets_sent = self.packets_sent.load(Ordering::Relaxed);
+        let packets_retransmitted = self.packets_retransmitted.load(Ordering::Relaxed);
+        let spurious_retransmits = self.spurious_retransmits.load(Ordering::Relaxed);
+        let loss_rate = if packets_sent == 0 {
+            0.0
+        } else {
+            (packets_retransmitted.saturating_sub(spurious_retransmits) as f64 / packets_sent as f64).min(1.0)
+        };
+        
         MetricsSnapshot {
-            packets_sent: self.packets_sent.load(Ordering::Relaxed),
+            packets_sent,
             packets_received: self.packets_received.load(Ordering::Relaxed),
             bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
             bytes_received: self.bytes_received.load(Ordering::Relaxed),
             packets_lost: self.packets_lost.load(Ordering::Relaxed),
-            packets_retransmitted: self.packets_retransmitted.load(Ordering::Relaxed),
+            packets_retransmitted,
+            spurious_retransmits,
+            loss_rate,
             duplicate_packets_received: self.duplicate_packets_received.load(Ordering::Relaxed),
             rtt_ms: self.rtt_ms.load(Ordering::Relaxed),
+            rtt_p50_ms: self.rtt_percentile_ms(0.50),
+            rtt_p95_ms: self.rtt_percentile_ms(0.95),
             congestion_window: self.congestion_window.load(Ordering::Relaxed),
+            congestion_state: None,
+            compression: None,
+            circuit_state: None,
+            send_queue_packets: 0,
+            send_queue_bytes: 0,
+            send_queue_dropped: 0,
+            send_queue_expired: 0,
+            migration_duration: match self.migration_duration_us.load(Ordering::Relaxed) {
+                0 => None,
+                us => Some(Duration::from_micros(us)),
+            },
             connection_errors: self.connection_errors.load(Ordering::Relaxed),
             timeouts: self.timeouts.load(Ordering::Relaxed),
             circuit_breaker_trips: self.circuit_breaker_trips.load(Ordering::Relaxed),
+            auth_failures: self.auth_failures.load(Ordering::Relaxed),
+            handshakes_rejected: self.handshakes_rejected.load(Ordering::Relaxed),
+            packets_dropped: self.packets_dropped.load(Ordering::Relaxed),
         }
     }
 }
 
 /// A snapshot of metrics values (immutable)
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct MetricsSnapshot {
     pub packets_sent: u64,
     pub packets_received: u64,
@@ -105,12 +215,59 @@ pub struct MetricsSnapshot {
     pub bytes_received: u64,
     pub packets_lost: u64,
     pub packets_retransmitted: u64,
+    pub spurious_retransmits: u64,
+    /// Fraction of sent packets lost, excluding spurious retransmits
+    pub loss_rate: f64,
     pub duplicate_packets_received: u64,
     pub rtt_ms: u64,
+    pub rtt_p50_ms: f64,
+    pub rtt_p95_ms: f64,
     pub congestion_window: u64,
+    /// State of the congestion controller; set by `Connection::metrics()`
+    pub congestion_state: Option<CongestionState>,
+    /// Adaptive compression's current decision; set by `Connection::metrics()`
+    pub compression: Option<CompressionDecision>,
+    /// State of the circuit breaker guarding sends; set by `Connection::metrics()`
+    pub circuit_state: Option<CircuitState>,
+    /// Packets waiting in the send queue; this and the other send queue
+    /// fields are set by `Connection::metrics()`
+    pub send_queue_packets: u64,
+    /// Stream payload bytes waiting in the send queue
+    pub send_queue_bytes: u64,
+    /// BestEffort packets the full send queue dropped
+    pub send_queue_dropped: u64,
+    /// PartiallyReliable packets dropped from the send queue past their TTL
+    pub send_queue_expired: u64,
+    /// Time from the last migration to the first ACK on the new path
+    pub migration_duration: Option<Duration>,
     pub connection_errors: u64,
     pub timeouts: u64,
     pub circuit_breaker_trips: u64,
+    /// Sealed packets dropped because their payload or header failed authentication
+    pub auth_failures: u64,
+    /// ClientHellos turned away by admission limits
+    pub handshakes_rejected: u64,
+    /// Datagrams discarded before parsing
+    pub packets_dropped: u64,
+}
+
+/// Traffic of one stream; see `Connection::stream_stats`
+///
+/// Bytes and messages count application data as passed to `send_on_stream`
+/// and handed out by `recv`, without headers, fragmentation or encryption.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamStats {
+    pub stream_id: u32,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    /// Packets of this stream sent again because they went unacknowledged
+    pub retransmits: u64,
+    /// Mode the stream was opened with, by us or else by the peer
+    pub delivery_mode: DeliveryMode,
+    /// Priority the stream was opened with; None if only the peer opened it
+    pub priority: Option<u8>,
 }
 
 impl fmt::Display for MetricsSnapshot {
@@ -121,15 +278,40 @@ impl fmt::Display for MetricsSnapshot {
         writeln!(f, "  Recv: {} pkts / {} bytes", self.packets_received, self.bytes_received)?;
         writeln!(f, "Reliability:")?;
         writeln!(f, "  Lost: {}", self.packets_lost)?;
-        writeln!(f, "  Retransmitted: {}", self.packets_retransmitted)?;
+        writeln!(f, "  Retransmitted: {} ({} spurious)", self.packets_retransmitted, self.spurious_retransmits)?;
+        writeln!(f, "  Loss rate: {:.2}%", self.loss_rate * 100.0)?;
         writeln!(f, "  Duplicates: {}", self.duplicate_packets_received)?;
         writeln!(f, "Performance:")?;
-        writeln!(f, "  RTT: {} ms", self.rtt_ms)?;
+        writeln!(f, "  RTT: {} ms (p50 {:.2} ms, p95 {:.2} ms)", self.rtt_ms, self.rtt_p50_ms, self.rtt_p95_ms)?;
         writeln!(f, "  Cwnd: {} bytes", self.congestion_window)?;
+        if let Some(state) = self.congestion_state {
+            writeln!(f, "  Congestion state: {:?}", state)?;
+        }
+        if let Some(decision) = self.compression {
+            match decision.algorithm {
+                Some(algorithm) => writeln!(f, "  Compression: {:?} level {} ({})", algorithm, decision.level, decision.reason.as_str())?,
+                None => writeln!(f, "  Compression: off ({})", decision.reason.as_str())?,
+            }
+        }
+        if let Some(duration) = self.migration_duration {
+            writeln!(f, "  Last migration: {:.2} ms", duration.as_secs_f64() * 1000.0)?;
+        }
+        writeln!(f, "Send queue:")?;
+        writeln!(f, "  Depth: {} pkts / {} bytes", self.send_queue_packets, self.send_queue_bytes)?;
+        writeln!(f, "  Dropped: {} best effort, {} expired", self.send_queue_dropped, self.send_queue_expired)?;
         writeln!(f, "Errors:")?;
         writeln!(f, "  Errors: {}", self.connection_errors)?;
         writeln!(f, "  Timeouts: {}", self.timeouts)?;
         writeln!(f, "  CB Trips: {}", self.circuit_breaker_trips)?;
+        if let Some(state) = self.circuit_state {
+            writeln!(f, "  CB State: {}", state.as_str())?;
+        }
+        writeln!(f, "  Auth failures: {}", self.auth_failures)?;
+        if self.handshakes_rejected > 0 || self.packets_dropped > 0 {
+            writeln!(f, "Admission:")?;
+            writeln!(f, "  Handshakes rejected: {}", self.handshakes_rejected)?;
+            writeln!(f, "  Packets dropped: {}", self.packets_dropped)?;
+        }
         Ok(())
     }
 }
@@ -167,4 +349,31 @@ mod tests {
         metrics.update_rtt(45);
         assert_eq!(metrics.snapshot().rtt_ms, 45);
     }
+
+    #[test]
+    fn test_rtt_samples() {
+        let metrics = Metrics::new();
+        assert_eq!(metrics.snapshot().rtt_p50_ms, 0.0);
+        
+        for ms in 1..=100 {
+            metrics.record_rtt_sample(Duration::from_millis(ms));
+        }
+        
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.rtt_p50_ms, 51.0);
+        assert_eq!(snapshot.rtt_p95_ms, 95.0);
+        // The smoothed RTT trails recent samples
+        assert!(snapshot.rtt_ms > 50 && snapshot.rtt_ms < 100);
+        assert_eq!(metrics.get_avg_rtt().as_millis() as u64, snapshot.rtt_ms);
+    }
+
+    #[test]
+    fn test_migration_duration() {
+        let metrics = Metrics::new();
+        assert_eq!(metrics.snapshot().migration_duration, None);
+        
+        metrics.record_migration(Duration::from_millis(12));
+        assert_eq!(metrics.snapshot().migration_duration, Some(Duration::from_millis(12)));
+        assert!(metrics.snapshot().to_string().contains("Last migration: 12.00 ms"));
+    }
 }
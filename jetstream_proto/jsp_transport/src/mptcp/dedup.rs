@@ -0,0 +1,75 @@
+//! Receive-side duplicate filter for redundant scheduling
+
+/// Number of sequence numbers tracked behind the highest one seen
+pub const DEDUP_WINDOW_SIZE: u64 = 128;
+
+/// Sliding bitmap of recently received sequence numbers
+///
+/// Memory is constant regardless of session length. Sequence numbers older
+/// than the window are let through; the reliability layer still discards
+/// them if they were already delivered.
+#[derive(Debug, Default, Clone)]
+pub struct DedupWindow {
+    /// Highest sequence number seen so far
+    highest: u64,
+    /// Bit `n` set means `highest - n` was received
+    bitmap: u128,
+}
+
+impl DedupWindow {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a sequence number, returning false if it is a duplicate
+    pub fn insert(&mut self, seq: u64) -> bool {
+        if seq > self.highest {
+            let shift = seq - self.highest;
+            self.bitmap = if shift >= DEDUP_WINDOW_SIZE { 0 } else { self.bitmap << shift };
+            self.bitmap |= 1;
+            self.highest = seq;
+            return true;
+        }
+
+        let offset = self.highest - seq;
+        if offset >= DEDUP_WINDOW_SIZE {
+            return true;
+        }
+
+        let bit = 1u128 << offset;
+        if self.bitmap & bit != 0 {
+            return false;
+        }
+        self.bitmap |= bit;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicates_dropped() {
+        let mut window = DedupWindow::new();
+        assert!(window.insert(1));
+        assert!(window.insert(3));
+        assert!(!window.insert(1));
+        assert!(!window.insert(3));
+        // Out of order but new
+        assert!(window.insert(2));
+        assert!(!window.insert(2));
+    }
+
+    #[test]
+    fn test_window_slides() {
+        let mut window = DedupWindow::new();
+        for seq in 1..=1000 {
+            assert!(window.insert(seq));
+            assert!(!window.insert(seq));
+        }
+        // Too old to track: left to the reliability layer
+        assert!(window.insert(1000 - DEDUP_WINDOW_SIZE));
+        assert!(!window.insert(1000 - DEDUP_WINDOW_SIZE + 1));
+    }
+}
@@ -1,61 +1,85 @@
 //! MPTCP Manager
 
-use super::{MptcpConfig, Subflow, InterfaceWatcher, Scheduler, create_scheduler};
+use super::{MptcpConfig, Subflow, SubflowHandshake, InterfaceWatcher, Scheduler, create_scheduler};
+use std::collections::BTreeMap;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Sent sequence numbers remembered for RTT attribution; older ones are
+/// forgotten when packets are lost or never ACKed
+const MAX_TRACKED_SEQUENCES: usize = 4096;
 
 pub struct MptcpManager {
     config: MptcpConfig,
     subflows: Arc<Mutex<Vec<Arc<Subflow>>>>,
+    next_subflow_id: Arc<AtomicU32>,
     watcher: InterfaceWatcher,
     scheduler: Box<dyn Scheduler>,
     remote_addr: SocketAddr,
+    // Sequence number -> subflow that carried it
+    sent_on: Mutex<BTreeMap<u64, u32>>,
+    // Validation new subflows go through before carrying traffic
+    handshake: Option<Arc<SubflowHandshake>>,
 }
 
 impl MptcpManager {
     pub fn new(config: MptcpConfig, remote_addr: SocketAddr) -> Self {
         let scheduler = create_scheduler(config.scheduler_algo);
-        
+
         Self {
             config,
             subflows: Arc::new(Mutex::new(Vec::new())),
+            next_subflow_id: Arc::new(AtomicU32::new(0)),
             watcher: InterfaceWatcher::new(),
             scheduler,
             remote_addr,
+            sent_on: Mutex::new(BTreeMap::new()),
+            handshake: None,
         }
     }
 
+    /// Have every subflow opened from now on pass `handshake` before it is used
+    pub fn with_handshake(mut self, handshake: SubflowHandshake) -> Self {
+        self.handshake = Some(Arc::new(handshake));
+        self
+    }
+
     pub async fn start(&self) {
         if !self.config.enabled {
             return;
         }
-        
+
         // Watch for interfaces and create subflows
         let mut rx = self.watcher.subscribe();
         let subflows = self.subflows.clone();
+        let next_subflow_id = self.next_subflow_id.clone();
+        let max_subflows = self.config.max_subflows;
         let remote = self.remote_addr;
-        
+        let handshake = self.handshake.clone();
+
         tokio::spawn(async move {
-            while let Ok(_) = rx.changed().await {
+            while rx.changed().await.is_ok() {
                 let ifaces = rx.borrow().clone();
-                // Simple logic: one subflow per non-loopback interface
+                // One subflow per interface that can reach the remote
                 for iface in ifaces {
-                    if iface.is_loopback { continue; }
-                    
-                    // Check if exists
-                    let exists = {
+                    if iface.is_loopback != remote.ip().is_loopback() || iface.ip.is_ipv4() != remote.is_ipv4() {
+                        continue;
+                    }
+
+                    // Check if exists or the subflow limit is reached
+                    let skip = {
                          let current_subflows = subflows.lock().unwrap();
-                         current_subflows.iter().any(|s| s.local_addr.ip() == iface.ip)
+                         current_subflows.len() >= max_subflows
+                             || current_subflows.iter().any(|s| s.local_addr.ip() == iface.ip)
                     };
 
-                    if !exists {
-                        let id = {
-                            let current_subflows = subflows.lock().unwrap();
-                            current_subflows.len() as u32
-                        };
+                    if !skip {
+                        let id = next_subflow_id.fetch_add(1, Ordering::Relaxed);
                         let local = SocketAddr::new(iface.ip, 0); // Ephemeral port
-                        
-                        match Subflow::new(id, local, remote).await {
+
+                        match open_subflow(id, local, remote, handshake.as_ref()).await {
                             Ok(subflow) => {
                                 tracing::info!("Created new subflow on {}", iface.name);
                                 let mut current_subflows = subflows.lock().unwrap();
@@ -71,13 +95,222 @@ impl MptcpManager {
         });
     }
 
+    /// Open a subflow from an explicit local address
+    ///
+    /// Returns the subflow ID. Fails once `max_subflows` subflows exist.
+    pub async fn add_subflow(&self, local: SocketAddr) -> std::io::Result<u32> {
+        self.add_subflow_to(local, self.remote_addr).await
+    }
+
+    /// Open a subflow from `local` to another address of the peer
+    pub async fn add_subflow_to(&self, local: SocketAddr, remote: SocketAddr) -> std::io::Result<u32> {
+        if self.subflow_count() >= self.config.max_subflows {
+            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Subflow limit reached"));
+        }
+
+        let id = self.next_subflow_id.fetch_add(1, Ordering::Relaxed);
+        let subflow = open_subflow(id, local, remote, self.handshake.as_ref()).await?;
+        tracing::info!(id, local = %subflow.local_addr, %remote, "Created new subflow");
+        self.subflows.lock().unwrap().push(Arc::new(subflow));
+        Ok(id)
+    }
+
+    /// Current subflows
+    pub fn subflows(&self) -> Vec<Arc<Subflow>> {
+        self.subflows.lock().unwrap().clone()
+    }
+
+    pub fn subflow_count(&self) -> usize {
+        self.subflows.lock().unwrap().len()
+    }
+
+    /// Subflows validated by the peer, the ones packets are scheduled on
+    pub fn usable_subflow_count(&self) -> usize {
+        self.subflows.lock().unwrap().iter().filter(|subflow| subflow.is_validated()).count()
+    }
+
+    /// Send on the subflow(s) picked by the scheduler
+    ///
+    /// With redundant scheduling the send succeeds if any subflow accepted it.
     pub async fn send(&self, data: &[u8]) -> std::io::Result<()> {
-        let subflows_lock = self.subflows.lock().unwrap();
-        
-        if let Some(subflow) = self.scheduler.select_subflow(&subflows_lock) {
-            subflow.send(data).await.map(|_| ())
-        } else {
-            Err(std::io::Error::new(std::io::ErrorKind::NotConnected, "No available subflows"))
+        self.send_sequenced(&[], data).await
+    }
+
+    /// Send a packet carrying the sequence numbers `seqs`
+    ///
+    /// Subflows whose send fails are closed and the packet is rescheduled on
+    /// the rest; the error is returned once none is left. The carrying
+    /// subflow is remembered so `on_rtt_sample` can credit it.
+    pub async fn send_sequenced(&self, seqs: &[u64], data: &[u8]) -> std::io::Result<()> {
+        loop {
+            // Don't hold the lock across the send
+            let selected: Vec<Arc<Subflow>> = {
+                let usable: Vec<Arc<Subflow>> = self.subflows.lock().unwrap().iter()
+                    .filter(|subflow| subflow.is_validated())
+                    .cloned()
+                    .collect();
+                self.scheduler.select_subflows(&usable).into_iter().cloned().collect()
+            };
+            if selected.is_empty() {
+                return Err(std::io::Error::new(std::io::ErrorKind::NotConnected, "No available subflows"));
+            }
+
+            let mut carriers = Vec::with_capacity(selected.len());
+            for subflow in selected {
+                match subflow.send(data).await {
+                    Ok(_) => carriers.push(subflow.id),
+                    Err(e) => {
+                        tracing::warn!(id = subflow.id, local = %subflow.local_addr, "Closing failed subflow: {}", e);
+                        self.remove_subflow(subflow.id);
+                    }
+                }
+            }
+
+            match carriers.as_slice() {
+                [] => continue,
+                // A copy on several subflows can't tell which one the ACK was for
+                [id] => self.record_sent(seqs, *id),
+                _ => {}
+            }
+            return Ok(());
+        }
+    }
+
+    /// Close a subflow; traffic moves to the remaining ones
+    pub fn remove_subflow(&self, id: u32) {
+        self.subflows.lock().unwrap().retain(|subflow| subflow.id != id);
+    }
+
+    fn record_sent(&self, seqs: &[u64], id: u32) {
+        if seqs.is_empty() {
+            return;
+        }
+        let mut sent_on = self.sent_on.lock().unwrap();
+        for &seq in seqs {
+            sent_on.insert(seq, id);
+        }
+        while sent_on.len() > MAX_TRACKED_SEQUENCES {
+            sent_on.pop_first();
+        }
+    }
+
+    /// Credit an ACK-derived RTT sample to the subflow that carried `seq`
+    pub fn on_rtt_sample(&self, seq: u64, rtt: Duration) {
+        let id = match self.sent_on.lock().unwrap().remove(&seq) {
+            Some(id) => id,
+            None => return,
+        };
+        if let Some(subflow) = self.subflows.lock().unwrap().iter().find(|subflow| subflow.id == id) {
+            subflow.update_rtt(rtt);
+        }
+    }
+}
+
+async fn open_subflow(id: u32, local: SocketAddr, remote: SocketAddr, handshake: Option<&Arc<SubflowHandshake>>) -> std::io::Result<Subflow> {
+    match handshake {
+        Some(handshake) => Subflow::with_handshake(id, local, remote, Arc::clone(handshake)).await,
+        None => Subflow::new(id, local, remote).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mptcp::SchedulerAlgorithm;
+    use tokio::net::UdpSocket;
+    use tokio::time::timeout;
+
+    fn manager(scheduler_algo: SchedulerAlgorithm, remote: SocketAddr) -> MptcpManager {
+        MptcpManager::new(MptcpConfig { enabled: true, max_subflows: 4, scheduler_algo }, remote)
+    }
+
+    #[tokio::test]
+    async fn test_failed_subflow_is_closed() {
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+        // Nothing listens here: sends fail once the ICMP error has come back
+        let dead = UdpSocket::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+
+        let manager = manager(SchedulerAlgorithm::RoundRobin, peer_addr);
+        let local: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        manager.add_subflow_to(local, dead).await.unwrap();
+        manager.add_subflow(local).await.unwrap();
+
+        for _ in 0..10 {
+            manager.send(b"ping").await.unwrap();
+            tokio::time::sleep(Duration::from_millis(5)).await;
         }
+        assert_eq!(manager.subflow_count(), 1);
+        assert_eq!(manager.subflows()[0].remote_addr, peer_addr);
+
+        // Only the first packet went to the dead subflow
+        let mut buf = [0u8; 16];
+        let mut received = 0;
+        while let Ok(Ok(_)) = timeout(Duration::from_millis(50), peer.recv_from(&mut buf)).await {
+            received += 1;
+        }
+        assert_eq!(received, 9);
+    }
+
+    #[tokio::test]
+    async fn test_rtt_credited_to_carrying_subflow() {
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let manager = manager(SchedulerAlgorithm::MinRtt, peer.local_addr().unwrap());
+        let local: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let slow = manager.add_subflow(local).await.unwrap();
+        let fast = manager.add_subflow(local).await.unwrap();
+
+        // Each unused subflow is tried once
+        manager.send_sequenced(&[1], b"one").await.unwrap();
+        manager.send_sequenced(&[2, 3], b"two and three").await.unwrap();
+        manager.on_rtt_sample(1, Duration::from_millis(40));
+        manager.on_rtt_sample(3, Duration::from_millis(5));
+        // Unknown or already credited sequences are ignored
+        manager.on_rtt_sample(1, Duration::from_millis(1));
+        manager.on_rtt_sample(99, Duration::from_millis(1));
+
+        let subflows = manager.subflows();
+        let subflow = |id: u32| subflows.iter().find(|subflow| subflow.id == id).unwrap();
+        assert_eq!(subflow(slow).rtt(), Some(Duration::from_millis(40)));
+        assert_eq!(subflow(fast).rtt(), Some(Duration::from_millis(5)));
+
+        manager.send_sequenced(&[4], b"four").await.unwrap();
+        assert_eq!(subflow(slow).packets_sent(), 1);
+        assert_eq!(subflow(fast).packets_sent(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_subflow_used_once_it_answered_a_challenge() {
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let manager = manager(SchedulerAlgorithm::RoundRobin, peer.local_addr().unwrap())
+            .with_handshake(SubflowHandshake {
+                hello: b"hello".to_vec(),
+                respond: Box::new(|packet: &[u8]| (packet == b"challenge").then(|| b"response".to_vec())),
+            });
+        manager.add_subflow("127.0.0.1:0".parse().unwrap()).await.unwrap();
+
+        let mut buf = [0u8; 16];
+        let (len, subflow_addr) = timeout(Duration::from_secs(1), peer.recv_from(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..len], b"hello");
+        assert_eq!(manager.usable_subflow_count(), 0);
+        assert!(manager.send(b"early").await.is_err());
+
+        // Anything but a challenge is not answered
+        peer.send_to(b"noise", subflow_addr).await.unwrap();
+        peer.send_to(b"challenge", subflow_addr).await.unwrap();
+        loop {
+            let len = timeout(Duration::from_secs(1), peer.recv(&mut buf)).await.unwrap().unwrap();
+            if &buf[..len] != b"hello" {
+                assert_eq!(&buf[..len], b"response");
+                break;
+            }
+        }
+        while manager.usable_subflow_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        manager.send(b"data").await.unwrap();
+        let len = timeout(Duration::from_secs(1), peer.recv(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..len], b"data");
     }
 }
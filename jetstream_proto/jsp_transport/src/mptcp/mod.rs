@@ -6,15 +6,20 @@ pub mod watcher;
 pub mod subflow;
 pub mod manager;
 pub mod scheduler;
+pub mod dedup;
 
 pub use watcher::InterfaceWatcher;
-pub use subflow::Subflow;
+pub use subflow::{Subflow, SubflowHandshake};
 pub use manager::MptcpManager;
 
 pub use scheduler::{Scheduler, create_scheduler};
+pub use dedup::DedupWindow;
+
+use serde::{Deserialize, Serialize};
 
 /// MPTCP Configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct MptcpConfig {
     pub enabled: bool,
     pub max_subflows: usize,
@@ -32,7 +37,8 @@ impl Default for MptcpConfig {
 }
 
 /// Scheduling algorithms
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum SchedulerAlgorithm {
     RoundRobin,
     MinRtt,
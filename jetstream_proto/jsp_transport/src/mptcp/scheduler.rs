@@ -5,14 +5,25 @@ use std::sync::Arc;
 
 pub trait Scheduler: Send + Sync {
     fn select_subflow<'a>(&self, subflows: &'a [Arc<Subflow>]) -> Option<&'a Arc<Subflow>>;
+
+    /// Subflows a packet is sent on; one unless the scheduler duplicates packets
+    fn select_subflows<'a>(&self, subflows: &'a [Arc<Subflow>]) -> Vec<&'a Arc<Subflow>> {
+        self.select_subflow(subflows).into_iter().collect()
+    }
 }
 
+/// Picks the subflow with the lowest smoothed RTT
+///
+/// A subflow that has not sent anything yet is tried first so every new path
+/// gets measured; until any sample is in, the least used subflow is picked.
 pub struct MinRttScheduler;
 
 impl Scheduler for MinRttScheduler {
     fn select_subflow<'a>(&self, subflows: &'a [Arc<Subflow>]) -> Option<&'a Arc<Subflow>> {
         subflows.iter()
-            .min_by_key(|s| s.rtt)
+            .find(|s| s.packets_sent() == 0)
+            .or_else(|| subflows.iter().filter(|s| s.rtt().is_some()).min_by_key(|s| s.rtt()))
+            .or_else(|| subflows.iter().min_by_key(|s| s.packets_sent()))
     }
 }
 
@@ -38,10 +49,23 @@ impl Scheduler for RoundRobinScheduler {
     }
 }
 
+/// Sends every packet on all subflows; the receiver keeps the first copy
+pub struct RedundantScheduler;
+
+impl Scheduler for RedundantScheduler {
+    fn select_subflow<'a>(&self, subflows: &'a [Arc<Subflow>]) -> Option<&'a Arc<Subflow>> {
+        MinRttScheduler.select_subflow(subflows)
+    }
+
+    fn select_subflows<'a>(&self, subflows: &'a [Arc<Subflow>]) -> Vec<&'a Arc<Subflow>> {
+        subflows.iter().collect()
+    }
+}
+
 pub fn create_scheduler(algo: SchedulerAlgorithm) -> Box<dyn Scheduler> {
     match algo {
         SchedulerAlgorithm::MinRtt => Box::new(MinRttScheduler),
         SchedulerAlgorithm::RoundRobin => Box::new(RoundRobinScheduler::new()),
-        SchedulerAlgorithm::Redundant => Box::new(MinRttScheduler), // Fallback
+        SchedulerAlgorithm::Redundant => Box::new(RedundantScheduler),
     }
 }
@@ -1,10 +1,32 @@
 //! MPTCP Subflow
 
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::Duration;
 use tokio::net::UdpSocket;
+use tokio::task::AbortHandle;
 use std::sync::Arc;
 
+/// Pause between hellos while the peer hasn't challenged the subflow
+const HELLO_INTERVAL: Duration = Duration::from_millis(250);
+/// Hellos sent before the subflow is left unused
+const HELLO_ATTEMPTS: u32 = 8;
+
+/// Builds the answer to a datagram from the peer, if it needs one
+pub type Responder = Box<dyn Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync>;
+
+/// Frames a subflow exchanges with the peer to have its address validated
+///
+/// The peer takes no frames from an address until it echoed a
+/// PATH_CHALLENGE, so a new subflow sends `hello` and carries traffic only
+/// once it answered the challenge that draws.
+pub struct SubflowHandshake {
+    /// Frame naming the connection, sent when the subflow opens
+    pub hello: Vec<u8>,
+    /// PATH_RESPONSE to a datagram from the peer, if it is a PATH_CHALLENGE
+    pub respond: Responder,
+}
+
 /// Represents a single subflow (path)
 #[derive(Debug)]
 pub struct Subflow {
@@ -12,35 +34,132 @@ pub struct Subflow {
     pub local_addr: SocketAddr,
     pub remote_addr: SocketAddr,
     socket: Arc<UdpSocket>,
+    // Whether the peer accepts frames from this subflow
+    validated: Arc<AtomicBool>,
+    // Task answering the peer's PATH_CHALLENGEs, when opened with a handshake
+    responder: Option<AbortHandle>,
     
     // Metrics
-    pub rtt: Duration,
+    /// Smoothed RTT in microseconds, 0 until the first ACK-derived sample
+    srtt_us: AtomicU64,
     pub cwnd: u32,
     pub bytes_inflight: u32,
+    packets_sent: AtomicU64,
+    bytes_sent: AtomicU64,
 }
 
 impl Subflow {
     pub async fn new(id: u32, local: SocketAddr, remote: SocketAddr) -> std::io::Result<Self> {
         let socket = UdpSocket::bind(local).await?;
         socket.connect(remote).await?;
+        // Report the bound port rather than the requested ephemeral one
+        let local_addr = socket.local_addr()?;
         
         Ok(Self {
             id,
-            local_addr: local,
+            local_addr,
             remote_addr: remote,
             socket: Arc::new(socket),
-            rtt: Duration::from_millis(100), // Initial estimate
+            validated: Arc::new(AtomicBool::new(true)),
+            responder: None,
+            srtt_us: AtomicU64::new(0),
             cwnd: 10 * 1400, // Initial CWND
             bytes_inflight: 0,
+            packets_sent: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
         })
     }
 
+    /// Open a subflow that is used once it passed `handshake` with the peer
+    pub async fn with_handshake(id: u32, local: SocketAddr, remote: SocketAddr, handshake: Arc<SubflowHandshake>) -> std::io::Result<Self> {
+        let mut subflow = Self::new(id, local, remote).await?;
+        subflow.validated.store(false, Ordering::Relaxed);
+        let task = tokio::spawn(answer_challenges(
+            Arc::clone(&subflow.socket),
+            Arc::clone(&subflow.validated),
+            handshake,
+        ));
+        subflow.responder = Some(task.abort_handle());
+        Ok(subflow)
+    }
+
+    /// Whether the peer validated this subflow's address, so it may carry traffic
+    pub fn is_validated(&self) -> bool {
+        self.validated.load(Ordering::Relaxed)
+    }
+
     pub async fn send(&self, data: &[u8]) -> std::io::Result<usize> {
-        self.socket.send(data).await
+        let sent = self.socket.send(data).await?;
+        self.packets_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(sent as u64, Ordering::Relaxed);
+        Ok(sent)
+    }
+
+    /// Packets sent over this subflow
+    pub fn packets_sent(&self) -> u64 {
+        self.packets_sent.load(Ordering::Relaxed)
     }
 
-    pub fn update_rtt(&mut self, rtt: Duration) {
-        // EWMA
-        self.rtt = rtt; 
+    /// Bytes sent over this subflow
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// Smoothed RTT of packets sent on this subflow, `None` before any was ACKed
+    pub fn rtt(&self) -> Option<Duration> {
+        match self.srtt_us.load(Ordering::Relaxed) {
+            0 => None,
+            us => Some(Duration::from_micros(us)),
+        }
+    }
+
+    /// Fold in the RTT of a packet that was sent only on this subflow
+    pub fn update_rtt(&self, rtt: Duration) {
+        // EWMA as in RFC 6298; the first sample replaces the estimate
+        let sample = (rtt.as_micros() as u64).max(1);
+        let _ = self.srtt_us.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |srtt| {
+            Some(if srtt == 0 { sample } else { (srtt * 7 + sample) / 8 })
+        });
+    }
+}
+
+impl Drop for Subflow {
+    fn drop(&mut self) {
+        if let Some(responder) = &self.responder {
+            responder.abort();
+        }
+    }
+}
+
+/// Send `hello` until the peer challenges the subflow, and answer its
+/// challenges for as long as the subflow is open
+async fn answer_challenges(socket: Arc<UdpSocket>, validated: Arc<AtomicBool>, handshake: Arc<SubflowHandshake>) {
+    let mut buf = vec![0u8; 2048];
+    let mut hellos = 0;
+    let mut resend = tokio::time::interval(HELLO_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = resend.tick(), if !validated.load(Ordering::Relaxed) && hellos < HELLO_ATTEMPTS => {
+                hellos += 1;
+                if let Err(e) = socket.send(&handshake.hello).await {
+                    tracing::debug!(local = ?socket.local_addr().ok(), "Subflow hello failed: {}", e);
+                }
+            }
+            res = socket.recv(&mut buf) => match res {
+                Ok(len) => {
+                    if let Some(response) = (handshake.respond)(&buf[..len]) {
+                        if socket.send(&response).await.is_ok() {
+                            validated.store(true, Ordering::Relaxed);
+                        }
+                    }
+                }
+                // An ICMP error for an earlier send; the socket is still usable
+                Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {}
+                Err(e) => {
+                    tracing::debug!(local = ?socket.local_addr().ok(), "Subflow stopped answering challenges: {}", e);
+                    return;
+                }
+            },
+        }
     }
 }
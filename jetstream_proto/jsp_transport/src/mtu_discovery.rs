@@ -1,9 +1,77 @@
+use serde::{Deserialize, Serialize};
 use std::time::{Duration, Instant};
 
+/// Bytes of a datagram that are not stream payload, at most: the header
+/// length prefix, an uncompressed CBOR header with a connection ID, a
+/// fragment header and an AEAD tag. Fragments are the path MTU less this.
+pub const PACKET_OVERHEAD: usize = 256;
+
+/// Probes sent at one size before the path is taken not to carry it
+pub const MTU_PROBE_ATTEMPTS: u32 = 3;
+
+/// The search stops once the largest size that got through is this close
+/// to the smallest that didn't
+pub const PROBE_GRANULARITY: usize = 8;
+
+/// Packetization-layer path MTU discovery (DPLPMTUD, RFC 8899)
+///
+/// Sizes are UDP payload bytes: what a datagram may carry, headers below
+/// UDP excluded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MtuDiscoveryConfig {
+    /// Probe the path and size packets by what gets through; when off,
+    /// `max_fragment_size` sizes them
+    pub enabled: bool,
+    /// Datagram size used until a probe answers, and again after a path change
+    pub base_mtu: usize,
+    /// Largest datagram size probed
+    pub max_mtu: usize,
+    /// How long the search stays converged before probing for a larger MTU
+    #[serde(with = "crate::duration_format")]
+    pub raise_interval: Duration,
+}
+
+impl Default for MtuDiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            base_mtu: 1200,  // QUIC's minimum, carried by nearly every path
+            max_mtu: 1472,   // 1500 byte Ethernet less IPv4 and UDP headers
+            raise_interval: Duration::from_secs(600),
+        }
+    }
+}
+
+/// Whether a send failed because the datagram exceeds the path MTU the
+/// kernel knows, from an ICMP "packet too big" or the local interface
+pub fn is_too_big(error: &anyhow::Error) -> bool {
+    error.chain()
+        .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .any(|error| {
+            #[cfg(unix)]
+            {
+                error.raw_os_error() == Some(libc::EMSGSIZE)
+            }
+            #[cfg(not(unix))]
+            {
+                let _ = error;
+                false
+            }
+        })
+}
+
 /// Path MTU Discovery
 /// 
 /// Automatically determines the maximum transmission unit (MTU) for a network path
 /// to optimize packet sizes and avoid fragmentation
+///
+/// Probes binary search between the current MTU and a ceiling, the
+/// largest size not yet seen to fail. A lost probe lowers the ceiling
+/// below it, an acknowledged one raises the MTU. Once the two are within
+/// `PROBE_GRANULARITY`, probing pauses for `probe_interval` and then
+/// searches up to `max_mtu` again, in case the path grew.
+#[derive(Debug)]
 pub struct MtuDiscovery {
     /// Current MTU estimate
     current_mtu: usize,
@@ -17,6 +85,8 @@ pub struct MtuDiscovery {
     last_probe_time: Option<Instant>,
     /// Probe interval
     probe_interval: Duration,
+    /// Largest size still worth probing
+    probe_ceiling: usize,
 }
 
 impl MtuDiscovery {
@@ -31,6 +101,7 @@ impl MtuDiscovery {
             last_successful_mtu: 1280,
             last_probe_time: None,
             probe_interval: Duration::from_secs(60), // Probe every 60 seconds
+            probe_ceiling: 1500,
         }
     }
     
@@ -44,8 +115,16 @@ impl MtuDiscovery {
             last_successful_mtu: initial_mtu,
             last_probe_time: None,
             probe_interval: Duration::from_secs(60),
+            probe_ceiling: max_mtu,
         }
     }
+
+    /// Discovery as configured, starting from `base_mtu`
+    pub fn from_config(config: &MtuDiscoveryConfig) -> Self {
+        let mut discovery = Self::with_range(config.base_mtu, config.max_mtu);
+        discovery.set_probe_interval(config.raise_interval);
+        discovery
+    }
     
     /// Get current MTU estimate
     pub fn current_mtu(&self) -> usize {
@@ -62,27 +141,39 @@ impl MtuDiscovery {
         self.current_mtu.saturating_sub(100) // Conservative overhead estimate
     }
     
-    /// Check if it's time to probe for MTU
+    /// Check if it's time to probe for MTU: while searching, or once
+    /// `probe_interval` passed since the search converged
     pub fn should_probe(&self) -> bool {
         match self.last_probe_time {
             None => true,
-            Some(last_time) => last_time.elapsed() >= self.probe_interval,
+            Some(last_time) => self.next_probe_size().is_some() || last_time.elapsed() >= self.probe_interval,
         }
     }
     
     /// Get next probe size for binary search
     pub fn next_probe_size(&self) -> Option<usize> {
-        if self.current_mtu >= self.max_mtu {
-            return None; // Already at maximum
+        if self.current_mtu + PROBE_GRANULARITY > self.probe_ceiling {
+            return None; // Converged, or already at maximum
         }
         
-        // Binary search between current and max
-        let mid = (self.current_mtu + self.max_mtu) / 2;
-        if mid <= self.current_mtu {
-            return None; // Converged
+        // Binary search between current and the ceiling, rounding up so
+        // the ceiling itself is tried once the gap is small
+        Some((self.current_mtu + self.probe_ceiling).div_ceil(2))
+    }
+
+    /// Size of the next probe, if one is due
+    ///
+    /// After `probe_interval` without probing, a converged search starts
+    /// over with the ceiling back at `max_mtu`.
+    pub fn due_probe(&mut self) -> Option<usize> {
+        if !self.should_probe() {
+            return None;
         }
-        
-        Some(mid)
+        if self.next_probe_size().is_none() {
+            self.probe_ceiling = self.max_mtu;
+            self.last_probe_time = Some(Instant::now());
+        }
+        self.next_probe_size()
     }
     
     /// Report successful transmission at given size
@@ -92,27 +183,49 @@ impl MtuDiscovery {
             self.current_mtu = size;
             tracing::debug!(mtu = size, "MTU increased");
         }
+        self.probe_ceiling = self.probe_ceiling.max(size);
         self.last_probe_time = Some(Instant::now());
     }
     
     /// Report failed transmission (likely MTU exceeded)
+    ///
+    /// A failed probe caps the search below its size. A datagram no
+    /// larger than the current MTU failing means the path shrank, so
+    /// discovery starts over from the minimum.
     pub fn report_failure(&mut self, size: usize) {
+        let ceiling = size.saturating_sub(1).max(self.min_mtu);
         if size <= self.current_mtu {
             // Failure at or below current MTU - reduce it
-            self.current_mtu = self.last_successful_mtu;
+            self.current_mtu = self.min_mtu;
+            self.last_successful_mtu = self.min_mtu;
+            self.probe_ceiling = ceiling;
             tracing::warn!(
                 failed_size = size,
                 new_mtu = self.current_mtu,
-                "MTU probe failed, reducing MTU"
+                "Datagram within the MTU failed, reducing MTU"
             );
+        } else {
+            self.probe_ceiling = self.probe_ceiling.min(ceiling);
+            tracing::debug!(failed_size = size, ceiling = self.probe_ceiling, "MTU probe failed");
         }
         self.last_probe_time = Some(Instant::now());
     }
     
+    /// Take a send refused as too big as a failure at `size`; returns
+    /// whether it was, as other errors say nothing about the MTU
+    pub fn on_send_error(&mut self, error: &anyhow::Error, size: usize) -> bool {
+        let too_big = is_too_big(error);
+        if too_big {
+            self.report_failure(size);
+        }
+        too_big
+    }
+    
     /// Reset to conservative MTU (e.g., after network change)
     pub fn reset(&mut self) {
         self.current_mtu = self.min_mtu;
         self.last_successful_mtu = self.min_mtu;
+        self.probe_ceiling = self.max_mtu;
         self.last_probe_time = None;
         tracing::info!(mtu = self.min_mtu, "MTU reset to minimum");
     }
@@ -130,6 +243,7 @@ impl MtuDiscovery {
             max_mtu: self.max_mtu,
             last_successful_mtu: self.last_successful_mtu,
             recommended_payload: self.recommended_payload_size(),
+            probe_ceiling: self.probe_ceiling,
         }
     }
 }
@@ -148,6 +262,8 @@ pub struct MtuStats {
     pub max_mtu: usize,
     pub last_successful_mtu: usize,
     pub recommended_payload: usize,
+    /// Largest size the search still probes
+    pub probe_ceiling: usize,
 }
 
 #[cfg(test)]
@@ -232,4 +348,60 @@ mod tests {
         assert_eq!(stats.min_mtu, 576);
         assert_eq!(stats.max_mtu, 1500);
     }
+
+    #[test]
+    fn test_search_converges_below_cap() {
+        let config = MtuDiscoveryConfig { enabled: true, ..Default::default() };
+        let mut mtu = MtuDiscovery::from_config(&config);
+        let cap = 1300;
+        let mut probes = 0;
+        while let Some(size) = mtu.due_probe() {
+            probes += 1;
+            if size <= cap {
+                mtu.report_success(size);
+            } else {
+                mtu.report_failure(size);
+            }
+        }
+        assert!(mtu.current_mtu() <= cap);
+        assert!(mtu.current_mtu() > cap - PROBE_GRANULARITY, "mtu {}", mtu.current_mtu());
+        assert!(probes < 10, "{} probes", probes);
+        // Converged, so nothing is probed until the raise interval passes
+        assert!(!mtu.should_probe());
+    }
+
+    #[test]
+    fn test_search_raises_again_after_interval() {
+        let mut mtu = MtuDiscovery::with_range(1200, 1400);
+        mtu.set_probe_interval(Duration::ZERO);
+        mtu.report_failure(1300);
+        mtu.report_success(1299);
+        assert_eq!(mtu.next_probe_size(), None);
+        // The path may have grown since 1300 failed
+        assert_eq!(mtu.due_probe(), Some(1350));
+    }
+
+    #[test]
+    fn test_failure_within_mtu_starts_over() {
+        let mut mtu = MtuDiscovery::with_range(1200, 1472);
+        mtu.report_success(1400);
+        // A datagram the path carried before is refused, as after an ICMP
+        // "packet too big" from a new hop
+        mtu.report_failure(1350);
+        assert_eq!(mtu.current_mtu(), 1200);
+        assert_eq!(mtu.stats().probe_ceiling, 1349);
+        assert_eq!(mtu.next_probe_size(), Some(1275));
+    }
+
+    #[test]
+    fn test_is_too_big() {
+        #[cfg(unix)]
+        {
+            let error = anyhow::Error::new(std::io::Error::from_raw_os_error(libc::EMSGSIZE));
+            assert!(is_too_big(&error));
+            assert!(is_too_big(&error.context("Sending MTU probe")));
+        }
+        let refused = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+        assert!(!is_too_big(&anyhow::Error::new(refused)));
+    }
 }
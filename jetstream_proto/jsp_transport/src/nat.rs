@@ -0,0 +1,62 @@
+//! NAT type, as told by the addresses STUN servers see
+//!
+//! `Connection::detect_nat_type` asks every configured STUN server for its
+//! mapped address. Behind a symmetric NAT each server sees another port,
+//! so a public address learned from one server is no use to a peer.
+
+use std::net::SocketAddr;
+
+/// What sits between a socket and the STUN servers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NatType {
+    /// Fewer than two servers answered, and the one that did saw a
+    /// translated address
+    #[default]
+    Unknown,
+    /// The servers see the socket's own address
+    Open,
+    /// Every server sees the same translated address, so peers can reach
+    /// it too
+    Cone,
+    /// Servers see different addresses; direct connections need the peer
+    /// to learn the address from its own traffic, or a relay
+    Symmetric,
+}
+
+impl NatType {
+    /// Classify from the addresses several servers mapped `local` to
+    pub fn classify(local: SocketAddr, mapped: &[SocketAddr]) -> Self {
+        let first = match mapped.first() {
+            Some(first) => *first,
+            None => return NatType::Unknown,
+        };
+        if mapped.iter().any(|addr| *addr != first) {
+            NatType::Symmetric
+        } else if first == local {
+            NatType::Open
+        } else if mapped.len() < 2 {
+            NatType::Unknown
+        } else {
+            NatType::Cone
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let local: SocketAddr = "192.168.1.10:5000".parse().unwrap();
+        let public: SocketAddr = "203.0.113.7:41000".parse().unwrap();
+        let other_port: SocketAddr = "203.0.113.7:41001".parse().unwrap();
+
+        assert_eq!(NatType::classify(local, &[]), NatType::Unknown);
+        assert_eq!(NatType::classify(local, &[public]), NatType::Unknown);
+        assert_eq!(NatType::classify(local, &[local]), NatType::Open);
+        assert_eq!(NatType::classify(local, &[public, public]), NatType::Cone);
+        assert_eq!(NatType::classify(local, &[public, other_port]), NatType::Symmetric);
+        assert_eq!(NatType::classify(local, &[local, public]), NatType::Symmetric);
+    }
+}
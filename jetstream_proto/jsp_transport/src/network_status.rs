@@ -10,6 +10,18 @@ pub enum NetworkType {
     Unknown,
 }
 
+impl NetworkType {
+    /// Lowercase name, as the language bindings report it
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NetworkType::Wifi => "wifi",
+            NetworkType::Cellular => "cellular",
+            NetworkType::Ethernet => "ethernet",
+            NetworkType::Unknown => "unknown",
+        }
+    }
+}
+
 /// Network status manager
 #[derive(Debug)]
 pub struct NetworkStatus {
@@ -24,12 +36,16 @@ impl NetworkStatus {
     }
 
     /// Update network type
-    pub async fn set_network_type(&self, net_type: NetworkType) {
+    ///
+    /// Returns the previous type if it changed.
+    pub async fn set_network_type(&self, net_type: NetworkType) -> Option<NetworkType> {
         let mut current = self.current_type.write().await;
-        if *current != net_type {
-            *current = net_type;
-            tracing::info!(network_type = ?net_type, "Network type changed");
+        if *current == net_type {
+            return None;
         }
+        let old = std::mem::replace(&mut *current, net_type);
+        tracing::info!(network_type = ?net_type, "Network type changed");
+        Some(old)
     }
 
     /// Get current network type
@@ -62,12 +78,13 @@ mod tests {
         assert!(!status.is_metered().await);
         
         // Switch to Cellular
-        status.set_network_type(NetworkType::Cellular).await;
+        assert_eq!(status.set_network_type(NetworkType::Cellular).await, Some(NetworkType::Unknown));
         assert_eq!(status.get_network_type().await, NetworkType::Cellular);
         assert!(status.is_metered().await);
+        assert_eq!(status.set_network_type(NetworkType::Cellular).await, None);
         
         // Switch to Wifi
-        status.set_network_type(NetworkType::Wifi).await;
+        assert_eq!(status.set_network_type(NetworkType::Wifi).await, Some(NetworkType::Cellular));
         assert_eq!(status.get_network_type().await, NetworkType::Wifi);
         assert!(!status.is_metered().await);
     }
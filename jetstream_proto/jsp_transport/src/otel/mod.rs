@@ -1,26 +1,223 @@
 //! OpenTelemetry Distributed Tracing Module
-//! 
-//! Simplified tracing implementation for JetStreamProto.
+//!
+//! Lightweight tracing for JetStreamProto: spans carry W3C trace and span
+//! IDs, children inherit their parent's trace, and completed spans are handed
+//! to a pluggable `SpanExporter`.
 
+use serde::Serialize;
 use std::collections::HashMap;
-use std::time::Instant;
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// 128-bit trace ID shared by every span of a trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceId([u8; 16]);
+
+/// 64-bit span ID, unique within a trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpanId([u8; 8]);
+
+impl TraceId {
+    pub fn random() -> Self {
+        Self(random_bytes())
+    }
+}
+
+impl SpanId {
+    pub fn random() -> Self {
+        Self(random_bytes())
+    }
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    // All-zero IDs are invalid in W3C trace context
+    while bytes.iter().all(|b| *b == 0) {
+        getrandom::getrandom(&mut bytes).expect("Failed to generate trace ID");
+    }
+    bytes
+}
+
+fn write_hex(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    bytes.iter().try_for_each(|b| write!(f, "{:02x}", b))
+}
+
+fn parse_hex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    if text.len() != N * 2 {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(text.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+impl fmt::Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_hex(f, &self.0)
+    }
+}
+
+impl fmt::Display for SpanId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_hex(f, &self.0)
+    }
+}
+
+/// Identity of a span, as propagated to its children
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanContext {
+    pub trace_id: TraceId,
+    pub span_id: SpanId,
+}
+
+impl SpanContext {
+    /// W3C `traceparent` header value (sampled)
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-01", self.trace_id, self.span_id)
+    }
+
+    /// Parse a W3C `traceparent` header value, e.g. from another service
+    pub fn from_traceparent(value: &str) -> Option<Self> {
+        let mut parts = value.trim().split('-');
+        let (version, trace_id, span_id, _flags) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        if version != "00" || parts.next().is_some() {
+            return None;
+        }
+        let context = Self {
+            trace_id: TraceId(parse_hex(trace_id)?),
+            span_id: SpanId(parse_hex(span_id)?),
+        };
+        let valid = context.trace_id.0.iter().any(|b| *b != 0) && context.span_id.0.iter().any(|b| *b != 0);
+        valid.then_some(context)
+    }
+}
+
+/// A completed span, as passed to a `SpanExporter`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpanData {
+    pub name: String,
+    pub service_name: String,
+    pub trace_id: String,
+    pub span_id: String,
+    pub parent_span_id: Option<String>,
+    /// Start time in nanoseconds since the Unix epoch
+    pub start_time_unix_nano: u128,
+    pub duration_nanos: u128,
+    pub attributes: HashMap<String, String>,
+    pub events: Vec<SpanEvent>,
+}
+
+/// Timestamped event recorded on a span
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpanEvent {
+    pub name: String,
+    /// Offset from the span's start
+    pub offset_nanos: u128,
+    pub attributes: HashMap<String, String>,
+}
+
+/// Destination for completed spans
+pub trait SpanExporter: Send + Sync {
+    fn export(&self, span: &SpanData);
+}
+
+/// Writes each completed span as one line of JSON
+pub struct JsonExporter<W: Write + Send> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonExporter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer: Mutex::new(writer) }
+    }
+}
+
+impl JsonExporter<std::io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(std::io::stdout())
+    }
+}
+
+impl<W: Write + Send> SpanExporter for JsonExporter<W> {
+    fn export(&self, span: &SpanData) {
+        let line = match serde_json::to_string(span) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::warn!("Failed to serialize span {}: {}", span.name, e);
+                return;
+            }
+        };
+        let mut writer = self.writer.lock().unwrap();
+        if let Err(e) = writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+            tracing::warn!("Failed to export span {}: {}", span.name, e);
+        }
+    }
+}
+
+/// Keeps completed spans in memory, e.g. for tests
+#[derive(Default)]
+pub struct InMemoryExporter {
+    spans: Mutex<Vec<SpanData>>,
+}
+
+impl InMemoryExporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spans exported so far, in the order they ended
+    pub fn spans(&self) -> Vec<SpanData> {
+        self.spans.lock().unwrap().clone()
+    }
+}
+
+impl SpanExporter for InMemoryExporter {
+    fn export(&self, span: &SpanData) {
+        self.spans.lock().unwrap().push(span.clone());
+    }
+}
 
 /// Trace span
+///
+/// Exported when ended, or when dropped without being ended.
 pub struct Span {
     name: String,
+    context: SpanContext,
+    parent_span_id: Option<SpanId>,
     start_time: Instant,
+    start_wall_time: SystemTime,
     attributes: HashMap<String, String>,
-    events: Vec<(String, HashMap<String, String>)>,
+    events: Vec<SpanEvent>,
+    service_name: String,
+    exporter: Option<Arc<dyn SpanExporter>>,
+    ended: bool,
 }
 
 impl Span {
-    /// Create a new span
+    /// Create a new root span that isn't exported
     pub fn new(name: impl Into<String>) -> Self {
+        Self::start(name.into(), None, String::new(), None)
+    }
+
+    fn start(name: String, parent: Option<&SpanContext>, service_name: String, exporter: Option<Arc<dyn SpanExporter>>) -> Self {
         Self {
-            name: name.into(),
+            name,
+            context: SpanContext {
+                trace_id: parent.map(|p| p.trace_id).unwrap_or_else(TraceId::random),
+                span_id: SpanId::random(),
+            },
+            parent_span_id: parent.map(|p| p.span_id),
             start_time: Instant::now(),
+            start_wall_time: SystemTime::now(),
             attributes: HashMap::new(),
             events: Vec::new(),
+            service_name,
+            exporter,
+            ended: false,
         }
     }
 
@@ -31,12 +228,44 @@ impl Span {
 
     /// Add event
     pub fn add_event(&mut self, name: impl Into<String>, attrs: HashMap<String, String>) {
-        self.events.push((name.into(), attrs));
+        self.events.push(SpanEvent {
+            name: name.into(),
+            offset_nanos: self.start_time.elapsed().as_nanos(),
+            attributes: attrs,
+        });
     }
 
-    /// End span and return duration
-    pub fn end(self) -> std::time::Duration {
-        self.start_time.elapsed()
+    /// Mark the span as failed
+    pub fn record_error(&mut self, error: &impl fmt::Display) {
+        self.set_attribute("error", error.to_string());
+    }
+
+    /// End span, export it and return its duration
+    pub fn end(mut self) -> Duration {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Duration {
+        let duration = self.start_time.elapsed();
+        if self.ended {
+            return duration;
+        }
+        self.ended = true;
+
+        if let Some(exporter) = &self.exporter {
+            exporter.export(&SpanData {
+                name: self.name.clone(),
+                service_name: self.service_name.clone(),
+                trace_id: self.context.trace_id.to_string(),
+                span_id: self.context.span_id.to_string(),
+                parent_span_id: self.parent_span_id.map(|id| id.to_string()),
+                start_time_unix_nano: self.start_wall_time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos(),
+                duration_nanos: duration.as_nanos(),
+                attributes: std::mem::take(&mut self.attributes),
+                events: std::mem::take(&mut self.events),
+            });
+        }
+        duration
     }
 
     /// Get span name
@@ -48,11 +277,27 @@ impl Span {
     pub fn attributes(&self) -> &HashMap<String, String> {
         &self.attributes
     }
+
+    /// Trace and span ID, to start children with
+    pub fn context(&self) -> SpanContext {
+        self.context
+    }
+
+    pub fn parent_span_id(&self) -> Option<SpanId> {
+        self.parent_span_id
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        self.finish();
+    }
 }
 
 /// Simple tracer
 pub struct Tracer {
     service_name: String,
+    exporter: Option<Arc<dyn SpanExporter>>,
 }
 
 impl Tracer {
@@ -60,12 +305,24 @@ impl Tracer {
     pub fn new(service_name: impl Into<String>) -> Self {
         Self {
             service_name: service_name.into(),
+            exporter: None,
         }
     }
 
-    /// Start a new span
+    /// Export every span this tracer starts to `exporter`
+    pub fn with_exporter(mut self, exporter: Arc<dyn SpanExporter>) -> Self {
+        self.exporter = Some(exporter);
+        self
+    }
+
+    /// Start a new root span, beginning a new trace
     pub fn start_span(&self, name: impl Into<String>) -> Span {
-        Span::new(name)
+        self.start_span_with_parent(name, None)
+    }
+
+    /// Start a span; with a parent it joins the parent's trace
+    pub fn start_span_with_parent(&self, name: impl Into<String>, parent: Option<&SpanContext>) -> Span {
+        Span::start(name.into(), parent, self.service_name.clone(), self.exporter.clone())
     }
 
     /// Get service name
@@ -79,7 +336,17 @@ static GLOBAL_TRACER: std::sync::OnceLock<Tracer> = std::sync::OnceLock::new();
 
 /// Initialize global tracer
 pub fn init_tracer(service_name: impl Into<String>) -> Result<(), Box<dyn std::error::Error>> {
-    GLOBAL_TRACER.set(Tracer::new(service_name))
+    install_tracer(Tracer::new(service_name))
+}
+
+/// Initialize the global tracer with an exporter; connections then trace
+/// their handshake, sends and receives
+pub fn init_tracer_with_exporter(service_name: impl Into<String>, exporter: Arc<dyn SpanExporter>) -> Result<(), Box<dyn std::error::Error>> {
+    install_tracer(Tracer::new(service_name).with_exporter(exporter))
+}
+
+fn install_tracer(tracer: Tracer) -> Result<(), Box<dyn std::error::Error>> {
+    GLOBAL_TRACER.set(tracer)
         .map_err(|_| "Tracer already initialized")?;
     Ok(())
 }
@@ -89,6 +356,11 @@ pub fn global_tracer() -> &'static Tracer {
     GLOBAL_TRACER.get().expect("Tracer not initialized")
 }
 
+/// Global tracer, if one was initialized
+pub fn try_global_tracer() -> Option<&'static Tracer> {
+    GLOBAL_TRACER.get()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -107,4 +379,61 @@ mod tests {
         let span = tracer.start_span("test");
         assert_eq!(span.name(), "test");
     }
+
+    #[test]
+    fn test_child_span_joins_parent_trace_and_is_exported() {
+        let exporter = Arc::new(InMemoryExporter::new());
+        let tracer = Tracer::new("test_service").with_exporter(exporter.clone());
+
+        let root = tracer.start_span("connection");
+        let mut child = tracer.start_span_with_parent("handshake", Some(&root.context()));
+        child.set_attribute("session.id", "7");
+        let grandchild = tracer.start_span_with_parent("send", Some(&child.context()));
+
+        assert_eq!(child.context().trace_id, root.context().trace_id);
+        assert_eq!(child.parent_span_id(), Some(root.context().span_id));
+        assert_ne!(child.context().span_id, root.context().span_id);
+        assert!(exporter.spans().is_empty());
+
+        let (root_context, child_context) = (root.context(), child.context());
+        grandchild.end();
+        child.end();
+        drop(root);
+
+        let spans = exporter.spans();
+        let names: Vec<&str> = spans.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["send", "handshake", "connection"]);
+        assert!(spans.iter().all(|s| s.trace_id == root_context.trace_id.to_string()));
+        assert!(spans.iter().all(|s| s.service_name == "test_service"));
+        assert_eq!(spans[0].parent_span_id, Some(child_context.span_id.to_string()));
+        assert_eq!(spans[1].parent_span_id, Some(root_context.span_id.to_string()));
+        assert_eq!(spans[1].attributes["session.id"], "7");
+        assert_eq!(spans[2].parent_span_id, None);
+
+        // A separate root starts a new trace
+        let other = tracer.start_span("other");
+        assert_ne!(other.context().trace_id, root_context.trace_id);
+    }
+
+    #[test]
+    fn test_traceparent_round_trip_and_json_export() {
+        let span = Span::new("remote");
+        let header = span.context().traceparent();
+        assert_eq!(header.len(), 55);
+        assert_eq!(SpanContext::from_traceparent(&header), Some(span.context()));
+        assert_eq!(SpanContext::from_traceparent("00-00000000000000000000000000000000-0000000000000001-01"), None);
+        assert_eq!(SpanContext::from_traceparent("garbage"), None);
+
+        let exporter = Arc::new(JsonExporter::new(Vec::new()));
+        let tracer = Tracer::new("svc").with_exporter(exporter.clone());
+        let mut child = tracer.start_span_with_parent("child", Some(&span.context()));
+        child.add_event("sent", HashMap::from([("bytes".to_string(), "4".to_string())]));
+        child.end();
+
+        let output = String::from_utf8(exporter.writer.lock().unwrap().clone()).unwrap();
+        let json: serde_json::Value = serde_json::from_str(output.trim_end()).unwrap();
+        assert_eq!(json["trace_id"], span.context().trace_id.to_string());
+        assert_eq!(json["parent_span_id"], span.context().span_id.to_string());
+        assert_eq!(json["events"][0]["name"], "sent");
+    }
 }
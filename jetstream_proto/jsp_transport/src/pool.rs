@@ -0,0 +1,409 @@
+//! Connection Pool
+//!
+//! Keeps handshaked connections to backends for reuse, so bursts of requests
+//! don't each pay for a Kyber key exchange. With `min_connections_per_backend`
+//! or `idle_timeout` set, a background task per backend keeps that many
+//! connections warm and closes idle ones beyond them.
+
+use crate::config::ConnectionConfig;
+use crate::connection::Connection;
+use anyhow::Result;
+use jsp_core::types::control::CloseReason;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+/// Longest wait between two rounds of backend maintenance
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Connection pool configuration
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    /// Maximum connections per backend, idle and checked out together
+    pub max_connections_per_backend: usize,
+    /// Connections kept established per backend, idle and checked out
+    /// together; the pool handshakes more in the background when below
+    pub min_connections_per_backend: usize,
+    /// Close connections idle this long, down to `min_connections_per_backend`
+    /// (None keeps them until `close_idle`)
+    pub idle_timeout: Option<Duration>,
+    /// Configuration of pooled connections
+    pub connection: ConnectionConfig,
+    /// Delay before re-establishing a broken connection, multiplied by the attempt number
+    pub reconnect_delay: Duration,
+    /// Attempts to re-establish a broken connection before giving up its slot
+    pub max_reconnect_attempts: u32,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_connections_per_backend: 4,
+            min_connections_per_backend: 0,
+            idle_timeout: None,
+            connection: ConnectionConfig::default(),
+            reconnect_delay: Duration::from_millis(100),
+            max_reconnect_attempts: 3,
+        }
+    }
+}
+
+/// Pooled connections of one backend
+struct BackendPool {
+    /// Idle connections with the time they were returned, oldest first
+    idle: Mutex<Vec<(Connection, Instant)>>,
+    /// Connections that exist or are being (re-)established
+    open: AtomicUsize,
+    /// One permit per connection that can be checked out
+    permits: Arc<Semaphore>,
+    /// Signalled when a connection goes back to `idle` or a slot frees up
+    returned: Notify,
+}
+
+impl BackendPool {
+    fn new(max_connections: usize) -> Self {
+        Self {
+            idle: Mutex::new(Vec::new()),
+            open: AtomicUsize::new(0),
+            permits: Arc::new(Semaphore::new(max_connections)),
+            returned: Notify::new(),
+        }
+    }
+
+    /// Claim a slot for a new connection, if the limit allows
+    fn reserve(&self, max_connections: usize) -> bool {
+        self.open
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| (open < max_connections).then_some(open + 1))
+            .is_ok()
+    }
+
+    fn release(&self, conn: Connection) {
+        self.idle.lock().unwrap().push((conn, Instant::now()));
+        self.returned.notify_one();
+    }
+
+    fn forget(&self) {
+        self.open.fetch_sub(1, Ordering::SeqCst);
+        self.returned.notify_one();
+    }
+}
+
+struct PoolInner {
+    config: PoolConfig,
+    backends: Mutex<HashMap<SocketAddr, Arc<BackendPool>>>,
+    handshakes: AtomicU64,
+}
+
+impl PoolInner {
+    fn backend(self: &Arc<Self>, addr: SocketAddr) -> Arc<BackendPool> {
+        let mut backends = self.backends.lock().unwrap();
+        if let Some(backend) = backends.get(&addr) {
+            return Arc::clone(backend);
+        }
+        let backend = Arc::new(BackendPool::new(self.config.max_connections_per_backend));
+        backends.insert(addr, Arc::clone(&backend));
+        self.spawn_maintenance(addr, Arc::clone(&backend));
+        backend
+    }
+
+    /// Maintain `backend` until the pool is dropped, if the config asks for it
+    fn spawn_maintenance(self: &Arc<Self>, addr: SocketAddr, backend: Arc<BackendPool>) {
+        if self.config.min_connections_per_backend == 0 && self.config.idle_timeout.is_none() {
+            return;
+        }
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => return,
+        };
+
+        let period = self.config.idle_timeout
+            .map_or(MAINTENANCE_INTERVAL, |timeout| (timeout / 2).clamp(Duration::from_millis(10), MAINTENANCE_INTERVAL));
+        let pool = Arc::downgrade(self);
+        handle.spawn(async move {
+            let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                ticks.tick().await;
+                match pool.upgrade() {
+                    Some(inner) => inner.maintain(addr, &backend).await,
+                    None => return,
+                }
+            }
+        });
+    }
+
+    /// Replace idle connections that closed, close those idle past
+    /// `idle_timeout` and top up to `min_connections_per_backend`
+    async fn maintain(self: &Arc<Self>, addr: SocketAddr, backend: &Arc<BackendPool>) {
+        let min = self.config.min_connections_per_backend;
+        let (dead, expired) = {
+            let mut idle = backend.idle.lock().unwrap();
+            let (dead, alive): (Vec<_>, Vec<_>) = idle.drain(..).partition(|(conn, _)| conn.is_closing());
+            *idle = alive;
+
+            let mut expired = Vec::new();
+            if let Some(timeout) = self.config.idle_timeout {
+                // Closed connections keep their slot while re-established
+                let mut open = backend.open.load(Ordering::SeqCst);
+                while open > min && idle.first().is_some_and(|(_, since)| since.elapsed() >= timeout) {
+                    expired.push(idle.remove(0).0);
+                    open -= 1;
+                }
+            }
+            (dead, expired)
+        };
+
+        for conn in dead {
+            tracing::debug!(backend = %addr, "Replacing closed idle connection");
+            drop(conn);
+            self.spawn_reconnect(addr, Arc::clone(backend));
+        }
+        for mut conn in expired {
+            backend.forget();
+            tracing::debug!(backend = %addr, "Closing idle pooled connection");
+            if let Err(e) = conn.close(CloseReason::GoingAway, Some("Idle timeout".to_string())).await {
+                tracing::debug!(backend = %addr, "Failed to close pooled connection: {}", e);
+            }
+        }
+        while backend.reserve(min) {
+            match self.connect(addr).await {
+                Ok(conn) => backend.release(conn),
+                Err(e) => {
+                    backend.forget();
+                    tracing::warn!(backend = %addr, "Failed to establish warm connection: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn connect(&self, addr: SocketAddr) -> Result<Connection> {
+        let mut conn = Connection::connect_with_config(&addr.to_string(), self.config.connection.clone()).await?;
+        conn.handshake().await?;
+        self.handshakes.fetch_add(1, Ordering::Relaxed);
+        tracing::debug!(backend = %addr, "Pooled connection established");
+        Ok(conn)
+    }
+
+    /// Replace a broken connection in the background, keeping its slot
+    fn spawn_reconnect(self: &Arc<Self>, addr: SocketAddr, backend: Arc<BackendPool>) {
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => {
+                backend.forget();
+                return;
+            }
+        };
+
+        let inner = Arc::clone(self);
+        handle.spawn(async move {
+            for attempt in 1..=inner.config.max_reconnect_attempts {
+                tokio::time::sleep(inner.config.reconnect_delay * attempt).await;
+                match inner.connect(addr).await {
+                    Ok(conn) => {
+                        backend.release(conn);
+                        return;
+                    }
+                    Err(e) => {
+                        tracing::warn!(backend = %addr, attempt, "Failed to re-establish pooled connection: {}", e);
+                    }
+                }
+            }
+            backend.forget();
+        });
+    }
+}
+
+/// Pool of handshaked connections, keyed by backend address
+///
+/// Cloning is cheap; clones share the same connections.
+#[derive(Clone)]
+pub struct ConnectionPool {
+    inner: Arc<PoolInner>,
+}
+
+impl ConnectionPool {
+    pub fn new(config: PoolConfig) -> Self {
+        Self {
+            inner: Arc::new(PoolInner {
+                config,
+                backends: Mutex::new(HashMap::new()),
+                handshakes: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Check out a connection to `addr`
+    ///
+    /// Reuses an idle connection when one passes the liveness check, otherwise
+    /// handshakes a new one. Waits while `max_connections_per_backend` are in use.
+    pub async fn acquire(&self, addr: SocketAddr) -> Result<PooledConnection> {
+        let backend = self.inner.backend(addr);
+        let permit = backend.permits.clone().acquire_owned().await?;
+
+        loop {
+            // The most recently returned, so surplus connections age out
+            let candidate = backend.idle.lock().unwrap().pop().map(|(conn, _)| conn);
+            match candidate {
+                Some(conn) => {
+                    if is_alive(&conn).await {
+                        return Ok(PooledConnection::new(conn, addr, backend, Arc::clone(&self.inner), permit));
+                    }
+                    tracing::debug!(backend = %addr, "Replacing broken pooled connection");
+                    drop(conn);
+                    self.inner.spawn_reconnect(addr, Arc::clone(&backend));
+                }
+                None => {
+                    if backend.reserve(self.inner.config.max_connections_per_backend) {
+                        return match self.inner.connect(addr).await {
+                            Ok(conn) => Ok(PooledConnection::new(conn, addr, backend, Arc::clone(&self.inner), permit)),
+                            Err(e) => {
+                                backend.forget();
+                                Err(e)
+                            }
+                        };
+                    }
+                    // Every slot is taken by a connection being re-established
+                    backend.returned.notified().await;
+                }
+            }
+        }
+    }
+
+    /// Establish idle connections to `addr` up to the per-backend limit
+    pub async fn warm(&self, addr: SocketAddr) -> Result<()> {
+        let backend = self.inner.backend(addr);
+        while backend.reserve(self.inner.config.max_connections_per_backend) {
+            match self.inner.connect(addr).await {
+                Ok(conn) => backend.release(conn),
+                Err(e) => {
+                    backend.forget();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of idle connections to `addr`
+    pub fn idle_count(&self, addr: SocketAddr) -> usize {
+        self.inner.backends.lock().unwrap()
+            .get(&addr)
+            .map_or(0, |backend| backend.idle.lock().unwrap().len())
+    }
+
+    /// Close every idle connection with `GoingAway`, e.g. when shutting down
+    ///
+    /// Backends release those sessions right away instead of at their idle
+    /// timeout. Checked out connections are left alone. Returns how many
+    /// connections were closed.
+    pub async fn close_idle(&self) -> usize {
+        let backends: Vec<(SocketAddr, Arc<BackendPool>)> = self.inner.backends.lock().unwrap()
+            .iter()
+            .map(|(addr, backend)| (*addr, Arc::clone(backend)))
+            .collect();
+
+        let mut closed = 0;
+        for (addr, backend) in backends {
+            let idle = std::mem::take(&mut *backend.idle.lock().unwrap());
+            for (mut conn, _) in idle {
+                backend.forget();
+                if let Err(e) = conn.close(CloseReason::GoingAway, Some("Pool closing".to_string())).await {
+                    tracing::debug!(backend = %addr, "Failed to close pooled connection: {}", e);
+                }
+                closed += 1;
+            }
+        }
+        closed
+    }
+
+    /// Handshakes performed by the pool since it was created
+    pub fn handshakes(&self) -> u64 {
+        self.inner.handshakes.load(Ordering::Relaxed)
+    }
+
+    pub fn config(&self) -> &PoolConfig {
+        &self.inner.config
+    }
+}
+
+impl fmt::Debug for ConnectionPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionPool")
+            .field("config", &self.inner.config)
+            .field("handshakes", &self.handshakes())
+            .finish()
+    }
+}
+
+/// A connection is reusable while it isn't closing and still hears from the peer
+async fn is_alive(conn: &Connection) -> bool {
+    !conn.is_closing() && !conn.heartbeat().is_timed_out().await
+}
+
+/// Connection checked out of a `ConnectionPool`, returned to it on drop
+pub struct PooledConnection {
+    conn: Option<Connection>,
+    addr: SocketAddr,
+    backend: Arc<BackendPool>,
+    pool: Arc<PoolInner>,
+    // Released after the connection is back in the idle list
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledConnection {
+    fn new(conn: Connection, addr: SocketAddr, backend: Arc<BackendPool>, pool: Arc<PoolInner>, permit: OwnedSemaphorePermit) -> Self {
+        Self {
+            conn: Some(conn),
+            addr,
+            backend,
+            pool,
+            _permit: permit,
+        }
+    }
+
+    /// Backend this connection goes to
+    pub fn backend_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Drop the connection instead of returning it, e.g. after a protocol error
+    ///
+    /// The pool re-establishes it in the background.
+    pub fn discard(mut self) {
+        if self.conn.take().is_some() {
+            self.pool.spawn_reconnect(self.addr, Arc::clone(&self.backend));
+        }
+    }
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().expect("connection present until drop")
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn.as_mut().expect("connection present until drop")
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            if conn.is_closing() {
+                drop(conn);
+                self.pool.spawn_reconnect(self.addr, Arc::clone(&self.backend));
+            } else {
+                self.backend.release(conn);
+            }
+        }
+    }
+}
@@ -1,5 +1,8 @@
+use std::cmp::Reverse;
 use std::collections::VecDeque;
-use jsp_core::qos::QosPriority;
+use std::time::{Duration, Instant};
+use jsp_core::qos::{QosPriority, QosWeights};
+use jsp_core::types::delivery::DeliveryMode;
 
 /// Priority queue item
 #[derive(Debug)]
@@ -8,19 +11,91 @@ pub struct PriorityItem<T> {
     pub priority: QosPriority,
 }
 
+/// What happened to the items of one priority level
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PriorityCounters {
+    pub enqueued: u64,
+    pub dequeued: u64,
+    /// Dropped to make room, for lack of it, or past their TTL
+    pub dropped: u64,
+    /// Time dequeued items spent in the queue, summed
+    pub queue_delay_total: Duration,
+    /// Longest time a dequeued item spent in the queue
+    pub queue_delay_max: Duration,
+}
+
+/// A queued item with what the drop policy needs to know about it
+#[derive(Debug)]
+struct Queued<T> {
+    data: T,
+    bytes: usize,
+    mode: DeliveryMode,
+    queued_at: Instant,
+    /// Enqueue order across all priorities
+    order: u64,
+}
+
+impl<T> Queued<T> {
+    /// When a PartiallyReliable item's TTL runs out; None for other modes
+    fn deadline(&self) -> Option<Instant> {
+        match self.mode {
+            DeliveryMode::PartiallyReliable { ttl_ms } => Some(self.queued_at + Duration::from_millis(ttl_ms as u64)),
+            _ => None,
+        }
+    }
+}
+
 /// Priority queue with weighted fair queuing
+///
+/// Dequeueing goes in rounds: each priority level with items may send as
+/// many as its weight, highest priority first. High priority items go out
+/// ahead of queued bulk data, and bulk data still gets its share however
+/// much high priority data keeps arriving.
+///
+/// Rounds bound a level's wait in dequeues, not in time: with a heavy
+/// weight above it, a bulk item can sit through hundreds of them. A queue
+/// created `with_aging` raises an item one level for every interval it has
+/// waited, up to System, and serves it ahead of the level whose turn it is
+/// once it ranks higher; at the same level the older item goes first.
+///
+/// Within a level items go first in, first out, except PartiallyReliable
+/// ones: whenever one is next, the one closest to its deadline goes in its
+/// place, and one past its deadline is dropped rather than sent. Other
+/// items keep their turn.
+///
+/// A queue created `with_capacity` is bounded in items and bytes. When an
+/// item doesn't fit, PartiallyReliable items past their TTL are dropped,
+/// then BestEffort items oldest first; if that is not enough, a BestEffort
+/// item is dropped itself and any other is refused so the caller backs off.
 pub struct PriorityQueue<T> {
     /// Queues for each priority level
-    queues: [VecDeque<T>; 4],
-    /// Credits for weighted fair queuing
+    queues: [VecDeque<Queued<T>>; 4],
+    /// Items each level may still send this round
     credits: [usize; 4],
+    /// Credits each level with items gets at the start of a round
+    weights: [usize; 4],
+    /// Wait that raises an item one level (None = no aging)
+    aging_interval: Option<Duration>,
+    counters: [PriorityCounters; 4],
     /// Total items in all queues
     total_items: usize,
+    /// Total bytes in all queues, as given on enqueue
+    total_bytes: usize,
+    max_items: usize,
+    max_bytes: usize,
+    next_order: u64,
+    dropped_best_effort: u64,
+    dropped_expired: u64,
 }
 
 impl<T> PriorityQueue<T> {
-    /// Create a new priority queue
+    /// Create a new, unbounded priority queue
     pub fn new() -> Self {
+        Self::with_capacity(usize::MAX, usize::MAX)
+    }
+    
+    /// Create a priority queue holding at most `max_items` items and `max_bytes` bytes
+    pub fn with_capacity(max_items: usize, max_bytes: usize) -> Self {
         Self {
             queues: [
                 VecDeque::new(), // Bulk (0)
@@ -29,55 +104,236 @@ impl<T> PriorityQueue<T> {
                 VecDeque::new(), // System (3)
             ],
             credits: [0; 4],
+            weights: Self::weight_array(&QosWeights::default()),
+            aging_interval: None,
+            counters: [PriorityCounters::default(); 4],
             total_items: 0,
+            total_bytes: 0,
+            max_items,
+            max_bytes,
+            next_order: 0,
+            dropped_best_effort: 0,
+            dropped_expired: 0,
         }
     }
     
+    /// Use other shares per level; a zero weight counts as one so no level stalls
+    pub fn with_weights(mut self, weights: &QosWeights) -> Self {
+        self.weights = Self::weight_array(weights);
+        self
+    }
+    
+    /// Raise waiting items one level per `interval` (None = strict rounds)
+    pub fn with_aging(mut self, interval: Option<Duration>) -> Self {
+        self.aging_interval = interval.filter(|interval| !interval.is_zero());
+        self
+    }
+    
+    fn weight_array(weights: &QosWeights) -> [usize; 4] {
+        let weight = |value: u8| {
+            let priority = QosPriority::from_value(value).unwrap();
+            weights.weight(priority).max(1) as usize
+        };
+        [weight(0), weight(1), weight(2), weight(3)]
+    }
+    
     /// Enqueue an item with priority
+    ///
+    /// The item is never dropped or refused and counts toward the item
+    /// capacity only; meant for control frames that must go out.
     pub fn enqueue(&mut self, item: T, priority: QosPriority) {
+        self.enqueue_sized(item, priority, 0, DeliveryMode::Reliable);
+    }
+    
+    /// Enqueue an item of `bytes` without checking capacity
+    ///
+    /// Call `make_room` first to keep the queue within its bounds.
+    pub fn enqueue_sized(&mut self, item: T, priority: QosPriority, bytes: usize, mode: DeliveryMode) {
         let index = priority.value() as usize;
-        self.queues[index].push_back(item);
+        self.queues[index].push_back(Queued {
+            data: item,
+            bytes,
+            mode,
+            queued_at: Instant::now(),
+            order: self.next_order,
+        });
+        self.counters[index].enqueued += 1;
+        self.next_order += 1;
         self.total_items += 1;
+        self.total_bytes += bytes;
     }
     
-    /// Dequeue an item using weighted fair queuing
-    /// 
-    /// Higher priority queues get more credits and are served more frequently
-    pub fn dequeue(&mut self) -> Option<T> {
-        if self.total_items == 0 {
-            return None;
+    /// Enqueue an item of `bytes`, applying the drop policy if it doesn't fit
+    ///
+    /// A BestEffort item that finds no room is dropped (and counted); any
+    /// other item is handed back.
+    pub fn try_enqueue(&mut self, item: T, priority: QosPriority, bytes: usize, mode: DeliveryMode) -> Result<(), T> {
+        if self.make_room(1, bytes) {
+            self.enqueue_sized(item, priority, bytes, mode);
+            return Ok(());
         }
-        
-        // Refill credits based on weights
-        self.refill_credits();
-        
-        // Try to dequeue from highest priority queue with credits
-        for priority_value in (0..4).rev() {
-            if self.credits[priority_value] > 0 && !self.queues[priority_value].is_empty() {
-                self.credits[priority_value] -= 1;
-                self.total_items -= 1;
-                return self.queues[priority_value].pop_front();
+        if mode == DeliveryMode::BestEffort {
+            self.dropped_best_effort += 1;
+            self.counters[priority.value() as usize].dropped += 1;
+            return Ok(());
+        }
+        Err(item)
+    }
+    
+    /// Drop expired and BestEffort items until `items` more items of `bytes`
+    /// in total fit; false if they still don't
+    pub fn make_room(&mut self, items: usize, bytes: usize) -> bool {
+        if self.fits(items, bytes) {
+            return true;
+        }
+        self.drop_expired();
+        while !self.fits(items, bytes) {
+            if !self.drop_oldest_best_effort() {
+                return false;
             }
         }
-        
-        // Fallback: dequeue from any non-empty queue
-        for priority_value in (0..4).rev() {
-            if !self.queues[priority_value].is_empty() {
-                self.total_items -= 1;
-                return self.queues[priority_value].pop_front();
+        true
+    }
+    
+    fn fits(&self, items: usize, bytes: usize) -> bool {
+        self.total_items.saturating_add(items) <= self.max_items
+            && self.total_bytes.saturating_add(bytes) <= self.max_bytes
+    }
+    
+    /// Drop PartiallyReliable items queued for longer than their TTL
+    fn drop_expired(&mut self) {
+        let now = Instant::now();
+        for (index, queue) in self.queues.iter_mut().enumerate() {
+            queue.retain(|queued| {
+                let expired = queued.deadline().is_some_and(|deadline| deadline <= now);
+                if expired {
+                    self.total_items -= 1;
+                    self.total_bytes -= queued.bytes;
+                    self.dropped_expired += 1;
+                    self.counters[index].dropped += 1;
+                }
+                !expired
+            });
+        }
+    }
+    
+    /// Drop the BestEffort item queued first; false if there is none
+    fn drop_oldest_best_effort(&mut self) -> bool {
+        // Queues are FIFO, so each one's first BestEffort item is its oldest
+        let oldest = self.queues.iter().enumerate()
+            .filter_map(|(index, queue)| {
+                let position = queue.iter().position(|queued| queued.mode == DeliveryMode::BestEffort)?;
+                Some((queue[position].order, index, position))
+            })
+            .min();
+        match oldest {
+            Some((_, index, position)) => {
+                if let Some(queued) = self.queues[index].remove(position) {
+                    self.total_items -= 1;
+                    self.total_bytes -= queued.bytes;
+                    self.dropped_best_effort += 1;
+                    self.counters[index].dropped += 1;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+    
+    /// Take the next item of one priority level, dropping expired ones on
+    /// the way; None once the level is empty
+    fn pop(&mut self, index: usize) -> Option<T> {
+        let now = Instant::now();
+        loop {
+            let front = self.queues[index].front()?;
+            let position = match front.deadline() {
+                Some(_) => self.earliest_deadline(index),
+                None => 0,
+            };
+            let queued = self.queues[index].remove(position)?;
+            self.total_items -= 1;
+            self.total_bytes -= queued.bytes;
+            
+            if queued.deadline().is_some_and(|deadline| deadline <= now) {
+                self.dropped_expired += 1;
+                self.counters[index].dropped += 1;
+                continue;
+            }
+            let delay = now.saturating_duration_since(queued.queued_at);
+            let counters = &mut self.counters[index];
+            counters.dequeued += 1;
+            counters.queue_delay_total += delay;
+            counters.queue_delay_max = counters.queue_delay_max.max(delay);
+            return Some(queued.data);
+        }
+    }
+    
+    /// Position of the PartiallyReliable item with the earliest deadline,
+    /// the first queued among equals
+    fn earliest_deadline(&self, index: usize) -> usize {
+        self.queues[index].iter().enumerate()
+            .filter_map(|(position, queued)| Some((queued.deadline()?, position)))
+            .min()
+            .map_or(0, |(_, position)| position)
+    }
+    
+    /// Dequeue an item using weighted fair queuing
+    /// 
+    /// Takes from the highest priority level with items and credit left;
+    /// once none has credit, a new round starts. An aged item that ranks
+    /// above that level's front item goes first, spending its own level's
+    /// credit if it has any.
+    pub fn dequeue(&mut self) -> Option<T> {
+        // A level may turn out to hold only expired items
+        while self.total_items > 0 {
+            let index = match self.next_with_credit() {
+                Some(index) => index,
+                None => {
+                    self.refill_credits();
+                    self.next_with_credit()?
+                }
+            };
+            let index = self.aged_ahead_of(index).unwrap_or(index);
+            if let Some(item) = self.pop(index) {
+                self.credits[index] = self.credits[index].saturating_sub(1);
+                return Some(item);
             }
         }
-        
         None
     }
     
-    /// Refill credits for weighted fair queuing
+    /// Level whose front item has aged past the front item of `index`
+    fn aged_ahead_of(&self, index: usize) -> Option<usize> {
+        let interval = self.aging_interval?.as_nanos();
+        let now = Instant::now();
+        // Effective level, then age, of a level's front item; queues are
+        // FIFO, so the front item is the one that has waited longest
+        let rank = |level: usize| {
+            let queued = self.queues[level].front()?;
+            let steps = now.duration_since(queued.queued_at).as_nanos() / interval;
+            let effective = (level as u128 + steps).min(3) as usize;
+            Some((effective, Reverse(queued.order)))
+        };
+        let current = rank(index)?;
+        (0..4)
+            .filter(|&level| level != index)
+            .filter_map(|level| Some((rank(level)?, level)))
+            .filter(|&((effective, _), level)| effective > level)
+            .max()
+            .filter(|&(rank, _)| rank > current)
+            .map(|(_, level)| level)
+    }
+    
+    /// Highest priority level with items and credit
+    fn next_with_credit(&self) -> Option<usize> {
+        (0..4).rev().find(|&index| self.credits[index] > 0 && !self.queues[index].is_empty())
+    }
+    
+    /// Start a round: levels with items get their weight in credits, empty
+    /// ones none, so idle levels don't save up credit
     fn refill_credits(&mut self) {
-        for priority_value in 0..4 {
-            if !self.queues[priority_value].is_empty() {
-                let priority = QosPriority::from_value(priority_value as u8).unwrap();
-                self.credits[priority_value] += priority.weight();
-            }
+        for index in 0..4 {
+            self.credits[index] = if self.queues[index].is_empty() { 0 } else { self.weights[index] };
         }
     }
     
@@ -91,6 +347,26 @@ impl<T> PriorityQueue<T> {
         self.total_items == 0
     }
     
+    /// Bytes queued, as given on enqueue
+    pub fn bytes(&self) -> usize {
+        self.total_bytes
+    }
+    
+    /// BestEffort items dropped to make room or for lack of it
+    pub fn dropped_best_effort(&self) -> u64 {
+        self.dropped_best_effort
+    }
+    
+    /// PartiallyReliable items dropped after their TTL ran out in the queue
+    pub fn dropped_expired(&self) -> u64 {
+        self.dropped_expired
+    }
+    
+    /// Enqueue, dequeue and drop counts of one priority level
+    pub fn counters(&self, priority: QosPriority) -> PriorityCounters {
+        self.counters[priority.value() as usize]
+    }
+    
     /// Get number of items for specific priority
     pub fn len_for_priority(&self, priority: QosPriority) -> usize {
         self.queues[priority.value() as usize].len()
@@ -103,6 +379,7 @@ impl<T> PriorityQueue<T> {
         }
         self.credits = [0; 4];
         self.total_items = 0;
+        self.total_bytes = 0;
     }
 }
 
@@ -173,6 +450,173 @@ mod tests {
         assert_eq!(bulk_count, 10);
     }
     
+    #[test]
+    fn test_flood_of_high_priority_does_not_starve_bulk() {
+        let mut queue = PriorityQueue::new();
+        for i in 0..100 {
+            queue.enqueue(("system", i), QosPriority::System);
+            queue.enqueue(("bulk", i), QosPriority::Bulk);
+        }
+        
+        // 8 System items then 1 Bulk, round after round
+        let served: Vec<&str> = (0..18).map(|_| queue.dequeue().unwrap().0).collect();
+        let mut expected = Vec::new();
+        for _ in 0..2 {
+            expected.extend(["system"; 8]);
+            expected.push("bulk");
+        }
+        assert_eq!(served, expected);
+        
+        // New high priority data keeps coming; bulk keeps moving
+        let mut bulk_served = 0;
+        for i in 0..90 {
+            queue.enqueue(("system", 100 + i), QosPriority::System);
+            if queue.dequeue().unwrap().0 == "bulk" {
+                bulk_served += 1;
+            }
+        }
+        assert_eq!(bulk_served, 10);
+    }
+    
+    #[test]
+    fn test_custom_weights() {
+        let mut queue = PriorityQueue::new().with_weights(&QosWeights { system: 1, media: 0, chat: 1, bulk: 2 });
+        for i in 0..4 {
+            queue.enqueue(i, QosPriority::System);
+            queue.enqueue(10 + i, QosPriority::Media);
+            queue.enqueue(20 + i, QosPriority::Bulk);
+        }
+        
+        // Zero counts as one
+        let served: Vec<i32> = (0..8).map(|_| queue.dequeue().unwrap()).collect();
+        assert_eq!(served, vec![0, 10, 20, 21, 1, 11, 22, 23]);
+    }
+    
+    #[test]
+    fn test_aging_sends_low_priority_under_flood() {
+        let aging = Duration::from_millis(10);
+        // Rounds alone would serve the Bulk item after 200 System items
+        let weights = QosWeights { system: 200, media: 1, chat: 1, bulk: 1 };
+        let mut queue = PriorityQueue::new().with_weights(&weights).with_aging(Some(aging));
+        queue.enqueue("system", QosPriority::System);
+        queue.enqueue("bulk", QosPriority::Bulk);
+        let queued_at = Instant::now();
+        
+        // Continuous System traffic, one item in for every one out
+        let mut sent_after = None;
+        for sent in 0..200 {
+            queue.enqueue("system", QosPriority::System);
+            if queue.dequeue() == Some("bulk") {
+                sent_after = Some(sent);
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let sent_after = sent_after.expect("Bulk item starved");
+        assert!(sent_after < 200);
+        // Three levels to climb before it outranks System
+        let waited = queued_at.elapsed();
+        assert!(waited >= 3 * aging, "{:?}", waited);
+        
+        // Without aging it waits out the round
+        let mut queue = PriorityQueue::new().with_weights(&weights);
+        queue.enqueue("system", QosPriority::System);
+        queue.enqueue("bulk", QosPriority::Bulk);
+        std::thread::sleep(4 * aging);
+        let position = (0..300)
+            .position(|_| {
+                queue.enqueue("system", QosPriority::System);
+                queue.dequeue() == Some("bulk")
+            })
+            .unwrap();
+        assert_eq!(position, 200);
+    }
+    
+    #[test]
+    fn test_aging_raises_one_level_per_interval() {
+        let mut queue = PriorityQueue::new().with_aging(Some(Duration::from_millis(50)));
+        queue.enqueue("bulk", QosPriority::Bulk);
+        std::thread::sleep(Duration::from_millis(60));
+        // Aged to Chat: ahead of fresh Chat, still behind Media
+        queue.enqueue("chat", QosPriority::Chat);
+        queue.enqueue("media", QosPriority::Media);
+        assert_eq!(queue.dequeue(), Some("media"));
+        assert_eq!(queue.dequeue(), Some("bulk"));
+        assert_eq!(queue.dequeue(), Some("chat"));
+        
+        // A zero interval disables aging
+        let mut queue = PriorityQueue::new().with_aging(Some(Duration::ZERO));
+        queue.enqueue("bulk", QosPriority::Bulk);
+        queue.enqueue("chat", QosPriority::Chat);
+        assert_eq!(queue.dequeue(), Some("chat"));
+    }
+    
+    #[test]
+    fn test_partially_reliable_earliest_deadline_first() {
+        let mut queue = PriorityQueue::new();
+        let slack = DeliveryMode::PartiallyReliable { ttl_ms: 1000 };
+        let urgent = DeliveryMode::PartiallyReliable { ttl_ms: 100 };
+        let expiring = DeliveryMode::PartiallyReliable { ttl_ms: 10 };
+        
+        queue.try_enqueue("slack", QosPriority::Media, 10, slack).unwrap();
+        queue.try_enqueue("urgent", QosPriority::Media, 10, urgent).unwrap();
+        queue.try_enqueue("expiring", QosPriority::Media, 10, expiring).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        
+        // The expired one is dropped, not sent; then nearest deadline first
+        assert_eq!(queue.dequeue(), Some("urgent"));
+        assert_eq!(queue.dropped_expired(), 1);
+        assert_eq!(queue.dequeue(), Some("slack"));
+        assert_eq!(queue.dequeue(), None);
+        assert_eq!(queue.bytes(), 0);
+        
+        let media = queue.counters(QosPriority::Media);
+        assert_eq!((media.enqueued, media.dequeued, media.dropped), (3, 2, 1));
+    }
+    
+    #[test]
+    fn test_deadlines_keep_other_items_turn() {
+        let mut queue = PriorityQueue::new();
+        let slack = DeliveryMode::PartiallyReliable { ttl_ms: 1000 };
+        let urgent = DeliveryMode::PartiallyReliable { ttl_ms: 100 };
+        
+        queue.try_enqueue("reliable 1", QosPriority::Chat, 10, DeliveryMode::Reliable).unwrap();
+        queue.try_enqueue("slack", QosPriority::Chat, 10, slack).unwrap();
+        queue.try_enqueue("urgent", QosPriority::Chat, 10, urgent).unwrap();
+        queue.try_enqueue("reliable 2", QosPriority::Chat, 10, DeliveryMode::Reliable).unwrap();
+        let served: Vec<&str> = std::iter::from_fn(|| queue.dequeue()).collect();
+        assert_eq!(served, vec!["reliable 1", "urgent", "slack", "reliable 2"]);
+        
+        // A level holding only expired items doesn't end the drain early
+        let expiring = DeliveryMode::PartiallyReliable { ttl_ms: 1 };
+        queue.try_enqueue("expired", QosPriority::System, 10, expiring).unwrap();
+        queue.try_enqueue("bulk", QosPriority::Bulk, 10, DeliveryMode::Reliable).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(queue.dequeue(), Some("bulk"));
+        assert!(queue.is_empty());
+    }
+    
+    #[test]
+    fn test_priority_counters() {
+        let mut queue = PriorityQueue::with_capacity(2, usize::MAX);
+        
+        queue.try_enqueue(1, QosPriority::Bulk, 10, DeliveryMode::BestEffort).unwrap();
+        queue.try_enqueue(2, QosPriority::Media, 10, DeliveryMode::Reliable).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        // Sheds the Bulk item
+        queue.try_enqueue(3, QosPriority::Media, 10, DeliveryMode::Reliable).unwrap();
+        assert_eq!(queue.dequeue(), Some(2));
+        
+        let media = queue.counters(QosPriority::Media);
+        assert_eq!((media.enqueued, media.dequeued, media.dropped), (2, 1, 0));
+        assert!(media.queue_delay_max >= Duration::from_millis(5));
+        assert_eq!(media.queue_delay_total, media.queue_delay_max);
+        
+        let bulk = queue.counters(QosPriority::Bulk);
+        assert_eq!((bulk.enqueued, bulk.dequeued, bulk.dropped), (1, 0, 1));
+        assert_eq!(queue.counters(QosPriority::System), PriorityCounters::default());
+    }
+    
     #[test]
     fn test_priority_queue_len() {
         let mut queue = PriorityQueue::new();
@@ -223,4 +667,63 @@ mod tests {
         
         assert_eq!(queue.dequeue(), None);
     }
+    
+    #[test]
+    fn test_full_queue_drops_oldest_best_effort() {
+        let mut queue = PriorityQueue::with_capacity(3, usize::MAX);
+        
+        queue.try_enqueue("old", QosPriority::Media, 10, DeliveryMode::BestEffort).unwrap();
+        queue.try_enqueue("reliable", QosPriority::Bulk, 10, DeliveryMode::Reliable).unwrap();
+        queue.try_enqueue("newer", QosPriority::Bulk, 10, DeliveryMode::BestEffort).unwrap();
+        
+        // Oldest first, regardless of priority
+        queue.try_enqueue("newest", QosPriority::Chat, 10, DeliveryMode::BestEffort).unwrap();
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.dropped_best_effort(), 1);
+        
+        let mut remaining = Vec::new();
+        while let Some(item) = queue.dequeue() {
+            remaining.push(item);
+        }
+        remaining.sort();
+        assert_eq!(remaining, vec!["newer", "newest", "reliable"]);
+    }
+    
+    #[test]
+    fn test_full_queue_refuses_reliable() {
+        let mut queue = PriorityQueue::with_capacity(usize::MAX, 100);
+        
+        queue.try_enqueue(1, QosPriority::Chat, 60, DeliveryMode::Reliable).unwrap();
+        queue.try_enqueue(2, QosPriority::Chat, 30, DeliveryMode::BestEffort).unwrap();
+        
+        // Shedding the BestEffort item makes room
+        queue.try_enqueue(3, QosPriority::Chat, 40, DeliveryMode::Reliable).unwrap();
+        assert_eq!(queue.bytes(), 100);
+        assert_eq!(queue.dropped_best_effort(), 1);
+        
+        // Nothing left to shed: reliable data comes back, BestEffort is dropped
+        assert_eq!(queue.try_enqueue(4, QosPriority::System, 1, DeliveryMode::Reliable), Err(4));
+        assert_eq!(queue.try_enqueue(5, QosPriority::System, 1, DeliveryMode::BestEffort), Ok(()));
+        assert_eq!(queue.dropped_best_effort(), 2);
+        assert_eq!(queue.len(), 2);
+        
+        queue.dequeue();
+        assert!(queue.make_room(1, 60));
+    }
+    
+    #[test]
+    fn test_full_queue_drops_expired_partially_reliable() {
+        let mut queue = PriorityQueue::with_capacity(2, usize::MAX);
+        let ttl = DeliveryMode::PartiallyReliable { ttl_ms: 10 };
+        
+        queue.try_enqueue(1, QosPriority::Media, 10, ttl).unwrap();
+        queue.try_enqueue(2, QosPriority::Media, 10, DeliveryMode::Reliable).unwrap();
+        assert!(queue.try_enqueue(3, QosPriority::Media, 10, ttl).is_err());
+        
+        std::thread::sleep(Duration::from_millis(20));
+        queue.try_enqueue(3, QosPriority::Media, 10, ttl).unwrap();
+        assert_eq!(queue.dropped_expired(), 1);
+        assert_eq!(queue.dequeue(), Some(2));
+        assert_eq!(queue.dequeue(), Some(3));
+    }
 }
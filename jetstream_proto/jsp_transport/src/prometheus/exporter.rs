@@ -4,8 +4,11 @@
 
 use std::net::SocketAddr;
 
-use hyper::{Body, Request, Response, Server, StatusCode};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::header::ALLOW;
 use hyper::service::{make_service_fn, service_fn};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
 
 /// Metrics exporter server
 pub struct MetricsExporter {
@@ -32,10 +35,64 @@ impl MetricsExporter {
         
         Ok(())
     }
+
+    /// Serve `/metrics` and `/healthz` in a background task
+    ///
+    /// Binding to port 0 picks an ephemeral port; see `ExporterHandle::local_addr`.
+    pub async fn serve(bind_addr: SocketAddr) -> Result<ExporterHandle, Box<dyn std::error::Error>> {
+        let make_svc = make_service_fn(|_conn| async {
+            Ok::<_, hyper::Error>(service_fn(handle_metrics_request))
+        });
+
+        let server = Server::try_bind(&bind_addr)?.serve(make_svc);
+        let local_addr = server.local_addr();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+
+        let task = tokio::spawn(server.with_graceful_shutdown(async {
+            let _ = shutdown_rx.await;
+        }));
+
+        tracing::info!("Metrics server listening on http://{}/metrics", local_addr);
+
+        Ok(ExporterHandle {
+            local_addr,
+            shutdown: shutdown_tx,
+            task,
+        })
+    }
+}
+
+/// Handle to a running metrics server started with `MetricsExporter::serve`
+pub struct ExporterHandle {
+    local_addr: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<Result<(), hyper::Error>>,
+}
+
+impl ExporterHandle {
+    /// Address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop accepting connections and wait for in-flight requests to finish
+    pub async fn shutdown(self) -> Result<(), Box<dyn std::error::Error>> {
+        let _ = self.shutdown.send(());
+        self.task.await??;
+        Ok(())
+    }
 }
 
 /// Handle metrics HTTP request
 async fn handle_metrics_request(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+    if req.method() != Method::GET {
+        return Ok(Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header(ALLOW, "GET")
+            .body(Body::from("Method Not Allowed"))
+            .unwrap());
+    }
+
     match req.uri().path() {
         "/metrics" => {
             match crate::prometheus::export_metrics() {
@@ -55,7 +112,7 @@ async fn handle_metrics_request(req: Request<Body>) -> Result<Response<Body>, hy
                 }
             }
         }
-        "/health" => {
+        "/health" | "/healthz" => {
             Ok(Response::builder()
                 .status(StatusCode::OK)
                 .body(Body::from("OK"))
@@ -83,4 +140,38 @@ mod tests {
         assert!(output.contains("jsp_connections_total"));
         assert!(output.contains("jsp_bytes_sent_total"));
     }
+
+    /// Send a raw HTTP/1.1 request and return the whole response
+    async fn http_request(addr: SocketAddr, method: &str, path: &str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!("{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", method, path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_serve_metrics_over_http() {
+        let handle = MetricsExporter::serve("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let addr = handle.local_addr();
+        assert_ne!(addr.port(), 0);
+
+        let response = http_request(addr, "GET", "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.to_ascii_lowercase().contains("content-type: text/plain; version=0.0.4"));
+        assert!(response.contains("# TYPE jsp_connections_total counter"));
+
+        let response = http_request(addr, "GET", "/healthz").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+
+        let response = http_request(addr, "POST", "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 405"));
+
+        handle.shutdown().await.unwrap();
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    }
 }
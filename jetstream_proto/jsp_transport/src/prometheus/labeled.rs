@@ -0,0 +1,360 @@
+//! Per-Connection Labeled Metrics
+//!
+//! Series labeled by session ID and peer address, so a misbehaving connection
+//! can be told apart from the aggregates. The number of label sets is capped;
+//! connections beyond the cap share the `other` series.
+//!
+//! Stream series add a `stream_id` label, capped per connection the same way.
+
+use crate::metrics::StreamStats;
+use prometheus::{Registry, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Label sets tracked before further connections collapse into `other`
+pub const DEFAULT_MAX_CONNECTION_LABELS: usize = 500;
+
+/// Label value used for connections over the cap
+pub const OTHER_LABEL: &str = "other";
+
+const LABELS: &[&str] = &["session_id", "peer"];
+const STREAM_LABELS: &[&str] = &["session_id", "peer", "stream_id"];
+
+/// Metric vectors shared by all connection series of one registry
+pub(crate) struct ConnectionLabels {
+    bytes_sent: IntCounterVec,
+    bytes_received: IntCounterVec,
+    retransmissions: IntCounterVec,
+    cwnd: IntGaugeVec,
+    stream_bytes_sent: IntCounterVec,
+    stream_bytes_received: IntCounterVec,
+    stream_messages_sent: IntCounterVec,
+    stream_messages_received: IntCounterVec,
+    stream_retransmissions: IntCounterVec,
+    max_label_sets: AtomicUsize,
+    // Label set -> number of live series using it
+    active: Mutex<HashMap<(String, String), usize>>,
+}
+
+impl ConnectionLabels {
+    pub(crate) fn new(registry: &Registry) -> prometheus::Result<Self> {
+        let bytes_sent = IntCounterVec::new(
+            Opts::new("jsp_connection_bytes_sent_total", "Bytes sent per connection"),
+            LABELS
+        )?;
+        registry.register(Box::new(bytes_sent.clone()))?;
+
+        let bytes_received = IntCounterVec::new(
+            Opts::new("jsp_connection_bytes_received_total", "Bytes received per connection"),
+            LABELS
+        )?;
+        registry.register(Box::new(bytes_received.clone()))?;
+
+        let retransmissions = IntCounterVec::new(
+            Opts::new("jsp_connection_retransmissions_total", "Retransmitted packets per connection"),
+            LABELS
+        )?;
+        registry.register(Box::new(retransmissions.clone()))?;
+
+        let cwnd = IntGaugeVec::new(
+            Opts::new("jsp_connection_cwnd_bytes", "Current congestion window per connection (summed for `other`)"),
+            LABELS
+        )?;
+        registry.register(Box::new(cwnd.clone()))?;
+
+        let stream_counter = |name: &str, help: &str| -> prometheus::Result<IntCounterVec> {
+            let counter = IntCounterVec::new(Opts::new(name, help), STREAM_LABELS)?;
+            registry.register(Box::new(counter.clone()))?;
+            Ok(counter)
+        };
+        let stream_bytes_sent = stream_counter("jsp_stream_bytes_sent_total", "Application bytes sent per stream")?;
+        let stream_bytes_received = stream_counter("jsp_stream_bytes_received_total", "Application bytes received per stream")?;
+        let stream_messages_sent = stream_counter("jsp_stream_messages_sent_total", "Messages sent per stream")?;
+        let stream_messages_received = stream_counter("jsp_stream_messages_received_total", "Messages received per stream")?;
+        let stream_retransmissions = stream_counter("jsp_stream_retransmissions_total", "Retransmitted packets per stream")?;
+
+        Ok(Self {
+            bytes_sent,
+            bytes_received,
+            retransmissions,
+            cwnd,
+            stream_bytes_sent,
+            stream_bytes_received,
+            stream_messages_sent,
+            stream_messages_received,
+            stream_retransmissions,
+            max_label_sets: AtomicUsize::new(DEFAULT_MAX_CONNECTION_LABELS),
+            active: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub(crate) fn set_max_label_sets(&self, max: usize) {
+        self.max_label_sets.store(max, Ordering::Relaxed);
+    }
+
+    pub(crate) fn series(self: &Arc<Self>, session_id: u64, peer: SocketAddr) -> ConnectionSeries {
+        let mut key = (session_id.to_string(), peer.to_string());
+        {
+            let mut active = self.active.lock().unwrap();
+            let max = self.max_label_sets.load(Ordering::Relaxed);
+            if !active.contains_key(&key) && active.keys().filter(|k| k.0 != OTHER_LABEL).count() >= max {
+                key = (OTHER_LABEL.to_string(), OTHER_LABEL.to_string());
+            }
+            *active.entry(key.clone()).or_insert(0) += 1;
+        }
+
+        let values = [key.0.as_str(), key.1.as_str()];
+        ConnectionSeries {
+            bytes_sent: self.bytes_sent.with_label_values(&values),
+            bytes_received: self.bytes_received.with_label_values(&values),
+            retransmissions: self.retransmissions.with_label_values(&values),
+            cwnd: self.cwnd.with_label_values(&values),
+            last: Mutex::new(Totals::default()),
+            streams: Mutex::new(HashMap::new()),
+            labels: Arc::clone(self),
+            key,
+        }
+    }
+
+    fn release(&self, key: &(String, String)) {
+        let mut active = self.active.lock().unwrap();
+        let remaining = match active.get_mut(key) {
+            Some(count) => {
+                *count -= 1;
+                *count
+            }
+            None => return,
+        };
+        // The `other` series keeps its totals for the life of the registry
+        if remaining == 0 && key.0 != OTHER_LABEL {
+            active.remove(key);
+            let values = [key.0.as_str(), key.1.as_str()];
+            let _ = self.bytes_sent.remove_label_values(&values);
+            let _ = self.bytes_received.remove_label_values(&values);
+            let _ = self.retransmissions.remove_label_values(&values);
+            let _ = self.cwnd.remove_label_values(&values);
+        }
+    }
+
+    fn stream_counters(&self) -> [&IntCounterVec; 5] {
+        [
+            &self.stream_bytes_sent,
+            &self.stream_bytes_received,
+            &self.stream_messages_sent,
+            &self.stream_messages_received,
+            &self.stream_retransmissions,
+        ]
+    }
+
+    fn release_stream(&self, key: &(String, String), stream_label: &str) {
+        let values = [key.0.as_str(), key.1.as_str(), stream_label];
+        for counter in self.stream_counters() {
+            let _ = counter.remove_label_values(&values);
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Totals {
+    bytes_sent: u64,
+    bytes_received: u64,
+    retransmissions: u64,
+    cwnd: i64,
+}
+
+/// A stream's last reported totals, in the order of `ConnectionLabels::stream_counters`
+type StreamTotals = [u64; 5];
+
+/// `stream_id` label a stream reports under and its last totals
+struct StreamEntry {
+    label: String,
+    last: StreamTotals,
+}
+
+/// One connection's labeled series, removed from the export when dropped
+pub struct ConnectionSeries {
+    bytes_sent: IntCounter,
+    bytes_received: IntCounter,
+    retransmissions: IntCounter,
+    cwnd: IntGauge,
+    // Last values seen, so series shared through `other` add up
+    last: Mutex<Totals>,
+    // Stream ID -> its series, for `update_streams`
+    streams: Mutex<HashMap<u32, StreamEntry>>,
+    labels: Arc<ConnectionLabels>,
+    key: (String, String),
+}
+
+impl ConnectionSeries {
+    /// Record the connection's current totals and congestion window
+    pub fn update(&self, bytes_sent: u64, bytes_received: u64, retransmissions: u64, cwnd: u64) {
+        let mut last = self.last.lock().unwrap();
+        self.bytes_sent.inc_by(bytes_sent.saturating_sub(last.bytes_sent));
+        self.bytes_received.inc_by(bytes_received.saturating_sub(last.bytes_received));
+        self.retransmissions.inc_by(retransmissions.saturating_sub(last.retransmissions));
+        let cwnd = cwnd as i64;
+        self.cwnd.add(cwnd - last.cwnd);
+        *last = Totals {
+            bytes_sent: bytes_sent.max(last.bytes_sent),
+            bytes_received: bytes_received.max(last.bytes_received),
+            retransmissions: retransmissions.max(last.retransmissions),
+            cwnd,
+        };
+    }
+
+    /// Whether this connection was over the cap and reports as `other`
+    pub fn is_collapsed(&self) -> bool {
+        self.key.0 == OTHER_LABEL
+    }
+
+    /// Record the current totals of every stream of the connection
+    ///
+    /// The first `max_labels` streams get their own `stream_id` label, later
+    /// ones share `other`. Streams missing from `streams` are taken out of the
+    /// export. Connections reporting as `other` export no stream series.
+    pub fn update_streams(&self, streams: &[StreamStats], max_labels: usize) {
+        if self.is_collapsed() {
+            return;
+        }
+        let mut entries = self.streams.lock().unwrap();
+
+        let gone: Vec<u32> = entries.keys()
+            .filter(|id| !streams.iter().any(|stats| stats.stream_id == **id))
+            .copied()
+            .collect();
+        for id in gone {
+            if let Some(entry) = entries.remove(&id) {
+                if entry.label != OTHER_LABEL {
+                    self.labels.release_stream(&self.key, &entry.label);
+                }
+            }
+        }
+
+        for stats in streams {
+            let labeled = entries.values().filter(|entry| entry.label != OTHER_LABEL).count();
+            let entry = entries.entry(stats.stream_id).or_insert_with(|| StreamEntry {
+                label: if labeled < max_labels { stats.stream_id.to_string() } else { OTHER_LABEL.to_string() },
+                last: StreamTotals::default(),
+            });
+            let totals: StreamTotals = [
+                stats.bytes_sent,
+                stats.bytes_received,
+                stats.messages_sent,
+                stats.messages_received,
+                stats.retransmits,
+            ];
+            let values = [self.key.0.as_str(), self.key.1.as_str(), entry.label.as_str()];
+            for ((counter, total), last) in self.labels.stream_counters().into_iter().zip(totals).zip(&mut entry.last) {
+                counter.with_label_values(&values).inc_by(total.saturating_sub(*last));
+                *last = total.max(*last);
+            }
+        }
+    }
+}
+
+impl Drop for ConnectionSeries {
+    fn drop(&mut self) {
+        let cwnd = self.last.lock().unwrap().cwnd;
+        self.cwnd.sub(cwnd);
+        if !self.is_collapsed() {
+            let entries = self.streams.lock().unwrap();
+            for entry in entries.values().filter(|entry| entry.label != OTHER_LABEL) {
+                self.labels.release_stream(&self.key, &entry.label);
+            }
+            // Also present if streams over the cap have gone away since
+            self.labels.release_stream(&self.key, OTHER_LABEL);
+        }
+        self.labels.release(&self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label_sets_are_capped() {
+        let registry = Registry::new();
+        let labels = Arc::new(ConnectionLabels::new(&registry).unwrap());
+        labels.set_max_label_sets(1);
+        let peer: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+
+        let first = labels.series(1, peer);
+        let second = labels.series(2, peer);
+        let third = labels.series(3, peer);
+        assert!(!first.is_collapsed());
+        assert!(second.is_collapsed() && third.is_collapsed());
+
+        first.update(100, 10, 1, 12000);
+        second.update(50, 0, 0, 6000);
+        third.update(25, 0, 2, 3000);
+        second.update(60, 0, 0, 6000);
+
+        let other = [OTHER_LABEL, OTHER_LABEL];
+        assert_eq!(labels.bytes_sent.with_label_values(&["1", "10.0.0.1:4000"]).get(), 100);
+        assert_eq!(labels.bytes_sent.with_label_values(&other).get(), 85);
+        assert_eq!(labels.retransmissions.with_label_values(&other).get(), 2);
+        assert_eq!(labels.cwnd.with_label_values(&other).get(), 9000);
+
+        // Dropping a labeled connection frees its slot and removes its series
+        drop(first);
+        drop(third);
+        assert_eq!(labels.cwnd.with_label_values(&other).get(), 6000);
+        let fourth = labels.series(4, peer);
+        assert!(!fourth.is_collapsed());
+        let exported: Vec<String> = registry.gather().iter()
+            .flat_map(|family| family.get_metric().iter()
+                .flat_map(|m| m.get_label().iter().map(|l| l.get_value().to_string()).collect::<Vec<_>>())
+                .collect::<Vec<_>>())
+            .collect();
+        assert!(!exported.contains(&"1".to_string()));
+        assert!(exported.contains(&"4".to_string()));
+    }
+
+    #[test]
+    fn test_stream_label_sets_are_capped() {
+        use jsp_core::types::delivery::DeliveryMode;
+
+        let registry = Registry::new();
+        let labels = Arc::new(ConnectionLabels::new(&registry).unwrap());
+        let series = labels.series(1, "10.0.0.1:4000".parse().unwrap());
+        let stats = |stream_id, bytes_sent| StreamStats {
+            stream_id,
+            bytes_sent,
+            bytes_received: 0,
+            messages_sent: 1,
+            messages_received: 0,
+            retransmits: 0,
+            delivery_mode: DeliveryMode::Reliable,
+            priority: Some(1),
+        };
+        let exported_streams = || -> Vec<(String, u64)> {
+            registry.gather().iter()
+                .filter(|family| family.get_name() == "jsp_stream_bytes_sent_total")
+                .flat_map(|family| family.get_metric().iter()
+                    .map(|m| {
+                        let label = m.get_label().iter().find(|l| l.get_name() == "stream_id").unwrap();
+                        (label.get_value().to_string(), m.get_counter().get_value() as u64)
+                    })
+                    .collect::<Vec<_>>())
+                .collect()
+        };
+
+        series.update_streams(&[stats(1, 100), stats(2, 50), stats(3, 25)], 2);
+        series.update_streams(&[stats(1, 150), stats(2, 50), stats(3, 30)], 2);
+        let mut streams = exported_streams();
+        streams.sort();
+        assert_eq!(streams, vec![("1".to_string(), 150), ("2".to_string(), 50), (OTHER_LABEL.to_string(), 30)]);
+
+        // A released stream leaves the export and frees its label
+        series.update_streams(&[stats(1, 150), stats(3, 30), stats(4, 10)], 2);
+        let mut streams = exported_streams();
+        streams.sort();
+        assert_eq!(streams, vec![("1".to_string(), 150), ("4".to_string(), 10), (OTHER_LABEL.to_string(), 30)]);
+
+        drop(series);
+        assert!(exported_streams().is_empty());
+    }
+}
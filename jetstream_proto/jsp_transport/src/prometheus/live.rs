@@ -0,0 +1,237 @@
+//! Live Metrics Sources
+//!
+//! Mirrors the counters of running connections and servers into Prometheus
+//! metrics, refreshed on every scrape.
+
+use crate::metrics::Metrics;
+use prometheus::{Registry, IntCounter, IntGauge, IntGaugeVec, Gauge, Opts};
+use prometheus::proto::MetricFamily;
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+
+/// Something that contributes metrics to `export_metrics()`
+pub trait MetricsSource: Send + Sync {
+    /// Copy the latest values into the Prometheus metrics
+    ///
+    /// Returns false once the underlying connection or server is gone, after
+    /// which the source is dropped from the registry.
+    fn refresh(&self) -> bool;
+
+    /// Collect the metric families of this source
+    fn gather(&self) -> Vec<MetricFamily>;
+}
+
+fn labeled(name: &str, help: &str, labels: &[(&str, &str)]) -> Opts {
+    let const_labels: HashMap<String, String> = labels.iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    Opts::new(name, help).const_labels(const_labels)
+}
+
+/// Set a counter to an absolute value taken from a snapshot
+fn sync_counter(counter: &IntCounter, value: u64) {
+    let current = counter.get();
+    if value > current {
+        counter.inc_by(value - current);
+    }
+}
+
+/// Prometheus view of one `Metrics` instance
+///
+/// Each source has its own registry, so sources with different label keys
+/// can coexist; labels should identify the connection (e.g. a connection ID).
+pub struct LiveMetrics {
+    registry: Registry,
+    source: Weak<Metrics>,
+    packets_sent: IntCounter,
+    packets_received: IntCounter,
+    bytes_sent: IntCounter,
+    bytes_received: IntCounter,
+    retransmissions: IntCounter,
+    errors: IntCounter,
+    timeouts: IntCounter,
+    smoothed_rtt_ms: Gauge,
+    loss_rate: Gauge,
+}
+
+impl LiveMetrics {
+    pub fn new(source: &Arc<Metrics>, labels: &[(&str, &str)]) -> prometheus::Result<Self> {
+        let registry = Registry::new();
+
+        let packets_sent = IntCounter::with_opts(labeled("jsp_packets_sent_total", "Total packets sent", labels))?;
+        registry.register(Box::new(packets_sent.clone()))?;
+
+        let packets_received = IntCounter::with_opts(labeled("jsp_packets_received_total", "Total packets received", labels))?;
+        registry.register(Box::new(packets_received.clone()))?;
+
+        let bytes_sent = IntCounter::with_opts(labeled("jsp_bytes_sent_total", "Total bytes sent", labels))?;
+        registry.register(Box::new(bytes_sent.clone()))?;
+
+        let bytes_received = IntCounter::with_opts(labeled("jsp_bytes_received_total", "Total bytes received", labels))?;
+        registry.register(Box::new(bytes_received.clone()))?;
+
+        let retransmissions = IntCounter::with_opts(labeled("jsp_retransmissions_total", "Total number of retransmissions", labels))?;
+        registry.register(Box::new(retransmissions.clone()))?;
+
+        let errors = IntCounter::with_opts(labeled("jsp_errors_total", "Total number of errors", labels))?;
+        registry.register(Box::new(errors.clone()))?;
+
+        let timeouts = IntCounter::with_opts(labeled("jsp_timeouts_total", "Total number of timeouts", labels))?;
+        registry.register(Box::new(timeouts.clone()))?;
+
+        let smoothed_rtt_ms = Gauge::with_opts(labeled("jsp_smoothed_rtt_milliseconds", "Smoothed round-trip time in milliseconds", labels))?;
+        registry.register(Box::new(smoothed_rtt_ms.clone()))?;
+
+        let loss_rate = Gauge::with_opts(labeled("jsp_loss_rate", "Fraction of sent packets lost (0-1)", labels))?;
+        registry.register(Box::new(loss_rate.clone()))?;
+
+        Ok(Self {
+            registry,
+            source: Arc::downgrade(source),
+            packets_sent,
+            packets_received,
+            bytes_sent,
+            bytes_received,
+            retransmissions,
+            errors,
+            timeouts,
+            smoothed_rtt_ms,
+            loss_rate,
+        })
+    }
+
+    /// Registry holding this source's metrics, for adding extra ones
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+}
+
+impl MetricsSource for LiveMetrics {
+    fn refresh(&self) -> bool {
+        let metrics = match self.source.upgrade() {
+            Some(metrics) => metrics,
+            None => return false,
+        };
+        let snapshot = metrics.snapshot();
+
+        sync_counter(&self.packets_sent, snapshot.packets_sent);
+        sync_counter(&self.packets_received, snapshot.packets_received);
+        sync_counter(&self.bytes_sent, snapshot.bytes_sent);
+        sync_counter(&self.bytes_received, snapshot.bytes_received);
+        sync_counter(&self.retransmissions, snapshot.packets_retransmitted);
+        sync_counter(&self.errors, snapshot.connection_errors);
+        sync_counter(&self.timeouts, snapshot.timeouts);
+        self.smoothed_rtt_ms.set(metrics.get_avg_rtt().as_secs_f64() * 1000.0);
+        self.loss_rate.set(snapshot.loss_rate);
+        true
+    }
+
+    fn gather(&self) -> Vec<MetricFamily> {
+        self.registry.gather()
+    }
+}
+
+/// Per-session values read from a server's session table on scrape
+pub trait SessionTable: Send + Sync {
+    /// Seconds since each session's last activity, keyed by connection ID;
+    /// `None` if the table is busy and the previous values should be kept
+    fn idle_seconds(&self) -> Option<Vec<(u64, f64)>>;
+}
+
+/// Server metrics: traffic counters plus session count and per-session gauges
+pub struct LiveServerMetrics {
+    traffic: LiveMetrics,
+    sessions: Weak<dyn SessionTable>,
+    sessions_active: IntGauge,
+    session_idle_seconds: IntGaugeVec,
+}
+
+impl LiveServerMetrics {
+    pub fn new(source: &Arc<Metrics>, sessions: Weak<dyn SessionTable>, labels: &[(&str, &str)]) -> prometheus::Result<Self> {
+        let traffic = LiveMetrics::new(source, labels)?;
+
+        let sessions_active = IntGauge::with_opts(labeled("jsp_sessions_active", "Number of sessions tracked by the server", labels))?;
+        traffic.registry().register(Box::new(sessions_active.clone()))?;
+
+        let session_idle_seconds = IntGaugeVec::new(
+            labeled("jsp_session_idle_seconds", "Seconds since the session's last activity", labels),
+            &["connection_id"]
+        )?;
+        traffic.registry().register(Box::new(session_idle_seconds.clone()))?;
+
+        Ok(Self {
+            traffic,
+            sessions,
+            sessions_active,
+            session_idle_seconds,
+        })
+    }
+}
+
+impl MetricsSource for LiveServerMetrics {
+    fn refresh(&self) -> bool {
+        if !self.traffic.refresh() {
+            return false;
+        }
+        let sessions = match self.sessions.upgrade() {
+            Some(sessions) => sessions,
+            None => return false,
+        };
+
+        if let Some(idle) = sessions.idle_seconds() {
+            self.sessions_active.set(idle.len() as i64);
+            // Drop series of sessions that have gone away
+            self.session_idle_seconds.reset();
+            for (connection_id, seconds) in idle {
+                self.session_idle_seconds
+                    .with_label_values(&[&connection_id.to_string()])
+                    .set(seconds as i64);
+            }
+        }
+        true
+    }
+
+    fn gather(&self) -> Vec<MetricFamily> {
+        self.traffic.gather()
+    }
+}
+
+/// Combine families with the same name from different registries
+pub(crate) fn merge_families(families: Vec<MetricFamily>) -> Vec<MetricFamily> {
+    let mut merged: Vec<MetricFamily> = Vec::new();
+    for mut family in families {
+        match merged.iter_mut().find(|f| f.get_name() == family.get_name()) {
+            Some(existing) => {
+                for metric in family.take_metric() {
+                    existing.mut_metric().push(metric);
+                }
+            }
+            None => merged.push(family),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refresh_mirrors_snapshot() {
+        let metrics = Arc::new(Metrics::new());
+        let live = LiveMetrics::new(&metrics, &[("connection_id", "7")]).unwrap();
+
+        metrics.record_packet_sent(100);
+        metrics.record_packet_sent(50);
+        assert!(live.refresh());
+        assert_eq!(live.packets_sent.get(), 2);
+        assert_eq!(live.bytes_sent.get(), 150);
+
+        // Refreshing again doesn't double count
+        assert!(live.refresh());
+        assert_eq!(live.packets_sent.get(), 2);
+
+        drop(metrics);
+        assert!(!live.refresh());
+    }
+}
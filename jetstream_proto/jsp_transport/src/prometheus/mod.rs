@@ -5,10 +5,14 @@
 pub mod registry;
 pub mod collectors;
 pub mod exporter;
+pub mod live;
+pub mod labeled;
 
 pub use registry::MetricsRegistry;
 pub use collectors::{ConnectionMetrics, TransportMetrics, MultiHopMetrics};
-pub use exporter::MetricsExporter;
+pub use exporter::{MetricsExporter, ExporterHandle};
+pub use live::{MetricsSource, LiveMetrics, LiveServerMetrics};
+pub use labeled::ConnectionSeries;
 
 use prometheus::{Encoder, TextEncoder};
 
@@ -23,9 +27,8 @@ pub fn global_registry() -> &'static MetricsRegistry {
 
 /// Export metrics in Prometheus format
 pub fn export_metrics() -> Result<String, Box<dyn std::error::Error>> {
-    let registry = global_registry().registry();
     let encoder = TextEncoder::new();
-    let metric_families = registry.gather();
+    let metric_families = global_registry().gather();
     let mut buffer = Vec::new();
     encoder.encode(&metric_families, &mut buffer)?;
     Ok(String::from_utf8(buffer)?)
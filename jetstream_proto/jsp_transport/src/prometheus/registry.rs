@@ -3,6 +3,12 @@
 //! Central registry for all Prometheus metrics.
 
 use prometheus::{Registry, IntCounter, IntGauge, Histogram, HistogramOpts, Opts};
+use prometheus::proto::MetricFamily;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use super::labeled::{ConnectionLabels, ConnectionSeries};
+use super::live::MetricsSource;
 
 /// Metrics registry for JetStreamProto
 pub struct MetricsRegistry {
@@ -19,11 +25,19 @@ pub struct MetricsRegistry {
     pub bytes_received_total: IntCounter,
     pub packets_sent_total: IntCounter,
     pub packets_received_total: IntCounter,
+    pub rtt_seconds: Histogram,
+    pub packet_payload_bytes: Histogram,
     
     // Error metrics
     pub errors_total: IntCounter,
     pub timeouts_total: IntCounter,
     pub retransmissions_total: IntCounter,
+    
+    // Per-connection series, labeled by session ID and peer
+    connection_labels: Arc<ConnectionLabels>,
+    
+    // Live connections and servers, refreshed on scrape
+    sources: Mutex<Vec<Box<dyn MetricsSource>>>,
 }
 
 impl MetricsRegistry {
@@ -75,6 +89,18 @@ impl MetricsRegistry {
         ).unwrap();
         registry.register(Box::new(packets_received_total.clone())).unwrap();
         
+        let rtt_seconds = Histogram::with_opts(
+            HistogramOpts::new("jsp_rtt_seconds", "Smoothed round-trip time in seconds, observed on each ACK")
+                .buckets(vec![0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5])
+        ).unwrap();
+        registry.register(Box::new(rtt_seconds.clone())).unwrap();
+        
+        let packet_payload_bytes = Histogram::with_opts(
+            HistogramOpts::new("jsp_packet_payload_bytes", "Payload size of sent data packets in bytes")
+                .buckets(vec![16.0, 64.0, 256.0, 512.0, 1024.0, 1200.0, 1400.0, 4096.0, 16384.0, 65536.0])
+        ).unwrap();
+        registry.register(Box::new(packet_payload_bytes.clone())).unwrap();
+        
         // Error metrics
         let errors_total = IntCounter::with_opts(
             Opts::new("jsp_errors_total", "Total number of errors")
@@ -91,6 +117,8 @@ impl MetricsRegistry {
         ).unwrap();
         registry.register(Box::new(retransmissions_total.clone())).unwrap();
         
+        let connection_labels = Arc::new(ConnectionLabels::new(&registry).unwrap());
+        
         Self {
             registry,
             connections_total,
@@ -101,9 +129,13 @@ impl MetricsRegistry {
             bytes_received_total,
             packets_sent_total,
             packets_received_total,
+            rtt_seconds,
+            packet_payload_bytes,
             errors_total,
             timeouts_total,
             retransmissions_total,
+            connection_labels,
+            sources: Mutex::new(Vec::new()),
         }
     }
     
@@ -112,6 +144,37 @@ impl MetricsRegistry {
         &self.registry
     }
     
+    /// Add a live metrics source, exported until its connection or server is dropped
+    pub fn add_source(&self, source: Box<dyn MetricsSource>) {
+        self.sources.lock().unwrap().push(source);
+    }
+    
+    /// Labeled series for one connection: bytes sent/received, retransmits and cwnd
+    ///
+    /// Series are removed when the returned handle is dropped. Past the label
+    /// cap (see `set_max_connection_labels`) connections share `other`.
+    pub fn connection_metrics(&self, session_id: u64, peer: SocketAddr) -> ConnectionSeries {
+        self.connection_labels.series(session_id, peer)
+    }
+    
+    /// Cap the number of distinct per-connection label sets
+    pub fn set_max_connection_labels(&self, max: usize) {
+        self.connection_labels.set_max_label_sets(max);
+    }
+    
+    /// Refresh live sources and collect every metric family
+    pub fn gather(&self) -> Vec<MetricFamily> {
+        let mut families = self.registry.gather();
+        
+        let mut sources = self.sources.lock().unwrap();
+        sources.retain(|source| source.refresh());
+        for source in sources.iter() {
+            families.extend(source.gather());
+        }
+        
+        super::live::merge_families(families)
+    }
+    
     /// Record a new connection
     pub fn record_connection(&self) {
         self.connections_total.inc();
@@ -149,6 +212,16 @@ impl MetricsRegistry {
         self.packets_received_total.inc();
     }
     
+    /// Record the smoothed RTT after an ACK
+    pub fn observe_rtt(&self, srtt: Duration) {
+        self.rtt_seconds.observe(srtt.as_secs_f64());
+    }
+    
+    /// Record the payload size of a sent data packet
+    pub fn observe_packet_size(&self, bytes: usize) {
+        self.packet_payload_bytes.observe(bytes as f64);
+    }
+    
     /// Record an error
     pub fn record_error(&self) {
         self.errors_total.inc();
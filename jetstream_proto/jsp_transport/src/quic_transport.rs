@@ -4,6 +4,7 @@ use std::sync::Arc;
 use anyhow::Result;
 
 /// QUIC transport wrapper
+#[derive(Clone)]
 pub struct QuicTransport {
     connection: QuinnConnection,
     local_addr: SocketAddr,
@@ -38,7 +39,7 @@ impl QuicTransport {
     }
     
     /// Send data over QUIC (opens uni-directional stream)
-    pub async fn send(&mut self, data: &[u8]) -> Result<usize> {
+    pub async fn send(&self, data: &[u8]) -> Result<usize> {
         let mut send_stream = self.connection.open_uni().await?;
         send_stream.write_all(data).await?;
         send_stream.finish()?;
@@ -53,7 +54,7 @@ impl QuicTransport {
     }
     
     /// Receive data from QUIC (accepts uni-directional stream)
-    pub async fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+    pub async fn recv(&self, buf: &mut [u8]) -> Result<usize> {
         let mut recv_stream = self.connection.accept_uni().await?;
         let len = recv_stream.read(buf).await?.unwrap_or(0);
         
@@ -66,6 +67,16 @@ impl QuicTransport {
         Ok(len)
     }
     
+    /// Open a bidirectional stream, for request/response exchanges
+    pub async fn open_bi(&self) -> Result<(quinn::SendStream, quinn::RecvStream)> {
+        Ok(self.connection.open_bi().await?)
+    }
+    
+    /// Accept the next bidirectional stream opened by the peer
+    pub async fn accept_bi(&self) -> Result<(quinn::SendStream, quinn::RecvStream)> {
+        Ok(self.connection.accept_bi().await?)
+    }
+    
     pub fn local_addr(&self) -> SocketAddr {
         self.local_addr
     }
@@ -160,7 +171,7 @@ mod tests {
         });
         
         // Accept connection
-        let mut server_conn = server.accept().await.unwrap();
+        let server_conn = server.accept().await.unwrap();
         let mut buf = [0u8; 1024];
         let len = server_conn.recv(&mut buf).await.unwrap();
         
@@ -1,7 +1,10 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::sync::{Arc, Mutex};
 
 /// Token bucket rate limiter for per-connection rate limiting
+///
+/// Tokens refill at the sustained rate up to the burst capacity, so an idle
+/// sender may send a burst at once while the long-run average stays capped.
 #[derive(Debug)]
 pub struct RateLimiter {
     /// Maximum tokens (burst capacity)
@@ -12,7 +15,7 @@ pub struct RateLimiter {
     refill_rate: f64,
     /// Last refill timestamp
     last_refill: Instant,
-    /// Maximum bytes per second
+    /// Maximum byte tokens (burst capacity)
     bytes_capacity: u64,
     /// Current byte tokens
     byte_tokens: f64,
@@ -21,14 +24,20 @@ pub struct RateLimiter {
 }
 
 impl RateLimiter {
+    /// Limiter whose burst capacity is one second's worth of the rate
     pub fn new(messages_per_second: u32, bytes_per_second: u64) -> Self {
+        Self::with_burst(messages_per_second, bytes_per_second, messages_per_second, bytes_per_second)
+    }
+
+    /// Limiter allowing bursts of up to `burst_messages` / `burst_bytes`
+    pub fn with_burst(messages_per_second: u32, bytes_per_second: u64, burst_messages: u32, burst_bytes: u64) -> Self {
         Self {
-            capacity: messages_per_second,
-            tokens: messages_per_second as f64,
+            capacity: burst_messages,
+            tokens: burst_messages as f64,
             refill_rate: messages_per_second as f64,
             last_refill: Instant::now(),
-            bytes_capacity: bytes_per_second,
-            byte_tokens: bytes_per_second as f64,
+            bytes_capacity: burst_bytes,
+            byte_tokens: burst_bytes as f64,
             byte_refill_rate: bytes_per_second as f64,
         }
     }
@@ -62,6 +71,26 @@ impl RateLimiter {
         }
     }
 
+    /// Time until a message of given size can be sent
+    ///
+    /// Returns `Duration::ZERO` if it can be sent now, or `None` if it can never
+    /// fit in the bucket.
+    pub fn time_until_available(&mut self, message_size: usize) -> Option<Duration> {
+        self.refill();
+        
+        if message_size as u64 > self.bytes_capacity || self.capacity == 0 {
+            return None;
+        }
+        
+        let message_deficit = (1.0 - self.tokens).max(0.0);
+        let byte_deficit = (message_size as f64 - self.byte_tokens).max(0.0);
+        
+        let message_wait = if message_deficit > 0.0 { message_deficit / self.refill_rate } else { 0.0 };
+        let byte_wait = if byte_deficit > 0.0 { byte_deficit / self.byte_refill_rate } else { 0.0 };
+        
+        Some(Duration::from_secs_f64(message_wait.max(byte_wait)))
+    }
+
     /// Get current available tokens
     pub fn available_tokens(&mut self) -> u32 {
         self.refill();
@@ -149,6 +178,41 @@ mod tests {
         assert!(limiter.check_and_consume(100));
     }
 
+    #[test]
+    fn test_burst_above_sustained_rate() {
+        let mut limiter = RateLimiter::with_burst(10, 1_000_000, 50, 1_000_000);
+        
+        // A burst up to the capacity passes at once
+        let start = std::time::Instant::now();
+        for _ in 0..50 {
+            assert!(limiter.check_and_consume(100));
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert!(!limiter.check_and_consume(100));
+        
+        // Afterwards only the sustained rate gets through
+        let mut sent = 0;
+        let start = std::time::Instant::now();
+        while start.elapsed() < Duration::from_millis(500) {
+            if limiter.check_and_consume(100) {
+                sent += 1;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!((3..=7).contains(&sent), "sent {} in 500ms at 10/s", sent);
+    }
+
+    #[test]
+    fn test_burst_bytes() {
+        let mut limiter = RateLimiter::with_burst(1000, 1000, 1000, 4000);
+        
+        // Bigger than a second's worth of bytes, within the burst
+        assert_eq!(limiter.time_until_available(3000), Some(Duration::ZERO));
+        assert!(limiter.check_and_consume(3000));
+        assert!(!limiter.check_and_consume(3000));
+        assert_eq!(limiter.time_until_available(5000), None);
+    }
+
     #[test]
     fn test_global_rate_limiter() {
         let limiter = GlobalRateLimiter::new(10, 1000);
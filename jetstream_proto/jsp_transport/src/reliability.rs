@@ -1,45 +1,149 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::time::{Duration, Instant};
 use std::fmt;
 use jsp_core::types::delivery::DeliveryMode;
-use crate::congestion::{CongestionController, NewReno};
+use crate::clock::{Clock, SystemClock};
+use crate::congestion::{CongestionController, CongestionState, NewReno};
 use bytes::Bytes;
+use std::sync::Arc;
+use tokio::sync::Notify;
+use jsp_core::types::header::FRAME_TYPE_DATA;
+use jsp_core::types::control::EcnCounts;
+use crate::udp::EcnCodepoint;
+
+/// Sequenced frame released in order by the reliability layer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedFrame {
+    pub seq: u64,
+    pub stream_id: u32,
+    /// Frame type (data or stream FIN)
+    pub msg_type: u8,
+    /// Header flags
+    pub flags: u8,
+    pub data: Bytes,
+}
+
+/// Sequenced frame handed back by the reliability layer for resending
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentFrame {
+    pub seq: u64,
+    pub stream_id: u32,
+    /// Frame type (data or stream FIN)
+    pub msg_type: u8,
+    /// Header flags
+    pub flags: u8,
+    pub mode: DeliveryMode,
+    pub data: Bytes,
+}
+
+/// Unacknowledged packet awaiting ACK or retransmission
+#[derive(Debug)]
+struct SentPacket {
+    /// Time of the first transmission (TTLs are measured from here)
+    first_sent: Instant,
+    /// Time of the most recent transmission (RTO is measured from here)
+    sent_time: Instant,
+    stream_id: u32,
+    msg_type: u8,
+    flags: u8,
+    data: Bytes,
+    mode: DeliveryMode,
+    /// Number of RTO retransmissions so far
+    retransmits: u32,
+}
+
+/// Packet loss counters kept by the reliability layer
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LossStats {
+    /// Fresh (first) transmissions of tracked packets
+    pub packets_sent: u64,
+    /// RTO retransmissions
+    pub packets_retransmitted: u64,
+    /// Retransmissions whose original turned out to have arrived
+    pub spurious_retransmits: u64,
+}
 
 pub struct ReliabilityLayer {
     next_seq: u64,
-    // Unacknowledged packets: Seq -> (SendTime, Payload, DeliveryMode)
-    sent_buffer: BTreeMap<u64, (Instant, Bytes, DeliveryMode)>,
+    // Unacknowledged packets: Seq -> packet
+    sent_buffer: BTreeMap<u64, SentPacket>,
     // Smoothed RTT
     srtt: Duration,
     // RTT Variance
     rttvar: Duration,
+    // Minimum RTT observed
+    min_rtt: Option<Duration>,
     // Congestion Controller
     congestion: Box<dyn CongestionController + Send + Sync>,
     // Bytes in flight
     inflight_bytes: usize,
+    // Receive window the peer last advertised (None = unlimited), and the
+    // largest it has advertised, i.e. its window with nothing buffered
+    peer_receive_window: Option<u64>,
+    peer_receive_window_max: u64,
     
     // Receiver state
     cumulative_ack: u64,
-    received_buffer: BTreeMap<u64, (u32, Bytes)>,
+    // Seq -> frame awaiting in-order delivery
+    received_buffer: BTreeMap<u64, ReceivedFrame>,
     
     // ACK Batching
     pending_ack_count: usize,
     last_ack_time: Instant,
+    
+    // Signalled whenever inflight bytes shrink
+    capacity_notify: Arc<Notify>,
+    
+    // Loss accounting
+    loss_stats: LossStats,
+    // Stream ID -> retransmissions of its frames
+    stream_retransmits: HashMap<u32, u64>,
+    
+    // (sequence, RTT) samples not yet collected by `take_rtt_samples`
+    rtt_samples: Vec<(u64, Duration)>,
+    
+    // ECN codepoints of received packets, echoed in ACKs
+    ecn_received: EcnCounts,
+    // Highest CE count the peer has reported back to us
+    peer_ce: u64,
+    
+    // Time source for RTOs, TTLs and ACK batching
+    clock: Arc<dyn Clock>,
 }
 
 impl ReliabilityLayer {
     pub fn new() -> Self {
+        Self::with_congestion(Box::new(NewReno::new(1200))) // Default MSS 1200
+    }
+
+    /// Create a layer driven by the given congestion controller
+    pub fn with_congestion(congestion: Box<dyn CongestionController + Send + Sync>) -> Self {
+        Self::with_clock(congestion, Arc::new(SystemClock))
+    }
+
+    /// Create a layer whose timers read the given clock
+    pub fn with_clock(congestion: Box<dyn CongestionController + Send + Sync>, clock: Arc<dyn Clock>) -> Self {
         Self {
             next_seq: 1,
             sent_buffer: BTreeMap::new(),
             srtt: Duration::from_millis(100), // Initial guess
             rttvar: Duration::from_millis(0),
-            congestion: Box::new(NewReno::new(1200)), // Default MSS 1200
+            min_rtt: None,
+            congestion,
             inflight_bytes: 0,
+            peer_receive_window: None,
+            peer_receive_window_max: 0,
             cumulative_ack: 0,
             received_buffer: BTreeMap::new(),
             pending_ack_count: 0,
-            last_ack_time: Instant::now(),
+            last_ack_time: clock.now(),
+            capacity_notify: Arc::new(Notify::new()),
+            loss_stats: LossStats::default(),
+            stream_retransmits: HashMap::new(),
+            rtt_samples: Vec::new(),
+            ecn_received: EcnCounts::default(),
+            peer_ce: 0,
+            clock,
         }
     }
 
@@ -50,13 +154,31 @@ impl ReliabilityLayer {
     }
 
     pub fn track_sent_packet(&mut self, seq: u64, data: Bytes, mode: DeliveryMode) {
+        self.track_sent_frame(seq, 0, FRAME_TYPE_DATA, 0, data, mode);
+    }
+
+    /// Track a sent sequenced frame together with what is needed to rebuild it
+    pub fn track_sent_frame(&mut self, seq: u64, stream_id: u32, msg_type: u8, flags: u8, data: Bytes, mode: DeliveryMode) {
         let len = data.len();
-        self.sent_buffer.insert(seq, (Instant::now(), data, mode));
+        let now = self.clock.now();
+        self.sent_buffer.insert(seq, SentPacket {
+            first_sent: now,
+            sent_time: now,
+            stream_id,
+            msg_type,
+            flags,
+            data,
+            mode,
+            retransmits: 0,
+        });
         self.inflight_bytes += len;
         self.congestion.on_packet_sent(len);
+        self.loss_stats.packets_sent += 1;
     }
 
     pub fn on_ack(&mut self, ack_seq: u64, ranges: &[(u64, u64)]) {
+        let inflight_before = self.inflight_bytes;
+        
         // Remove cumulative ack
         let keys_to_remove: Vec<u64> = self.sent_buffer.keys()
             .filter(|&&k| k <= ack_seq)
@@ -64,11 +186,8 @@ impl ReliabilityLayer {
             .collect();
         
         for k in keys_to_remove {
-            if let Some((sent_time, data, _)) = self.sent_buffer.remove(&k) {
-                let len = data.len();
-                self.inflight_bytes = self.inflight_bytes.saturating_sub(len);
-                self.update_rtt(sent_time.elapsed());
-                self.congestion.on_packet_acked(len, sent_time.elapsed());
+            if let Some(packet) = self.sent_buffer.remove(&k) {
+                self.on_packet_acked(k, packet);
             }
         }
 
@@ -80,19 +199,46 @@ impl ReliabilityLayer {
                 .collect();
             
             for k in sack_keys {
-                if let Some((sent_time, data, _)) = self.sent_buffer.remove(&k) {
-                    let len = data.len();
-                    self.inflight_bytes = self.inflight_bytes.saturating_sub(len);
-                    self.update_rtt(sent_time.elapsed());
-                    self.congestion.on_packet_acked(len, sent_time.elapsed());
+                if let Some(packet) = self.sent_buffer.remove(&k) {
+                    self.on_packet_acked(k, packet);
                 }
             }
         }
+        
+        if self.inflight_bytes < inflight_before {
+            self.capacity_notify.notify_one();
+        }
+    }
+
+    fn on_packet_acked(&mut self, seq: u64, packet: SentPacket) {
+        let len = packet.data.len();
+        let elapsed = self.clock.now().duration_since(packet.sent_time);
+        self.inflight_bytes = self.inflight_bytes.saturating_sub(len);
+        
+        if packet.retransmits == 0 {
+            self.update_rtt(elapsed);
+            self.rtt_samples.push((seq, elapsed));
+        } else if self.min_rtt.is_some_and(|min_rtt| elapsed < min_rtt) {
+            // ACKed faster than any round trip we've seen: it was for the
+            // original transmission, so the retransmission was spurious
+            self.loss_stats.spurious_retransmits += 1;
+        }
+        // Karn's algorithm: no RTT samples from retransmitted packets
+        
+        self.congestion.on_packet_acked(len, elapsed);
+    }
+
+    /// Notify handle that is signalled when ACKs or expiry free window space
+    pub fn capacity_notify(&self) -> Arc<Notify> {
+        Arc::clone(&self.capacity_notify)
     }
 
     fn update_rtt(&mut self, rtt: Duration) {
-        // RFC 6298 standard RTT update
-        if self.srtt == Duration::from_millis(0) {
+        // RFC 6298 standard RTT update; the first sample replaces the initial guess
+        let first_sample = self.min_rtt.is_none();
+        self.min_rtt = Some(self.min_rtt.map_or(rtt, |min_rtt| min_rtt.min(rtt)));
+        
+        if first_sample {
             self.srtt = rtt;
             self.rttvar = rtt / 2;
         } else {
@@ -102,14 +248,30 @@ impl ReliabilityLayer {
         }
     }
 
+    /// Smoothed round-trip time (SRTT)
+    pub fn smoothed_rtt(&self) -> Duration {
+        self.srtt
+    }
+
+    /// Round-trip time variation (RTTVAR)
+    pub fn rtt_var(&self) -> Duration {
+        self.rttvar
+    }
+
+    /// Drain the RTT samples measured since the last call, with the
+    /// sequence number of the packet each was taken from
+    pub fn take_rtt_samples(&mut self) -> Vec<(u64, Duration)> {
+        std::mem::take(&mut self.rtt_samples)
+    }
+
     pub fn get_retransmits(&mut self) -> Vec<(u64, Bytes)> {
-        let now = Instant::now();
+        let now = self.clock.now();
         let rto = self.srtt + 4 * self.rttvar;
         let rto = std::cmp::max(rto, Duration::from_millis(200)); // Min RTO
 
-        let retransmits: Vec<(u64, Bytes)> = self.sent_buffer.iter()
-            .filter_map(|(seq, (sent_time, data, mode))| {
-                let elapsed = now.duration_since(*sent_time);
+        let retransmits: Vec<(u64, Bytes)> = self.sent_buffer.iter_mut()
+            .filter_map(|(seq, packet)| {
+                let elapsed = now.duration_since(packet.sent_time);
                 
                 // If not yet time for RTO, skip
                 if elapsed <= rto {
@@ -117,26 +279,30 @@ impl ReliabilityLayer {
                 }
 
                 // Check delivery mode rules
-                match mode {
+                let retransmit = match packet.mode {
                     DeliveryMode::Reliable => {
                         // Always retransmit
-                        Some((*seq, data.clone()))
+                        true
                     }
                     DeliveryMode::PartiallyReliable { ttl_ms } => {
-                        let ttl = Duration::from_millis(*ttl_ms as u64);
-                        if elapsed < ttl {
-                            // Still within TTL, retransmit
-                            Some((*seq, data.clone()))
-                        } else {
-                            // TTL expired, do not retransmit
-                            None
-                        }
+                        // Retransmit only while still within TTL
+                        now.duration_since(packet.first_sent) < Duration::from_millis(ttl_ms as u64)
                     }
                     DeliveryMode::BestEffort => {
                         // Never retransmit
-                        None
+                        false
                     }
+                };
+                
+                if !retransmit {
+                    return None;
                 }
+                
+                // Restart the RTO so the packet is counted once per timeout
+                packet.sent_time = now;
+                packet.retransmits += 1;
+                *self.stream_retransmits.entry(packet.stream_id).or_insert(0) += 1;
+                Some((*seq, packet.data.clone()))
             })
             .collect();
 
@@ -147,18 +313,65 @@ impl ReliabilityLayer {
             let lost_bytes = retransmits[0].1.len();
             self.congestion.on_packet_lost(lost_bytes);
         }
+        self.loss_stats.packets_retransmitted += retransmits.len() as u64;
 
         retransmits
     }
 
+    /// Every unacknowledged frame still worth delivering, to resend after a path change
+    ///
+    /// Frames sent on the old path may have arrived with their ACKs lost, or
+    /// been dropped while the peer validated the new one, so all of them are
+    /// resent at once instead of waiting for the RTO. This is not a congestion
+    /// signal: the losses were caused by the switch, not by the network.
+    pub fn unacked_frames(&mut self) -> Vec<SentFrame> {
+        let now = self.clock.now();
+        let frames: Vec<SentFrame> = self.sent_buffer.iter_mut()
+            .filter(|(_, packet)| match packet.mode {
+                DeliveryMode::Reliable => true,
+                DeliveryMode::PartiallyReliable { ttl_ms } => {
+                    now.duration_since(packet.first_sent) < Duration::from_millis(ttl_ms as u64)
+                }
+                DeliveryMode::BestEffort => false,
+            })
+            .map(|(seq, packet)| {
+                packet.sent_time = now;
+                packet.retransmits += 1;
+                *self.stream_retransmits.entry(packet.stream_id).or_insert(0) += 1;
+                SentFrame {
+                    seq: *seq,
+                    stream_id: packet.stream_id,
+                    msg_type: packet.msg_type,
+                    flags: packet.flags,
+                    mode: packet.mode,
+                    data: packet.data.clone(),
+                }
+            })
+            .collect();
+        self.loss_stats.packets_retransmitted += frames.len() as u64;
+        frames
+    }
+
+    /// Number of tracked packets not yet acknowledged
+    pub fn unacked_count(&self) -> usize {
+        self.sent_buffer.len()
+    }
+
+    /// Whether the tracked packet `seq` still awaits its ACK
+    pub fn is_unacked(&self, seq: u64) -> bool {
+        self.sent_buffer.contains_key(&seq)
+    }
+
     pub fn cleanup_expired(&mut self) {
-        let now = Instant::now();
+        let now = self.clock.now();
+        let inflight_before = self.inflight_bytes;
         
-        self.sent_buffer.retain(|_, (sent_time, _, mode)| {
-            match mode {
+        self.sent_buffer.retain(|_, packet| {
+            let sent_time = packet.sent_time;
+            match packet.mode {
                 DeliveryMode::PartiallyReliable { ttl_ms } => {
-                    let ttl = Duration::from_millis(*ttl_ms as u64);
-                    let elapsed = now.duration_since(*sent_time);
+                    let ttl = Duration::from_millis(ttl_ms as u64);
+                    let elapsed = now.duration_since(packet.first_sent);
                     elapsed < ttl // Keep if not expired
                 }
                 DeliveryMode::BestEffort => {
@@ -169,7 +382,7 @@ impl ReliabilityLayer {
                     // Let's keep them until RTO to allow RTT updates, then drop.
                     let rto = self.srtt + 4 * self.rttvar;
                     let rto = std::cmp::max(rto, Duration::from_millis(200));
-                    now.duration_since(*sent_time) <= rto
+                    now.duration_since(sent_time) <= rto
                 }
                 DeliveryMode::Reliable => {
                     // Keep until ACKed
@@ -181,14 +394,113 @@ impl ReliabilityLayer {
         // Recalculate inflight bytes after cleanup
         // This is expensive but accurate. Alternatively we could track removals in retain but retain doesn't give us the removed items easily in stable Rust without drain_filter (nightly).
         // So let's just recalculate.
-        self.inflight_bytes = self.sent_buffer.values().map(|(_, data, _)| data.len()).sum();
+        self.inflight_bytes = self.sent_buffer.values().map(|packet| packet.data.len()).sum();
+        
+        if self.inflight_bytes < inflight_before {
+            self.capacity_notify.notify_one();
+        }
+    }
+
+    /// Estimated packet loss rate in [0.0, 1.0]
+    ///
+    /// Retransmissions that proved spurious are not counted as losses, and
+    /// retransmissions are never counted as fresh sends.
+    pub fn loss_rate(&self) -> f64 {
+        let stats = &self.loss_stats;
+        if stats.packets_sent == 0 {
+            return 0.0;
+        }
+        let lost = stats.packets_retransmitted.saturating_sub(stats.spurious_retransmits);
+        (lost as f64 / stats.packets_sent as f64).min(1.0)
+    }
+
+    /// Sent / retransmitted / spurious retransmit counters
+    pub fn loss_stats(&self) -> LossStats {
+        self.loss_stats
+    }
+
+    /// Retransmissions of frames sent on `stream_id`
+    pub fn stream_retransmits(&self, stream_id: u32) -> u64 {
+        self.stream_retransmits.get(&stream_id).copied().unwrap_or(0)
     }
 
     pub fn can_send(&self) -> bool {
         self.congestion.can_send(self.inflight_bytes)
     }
 
+    /// Current congestion window in bytes
+    pub fn congestion_window(&self) -> usize {
+        self.congestion.congestion_window()
+    }
+
+    /// Current state of the congestion controller
+    pub fn congestion_state(&self) -> CongestionState {
+        self.congestion.state()
+    }
+
+    /// Start over on a new network path
+    ///
+    /// RTT estimates go back to the initial guess, so the next sample
+    /// replaces them, and the congestion controller re-enters slow start
+    /// (or BBR Startup). Packets in flight stay tracked and are ACKed or
+    /// retransmitted as before.
+    pub fn on_path_changed(&mut self) {
+        self.srtt = Duration::from_millis(100);
+        self.rttvar = Duration::ZERO;
+        self.min_rtt = None;
+        self.congestion.on_path_changed();
+        self.capacity_notify.notify_one();
+    }
+
+    /// Segment size the congestion controller counts in, once path MTU
+    /// discovery found how much fits in a datagram
+    pub fn set_mss(&mut self, mss: usize) {
+        self.congestion.set_mss(mss);
+        self.capacity_notify.notify_one();
+    }
+
+    /// Bytes sent and tracked but not yet acknowledged
+    pub fn inflight_bytes(&self) -> usize {
+        self.inflight_bytes
+    }
+
+    /// Record the receive window the peer advertised
+    pub fn on_receive_window(&mut self, window: u64) {
+        let grew = self.peer_receive_window.is_some_and(|old| window > old);
+        self.peer_receive_window = Some(window);
+        self.peer_receive_window_max = self.peer_receive_window_max.max(window);
+        if grew {
+            self.capacity_notify.notify_one();
+        }
+    }
+
+    /// Receive window the peer last advertised (None = unlimited)
+    pub fn peer_receive_window(&self) -> Option<u64> {
+        self.peer_receive_window
+    }
+
+    /// Whether `len` more bytes in flight fit the peer's receive window
+    ///
+    /// A message larger than the peer's whole buffer may still go out, alone,
+    /// once the peer has drained everything it held.
+    pub fn window_allows(&self, len: usize) -> bool {
+        self.peer_receive_window.is_none_or(|window| {
+            (self.inflight_bytes + len) as u64 <= window
+                || (self.inflight_bytes == 0 && window == self.peer_receive_window_max)
+        })
+    }
+
+    /// Bytes held for frames still waiting for the ones before them
+    pub fn buffered_bytes(&self) -> usize {
+        self.received_buffer.values().map(|frame| frame.data.len()).sum()
+    }
+
     pub fn track_received_packet(&mut self, seq: u64, stream_id: u32, data: Bytes) {
+        self.track_received_frame(seq, stream_id, FRAME_TYPE_DATA, 0, data);
+    }
+
+    /// Track a received sequenced frame together with its type and header flags
+    pub fn track_received_frame(&mut self, seq: u64, stream_id: u32, msg_type: u8, flags: u8, data: Bytes) {
         if seq <= self.cumulative_ack && !self.received_buffer.contains_key(&seq) {
             // Duplicate and already processed (popped)
             return;
@@ -198,7 +510,7 @@ impl ReliabilityLayer {
             return;
         }
         
-        self.received_buffer.insert(seq, (stream_id, data));
+        self.received_buffer.insert(seq, ReceivedFrame { seq, stream_id, msg_type, flags, data });
         
         // Update cumulative ack
         while self.received_buffer.contains_key(&(self.cumulative_ack + 1)) {
@@ -211,6 +523,14 @@ impl ReliabilityLayer {
 
     /// Pop all received packets that are ready (in-order)
     pub fn pop_received_packets(&mut self) -> Vec<(u64, u32, Bytes)> {
+        self.pop_received_frames()
+            .into_iter()
+            .map(|frame| (frame.seq, frame.stream_id, frame.data))
+            .collect()
+    }
+
+    /// Pop all received frames that are ready (in-order), including type and flags
+    pub fn pop_received_frames(&mut self) -> Vec<ReceivedFrame> {
         let mut packets = Vec::new();
         
         // We can only return packets up to cumulative_ack
@@ -227,8 +547,8 @@ impl ReliabilityLayer {
              // If we have cumulative_ack = N, it means we have everything up to N.
              
              if seq <= self.cumulative_ack {
-                 if let Some((stream_id, data)) = self.received_buffer.remove(&seq) {
-                     packets.push((seq, stream_id, data));
+                 if let Some(frame) = self.received_buffer.remove(&seq) {
+                     packets.push(frame);
                  }
              } else {
                  // Stop at the first gap (which is after cumulative_ack)
@@ -247,7 +567,15 @@ impl ReliabilityLayer {
         if self.pending_ack_count >= batch_size {
             return true;
         }
-        self.last_ack_time.elapsed() >= batch_timeout
+        self.clock.now().duration_since(self.last_ack_time) >= batch_timeout
+    }
+
+    /// Note a frame that was already received, so it is ACKed again
+    ///
+    /// The sender only resends what it never saw ACKed, so the earlier ACK
+    /// was probably lost.
+    pub fn on_duplicate_received(&mut self) {
+        self.pending_ack_count += 1;
     }
 
     /// Check if there are any pending ACKs
@@ -258,7 +586,38 @@ impl ReliabilityLayer {
     /// Reset ACK batching state after sending ACK
     pub fn on_ack_sent(&mut self) {
         self.pending_ack_count = 0;
-        self.last_ack_time = Instant::now();
+        self.last_ack_time = self.clock.now();
+    }
+
+    /// Count the ECN codepoint of a received packet
+    pub fn on_ecn_received(&mut self, ecn: EcnCodepoint) {
+        match ecn {
+            EcnCodepoint::NotEct => {}
+            EcnCodepoint::Ect0 => self.ecn_received.ect0 += 1,
+            EcnCodepoint::Ect1 => self.ecn_received.ect1 += 1,
+            EcnCodepoint::Ce => self.ecn_received.ce += 1,
+        }
+    }
+
+    /// ECN counts to echo in the next ACK; `None` if no packet carried ECN
+    pub fn ecn_counts(&self) -> Option<EcnCounts> {
+        if self.ecn_received.is_empty() {
+            None
+        } else {
+            Some(self.ecn_received)
+        }
+    }
+
+    /// Process the ECN counts echoed in an ACK
+    ///
+    /// Counts are running totals, so only CE marks beyond the highest total
+    /// seen so far reach the congestion controller; reordered ACKs add none.
+    pub fn on_ecn_counts(&mut self, counts: &EcnCounts) {
+        if counts.ce > self.peer_ce {
+            let marked = counts.ce - self.peer_ce;
+            self.peer_ce = counts.ce;
+            self.congestion.on_ecn_marked(marked);
+        }
     }
 
     /// Get ACK information (cumulative ACK and SACK ranges)
@@ -314,11 +673,18 @@ impl fmt::Debug for ReliabilityLayer {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::thread;
+    use crate::clock::MockClock;
+
+    /// Layer whose timers only move when the returned clock is advanced
+    fn mock_layer() -> (ReliabilityLayer, MockClock) {
+        let clock = MockClock::new();
+        let layer = ReliabilityLayer::with_clock(Box::new(NewReno::new(1200)), Arc::new(clock.clone()));
+        (layer, clock)
+    }
 
     #[test]
     fn test_reliable_retransmit() {
-        let mut reliability = ReliabilityLayer::new();
+        let (mut reliability, clock) = mock_layer();
         let data = Bytes::from(vec![1, 2, 3]);
         
         reliability.track_sent_packet(1, data.clone(), DeliveryMode::Reliable);
@@ -327,25 +693,79 @@ mod tests {
         assert!(reliability.get_retransmits().is_empty());
         
         // Wait for RTO (min 200ms)
-        thread::sleep(Duration::from_millis(250));
+        clock.advance(Duration::from_millis(250));
         
         // Should retransmit
         let retransmits = reliability.get_retransmits();
         assert_eq!(retransmits.len(), 1);
         assert_eq!(retransmits[0].0, 1);
         assert_eq!(retransmits[0].1, data);
+        assert_eq!(reliability.loss_rate(), 1.0);
     }
 
     #[test]
-    fn test_partially_reliable_ttl() {
+    fn test_unacked_frames_after_path_change() {
+        use jsp_core::types::header::FRAME_TYPE_STREAM_FIN;
+
         let mut reliability = ReliabilityLayer::new();
+        reliability.track_sent_frame(1, 3, FRAME_TYPE_DATA, 0, Bytes::from_static(b"acked"), DeliveryMode::Reliable);
+        reliability.track_sent_frame(2, 3, FRAME_TYPE_DATA, 0, Bytes::from_static(b"lost"), DeliveryMode::Reliable);
+        reliability.track_sent_frame(3, 5, FRAME_TYPE_DATA, 0, Bytes::from_static(b"stale"), DeliveryMode::BestEffort);
+        reliability.track_sent_frame(4, 3, FRAME_TYPE_STREAM_FIN, 0, Bytes::new(), DeliveryMode::Reliable);
+        reliability.on_ack(1, &[]);
+        assert_eq!(reliability.unacked_count(), 3);
+
+        // Resent right away, without waiting for the RTO
+        let frames = reliability.unacked_frames();
+        assert_eq!(frames.iter().map(|frame| frame.seq).collect::<Vec<_>>(), vec![2, 4]);
+        assert_eq!(frames[0].stream_id, 3);
+        assert_eq!(frames[0].data, Bytes::from_static(b"lost"));
+        assert_eq!(frames[1].msg_type, FRAME_TYPE_STREAM_FIN);
+        assert_eq!(reliability.loss_stats().packets_retransmitted, 2);
+        assert_eq!(reliability.stream_retransmits(3), 2);
+        assert_eq!(reliability.stream_retransmits(5), 0);
+
+        // Kept until ACKed
+        reliability.on_ack(4, &[]);
+        assert_eq!(reliability.unacked_count(), 0);
+        assert!(reliability.unacked_frames().is_empty());
+    }
+
+    #[test]
+    fn test_retransmit_exactly_at_rto() {
+        let (mut reliability, clock) = mock_layer();
+        
+        // A 50ms sample gives SRTT 50ms, RTTVAR 25ms: RTO 150ms, raised to the 200ms minimum
+        reliability.track_sent_packet(1, Bytes::from(vec![0u8; 100]), DeliveryMode::Reliable);
+        clock.advance(Duration::from_millis(50));
+        reliability.on_ack(1, &[]);
+        
+        reliability.track_sent_packet(2, Bytes::from(vec![0u8; 100]), DeliveryMode::Reliable);
+        clock.advance(Duration::from_millis(200));
+        assert!(reliability.get_retransmits().is_empty());
+        
+        clock.advance(Duration::from_millis(1));
+        let retransmits = reliability.get_retransmits();
+        assert_eq!(retransmits.iter().map(|(seq, _)| *seq).collect::<Vec<_>>(), vec![2]);
+        
+        // The RTO restarts from the retransmission
+        clock.advance(Duration::from_millis(200));
+        assert!(reliability.get_retransmits().is_empty());
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(reliability.get_retransmits().len(), 1);
+        assert_eq!(reliability.loss_stats().packets_retransmitted, 2);
+    }
+
+    #[test]
+    fn test_partially_reliable_ttl() {
+        let (mut reliability, clock) = mock_layer();
         let data = Bytes::from(vec![1, 2, 3]);
         
         // TTL = 100ms
         reliability.track_sent_packet(1, data.clone(), DeliveryMode::PartiallyReliable { ttl_ms: 100 });
         
         // Wait for 250ms (RTO passed, TTL passed)
-        thread::sleep(Duration::from_millis(250));
+        clock.advance(Duration::from_millis(250));
         
         // Should NOT retransmit because TTL expired
         assert!(reliability.get_retransmits().is_empty());
@@ -358,13 +778,13 @@ mod tests {
     
     #[test]
     fn test_best_effort_no_retransmit() {
-        let mut reliability = ReliabilityLayer::new();
+        let (mut reliability, clock) = mock_layer();
         let data = Bytes::from(vec![1, 2, 3]);
         
         reliability.track_sent_packet(1, data.clone(), DeliveryMode::BestEffort);
         
         // Wait for RTO
-        thread::sleep(Duration::from_millis(250));
+        clock.advance(Duration::from_millis(250));
         
         // Should NOT retransmit
         assert!(reliability.get_retransmits().is_empty());
@@ -403,7 +823,7 @@ mod tests {
 
     #[test]
     fn test_ack_batching() {
-        let mut reliability = ReliabilityLayer::new();
+        let (mut reliability, clock) = mock_layer();
         let batch_size = 3;
         let batch_timeout = Duration::from_millis(50);
         
@@ -429,10 +849,26 @@ mod tests {
         reliability.track_received_packet(4, 0, Bytes::new());
         assert!(!reliability.should_send_ack(batch_size, batch_timeout));
         
-        thread::sleep(Duration::from_millis(60));
+        clock.advance(Duration::from_millis(49));
+        assert!(!reliability.should_send_ack(batch_size, batch_timeout));
+        clock.advance(Duration::from_millis(1));
         assert!(reliability.should_send_ack(batch_size, batch_timeout));
     }
 
+    #[tokio::test]
+    async fn test_ack_signals_capacity() {
+        let mut reliability = ReliabilityLayer::new();
+        let notify = reliability.capacity_notify();
+        
+        reliability.track_sent_packet(1, Bytes::from(vec![0u8; 100]), DeliveryMode::Reliable);
+        reliability.on_ack(1, &[]);
+        
+        // Permit stored by notify_one resolves immediately
+        tokio::time::timeout(Duration::from_millis(100), notify.notified())
+            .await
+            .expect("ACK should signal capacity");
+    }
+
     #[test]
     fn test_piggybacking_logic() {
         let mut reliability = ReliabilityLayer::new();
@@ -467,4 +903,140 @@ mod tests {
         reliability.on_ack_sent();
         assert!(!reliability.has_pending_acks());
     }
+
+    #[test]
+    fn test_loss_rate_rises_without_acks() {
+        let (mut reliability, clock) = mock_layer();
+        
+        for seq in 1..=4 {
+            reliability.track_sent_packet(seq, Bytes::from(vec![0u8; 100]), DeliveryMode::Reliable);
+        }
+        assert_eq!(reliability.loss_rate(), 0.0);
+        
+        // ACK half of them, withhold the rest past the RTO
+        reliability.on_ack(2, &[]);
+        clock.advance(Duration::from_millis(250));
+        
+        assert_eq!(reliability.get_retransmits().len(), 2);
+        assert_eq!(reliability.loss_rate(), 0.5);
+        
+        // Asking again before the next RTO does not count the same loss twice
+        assert!(reliability.get_retransmits().is_empty());
+        assert_eq!(reliability.loss_stats().packets_retransmitted, 2);
+        assert_eq!(reliability.loss_stats().packets_sent, 4);
+    }
+
+    #[test]
+    fn test_spurious_retransmit_not_counted_as_loss() {
+        let (mut reliability, clock) = mock_layer();
+        
+        // Establish a minimum RTT
+        reliability.track_sent_packet(1, Bytes::from(vec![0u8; 100]), DeliveryMode::Reliable);
+        clock.advance(Duration::from_millis(50));
+        reliability.on_ack(1, &[]);
+        
+        reliability.track_sent_packet(2, Bytes::from(vec![0u8; 100]), DeliveryMode::Reliable);
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(reliability.get_retransmits().len(), 1);
+        
+        // ACK arrives well within one RTT of the retransmission
+        reliability.on_ack(2, &[]);
+        
+        let stats = reliability.loss_stats();
+        assert_eq!(stats.packets_retransmitted, 1);
+        assert_eq!(stats.spurious_retransmits, 1);
+        assert_eq!(reliability.loss_rate(), 0.0);
+    }
+
+    #[test]
+    fn test_first_rtt_sample_replaces_initial_guess() {
+        let (mut reliability, clock) = mock_layer();
+        
+        reliability.track_sent_packet(1, Bytes::from(vec![0u8; 100]), DeliveryMode::Reliable);
+        clock.advance(Duration::from_millis(20));
+        reliability.on_ack(1, &[]);
+        
+        let samples = reliability.take_rtt_samples();
+        assert_eq!(samples.len(), 1);
+        let (seq, rtt) = samples[0];
+        assert_eq!(seq, 1);
+        assert_eq!(rtt, Duration::from_millis(20));
+        assert_eq!(reliability.smoothed_rtt(), rtt);
+        assert_eq!(reliability.rtt_var(), rtt / 2);
+        assert!(reliability.take_rtt_samples().is_empty());
+    }
+
+    #[test]
+    fn test_peer_receive_window_caps_inflight() {
+        let mut reliability = ReliabilityLayer::new();
+        assert!(reliability.window_allows(1_000_000));
+
+        reliability.on_receive_window(3000);
+        reliability.track_sent_packet(1, Bytes::from(vec![0u8; 1000]), DeliveryMode::Reliable);
+        reliability.track_sent_packet(2, Bytes::from(vec![0u8; 1000]), DeliveryMode::Reliable);
+        assert!(reliability.window_allows(1000));
+        assert!(!reliability.window_allows(1001));
+
+        // The peer ACKs but its application has not read the data yet
+        reliability.on_ack(2, &[]);
+        reliability.on_receive_window(1000);
+        assert_eq!(reliability.inflight_bytes(), 0);
+        assert!(reliability.window_allows(1000));
+        assert!(!reliability.window_allows(1001));
+
+        // Once it has drained everything, even an oversized message may go alone
+        reliability.on_receive_window(3000);
+        assert!(reliability.window_allows(5000));
+        reliability.track_sent_packet(3, Bytes::from(vec![0u8; 5000]), DeliveryMode::Reliable);
+        assert!(!reliability.window_allows(1));
+    }
+
+    #[test]
+    fn test_ecn_marked_acks_shrink_congestion_window() {
+        let mut receiver = ReliabilityLayer::new();
+        assert_eq!(receiver.ecn_counts(), None);
+        receiver.on_ecn_received(EcnCodepoint::Ect0);
+        receiver.on_ecn_received(EcnCodepoint::Ce);
+        receiver.on_ecn_received(EcnCodepoint::NotEct);
+        assert_eq!(receiver.ecn_counts(), Some(EcnCounts { ect0: 1, ect1: 0, ce: 1 }));
+
+        let mut sender = ReliabilityLayer::new();
+        sender.track_sent_packet(1, Bytes::from(vec![0u8; 1200]), DeliveryMode::Reliable);
+        sender.on_ack(1, &[]);
+        let cwnd = sender.congestion_window();
+
+        // The first report of a CE mark halves the window
+        sender.on_ecn_counts(&receiver.ecn_counts().unwrap());
+        assert_eq!(sender.congestion_window(), cwnd / 2);
+        assert_eq!(sender.congestion_state(), CongestionState::CongestionAvoidance);
+
+        // Repeating the same totals reports nothing new
+        sender.on_ecn_counts(&EcnCounts { ect0: 1, ect1: 0, ce: 1 });
+        assert_eq!(sender.congestion_window(), cwnd / 2);
+    }
+
+    #[test]
+    fn test_path_change_resets_estimates() {
+        let (mut reliability, clock) = mock_layer();
+        for seq in 1..=20 {
+            reliability.track_sent_packet(seq, Bytes::from(vec![0u8; 1200]), DeliveryMode::Reliable);
+        }
+        clock.advance(Duration::from_millis(30));
+        reliability.on_ack(20, &[]);
+        reliability.track_sent_packet(21, Bytes::from(vec![0u8; 1200]), DeliveryMode::Reliable);
+        assert_eq!(reliability.smoothed_rtt(), Duration::from_millis(30));
+        assert!(reliability.congestion_window() > 12_000);
+
+        reliability.on_path_changed();
+        assert_eq!(reliability.smoothed_rtt(), Duration::from_millis(100));
+        assert_eq!(reliability.rtt_var(), Duration::ZERO);
+        assert_eq!(reliability.congestion_window(), 12_000);
+        assert_eq!(reliability.congestion_state(), CongestionState::SlowStart);
+        assert_eq!(reliability.inflight_bytes(), 1200);
+
+        // The first sample on the new path replaces the guess outright
+        clock.advance(Duration::from_millis(80));
+        reliability.on_ack(21, &[]);
+        assert_eq!(reliability.smoothed_rtt(), Duration::from_millis(80));
+    }
 }
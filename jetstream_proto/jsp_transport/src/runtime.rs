@@ -0,0 +1,56 @@
+//! Process-wide runtime for the language bindings
+//!
+//! The C, C++ and Python SDKs run every connection on one multi-threaded
+//! Tokio runtime, started by the first connection. Its worker count can be
+//! set until then.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use tokio::runtime::Runtime;
+
+/// Worker threads of the shared runtime; 0 = Tokio's default, one per core
+static WORKER_THREADS: AtomicUsize = AtomicUsize::new(0);
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// The shared runtime, started on first use
+pub fn shared() -> std::io::Result<&'static Runtime> {
+    if let Some(runtime) = RUNTIME.get() {
+        return Ok(runtime);
+    }
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all().thread_name("jsp-worker");
+    match WORKER_THREADS.load(Ordering::Relaxed) {
+        0 => {}
+        threads => {
+            builder.worker_threads(threads);
+        }
+    }
+    let runtime = builder.build()?;
+    // A thread racing this one may have started it first; its runtime wins
+    Ok(RUNTIME.get_or_init(|| runtime))
+}
+
+/// Set the worker threads of the shared runtime, 0 for one per CPU core
+///
+/// Returns false, changing nothing, once the runtime has started.
+pub fn set_worker_threads(threads: usize) -> bool {
+    if RUNTIME.get().is_some() {
+        return false;
+    }
+    WORKER_THREADS.store(threads, Ordering::Relaxed);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_worker_threads_fixed_once_started() {
+        assert!(set_worker_threads(2));
+        let runtime = shared().unwrap();
+        assert_eq!(runtime.metrics().num_workers(), 2);
+        assert!(std::ptr::eq(runtime, shared().unwrap()));
+        assert!(!set_worker_threads(4));
+    }
+}
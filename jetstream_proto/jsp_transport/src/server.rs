@@ -1,20 +1,35 @@
 use crate::udp::UdpTransport;
 use jsp_core::session::Session;
-use jsp_core::types::control::SessionConfig;
+use jsp_core::types::control::{AckFrame, CloseFrame, CloseReason, MtuProbeAckFrame, SessionConfig};
 use jsp_core::types::connection_id::ConnectionId;
+use jsp_core::types::handshake::{ClientHello, RetryFrame};
 use jsp_core::types::path_validation::{PathChallenge, PathResponse};
-use jsp_core::types::header::{Header, FRAME_TYPE_PATH_CHALLENGE, FRAME_TYPE_PATH_RESPONSE};
+use jsp_core::types::delivery::DeliveryMode;
+use jsp_core::types::fragment::{FragmentHeader, FRAGMENT_HEADER_LEN};
+use jsp_core::types::header::{Header, FLAG_COMPRESSED, FLAG_FRAGMENT, FRAME_TYPE_ACK, FRAME_TYPE_CLOSE, FRAME_TYPE_DATA, FRAME_TYPE_MTU_PROBE, FRAME_TYPE_MTU_PROBE_ACK, FRAME_TYPE_PATH_CHALLENGE, FRAME_TYPE_PATH_RESPONSE, FRAME_TYPE_RETRY, FRAME_TYPE_STREAM_FIN};
 use jsp_core::compression::header_compression::HeaderCompressor;
+use jsp_core::codec::{decode_any_header, Codec, SerializationFormat};
 use anyhow::Result;
-use std::net::SocketAddr;
-use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
 use crate::rate_limit::GlobalRateLimiter;
 use crate::ddos_protection::DdosProtection;
+use crate::ip_blacklist::{BlacklistEntry, IpBlacklist};
+use crate::amplification::AmplificationLimit;
 use crate::config::ServerConfig;
-use bytes::BytesMut;
+use crate::error::TransportError;
+use crate::metrics::{Metrics, MetricsSnapshot};
+use crate::prometheus::live::SessionTable;
+use crate::events::ServerEvent;
+use crate::stats_stream::{SessionStats, StatsFrame, StatsSource, STATS_STREAM_ID};
+use crate::memory_pool::{PacketPool, RECV_BUFFER_SIZE};
+use crate::compression;
+use crate::udp::{RecvMeta, GRO_BUFFER_SIZE, MAX_BATCH};
+use bytes::{Bytes, BytesMut};
 
 pub struct ServerConnectionState {
     pub session: Session,
@@ -23,6 +38,110 @@ pub struct ServerConnectionState {
     pub pending_challenge: Option<([u8; 8], std::time::Instant)>, // token, timestamp
     pub header_compressor: Option<HeaderCompressor>,
     pub header_decompressor: Option<HeaderCompressor>,
+    /// Nonce of the ClientHello that opened the session
+    pub client_nonce: u64,
+    /// ServerHello sent in reply, resent if that ClientHello is retransmitted
+    pub server_hello: Vec<u8>,
+    /// What may still be sent to `peer_addr` before it proved it is reachable;
+    /// a ServerHello over the limit waits for the client to repeat its hello
+    pub amplification: AmplificationLimit,
+    /// Sequence number of the next packet sent by `Server::send_on_stream`
+    pub next_send_seq: u64,
+    /// Highest sequence number received with no gaps before it
+    pub cumulative_ack: u64,
+    /// Sequence numbers received past a gap
+    pub received_ahead: BTreeSet<u64>,
+    /// Identifier of the next message sent in fragments
+    pub next_message_id: u32,
+    /// Traffic with this client, reported on the stats stream
+    pub metrics: Metrics,
+    /// Send times of sequenced packets the client hasn't acknowledged yet
+    pub unacked: VecDeque<(u64, std::time::Instant)>,
+}
+
+/// Sent packets tracked per session for RTT samples and queue depth
+const MAX_UNACKED: usize = 1024;
+
+impl ServerConnectionState {
+    /// Record a received sequence number; false if it was seen before
+    fn track_received(&mut self, sequence: u64) -> bool {
+        if sequence <= self.cumulative_ack || !self.received_ahead.insert(sequence) {
+            return false;
+        }
+        while self.received_ahead.remove(&(self.cumulative_ack + 1)) {
+            self.cumulative_ack += 1;
+        }
+        true
+    }
+
+    /// Sequence `data` into packets for the client
+    ///
+    /// Payloads over `max_fragment` bytes are split into fragments the client
+    /// reassembles, since it only reads datagrams up to `RECV_BUFFER_SIZE`.
+    fn data_packets(&mut self, stream_id: u32, data: &[u8], mode: DeliveryMode, max_fragment: usize) -> Result<Vec<Vec<u8>>> {
+        let max_fragment = max_fragment.max(1);
+        let fragment_count = data.len().div_ceil(max_fragment).max(1);
+        if fragment_count > u16::MAX as usize {
+            return Err(TransportError::MessageTooLarge(data.len()).into());
+        }
+        let message_id = self.next_message_id;
+        if fragment_count > 1 {
+            self.next_message_id = self.next_message_id.wrapping_add(1);
+        }
+        
+        let format = self.session.serialization_format();
+        let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_millis() as u64;
+        let mut packets = Vec::with_capacity(fragment_count);
+        for index in 0..fragment_count {
+            let chunk = &data[(index * max_fragment).min(data.len())..((index + 1) * max_fragment).min(data.len())];
+            let (flags, payload) = if fragment_count > 1 {
+                let mut payload = Vec::with_capacity(FRAGMENT_HEADER_LEN + chunk.len());
+                payload.extend_from_slice(&FragmentHeader::new(message_id, index as u16, fragment_count as u16).to_bytes());
+                payload.extend_from_slice(chunk);
+                (FLAG_FRAGMENT, payload)
+            } else {
+                (0, chunk.to_vec())
+            };
+            
+            let sequence = self.next_send_seq;
+            self.next_send_seq += 1;
+            let header = Header::new(stream_id, FRAME_TYPE_DATA, flags, sequence, timestamp, 0, mode, None, Some(payload.len() as u32));
+            let packet = build_packet(header, &payload, format)?;
+            self.metrics.record_packet_sent(packet.len());
+            if self.unacked.len() == MAX_UNACKED {
+                self.unacked.pop_front();
+            }
+            self.unacked.push_back((sequence, std::time::Instant::now()));
+            packets.push(packet);
+        }
+        self.last_activity = std::time::Instant::now();
+        Ok(packets)
+    }
+
+    /// Forget packets up to `cumulative_ack`, sampling the RTT of the newest
+    fn on_ack(&mut self, cumulative_ack: u64) {
+        let mut newest = None;
+        while let Some(&(sequence, sent_at)) = self.unacked.front() {
+            if sequence > cumulative_ack {
+                break;
+            }
+            self.unacked.pop_front();
+            newest = Some(sent_at);
+        }
+        if let Some(sent_at) = newest {
+            self.metrics.record_rtt_sample(sent_at.elapsed());
+        }
+    }
+
+    fn stats(&self) -> SessionStats {
+        SessionStats {
+            session_id: self.session.session_id,
+            peer: self.peer_addr,
+            backend: None,
+            queue_depth: self.unacked.len() as u64,
+            metrics: self.metrics.snapshot(),
+        }
+    }
 }
 
 pub struct Server {
@@ -33,16 +152,44 @@ pub struct Server {
     config: ServerConfig,
     global_rate_limiter: Option<GlobalRateLimiter>,
     ddos_protection: Option<DdosProtection>,
+    blacklist: IpBlacklist,
     cleanup_task: Option<tokio::task::JoinHandle<()>>,
+    metrics: Arc<Metrics>,
+    key_exchanges: u64,
+    /// Receive buffers, handed out as `Bytes` slices
+    recv_pool: PacketPool,
+    /// Buffers ready for the next batched receive
+    recv_bufs: Vec<Vec<u8>>,
+    /// Datagrams read in a batch and not returned yet
+    recv_backlog: VecDeque<(Bytes, SocketAddr)>,
+    /// Monitors subscribed on `STATS_STREAM_ID`
+    stats_subscribers: Arc<RwLock<HashSet<SocketAddr>>>,
+    /// Sends stats frames, started by the first subscription
+    stats_task: Option<tokio::task::JoinHandle<()>>,
+    /// Describes what the stats frames report instead of this server's sessions
+    stats_source: Option<Arc<dyn StatsSource>>,
+}
+
+impl SessionTable for RwLock<HashMap<ConnectionId, ServerConnectionState>> {
+    fn idle_seconds(&self) -> Option<Vec<(u64, f64)>> {
+        let connections = self.try_read().ok()?;
+        Some(connections.iter()
+            .map(|(id, state)| (id.as_u64(), state.last_activity.elapsed().as_secs_f64()))
+            .collect())
+    }
 }
 
 impl Server {
-    pub async fn bind(addr: &str) -> Result<Self> {
+    pub async fn bind(addr: &str) -> Result<Self, TransportError> {
         Self::bind_with_config(addr, ServerConfig::default()).await
     }
 
-    pub async fn bind_with_config(addr: &str, config: ServerConfig) -> Result<Self> {
+    pub async fn bind_with_config(addr: &str, config: ServerConfig) -> Result<Self, TransportError> {
         let transport = UdpTransport::bind(addr).await?;
+        if config.connection.enable_gso {
+            let (gso, gro) = (transport.enable_gso(), transport.enable_gro());
+            tracing::debug!(gso, gro, "UDP offload");
+        }
         
         let global_rate_limiter = if let (Some(msg_limit), Some(byte_limit)) = 
             (config.global_rate_limit_messages, config.global_rate_limit_bytes) {
@@ -52,6 +199,8 @@ impl Server {
         };
         
         let ddos_protection = Some(DdosProtection::new(config.ddos_config.clone()));
+        let recv_size = if transport.gro_enabled() { GRO_BUFFER_SIZE } else { RECV_BUFFER_SIZE };
+        let recv_pool = PacketPool::new(config.connection.pool_capacity, recv_size);
         
         tracing::info!(addr, "Server bound");
         
@@ -63,7 +212,16 @@ impl Server {
             config,
             global_rate_limiter,
             ddos_protection,
+            blacklist: IpBlacklist::new(),
             cleanup_task: None,
+            metrics: Arc::new(Metrics::new()),
+            key_exchanges: 0,
+            recv_pool,
+            recv_bufs: Vec::new(),
+            recv_backlog: VecDeque::new(),
+            stats_subscribers: Arc::new(RwLock::new(HashSet::new())),
+            stats_task: None,
+            stats_source: None,
         };
         
         server.start_cleanup_task();
@@ -117,36 +275,210 @@ impl Server {
         self.cleanup_task = Some(task);
     }
 
+    /// Report `source` on the stats stream instead of this server's sessions
+    ///
+    /// Lets a gateway publish its relayed flows through a server that only
+    /// serves monitors.
+    pub fn set_stats_source(&mut self, source: Arc<dyn StatsSource>) {
+        self.stats_source = Some(source);
+        // Pick the new source up right away
+        if let Some(task) = self.stats_task.take() {
+            task.abort();
+            self.start_stats_task();
+        }
+    }
+
+    /// Send a stats frame to every subscriber each `stats_stream_interval`
+    fn start_stats_task(&mut self) {
+        let interval = match self.config.stats_stream_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        let connections = self.connections.clone();
+        let addr_map = self.addr_map.clone();
+        let subscribers = self.stats_subscribers.clone();
+        let transport = self.transport.clone();
+        let metrics = self.metrics.clone();
+        let source = self.stats_source.clone();
+        let max_fragment = self.config.connection.max_fragment_size;
+        
+        let task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            
+            loop {
+                ticker.tick().await;
+                
+                let frame = match &source {
+                    Some(source) => source.stats_frame(),
+                    None => {
+                        let connections = connections.read().await;
+                        Some(StatsFrame::new(metrics.snapshot(), connections.values().map(ServerConnectionState::stats).collect()))
+                    }
+                };
+                let payload = match frame.map(|frame| frame.to_bytes()) {
+                    Some(Ok(payload)) => payload,
+                    Some(Err(e)) => {
+                        tracing::warn!("Failed to encode stats frame: {}", e);
+                        continue;
+                    }
+                    None => continue,
+                };
+                
+                let targets: Vec<SocketAddr> = subscribers.read().await.iter().copied().collect();
+                let mut gone = Vec::new();
+                for addr in targets {
+                    let packets = {
+                        let mut connections = connections.write().await;
+                        let addr_map = addr_map.read().await;
+                        match addr_map.get(&addr).and_then(|conn_id| connections.get_mut(conn_id)) {
+                            Some(state) => state.data_packets(STATS_STREAM_ID, &payload, DeliveryMode::Reliable, max_fragment),
+                            None => {
+                                gone.push(addr);
+                                continue;
+                            }
+                        }
+                    };
+                    let packets = match packets {
+                        Ok(packets) => packets,
+                        Err(e) => {
+                            tracing::warn!(peer = %addr, "Failed to frame stats: {}", e);
+                            continue;
+                        }
+                    };
+                    for packet in packets {
+                        if let Err(e) = transport.send_to(&packet, addr).await {
+                            tracing::debug!(peer = %addr, "Failed to send stats: {}", e);
+                            break;
+                        }
+                        metrics.record_packet_sent(packet.len());
+                    }
+                }
+                
+                if !gone.is_empty() {
+                    let mut subscribers = subscribers.write().await;
+                    for addr in gone {
+                        tracing::debug!(peer = %addr, "Stats subscriber's session ended");
+                        subscribers.remove(&addr);
+                    }
+                }
+            }
+        });
+        
+        self.stats_task = Some(task);
+    }
+
+    /// Start sending stats frames to `addr`
+    async fn subscribe_stats(&mut self, addr: SocketAddr) {
+        if self.stats_subscribers.write().await.insert(addr) {
+            tracing::info!(peer = %addr, "Monitor subscribed to stats");
+        }
+        if self.stats_task.is_none() {
+            self.start_stats_task();
+        }
+    }
+
     /// Get the local address the server is bound to
-    pub fn local_addr(&self) -> Result<SocketAddr> {
-        self.transport.local_addr()
+    pub fn local_addr(&self) -> Result<SocketAddr, TransportError> {
+        Ok(self.transport.local_addr()?)
     }
 
-    pub async fn accept(&mut self) -> Result<(SocketAddr, Session)> {
-        let mut buf = BytesMut::with_capacity(2048);
-        buf.resize(2048, 0);
-        let (len, src_addr) = self.transport.recv_from(&mut buf).await?;
-        buf.truncate(len);
-        let data = buf.freeze();
+    pub async fn accept(&mut self) -> Result<(SocketAddr, Session), TransportError> {
+        let (data, src_addr) = self.recv_datagram().await?;
+        let len = data.len();
+        if self.is_banned(src_addr).await {
+            return Err(anyhow::anyhow!("Dropped packet from banned address {}", src_addr.ip()).into());
+        }
         
         // Check global rate limit
         if let Some(ref limiter) = self.global_rate_limiter {
             if !limiter.check_and_consume(len) {
                 tracing::warn!(peer = %src_addr, "Global rate limit exceeded");
-                return Err(anyhow::anyhow!("Global rate limit exceeded"));
+                return Err(TransportError::RateLimited);
             }
         }
         
+        match self.handle_client_hello(&data, src_addr).await? {
+            Some((_session_id, session)) => Ok((src_addr, session)),
+            // Existing session found via address
+            // Create a copy for return (simplified)
+            None => Ok((src_addr, Session::new())),
+        }
+    }
+
+    /// Establish a session for a ClientHello from a new address
+    ///
+    /// Returns the new session ID and a copy of the session, or `None` when
+    /// `src_addr` already has a session (resending the ServerHello if the
+    /// datagram repeats its ClientHello) or was sent a Retry instead.
+    /// Under handshake pressure (or always, with `retry_threshold: Some(0)`)
+    /// a ClientHello without a valid retry token costs only an HMAC: no
+    /// session or key exchange is created for it.
+    async fn handle_client_hello(&mut self, data: &[u8], src_addr: SocketAddr) -> Result<Option<(u64, Session)>> {
         let mut connections = self.connections.write().await;
         let mut addr_map = self.addr_map.write().await;
         
         // Check if we already know this address
         if let Some(conn_id) = addr_map.get(&src_addr) {
-            if let Some(_state) = connections.get(conn_id) {
-                // Existing session found via address
-                // Create a copy for return (simplified)
-                let session_copy = Session::new(); // In real impl, we might need more state
-                return Ok((src_addr, session_copy));
+            if let Some(state) = connections.get_mut(conn_id) {
+                // The client lost our ServerHello and retried: answer with the
+                // same bytes so it derives the keys this session already holds
+                let retransmitted = serde_cbor::from_slice::<ClientHello>(data)
+                    .map(|hello| hello.nonce == state.client_nonce)
+                    .unwrap_or(false);
+                if retransmitted {
+                    // Repeated hellos also make room for a ServerHello held back so far
+                    state.amplification.on_received(data.len());
+                    if state.amplification.try_send(state.server_hello.len()) {
+                        tracing::debug!(peer = %src_addr, connection_id = %conn_id, "Duplicate ClientHello, resending ServerHello");
+                        self.transport.send_to(&state.server_hello, src_addr).await?;
+                    } else {
+                        tracing::debug!(peer = %src_addr, connection_id = %conn_id, "Duplicate ClientHello, ServerHello still over the amplification limit");
+                    }
+                }
+                return Ok(None);
+            }
+        }
+        
+        // Replies go to an address nobody has validated yet
+        let mut amplification = AmplificationLimit::default();
+        amplification.on_received(data.len());
+        
+        // Under pressure, new clients prove they own their address first
+        if let Some(ref ddos) = self.ddos_protection {
+            let hello: ClientHello = serde_cbor::from_slice(data)?;
+            let tokens = ddos.retry_tokens();
+            match hello.retry_token {
+                Some(ref token) if !tokens.validate(token, src_addr, &hello.random) => {
+                    tracing::warn!(peer = %src_addr, "ClientHello with invalid retry token");
+                    self.metrics.record_handshake_rejected();
+                    return Err(anyhow::anyhow!("Invalid retry token"));
+                }
+                None if ddos.requires_retry() => {
+                    let retry = serde_cbor::to_vec(&RetryFrame { token: tokens.issue(src_addr, &hello.random) })?;
+                    let packet = build_packet(Header::new(0, FRAME_TYPE_RETRY, 0, 0, 0, 0, DeliveryMode::BestEffort, None, Some(retry.len() as u32)), &retry, SerializationFormat::Cbor)?;
+                    if amplification.try_send(packet.len()) {
+                        tracing::debug!(peer = %src_addr, "Handshake pressure, answering ClientHello with Retry");
+                        self.transport.send_to(&packet, src_addr).await?;
+                    }
+                    return Ok(None);
+                }
+                // Only whoever receives at the address could echo our token
+                Some(_) => amplification.validate(),
+                None => {}
+            }
+        }
+        
+        if let Some(limit) = self.config.max_sessions_per_ip {
+            let sessions = addr_map.keys().filter(|addr| addr.ip() == src_addr.ip()).count();
+            if sessions >= limit as usize {
+                tracing::warn!(peer = %src_addr, sessions, limit, "Handshake rejected: too many sessions from this address");
+                self.metrics.record_handshake_rejected();
+                let close = serde_cbor::to_vec(&CloseFrame::with_reason(CloseReason::RateLimitExceeded, "Too many sessions from this address"))?;
+                let packet = build_packet(Header::new(0, FRAME_TYPE_CLOSE, 0, 0, 0, 0, DeliveryMode::BestEffort, None, Some(close.len() as u32)), &close, SerializationFormat::Cbor)?;
+                if amplification.try_send(packet.len()) {
+                    self.transport.send_to(&packet, src_addr).await?;
+                }
+                return Err(anyhow::anyhow!("Too many sessions from {}", src_addr.ip()));
             }
         }
         
@@ -161,14 +493,17 @@ impl Server {
             max_clock_skew_secs: 300,
         };
             let mut session = Session::with_config(session_config);
+            session.set_serialization_formats(&self.config.connection.serialization_formats);
             
             // Process ClientHello
-            let client_hello = session.process_client_hello(&data)?;
+            let client_hello = session.process_client_hello(data)
+                .map_err(TransportError::crypto)?;
             
             // Check DDoS protection for handshake
             if let Some(ref ddos) = self.ddos_protection {
                 if !ddos.check_handshake(src_addr.ip()).await {
                     tracing::warn!(peer = %src_addr, "Handshake rejected by DDoS protection");
+                    self.metrics.record_handshake_rejected();
                     return Err(anyhow::anyhow!("Handshake rejected"));
                 }
             }
@@ -190,18 +525,27 @@ impl Server {
             *next_id += 1;
             drop(next_id);
             
+            self.key_exchanges += 1;
             let (server_hello, kyber_shared) = session.generate_server_hello(
                 session_id,
                 cipher_suite,
                 &client_hello.kyber_public_key,
                 &client_hello.supported_formats  // Pass client's supported formats
-            )?;
+            ).map_err(TransportError::crypto)?;
             
             // Derive keys
             session.derive_keys_from_client_hello(&client_hello.public_key, Some(&kyber_shared));
             
-            // Send ServerHello
-            self.transport.send_to(&server_hello, src_addr).await?;
+            // Send ServerHello, unless it would turn a spoofed hello into a reflection
+            if amplification.try_send(server_hello.len()) {
+                self.transport.send_to(&server_hello, src_addr).await?;
+            } else {
+                tracing::debug!(
+                    peer = %src_addr,
+                    bytes = server_hello.len(),
+                    "ServerHello over the amplification limit, waiting for the client to repeat its hello"
+                );
+            }
             
             tracing::info!(
                 peer = %src_addr,
@@ -212,24 +556,36 @@ impl Server {
             // Store session
             let session_copy = Session::with_config(session_config);
             
-            // Create ConnectionId (for now, generate one or use session_id if we map it)
-            // In a real implementation, ConnectionId should be negotiated or derived
-            // For this phase, we'll generate a new one
-            let connection_id = ConnectionId::generate();
+            // Clients stamp their headers with the session ID as ConnectionId,
+            // so key the session the same way to recognize it after migration
+            let connection_id = ConnectionId::from_u64(session_id);
+            
+            // Compressed headers can't be told apart from FlatBuffers ones
+            let compress_headers = self.config.connection.enable_header_compression
+                && session.serialization_format() == SerializationFormat::Cbor;
             
             let state = ServerConnectionState {
                 session,
                 peer_addr: src_addr,
                 last_activity: std::time::Instant::now(),
                 pending_challenge: None,
-                header_compressor: if self.config.connection.enable_header_compression { Some(HeaderCompressor::new()) } else { None },
-                header_decompressor: if self.config.connection.enable_header_compression { Some(HeaderCompressor::new()) } else { None },
+                header_compressor: compress_headers.then(HeaderCompressor::new),
+                header_decompressor: compress_headers.then(HeaderCompressor::new),
+                client_nonce: client_hello.nonce,
+                server_hello,
+                amplification,
+                next_send_seq: 1,
+                cumulative_ack: 0,
+                received_ahead: BTreeSet::new(),
+                next_message_id: 0,
+                metrics: Metrics::new(),
+                unacked: VecDeque::new(),
             };
             
             connections.insert(connection_id, state);
             addr_map.insert(src_addr, connection_id);
             
-            Ok((src_addr, session_copy))
+            Ok(Some((session_id, session_copy)))
     }
 
     pub async fn get_session(&self, addr: &SocketAddr) -> Option<Session> {
@@ -242,32 +598,38 @@ impl Server {
         }
     }
 
-    pub async fn send_to(&mut self, data: &[u8], addr: SocketAddr) -> Result<()> {
+    pub async fn send_to(&mut self, data: &[u8], addr: SocketAddr) -> Result<(), TransportError> {
         // Check global rate limit
         if let Some(ref limiter) = self.global_rate_limiter {
             if !limiter.check_and_consume(data.len()) {
                 tracing::warn!(peer = %addr, "Global rate limit exceeded");
-                return Err(anyhow::anyhow!("Global rate limit exceeded"));
+                return Err(TransportError::RateLimited);
             }
         }
         
         self.transport.send_to(data, addr).await?;
+        self.metrics.record_packet_sent(data.len());
         Ok(())
     }
 
-    pub async fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+    pub async fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, SocketAddr), TransportError> {
         let (len, addr) = self.transport.recv_from(buf).await?;
+        self.metrics.record_packet_received(len);
+        if self.is_banned(addr).await {
+            return Err(anyhow::anyhow!("Dropped packet from banned address {}", addr.ip()).into());
+        }
         
         // Check DDoS protection
         if let Some(ref ddos) = self.ddos_protection {
             if !ddos.check_packet(addr.ip(), len).await {
+                self.metrics.record_packet_dropped();
                 // Packet rejected
                 // We return a length of 0 to indicate no data, or we could loop and receive next
                 // For simplicity, let's just drop it and return 0 length, caller should handle
                 // But recv_from expects to return data. Better to loop here?
                 // Or return an error?
                 // Let's return a specific error so the loop can continue
-                return Err(anyhow::anyhow!("DDoS protection rejected packet"));
+                return Err(anyhow::anyhow!("DDoS protection rejected packet").into());
             }
         }
         
@@ -277,7 +639,7 @@ impl Server {
         if len > 2 {
             let header_len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
             if len >= 2 + header_len {
-                if let Ok(header) = serde_cbor::from_slice::<Header>(&buf[2..2+header_len]) {
+                if let Ok(header) = decode_any_header(&buf[2..2+header_len]) {
                     if let Some(conn_id) = header.connection_id {
                         self.handle_connection_packet(conn_id, addr, &header, &buf[2+header_len..len]).await?;
                     }
@@ -375,12 +737,53 @@ impl Server {
         Ok(())
     }
 
-    pub async fn recv_packet(&mut self) -> Result<(Header, Vec<u8>, SocketAddr)> {
-        let mut buf = BytesMut::with_capacity(2048);
-        buf.resize(2048, 0);
-        let (len, addr) = self.transport.recv_from(&mut buf).await?;
-        buf.truncate(len);
+    /// Next datagram, in a pooled buffer
+    ///
+    /// Reads up to `io_batch_size` datagrams at once when none are left
+    /// from the last read. A GRO read is split back into its datagrams,
+    /// each a slice of the one buffer.
+    async fn recv_datagram(&mut self) -> Result<(Bytes, SocketAddr)> {
+        if let Some(datagram) = self.recv_backlog.pop_front() {
+            return Ok(datagram);
+        }
+        
+        let batch = self.config.connection.io_batch_size.clamp(1, MAX_BATCH);
+        let size = if self.transport.gro_enabled() { GRO_BUFFER_SIZE } else { RECV_BUFFER_SIZE };
+        while self.recv_bufs.len() < batch {
+            let mut buf = self.recv_pool.acquire();
+            buf.resize(size, 0);
+            self.recv_bufs.push(buf);
+        }
+        let mut meta = [RecvMeta::default(); MAX_BATCH];
+        let count = self.transport.recv_batch(&mut self.recv_bufs[..batch], &mut meta[..batch]).await?;
+        for (mut buf, meta) in self.recv_bufs.drain(..count).zip(meta) {
+            buf.truncate(meta.len);
+            let data = self.recv_pool.freeze(buf);
+            for segment in meta.segments() {
+                self.recv_backlog.push_back((data.slice(segment), meta.addr));
+            }
+        }
+        self.recv_backlog.pop_front()
+            .ok_or_else(|| anyhow::anyhow!("Receive returned no datagrams"))
+    }
+
+    /// Receive one packet; its payload is a slice of the receive buffer
+    pub async fn recv_packet(&mut self) -> Result<(Header, Bytes, SocketAddr), TransportError> {
+        let (buf, addr) = self.recv_datagram().await?;
+        if self.is_banned(addr).await {
+            return Err(anyhow::anyhow!("Dropped packet from banned address {}", addr.ip()).into());
+        }
         
+        let (header, payload, _migrated_from) = self.parse_packet(&buf, addr).await?;
+        Ok((header, payload, addr))
+    }
+
+    /// Parse a `[u16 header_len][Header][payload]` packet from `addr`
+    ///
+    /// Also drives path validation for packets from new addresses; the third
+    /// value is the client's previous address once a migration completes.
+    async fn parse_packet(&mut self, buf: &Bytes, addr: SocketAddr) -> Result<(Header, Bytes, Option<SocketAddr>)> {
+        let len = buf.len();
         if len < 2 {
             return Err(anyhow::anyhow!("Packet too short"));
         }
@@ -391,30 +794,38 @@ impl Server {
         }
         
         let header_data = &buf[2..2+header_len];
-        let payload = buf[2+header_len..].to_vec();
+        let payload = buf.slice(2+header_len..);
         
         let mut connections = self.connections.write().await;
         let mut addr_map = self.addr_map.write().await;
+        let mut migrated_from = None;
         
         // Try to find session by address
         let header = if let Some(conn_id) = addr_map.get(&addr).copied() {
             if let Some(state) = connections.get_mut(&conn_id) {
                 state.last_activity = std::time::Instant::now();
                 
-                if let Some(decompressor) = &mut state.header_decompressor {
+                // Compressed headers start with a flags byte below 0x80, CBOR maps
+                // at 0xA0 or above; migrating clients send CBOR for a while.
+                // Sessions using FlatBuffers have no decompressor.
+                let compressed = header_data.first().is_some_and(|&first| first < 0x80);
+                let header = if let (true, Some(decompressor)) = (compressed, &mut state.header_decompressor) {
                     match decompressor.decompress(header_data) {
                         Ok(h) => h,
                         Err(_) => serde_cbor::from_slice(header_data)?
                     }
                 } else {
-                    serde_cbor::from_slice(header_data)?
-                }
+                    decode_any_header(header_data)?
+                };
+                // Clients only frame packets once our ServerHello reached them
+                state.amplification.validate();
+                header
             } else {
-                serde_cbor::from_slice(header_data)?
+                decode_any_header(header_data)?
             }
         } else {
-            // Unknown address, try standard CBOR
-            let header: Header = serde_cbor::from_slice(header_data)?;
+            // Unknown address, so no decompressor: the header is CBOR or FlatBuffers
+            let header = decode_any_header(header_data)?;
             
             // Check for migration
             if let Some(conn_id) = header.connection_id {
@@ -435,6 +846,7 @@ impl Server {
                                         );
                                         
                                         addr_map.remove(&state.peer_addr);
+                                        migrated_from = Some(state.peer_addr);
                                         state.peer_addr = addr;
                                         state.pending_challenge = None;
                                         addr_map.insert(addr, conn_id);
@@ -490,17 +902,207 @@ impl Server {
             header
         };
         
-        Ok((header, payload, addr))
+        Ok((header, payload, migrated_from))
+    }
+
+    /// Wait for the next event on any session
+    ///
+    /// Completes handshakes, acknowledges sequenced packets and validates
+    /// migrating clients along the way. Datagrams that produce no event
+    /// (heartbeats, ACKs, stats subscriptions, unparseable packets) are
+    /// consumed silently.
+    pub async fn next_event(&mut self) -> Result<ServerEvent, TransportError> {
+        loop {
+            let (buf, addr) = self.recv_datagram().await?;
+            let len = buf.len();
+            self.metrics.record_packet_received(len);
+            if self.is_banned(addr).await {
+                continue;
+            }
+            
+            if let Some(ref limiter) = self.global_rate_limiter {
+                if !limiter.check_and_consume(len) {
+                    tracing::warn!(peer = %addr, "Global rate limit exceeded");
+                    continue;
+                }
+            }
+            
+            // Hellos and heartbeats are bare CBOR, whose first bytes read as a huge header length
+            let framed = len >= 2 && 2 + u16::from_be_bytes([buf[0], buf[1]]) as usize <= len;
+            if !framed {
+                match self.handle_client_hello(&buf, addr).await {
+                    Ok(Some((session_id, _))) => return Ok(ServerEvent::SessionEstablished { addr, session_id }),
+                    Ok(None) => {}
+                    Err(e) => tracing::debug!(peer = %addr, "Ignoring datagram: {}", e),
+                }
+                continue;
+            }
+            
+            let (header, payload, migrated_from) = match self.parse_packet(&buf, addr).await {
+                Ok(packet) => packet,
+                Err(e) => {
+                    tracing::debug!(peer = %addr, "Ignoring packet: {}", e);
+                    continue;
+                }
+            };
+            if let Some(old) = migrated_from {
+                let mut subscribers = self.stats_subscribers.write().await;
+                if subscribers.remove(&old) {
+                    subscribers.insert(addr);
+                }
+                return Ok(ServerEvent::PeerMigrated { old, new: addr });
+            }
+            
+            match header.msg_type {
+                FRAME_TYPE_DATA | FRAME_TYPE_STREAM_FIN => {
+                    if !self.acknowledge(&header, addr, len).await? {
+                        continue;
+                    }
+                    if header.msg_type == FRAME_TYPE_DATA && header.flags & FLAG_FRAGMENT == 0 {
+                        // Monitors subscribe by writing anything on the stats stream
+                        if header.stream_id == STATS_STREAM_ID && self.config.stats_stream_interval.is_some() {
+                            self.subscribe_stats(addr).await;
+                            continue;
+                        }
+                        let data = if header.flags & FLAG_COMPRESSED != 0 {
+                            match compression::decompress_payload(&payload) {
+                                Ok(data) => Bytes::from(data),
+                                Err(e) => {
+                                    tracing::warn!(peer = %addr, "Dropping message that failed to decompress: {}", e);
+                                    continue;
+                                }
+                            }
+                        } else {
+                            payload
+                        };
+                        return Ok(ServerEvent::DataReceived { addr, stream_id: header.stream_id, data });
+                    }
+                }
+                FRAME_TYPE_ACK => {
+                    if let Ok(ack) = serde_cbor::from_slice::<AckFrame>(&payload) {
+                        let mut connections = self.connections.write().await;
+                        let addr_map = self.addr_map.read().await;
+                        if let Some(state) = addr_map.get(&addr).and_then(|conn_id| connections.get_mut(conn_id)) {
+                            state.on_ack(ack.cumulative_ack);
+                        }
+                    }
+                }
+                FRAME_TYPE_CLOSE => {
+                    if !self.remove_session(addr).await {
+                        continue;
+                    }
+                    let (reason, message) = match serde_cbor::from_slice::<CloseFrame>(&payload) {
+                        Ok(close) => (close.reason_code, close.message),
+                        Err(_) => (CloseReason::Normal, None),
+                    };
+                    tracing::info!(peer = %addr, ?reason, "Client closed session");
+                    return Ok(ServerEvent::Closed { addr, reason, message });
+                }
+                FRAME_TYPE_MTU_PROBE => {
+                    // Clients discover the path MTU; the server sends no probes
+                    if let Err(e) = self.answer_mtu_probe(addr, len).await {
+                        tracing::debug!(peer = %addr, "Answering MTU probe failed: {}", e);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Track a sequenced packet and ACK everything received in order
+    ///
+    /// Returns false for packets from unknown addresses and duplicates.
+    /// `len` is the datagram's size, counted in the session's metrics.
+    async fn acknowledge(&mut self, header: &Header, addr: SocketAddr, len: usize) -> Result<bool> {
+        let (fresh, cumulative_ack, format) = {
+            let mut connections = self.connections.write().await;
+            let addr_map = self.addr_map.read().await;
+            let state = match addr_map.get(&addr).and_then(|conn_id| connections.get_mut(conn_id)) {
+                Some(state) => state,
+                None => return Ok(false),
+            };
+            let fresh = state.track_received(header.sequence);
+            if fresh {
+                state.metrics.record_packet_received(len);
+            } else {
+                state.metrics.record_duplicate();
+            }
+            (fresh, state.cumulative_ack, state.session.serialization_format())
+        };
+        
+        let ack = serde_cbor::to_vec(&AckFrame { cumulative_ack, sack_ranges: Vec::new(), ecn_counts: None, receive_window: None })?;
+        let packet = build_packet(Header::new(0, FRAME_TYPE_ACK, 0, 0, 0, 0, DeliveryMode::BestEffort, None, Some(ack.len() as u32)), &ack, format)?;
+        self.send_to(&packet, addr).await?;
+        Ok(fresh)
+    }
+
+    /// Tell the client at `addr` its MTU probe of `size` bytes arrived
+    async fn answer_mtu_probe(&mut self, addr: SocketAddr, size: usize) -> Result<()> {
+        let format = {
+            let connections = self.connections.read().await;
+            let addr_map = self.addr_map.read().await;
+            match addr_map.get(&addr).and_then(|conn_id| connections.get(conn_id)) {
+                Some(state) => state.session.serialization_format(),
+                None => return Ok(()),
+            }
+        };
+        let ack = serde_cbor::to_vec(&MtuProbeAckFrame { size: size as u32 })?;
+        let packet = build_packet(Header::new(0, FRAME_TYPE_MTU_PROBE_ACK, 0, 0, 0, 0, DeliveryMode::BestEffort, None, Some(ack.len() as u32)), &ack, format)?;
+        self.send_to(&packet, addr).await?;
+        Ok(())
+    }
+
+    /// Forget the session at `addr`; false if there was none
+    async fn remove_session(&mut self, addr: SocketAddr) -> bool {
+        self.stats_subscribers.write().await.remove(&addr);
+        let mut connections = self.connections.write().await;
+        let mut addr_map = self.addr_map.write().await;
+        match addr_map.remove(&addr) {
+            Some(conn_id) => connections.remove(&conn_id).is_some(),
+            None => false,
+        }
+    }
+
+    /// Send `data` to the client at `addr` on `stream_id`
+    ///
+    /// Packets are sequenced so the client delivers them in order, but are
+    /// not retransmitted. Data over `max_fragment_size` is sent in fragments.
+    pub async fn send_on_stream(&mut self, addr: SocketAddr, stream_id: u32, data: &[u8]) -> Result<(), TransportError> {
+        self.send_on_stream_with_mode(addr, stream_id, data, DeliveryMode::Reliable).await
+    }
+
+    /// Like `send_on_stream`, stamping `mode` on the packet
+    ///
+    /// The server still sends once; the mode tells the client how to treat
+    /// the stream, e.g. whether to wait for missing fragments.
+    pub async fn send_on_stream_with_mode(&mut self, addr: SocketAddr, stream_id: u32, data: &[u8], mode: DeliveryMode) -> Result<(), TransportError> {
+        let packets = {
+            let mut connections = self.connections.write().await;
+            let addr_map = self.addr_map.read().await;
+            let state = addr_map.get(&addr)
+                .and_then(|conn_id| connections.get_mut(conn_id))
+                .ok_or(TransportError::NotConnected)?;
+            state.data_packets(stream_id, data, mode, self.config.connection.max_fragment_size)?
+        };
+        
+        for packet in packets {
+            self.send_to(&packet, addr).await?;
+        }
+        Ok(())
     }
 
     /// Gracefully shutdown the server
-    pub async fn shutdown(&mut self) -> Result<()> {
+    pub async fn shutdown(&mut self) -> Result<(), TransportError> {
         tracing::info!("Server shutting down");
         
-        // Stop cleanup task
+        // Stop background tasks
         if let Some(task) = self.cleanup_task.take() {
             task.abort();
         }
+        if let Some(task) = self.stats_task.take() {
+            task.abort();
+        }
+        self.stats_subscribers.write().await.clear();
         
         // Clear all sessions
         let mut connections = self.connections.write().await;
@@ -514,10 +1116,79 @@ impl Server {
         Ok(())
     }
 
+    /// Ban `ip` for `duration`, dropping its sessions
+    ///
+    /// Until the ban expires, datagrams from the address are discarded as
+    /// soon as they are received.
+    pub async fn ban_ip(&self, ip: IpAddr, duration: Duration) {
+        self.blacklist.ban_temporary(ip, "Banned by operator".to_string(), duration).await;
+        
+        let mut connections = self.connections.write().await;
+        let mut addr_map = self.addr_map.write().await;
+        addr_map.retain(|addr, conn_id| {
+            if addr.ip() != ip {
+                return true;
+            }
+            tracing::info!(peer = %addr, connection_id = %conn_id, "Session dropped by ban");
+            connections.remove(conn_id);
+            false
+        });
+    }
+
+    /// Lift a ban placed with `ban_ip`
+    pub async fn unban_ip(&self, ip: IpAddr) {
+        self.blacklist.unban(ip).await;
+    }
+
+    /// Addresses currently banned
+    pub async fn banned_ips(&self) -> Vec<BlacklistEntry> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.blacklist.get_all().await
+            .into_iter()
+            .filter(|entry| entry.expires_at.is_none_or(|expires| expires > now))
+            .collect()
+    }
+
+    /// True (counting the drop) if `addr` is banned
+    async fn is_banned(&self, addr: SocketAddr) -> bool {
+        let banned = self.blacklist.is_blacklisted(addr.ip()).await;
+        if banned {
+            tracing::trace!(peer = %addr, "Dropping packet from banned address");
+            self.metrics.record_packet_dropped();
+        }
+        banned
+    }
+
+    /// Number of ClientHellos that reached the key exchange
+    pub fn key_exchanges(&self) -> u64 {
+        self.key_exchanges
+    }
+
     /// Get current session count
     pub async fn session_count(&self) -> usize {
         self.connections.read().await.len()
     }
+
+    /// Get server traffic metrics
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// Export server metrics through `prometheus::export_metrics()`
+    ///
+    /// Besides traffic counters this exports `jsp_sessions_active` and
+    /// `jsp_session_idle_seconds` labeled by connection ID. Values are
+    /// refreshed on every scrape until the server is dropped.
+    pub fn register_metrics(&self, labels: &[(&str, &str)]) -> Result<()> {
+        let sessions = Arc::downgrade(&self.connections);
+        let sessions: std::sync::Weak<dyn SessionTable> = sessions;
+        let live = crate::prometheus::LiveServerMetrics::new(&self.metrics, sessions, labels)?;
+        crate::prometheus::global_registry().add_source(Box::new(live));
+        Ok(())
+    }
 }
 
 impl Drop for Server {
@@ -525,5 +1196,18 @@ impl Drop for Server {
         if let Some(task) = self.cleanup_task.take() {
             task.abort();
         }
+        if let Some(task) = self.stats_task.take() {
+            task.abort();
+        }
     }
 }
+
+/// `[u16 header_len][Header][payload]` with an uncompressed header in `format`
+fn build_packet(header: Header, payload: &[u8], format: SerializationFormat) -> Result<Vec<u8>> {
+    let header_bytes = Codec::new(format).encode_header(&header)?;
+    let mut packet = Vec::with_capacity(2 + header_bytes.len() + payload.len());
+    packet.extend_from_slice(&(header_bytes.len() as u16).to_be_bytes());
+    packet.extend_from_slice(&header_bytes);
+    packet.extend_from_slice(payload);
+    Ok(packet)
+}
@@ -1,12 +1,15 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{mpsc, Mutex};
-use anyhow::Result;
+use anyhow::{bail, Result};
+use async_trait::async_trait;
 use tracing::{info, error, warn};
+use crate::rate_limit::RateLimiter;
 
 /// Messages exchanged over the signaling channel
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,35 +24,102 @@ pub enum SignalingMessage {
     Answer { target: String, sdp: String },
     /// Send an ICE candidate to a target peer
     Candidate { target: String, candidate: String },
+    /// Join a room of at most two peers, to be paired with the other one
+    Join { room: String },
+    /// The other peer of the room (sent to both once the room is full)
+    PeerJoined { peer_id: String },
+    /// A peer this one was paired or exchanging messages with disconnected
+    PeerLeft { peer_id: String },
     /// Error message
     Error { message: String },
 }
 
+/// Peers paired per room
+pub const ROOM_SIZE: usize = 2;
+
+/// Limits the signaling server enforces on every peer
+#[derive(Debug, Clone)]
+pub struct SignalingConfig {
+    /// Largest message accepted, in bytes; a peer sending a larger one is
+    /// told so and disconnected
+    pub max_message_size: usize,
+    /// Messages a peer may send per second; more are dropped with an `Error`
+    pub rate_limit_messages: u32,
+    /// Messages a peer may send at once, such as a burst of candidates
+    pub rate_limit_burst: u32,
+}
+
+impl Default for SignalingConfig {
+    fn default() -> Self {
+        Self {
+            max_message_size: 64 * 1024,
+            rate_limit_messages: 50,
+            rate_limit_burst: 200,
+        }
+    }
+}
+
 type Tx = mpsc::UnboundedSender<SignalingMessage>;
 
+/// A registered peer
+struct Peer {
+    tx: Tx,
+    room: Option<String>,
+    /// Peers it relayed messages to or received messages from
+    contacts: HashSet<String>,
+}
+
+#[derive(Default)]
+struct State {
+    peers: HashMap<String, Peer>,
+    rooms: HashMap<String, Vec<String>>,
+}
+
+/// How long a disconnecting peer's pending messages (such as the error
+/// that closed it) get to be written
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
 /// A simple TCP-based signaling server
+///
+/// Messages are JSON `SignalingMessage`s behind a 4-byte big-endian length.
+/// Peers register by ID, then either address each other directly or join
+/// a room to learn the ID of the peer they are paired with. When a peer
+/// disconnects, its room partner and every peer it exchanged messages with
+/// get a `PeerLeft`.
 pub struct SignalingServer {
     addr: String,
-    peers: Arc<Mutex<HashMap<String, Tx>>>,
+    config: SignalingConfig,
+    state: Arc<Mutex<State>>,
 }
 
 impl SignalingServer {
     pub fn new(addr: impl Into<String>) -> Self {
+        Self::with_config(addr, SignalingConfig::default())
+    }
+
+    pub fn with_config(addr: impl Into<String>, config: SignalingConfig) -> Self {
         Self {
             addr: addr.into(),
-            peers: Arc::new(Mutex::new(HashMap::new())),
+            config,
+            state: Arc::new(Mutex::new(State::default())),
         }
     }
 
     pub async fn run(&self) -> Result<()> {
         let listener = TcpListener::bind(&self.addr).await?;
-        info!("Signaling server listening on {}", self.addr);
+        self.serve(listener).await
+    }
+
+    /// Accept peers on an already bound listener
+    pub async fn serve(&self, listener: TcpListener) -> Result<()> {
+        info!("Signaling server listening on {}", listener.local_addr()?);
 
         loop {
             let (stream, addr) = listener.accept().await?;
-            let peers = self.peers.clone();
+            let state = self.state.clone();
+            let config = self.config.clone();
             tokio::spawn(async move {
-                if let Err(e) = handle_connection(stream, addr, peers).await {
+                if let Err(e) = handle_connection(stream, addr, state, config).await {
                     error!("Connection error from {}: {}", addr, e);
                 }
             });
@@ -60,18 +130,20 @@ impl SignalingServer {
 async fn handle_connection(
     stream: TcpStream,
     addr: SocketAddr,
-    peers: Arc<Mutex<HashMap<String, Tx>>>,
+    state: Arc<Mutex<State>>,
+    config: SignalingConfig,
 ) -> Result<()> {
     info!("New connection from {}", addr);
 
     let (tx, mut rx) = mpsc::unbounded_channel();
     let mut peer_id: Option<String> = None;
+    let mut limiter = RateLimiter::with_burst(config.rate_limit_messages, u64::MAX, config.rate_limit_burst, u64::MAX);
 
     // Split stream into owned halves
     let (mut reader, mut writer) = stream.into_split();
     
     // Task to write messages to the socket
-    let write_task = tokio::spawn(async move {
+    let mut write_task = tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
             let json = serde_json::to_string(&msg).unwrap();
             let len = (json.len() as u32).to_be_bytes();
@@ -91,6 +163,12 @@ async fn handle_connection(
             break;
         }
         let len = u32::from_be_bytes(buf) as usize;
+        if len > config.max_message_size {
+            warn!("{} sent a {} byte message, over the {} byte limit", addr, len, config.max_message_size);
+            let message = format!("Message of {} bytes exceeds the {} byte limit", len, config.max_message_size);
+            let _ = tx.send(SignalingMessage::Error { message });
+            break;
+        }
         
         // Read payload
         let mut payload = vec![0u8; len];
@@ -98,6 +176,12 @@ async fn handle_connection(
             break;
         }
 
+        if !limiter.check_and_consume(len) {
+            warn!("Rate limit exceeded by {}", addr);
+            let _ = tx.send(SignalingMessage::Error { message: "Rate limit exceeded, message dropped".to_string() });
+            continue;
+        }
+
         let msg: SignalingMessage = match serde_json::from_slice(&payload) {
             Ok(m) => m,
             Err(e) => {
@@ -106,50 +190,142 @@ async fn handle_connection(
             }
         };
 
-        match msg {
-            SignalingMessage::Register { peer_id: pid } => {
+        let mut registry = state.lock().await;
+        if let SignalingMessage::Register { peer_id: pid } = msg {
+            if let Some(registered) = &peer_id {
+                let message = format!("Already registered as {}", registered);
+                let _ = tx.send(SignalingMessage::Error { message });
+            } else if registry.peers.contains_key(&pid) {
+                warn!("Peer ID {} from {} is already registered", pid, addr);
+                let _ = tx.send(SignalingMessage::Error { message: format!("Peer ID already registered: {}", pid) });
+            } else {
                 info!("Peer registered: {}", pid);
-                let mut map = peers.lock().await;
-                map.insert(pid.clone(), tx.clone());
+                registry.peers.insert(pid.clone(), Peer { tx: tx.clone(), room: None, contacts: HashSet::new() });
                 peer_id = Some(pid);
                 let _ = tx.send(SignalingMessage::Registered);
             }
+            continue;
+        }
+        let from = match &peer_id {
+            Some(from) => from.clone(),
+            None => {
+                let _ = tx.send(SignalingMessage::Error { message: "Register before sending messages".to_string() });
+                continue;
+            }
+        };
+        match msg {
             SignalingMessage::Offer { target, sdp } => {
-                relay_message(&peers, &target, SignalingMessage::Offer { target: peer_id.clone().unwrap(), sdp }).await;
+                relay_message(&mut registry, &from, &target, SignalingMessage::Offer { target: from.clone(), sdp }, &tx);
             }
             SignalingMessage::Answer { target, sdp } => {
-                relay_message(&peers, &target, SignalingMessage::Answer { target: peer_id.clone().unwrap(), sdp }).await;
+                relay_message(&mut registry, &from, &target, SignalingMessage::Answer { target: from.clone(), sdp }, &tx);
             }
             SignalingMessage::Candidate { target, candidate } => {
-                relay_message(&peers, &target, SignalingMessage::Candidate { target: peer_id.clone().unwrap(), candidate }).await;
+                relay_message(&mut registry, &from, &target, SignalingMessage::Candidate { target: from.clone(), candidate }, &tx);
             }
+            SignalingMessage::Join { room } => join_room(&mut registry, &from, room, &tx),
             _ => {}
         }
     }
 
     if let Some(pid) = peer_id {
         info!("Peer disconnected: {}", pid);
-        peers.lock().await.remove(&pid);
+        remove_peer(&mut *state.lock().await, &pid);
+    }
+    // Let queued messages, such as the error that ended the connection, out
+    drop(tx);
+    if tokio::time::timeout(FLUSH_TIMEOUT, &mut write_task).await.is_err() {
+        write_task.abort();
     }
-    write_task.abort();
 
     Ok(())
 }
 
-async fn relay_message(peers: &Arc<Mutex<HashMap<String, Tx>>>, target: &str, msg: SignalingMessage) {
-    let map = peers.lock().await;
-    if let Some(tx) = map.get(target) {
-        let _ = tx.send(msg);
-    } else {
-        warn!("Target peer not found: {}", target);
+/// Forward `msg` from `from` to `target`, telling the sender when the
+/// target isn't registered
+fn relay_message(state: &mut State, from: &str, target: &str, msg: SignalingMessage, sender: &Tx) {
+    match state.peers.get_mut(target) {
+        Some(peer) => {
+            peer.contacts.insert(from.to_string());
+            let _ = peer.tx.send(msg);
+        }
+        None => {
+            warn!("Target peer not found: {}", target);
+            let _ = sender.send(SignalingMessage::Error { message: format!("Target peer not found: {}", target) });
+            return;
+        }
+    }
+    if let Some(peer) = state.peers.get_mut(from) {
+        peer.contacts.insert(target.to_string());
+    }
+}
+
+/// Put `peer_id` in `room`, pairing it with the peer already there
+fn join_room(state: &mut State, peer_id: &str, room: String, sender: &Tx) {
+    if let Some(current) = state.peers.get(peer_id).and_then(|peer| peer.room.as_ref()) {
+        let _ = sender.send(SignalingMessage::Error { message: format!("Already in room {}", current) });
+        return;
+    }
+    let members = state.rooms.entry(room.clone()).or_default();
+    if members.len() >= ROOM_SIZE {
+        let _ = sender.send(SignalingMessage::Error { message: format!("Room {} is full", room) });
+        return;
+    }
+    members.push(peer_id.to_string());
+    let members = members.clone();
+    info!("Peer {} joined room {}", peer_id, room);
+    if let Some(peer) = state.peers.get_mut(peer_id) {
+        peer.room = Some(room);
+    }
+
+    for other in members.iter().filter(|member| *member != peer_id) {
+        if let Some(peer) = state.peers.get(other) {
+            let _ = peer.tx.send(SignalingMessage::PeerJoined { peer_id: peer_id.to_string() });
+        }
+        let _ = sender.send(SignalingMessage::PeerJoined { peer_id: other.clone() });
+    }
+}
+
+/// Forget `peer_id`, telling its room partner and contacts it left
+fn remove_peer(state: &mut State, peer_id: &str) {
+    let peer = match state.peers.remove(peer_id) {
+        Some(peer) => peer,
+        None => return,
+    };
+    let mut notify = peer.contacts;
+    if let Some(room) = peer.room {
+        if let Some(members) = state.rooms.get_mut(&room) {
+            members.retain(|member| member != peer_id);
+            notify.extend(members.iter().cloned());
+            if members.is_empty() {
+                state.rooms.remove(&room);
+            }
+        }
+    }
+    for other in notify {
+        if let Some(other) = state.peers.get_mut(&other) {
+            other.contacts.remove(peer_id);
+            let _ = other.tx.send(SignalingMessage::PeerLeft { peer_id: peer_id.to_string() });
+        }
     }
 }
 
+/// Channel that carries signaling messages to and from a peer
+///
+/// `recv` must be cancel-safe: callers poll it under timeouts.
+#[async_trait]
+pub trait SignalingChannel: Send {
+    async fn send(&mut self, msg: SignalingMessage) -> Result<()>;
+    async fn recv(&mut self) -> Result<SignalingMessage>;
+}
+
 /// Client for the signaling server
 pub struct SignalingClient {
     stream: TcpStream,
     #[allow(dead_code)]
     peer_id: String,
+    /// Bytes of a partially received message
+    read_buf: Vec<u8>,
 }
 
 impl SignalingClient {
@@ -160,7 +336,7 @@ impl SignalingClient {
         let msg = SignalingMessage::Register { peer_id: peer_id.clone() };
         Self::send_msg(&mut stream, &msg).await?;
 
-        Ok(Self { stream, peer_id })
+        Ok(Self { stream, peer_id, read_buf: Vec::new() })
     }
 
     async fn send_msg(stream: &mut TcpStream, msg: &SignalingMessage) -> Result<()> {
@@ -175,15 +351,141 @@ impl SignalingClient {
         Self::send_msg(&mut self.stream, &msg).await
     }
 
+    /// Join `room`; a `PeerJoined` follows once another peer is in it
+    pub async fn join(&mut self, room: impl Into<String>) -> Result<()> {
+        self.send(SignalingMessage::Join { room: room.into() }).await
+    }
+
+    /// Receive the next message; cancelling this loses no data
     pub async fn recv(&mut self) -> Result<SignalingMessage> {
-        let mut buf = [0u8; 4];
-        self.stream.read_exact(&mut buf).await?;
-        let len = u32::from_be_bytes(buf) as usize;
-        
-        let mut payload = vec![0u8; len];
-        self.stream.read_exact(&mut payload).await?;
-        
-        let msg = serde_json::from_slice(&payload)?;
-        Ok(msg)
+        loop {
+            if self.read_buf.len() >= 4 {
+                let len = u32::from_be_bytes([self.read_buf[0], self.read_buf[1], self.read_buf[2], self.read_buf[3]]) as usize;
+                if self.read_buf.len() >= 4 + len {
+                    let payload: Vec<u8> = self.read_buf.drain(..4 + len).skip(4).collect();
+                    let msg = serde_json::from_slice(&payload)?;
+                    return Ok(msg);
+                }
+            }
+
+            let mut chunk = [0u8; 4096];
+            let read = self.stream.read(&mut chunk).await?;
+            if read == 0 {
+                bail!("Signaling connection closed");
+            }
+            self.read_buf.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+#[async_trait]
+impl SignalingChannel for SignalingClient {
+    async fn send(&mut self, msg: SignalingMessage) -> Result<()> {
+        SignalingClient::send(self, msg).await
+    }
+
+    async fn recv(&mut self) -> Result<SignalingMessage> {
+        SignalingClient::recv(self).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn start(config: SignalingConfig) -> Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?.to_string();
+        let server = SignalingServer::with_config(addr.clone(), config);
+        tokio::spawn(async move { server.serve(listener).await });
+        Ok(addr)
+    }
+
+    async fn registered(addr: &str, peer_id: &str) -> Result<SignalingClient> {
+        let mut client = SignalingClient::connect(addr, peer_id.to_string()).await?;
+        assert!(matches!(next(&mut client).await?, SignalingMessage::Registered));
+        Ok(client)
+    }
+
+    async fn next(client: &mut SignalingClient) -> Result<SignalingMessage> {
+        tokio::time::timeout(Duration::from_secs(2), client.recv()).await?
+    }
+
+    fn candidate(target: &str) -> SignalingMessage {
+        SignalingMessage::Candidate { target: target.to_string(), candidate: "{}".to_string() }
+    }
+
+    #[tokio::test]
+    async fn test_room_pairs_peers_and_reports_disconnect() -> Result<()> {
+        let addr = start(SignalingConfig::default()).await?;
+        let mut a = registered(&addr, "a").await?;
+        let mut b = registered(&addr, "b").await?;
+
+        a.join("room").await?;
+        b.join("room").await?;
+        assert!(matches!(next(&mut a).await?, SignalingMessage::PeerJoined { peer_id } if peer_id == "b"));
+        assert!(matches!(next(&mut b).await?, SignalingMessage::PeerJoined { peer_id } if peer_id == "a"));
+
+        // Relayed with the sender as the target, so the answer goes back to it
+        a.send(candidate("b")).await?;
+        assert!(matches!(next(&mut b).await?, SignalingMessage::Candidate { target, .. } if target == "a"));
+
+        let mut c = registered(&addr, "c").await?;
+        c.join("room").await?;
+        assert!(matches!(next(&mut c).await?, SignalingMessage::Error { message } if message.contains("full")));
+        // IDs are unique
+        let mut duplicate = SignalingClient::connect(&addr, "a".to_string()).await?;
+        assert!(matches!(next(&mut duplicate).await?, SignalingMessage::Error { message } if message.contains("already registered")));
+
+        drop(b);
+        assert!(matches!(next(&mut a).await?, SignalingMessage::PeerLeft { peer_id } if peer_id == "b"));
+        // The room has space again
+        c.join("room").await?;
+        assert!(matches!(next(&mut c).await?, SignalingMessage::PeerJoined { peer_id } if peer_id == "a"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unregistered_peer_cannot_relay() -> Result<()> {
+        let addr = start(SignalingConfig::default()).await?;
+        let _b = registered(&addr, "b").await?;
+
+        let mut stream = TcpStream::connect(&addr).await?;
+        SignalingClient::send_msg(&mut stream, &candidate("b")).await?;
+        let mut client = SignalingClient { stream, peer_id: String::new(), read_buf: Vec::new() };
+        assert!(matches!(next(&mut client).await?, SignalingMessage::Error { message } if message.contains("Register")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_oversized_message_closes_connection() -> Result<()> {
+        let config = SignalingConfig { max_message_size: 256, ..SignalingConfig::default() };
+        let addr = start(config).await?;
+        let _b = registered(&addr, "b").await?;
+        let mut a = registered(&addr, "a").await?;
+
+        a.send(SignalingMessage::Offer { target: "b".to_string(), sdp: "x".repeat(1024) }).await?;
+        assert!(matches!(next(&mut a).await?, SignalingMessage::Error { message } if message.contains("256 byte limit")));
+        assert!(next(&mut a).await.is_err(), "connection stayed open");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_drops_excess_messages() -> Result<()> {
+        // Registering uses one of the two messages the burst allows
+        let config = SignalingConfig { rate_limit_messages: 1, rate_limit_burst: 2, ..SignalingConfig::default() };
+        let addr = start(config).await?;
+        let mut b = registered(&addr, "b").await?;
+        let mut a = registered(&addr, "a").await?;
+
+        for _ in 0..3 {
+            a.send(candidate("b")).await?;
+        }
+        assert!(matches!(next(&mut b).await?, SignalingMessage::Candidate { .. }));
+        for _ in 0..2 {
+            assert!(matches!(next(&mut a).await?, SignalingMessage::Error { message } if message.contains("Rate limit")));
+        }
+        assert!(next(&mut b).await.is_err(), "dropped messages were relayed");
+        Ok(())
     }
 }
@@ -0,0 +1,127 @@
+//! Stats stream
+//!
+//! A monitor such as `jsp-cli monitor` opens `STATS_STREAM_ID` and sends
+//! anything on it to subscribe. A server with `stats_stream_interval` set
+//! then sends a CBOR `StatsFrame` on that stream every interval, until the
+//! monitor's session ends.
+
+use std::net::SocketAddr;
+use serde::{Deserialize, Serialize};
+use jsp_core::stream::RESERVED_STREAM_ID_START;
+use crate::metrics::MetricsSnapshot;
+
+/// Reserved stream carrying subscriptions and stats frames
+pub const STATS_STREAM_ID: u32 = RESERVED_STREAM_ID_START + 0x300;
+
+/// Sessions listed per frame, busiest first
+pub const MAX_FRAME_SESSIONS: usize = 32;
+
+/// One session (or gateway flow) in a stats frame
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionStats {
+    /// 0 for gateway flows, whose sessions the gateway can't see into
+    pub session_id: u64,
+    pub peer: SocketAddr,
+    /// Backend a gateway relays the flow to
+    pub backend: Option<SocketAddr>,
+    /// Packets sent and not acknowledged yet
+    pub queue_depth: u64,
+    pub metrics: MetricsSnapshot,
+}
+
+impl SessionStats {
+    /// Bytes both ways
+    pub fn bytes_total(&self) -> u64 {
+        self.metrics.bytes_sent + self.metrics.bytes_received
+    }
+}
+
+/// What a server sends subscribers every stats interval
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatsFrame {
+    /// Milliseconds since the UNIX epoch
+    pub timestamp_ms: u64,
+    /// Totals across every session
+    pub metrics: MetricsSnapshot,
+    /// Sessions at the time of the frame, busiest first
+    pub sessions: Vec<SessionStats>,
+    /// Sessions left out past `MAX_FRAME_SESSIONS`
+    pub omitted_sessions: usize,
+}
+
+impl StatsFrame {
+    /// Frame stamped with the current time, keeping the busiest sessions
+    pub fn new(metrics: MetricsSnapshot, mut sessions: Vec<SessionStats>) -> Self {
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.bytes_total()));
+        let omitted_sessions = sessions.len().saturating_sub(MAX_FRAME_SESSIONS);
+        sessions.truncate(MAX_FRAME_SESSIONS);
+        let timestamp_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        Self { timestamp_ms, metrics, sessions, omitted_sessions }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, serde_cbor::Error> {
+        serde_cbor::to_vec(self)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, serde_cbor::Error> {
+        serde_cbor::from_slice(data)
+    }
+}
+
+/// Where a server's stats frames come from
+///
+/// Servers describe their own sessions; a gateway plugs in its flows with
+/// `Server::set_stats_source`.
+pub trait StatsSource: Send + Sync {
+    /// Current frame, or None to skip this interval
+    fn stats_frame(&self) -> Option<StatsFrame>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Metrics;
+    use std::time::Duration;
+
+    fn session(session_id: u64, bytes: usize) -> SessionStats {
+        let metrics = Metrics::new();
+        metrics.record_packet_sent(bytes);
+        metrics.record_rtt_sample(Duration::from_millis(session_id));
+        SessionStats {
+            session_id,
+            peer: format!("127.0.0.1:{}", 4000 + session_id).parse().unwrap(),
+            backend: None,
+            queue_depth: session_id,
+            metrics: metrics.snapshot(),
+        }
+    }
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let totals = Metrics::new();
+        totals.record_packet_received(1200);
+        totals.record_duplicate();
+        let mut sessions = vec![session(1, 100), session(2, 5000)];
+        sessions[0].backend = Some("10.0.0.1:8080".parse().unwrap());
+
+        let frame = StatsFrame::new(totals.snapshot(), sessions);
+        let decoded = StatsFrame::from_bytes(&frame.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded, frame);
+        // Busiest first
+        assert_eq!(decoded.sessions.iter().map(|s| s.session_id).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(decoded.sessions[0].metrics.rtt_p50_ms, 2.0);
+        assert!(StatsFrame::from_bytes(b"not cbor").is_err());
+    }
+
+    #[test]
+    fn test_frame_caps_sessions() {
+        let sessions = (1..=MAX_FRAME_SESSIONS as u64 + 3).map(|id| session(id, id as usize)).collect();
+        let frame = StatsFrame::new(Metrics::new().snapshot(), sessions);
+        assert_eq!(frame.sessions.len(), MAX_FRAME_SESSIONS);
+        assert_eq!(frame.omitted_sessions, 3);
+        assert_eq!(frame.sessions[0].session_id, MAX_FRAME_SESSIONS as u64 + 3);
+    }
+}
@@ -1,6 +1,9 @@
 use tokio::net::{TcpListener, TcpStream};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Mutex;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use anyhow::Result;
 
 /// TCP transport for fallback when UDP is blocked
@@ -105,6 +108,72 @@ impl TcpTransport {
         tracing::debug!(peer = %self.peer_addr, "TCP connection closed");
         Ok(())
     }
+
+    /// Split into a cloneable transport whose read and write halves
+    /// can be used concurrently from different tasks
+    pub fn into_shared(self) -> SharedTcpTransport {
+        let (reader, writer) = self.stream.into_split();
+        SharedTcpTransport {
+            reader: Arc::new(Mutex::new(reader)),
+            writer: Arc::new(Mutex::new(writer)),
+            local_addr: self.local_addr,
+            peer_addr: self.peer_addr,
+        }
+    }
+}
+
+/// Cloneable TCP transport using the same length-prefixed framing as `TcpTransport`
+#[derive(Clone)]
+pub struct SharedTcpTransport {
+    reader: Arc<Mutex<OwnedReadHalf>>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    local_addr: SocketAddr,
+    peer_addr: SocketAddr,
+}
+
+impl SharedTcpTransport {
+    /// Send data over TCP with length prefix
+    pub async fn send(&self, data: &[u8]) -> Result<usize> {
+        let len = (data.len() as u32).to_be_bytes();
+        let mut writer = self.writer.lock().await;
+        writer.write_all(&len).await?;
+        writer.write_all(data).await?;
+        writer.flush().await?;
+        Ok(data.len())
+    }
+
+    /// Receive one length-prefixed message
+    pub async fn recv(&self, buf: &mut [u8]) -> Result<usize> {
+        let mut reader = self.reader.lock().await;
+        let mut len_buf = [0u8; 4];
+        reader.read_exact(&mut len_buf).await?;
+        let len = u32::from_be_bytes(len_buf) as usize;
+
+        if len == 0 {
+            return Err(anyhow::anyhow!("Received zero-length message"));
+        }
+
+        if len > buf.len() {
+            return Err(anyhow::anyhow!(
+                "Buffer too small: need {}, have {}",
+                len,
+                buf.len()
+            ));
+        }
+
+        reader.read_exact(&mut buf[..len]).await?;
+        Ok(len)
+    }
+
+    /// Get local address
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Get peer address
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
 }
 
 /// TCP server for accepting connections
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use jsp_core::crypto::open_ticket_state;
+use jsp_core::replay_protection::{ReplayError, ReplayProtection};
+use jsp_core::session::Session;
+use jsp_core::types::control::SessionTicket;
+use rand::RngCore;
+
+/// Resumption ClientHellos remembered for replay detection
+const REPLAY_WINDOW_SIZE: usize = 10000;
+/// Accepted difference between a ClientHello timestamp and the server clock
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(300);
+
+/// Server-side record of issued session tickets
+///
+/// Cloning shares the underlying store, so one store can back every
+/// connection accepted by a server. Tickets are single-use: redeeming a
+/// ticket removes it, which stops replayed ClientHellos from resuming.
+/// The nonce and timestamp of every resumption ClientHello are also checked
+/// across all connections, so early data is never accepted twice.
+///
+/// Ticket state is sealed with a random key that never leaves the store, so
+/// it is shared by its clones and lost with the last of them.
+#[derive(Clone)]
+pub struct TicketStore {
+    tickets: Arc<Mutex<HashMap<[u8; 32], SessionTicket>>>,
+    replay: Arc<Mutex<ReplayProtection>>,
+    key: [u8; 32],
+}
+
+impl Default for TicketStore {
+    fn default() -> Self {
+        let mut key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        Self {
+            tickets: Arc::new(Mutex::new(HashMap::new())),
+            replay: Arc::new(Mutex::new(ReplayProtection::new(REPLAY_WINDOW_SIZE, MAX_CLOCK_SKEW))),
+            key,
+        }
+    }
+}
+
+// Leaves the key out
+impl fmt::Debug for TicketStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TicketStore").field("tickets", &self.len()).finish_non_exhaustive()
+    }
+}
+
+impl TicketStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the nonce of a resumption ClientHello
+    ///
+    /// Fails if the nonce was seen before or the timestamp is too far off,
+    /// in which case the ticket must not be redeemed and 0-RTT data is dropped.
+    pub fn check_replay(&self, nonce: u64, timestamp: u64) -> Result<(), ReplayError> {
+        self.replay.lock().unwrap().check_and_register(nonce, timestamp)
+    }
+
+    /// Issue a ticket for an established session and remember it
+    pub fn issue(&self, session: &Session) -> anyhow::Result<SessionTicket> {
+        let ticket = session.generate_session_ticket(&self.key)?;
+        self.insert(ticket.clone());
+        Ok(ticket)
+    }
+
+    /// Remember a ticket issued to a client
+    pub fn insert(&self, ticket: SessionTicket) {
+        let mut tickets = self.tickets.lock().unwrap();
+        // Drop expired tickets so the store doesn't grow unbounded
+        tickets.retain(|_, t| !t.is_expired());
+        tickets.insert(ticket.ticket_id, ticket);
+    }
+
+    /// Validate a ticket presented by a client
+    ///
+    /// Returns the resumption secret the ticket carries, or `None` if the
+    /// ticket is unknown, expired or already used, or its state doesn't open
+    /// with our key.
+    pub fn redeem(&self, presented: &SessionTicket) -> Option<Vec<u8>> {
+        let issued = self.tickets.lock().unwrap().remove(&presented.ticket_id)?;
+        if issued.is_expired() {
+            return None;
+        }
+        open_ticket_state(&self.key, &presented.ticket_id, &presented.encrypted_state).ok()
+    }
+
+    /// Number of outstanding tickets
+    pub fn len(&self) -> usize {
+        self.tickets.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsp_core::crypto::seal_ticket_state;
+
+    fn ticket(store: &TicketStore, id: u8, created_at: u64) -> SessionTicket {
+        SessionTicket {
+            ticket_id: [id; 32],
+            encrypted_state: seal_ticket_state(&store.key, &[id; 32], &[id; 32]).unwrap(),
+            created_at,
+            lifetime: 3600,
+            resumption_secret: None,
+        }
+    }
+
+    fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn test_redeem_is_single_use() {
+        let store = TicketStore::new();
+        store.insert(ticket(&store, 1, now()));
+
+        assert_eq!(store.redeem(&ticket(&store, 1, now())), Some(vec![1; 32]));
+        assert_eq!(store.redeem(&ticket(&store, 1, now())), None);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_reject_unknown_and_expired() {
+        let store = TicketStore::new();
+        store.insert(ticket(&store, 2, now() - 7200));
+
+        assert_eq!(store.redeem(&ticket(&store, 3, now())), None);
+        assert_eq!(store.redeem(&ticket(&store, 2, now())), None);
+    }
+
+    #[test]
+    fn test_reject_state_sealed_elsewhere() {
+        let store = TicketStore::new();
+        let other = TicketStore::new();
+        store.insert(ticket(&store, 4, now()));
+        store.insert(ticket(&store, 5, now()));
+
+        // Another server's key, and state moved over from another ticket
+        assert_eq!(store.redeem(&ticket(&other, 4, now())), None);
+        let moved = SessionTicket { ticket_id: [5; 32], ..ticket(&store, 6, now()) };
+        assert_eq!(store.redeem(&moved), None);
+    }
+
+    #[test]
+    fn test_replayed_hello_rejected_across_clones() {
+        let store = TicketStore::new();
+        let other = store.clone();
+
+        assert!(store.check_replay(42, now()).is_ok());
+        assert!(other.check_replay(42, now()).is_err());
+        assert!(other.check_replay(43, now() - 3600).is_err());
+    }
+}
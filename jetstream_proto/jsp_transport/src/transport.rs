@@ -1,8 +1,11 @@
 use tokio::net::UdpSocket;
 use std::net::SocketAddr;
 use anyhow::Result;
-use crate::tcp_transport::TcpTransport;
+use crate::tcp_transport::{TcpTransport, SharedTcpTransport};
 use crate::quic_transport::QuicTransport;
+use crate::udp::{UdpTransport, EcnCodepoint, RecvMeta};
+use crate::transport_selector::TransportType;
+use crate::webrtc::WebRTCTransport;
 
 /// Transport abstraction supporting UDP, TCP, and QUIC
 pub enum Transport {
@@ -107,6 +110,134 @@ impl Transport {
     }
 }
 
+/// Cloneable transport used by `Connection` so background tasks can share it
+#[derive(Clone)]
+pub enum ConnectionTransport {
+    /// UDP transport (default)
+    Udp(UdpTransport),
+    /// TCP transport with split read/write halves
+    Tcp(SharedTcpTransport),
+    /// QUIC transport
+    Quic(QuicTransport),
+    /// WebRTC data channel (opened before the handshake)
+    WebRtc(WebRTCTransport),
+}
+
+impl ConnectionTransport {
+    /// Send data to address (address is ignored for connection-oriented transports)
+    pub async fn send_to(&self, data: &[u8], addr: SocketAddr) -> Result<usize> {
+        match self {
+            ConnectionTransport::Udp(udp) => udp.send_to(data, addr).await,
+            ConnectionTransport::Tcp(tcp) => tcp.send(data).await,
+            ConnectionTransport::Quic(quic) => quic.send(data).await,
+            ConnectionTransport::WebRtc(webrtc) => webrtc.send(data).await
+                .map_err(|e| anyhow::anyhow!("WebRTC send failed: {}", e)),
+        }
+    }
+
+    /// Send packets in order as separate datagrams (messages on the stream transports)
+    ///
+    /// UDP batches them into as few syscalls as the platform allows.
+    pub async fn send_batch<B: AsRef<[u8]>>(&self, packets: &[(B, SocketAddr)]) -> Result<()> {
+        match self {
+            ConnectionTransport::Udp(udp) => udp.send_batch(packets).await,
+            _ => {
+                for (data, addr) in packets {
+                    self.send_to(data.as_ref(), *addr).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Receive data from transport
+    pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        match self {
+            ConnectionTransport::Udp(udp) => udp.recv_from(buf).await,
+            ConnectionTransport::Tcp(tcp) => {
+                let len = tcp.recv(buf).await?;
+                Ok((len, tcp.peer_addr()))
+            }
+            ConnectionTransport::Quic(quic) => {
+                let len = quic.recv(buf).await?;
+                Ok((len, quic.peer_addr()))
+            }
+            ConnectionTransport::WebRtc(webrtc) => {
+                let len = webrtc.recv(buf).await
+                    .map_err(|e| anyhow::anyhow!("WebRTC receive failed: {}", e))?;
+                let peer = webrtc.remote_addr()
+                    .ok_or_else(|| anyhow::anyhow!("WebRTC data channel is not connected"))?;
+                Ok((len, peer))
+            }
+        }
+    }
+
+    /// Receive data along with its ECN codepoint
+    ///
+    /// Only UDP sees the IP header; the stream transports always report Not-ECT.
+    pub async fn recv_from_ecn(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr, EcnCodepoint)> {
+        match self {
+            ConnectionTransport::Udp(udp) => udp.recv_from_ecn(buf).await,
+            _ => {
+                let (len, addr) = self.recv_from(buf).await?;
+                Ok((len, addr, EcnCodepoint::NotEct))
+            }
+        }
+    }
+
+    /// Receive up to `bufs.len()` datagrams, see `UdpTransport::recv_batch`
+    ///
+    /// The stream transports return one message per call.
+    pub async fn recv_batch<B: AsMut<[u8]>>(&self, bufs: &mut [B], meta: &mut [RecvMeta]) -> Result<usize> {
+        match self {
+            ConnectionTransport::Udp(udp) => udp.recv_batch(bufs, meta).await,
+            _ => match (bufs.first_mut(), meta.first_mut()) {
+                (Some(buf), Some(slot)) => {
+                    let (len, addr, ecn) = self.recv_from_ecn(buf.as_mut()).await?;
+                    *slot = RecvMeta::single(len, addr, ecn);
+                    Ok(1)
+                }
+                _ => Ok(0),
+            },
+        }
+    }
+
+    /// Get local address
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        match self {
+            ConnectionTransport::Udp(udp) => udp.local_addr(),
+            ConnectionTransport::Tcp(tcp) => Ok(tcp.local_addr()),
+            ConnectionTransport::Quic(quic) => Ok(quic.local_addr()),
+            ConnectionTransport::WebRtc(webrtc) => webrtc.local_addr()
+                .ok_or_else(|| anyhow::anyhow!("WebRTC transport is not initialized")),
+        }
+    }
+
+    /// Get the underlying UDP transport, if any (needed for STUN/TURN)
+    pub fn as_udp(&self) -> Option<&UdpTransport> {
+        match self {
+            ConnectionTransport::Udp(udp) => Some(udp),
+            _ => None,
+        }
+    }
+
+    /// Get transport type
+    pub fn transport_type(&self) -> TransportType {
+        match self {
+            ConnectionTransport::Udp(_) => TransportType::Udp,
+            ConnectionTransport::Tcp(_) => TransportType::Tcp,
+            ConnectionTransport::Quic(_) => TransportType::Quic,
+            ConnectionTransport::WebRtc(_) => TransportType::WebRtc,
+        }
+    }
+}
+
+impl From<UdpTransport> for ConnectionTransport {
+    fn from(udp: UdpTransport) -> Self {
+        ConnectionTransport::Udp(udp)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
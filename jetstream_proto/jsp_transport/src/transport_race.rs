@@ -0,0 +1,220 @@
+//! Happy-eyeballs style transport racing
+//!
+//! Attempts the JetStream handshake over UDP, QUIC and TCP and keeps the
+//! first transport whose handshake completes. The peer is expected to listen
+//! on the same port number for every transport.
+
+use std::net::SocketAddr;
+use anyhow::Result;
+use jsp_core::session::Session;
+use jsp_core::types::control::CloseFrame;
+use jsp_core::types::handshake::{ClientHello, RetryFrame, ServerHello};
+use jsp_core::types::header::{Header, FRAME_TYPE_CLOSE, FRAME_TYPE_RETRY};
+use serde::de::DeserializeOwned;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+use crate::config::{ConnectionConfig, ConnectStrategy};
+use crate::error::TransportError;
+use crate::quic_transport::QuicTransport;
+use crate::tcp_transport::TcpTransport;
+use crate::transport::ConnectionTransport;
+use crate::transport_selector::TransportType;
+use crate::udp::UdpTransport;
+
+/// Order in which transports are attempted (and started, when racing)
+pub const TRANSPORT_ORDER: [TransportType; 3] = [TransportType::Udp, TransportType::Quic, TransportType::Tcp];
+
+/// Transport and session produced by a successful handshake attempt
+pub struct RaceOutcome {
+    pub transport: ConnectionTransport,
+    pub session: Session,
+}
+
+/// Connect to `addr` using the configured strategy
+pub async fn connect(addr: SocketAddr, config: &ConnectionConfig) -> Result<RaceOutcome> {
+    match config.connect_strategy {
+        ConnectStrategy::UdpOnly => attempt(TransportType::Udp, addr, config).await,
+        ConnectStrategy::Race => race(addr, config).await,
+        ConnectStrategy::Ordered => ordered(addr, config).await,
+        ConnectStrategy::WebRtc => Err(anyhow::anyhow!("WebRTC connects through signaling, not to {}", addr)),
+    }
+}
+
+/// Start all transports in parallel, keep the first handshake and cancel the rest
+async fn race(addr: SocketAddr, config: &ConnectionConfig) -> Result<RaceOutcome> {
+    let mut attempts = JoinSet::new();
+    for kind in TRANSPORT_ORDER {
+        let config = config.clone();
+        attempts.spawn(async move {
+            attempt(kind, addr, &config).await.map_err(|e| (kind, e))
+        });
+    }
+
+    let winner = tokio::time::timeout(config.connect_timeout, async {
+        while let Some(joined) = attempts.join_next().await {
+            match joined {
+                Ok(Ok(outcome)) => return Some(outcome),
+                Ok(Err((kind, e))) => {
+                    tracing::debug!(?kind, peer = %addr, "Transport attempt failed: {}", e);
+                }
+                Err(e) => {
+                    tracing::debug!(peer = %addr, "Transport attempt aborted: {}", e);
+                }
+            }
+        }
+        None
+    }).await;
+
+    // Cancel the losers
+    attempts.abort_all();
+
+    match winner {
+        Ok(Some(outcome)) => {
+            tracing::info!(
+                peer = %addr,
+                transport = ?outcome.transport.transport_type(),
+                "Transport race won"
+            );
+            Ok(outcome)
+        }
+        Ok(None) => Err(anyhow::anyhow!("All transports failed to connect to {}", addr)),
+        Err(_) => {
+            tracing::debug!(peer = %addr, "Transport race timed out after {:?}", config.connect_timeout);
+            Err(TransportError::HandshakeTimeout.into())
+        }
+    }
+}
+
+/// Try each transport in turn, each bounded by `connect_timeout`
+async fn ordered(addr: SocketAddr, config: &ConnectionConfig) -> Result<RaceOutcome> {
+    for kind in TRANSPORT_ORDER {
+        match tokio::time::timeout(config.connect_timeout, attempt(kind, addr, config)).await {
+            Ok(Ok(outcome)) => return Ok(outcome),
+            Ok(Err(e)) => tracing::debug!(?kind, peer = %addr, "Transport attempt failed: {}", e),
+            Err(_) => tracing::debug!(?kind, peer = %addr, "Transport attempt timed out"),
+        }
+    }
+    Err(anyhow::anyhow!("All transports failed to connect to {}", addr))
+}
+
+/// Open a transport of the given kind and run the client handshake over it
+async fn attempt(kind: TransportType, addr: SocketAddr, config: &ConnectionConfig) -> Result<RaceOutcome> {
+    let transport = match kind {
+        TransportType::Udp => {
+            let bind_addr = config.bind_addr.as_deref().unwrap_or("0.0.0.0:0");
+            ConnectionTransport::Udp(UdpTransport::bind(bind_addr).await?)
+        }
+        TransportType::Tcp => ConnectionTransport::Tcp(TcpTransport::connect(addr).await?.into_shared()),
+        TransportType::Quic => ConnectionTransport::Quic(QuicTransport::connect(addr).await?),
+        TransportType::WebRtc => return Err(anyhow::anyhow!("WebRTC is not raced")),
+    };
+
+    let session = client_handshake(&transport, addr, config).await?;
+    Ok(RaceOutcome { transport, session })
+}
+
+/// Perform the ClientHello / ServerHello exchange
+async fn client_handshake(transport: &ConnectionTransport, addr: SocketAddr, config: &ConnectionConfig) -> Result<Session> {
+    let mut session = Session::new();
+    if config.enable_double_ratchet {
+        session.enable_double_ratchet();
+    }
+    session.set_serialization_formats(&config.serialization_formats);
+    let hello = session.generate_client_hello()?;
+    transport.send_to(&hello, addr).await?;
+
+    let server_hello = await_server_hello(transport, addr, &hello, config).await?;
+    session.process_server_hello(&server_hello)
+        .map_err(TransportError::crypto)?;
+    Ok(session)
+}
+
+/// Wait for the ServerHello answering `hello`, which was already sent once
+///
+/// Over UDP the ClientHello is resent every `handshake_retry_interval`, up to
+/// `handshake_max_retries` times. Stream transports retransmit on their own,
+/// so over them the hello is sent once and the ServerHello awaited for as
+/// long as those attempts would take. Datagrams from other sources, and packets
+/// from the peer that aren't a ServerHello (such as a session ticket that
+/// overtook it), are skipped.
+///
+/// A server under handshake pressure answers with a Retry instead; the hello
+/// is then repeated once with the Retry's token, and that tokened hello is
+/// what later resends carry. A Close from the peer (such as a per-IP session
+/// limit being hit) fails the handshake at once. Running out of retries
+/// fails with `TransportError::HandshakeTimeout`.
+pub(crate) async fn await_server_hello(
+    transport: &ConnectionTransport,
+    addr: SocketAddr,
+    hello: &[u8],
+    config: &ConnectionConfig,
+) -> Result<Vec<u8>> {
+    // Stream transports retransmit on their own; a single wait also avoids
+    // cancelling a stream read halfway through a frame
+    let (resends, wait) = if transport.as_udp().is_some() {
+        (config.handshake_max_retries, config.handshake_retry_interval)
+    } else {
+        (0, config.handshake_retry_interval.saturating_mul(config.handshake_max_retries.saturating_add(1)))
+    };
+    let mut buf = [0u8; 2048];
+    let mut hello = hello.to_vec();
+    let mut retried = false;
+
+    for attempt in 0..=resends {
+        if attempt > 0 {
+            tracing::debug!(peer = %addr, attempt, "No ServerHello yet, resending ClientHello");
+            transport.send_to(&hello, addr).await?;
+        }
+
+        let mut deadline = Instant::now() + wait;
+        while let Ok(received) = tokio::time::timeout_at(deadline, transport.recv_from(&mut buf)).await {
+            let (len, src) = received?;
+            if src != addr {
+                tracing::trace!(peer = %addr, %src, "Ignoring datagram from another source during handshake");
+                continue;
+            }
+            if let Some(close) = parse_control::<CloseFrame>(&buf[..len], FRAME_TYPE_CLOSE) {
+                anyhow::bail!(
+                    "Handshake with {} refused: {:?} ({})",
+                    addr,
+                    close.reason_code,
+                    close.message.as_deref().unwrap_or("no reason given")
+                );
+            }
+            if let Some(retry) = parse_control::<RetryFrame>(&buf[..len], FRAME_TYPE_RETRY).filter(|_| !retried) {
+                let mut tokened: ClientHello = serde_cbor::from_slice(&hello)?;
+                tokened.retry_token = Some(retry.token);
+                hello = serde_cbor::to_vec(&tokened)?;
+                retried = true;
+                tracing::debug!(peer = %addr, "Server asked for a retry, resending ClientHello with its token");
+                transport.send_to(&hello, addr).await?;
+                deadline = Instant::now() + wait;
+                continue;
+            }
+            if serde_cbor::from_slice::<ServerHello>(&buf[..len]).is_err() {
+                tracing::trace!(peer = %addr, len, "Ignoring non-handshake packet while waiting for ServerHello");
+                continue;
+            }
+            return Ok(buf[..len].to_vec());
+        }
+    }
+
+    tracing::debug!(
+        peer = %addr,
+        "No ServerHello after {} ClientHello(s) sent {:?} apart",
+        config.handshake_max_retries + 1,
+        config.handshake_retry_interval
+    );
+    Err(TransportError::HandshakeTimeout.into())
+}
+
+/// Payload of a framed `msg_type` control packet, if `packet` is one
+fn parse_control<T: DeserializeOwned>(packet: &[u8], msg_type: u8) -> Option<T> {
+    let header_len = u16::from_be_bytes([*packet.first()?, *packet.get(1)?]) as usize;
+    let header: Header = serde_cbor::from_slice(packet.get(2..2 + header_len)?).ok()?;
+    if header.msg_type != msg_type {
+        return None;
+    }
+    serde_cbor::from_slice(&packet[2 + header_len..]).ok()
+}
@@ -10,6 +10,8 @@ pub enum TransportType {
     Tcp,
     /// QUIC transport (best of both worlds)
     Quic,
+    /// WebRTC data channel established through a signaling server
+    WebRtc,
 }
 
 /// Network conditions
@@ -88,6 +90,7 @@ impl TransportSelector {
             TransportType::Udp => vec![TransportType::Quic, TransportType::Tcp],
             TransportType::Quic => vec![TransportType::Udp, TransportType::Tcp],
             TransportType::Tcp => vec![TransportType::Quic, TransportType::Udp],
+            TransportType::WebRtc => vec![TransportType::Udp, TransportType::Quic, TransportType::Tcp],
         };
 
         Self {
@@ -165,6 +168,7 @@ impl TransportSelector {
             TransportType::Quic => true, // QUIC available if supported
             TransportType::Tcp => true,  // TCP always available
             TransportType::Udp => true,  // UDP always available
+            TransportType::WebRtc => false, // Needs a signaling server and a peer ID
         }
     }
 
@@ -1,17 +1,108 @@
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use anyhow::Result;
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
 use jsp_core::types::turn::TurnMessage;
 use jsp_core::types::header::{Header, FRAME_TYPE_TURN};
 use bytes::BytesMut;
 use crate::udp::UdpTransport;
+use tokio::sync::mpsc;
 use tokio::time::{timeout, Duration};
 
+/// Refresh allocations once this fraction of their lifetime has passed
+const REFRESH_AT: f64 = 0.5;
+/// Refresh requests sent before an allocation is given up
+const REFRESH_ATTEMPTS: u32 = 3;
+
+/// Why a refreshed allocation was given up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllocationLost {
+    /// The server no longer knows the allocation
+    Rejected { code: u16, reason: String },
+    /// No answer to any refresh before the allocation expired
+    Timeout,
+}
+
+/// Handle to the task started by `TurnClient::start_refresh`; dropping it stops the refreshes
+pub struct TurnRefreshHandle {
+    task: tokio::task::JoinHandle<()>,
+    refreshes: Arc<AtomicU64>,
+}
+
+impl TurnRefreshHandle {
+    /// Number of refreshes the server confirmed so far
+    pub fn refresh_count(&self) -> u64 {
+        self.refreshes.load(Ordering::Relaxed)
+    }
+
+    /// Whether the task stopped, because the allocation was lost
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    pub fn stop(&self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for TurnRefreshHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// How a transport relaying through an allocation reaches its peers
+///
+/// Set with `UdpTransport::set_relay`: datagrams to any address but the
+/// server's go out as `Send`, and `Data` from the server comes back as a
+/// datagram from the peer that sent it.
+#[derive(Clone)]
+pub struct TurnRoute {
+    pub server: SocketAddr,
+    pub allocation_id: u64,
+    pub relay_addr: SocketAddr,
+    /// Other TURN messages from the server, for the refresh task
+    control: mpsc::UnboundedSender<TurnMessage>,
+}
+
+impl TurnRoute {
+    /// `data` for `peer_addr`, framed as a `Send` to the server
+    pub(crate) fn wrap(&self, data: &[u8], peer_addr: SocketAddr) -> Result<BytesMut> {
+        turn_packet(&TurnMessage::Send {
+            allocation_id: self.allocation_id,
+            peer_addr,
+            data: data.to_vec(),
+        })
+    }
+
+    /// Unwrap the datagram in `buf[..len]` the server sent
+    ///
+    /// Relayed data is moved to the front of `buf` and returned with the
+    /// peer it came from; other TURN messages go to the refresh task and
+    /// give None. Anything else is returned as it is.
+    pub(crate) fn unwrap(&self, buf: &mut [u8], len: usize) -> Option<(usize, SocketAddr)> {
+        match parse_turn_packet(&buf[..len]) {
+            Some(TurnMessage::Data { peer_addr, data }) => {
+                buf[..data.len()].copy_from_slice(&data);
+                Some((data.len(), peer_addr))
+            }
+            Some(msg) => {
+                let _ = self.control.send(msg);
+                None
+            }
+            None => Some((len, self.server)),
+        }
+    }
+}
+
 pub struct TurnClient {
     turn_server_addr: SocketAddr,
     allocation_id: Option<u64>,
     relay_addr: Option<SocketAddr>,
     lifetime: u32,
+    /// Peers with a permission, re-created on every refresh
+    permissions: Arc<Mutex<Vec<SocketAddr>>>,
 }
 
 impl TurnClient {
@@ -21,8 +112,15 @@ impl TurnClient {
             allocation_id: None,
             relay_addr: None,
             lifetime: 600, // 10 minutes default
+            permissions: Arc::new(Mutex::new(Vec::new())),
         }
     }
+
+    /// Request allocations lasting `seconds` (the server may grant another lifetime)
+    pub fn with_lifetime(mut self, seconds: u32) -> Self {
+        self.lifetime = seconds;
+        self
+    }
     
     /// Allocate a relay address from TURN server
     pub async fn allocate(&mut self, transport: &UdpTransport) -> Result<SocketAddr> {
@@ -47,6 +145,7 @@ impl TurnClient {
                             self.allocation_id = Some(allocation_id);
                             self.relay_addr = Some(relay_addr);
                             self.lifetime = lifetime;
+                            self.permissions = Arc::new(Mutex::new(Vec::new()));
                             return Ok(relay_addr);
                         }
                         TurnMessage::AllocateError { code, reason } => {
@@ -67,7 +166,7 @@ impl TurnClient {
     }
     
     /// Create permission for peer to send data
-    pub async fn create_permission(&self, transport: &UdpTransport, peer_addr: SocketAddr) -> Result<()> {
+    pub async fn create_permission(&mut self, transport: &UdpTransport, peer_addr: SocketAddr) -> Result<()> {
         let allocation_id = self.allocation_id.ok_or_else(|| anyhow::anyhow!("No allocation"))?;
         
         info!("Creating TURN permission for {}", peer_addr);
@@ -79,16 +178,41 @@ impl TurnClient {
         
         self.send_turn_message(transport, request).await?;
         
-        // Wait for response
-        match timeout(Duration::from_secs(2), self.recv_turn_message(transport)).await {
-            Ok(Ok(TurnMessage::PermissionSuccess)) => {
-                info!("Permission created for {}", peer_addr);
-                Ok(())
-            }
-            Ok(Ok(TurnMessage::Error { code, reason })) => {
-                Err(anyhow::anyhow!("Permission failed: {} - {}", code, reason))
+        // Wait for response, skipping whatever else arrives meanwhile
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
+        while let Ok(received) = tokio::time::timeout_at(deadline, self.recv_turn_message(transport)).await {
+            match received {
+                Ok(TurnMessage::PermissionSuccess) => {
+                    info!("Permission created for {}", peer_addr);
+                    self.record_permission(peer_addr);
+                    return Ok(());
+                }
+                Ok(TurnMessage::Error { code, reason }) => {
+                    return Err(anyhow::anyhow!("Permission failed: {} - {}", code, reason));
+                }
+                Ok(_) | Err(_) => {}
             }
-            _ => Err(anyhow::anyhow!("Permission timeout")),
+        }
+        Err(anyhow::anyhow!("Permission timeout"))
+    }
+
+    /// Ask for a permission for `peer_addr` without waiting for the answer
+    ///
+    /// For a transport relaying through the allocation (see `start_relay`),
+    /// whose answers go to the refresh task; it re-creates the permission
+    /// on its next refresh if this one was lost.
+    pub async fn add_permission(&self, transport: &UdpTransport, peer_addr: SocketAddr) -> Result<()> {
+        let allocation_id = self.allocation_id.ok_or_else(|| anyhow::anyhow!("No allocation"))?;
+
+        info!("Creating TURN permission for {}", peer_addr);
+        self.record_permission(peer_addr);
+        self.send_turn_message(transport, TurnMessage::CreatePermission { allocation_id, peer_addr }).await
+    }
+
+    fn record_permission(&self, peer_addr: SocketAddr) {
+        let mut permissions = self.permissions.lock().unwrap();
+        if !permissions.contains(&peer_addr) {
+            permissions.push(peer_addr);
         }
     }
     
@@ -119,59 +243,379 @@ impl TurnClient {
         Ok(())
     }
     
+    /// Keep the allocation alive from a background task
+    ///
+    /// The task sends a Refresh at half the lifetime, retrying until the
+    /// allocation would expire, and then re-creates the permissions. When
+    /// the server rejects a refresh or never answers, `on_lost` is called
+    /// and the task stops. It reads the answers from `transport`, so nothing
+    /// else should read from it meanwhile.
+    pub fn start_refresh(
+        &self,
+        transport: UdpTransport,
+        on_lost: impl FnOnce(AllocationLost) + Send + 'static,
+    ) -> Result<TurnRefreshHandle> {
+        self.spawn_refresher(transport, None, on_lost)
+    }
+
+    /// Relay all of `transport`'s peer traffic through the allocation, and
+    /// keep the allocation alive as `start_refresh` does
+    ///
+    /// The transport keeps reading normally: relayed data arrives as
+    /// datagrams from the peers themselves, and the server's answers are
+    /// handed to the refresh task. If the allocation is lost the transport
+    /// goes back to sending directly.
+    pub fn start_relay(
+        &self,
+        transport: &UdpTransport,
+        on_lost: impl FnOnce(AllocationLost) + Send + 'static,
+    ) -> Result<TurnRefreshHandle> {
+        let allocation_id = self.allocation_id.ok_or_else(|| anyhow::anyhow!("No allocation"))?;
+        let relay_addr = self.relay_addr.ok_or_else(|| anyhow::anyhow!("No allocation"))?;
+        let (control, responses) = mpsc::unbounded_channel();
+        transport.set_relay(Some(TurnRoute {
+            server: self.turn_server_addr,
+            allocation_id,
+            relay_addr,
+            control,
+        }));
+
+        let relayed = transport.clone();
+        self.spawn_refresher(transport.clone(), Some(responses), move |lost| {
+            if relayed.relay().is_some_and(|route| route.allocation_id == allocation_id) {
+                relayed.set_relay(None);
+            }
+            on_lost(lost);
+        })
+    }
+
+    /// Delete the allocation, and stop relaying `transport` through it
+    ///
+    /// Stop its refresh task first. The server isn't waited for: an
+    /// allocation whose deletion is lost expires on its own.
+    pub async fn deallocate(&mut self, transport: &UdpTransport) -> Result<()> {
+        let allocation_id = match self.allocation_id.take() {
+            Some(allocation_id) => allocation_id,
+            None => return Ok(()),
+        };
+        if transport.relay().is_some_and(|route| route.allocation_id == allocation_id) {
+            transport.set_relay(None);
+        }
+        self.relay_addr = None;
+        self.permissions.lock().unwrap().clear();
+
+        info!("Deleting TURN allocation {}", allocation_id);
+        self.send_turn_message(transport, TurnMessage::Refresh { allocation_id, lifetime: 0 }).await
+    }
+
+    fn spawn_refresher(
+        &self,
+        transport: UdpTransport,
+        responses: Option<mpsc::UnboundedReceiver<TurnMessage>>,
+        on_lost: impl FnOnce(AllocationLost) + Send + 'static,
+    ) -> Result<TurnRefreshHandle> {
+        let allocation_id = self.allocation_id.ok_or_else(|| anyhow::anyhow!("No allocation"))?;
+        let refresher = Refresher {
+            transport,
+            responses,
+            turn_server_addr: self.turn_server_addr,
+            allocation_id,
+            lifetime: self.lifetime,
+            permissions: Arc::clone(&self.permissions),
+        };
+        let refreshes = Arc::new(AtomicU64::new(0));
+        let counter = Arc::clone(&refreshes);
+
+        let task = tokio::spawn(async move {
+            let lost = refresher.run(&counter).await;
+            warn!("TURN allocation {} lost: {:?}", allocation_id, lost);
+            on_lost(lost);
+        });
+
+        Ok(TurnRefreshHandle { task, refreshes })
+    }
+
     pub fn get_relay_addr(&self) -> Option<SocketAddr> {
         self.relay_addr
     }
     
+    /// Granted allocation lifetime in seconds
+    pub fn lifetime(&self) -> u32 {
+        self.lifetime
+    }
+
     async fn send_turn_message(&self, transport: &UdpTransport, msg: TurnMessage) -> Result<()> {
-        let payload = msg.to_bytes();
-        
-        let header = Header::new(
-            0,
-            FRAME_TYPE_TURN,
-            0,
-            0,
-            0,
-            0,
-            Default::default(),
-            None,
-            Some(payload.len() as u32),
-        );
-        
-        let header_bytes = serde_cbor::to_vec(&header)?;
-        let header_len = header_bytes.len() as u16;
-        
-        let mut packet = BytesMut::with_capacity(2 + header_bytes.len() + payload.len());
-        packet.extend_from_slice(&header_len.to_be_bytes());
-        packet.extend_from_slice(&header_bytes);
-        packet.extend_from_slice(&payload);
-        
-        transport.send_to(&packet, self.turn_server_addr).await?;
-        Ok(())
+        send_turn_message(transport, self.turn_server_addr, msg).await
     }
     
     async fn recv_turn_message(&self, transport: &UdpTransport) -> Result<TurnMessage> {
-        let mut buf = vec![0u8; 65536];
-        let (len, _src) = transport.recv_from(&mut buf).await?;
-        
-        if len < 2 {
-            return Err(anyhow::anyhow!("Packet too small"));
+        recv_turn_message(transport).await
+    }
+}
+
+/// State of the refresh task
+struct Refresher {
+    transport: UdpTransport,
+    /// Answers routed from a relaying transport, instead of reading it
+    responses: Option<mpsc::UnboundedReceiver<TurnMessage>>,
+    turn_server_addr: SocketAddr,
+    allocation_id: u64,
+    lifetime: u32,
+    permissions: Arc<Mutex<Vec<SocketAddr>>>,
+}
+
+impl Refresher {
+    /// Refresh until the allocation is lost
+    async fn run(mut self, refreshes: &AtomicU64) -> AllocationLost {
+        loop {
+            let lifetime = Duration::from_secs(self.lifetime as u64);
+            let refresh_at = lifetime.mul_f64(REFRESH_AT);
+            tokio::time::sleep(refresh_at).await;
+
+            // Spread the attempts over what is left of the lifetime
+            let attempt_timeout = ((lifetime - refresh_at) / REFRESH_ATTEMPTS).max(Duration::from_millis(50));
+            if let Err(lost) = self.refresh(attempt_timeout).await {
+                return lost;
+            }
+            refreshes.fetch_add(1, Ordering::Relaxed);
+
+            let permissions = self.permissions.lock().unwrap().clone();
+            for peer_addr in permissions {
+                if let Err(lost) = self.create_permission(peer_addr, attempt_timeout).await {
+                    return lost;
+                }
+            }
         }
-        
-        let header_len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
-        if len < 2 + header_len {
-            return Err(anyhow::anyhow!("Invalid header length"));
+    }
+
+    async fn refresh(&mut self, attempt_timeout: Duration) -> Result<(), AllocationLost> {
+        let request = TurnMessage::Refresh {
+            allocation_id: self.allocation_id,
+            lifetime: self.lifetime,
+        };
+        match self.request(request, attempt_timeout).await? {
+            TurnMessage::RefreshSuccess { lifetime } => {
+                debug!("Refreshed TURN allocation {} for {}s", self.allocation_id, lifetime);
+                // Never stop refreshing because of a zero lifetime
+                self.lifetime = lifetime.max(1);
+                Ok(())
+            }
+            _ => Err(AllocationLost::Timeout),
         }
-        
-        let header: Header = serde_cbor::from_slice(&buf[2..2 + header_len])?;
-        
-        if header.msg_type != FRAME_TYPE_TURN {
-            return Err(anyhow::anyhow!("Not a TURN message"));
+    }
+
+    async fn create_permission(&mut self, peer_addr: SocketAddr, attempt_timeout: Duration) -> Result<(), AllocationLost> {
+        let request = TurnMessage::CreatePermission {
+            allocation_id: self.allocation_id,
+            peer_addr,
+        };
+        match self.request(request, attempt_timeout).await {
+            Ok(_) => Ok(()),
+            Err(AllocationLost::Timeout) => {
+                // The allocation itself was just refreshed; retry on the next cycle
+                warn!("No answer re-creating TURN permission for {}", peer_addr);
+                Ok(())
+            }
+            Err(lost) => Err(lost),
+        }
+    }
+
+    /// Send `request` up to `REFRESH_ATTEMPTS` times until the server answers it
+    async fn request(&mut self, request: TurnMessage, attempt_timeout: Duration) -> Result<TurnMessage, AllocationLost> {
+        for _ in 0..REFRESH_ATTEMPTS {
+            if let Err(e) = send_turn_message(&self.transport, self.turn_server_addr, request.clone()).await {
+                warn!("Failed to send TURN request: {}", e);
+            }
+
+            let deadline = tokio::time::Instant::now() + attempt_timeout;
+            while let Ok(received) = tokio::time::timeout_at(deadline, self.recv()).await {
+                match received {
+                    Ok(TurnMessage::Error { code, reason }) => {
+                        return Err(AllocationLost::Rejected { code, reason });
+                    }
+                    Ok(msg @ TurnMessage::RefreshSuccess { .. }) if matches!(request, TurnMessage::Refresh { .. }) => {
+                        return Ok(msg);
+                    }
+                    Ok(msg @ TurnMessage::PermissionSuccess) if matches!(request, TurnMessage::CreatePermission { .. }) => {
+                        return Ok(msg);
+                    }
+                    // Stale answers to an earlier attempt, or not TURN at all
+                    Ok(_) | Err(_) => {}
+                }
+            }
+        }
+        Err(AllocationLost::Timeout)
+    }
+
+    async fn recv(&mut self) -> Result<TurnMessage> {
+        match &mut self.responses {
+            Some(responses) => match responses.recv().await {
+                Some(msg) => Ok(msg),
+                // The transport stopped relaying; nothing will answer
+                None => std::future::pending().await,
+            },
+            None => recv_turn_message(&self.transport).await,
+        }
+    }
+}
+
+async fn send_turn_message(transport: &UdpTransport, turn_server_addr: SocketAddr, msg: TurnMessage) -> Result<()> {
+    let packet = turn_packet(&msg)?;
+    transport.send_to(&packet, turn_server_addr).await?;
+    Ok(())
+}
+
+/// Frame a TURN message the way `TurnServer` expects it
+fn turn_packet(msg: &TurnMessage) -> Result<BytesMut> {
+    let payload = msg.to_bytes();
+
+    let header = Header::new(
+        0,
+        FRAME_TYPE_TURN,
+        0,
+        0,
+        0,
+        0,
+        Default::default(),
+        None,
+        Some(payload.len() as u32),
+    );
+
+    let header_bytes = serde_cbor::to_vec(&header)?;
+    let header_len = header_bytes.len() as u16;
+
+    let mut packet = BytesMut::with_capacity(2 + header_bytes.len() + payload.len());
+    packet.extend_from_slice(&header_len.to_be_bytes());
+    packet.extend_from_slice(&header_bytes);
+    packet.extend_from_slice(&payload);
+    Ok(packet)
+}
+
+fn parse_turn_packet(data: &[u8]) -> Option<TurnMessage> {
+    let header_len = u16::from_be_bytes([*data.first()?, *data.get(1)?]) as usize;
+    let header: Header = serde_cbor::from_slice(data.get(2..2 + header_len)?).ok()?;
+    if header.msg_type != FRAME_TYPE_TURN {
+        return None;
+    }
+    TurnMessage::from_bytes(&data[2 + header_len..]).ok()
+}
+
+async fn recv_turn_message(transport: &UdpTransport) -> Result<TurnMessage> {
+    let mut buf = vec![0u8; 65536];
+    let (len, _src) = transport.recv_from(&mut buf).await?;
+    parse_turn_packet(&buf[..len]).ok_or_else(|| anyhow::anyhow!("Not a TURN message"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::turn_server::TurnServer;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn test_refresh_keeps_short_allocation_alive() {
+        let server = Arc::new(TurnServer::new("127.0.0.1:0", (41000, 41010)).await.unwrap());
+        let server_addr = server.local_addr().unwrap();
+        let running = Arc::clone(&server);
+        let server_task = tokio::spawn(async move { running.run().await });
+
+        let transport = UdpTransport::bind("127.0.0.1:0").await.unwrap();
+        let peer = UdpTransport::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+
+        let mut client = TurnClient::new(server_addr).with_lifetime(1);
+        client.allocate(&transport).await.unwrap();
+        client.create_permission(&transport, peer_addr).await.unwrap();
+
+        let (lost_tx, lost_rx) = oneshot::channel();
+        let handle = client.start_refresh(transport.clone(), move |lost| {
+            let _ = lost_tx.send(lost);
+        }).unwrap();
+
+        // Three lifetimes later the allocation and permission still work
+        tokio::time::sleep(Duration::from_millis(3200)).await;
+        assert!(handle.refresh_count() >= 3, "only {} refreshes", handle.refresh_count());
+        client.send_data(&transport, peer_addr, b"still here".to_vec()).await.unwrap();
+        let mut buf = [0u8; 64];
+        match timeout(Duration::from_secs(1), peer.recv_from(&mut buf)).await {
+            Ok(Ok((len, from))) => {
+                assert_eq!(&buf[..len], b"still here");
+                assert_eq!(Some(from), client.get_relay_addr());
+            }
+            other => panic!("relay did not forward data: {:?}", other.map(|r| r.is_ok())),
+        }
+
+        // Without a server the next refresh goes unanswered
+        server_task.abort();
+        let lost = timeout(Duration::from_secs(2), lost_rx).await.unwrap().unwrap();
+        assert_eq!(lost, AllocationLost::Timeout);
+        assert!(handle.is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_relayed_transport_sends_and_receives_as_peer() {
+        let server = Arc::new(TurnServer::new("127.0.0.1:0", (41040, 41050)).await.unwrap());
+        let server_addr = server.local_addr().unwrap();
+        let running = Arc::clone(&server);
+        let _server_task = tokio::spawn(async move { running.run().await });
+
+        let transport = UdpTransport::bind("127.0.0.1:0").await.unwrap();
+        let peer = UdpTransport::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+
+        let mut client = TurnClient::new(server_addr).with_lifetime(1);
+        let relay_addr = client.allocate(&transport).await.unwrap();
+        client.create_permission(&transport, peer_addr).await.unwrap();
+        let handle = client.start_relay(&transport, |_| {}).unwrap();
+
+        // Plain sends and receives, with the relay in between
+        let mut buf = vec![0u8; 2048];
+        transport.send_to(b"out", peer_addr).await.unwrap();
+        let (len, from) = timeout(Duration::from_secs(1), peer.recv_from(&mut buf)).await.unwrap().unwrap();
+        assert_eq!((&buf[..len], from), (&b"out"[..], relay_addr));
+        peer.send_to(b"back", relay_addr).await.unwrap();
+        let (len, from) = timeout(Duration::from_secs(1), transport.recv_from(&mut buf)).await.unwrap().unwrap();
+        assert_eq!((&buf[..len], from), (&b"back"[..], peer_addr));
+
+        // Refreshes are answered through the relayed transport's reads
+        let reader = transport.clone();
+        let reading = tokio::spawn(async move {
+            let mut buf = vec![0u8; 2048];
+            loop {
+                let _ = reader.recv_from(&mut buf).await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(1600)).await;
+        assert!(handle.refresh_count() >= 2, "only {} refreshes", handle.refresh_count());
+        assert!(!handle.is_finished());
+        reading.abort();
+
+        handle.stop();
+        client.deallocate(&transport).await.unwrap();
+        assert!(transport.relay().is_none());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(server.allocation_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_expired_allocation_is_reported_lost() {
+        let server = Arc::new(TurnServer::new("127.0.0.1:0", (41020, 41030)).await.unwrap());
+        let server_addr = server.local_addr().unwrap();
+        let running = Arc::clone(&server);
+        let _server_task = tokio::spawn(async move { running.run().await });
+
+        let transport = UdpTransport::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TurnClient::new(server_addr).with_lifetime(1);
+        client.allocate(&transport).await.unwrap();
+
+        // Let it expire before refreshing by hand
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        client.refresh(&transport).await.unwrap();
+        match timeout(Duration::from_secs(1), recv_turn_message(&transport)).await {
+            Ok(Ok(TurnMessage::Error { code, .. })) => {
+                assert_eq!(code, jsp_core::types::turn::error_codes::ALLOCATION_MISMATCH);
+            }
+            other => panic!("expected an error, got {:?}", other.map(|r| r.is_ok())),
         }
-        
-        let payload = &buf[2 + header_len..len];
-        let msg = TurnMessage::from_bytes(payload)?;
-        
-        Ok(msg)
     }
 }
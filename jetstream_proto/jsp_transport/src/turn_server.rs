@@ -5,8 +5,8 @@ use std::time::{Duration, Instant};
 use tokio::net::UdpSocket;
 use tokio::sync::Mutex;
 use anyhow::Result;
-use tracing::{info, warn, error};
-use jsp_core::types::turn::TurnMessage;
+use tracing::{debug, info, warn, error};
+use jsp_core::types::turn::{error_codes, TurnMessage};
 use jsp_core::types::header::{Header, FRAME_TYPE_TURN};
 use bytes::BytesMut;
 
@@ -14,6 +14,10 @@ use bytes::BytesMut;
 struct Allocation {
     client_addr: SocketAddr,
     relay_addr: SocketAddr,
+    /// Bound to `relay_addr`; peers send to it and relayed data leaves from it
+    relay_socket: Arc<UdpSocket>,
+    /// Forwards what peers send to the relay socket; stopped with the allocation
+    relay_task: tokio::task::JoinHandle<()>,
     #[allow(dead_code)]
     allocation_id: u64,
     created_at: Instant,
@@ -21,6 +25,30 @@ struct Allocation {
     permissions: Vec<SocketAddr>, // Allowed peer addresses
 }
 
+impl Allocation {
+    /// Whether the lifetime passed without a refresh
+    fn is_expired(&self) -> bool {
+        self.created_at.elapsed() >= self.lifetime
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        // Frees the relay port
+        self.relay_task.abort();
+    }
+}
+
+/// Look up a live allocation, dropping it if it has expired
+fn live_allocation(allocs: &mut HashMap<u64, Allocation>, allocation_id: u64) -> Option<&mut Allocation> {
+    if allocs.get(&allocation_id).is_some_and(|allocation| allocation.is_expired()) {
+        if let Some(allocation) = allocs.remove(&allocation_id) {
+            info!("Allocation {} for {} expired", allocation_id, allocation.client_addr);
+        }
+    }
+    allocs.get_mut(&allocation_id)
+}
+
 /// TURN Relay Server
 pub struct TurnServer {
     socket: Arc<UdpSocket>,
@@ -44,6 +72,15 @@ impl TurnServer {
         })
     }
     
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Allocations neither deleted nor noticed to have expired
+    pub async fn allocation_count(&self) -> usize {
+        self.allocations.lock().await.len()
+    }
+    
     pub async fn run(&self) -> Result<()> {
         let mut buf = vec![0u8; 65536];
         
@@ -117,7 +154,7 @@ impl TurnServer {
             }
             
             TurnMessage::Send { allocation_id, peer_addr, data } => {
-                Self::handle_send(socket, allocations, src, allocation_id, peer_addr, data).await?;
+                Self::handle_send(allocations, src, allocation_id, peer_addr, data).await?;
             }
             
             TurnMessage::Refresh { allocation_id, lifetime } => {
@@ -145,18 +182,34 @@ impl TurnServer {
         client_addr: SocketAddr,
         requested_lifetime: u32,
     ) -> Result<()> {
-        // Allocate relay port
+        // Bind a relay port, skipping those still in use
+        let local_ip = socket.local_addr()?.ip();
+        let mut relay_socket = None;
         let mut port_guard = next_relay_port.lock().await;
-        let relay_port = *port_guard;
-        *port_guard += 1;
-        if *port_guard > relay_port_range.1 {
-            *port_guard = relay_port_range.0;
+        for _ in relay_port_range.0..=relay_port_range.1 {
+            let relay_port = *port_guard;
+            *port_guard = if relay_port >= relay_port_range.1 { relay_port_range.0 } else { relay_port + 1 };
+            match UdpSocket::bind(SocketAddr::new(local_ip, relay_port)).await {
+                Ok(bound) => {
+                    relay_socket = Some(Arc::new(bound));
+                    break;
+                }
+                Err(e) => debug!("Relay port {} unavailable: {}", relay_port, e),
+            }
         }
         drop(port_guard);
-        
-        // Create relay address
-        let local_ip = socket.local_addr()?.ip();
-        let relay_addr = SocketAddr::new(local_ip, relay_port);
+        let relay_socket = match relay_socket {
+            Some(relay_socket) => relay_socket,
+            None => {
+                warn!("No free relay port for {}", client_addr);
+                let response = TurnMessage::AllocateError {
+                    code: error_codes::INSUFFICIENT_CAPACITY,
+                    reason: "No free relay port".to_string(),
+                };
+                return Self::send_turn_message(&socket, client_addr, response).await;
+            }
+        };
+        let relay_addr = relay_socket.local_addr()?;
         
         // Generate allocation ID
         let mut id_guard = next_allocation_id.lock().await;
@@ -164,17 +217,27 @@ impl TurnServer {
         *id_guard += 1;
         drop(id_guard);
         
-        // Create allocation
+        // Create allocation; its relay task waits for the lock until it is in the map
+        let mut allocs = allocations.lock().await;
+        let relay_task = tokio::spawn(Self::relay_from_peers(
+            Arc::clone(&socket),
+            Arc::clone(&relay_socket),
+            Arc::clone(&allocations),
+            allocation_id,
+        ));
         let allocation = Allocation {
             client_addr,
             relay_addr,
+            relay_socket,
+            relay_task,
             allocation_id,
             created_at: Instant::now(),
             lifetime: Duration::from_secs(requested_lifetime as u64),
             permissions: Vec::new(),
         };
         
-        allocations.lock().await.insert(allocation_id, allocation);
+        allocs.insert(allocation_id, allocation);
+        drop(allocs);
         
         info!("Allocated relay {} for client {} (ID: {})", relay_addr, client_addr, allocation_id);
         
@@ -191,16 +254,15 @@ impl TurnServer {
     }
     
     async fn handle_send(
-        socket: Arc<UdpSocket>,
         allocations: Arc<Mutex<HashMap<u64, Allocation>>>,
         client_addr: SocketAddr,
         allocation_id: u64,
         peer_addr: SocketAddr,
         data: Vec<u8>,
     ) -> Result<()> {
-        let allocs = allocations.lock().await;
+        let mut allocs = allocations.lock().await;
         
-        if let Some(allocation) = allocs.get(&allocation_id) {
+        if let Some(allocation) = live_allocation(&mut allocs, allocation_id) {
             if allocation.client_addr != client_addr {
                 warn!("Allocation mismatch: {} != {}", allocation.client_addr, client_addr);
                 return Ok(());
@@ -212,15 +274,11 @@ impl TurnServer {
                 return Ok(());
             }
             
-            // Relay data to peer
-            info!("Relaying {} bytes from {} to {} via {}", data.len(), client_addr, peer_addr, allocation.relay_addr);
-            
-            let data_msg = TurnMessage::Data {
-                peer_addr: allocation.relay_addr,
-                data,
-            };
-            
-            Self::send_turn_message(&socket, peer_addr, data_msg).await?;
+            // Relay data to peer, as it came from the relay address
+            debug!("Relaying {} bytes from {} to {} via {}", data.len(), client_addr, peer_addr, allocation.relay_addr);
+            let relay_socket = Arc::clone(&allocation.relay_socket);
+            drop(allocs);
+            relay_socket.send_to(&data, peer_addr).await?;
         } else {
             warn!("Allocation {} not found", allocation_id);
         }
@@ -237,20 +295,28 @@ impl TurnServer {
     ) -> Result<()> {
         let mut allocs = allocations.lock().await;
         
-        if let Some(allocation) = allocs.get_mut(&allocation_id) {
-            if allocation.client_addr != client_addr {
-                return Ok(());
+        let response = match live_allocation(&mut allocs, allocation_id) {
+            // A zero lifetime deletes the allocation
+            Some(allocation) if allocation.client_addr == client_addr && lifetime == 0 => {
+                allocs.remove(&allocation_id);
+                info!("Deleted allocation {} for {}", allocation_id, client_addr);
+                TurnMessage::RefreshSuccess { lifetime }
             }
-            
-            allocation.lifetime = Duration::from_secs(lifetime as u64);
-            allocation.created_at = Instant::now();
-            
-            info!("Refreshed allocation {} for {}", allocation_id, client_addr);
-            
-            let response = TurnMessage::RefreshSuccess { lifetime };
-            Self::send_turn_message(&socket, client_addr, response).await?;
-        }
+            Some(allocation) if allocation.client_addr == client_addr => {
+                allocation.lifetime = Duration::from_secs(lifetime as u64);
+                allocation.created_at = Instant::now();
+                
+                info!("Refreshed allocation {} for {}", allocation_id, client_addr);
+                TurnMessage::RefreshSuccess { lifetime }
+            }
+            _ => TurnMessage::Error {
+                code: error_codes::ALLOCATION_MISMATCH,
+                reason: format!("Allocation {} not found", allocation_id),
+            },
+        };
+        drop(allocs);
         
+        Self::send_turn_message(&socket, client_addr, response).await?;
         Ok(())
     }
     
@@ -263,23 +329,74 @@ impl TurnServer {
     ) -> Result<()> {
         let mut allocs = allocations.lock().await;
         
-        if let Some(allocation) = allocs.get_mut(&allocation_id) {
-            if allocation.client_addr != client_addr {
-                return Ok(());
-            }
-            
-            if !allocation.permissions.contains(&peer_addr) {
-                allocation.permissions.push(peer_addr);
-                info!("Created permission for {} to receive from {}", client_addr, peer_addr);
+        let response = match live_allocation(&mut allocs, allocation_id) {
+            Some(allocation) if allocation.client_addr == client_addr => {
+                if !allocation.permissions.contains(&peer_addr) {
+                    allocation.permissions.push(peer_addr);
+                    info!("Created permission for {} to receive from {}", client_addr, peer_addr);
+                }
+                TurnMessage::PermissionSuccess
             }
-            
-            let response = TurnMessage::PermissionSuccess;
-            Self::send_turn_message(&socket, client_addr, response).await?;
-        }
+            _ => TurnMessage::Error {
+                code: error_codes::ALLOCATION_MISMATCH,
+                reason: format!("Allocation {} not found", allocation_id),
+            },
+        };
+        drop(allocs);
         
+        Self::send_turn_message(&socket, client_addr, response).await?;
         Ok(())
     }
     
+    /// Hand what permitted peers send to the relay address to the client,
+    /// as `Data` from the server's own socket
+    ///
+    /// Runs until the allocation expires or is deleted.
+    async fn relay_from_peers(
+        socket: Arc<UdpSocket>,
+        relay_socket: Arc<UdpSocket>,
+        allocations: Arc<Mutex<HashMap<u64, Allocation>>>,
+        allocation_id: u64,
+    ) {
+        let mut buf = vec![0u8; 65536];
+        loop {
+            let expires_in = match allocations.lock().await.get(&allocation_id) {
+                Some(allocation) => allocation.lifetime.saturating_sub(allocation.created_at.elapsed()),
+                None => return,
+            };
+            let received = tokio::time::timeout(expires_in, relay_socket.recv_from(&mut buf)).await;
+
+            let mut allocs = allocations.lock().await;
+            let allocation = match live_allocation(&mut allocs, allocation_id) {
+                Some(allocation) => allocation,
+                None => return,
+            };
+            let (len, peer_addr) = match received {
+                Ok(Ok(received)) => received,
+                Ok(Err(e)) => {
+                    debug!("Relay {} receive error: {}", allocation.relay_addr, e);
+                    continue;
+                }
+                // Refreshed meanwhile, or removed above
+                Err(_) => continue,
+            };
+            if !allocation.permissions.contains(&peer_addr) {
+                debug!("Dropping {} bytes from {} to {}: no permission", len, peer_addr, allocation.relay_addr);
+                continue;
+            }
+            let client_addr = allocation.client_addr;
+            drop(allocs);
+
+            let data_msg = TurnMessage::Data {
+                peer_addr,
+                data: buf[..len].to_vec(),
+            };
+            if let Err(e) = Self::send_turn_message(&socket, client_addr, data_msg).await {
+                warn!("Failed to relay data from {} to {}: {}", peer_addr, client_addr, e);
+            }
+        }
+    }
+
     async fn send_turn_message(socket: &UdpSocket, dest: SocketAddr, msg: TurnMessage) -> Result<()> {
         let payload = msg.to_bytes();
         
@@ -1,13 +1,112 @@
 use tokio::net::UdpSocket;
+use tokio::sync::watch;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::ops::Range;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 use anyhow::Result;
 use socket2::{Socket, Domain, Type, Protocol};
+use crate::turn_client::TurnRoute;
+
+/// ECN codepoint of a datagram: the low two bits of its IP TOS / traffic class
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EcnCodepoint {
+    /// Not ECN-capable transport
+    #[default]
+    NotEct,
+    /// ECN-capable transport, ECT(1)
+    Ect1,
+    /// ECN-capable transport, ECT(0); what this transport sends
+    Ect0,
+    /// Congestion experienced, set by a router instead of dropping
+    Ce,
+}
+
+impl EcnCodepoint {
+    pub fn from_bits(tos: u8) -> Self {
+        match tos & 0b11 {
+            0b00 => EcnCodepoint::NotEct,
+            0b01 => EcnCodepoint::Ect1,
+            0b10 => EcnCodepoint::Ect0,
+            _ => EcnCodepoint::Ce,
+        }
+    }
+
+    pub fn bits(self) -> u8 {
+        match self {
+            EcnCodepoint::NotEct => 0b00,
+            EcnCodepoint::Ect1 => 0b01,
+            EcnCodepoint::Ect0 => 0b10,
+            EcnCodepoint::Ce => 0b11,
+        }
+    }
+}
+
+/// Most datagrams `send_batch` and `recv_batch` move per syscall
+pub const MAX_BATCH: usize = 64;
+
+/// Receive buffer that fits anything the kernel coalesces with GRO
+pub const GRO_BUFFER_SIZE: usize = 65_535;
+
+/// Segments the kernel accepts in one GSO send (UDP_MAX_SEGMENTS)
+#[cfg(target_os = "linux")]
+const GSO_MAX_SEGMENTS: usize = 64;
+
+/// Largest GSO send, the UDP payload limit over IPv4
+#[cfg(target_os = "linux")]
+const GSO_MAX_BYTES: usize = 65_507;
+
+// Not exported by libc for glibc targets
+#[cfg(target_os = "linux")]
+const UDP_SEGMENT: libc::c_int = 103;
+#[cfg(target_os = "linux")]
+const UDP_GRO: libc::c_int = 104;
+
+/// What `recv_batch` received into one buffer
+#[derive(Debug, Clone, Copy)]
+pub struct RecvMeta {
+    /// Bytes written to the buffer
+    pub len: usize,
+    /// Size of each datagram in the buffer; less than `len` when GRO
+    /// coalesced several, the last of which may be shorter
+    pub stride: usize,
+    pub addr: SocketAddr,
+    pub ecn: EcnCodepoint,
+}
+
+impl RecvMeta {
+    /// A single datagram of `len` bytes
+    pub fn single(len: usize, addr: SocketAddr, ecn: EcnCodepoint) -> Self {
+        Self { len, stride: len, addr, ecn }
+    }
+
+    /// Where each datagram lies in the buffer
+    pub fn segments(&self) -> impl Iterator<Item = Range<usize>> {
+        let (len, stride) = (self.len, self.stride.max(1));
+        (0..len).step_by(stride).map(move |start| start..(start + stride).min(len))
+    }
+}
+
+impl Default for RecvMeta {
+    fn default() -> Self {
+        Self::single(0, SocketAddr::from(([0, 0, 0, 0], 0)), EcnCodepoint::NotEct)
+    }
+}
 
 /// Optimized UDP transport with socket options for maximum performance
 #[derive(Clone)]
 pub struct UdpTransport {
     socket: Arc<UdpSocket>,
+    // Set by `close`, shared by all clones
+    closed: Arc<watch::Sender<bool>>,
+    // Segmentation and receive offload, shared by all clones
+    gso: Arc<AtomicBool>,
+    gro: Arc<AtomicBool>,
+    // TURN allocation peer traffic goes through, shared by all clones
+    relay: Arc<RwLock<Option<TurnRoute>>>,
+    // Sends fail while set, standing in for a path that drops out
+    #[cfg(test)]
+    send_fault: Arc<AtomicBool>,
 }
 
 impl UdpTransport {
@@ -24,6 +123,7 @@ impl UdpTransport {
         
         // Apply socket optimizations
         Self::configure_socket(&socket)?;
+        Self::enable_ecn(&socket, addr.is_ipv4());
         
         // Bind socket
         socket.bind(&addr.into())?;
@@ -40,6 +140,12 @@ impl UdpTransport {
         
         Ok(Self {
             socket: Arc::new(tokio_socket),
+            closed: Arc::new(watch::channel(false).0),
+            gso: Arc::new(AtomicBool::new(false)),
+            gro: Arc::new(AtomicBool::new(false)),
+            relay: Arc::new(RwLock::new(None)),
+            #[cfg(test)]
+            send_fault: Arc::new(AtomicBool::new(false)),
         })
     }
     
@@ -110,17 +216,683 @@ impl UdpTransport {
         Ok(())
     }
 
+    /// Mark outgoing datagrams ECT(0) and ask for the codepoint of incoming ones
+    ///
+    /// Best effort: without it datagrams go out Not-ECT and are read as such.
+    fn enable_ecn(socket: &Socket, ipv4: bool) {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::io::AsRawFd;
+            let fd = socket.as_raw_fd();
+            let (level, tos, recv_tos) = if ipv4 {
+                (libc::IPPROTO_IP, libc::IP_TOS, libc::IP_RECVTOS)
+            } else {
+                (libc::IPPROTO_IPV6, libc::IPV6_TCLASS, libc::IPV6_RECVTCLASS)
+            };
+            let ect0: libc::c_int = EcnCodepoint::Ect0.bits() as libc::c_int;
+            let on: libc::c_int = 1;
+            for (name, val) in [(tos, &ect0), (recv_tos, &on)] {
+                let ret = unsafe {
+                    libc::setsockopt(
+                        fd,
+                        level,
+                        name,
+                        val as *const _ as *const libc::c_void,
+                        std::mem::size_of_val(val) as libc::socklen_t,
+                    )
+                };
+                if ret != 0 {
+                    tracing::debug!("ECN socket option {} not available: {}", name, std::io::Error::last_os_error());
+                    return;
+                }
+            }
+            tracing::debug!("ECN enabled");
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = (socket, ipv4);
+    }
+
+    /// Let `send_batch` hand runs of equal-sized packets to the kernel as one
+    /// buffer it splits into datagrams (UDP GSO)
+    ///
+    /// Returns false, leaving every packet its own send, where the kernel
+    /// lacks UDP_SEGMENT.
+    pub fn enable_gso(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::io::AsRawFd;
+            let mut segment: libc::c_int = 0;
+            let mut len = std::mem::size_of_val(&segment) as libc::socklen_t;
+            let ret = unsafe {
+                libc::getsockopt(
+                    self.socket.as_raw_fd(),
+                    libc::SOL_UDP,
+                    UDP_SEGMENT,
+                    &mut segment as *mut _ as *mut libc::c_void,
+                    &mut len,
+                )
+            };
+            if ret == 0 {
+                self.gso.store(true, Ordering::Relaxed);
+                tracing::debug!("UDP GSO enabled");
+            }
+        }
+        self.gso_enabled()
+    }
+
+    pub fn gso_enabled(&self) -> bool {
+        self.gso.load(Ordering::Relaxed)
+    }
+
+    /// Have the kernel coalesce consecutive datagrams of a flow (UDP GRO)
+    ///
+    /// `recv_batch` then needs `GRO_BUFFER_SIZE` buffers and may return
+    /// several datagrams in one, see `RecvMeta::segments`. Returns false
+    /// where the kernel lacks UDP_GRO.
+    pub fn enable_gro(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::io::AsRawFd;
+            let on: libc::c_int = 1;
+            let ret = unsafe {
+                libc::setsockopt(
+                    self.socket.as_raw_fd(),
+                    libc::SOL_UDP,
+                    UDP_GRO,
+                    &on as *const _ as *const libc::c_void,
+                    std::mem::size_of_val(&on) as libc::socklen_t,
+                )
+            };
+            if ret == 0 {
+                self.gro.store(true, Ordering::Relaxed);
+                tracing::debug!("UDP GRO enabled");
+            }
+        }
+        self.gro_enabled()
+    }
+
+    pub fn gro_enabled(&self) -> bool {
+        self.gro.load(Ordering::Relaxed)
+    }
+
+    /// Send and receive peer traffic through a TURN allocation, or directly
+    /// again with None
+    ///
+    /// See `TurnRoute`; `TurnClient::start_relay` sets this. GRO is turned
+    /// off, since the server's datagrams each need unwrapping.
+    pub fn set_relay(&self, route: Option<TurnRoute>) {
+        if route.is_some() && self.gro_enabled() {
+            self.disable_gro();
+        }
+        *self.relay.write().unwrap() = route;
+    }
+
+    pub fn relay(&self) -> Option<TurnRoute> {
+        self.relay.read().unwrap().clone()
+    }
+
+    fn disable_gro(&self) {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::io::AsRawFd;
+            let off: libc::c_int = 0;
+            unsafe {
+                libc::setsockopt(
+                    self.socket.as_raw_fd(),
+                    libc::SOL_UDP,
+                    UDP_GRO,
+                    &off as *const _ as *const libc::c_void,
+                    std::mem::size_of_val(&off) as libc::socklen_t,
+                );
+            }
+        }
+        self.gro.store(false, Ordering::Relaxed);
+        tracing::debug!("UDP GRO disabled");
+    }
+
     pub async fn send_to(&self, data: &[u8], addr: SocketAddr) -> Result<usize> {
+        if self.is_closed() {
+            return Err(closed_error().into());
+        }
+        #[cfg(test)]
+        self.check_send_fault()?;
+        if let Some(route) = self.relay().filter(|route| route.server != addr) {
+            self.socket.send_to(&route.wrap(data, addr)?, route.server).await?;
+            return Ok(data.len());
+        }
         let len = self.socket.send_to(data, addr).await?;
         Ok(len)
     }
 
+    /// Send each packet as its own datagram, in order
+    ///
+    /// On Linux this takes one `sendmmsg` per `MAX_BATCH` packets, elsewhere
+    /// one `send_to` each. With GSO enabled, each run of packets of the same
+    /// size to the same address goes out as one GSO buffer. An error means
+    /// the packets before the one the socket refused were sent and the rest
+    /// were not. While relaying, each packet is wrapped and sent on its own.
+    pub async fn send_batch<B: AsRef<[u8]>>(&self, packets: &[(B, SocketAddr)]) -> Result<()> {
+        if self.is_closed() {
+            return Err(closed_error().into());
+        }
+        #[cfg(test)]
+        self.check_send_fault()?;
+        if self.relay().is_some() {
+            for (data, addr) in packets {
+                self.send_to(data.as_ref(), *addr).await?;
+            }
+            return Ok(());
+        }
+        self.send_mmsg(packets).await?;
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn send_mmsg<B: AsRef<[u8]>>(&self, packets: &[(B, SocketAddr)]) -> std::io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+        let fd = self.socket.as_raw_fd();
+        let mut gso = self.gso_enabled();
+        let mut sent = 0;
+        while sent < packets.len() {
+            self.socket.writable().await?;
+            match self.socket.try_io(tokio::io::Interest::WRITABLE, || sendmmsg(fd, &packets[sent..], gso)) {
+                Ok(count) => sent += count,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+                // The device can't offload the checksum (EIO), or this path
+                // can't take the segment size (EINVAL); send them one by one
+                Err(e) if gso && matches!(e.raw_os_error(), Some(libc::EIO) | Some(libc::EINVAL)) => {
+                    if e.raw_os_error() == Some(libc::EIO) {
+                        tracing::debug!("UDP GSO not supported by the device, disabling: {}", e);
+                        self.gso.store(false, Ordering::Relaxed);
+                    }
+                    gso = false;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn send_mmsg<B: AsRef<[u8]>>(&self, packets: &[(B, SocketAddr)]) -> std::io::Result<()> {
+        for (data, addr) in packets {
+            self.socket.send_to(data.as_ref(), *addr).await?;
+        }
+        Ok(())
+    }
+
     pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
-        let (len, addr) = self.socket.recv_from(buf).await?;
+        let (len, addr, _) = self.recv_from_ecn(buf).await?;
         Ok((len, addr))
     }
 
+    /// Receive a datagram along with its ECN codepoint
+    pub async fn recv_from_ecn(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr, EcnCodepoint)> {
+        let mut closed = self.closed.subscribe();
+        loop {
+            let (len, addr, ecn) = tokio::select! {
+                res = self.recv_with_ecn(buf) => res?,
+                _ = closed.wait_for(|closed| *closed) => return Err(closed_error().into()),
+            };
+            match self.relay().filter(|route| route.server == addr) {
+                Some(route) => {
+                    if let Some((len, peer_addr)) = route.unwrap(buf, len) {
+                        return Ok((len, peer_addr, ecn));
+                    }
+                }
+                None => return Ok((len, addr, ecn)),
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn recv_with_ecn(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr, EcnCodepoint)> {
+        use std::os::unix::io::AsRawFd;
+        let fd = self.socket.as_raw_fd();
+        loop {
+            self.socket.readable().await?;
+            match self.socket.try_io(tokio::io::Interest::READABLE, || recvmsg_ecn(fd, buf)) {
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+                res => return res,
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn recv_with_ecn(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr, EcnCodepoint)> {
+        let (len, addr) = self.socket.recv_from(buf).await?;
+        Ok((len, addr, EcnCodepoint::NotEct))
+    }
+
+    /// Receive up to `bufs.len()` datagrams, waiting for at least one
+    ///
+    /// Datagram `i` is written to `bufs[i]` and described by `meta[i]`;
+    /// returns how many arrived. Linux reads what is queued with one
+    /// `recvmmsg`, elsewhere each call returns a single datagram. With GRO
+    /// enabled a buffer may hold several datagrams. While relaying, the
+    /// server's own messages are left out and the rest moved up to fill
+    /// their places.
+    pub async fn recv_batch<B: AsMut<[u8]>>(&self, bufs: &mut [B], meta: &mut [RecvMeta]) -> Result<usize> {
+        let mut closed = self.closed.subscribe();
+        loop {
+            let received = tokio::select! {
+                res = self.recv_mmsg(bufs, meta) => res?,
+                _ = closed.wait_for(|closed| *closed) => return Err(closed_error().into()),
+            };
+            let route = match self.relay() {
+                Some(route) => route,
+                None => return Ok(received),
+            };
+            let mut kept = 0;
+            for i in 0..received {
+                if meta[i].addr == route.server {
+                    match route.unwrap(bufs[i].as_mut(), meta[i].len) {
+                        Some((len, peer_addr)) => meta[i] = RecvMeta::single(len, peer_addr, meta[i].ecn),
+                        None => continue,
+                    }
+                }
+                bufs.swap(kept, i);
+                meta.swap(kept, i);
+                kept += 1;
+            }
+            if kept > 0 {
+                return Ok(kept);
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn recv_mmsg<B: AsMut<[u8]>>(&self, bufs: &mut [B], meta: &mut [RecvMeta]) -> std::io::Result<usize> {
+        use std::os::unix::io::AsRawFd;
+        let fd = self.socket.as_raw_fd();
+        loop {
+            self.socket.readable().await?;
+            match self.socket.try_io(tokio::io::Interest::READABLE, || recvmmsg_ecn(fd, bufs, meta)) {
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+                res => return res,
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn recv_mmsg<B: AsMut<[u8]>>(&self, bufs: &mut [B], meta: &mut [RecvMeta]) -> std::io::Result<usize> {
+        match (bufs.first_mut(), meta.first_mut()) {
+            (Some(buf), Some(slot)) => {
+                let (len, addr, ecn) = self.recv_with_ecn(buf.as_mut()).await?;
+                *slot = RecvMeta::single(len, addr, ecn);
+                Ok(1)
+            }
+            _ => Ok(0),
+        }
+    }
+
+    /// Stop using the socket: pending and later sends and receives on every
+    /// clone fail with `NotConnected`, as they would once its interface is gone
+    pub fn close(&self) {
+        self.closed.send_replace(true);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        *self.closed.borrow()
+    }
+
+    /// Make sends on every clone fail, or work again, leaving receives alone
+    #[cfg(test)]
+    pub(crate) fn set_send_fault(&self, fail: bool) {
+        self.send_fault.store(fail, Ordering::Relaxed);
+    }
+
+    #[cfg(test)]
+    fn check_send_fault(&self) -> std::io::Result<()> {
+        if self.send_fault.load(Ordering::Relaxed) {
+            return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Injected send failure"));
+        }
+        Ok(())
+    }
+
     pub fn local_addr(&self) -> Result<SocketAddr> {
         Ok(self.socket.local_addr()?)
     }
 }
+
+fn closed_error() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::NotConnected, "UDP transport closed")
+}
+
+/// `recvmsg` one datagram, reading the ECN bits from the TOS / TCLASS control message
+#[cfg(target_os = "linux")]
+fn recvmsg_ecn(fd: std::os::unix::io::RawFd, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr, EcnCodepoint)> {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // u64s keep the control buffer aligned for cmsghdr
+    let mut control = [0u64; 8];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = &mut storage as *mut _ as *mut libc::c_void;
+    msg.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    let len = unsafe { libc::recvmsg(fd, &mut msg, 0) };
+    if len < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let (ecn, _) = parse_cmsgs(&msg);
+    Ok((len as usize, socket_addr(storage, msg.msg_namelen)?, ecn))
+}
+
+/// `recvmmsg` as many queued datagrams as there are buffers, up to `MAX_BATCH`
+#[cfg(target_os = "linux")]
+fn recvmmsg_ecn<B: AsMut<[u8]>>(fd: std::os::unix::io::RawFd, bufs: &mut [B], meta: &mut [RecvMeta]) -> std::io::Result<usize> {
+    let count = bufs.len().min(meta.len()).min(MAX_BATCH);
+    let mut storage: [libc::sockaddr_storage; MAX_BATCH] = unsafe { std::mem::zeroed() };
+    let mut iovs: [libc::iovec; MAX_BATCH] = unsafe { std::mem::zeroed() };
+    let mut control = [[0u64; 8]; MAX_BATCH];
+    let mut msgs: [libc::mmsghdr; MAX_BATCH] = unsafe { std::mem::zeroed() };
+    for (i, buf) in bufs.iter_mut().take(count).enumerate() {
+        let buf = buf.as_mut();
+        iovs[i] = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        let msg = &mut msgs[i].msg_hdr;
+        msg.msg_name = &mut storage[i] as *mut _ as *mut libc::c_void;
+        msg.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        msg.msg_iov = &mut iovs[i];
+        msg.msg_iovlen = 1;
+        msg.msg_control = control[i].as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = std::mem::size_of_val(&control[i]) as _;
+    }
+
+    let received = unsafe { libc::recvmmsg(fd, msgs.as_mut_ptr(), count as _, 0, std::ptr::null_mut()) };
+    if received < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    for (i, slot) in meta.iter_mut().take(received as usize).enumerate() {
+        let msg = &msgs[i];
+        let len = msg.msg_len as usize;
+        let (ecn, stride) = parse_cmsgs(&msg.msg_hdr);
+        *slot = RecvMeta {
+            len,
+            stride: stride.unwrap_or(len),
+            addr: socket_addr(storage[i], msg.msg_hdr.msg_namelen)?,
+            ecn,
+        };
+    }
+    Ok(received as usize)
+}
+
+/// How many packets from the start of `packets` one GSO send can carry:
+/// those to the same address and of the same size as the first, plus a
+/// final shorter one
+#[cfg(target_os = "linux")]
+fn gso_run<B: AsRef<[u8]>>(packets: &[(B, SocketAddr)]) -> usize {
+    let (first, addr) = match packets.first() {
+        Some((data, addr)) => (data.as_ref().len(), *addr),
+        None => return 0,
+    };
+    if first == 0 || first > u16::MAX as usize {
+        return 1;
+    }
+    let mut run = 1;
+    let mut bytes = first;
+    for (data, to) in &packets[1..] {
+        let len = data.as_ref().len();
+        if *to != addr || len > first || len == 0 || run == GSO_MAX_SEGMENTS || bytes + len > GSO_MAX_BYTES {
+            break;
+        }
+        run += 1;
+        bytes += len;
+        if len < first {
+            break;
+        }
+    }
+    run
+}
+
+/// `sendmmsg` up to `MAX_BATCH` of `packets`, returning how many were sent
+///
+/// With `gso`, each run of packets that `gso_run` allows is one message
+/// carrying a UDP_SEGMENT control message.
+#[cfg(target_os = "linux")]
+fn sendmmsg<B: AsRef<[u8]>>(fd: std::os::unix::io::RawFd, packets: &[(B, SocketAddr)], gso: bool) -> std::io::Result<usize> {
+    let total = packets.len().min(MAX_BATCH);
+    let mut storage: [libc::sockaddr_storage; MAX_BATCH] = unsafe { std::mem::zeroed() };
+    let mut iovs: [libc::iovec; MAX_BATCH] = unsafe { std::mem::zeroed() };
+    // u64s keep each control buffer aligned for cmsghdr
+    let mut control = [[0u64; 4]; MAX_BATCH];
+    let mut msgs: [libc::mmsghdr; MAX_BATCH] = unsafe { std::mem::zeroed() };
+    // Packets in each message
+    let mut runs = [0usize; MAX_BATCH];
+
+    for (i, (data, _)) in packets.iter().take(total).enumerate() {
+        let data = data.as_ref();
+        // The kernel only reads the buffers, despite the `*mut`
+        iovs[i] = libc::iovec {
+            iov_base: data.as_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+    }
+
+    let (mut count, mut used) = (0, 0);
+    while used < total {
+        let run = if gso { gso_run(&packets[used..total]) } else { 1 };
+        let addr = socket2::SockAddr::from(packets[used].1);
+        unsafe {
+            std::ptr::copy_nonoverlapping(addr.as_ptr() as *const u8, &mut storage[count] as *mut _ as *mut u8, addr.len() as usize);
+        }
+        let msg = &mut msgs[count].msg_hdr;
+        msg.msg_name = &mut storage[count] as *mut _ as *mut libc::c_void;
+        msg.msg_namelen = addr.len();
+        msg.msg_iov = &mut iovs[used];
+        msg.msg_iovlen = run as _;
+        if run > 1 {
+            // The kernel splits the gathered buffer every `segment` bytes
+            let segment = packets[used].0.as_ref().len() as u16;
+            msg.msg_control = control[count].as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = unsafe { libc::CMSG_SPACE(std::mem::size_of::<u16>() as u32) } as _;
+            unsafe {
+                let cmsg = libc::CMSG_FIRSTHDR(msg);
+                (*cmsg).cmsg_level = libc::SOL_UDP;
+                (*cmsg).cmsg_type = UDP_SEGMENT;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<u16>() as u32) as _;
+                std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, segment);
+            }
+        }
+        runs[count] = run;
+        count += 1;
+        used += run;
+    }
+
+    let sent = unsafe { libc::sendmmsg(fd, msgs.as_mut_ptr(), count as _, 0) };
+    if sent < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(runs[..sent as usize].iter().sum())
+}
+
+/// ECN codepoint and GRO segment size from the control messages of a received datagram
+#[cfg(target_os = "linux")]
+fn parse_cmsgs(msg: &libc::msghdr) -> (EcnCodepoint, Option<usize>) {
+    let mut ecn = EcnCodepoint::NotEct;
+    let mut stride = None;
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(msg) };
+    while !cmsg.is_null() {
+        let (level, kind) = unsafe { ((*cmsg).cmsg_level, (*cmsg).cmsg_type) };
+        // IP_TOS arrives as a single byte, IPV6_TCLASS and UDP_GRO as an int
+        if level == libc::IPPROTO_IP && kind == libc::IP_TOS {
+            ecn = EcnCodepoint::from_bits(unsafe { *libc::CMSG_DATA(cmsg) });
+        } else if level == libc::IPPROTO_IPV6 && kind == libc::IPV6_TCLASS {
+            let tclass = unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int) };
+            ecn = EcnCodepoint::from_bits(tclass as u8);
+        } else if level == libc::SOL_UDP && kind == UDP_GRO {
+            let segment = unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int) };
+            stride = Some(segment as usize);
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(msg, cmsg) };
+    }
+    (ecn, stride)
+}
+
+#[cfg(target_os = "linux")]
+fn socket_addr(storage: libc::sockaddr_storage, len: libc::socklen_t) -> std::io::Result<SocketAddr> {
+    let addr = unsafe { socket2::SockAddr::new(storage, len) };
+    addr.as_socket()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "recvmsg returned a non-IP address"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ecn_codepoint_bits() {
+        for ecn in [EcnCodepoint::NotEct, EcnCodepoint::Ect1, EcnCodepoint::Ect0, EcnCodepoint::Ce] {
+            assert_eq!(EcnCodepoint::from_bits(ecn.bits()), ecn);
+        }
+        // DSCP bits above the ECN field are ignored
+        assert_eq!(EcnCodepoint::from_bits(0xb8 | 0b11), EcnCodepoint::Ce);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_recv_from_ecn_reads_ect0() {
+        let a = UdpTransport::bind("127.0.0.1:0").await.unwrap();
+        let b = UdpTransport::bind("127.0.0.1:0").await.unwrap();
+
+        a.send_to(b"ecn", b.local_addr().unwrap()).await.unwrap();
+        let mut buf = [0u8; 16];
+        let (len, from, ecn) = b.recv_from_ecn(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"ecn");
+        assert_eq!(from, a.local_addr().unwrap());
+        assert_eq!(ecn, EcnCodepoint::Ect0);
+    }
+
+    #[tokio::test]
+    async fn test_send_batch_keeps_datagram_boundaries() {
+        let a = UdpTransport::bind("127.0.0.1:0").await.unwrap();
+        let b = UdpTransport::bind("127.0.0.1:0").await.unwrap();
+        let to = b.local_addr().unwrap();
+
+        // More than one syscall's worth, each a different length
+        let packets: Vec<(Vec<u8>, SocketAddr)> = (0..MAX_BATCH + 10)
+            .map(|i| (vec![i as u8; i + 1], to))
+            .collect();
+        a.send_batch(&packets).await.unwrap();
+
+        let mut buf = [0u8; 256];
+        for (data, _) in &packets {
+            let (len, from) = b.recv_from(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], &data[..]);
+            assert_eq!(from, a.local_addr().unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recv_batch_keeps_datagram_boundaries() {
+        let a = UdpTransport::bind("127.0.0.1:0").await.unwrap();
+        let b = UdpTransport::bind("127.0.0.1:0").await.unwrap();
+        let to = b.local_addr().unwrap();
+
+        for i in 0..10u8 {
+            a.send_to(&vec![i; i as usize + 1], to).await.unwrap();
+        }
+        // Longer than a buffer: truncated, not continued in the next one
+        a.send_to(&[0xff; 100], to).await.unwrap();
+        a.send_to(b"last", to).await.unwrap();
+
+        let mut bufs = vec![[0u8; 16]; 4];
+        let mut meta = [RecvMeta::default(); 4];
+        let mut received = Vec::new();
+        while received.len() < 12 {
+            let count = b.recv_batch(&mut bufs, &mut meta).await.unwrap();
+            assert!((1..=4).contains(&count));
+            for (buf, meta) in bufs.iter().zip(&meta).take(count) {
+                assert_eq!(meta.addr, a.local_addr().unwrap());
+                if cfg!(target_os = "linux") {
+                    assert_eq!(meta.ecn, EcnCodepoint::Ect0);
+                }
+                received.push(buf[..meta.len].to_vec());
+            }
+        }
+        for i in 0..10u8 {
+            assert_eq!(received[i as usize], vec![i; i as usize + 1]);
+        }
+        assert_eq!(received[10], vec![0xff; 16]);
+        assert_eq!(received[11], b"last");
+    }
+
+    #[test]
+    fn test_recv_meta_segments() {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1));
+        let single = RecvMeta::single(1200, addr, EcnCodepoint::NotEct);
+        assert_eq!(single.segments().collect::<Vec<_>>(), vec![0..1200]);
+
+        let coalesced = RecvMeta { stride: 1200, len: 3000, ..single };
+        assert_eq!(coalesced.segments().collect::<Vec<_>>(), vec![0..1200, 1200..2400, 2400..3000]);
+
+        assert_eq!(RecvMeta::default().segments().count(), 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_gso_run() {
+        let a = SocketAddr::from(([127, 0, 0, 1], 1));
+        let b = SocketAddr::from(([127, 0, 0, 1], 2));
+        let run = |sizes: &[(usize, SocketAddr)]| {
+            let packets: Vec<(Vec<u8>, SocketAddr)> = sizes.iter().map(|&(len, to)| (vec![0; len], to)).collect();
+            gso_run(&packets)
+        };
+
+        // Equal sizes, then one shorter packet that ends the run
+        assert_eq!(run(&[(100, a), (100, a), (100, a), (50, a), (50, a)]), 4);
+        // Larger packets and other destinations start a new run
+        assert_eq!(run(&[(100, a), (200, a)]), 1);
+        assert_eq!(run(&[(100, a), (100, b)]), 1);
+        // Kernel limits on segments and bytes
+        assert_eq!(run(&vec![(100, a); 100]), GSO_MAX_SEGMENTS);
+        assert_eq!(run(&[(40_000, a), (40_000, a)]), 1);
+        assert_eq!(run(&[]), 0);
+    }
+
+    #[tokio::test]
+    async fn test_gso_gro_loopback_keeps_payloads() {
+        let tx = UdpTransport::bind("127.0.0.1:0").await.unwrap();
+        let gro_rx = UdpTransport::bind("127.0.0.1:0").await.unwrap();
+        let plain_rx = UdpTransport::bind("127.0.0.1:0").await.unwrap();
+        // Either may be missing; the payloads must come through regardless
+        let gso = tx.enable_gso();
+        let gro = gro_rx.enable_gro();
+        tracing::debug!("GSO: {}, GRO: {}", gso, gro);
+
+        // Runs of equal sizes with shorter packets between them, each numbered
+        let sizes = [vec![1200; 20], vec![500], vec![800; 10], vec![1200; 3]].concat();
+        let payloads: Vec<Vec<u8>> = sizes.iter().enumerate()
+            .map(|(i, &len)| (0..len).map(|j| (i + j) as u8).collect())
+            .collect();
+
+        for rx in [&gro_rx, &plain_rx] {
+            let to = rx.local_addr().unwrap();
+            let packets: Vec<(&[u8], SocketAddr)> = payloads.iter().map(|p| (p.as_slice(), to)).collect();
+            tx.send_batch(&packets).await.unwrap();
+
+            let mut bufs = vec![vec![0u8; GRO_BUFFER_SIZE]; 8];
+            let mut meta = [RecvMeta::default(); 8];
+            let mut received = Vec::new();
+            while received.len() < payloads.len() {
+                let count = rx.recv_batch(&mut bufs, &mut meta).await.unwrap();
+                for (buf, meta) in bufs.iter().zip(&meta).take(count) {
+                    assert_eq!(meta.addr, tx.local_addr().unwrap());
+                    received.extend(meta.segments().map(|range| buf[range].to_vec()));
+                }
+            }
+            assert_eq!(received, payloads);
+        }
+    }
+}
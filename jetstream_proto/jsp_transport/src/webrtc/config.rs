@@ -1,6 +1,7 @@
 //! WebRTC Configuration
 
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 /// ICE transport policy
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -42,6 +43,7 @@ pub struct TurnServer {
 
 /// WebRTC configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct WebRTCConfig {
     /// STUN servers for NAT discovery
     pub stun_servers: Vec<String>,
@@ -66,6 +68,19 @@ pub struct WebRTCConfig {
     
     /// Max retransmits (None = reliable)
     pub max_retransmits: Option<u16>,
+
+    /// Local address for the data channel's UDP socket
+    pub bind_addr: String,
+
+    /// Signaling server used to exchange the offer and answer
+    pub signaling_server: Option<String>,
+
+    /// ID to register with the signaling server (random when unset)
+    pub peer_id: Option<String>,
+
+    /// How long the answer and connectivity checks may take
+    #[serde(with = "crate::duration_format")]
+    pub ice_timeout: Duration,
 }
 
 impl Default for WebRTCConfig {
@@ -82,6 +97,10 @@ impl Default for WebRTCConfig {
             data_channel_label: "jetstream_proto".to_string(),
             ordered: true,
             max_retransmits: None, // Reliable by default
+            bind_addr: "0.0.0.0:0".to_string(),
+            signaling_server: None,
+            peer_id: None,
+            ice_timeout: Duration::from_secs(10),
         }
     }
 }
@@ -96,6 +115,10 @@ impl WebRTCConfig {
         if self.data_channel_label.is_empty() {
             return Err("Data channel label cannot be empty".to_string());
         }
+
+        if self.ice_timeout.is_zero() {
+            return Err("ICE timeout must be greater than zero".to_string());
+        }
         
         Ok(())
     }
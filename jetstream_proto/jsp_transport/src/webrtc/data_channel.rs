@@ -1,7 +1,15 @@
 //! WebRTC Data Channel
+//!
+//! A connected channel wraps an SCTP data channel of a `webrtc` peer
+//! connection. A channel that was never connected loops sent data back to its
+//! own receiver.
 
-use tokio::sync::mpsc;
+use ::webrtc::data_channel::data_channel_message::DataChannelMessage;
+use ::webrtc::data_channel::RTCDataChannel;
+use tokio::sync::{mpsc, watch, Mutex};
 use bytes::Bytes;
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
 
 /// Data channel state
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -19,57 +27,126 @@ pub struct DataChannel {
     ordered: bool,
     #[allow(dead_code)]
     max_retransmits: Option<u16>,
-    state: DataChannelState,
+    state: Arc<watch::Sender<DataChannelState>>,
     tx: mpsc::UnboundedSender<Bytes>,
-    rx: mpsc::UnboundedReceiver<Bytes>,
+    rx: Mutex<mpsc::UnboundedReceiver<Bytes>>,
+    rtc: Option<Arc<RTCDataChannel>>,
+    remote: OnceLock<SocketAddr>,
 }
 
 impl DataChannel {
     /// Create a new data channel
     pub fn new(label: String, ordered: bool, max_retransmits: Option<u16>) -> Self {
         let (tx, rx) = mpsc::unbounded_channel();
-        
+
         Self {
             label,
             ordered,
             max_retransmits,
-            state: DataChannelState::Connecting,
+            state: Arc::new(watch::Sender::new(DataChannelState::Connecting)),
             tx,
-            rx,
+            rx: Mutex::new(rx),
+            rtc: None,
+            remote: OnceLock::new(),
         }
     }
-    
+
+    /// Wrap an SCTP data channel before it opens
+    ///
+    /// Handlers are installed right away so no message that arrives between
+    /// the channel opening and the first `recv` is lost.
+    pub(crate) fn attach(rtc: Arc<RTCDataChannel>, ordered: bool, max_retransmits: Option<u16>) -> Self {
+        let mut channel = Self::new(rtc.label().to_string(), ordered, max_retransmits);
+
+        let state = Arc::clone(&channel.state);
+        rtc.on_open(Box::new(move || {
+            state.send_replace(DataChannelState::Open);
+            Box::pin(async {})
+        }));
+        let state = Arc::clone(&channel.state);
+        rtc.on_close(Box::new(move || {
+            state.send_replace(DataChannelState::Closed);
+            Box::pin(async {})
+        }));
+        let tx = channel.tx.clone();
+        rtc.on_message(Box::new(move |message: DataChannelMessage| {
+            let _ = tx.send(message.data);
+            Box::pin(async {})
+        }));
+
+        channel.rtc = Some(rtc);
+        channel
+    }
+
+    /// Wait until the channel leaves `Connecting`; true if it opened
+    pub(crate) async fn opened(&self) -> bool {
+        let mut state = self.state.subscribe();
+        let opened = match state.wait_for(|state| *state != DataChannelState::Connecting).await {
+            Ok(state) => *state == DataChannelState::Open,
+            Err(_) => false,
+        };
+        opened
+    }
+
+    /// Record the peer end of the candidate pair the channel runs over
+    pub(crate) fn set_remote_addr(&self, remote: SocketAddr) {
+        let _ = self.remote.set(remote);
+    }
+
     /// Get channel label
     pub fn label(&self) -> &str {
         &self.label
     }
-    
+
     /// Get channel state
     pub fn state(&self) -> DataChannelState {
-        self.state
+        *self.state.borrow()
     }
-    
+
+    /// Address of the peer, once the channel is connected
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote.get().copied()
+    }
+
     /// Send data on the channel
     pub async fn send(&self, data: Bytes) -> Result<(), Box<dyn std::error::Error>> {
-        if self.state != DataChannelState::Open {
+        if self.state() != DataChannelState::Open {
             return Err("Data channel not open".into());
         }
-        
-        self.tx.send(data)?;
+
+        match &self.rtc {
+            Some(rtc) => {
+                rtc.send(&data).await?;
+            }
+            None => self.tx.send(data)?,
+        }
         Ok(())
     }
-    
-    /// Receive data from the channel
-    pub async fn recv(&mut self) -> Option<Bytes> {
-        self.rx.recv().await
+
+    /// Receive data from the channel; `None` once it is closed
+    pub async fn recv(&self) -> Option<Bytes> {
+        let mut state = self.state.subscribe();
+        let mut rx = self.rx.lock().await;
+        tokio::select! {
+            biased;
+            data = rx.recv() => data,
+            _ = state.wait_for(|state| *state == DataChannelState::Closed) => None,
+        }
     }
-    
+
     /// Close the data channel
-    pub fn close(&mut self) {
-        self.state = DataChannelState::Closing;
-        // Channel will be closed when tx is dropped
+    pub fn close(&self) {
+        if let Some(rtc) = &self.rtc {
+            let rtc = Arc::clone(rtc);
+            tokio::spawn(async move {
+                if let Err(e) = rtc.close().await {
+                    tracing::debug!("Closing WebRTC data channel failed: {}", e);
+                }
+            });
+        }
+        self.state.send_replace(DataChannelState::Closed);
     }
-    
+
     /// Check if channel is reliable
     pub fn is_reliable(&self) -> bool {
         self.max_retransmits.is_none()
@@ -87,16 +164,27 @@ mod tests {
         assert_eq!(channel.state(), DataChannelState::Connecting);
         assert!(channel.is_reliable());
     }
-    
+
     #[tokio::test]
     async fn test_data_channel_send_recv() {
-        let mut channel = DataChannel::new("test".to_string(), true, None);
-        channel.state = DataChannelState::Open;
-        
+        let channel = DataChannel::new("test".to_string(), true, None);
+        channel.state.send_replace(DataChannelState::Open);
+
         let data = Bytes::from("hello");
         channel.send(data.clone()).await.unwrap();
-        
+
         let received = channel.recv().await.unwrap();
         assert_eq!(received, data);
     }
+
+    #[tokio::test]
+    async fn test_closed_channel_ends_recv() {
+        let channel = DataChannel::new("test".to_string(), true, None);
+        channel.state.send_replace(DataChannelState::Open);
+
+        channel.close();
+        assert!(!channel.opened().await);
+        assert_eq!(channel.recv().await, None);
+        assert!(channel.send(Bytes::from("late")).await.is_err());
+    }
 }
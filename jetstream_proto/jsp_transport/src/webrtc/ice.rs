@@ -147,6 +147,73 @@ impl IceCandidate {
         
         sdp
     }
+
+    /// Parse a candidate attribute as produced by `to_sdp` (an `a=` prefix is allowed)
+    pub fn from_sdp(line: &str) -> Option<Self> {
+        let line = line.trim();
+        let line = line.strip_prefix("a=").unwrap_or(line);
+        let fields: Vec<&str> = line.strip_prefix("candidate:")?.split_whitespace().collect();
+        if fields.len() < 8 || fields[6] != "typ" {
+            return None;
+        }
+
+        let candidate_type = match fields[7] {
+            "host" => IceCandidateType::Host,
+            "srflx" => IceCandidateType::Srflx,
+            "prflx" => IceCandidateType::Prflx,
+            "relay" => IceCandidateType::Relay,
+            _ => return None,
+        };
+        let related = |key: &str| fields.iter()
+            .position(|field| *field == key)
+            .and_then(|i| fields.get(i + 1).copied());
+
+        Some(Self {
+            candidate_type,
+            foundation: fields[0].to_string(),
+            component: fields[1].parse().ok()?,
+            protocol: fields[2].to_lowercase(),
+            priority: fields[3].parse().ok()?,
+            address: fields[4].to_string(),
+            port: fields[5].parse().ok()?,
+            related_address: related("raddr").map(str::to_string),
+            related_port: related("rport").and_then(|port| port.parse().ok()),
+        })
+    }
+
+    /// Address to send connectivity checks to
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        let ip = self.address.parse().ok()?;
+        Some(SocketAddr::new(ip, self.port))
+    }
+}
+
+/// Candidates signaled by an `IceAgent` carry no related address, so
+/// derived candidates get an unspecified one
+impl From<&crate::ice::Candidate> for IceCandidate {
+    fn from(candidate: &crate::ice::Candidate) -> Self {
+        use crate::ice::CandidateType;
+
+        let unspecified = if candidate.addr.is_ipv4() { "0.0.0.0" } else { "::" };
+        let (candidate_type, related) = match candidate.candidate_type {
+            CandidateType::Host => (IceCandidateType::Host, None),
+            CandidateType::ServerReflexive => (IceCandidateType::Srflx, Some(unspecified)),
+            CandidateType::PeerReflexive => (IceCandidateType::Prflx, Some(unspecified)),
+            CandidateType::Relayed => (IceCandidateType::Relay, Some(unspecified)),
+        };
+
+        Self {
+            candidate_type,
+            foundation: format!("jsp{}", candidate.priority),
+            component: 1,
+            protocol: "udp".to_string(),
+            priority: candidate.priority,
+            address: candidate.addr.ip().to_string(),
+            port: candidate.addr.port(),
+            related_address: related.map(str::to_string),
+            related_port: related.map(|_| 0),
+        }
+    }
 }
 
 /// ICE connection state
@@ -192,6 +259,34 @@ impl IceGatherer {
         Ok(())
     }
     
+    /// Gather host candidates for a bound socket
+    ///
+    /// A socket bound to the unspecified address gets one candidate per local
+    /// address of the same family.
+    pub fn gather_socket_candidates(&mut self, local: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
+        let addrs = if local.ip().is_unspecified() {
+            get_local_addresses()?.into_iter()
+                .filter(|addr| addr.is_ipv4() == local.is_ipv4())
+                .map(|addr| SocketAddr::new(addr.ip(), local.port()))
+                .collect()
+        } else {
+            vec![local]
+        };
+
+        for addr in addrs {
+            let foundation = format!("host{}", self.candidates.len());
+            self.candidates.push(IceCandidate::host(addr, foundation, 1));
+        }
+        self.state = IceConnectionState::Checking;
+
+        Ok(())
+    }
+
+    /// Record a candidate gathered elsewhere, e.g. by a peer connection
+    pub fn add_candidate(&mut self, candidate: IceCandidate) {
+        self.candidates.push(candidate);
+    }
+
     /// Get all gathered candidates
     pub fn candidates(&self) -> &[IceCandidate] {
         &self.candidates
@@ -213,11 +308,15 @@ impl Default for IceGatherer {
 fn get_local_addresses() -> Result<Vec<SocketAddr>, Box<dyn std::error::Error>> {
     use std::net::{IpAddr, Ipv4Addr};
     
-    // Simplified: return localhost for now
-    // In production, enumerate actual network interfaces
-    Ok(vec![
-        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0),
-    ])
+    let mut addrs: Vec<SocketAddr> = if_addrs::get_if_addrs()?
+        .into_iter()
+        .filter(|iface| !iface.is_loopback())
+        .map(|iface| SocketAddr::new(iface.ip(), 0))
+        .collect();
+
+    // Loopback keeps same-host peers reachable
+    addrs.push(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0));
+    Ok(addrs)
 }
 
 #[cfg(test)]
@@ -258,4 +357,31 @@ mod tests {
         assert!(sdp.contains("typ host"));
         assert!(sdp.contains("192.168.1.1"));
     }
+
+    #[test]
+    fn test_sdp_round_trip() {
+        let local = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 4000);
+        let public = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), 5000);
+        let candidate = IceCandidate::srflx(public, local, "s1".to_string(), 1);
+
+        let parsed = IceCandidate::from_sdp(&format!("a={}", candidate.to_sdp())).unwrap();
+        assert_eq!(parsed.candidate_type, IceCandidateType::Srflx);
+        assert_eq!(parsed.priority, candidate.priority);
+        assert_eq!(parsed.socket_addr(), Some(public));
+        assert_eq!(parsed.related_port, Some(4000));
+        assert!(IceCandidate::from_sdp("candidate:garbage").is_none());
+    }
+
+    #[test]
+    fn test_from_ice_agent_candidate() {
+        use crate::ice::{Candidate, CandidateType};
+
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), 5000);
+        let agent = Candidate { addr, candidate_type: CandidateType::ServerReflexive, priority: 90 };
+        let candidate = IceCandidate::from(&agent);
+
+        assert_eq!(candidate.candidate_type, IceCandidateType::Srflx);
+        assert_eq!(candidate.socket_addr(), Some(addr));
+        assert!(candidate.to_sdp().ends_with("typ srflx raddr 0.0.0.0 rport 0"));
+    }
 }
@@ -2,6 +2,7 @@
 //! 
 //! Handles SDP offer/answer exchange for WebRTC connection establishment.
 
+use super::ice::IceCandidate;
 use serde::{Deserialize, Serialize};
 
 /// SDP type
@@ -34,6 +35,27 @@ impl SessionDescription {
             sdp,
         }
     }
+
+    /// Describe a data channel session with the candidates gathered so far
+    pub fn for_data_channel(sdp_type: SdpType, session_id: u64, label: &str, candidates: &[IceCandidate]) -> Self {
+        let mut sdp = format!(
+            "v=0\r\no=- {} 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\n\
+             m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\nc=IN IP4 0.0.0.0\r\n\
+             a=mid:0\r\na=label:{}\r\n",
+            session_id, label
+        );
+        for candidate in candidates {
+            sdp.push_str(&format!("a={}\r\n", candidate.to_sdp()));
+        }
+        sdp.push_str("a=end-of-candidates\r\n");
+
+        Self { sdp_type, sdp }
+    }
+
+    /// Candidates listed in the description
+    pub fn candidates(&self) -> Vec<IceCandidate> {
+        self.sdp.lines().filter_map(IceCandidate::from_sdp).collect()
+    }
 }
 
 /// Signaling message for WebRTC
@@ -104,4 +126,16 @@ mod tests {
         let json = serde_json::to_string(&message).unwrap();
         assert!(json.contains("\"type\":\"sdp\""));
     }
+
+    #[test]
+    fn test_data_channel_description_lists_candidates() {
+        let addr = "192.168.1.7:5000".parse().unwrap();
+        let candidate = IceCandidate::host(addr, "host0".to_string(), 1);
+        let answer = SessionDescription::for_data_channel(SdpType::Answer, 42, "jetstream_proto", &[candidate]);
+
+        assert!(answer.sdp.contains("webrtc-datachannel"));
+        let candidates = answer.candidates();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].socket_addr(), Some(addr));
+    }
 }
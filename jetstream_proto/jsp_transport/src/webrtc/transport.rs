@@ -1,103 +1,457 @@
 //! WebRTC Transport Implementation
+//!
+//! `connect` offers a data channel to a peer through the signaling server and
+//! `accept` answers the first offer that arrives. ICE, DTLS and SCTP are
+//! provided by the `webrtc` crate, running on one UDP socket bound to
+//! `bind_addr`.
+//!
+//! Candidates are trickled as `Candidate` messages in SDP attribute form once
+//! the answer is in. Candidates an `IceAgent` signals (JSON) are accepted too.
 
-use super::WebRTCConfig;
+use super::config::{BundlePolicy, IceTransportPolicy};
 use super::data_channel::DataChannel;
-use super::ice::{IceGatherer, IceConnectionState};
+use super::ice::{IceCandidate, IceConnectionState, IceGatherer};
+use super::WebRTCConfig;
+use crate::signaling::{SignalingClient, SignalingMessage};
+use ::webrtc::api::setting_engine::SettingEngine;
+use ::webrtc::api::APIBuilder;
+use ::webrtc::data_channel::data_channel_init::RTCDataChannelInit;
+use ::webrtc::ice::candidate::CandidatePairState;
+use ::webrtc::ice::network_type::NetworkType;
+use ::webrtc::ice::udp_mux::{UDPMuxDefault, UDPMuxParams};
+use ::webrtc::ice::udp_network::UDPNetwork;
+use ::webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
+use ::webrtc::ice_transport::ice_candidate_type::RTCIceCandidateType;
+use ::webrtc::ice_transport::ice_connection_state::RTCIceConnectionState;
+use ::webrtc::ice_transport::ice_server::RTCIceServer;
+use ::webrtc::peer_connection::configuration::RTCConfiguration;
+use ::webrtc::peer_connection::policy::bundle_policy::RTCBundlePolicy;
+use ::webrtc::peer_connection::policy::ice_transport_policy::RTCIceTransportPolicy;
+use ::webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use ::webrtc::peer_connection::RTCPeerConnection;
+use ::webrtc::stats::StatsReportType;
+use anyhow::{anyhow, bail};
 use bytes::Bytes;
+use std::future::Future;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::Instant;
+
+/// Pause before repeating an offer to a peer that isn't registered yet
+const OFFER_RETRY_INTERVAL: Duration = Duration::from_millis(250);
 
 /// WebRTC transport for JetStreamProto
+#[derive(Clone)]
 pub struct WebRTCTransport {
     config: WebRTCConfig,
-    data_channel: Arc<Mutex<Option<DataChannel>>>,
+    local_addr: Arc<std::sync::Mutex<Option<SocketAddr>>>,
+    peer_connection: Arc<std::sync::Mutex<Option<Arc<RTCPeerConnection>>>>,
+    data_channel: Arc<std::sync::Mutex<Option<Arc<DataChannel>>>>,
     ice_gatherer: Arc<Mutex<IceGatherer>>,
-    state: Arc<Mutex<IceConnectionState>>,
+    /// Local candidates not yet trickled to the peer
+    pending_candidates: Arc<Mutex<Option<mpsc::UnboundedReceiver<IceCandidate>>>>,
+    state: Arc<std::sync::Mutex<IceConnectionState>>,
 }
 
 impl WebRTCTransport {
     /// Create a new WebRTC transport
     pub fn new(config: WebRTCConfig) -> Result<Self, Box<dyn std::error::Error>> {
         config.validate()?;
-        
+
         Ok(Self {
             config,
-            data_channel: Arc::new(Mutex::new(None)),
+            local_addr: Arc::new(std::sync::Mutex::new(None)),
+            peer_connection: Arc::new(std::sync::Mutex::new(None)),
+            data_channel: Arc::new(std::sync::Mutex::new(None)),
             ice_gatherer: Arc::new(Mutex::new(IceGatherer::new())),
-            state: Arc::new(Mutex::new(IceConnectionState::New)),
+            pending_candidates: Arc::new(Mutex::new(None)),
+            state: Arc::new(std::sync::Mutex::new(IceConnectionState::New)),
         })
     }
-    
+
     /// Initialize the WebRTC connection
+    ///
+    /// Binds the UDP socket and creates the peer connection on it. Candidates
+    /// are gathered once `connect` or `accept` sets the local description.
     pub async fn initialize(&self) -> Result<(), Box<dyn std::error::Error>> {
-        // Gather ICE candidates
-        let mut gatherer = self.ice_gatherer.lock().await;
-        gatherer.gather_host_candidates().await?;
-        
-        // Create data channel
-        let channel = DataChannel::new(
-            self.config.data_channel_label.clone(),
-            self.config.ordered,
-            self.config.max_retransmits,
-        );
-        
-        *self.data_channel.lock().await = Some(channel);
-        *self.state.lock().await = IceConnectionState::Checking;
-        
-        tracing::info!("WebRTC transport initialized");
+        let socket = UdpSocket::bind(&self.config.bind_addr).await?;
+        let local = socket.local_addr()?;
+
+        let mut settings = SettingEngine::default();
+        settings.set_network_types(vec![if local.is_ipv4() { NetworkType::Udp4 } else { NetworkType::Udp6 }]);
+        // Advertise the address we're bound to rather than every interface
+        if !local.ip().is_unspecified() {
+            settings.set_nat_1to1_ips(vec![local.ip().to_string()], RTCIceCandidateType::Host);
+        }
+        settings.set_udp_network(UDPNetwork::Muxed(UDPMuxDefault::new(UDPMuxParams::new(socket))));
+
+        let api = APIBuilder::new().with_setting_engine(settings).build();
+        let peer_connection = Arc::new(api.new_peer_connection(self.rtc_configuration()).await?);
+
+        let (candidate_tx, candidate_rx) = mpsc::unbounded_channel();
+        let gatherer = Arc::clone(&self.ice_gatherer);
+        peer_connection.on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
+            // `None` marks the end of gathering
+            let candidate = candidate
+                .and_then(|candidate| candidate.to_json().ok())
+                .and_then(|init| IceCandidate::from_sdp(&init.candidate));
+            let gatherer = Arc::clone(&gatherer);
+            let candidate_tx = candidate_tx.clone();
+            Box::pin(async move {
+                if let Some(candidate) = candidate {
+                    gatherer.lock().await.add_candidate(candidate.clone());
+                    let _ = candidate_tx.send(candidate);
+                }
+            })
+        }));
+
+        let state = Arc::clone(&self.state);
+        peer_connection.on_ice_connection_state_change(Box::new(move |rtc_state: RTCIceConnectionState| {
+            tracing::debug!("WebRTC ICE connection state: {}", rtc_state);
+            *state.lock().unwrap() = ice_state(rtc_state);
+            Box::pin(async {})
+        }));
+
+        *self.pending_candidates.lock().await = Some(candidate_rx);
+        *self.local_addr.lock().unwrap() = Some(local);
+        *self.peer_connection.lock().unwrap() = Some(peer_connection);
+        *self.state.lock().unwrap() = IceConnectionState::Checking;
+
+        tracing::info!(%local, "WebRTC transport initialized");
         Ok(())
     }
-    
+
+    /// Offer a data channel to `remote_peer` and return the selected remote address
+    ///
+    /// The offer is repeated until the peer has registered with the signaling
+    /// server; the answer, ICE and the DTLS/SCTP handshakes must complete
+    /// within `ice_timeout`.
+    pub async fn connect(&self, remote_peer: &str) -> anyhow::Result<SocketAddr> {
+        let peer_connection = self.prepare().await?;
+        let deadline = Instant::now() + self.config.ice_timeout;
+        let mut signaling = self.register(deadline).await?;
+
+        let init = RTCDataChannelInit {
+            ordered: Some(self.config.ordered),
+            max_retransmits: self.config.max_retransmits,
+            ..Default::default()
+        };
+        let rtc_channel = peer_connection.create_data_channel(&self.config.data_channel_label, Some(init)).await?;
+        let channel = Arc::new(DataChannel::attach(rtc_channel, self.config.ordered, self.config.max_retransmits));
+
+        let offer = peer_connection.create_offer(None).await?;
+        let offer_sdp = offer.sdp.clone();
+        peer_connection.set_local_description(offer).await?;
+
+        let answer = tokio::time::timeout_at(deadline, async {
+            loop {
+                let message = SignalingMessage::Offer { target: remote_peer.to_string(), sdp: offer_sdp.clone() };
+                signaling.send(message).await?;
+                loop {
+                    match signaling.recv().await? {
+                        SignalingMessage::Answer { target, sdp } if target == remote_peer => {
+                            return Ok::<_, anyhow::Error>(sdp);
+                        }
+                        SignalingMessage::Error { message } => {
+                            tracing::debug!(peer = remote_peer, "Offer not delivered: {}", message);
+                            tokio::time::sleep(OFFER_RETRY_INTERVAL).await;
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }).await.map_err(|_| anyhow!("No answer from {} within {:?}", remote_peer, self.config.ice_timeout))??;
+        peer_connection.set_remote_description(RTCSessionDescription::answer(answer)?).await?;
+
+        let opened = async move {
+            if !channel.opened().await {
+                bail!("Data channel closed before it opened");
+            }
+            Ok(channel)
+        };
+        let remote = self.establish(&peer_connection, &mut signaling, remote_peer, opened, deadline).await
+            .map_err(|e| self.fail(e))?;
+
+        tracing::info!(peer = remote_peer, %remote, "WebRTC data channel open");
+        Ok(remote)
+    }
+
+    /// Answer the first offer and return the selected remote address
+    pub async fn accept(&self) -> anyhow::Result<SocketAddr> {
+        let peer_connection = self.prepare().await?;
+        let mut signaling = self.register(Instant::now() + self.config.ice_timeout).await?;
+
+        let (channel_tx, mut channel_rx) = mpsc::unbounded_channel();
+        let (ordered, max_retransmits) = (self.config.ordered, self.config.max_retransmits);
+        peer_connection.on_data_channel(Box::new(move |rtc_channel| {
+            let _ = channel_tx.send(Arc::new(DataChannel::attach(rtc_channel, ordered, max_retransmits)));
+            Box::pin(async {})
+        }));
+
+        let (offerer, offer) = loop {
+            if let SignalingMessage::Offer { target, sdp } = signaling.recv().await? {
+                break (target, sdp);
+            }
+        };
+        let deadline = Instant::now() + self.config.ice_timeout;
+        peer_connection.set_remote_description(RTCSessionDescription::offer(offer)?).await?;
+        let answer = peer_connection.create_answer(None).await?;
+        let answer_sdp = answer.sdp.clone();
+        peer_connection.set_local_description(answer).await?;
+        signaling.send(SignalingMessage::Answer { target: offerer.clone(), sdp: answer_sdp }).await?;
+
+        let opened = async move {
+            let channel: Arc<DataChannel> = channel_rx.recv().await
+                .ok_or_else(|| anyhow!("Peer connection closed before a data channel arrived"))?;
+            if !channel.opened().await {
+                bail!("Data channel closed before it opened");
+            }
+            Ok(channel)
+        };
+        let remote = self.establish(&peer_connection, &mut signaling, &offerer, opened, deadline).await
+            .map_err(|e| self.fail(e))?;
+
+        tracing::info!(peer = %offerer, %remote, "WebRTC data channel open");
+        Ok(remote)
+    }
+
+    /// Add a candidate of the peer, e.g. one signaled out of band
+    ///
+    /// Needs the remote description, so only works once the offer or answer
+    /// has been applied.
+    pub async fn add_remote_candidate(&self, candidate: &IceCandidate) -> anyhow::Result<()> {
+        let peer_connection = self.peer_connection.lock().unwrap().clone()
+            .ok_or_else(|| anyhow!("WebRTC transport is not initialized"))?;
+        let init = RTCIceCandidateInit { candidate: candidate.to_sdp(), ..Default::default() };
+        peer_connection.add_ice_candidate(init).await?;
+        Ok(())
+    }
+
+    /// Local candidates gathered so far
+    pub async fn local_candidates(&self) -> Vec<IceCandidate> {
+        self.ice_gatherer.lock().await.candidates().to_vec()
+    }
+
     /// Send data over WebRTC
     pub async fn send(&self, data: &[u8]) -> Result<usize, Box<dyn std::error::Error>> {
-        let channel_guard = self.data_channel.lock().await;
-        let channel = channel_guard.as_ref()
-            .ok_or("Data channel not initialized")?;
-        
+        let channel = self.channel().ok_or("Data channel not initialized")?;
+
         let bytes = Bytes::copy_from_slice(data);
         let len = bytes.len();
         channel.send(bytes).await?;
-        
+
         Ok(len)
     }
-    
+
     /// Receive data from WebRTC
     pub async fn recv(&self, buf: &mut [u8]) -> Result<usize, Box<dyn std::error::Error>> {
-        let mut channel_guard = self.data_channel.lock().await;
-        let channel = channel_guard.as_mut()
-            .ok_or("Data channel not initialized")?;
-        
+        let channel = self.channel().ok_or("Data channel not initialized")?;
+
         let data = channel.recv().await
             .ok_or("Channel closed")?;
-        
+
         let len = data.len().min(buf.len());
         buf[..len].copy_from_slice(&data[..len]);
-        
+
         Ok(len)
     }
-    
+
+    /// Local address of the UDP socket, once initialized
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        *self.local_addr.lock().unwrap()
+    }
+
+    /// Address of the peer, once the data channel is open
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.channel().and_then(|channel| channel.remote_addr())
+    }
+
     /// Get ICE connection state
     pub async fn connection_state(&self) -> IceConnectionState {
-        *self.state.lock().await
+        *self.state.lock().unwrap()
     }
-    
+
     /// Close the WebRTC connection
     pub async fn close(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let mut channel_guard = self.data_channel.lock().await;
-        if let Some(channel) = channel_guard.as_mut() {
+        if let Some(channel) = self.channel() {
             channel.close();
         }
-        
-        *self.state.lock().await = IceConnectionState::Closed;
+        let peer_connection = self.peer_connection.lock().unwrap().clone();
+        if let Some(peer_connection) = peer_connection {
+            peer_connection.close().await?;
+        }
+
+        *self.state.lock().unwrap() = IceConnectionState::Closed;
         tracing::info!("WebRTC transport closed");
-        
+
         Ok(())
     }
+
+    fn channel(&self) -> Option<Arc<DataChannel>> {
+        self.data_channel.lock().unwrap().clone()
+    }
+
+    fn rtc_configuration(&self) -> RTCConfiguration {
+        let mut ice_servers = vec![RTCIceServer {
+            urls: self.config.stun_servers.clone(),
+            ..Default::default()
+        }];
+        ice_servers.extend(self.config.turn_servers.iter().map(|server| RTCIceServer {
+            urls: server.urls.clone(),
+            username: server.username.clone().unwrap_or_default(),
+            credential: server.credential.clone().unwrap_or_default(),
+            ..Default::default()
+        }));
+        ice_servers.retain(|server| !server.urls.is_empty());
+
+        RTCConfiguration {
+            ice_servers,
+            ice_transport_policy: match self.config.ice_transport_policy {
+                IceTransportPolicy::All => RTCIceTransportPolicy::All,
+                IceTransportPolicy::Relay => RTCIceTransportPolicy::Relay,
+            },
+            bundle_policy: match self.config.bundle_policy {
+                BundlePolicy::MaxBundle => RTCBundlePolicy::MaxBundle,
+                BundlePolicy::MaxCompat => RTCBundlePolicy::MaxCompat,
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Peer connection to run ICE on, initializing the transport first if needed
+    async fn prepare(&self) -> anyhow::Result<Arc<RTCPeerConnection>> {
+        let peer_connection = self.peer_connection.lock().unwrap().clone();
+        if let Some(peer_connection) = peer_connection {
+            return Ok(peer_connection);
+        }
+
+        self.initialize().await.map_err(|e| anyhow!("WebRTC initialization failed: {}", e))?;
+        self.peer_connection.lock().unwrap().clone()
+            .ok_or_else(|| anyhow!("WebRTC peer connection missing after initialization"))
+    }
+
+    /// Connect to the signaling server and wait until it confirms our peer ID
+    async fn register(&self, deadline: Instant) -> anyhow::Result<SignalingClient> {
+        let server = self.config.signaling_server.as_deref()
+            .ok_or_else(|| anyhow!("WebRTC needs a signaling server"))?;
+        let peer_id = self.config.peer_id.clone().unwrap_or_else(|| {
+            let mut id = [0u8; 8];
+            getrandom::getrandom(&mut id).expect("Failed to generate peer ID");
+            format!("jsp-{:016x}", u64::from_be_bytes(id))
+        });
+
+        tokio::time::timeout_at(deadline, async {
+            let mut client = SignalingClient::connect(server, peer_id).await?;
+            loop {
+                if let SignalingMessage::Registered = client.recv().await? {
+                    return Ok(client);
+                }
+            }
+        }).await.map_err(|_| anyhow!("Signaling server {} did not confirm registration", server))?
+    }
+
+    /// Trickle candidates with `peer` until `opened` yields the open data
+    /// channel, then make it the transport's channel
+    async fn establish(
+        &self,
+        peer_connection: &RTCPeerConnection,
+        signaling: &mut SignalingClient,
+        peer: &str,
+        opened: impl Future<Output = anyhow::Result<Arc<DataChannel>>>,
+        deadline: Instant,
+    ) -> anyhow::Result<SocketAddr> {
+        let mut local_candidates = self.pending_candidates.lock().await.take()
+            .ok_or_else(|| anyhow!("WebRTC transport is already connected"))?;
+        tokio::pin!(opened);
+
+        let channel = tokio::time::timeout_at(deadline, async {
+            loop {
+                tokio::select! {
+                    channel = &mut opened => return channel,
+                    Some(candidate) = local_candidates.recv() => {
+                        let message = SignalingMessage::Candidate { target: peer.to_string(), candidate: candidate.to_sdp() };
+                        signaling.send(message).await?;
+                    }
+                    message = signaling.recv() => {
+                        if let SignalingMessage::Candidate { target, candidate } = message? {
+                            if target == peer {
+                                self.add_signaled_candidate(&candidate).await;
+                            }
+                        }
+                    }
+                }
+            }
+        }).await.map_err(|_| anyhow!("Data channel to {} did not open within {:?}", peer, self.config.ice_timeout))??;
+
+        let remote = selected_remote(peer_connection).await
+            .ok_or_else(|| anyhow!("Data channel to {} is open but no candidate pair is selected", peer))?;
+        channel.set_remote_addr(remote);
+        *self.data_channel.lock().unwrap() = Some(channel);
+        *self.state.lock().unwrap() = IceConnectionState::Connected;
+        Ok(remote)
+    }
+
+    /// Add a candidate from a `Candidate` message, in SDP form or as sent by an `IceAgent`
+    async fn add_signaled_candidate(&self, candidate: &str) {
+        let parsed = IceCandidate::from_sdp(candidate).or_else(|| {
+            serde_json::from_str::<crate::ice::Candidate>(candidate).ok()
+                .map(|candidate| IceCandidate::from(&candidate))
+        });
+
+        match parsed {
+            Some(parsed) => {
+                if let Err(e) = self.add_remote_candidate(&parsed).await {
+                    tracing::debug!("Ignoring remote candidate {}: {}", candidate, e);
+                }
+            }
+            None => tracing::debug!("Ignoring malformed remote candidate {}", candidate),
+        }
+    }
+
+    fn fail(&self, error: anyhow::Error) -> anyhow::Error {
+        *self.state.lock().unwrap() = IceConnectionState::Failed;
+        error
+    }
+}
+
+fn ice_state(state: RTCIceConnectionState) -> IceConnectionState {
+    match state {
+        RTCIceConnectionState::Checking => IceConnectionState::Checking,
+        RTCIceConnectionState::Connected => IceConnectionState::Connected,
+        RTCIceConnectionState::Completed => IceConnectionState::Completed,
+        RTCIceConnectionState::Failed => IceConnectionState::Failed,
+        RTCIceConnectionState::Disconnected => IceConnectionState::Disconnected,
+        RTCIceConnectionState::Closed => IceConnectionState::Closed,
+        _ => IceConnectionState::New,
+    }
+}
+
+/// Peer end of the candidate pair ICE selected, preferring the nominated one
+async fn selected_remote(peer_connection: &RTCPeerConnection) -> Option<SocketAddr> {
+    let stats = peer_connection.get_stats().await;
+    let pair = stats.reports.values()
+        .filter_map(|report| match report {
+            StatsReportType::CandidatePair(pair) if pair.state == CandidatePairState::Succeeded => Some(pair),
+            _ => None,
+        })
+        .max_by_key(|pair| pair.nominated)?;
+
+    match stats.reports.get(&pair.remote_candidate_id) {
+        Some(StatsReportType::RemoteCandidate(candidate)) => {
+            Some(SocketAddr::new(candidate.ip.parse().ok()?, candidate.port))
+        }
+        _ => None,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::signaling::SignalingServer;
 
     #[tokio::test]
     async fn test_webrtc_transport_creation() {
@@ -105,16 +459,57 @@ mod tests {
         let transport = WebRTCTransport::new(config);
         assert!(transport.is_ok());
     }
-    
+
     #[tokio::test]
     async fn test_webrtc_transport_initialize() {
         let config = WebRTCConfig::default();
         let transport = WebRTCTransport::new(config).unwrap();
-        
+
         let result = transport.initialize().await;
         assert!(result.is_ok());
-        
+
         let state = transport.connection_state().await;
         assert_eq!(state, IceConnectionState::Checking);
     }
+
+    #[tokio::test]
+    async fn test_data_channel_between_two_peers() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let signaling = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let server = SignalingServer::new(signaling.clone());
+        tokio::spawn(async move { server.run().await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let config = |peer_id: &str| WebRTCConfig {
+            bind_addr: "127.0.0.1:0".to_string(),
+            signaling_server: Some(signaling.clone()),
+            peer_id: Some(peer_id.to_string()),
+            ..WebRTCConfig::default()
+        };
+        let answerer = WebRTCTransport::new(config("answerer")).unwrap();
+        let offerer = WebRTCTransport::new(config("offerer")).unwrap();
+
+        let accepting = answerer.clone();
+        let accepted = tokio::spawn(async move { accepting.accept().await });
+        let remote = offerer.connect("answerer").await.unwrap();
+        let accepted = accepted.await.unwrap().unwrap();
+
+        assert_eq!(Some(remote), answerer.local_addr());
+        assert_eq!(Some(accepted), offerer.local_addr());
+        assert_eq!(offerer.connection_state().await, IceConnectionState::Connected);
+        assert!(!offerer.local_candidates().await.is_empty());
+
+        offerer.send(b"ping").await.unwrap();
+        let mut buf = [0u8; 16];
+        let len = answerer.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"ping");
+
+        answerer.send(b"pong").await.unwrap();
+        let len = offerer.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"pong");
+
+        offerer.close().await.unwrap();
+        answerer.close().await.unwrap();
+    }
 }
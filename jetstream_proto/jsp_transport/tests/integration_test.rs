@@ -1,4 +1,5 @@
 use jsp_transport::connection::Connection;
+use jsp_transport::error::TransportError;
 use jsp_transport::server::Server;
 use jsp_transport::config::{ConnectionConfig, ServerConfig};
 use jsp_core::types::control::CloseReason;
@@ -113,7 +114,10 @@ async fn test_rate_limiting() -> Result<()> {
     // 6th message should fail due to rate limit
     let result = client.send_on_stream(stream_id, data).await;
     assert!(result.is_err());
-    assert!(result.unwrap_err().to_string().contains("Rate limit"));
+    let err = result.unwrap_err();
+    assert!(err.to_string().contains("Rate limit"));
+    assert!(matches!(err, TransportError::RateLimited), "{:?}", err);
+    assert!(err.is_transient());
     
     Ok(())
 }
@@ -150,6 +154,7 @@ fn test_session_resumption() -> Result<()> {
         encrypted_state: vec![1, 2, 3, 4, 5],
         created_at: now,
         lifetime: 3600,
+        resumption_secret: None,
     };
     
     // Verify ticket structure
@@ -241,3 +246,3157 @@ fn test_configuration() {
     
     assert_eq!(server_config.global_rate_limit_messages, Some(10000));
 }
+
+/// Test transport racing: UDP is silently dropped, so TCP must win
+#[tokio::test]
+async fn test_transport_race_tcp_wins_when_udp_blocked() -> Result<()> {
+    use jsp_core::session::Session;
+    use jsp_transport::config::ConnectStrategy;
+    use jsp_transport::tcp_transport::TcpServer;
+    use jsp_transport::transport_selector::TransportType;
+
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let tcp_server = TcpServer::bind("127.0.0.1:0".parse()?).await?;
+    let addr = tcp_server.local_addr();
+
+    // Dead UDP port: bound but never answers, so UDP and QUIC attempts hang
+    let _blackhole = tokio::net::UdpSocket::bind(addr).await?;
+
+    let server_task = tokio::spawn(async move {
+        let mut transport = tcp_server.accept().await.unwrap();
+        let mut buf = vec![0u8; 4096];
+        let len = transport.recv(&mut buf).await.unwrap();
+
+        let mut session = Session::new();
+        let hello = session.process_client_hello(&buf[..len]).unwrap();
+        let (server_hello, kyber_shared) = session
+            .generate_server_hello(1, 0x1303, &hello.kyber_public_key, &hello.supported_formats)
+            .unwrap();
+        session.derive_keys_from_client_hello(&hello.public_key, Some(&kyber_shared));
+        transport.send(&server_hello).await.unwrap();
+
+        // Keep the connection open until the client is done
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    });
+
+    let config = ConnectionConfig::builder()
+        .connect_strategy(ConnectStrategy::Race)
+        .connect_timeout(Duration::from_secs(3))
+        .build();
+
+    let start = std::time::Instant::now();
+    let mut client = Connection::connect_with_config(&addr.to_string(), config).await?;
+    assert!(start.elapsed() < Duration::from_secs(3));
+
+    assert_eq!(client.active_transport(), TransportType::Tcp);
+    assert_eq!(client.session_id(), 1);
+
+    // Handshake already happened during the race
+    client.handshake().await?;
+
+    server_task.await?;
+    Ok(())
+}
+
+/// Test that a stream transport waits past one retry interval for a slow ServerHello
+#[tokio::test]
+async fn test_transport_race_waits_for_slow_tcp_server_hello() -> Result<()> {
+    use jsp_core::session::Session;
+    use jsp_transport::config::ConnectStrategy;
+    use jsp_transport::tcp_transport::TcpServer;
+    use jsp_transport::transport_selector::TransportType;
+
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let tcp_server = TcpServer::bind("127.0.0.1:0".parse()?).await?;
+    let addr = tcp_server.local_addr();
+    let _blackhole = tokio::net::UdpSocket::bind(addr).await?;
+
+    let server_task = tokio::spawn(async move {
+        let mut transport = tcp_server.accept().await.unwrap();
+        let mut buf = vec![0u8; 4096];
+        let len = transport.recv(&mut buf).await.unwrap();
+
+        // Answer only after two and a half retry intervals
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let mut session = Session::new();
+        let hello = session.process_client_hello(&buf[..len]).unwrap();
+        let (server_hello, kyber_shared) = session
+            .generate_server_hello(1, 0x1303, &hello.kyber_public_key, &hello.supported_formats)
+            .unwrap();
+        session.derive_keys_from_client_hello(&hello.public_key, Some(&kyber_shared));
+        transport.send(&server_hello).await.unwrap();
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    });
+
+    let config = ConnectionConfig::builder()
+        .connect_strategy(ConnectStrategy::Race)
+        .connect_timeout(Duration::from_secs(3))
+        .handshake_retry_interval(Duration::from_millis(100))
+        .handshake_max_retries(4)
+        .build();
+
+    let client = Connection::connect_with_config(&addr.to_string(), config).await?;
+    assert_eq!(client.active_transport(), TransportType::Tcp);
+    assert_eq!(client.session_id(), 1);
+
+    server_task.await?;
+    Ok(())
+}
+
+/// Test that a 1 MB payload is fragmented and reassembled into one message
+#[tokio::test]
+async fn test_large_message_fragmentation() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+
+    let config = ConnectionConfig::builder()
+        .rate_limit_messages(10_000)
+        .rate_limit_bytes(100_000_000)
+        .heartbeat_interval(Duration::from_secs(30))
+        .build();
+
+    let server_config = config.clone();
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen_with_config("127.0.0.1:9010", server_config).await.unwrap();
+        loop {
+            let packets = server.recv().await.unwrap();
+            if let Some(message) = packets.into_iter().next() {
+                return message;
+            }
+        }
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9010", config).await?;
+    client.handshake().await?;
+
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    let payload: Vec<u8> = (0..1_048_576u32).map(|i| (i % 251) as u8).collect();
+    client.send_on_stream(stream_id, &payload).await?;
+
+    let (received_stream, data) = timeout(Duration::from_secs(10), server_task).await??;
+    assert_eq!(received_stream, stream_id);
+    assert_eq!(data.len(), payload.len());
+    assert_eq!(&data[..], &payload[..]);
+
+    Ok(())
+}
+
+/// Test that waiting sends push 10 MB through a small congestion window without errors
+#[tokio::test]
+async fn test_backpressure_send_waits_for_window() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+
+    const TOTAL: usize = 10 * 1024 * 1024;
+    const CHUNK: usize = 64 * 1024;
+
+    // An MSS of 100 bytes gives an initial window of 1000, far below one chunk
+    let config = ConnectionConfig::builder()
+        .rate_limit_messages(100_000)
+        .rate_limit_bytes(1_000_000_000)
+        .heartbeat_interval(Duration::from_secs(30))
+        .mss(100)
+        .build();
+
+    // The server doesn't read (and so doesn't ACK) until told to
+    let (ack_tx, ack_rx) = tokio::sync::oneshot::channel::<()>();
+    let server_config = config.clone();
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen_with_config("127.0.0.1:9011", server_config).await.unwrap();
+        ack_rx.await.unwrap();
+        let mut received = 0usize;
+        while received < TOTAL {
+            for (_stream_id, data) in server.recv().await.unwrap() {
+                received += data.len();
+            }
+            server.flush_acks().await.unwrap();
+        }
+        received
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9011", config).await?;
+    client.handshake().await?;
+
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    let chunk = vec![0x5Au8; CHUNK];
+    assert!(client.metrics().congestion_window < CHUNK as u64);
+
+    // The first chunk goes out on the empty window and overfills it
+    client.send_on_stream_wait(stream_id, &chunk, Some(Duration::from_secs(1))).await?;
+
+    // Nothing is ACKed yet, so the next chunk really waits
+    let blocked = client.send_on_stream_wait(stream_id, &chunk, Some(Duration::from_millis(300))).await;
+    assert!(matches!(blocked, Err(TransportError::SendTimeout)), "{:?}", blocked);
+
+    // Once the server ACKs, the same send completes, and so does the rest
+    ack_tx.send(()).unwrap();
+    for _ in 1..TOTAL / CHUNK {
+        client.send_on_stream_wait(stream_id, &chunk, Some(Duration::from_secs(10))).await?;
+    }
+
+    let received = timeout(Duration::from_secs(30), server_task).await??;
+    assert_eq!(received, TOTAL);
+
+    Ok(())
+}
+
+/// A connection configured for BBR reports its state machine through `metrics()`
+#[tokio::test]
+async fn test_bbr_state_progression_in_metrics() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::congestion::{CongestionAlgorithm, CongestionState};
+
+    const TOTAL: usize = 2_000_000;
+    const CHUNK: usize = 1000;
+
+    let config = ConnectionConfig::builder()
+        .congestion_algorithm(CongestionAlgorithm::Bbr)
+        .mss(1000)
+        .rate_limit_messages(100_000)
+        .rate_limit_bytes(1_000_000_000)
+        .heartbeat_interval(Duration::from_secs(30))
+        .build();
+
+    let server_config = config.clone();
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen_with_config("127.0.0.1:9043", server_config).await.unwrap();
+        let mut received = 0usize;
+        while received < TOTAL {
+            for (_stream_id, data) in server.recv().await.unwrap() {
+                received += data.len();
+            }
+            server.flush_acks().await.unwrap();
+        }
+        // Keep the connection open until the client is done
+        server
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9043", config).await?;
+    client.handshake().await?;
+
+    let metrics = client.metrics();
+    assert_eq!(metrics.congestion_state, Some(CongestionState::Startup));
+    assert_eq!(metrics.congestion_window, 10 * 1000);
+
+    let mut states = vec![CongestionState::Startup];
+    let mut record = |state: Option<CongestionState>| {
+        let state = state.expect("connections report their congestion state");
+        if states.last() != Some(&state) {
+            states.push(state);
+        }
+    };
+
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    let chunk = vec![0xB5u8; CHUNK];
+    for _ in 0..TOTAL / CHUNK {
+        client.send_on_stream_wait(stream_id, &chunk, Some(Duration::from_secs(10))).await?;
+        record(client.metrics().congestion_state);
+    }
+    let _server = timeout(Duration::from_secs(30), server_task).await??;
+
+    // Collect the last ACKs
+    for _ in 0..10 {
+        let _ = timeout(Duration::from_millis(50), client.recv()).await;
+        record(client.metrics().congestion_state);
+    }
+
+    // Startup ends once the bandwidth estimate plateaus and is never re-entered
+    assert!(states.contains(&CongestionState::ProbeBW), "{:?}", states);
+    assert!(!states[1..].contains(&CongestionState::Startup), "{:?}", states);
+
+    Ok(())
+}
+
+/// Accept one connection and return whether it resumed plus the first message
+fn spawn_resumption_server(addr: &'static str, config: ConnectionConfig) -> tokio::task::JoinHandle<(bool, Vec<u8>)> {
+    tokio::spawn(async move {
+        let mut server = Connection::listen_with_config(addr, config).await.unwrap();
+        loop {
+            let packets = server.recv().await.unwrap();
+            if let Some((_stream_id, data)) = packets.into_iter().next() {
+                server.flush_acks().await.unwrap();
+                return (server.is_resumed(), data.to_vec());
+            }
+        }
+    })
+}
+
+/// Test that resuming from a persisted ticket delivers early data without a round trip
+#[tokio::test]
+async fn test_session_ticket_resumption_saves_round_trip() -> Result<()> {
+    use jsp_core::types::control::SessionTicket;
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::ticket_store::TicketStore;
+
+    let store = TicketStore::new();
+    let server_config = ConnectionConfig::builder().ticket_store(store.clone()).build();
+
+    // Full handshake issues a ticket
+    let server_task = spawn_resumption_server("127.0.0.1:9012", server_config.clone());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9012", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+    let full_round_trips = client.handshake_round_trips();
+    assert_eq!(full_round_trips, 1);
+    assert!(!client.is_resumed());
+
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    client.send_on_stream(stream_id, b"full").await?;
+
+    let mut ticket = None;
+    for _ in 0..20 {
+        let _ = timeout(Duration::from_millis(100), client.recv()).await;
+        ticket = client.session_ticket();
+        if ticket.is_some() {
+            break;
+        }
+    }
+    let ticket = ticket.expect("server should issue a session ticket");
+
+    let (resumed, data) = timeout(Duration::from_secs(5), server_task).await??;
+    assert!(!resumed);
+    assert_eq!(data, b"full");
+    assert_eq!(store.len(), 1);
+
+    // Persist and reload the ticket as a client restarting would
+    let ticket = SessionTicket::from_bytes(&ticket.to_bytes()?)?;
+
+    // Resumed handshake sends data before the ServerHello arrives
+    let server_task = spawn_resumption_server("127.0.0.1:9013", server_config);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::resume_with_ticket("127.0.0.1:9013", ticket).await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    client.send_on_stream(stream_id, b"early").await?;
+    client.handshake().await?;
+
+    let (resumed, data) = timeout(Duration::from_secs(5), server_task).await??;
+    assert!(resumed);
+    assert!(client.is_resumed());
+    assert_eq!(data, b"early");
+    assert!(client.handshake_round_trips() < full_round_trips);
+
+    // The redeemed ticket is gone; only the newly issued one remains
+    assert_eq!(store.len(), 1);
+
+    Ok(())
+}
+
+/// Test that sealed early data opens on a resumed session with its own key
+#[tokio::test]
+async fn test_resumption_with_sealed_packets() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::ticket_store::TicketStore;
+
+    let config = ConnectionConfig::builder().enable_key_updates(true).build();
+    let server_config = ConnectionConfig::builder()
+        .enable_key_updates(true)
+        .ticket_store(TicketStore::new())
+        .build();
+
+    let server_task = spawn_resumption_server("127.0.0.1:9079", server_config.clone());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9079", config.clone()).await?;
+    client.handshake().await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    client.send_on_stream(stream_id, b"full").await?;
+
+    let mut ticket = None;
+    for _ in 0..20 {
+        let _ = timeout(Duration::from_millis(100), client.recv()).await;
+        ticket = client.session_ticket();
+        if ticket.is_some() {
+            break;
+        }
+    }
+    let ticket = ticket.expect("server should issue a session ticket");
+    assert!(ticket.resumption_secret.is_some());
+    timeout(Duration::from_secs(5), server_task).await??;
+
+    let server_task = spawn_resumption_server("127.0.0.1:9079", server_config);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let config = ConnectionConfig { session_ticket: Some(ticket), ..config };
+    let mut client = Connection::connect_with_config("127.0.0.1:9079", config).await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    client.send_on_stream(stream_id, b"early").await?;
+    client.handshake().await?;
+
+    let (resumed, data) = timeout(Duration::from_secs(5), server_task).await??;
+    assert!(resumed);
+    assert!(client.is_resumed());
+    assert_eq!(data, b"early");
+
+    Ok(())
+}
+
+/// Test that a ticket passed to `connect_with_config` resumes without a key exchange
+#[tokio::test]
+async fn test_ticket_resumption_skips_key_exchange() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_core::types::handshake::{ClientHello, ServerHello};
+    use jsp_transport::ticket_store::TicketStore;
+    use std::sync::{Arc, Mutex};
+
+    let server_config = ConnectionConfig::builder().ticket_store(TicketStore::new()).build();
+
+    // Full handshake issues a ticket
+    let server_task = spawn_resumption_server("127.0.0.1:9027", server_config.clone());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9027", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    client.send_on_stream(stream_id, b"full").await?;
+
+    let mut ticket = None;
+    for _ in 0..20 {
+        let _ = timeout(Duration::from_millis(100), client.recv()).await;
+        ticket = client.session_ticket();
+        if ticket.is_some() {
+            break;
+        }
+    }
+    let ticket = ticket.expect("server should issue a session ticket");
+    timeout(Duration::from_secs(5), server_task).await??;
+
+    // Reconnect through a proxy recording the handshake messages
+    let server_task = spawn_resumption_server("127.0.0.1:9028", server_config);
+    let client_hellos = Arc::new(Mutex::new(Vec::new()));
+    let server_hellos = Arc::new(Mutex::new(Vec::new()));
+    let (client_tap, server_tap) = (Arc::clone(&client_hellos), Arc::clone(&server_hellos));
+    spawn_proxy("127.0.0.1:9029", "127.0.0.1:9028".parse()?, Duration::ZERO, move |from_client, packet| {
+        if from_client {
+            if let Ok(hello) = serde_cbor::from_slice::<ClientHello>(packet) {
+                client_tap.lock().unwrap().push(hello);
+            }
+        } else if let Ok(hello) = serde_cbor::from_slice::<ServerHello>(packet) {
+            server_tap.lock().unwrap().push(hello);
+        }
+        true
+    }).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let config = ConnectionConfig::builder().session_ticket(ticket).build();
+    let mut client = Connection::connect_with_config("127.0.0.1:9029", config).await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    client.send_on_stream(stream_id, b"early").await?;
+    client.handshake().await?;
+
+    let (resumed, data) = timeout(Duration::from_secs(5), server_task).await??;
+    assert!(resumed);
+    assert!(client.is_resumed());
+    assert_eq!(data, b"early");
+
+    // One ClientHello/ServerHello exchange and no Kyber ciphertext in it
+    let client_hellos = client_hellos.lock().unwrap();
+    let server_hellos = server_hellos.lock().unwrap();
+    assert_eq!(client_hellos.len(), 1);
+    // The ticket is offered without the client's copy of its secret
+    let offered = client_hellos[0].session_ticket.as_ref().expect("ticket should be offered");
+    assert!(offered.resumption_secret.is_none());
+    assert_eq!(server_hellos.len(), 1);
+    assert!(server_hellos[0].resumed);
+    assert!(server_hellos[0].kyber_ciphertext.is_empty());
+    // The early data didn't wait for that exchange
+    assert_eq!(client.handshake_round_trips(), 0);
+
+    Ok(())
+}
+
+/// Test that an unknown ticket falls back to a full handshake and early data is resent
+#[tokio::test]
+async fn test_invalid_session_ticket_falls_back() -> Result<()> {
+    use jsp_core::types::control::SessionTicket;
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::ticket_store::TicketStore;
+
+    let server_config = ConnectionConfig::builder().ticket_store(TicketStore::new()).build();
+    let server_task = spawn_resumption_server("127.0.0.1:9014", server_config);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let forged = SessionTicket {
+        ticket_id: [7u8; 32],
+        encrypted_state: vec![7u8; 32],
+        created_at: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs(),
+        lifetime: 3600,
+        resumption_secret: Some([7u8; 32]),
+    };
+
+    let mut client = Connection::resume_with_ticket("127.0.0.1:9014", forged).await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    client.send_on_stream(stream_id, b"retry me").await?;
+    client.handshake().await?;
+
+    assert!(!client.is_resumed());
+    assert_eq!(client.handshake_round_trips(), 1);
+
+    let (resumed, data) = timeout(Duration::from_secs(5), server_task).await??;
+    assert!(!resumed);
+    assert_eq!(data, b"retry me");
+
+    Ok(())
+}
+
+/// Test that a stream FIN is delivered after the stream's data and blocks further sends
+#[tokio::test]
+async fn test_stream_close_ordering() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::events::ConnectionEvent;
+
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen("127.0.0.1:9015").await.unwrap();
+        let mut events = Vec::new();
+        while !matches!(events.last(), Some(ConnectionEvent::StreamFinished(_))) {
+            events.extend(server.recv_events().await.unwrap());
+        }
+        server.flush_acks().await.unwrap();
+        // Hand the connection back so it stays alive to send its FIN
+        (events, server)
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9015", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    for message in [&b"one"[..], b"two", b"three"] {
+        client.send_on_stream(stream_id, message).await?;
+    }
+    client.close_stream(stream_id).await?;
+
+    // The stream is closed for sending and cannot be closed twice
+    assert!(client.send_on_stream(stream_id, b"four").await.is_err());
+    assert!(client.close_stream(stream_id).await.is_err());
+
+    let (events, _server) = timeout(Duration::from_secs(5), server_task).await??;
+    assert_eq!(events, vec![
+        ConnectionEvent::DataReceived { stream_id, data: bytes::Bytes::from_static(b"one") },
+        ConnectionEvent::DataReceived { stream_id, data: bytes::Bytes::from_static(b"two") },
+        ConnectionEvent::DataReceived { stream_id, data: bytes::Bytes::from_static(b"three") },
+        ConnectionEvent::StreamFinished(stream_id),
+    ]);
+
+    // The server answers with its own FIN, releasing the stream on both sides
+    let mut reply = Vec::new();
+    while reply.is_empty() {
+        reply = timeout(Duration::from_secs(2), client.recv_events()).await??;
+    }
+    assert_eq!(reply, vec![ConnectionEvent::StreamFinished(stream_id)]);
+
+    Ok(())
+}
+
+/// Test that a client migration and close are surfaced as events on the server
+#[tokio::test]
+async fn test_peer_migration_event() -> Result<()> {
+    use jsp_transport::events::ConnectionEvent;
+
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen("127.0.0.1:9016").await.unwrap();
+        let mut events = Vec::new();
+        loop {
+            let event = server.next_event().await.unwrap();
+            let closed = matches!(event, ConnectionEvent::Closed { .. });
+            events.push(event);
+            if closed {
+                break;
+            }
+        }
+        events
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9016", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+    let old_addr = client.local_addr()?;
+
+    client.migrate("127.0.0.1:0").await?;
+    assert_eq!(client.local_addr()?, old_addr);
+    // The switch happens once the server's answer on the new path is read
+    while client.is_migrating() {
+        let _ = timeout(Duration::from_millis(50), client.next_event()).await;
+    }
+    let new_addr = client.local_addr()?;
+    assert_ne!(old_addr, new_addr);
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    client.close(CloseReason::Normal, Some("bye".to_string())).await?;
+
+    let events = timeout(Duration::from_secs(5), server_task).await??;
+    assert_eq!(events.len(), 2);
+    match &events[0] {
+        // The client socket is bound to a wildcard address, so compare ports
+        ConnectionEvent::PeerMigrated { old, new } => {
+            assert_eq!(old.port(), old_addr.port());
+            assert_eq!(new.port(), new_addr.port());
+        }
+        other => panic!("expected PeerMigrated, got {:?}", other),
+    }
+    assert_eq!(events[1], ConnectionEvent::Closed { reason: CloseReason::Normal, message: Some("bye".to_string()) });
+
+    Ok(())
+}
+
+/// Relay UDP between clients on `listen` and `upstream`, delaying each datagram
+///
+/// Packets for which `forward(from_client, packet)` returns false are dropped;
+/// replies go to wherever the last forwarded client packet came from.
+async fn spawn_proxy<F>(listen: &str, upstream: std::net::SocketAddr, delay: Duration, forward: F) -> Result<()>
+where
+    F: Fn(bool, &[u8]) -> bool + Send + Sync + 'static,
+{
+    spawn_rewriting_proxy(listen, upstream, delay, move |from_client, packet| {
+        if forward(from_client, packet) { vec![packet.to_vec()] } else { Vec::new() }
+    }).await
+}
+
+/// Like `spawn_proxy`, but each packet is replaced by the datagrams `rewrite` returns
+async fn spawn_rewriting_proxy<F>(listen: &str, upstream: std::net::SocketAddr, delay: Duration, rewrite: F) -> Result<()>
+where
+    F: Fn(bool, &[u8]) -> Vec<Vec<u8>> + Send + Sync + 'static,
+{
+    spawn_delaying_proxy(listen, upstream, std::sync::Arc::new(std::sync::Mutex::new(delay)), rewrite).await
+}
+
+/// Like `spawn_rewriting_proxy`, with a delay the test can change as it runs
+async fn spawn_delaying_proxy<F>(listen: &str, upstream: std::net::SocketAddr, delay: std::sync::Arc<std::sync::Mutex<Duration>>, rewrite: F) -> Result<()>
+where
+    F: Fn(bool, &[u8]) -> Vec<Vec<u8>> + Send + Sync + 'static,
+{
+    use std::sync::{Arc, Mutex};
+    use tokio::net::UdpSocket;
+
+    let front = Arc::new(UdpSocket::bind(listen).await?);
+    let back = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    let client = Arc::new(Mutex::new(None));
+    let rewrite = Arc::new(rewrite);
+
+    let (front_rx, back_tx, client_rx, rewrite_up, delay_up) = (Arc::clone(&front), Arc::clone(&back), Arc::clone(&client), Arc::clone(&rewrite), Arc::clone(&delay));
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 65536];
+        while let Ok((len, src)) = front_rx.recv_from(&mut buf).await {
+            let packets = rewrite_up(true, &buf[..len]);
+            if packets.is_empty() {
+                continue;
+            }
+            *client_rx.lock().unwrap() = Some(src);
+            let socket = Arc::clone(&back_tx);
+            let delay = *delay_up.lock().unwrap();
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                for data in packets {
+                    let _ = socket.send_to(&data, upstream).await;
+                }
+            });
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 65536];
+        while let Ok((len, _)) = back.recv_from(&mut buf).await {
+            let packets = rewrite(false, &buf[..len]);
+            if packets.is_empty() {
+                continue;
+            }
+            let dest = match *client.lock().unwrap() {
+                Some(dest) => dest,
+                None => continue,
+            };
+            let socket = Arc::clone(&front);
+            let delay = *delay.lock().unwrap();
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                for data in packets {
+                    let _ = socket.send_to(&data, dest).await;
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+/// Test that ACKs over a delayed path produce a plausible RTT in the metrics
+#[tokio::test]
+async fn test_ack_rtt_metrics() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+
+    const MESSAGES: usize = 5;
+    let one_way = Duration::from_millis(20);
+
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen("127.0.0.1:9017").await.unwrap();
+        let mut received = 0;
+        while received < MESSAGES {
+            received += server.recv().await.unwrap().len();
+            server.flush_acks().await.unwrap();
+        }
+        // Keep the connection open until the client is done
+        server
+    });
+
+    spawn_proxy("127.0.0.1:9018", "127.0.0.1:9017".parse()?, one_way, |_, _| true).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9018", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    for _ in 0..MESSAGES {
+        client.send_on_stream(stream_id, b"ping").await?;
+    }
+
+    // Read ACKs until every message has been sampled
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    while client.metrics().rtt_p95_ms == 0.0 && tokio::time::Instant::now() < deadline {
+        let _ = timeout(Duration::from_millis(200), client.recv_events()).await;
+    }
+
+    let snapshot = client.metrics();
+    let round_trip_ms = 2.0 * one_way.as_millis() as f64;
+    assert!(snapshot.rtt_p50_ms >= round_trip_ms, "p50 {} ms", snapshot.rtt_p50_ms);
+    assert!(snapshot.rtt_p95_ms < 1000.0, "p95 {} ms", snapshot.rtt_p95_ms);
+    assert!(snapshot.rtt_ms >= round_trip_ms as u64);
+
+    let _server = timeout(Duration::from_secs(5), server_task).await??;
+    Ok(())
+}
+
+/// Wait until the server has validated every subflow's address
+async fn subflows_validated(mptcp: &jsp_transport::mptcp::MptcpManager) -> Result<()> {
+    timeout(Duration::from_secs(5), async {
+        while mptcp.usable_subflow_count() < mptcp.subflow_count() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await?;
+    Ok(())
+}
+
+/// Test that round-robin MPTCP spreads packets over two loopback subflows
+#[tokio::test]
+async fn test_mptcp_round_robin_subflows() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::mptcp::{MptcpConfig, SchedulerAlgorithm};
+
+    const MESSAGES: usize = 10;
+
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen("127.0.0.1:9019").await.unwrap();
+        let mut received = Vec::new();
+        while received.len() < MESSAGES {
+            for (_stream_id, data) in server.recv().await.unwrap() {
+                received.push(data.to_vec());
+            }
+            server.flush_acks().await.unwrap();
+        }
+        received
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let config = ConnectionConfig::builder()
+        .mptcp_config(MptcpConfig {
+            enabled: true,
+            max_subflows: 2,
+            scheduler_algo: SchedulerAlgorithm::RoundRobin,
+        })
+        .build();
+    let mut client = Connection::connect_with_config("127.0.0.1:9019", config).await?;
+    client.handshake().await?;
+
+    // The interface watcher may open the loopback subflow itself
+    let mptcp = client.mptcp().expect("MPTCP enabled");
+    while mptcp.subflow_count() < 2 {
+        let _ = mptcp.add_subflow("127.0.0.1:0".parse()?).await;
+    }
+    subflows_validated(mptcp).await?;
+
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    for i in 0..MESSAGES {
+        client.send_on_stream(stream_id, format!("message {}", i).as_bytes()).await?;
+    }
+
+    // Every subflow's packets converge into the server's single reliability layer
+    let received = timeout(Duration::from_secs(5), server_task).await??;
+    let expected: Vec<Vec<u8>> = (0..MESSAGES).map(|i| format!("message {}", i).into_bytes()).collect();
+    assert_eq!(received, expected);
+
+    let subflows = client.mptcp().unwrap().subflows();
+    assert!(subflows.len() >= 2);
+    for subflow in subflows {
+        assert!(subflow.packets_sent() > 0, "subflow {} carried no traffic", subflow.id);
+    }
+
+    Ok(())
+}
+
+/// Test that redundant MPTCP sends every packet on both subflows but delivers it once
+#[tokio::test]
+async fn test_mptcp_redundant_dedup() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::mptcp::{MptcpConfig, SchedulerAlgorithm};
+
+    const MESSAGES: usize = 5;
+
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen("127.0.0.1:9020").await.unwrap();
+        let mut received = Vec::new();
+        while received.len() < MESSAGES {
+            for (_stream_id, data) in server.recv().await.unwrap() {
+                received.push(data.to_vec());
+            }
+            server.flush_acks().await.unwrap();
+        }
+        // Give the slower copies time to arrive; none may be delivered
+        while let Ok(Ok(packets)) = timeout(Duration::from_millis(300), server.recv()).await {
+            received.extend(packets.into_iter().map(|(_, data)| data.to_vec()));
+        }
+        (received, server.metrics().duplicate_packets_received)
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let config = ConnectionConfig::builder()
+        .mptcp_config(MptcpConfig {
+            enabled: true,
+            max_subflows: 2,
+            scheduler_algo: SchedulerAlgorithm::Redundant,
+        })
+        .build();
+    let mut client = Connection::connect_with_config("127.0.0.1:9020", config).await?;
+    client.handshake().await?;
+
+    let mptcp = client.mptcp().expect("MPTCP enabled");
+    while mptcp.subflow_count() < 2 {
+        let _ = mptcp.add_subflow("127.0.0.1:0".parse()?).await;
+    }
+    subflows_validated(mptcp).await?;
+
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    for i in 0..MESSAGES {
+        client.send_on_stream(stream_id, format!("message {}", i).as_bytes()).await?;
+    }
+
+    let (received, duplicates) = timeout(Duration::from_secs(5), server_task).await??;
+    let expected: Vec<Vec<u8>> = (0..MESSAGES).map(|i| format!("message {}", i).into_bytes()).collect();
+    assert_eq!(received, expected);
+    assert!(duplicates >= MESSAGES as u64, "only {} duplicates dropped", duplicates);
+
+    for subflow in client.mptcp().unwrap().subflows() {
+        assert_eq!(subflow.packets_sent(), MESSAGES as u64);
+    }
+
+    Ok(())
+}
+
+/// Test that min-RTT MPTCP learns per-subflow RTTs from ACKs and prefers the fast path
+#[tokio::test]
+async fn test_mptcp_min_rtt_prefers_fast_subflow() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::mptcp::{MptcpConfig, SchedulerAlgorithm};
+
+    const MESSAGES: usize = 40;
+
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen("127.0.0.1:9041").await.unwrap();
+        let mut received = 0;
+        while received < MESSAGES {
+            received += server.recv().await.unwrap().len();
+            server.flush_acks().await.unwrap();
+        }
+        received
+    });
+
+    // The slow subflow goes through a proxy adding 50 ms
+    spawn_proxy("127.0.0.1:9042", "127.0.0.1:9041".parse()?, Duration::from_millis(50), |_, _| true).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let config = ConnectionConfig::builder()
+        .mptcp_config(MptcpConfig {
+            enabled: true,
+            max_subflows: 3,
+            scheduler_algo: SchedulerAlgorithm::MinRtt,
+        })
+        .build();
+    let mut client = Connection::connect_with_config("127.0.0.1:9041", config).await?;
+    client.handshake().await?;
+
+    // Opened first so it wins the tie while neither path is measured
+    let mptcp = client.mptcp().expect("MPTCP enabled");
+    let slow = mptcp.add_subflow_to("127.0.0.1:0".parse()?, "127.0.0.1:9042".parse()?).await?;
+    // The interface watcher may open the loopback subflow itself
+    while mptcp.subflow_count() < 2 {
+        let _ = mptcp.add_subflow("127.0.0.1:0".parse()?).await;
+    }
+    subflows_validated(mptcp).await?;
+
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    for i in 0..MESSAGES {
+        client.send_on_stream(stream_id, format!("message {}", i).as_bytes()).await?;
+        // Take in ACKs so each subflow's RTT is measured as we go
+        while let Ok(Ok(_)) = timeout(Duration::from_millis(5), client.recv_events()).await {}
+    }
+
+    assert_eq!(timeout(Duration::from_secs(5), server_task).await??, MESSAGES);
+
+    let subflows = client.mptcp().unwrap().subflows();
+    let (slow_subflows, fast_subflows): (Vec<_>, Vec<_>) = subflows.iter().partition(|subflow| subflow.id == slow);
+    let slow_sent = slow_subflows[0].packets_sent();
+    let fast_sent: u64 = fast_subflows.iter().map(|subflow| subflow.packets_sent()).sum();
+    assert!(fast_sent > 3 * slow_sent, "fast subflows sent {}, slow one {}", fast_sent, slow_sent);
+
+    let slow_rtt = slow_subflows[0].rtt().expect("slow subflow measured");
+    assert!(slow_rtt >= Duration::from_millis(50), "slow RTT {:?}", slow_rtt);
+    for subflow in fast_subflows.iter().filter(|subflow| subflow.packets_sent() > 0) {
+        assert!(subflow.rtt().is_some_and(|rtt| rtt < slow_rtt));
+    }
+
+    Ok(())
+}
+
+/// Value of the first exported sample of `metric` whose labels contain `label`
+fn scrape_value(metric: &str, label: &str) -> Option<f64> {
+    let output = jsp_transport::prometheus::export_metrics().unwrap();
+    output.lines()
+        .filter(|line| line.starts_with(&format!("{}{{", metric)) && line.contains(label))
+        .find_map(|line| line.rsplit(' ').next()?.parse().ok())
+}
+
+/// Test that registered connections and servers show up in the Prometheus export
+#[tokio::test]
+async fn test_prometheus_live_metrics() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen("127.0.0.1:9021").await.unwrap();
+        let mut received = 0;
+        while received < 3 {
+            received += server.recv().await.unwrap().len();
+        }
+        server
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9021", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+    let session_id = client.session_id().to_string();
+    client.register_metrics(&[("connection_id", &session_id), ("role", "client")])?;
+
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    for _ in 0..3 {
+        client.send_on_stream(stream_id, b"metrics").await?;
+    }
+    let _server = timeout(Duration::from_secs(5), server_task).await??;
+
+    let sent = scrape_value("jsp_packets_sent_total", "role=\"client\"").expect("client series exported");
+    assert!(sent > 0.0);
+
+    // Servers export their session table too
+    let mut server = Server::bind("127.0.0.1:9022").await?;
+    server.register_metrics(&[("role", "server")])?;
+    let client_task = tokio::spawn(async {
+        let mut client = Connection::connect_with_config("127.0.0.1:9022", ConnectionConfig::default()).await.unwrap();
+        client.handshake().await.unwrap();
+        client
+    });
+    server.accept().await?;
+    let _client = client_task.await?;
+
+    assert_eq!(scrape_value("jsp_sessions_active", "role=\"server\""), Some(1.0));
+    assert!(scrape_value("jsp_session_idle_seconds", "connection_id=").is_some());
+
+    // Dropped connections stop being exported
+    drop(client);
+    assert_eq!(scrape_value("jsp_packets_sent_total", "role=\"client\""), None);
+
+    Ok(())
+}
+
+/// Test that datagrams bypass sequencing and are not retransmitted when lost
+#[tokio::test]
+async fn test_datagrams_unsequenced_and_not_retransmitted() -> Result<()> {
+    use jsp_core::types::header::{Header, FRAME_TYPE_DATAGRAM};
+    use std::sync::{Arc, Mutex};
+
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen("127.0.0.1:9023").await.unwrap();
+        let first = server.recv_datagram().await.unwrap();
+        let second = server.recv_datagram().await.unwrap();
+        (vec![first, second], server)
+    });
+
+    // Record the client's datagram headers and drop the first datagram
+    let seen: Arc<Mutex<Vec<Header>>> = Arc::new(Mutex::new(Vec::new()));
+    let tap = Arc::clone(&seen);
+    spawn_proxy("127.0.0.1:9024", "127.0.0.1:9023".parse()?, Duration::ZERO, move |from_client, packet| {
+        if !from_client {
+            return true;
+        }
+        let header_len = u16::from_be_bytes([packet[0], packet[1]]) as usize;
+        let header: Header = match serde_cbor::from_slice(&packet[2..2 + header_len]) {
+            Ok(header) => header,
+            Err(_) => return true, // Handshake
+        };
+        if header.msg_type != FRAME_TYPE_DATAGRAM {
+            return true;
+        }
+        let mut seen = tap.lock().unwrap();
+        seen.push(header);
+        seen.len() > 1
+    }).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9024", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+
+    for datagram in [&b"lost"[..], b"state 1", b"state 2"] {
+        client.send_datagram(datagram).await?;
+    }
+
+    let (received, _server) = timeout(Duration::from_secs(5), server_task).await??;
+    assert_eq!(received, vec![bytes::Bytes::from_static(b"state 1"), bytes::Bytes::from_static(b"state 2")]);
+
+    // Well past the minimum RTO nothing has been resent
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let _ = timeout(Duration::from_millis(100), client.recv_events()).await;
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.len(), 3);
+    assert!(seen.iter().all(|header| header.sequence == 0 && header.piggybacked_ack.is_none()));
+    assert_eq!(client.metrics().packets_retransmitted, 0);
+
+    // Too large to fit in one packet
+    let oversized = vec![0u8; client.config().max_fragment_size + 1];
+    assert!(client.send_datagram(&oversized).await.is_err());
+
+    Ok(())
+}
+
+/// Test that a half-closed stream delivers queued data and stays open in the other direction
+#[tokio::test]
+async fn test_stream_half_close() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::events::ConnectionEvent;
+
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen("127.0.0.1:9025").await.unwrap();
+        let stream_id = server.open_stream(1, DeliveryMode::Reliable).unwrap();
+        let mut events = Vec::new();
+        while !matches!(events.last(), Some(ConnectionEvent::StreamFinished(_))) {
+            events.extend(server.recv_events().await.unwrap());
+        }
+
+        // The client finished its side only; reply on the same stream, then finish ours
+        server.send_on_stream(stream_id, b"reply").await.unwrap();
+        server.finish_stream(stream_id).await.unwrap();
+        server.flush_acks().await.unwrap();
+        (events, server)
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9025", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    for message in [&b"one"[..], b"two"] {
+        client.send_on_stream(stream_id, message).await?;
+    }
+    client.finish_stream(stream_id).await?;
+
+    assert!(client.send_on_stream(stream_id, b"three").await.is_err());
+    assert!(client.finish_stream(stream_id).await.is_err());
+
+    let (events, _server) = timeout(Duration::from_secs(5), server_task).await??;
+    assert_eq!(events, vec![
+        ConnectionEvent::DataReceived { stream_id, data: bytes::Bytes::from_static(b"one") },
+        ConnectionEvent::DataReceived { stream_id, data: bytes::Bytes::from_static(b"two") },
+        ConnectionEvent::StreamFinished(stream_id),
+    ]);
+
+    // The client still reads what the server sends before it finishes
+    let mut reply = Vec::new();
+    while !matches!(reply.last(), Some(ConnectionEvent::StreamFinished(_))) {
+        reply.extend(timeout(Duration::from_secs(2), client.recv_events()).await??);
+    }
+    assert_eq!(reply, vec![
+        ConnectionEvent::DataReceived { stream_id, data: bytes::Bytes::from_static(b"reply") },
+        ConnectionEvent::StreamFinished(stream_id),
+    ]);
+
+    Ok(())
+}
+
+/// Test that data keeps flowing over a Double Ratchet while directions alternate
+#[tokio::test]
+async fn test_double_ratchet_alternating_directions() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use std::sync::{Arc, Mutex};
+
+    const ROUNDS: usize = 6;
+    let config = ConnectionConfig::builder().enable_double_ratchet(true).build();
+
+    let server_config = config.clone();
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen_with_config("127.0.0.1:9030", server_config).await.unwrap();
+        let stream_id = server.open_stream(1, DeliveryMode::Reliable).unwrap();
+        let mut received = 0;
+        while received < ROUNDS {
+            for (_stream_id, data) in server.recv().await.unwrap() {
+                let mut reply = b"reply:".to_vec();
+                reply.extend_from_slice(&data);
+                server.send_on_stream(stream_id, &reply).await.unwrap();
+                received += 1;
+            }
+        }
+        server.flush_acks().await.unwrap();
+        server.ratchet_steps()
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Record what crosses the wire to make sure no plaintext does
+    let wire = Arc::new(Mutex::new(Vec::new()));
+    let tap = Arc::clone(&wire);
+    spawn_proxy("127.0.0.1:9031", "127.0.0.1:9030".parse()?, Duration::ZERO, move |_, packet| {
+        tap.lock().unwrap().extend_from_slice(packet);
+        true
+    }).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9031", config).await?;
+    client.handshake().await?;
+    assert_eq!(client.ratchet_steps(), Some(0));
+
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    for round in 0..ROUNDS {
+        // One message spans several fragments
+        let message = if round == 3 { vec![b'x'; 5000] } else { format!("secret-{}", round).into_bytes() };
+        client.send_on_stream(stream_id, &message).await?;
+
+        let reply = loop {
+            let packets = timeout(Duration::from_secs(5), client.recv()).await??;
+            if let Some((_stream_id, data)) = packets.into_iter().next() {
+                break data;
+            }
+        };
+        assert_eq!(&reply[..6], b"reply:");
+        assert_eq!(&reply[6..], &message[..]);
+    }
+
+    // Every reply carried a fresh server key, and every new message a fresh client key
+    assert_eq!(client.ratchet_steps(), Some(ROUNDS as u64));
+    let server_steps = timeout(Duration::from_secs(5), server_task).await??;
+    assert!(server_steps.unwrap() >= ROUNDS as u64);
+
+    let wire = wire.lock().unwrap();
+    assert!(!wire.windows(8).any(|w| w == b"secret-0"));
+
+    Ok(())
+}
+
+/// Test that messages survive three forced key updates, from either side
+#[tokio::test]
+async fn test_key_updates_never_lose_messages() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use std::sync::{Arc, Mutex};
+
+    const ROUNDS: usize = 8;
+    const BURST: usize = 3;
+    let config = ConnectionConfig::builder().enable_key_updates(true).build();
+
+    let server_config = config.clone();
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen_with_config("127.0.0.1:9050", server_config).await.unwrap();
+        let stream_id = server.open_stream(1, DeliveryMode::Reliable).unwrap();
+        let mut received = 0;
+        while received < ROUNDS * BURST {
+            for (_stream_id, data) in server.recv().await.unwrap() {
+                received += 1;
+                // The second of the three updates comes from this side
+                if received == 4 * BURST {
+                    server.update_keys().await.unwrap();
+                }
+                let mut reply = b"reply:".to_vec();
+                reply.extend_from_slice(&data);
+                server.send_on_stream(stream_id, &reply).await.unwrap();
+            }
+        }
+        server.flush_acks().await.unwrap();
+        server.key_epoch()
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Record what crosses the wire to make sure no plaintext does
+    let wire = Arc::new(Mutex::new(Vec::new()));
+    let tap = Arc::clone(&wire);
+    spawn_proxy("127.0.0.1:9051", "127.0.0.1:9050".parse()?, Duration::ZERO, move |_, packet| {
+        tap.lock().unwrap().extend_from_slice(packet);
+        true
+    }).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9051", config).await?;
+    client.handshake().await?;
+    assert_eq!(client.key_epoch(), 0);
+
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    for round in 0..ROUNDS {
+        if round == 2 || round == 6 {
+            client.update_keys().await?;
+        }
+        let messages: Vec<Vec<u8>> = (0..BURST)
+            .map(|i| if i == 1 { vec![b'0' + round as u8; 3000] } else { format!("secret-{}-{}", round, i).into_bytes() })
+            .collect();
+        for message in &messages {
+            client.send_on_stream(stream_id, message).await?;
+        }
+
+        let mut replies = Vec::new();
+        while replies.len() < BURST {
+            let packets = timeout(Duration::from_secs(5), client.recv()).await??;
+            replies.extend(packets.into_iter().map(|(_stream_id, data)| data));
+        }
+        for (reply, message) in replies.iter().zip(&messages) {
+            assert_eq!(&reply[..6], b"reply:");
+            assert_eq!(&reply[6..], &message[..], "round {}", round);
+        }
+    }
+
+    assert_eq!(client.key_epoch(), 3);
+    assert_eq!(timeout(Duration::from_secs(5), server_task).await??, 3);
+
+    let wire = wire.lock().unwrap();
+    assert!(!wire.windows(8).any(|w| w == b"secret-0"));
+
+    Ok(())
+}
+
+/// Test that a key update starts by itself once an epoch sealed `key_update_bytes`
+#[tokio::test]
+async fn test_key_update_after_byte_limit() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+
+    let config = ConnectionConfig::builder()
+        .enable_key_updates(true)
+        .key_update_bytes(4096)
+        .build();
+
+    let server_config = config.clone();
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen_with_config("127.0.0.1:9052", server_config).await.unwrap();
+        let mut received = Vec::new();
+        while received.len() < 10 {
+            received.extend(server.recv().await.unwrap().into_iter().map(|(_stream_id, data)| data));
+        }
+        server.flush_acks().await.unwrap();
+        (received, server.key_epoch())
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9052", config).await?;
+    client.handshake().await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    for i in 0..10u8 {
+        client.send_on_stream_wait(stream_id, &[i; 1000], Some(Duration::from_secs(5))).await?;
+    }
+
+    let (received, server_epoch) = timeout(Duration::from_secs(5), server_task).await??;
+    for (i, data) in received.iter().enumerate() {
+        assert_eq!(&data[..], &[i as u8; 1000][..]);
+    }
+    assert!(client.key_epoch() >= 1);
+    assert_eq!(server_epoch, client.key_epoch());
+
+    Ok(())
+}
+
+/// Test that a send blocked on a full congestion window completes once an ACK arrives
+#[tokio::test]
+async fn test_blocked_send_resumes_on_ack() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+
+    const CHUNK: usize = 1000;
+
+    let config = ConnectionConfig::builder()
+        .rate_limit_messages(100_000)
+        .rate_limit_bytes(1_000_000_000)
+        .build();
+
+    // The server doesn't read (and so doesn't ACK) until told to
+    let (ack_tx, ack_rx) = tokio::sync::oneshot::channel::<()>();
+    let server_config = config.clone();
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen_with_config("127.0.0.1:9026", server_config).await.unwrap();
+        ack_rx.await.unwrap();
+        loop {
+            server.recv().await.unwrap();
+            server.flush_acks().await.unwrap();
+        }
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9026", config).await?;
+    client.handshake().await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    let chunk = vec![0xA5u8; CHUNK];
+
+    // Fill the window; the non-blocking send reports it instead of waiting
+    let mut sent = 0;
+    let err = loop {
+        match client.send_on_stream(stream_id, &chunk).await {
+            Ok(()) => sent += 1,
+            Err(e) => break e,
+        }
+        assert!(sent < 1000, "congestion window never filled");
+    };
+    assert!(err.to_string().contains("Congestion window full"));
+    assert!(matches!(err, TransportError::CongestionLimited), "{:?}", err);
+
+    // Without ACKs the waiting send stays blocked
+    let blocked = client.send_on_stream_wait(stream_id, &chunk, Some(Duration::from_millis(300))).await;
+    assert!(matches!(blocked, Err(TransportError::SendTimeout)), "{:?}", blocked);
+
+    // Once the server ACKs, the waiting send goes through
+    ack_tx.send(()).unwrap();
+    timeout(Duration::from_secs(5), client.send_on_stream_wait(stream_id, &chunk, None)).await??;
+
+    server_task.abort();
+    Ok(())
+}
+
+/// Test that the client resends its ClientHello when the ServerHello is lost
+#[tokio::test]
+async fn test_handshake_survives_lost_server_hello() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_core::types::handshake::{ClientHello, ServerHello};
+    use std::sync::{Arc, Mutex};
+
+    let server_task = tokio::spawn(async {
+        let mut server = Connection::listen("127.0.0.1:9032").await.unwrap();
+        let stream_id = server.open_stream(1, DeliveryMode::Reliable).unwrap();
+        loop {
+            if let Some((_stream_id, data)) = server.recv().await.unwrap().into_iter().next() {
+                server.send_on_stream(stream_id, &data).await.unwrap();
+                server.flush_acks().await.unwrap();
+                return server.session_id();
+            }
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Drop the first ServerHello, keeping copies of every hello
+    let client_hellos = Arc::new(Mutex::new(Vec::new()));
+    let server_hellos = Arc::new(Mutex::new(Vec::new()));
+    let (client_tap, server_tap) = (Arc::clone(&client_hellos), Arc::clone(&server_hellos));
+    spawn_proxy("127.0.0.1:9033", "127.0.0.1:9032".parse()?, Duration::ZERO, move |from_client, packet| {
+        if from_client {
+            if let Ok(hello) = serde_cbor::from_slice::<ClientHello>(packet) {
+                client_tap.lock().unwrap().push(hello.nonce);
+            }
+        } else if serde_cbor::from_slice::<ServerHello>(packet).is_ok() {
+            let mut server_hellos = server_tap.lock().unwrap();
+            server_hellos.push(packet.to_vec());
+            return server_hellos.len() > 1;
+        }
+        true
+    }).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let config = ConnectionConfig::builder()
+        .handshake_retry_interval(Duration::from_millis(200))
+        .handshake_max_retries(3)
+        .build();
+    let mut client = Connection::connect_with_config("127.0.0.1:9033", config).await?;
+    timeout(Duration::from_secs(5), client.handshake()).await??;
+
+    // The same hello went out twice and was answered with the same ServerHello
+    {
+        let client_hellos = client_hellos.lock().unwrap();
+        assert_eq!(client_hellos.len(), 2);
+        assert_eq!(client_hellos[0], client_hellos[1]);
+        let server_hellos = server_hellos.lock().unwrap();
+        assert_eq!(server_hellos.len(), 2);
+        assert_eq!(server_hellos[0], server_hellos[1]);
+    }
+
+    // Both sides derived the same keys
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    client.send_on_stream(stream_id, b"after retry").await?;
+    let echo = loop {
+        let packets = timeout(Duration::from_secs(5), client.recv()).await??;
+        if let Some((_stream_id, data)) = packets.into_iter().next() {
+            break data;
+        }
+    };
+    assert_eq!(&echo[..], b"after retry");
+    assert_eq!(timeout(Duration::from_secs(5), server_task).await??, client.session_id());
+
+    Ok(())
+}
+
+/// Test that the handshake fails with a clear error when the server never answers
+#[tokio::test]
+async fn test_handshake_gives_up_after_retries() -> Result<()> {
+    // Bound but never read, so ClientHellos go unanswered
+    let silent = tokio::net::UdpSocket::bind("127.0.0.1:9034").await?;
+
+    let config = ConnectionConfig::builder()
+        .handshake_retry_interval(Duration::from_millis(50))
+        .handshake_max_retries(2)
+        .build();
+    let mut client = Connection::connect_with_config("127.0.0.1:9034", config).await?;
+    let err = timeout(Duration::from_secs(5), client.handshake()).await?.unwrap_err();
+    assert!(matches!(err, TransportError::HandshakeTimeout), "{:?}", err);
+
+    // Every attempt reached the server address
+    let mut buf = [0u8; 4096];
+    for _ in 0..3 {
+        timeout(Duration::from_millis(100), silent.recv_from(&mut buf)).await??;
+    }
+
+    Ok(())
+}
+
+/// Test that a retransmitted ClientHello reuses the session it already opened
+#[tokio::test]
+async fn test_duplicate_client_hello_reuses_session() -> Result<()> {
+    use jsp_core::session::Session;
+
+    let server_task = tokio::spawn(async {
+        let mut server = Server::bind("127.0.0.1:9035").await.unwrap();
+        server.accept().await.unwrap();
+        server.accept().await.unwrap();
+        server.session_count().await
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    socket.connect("127.0.0.1:9035").await?;
+    let mut session = Session::new();
+    let hello = session.generate_client_hello()?;
+
+    // The same hello twice, as a client would after losing the first reply
+    let mut replies = Vec::new();
+    for _ in 0..2 {
+        socket.send(&hello).await?;
+        let mut buf = [0u8; 4096];
+        let len = timeout(Duration::from_secs(2), socket.recv(&mut buf)).await??;
+        replies.push(buf[..len].to_vec());
+    }
+
+    assert_eq!(replies[0], replies[1]);
+    assert_eq!(timeout(Duration::from_secs(2), server_task).await??, 1);
+    session.process_server_hello(&replies[1])?;
+
+    Ok(())
+}
+
+/// Test a server echoing to a client through `Server::next_event`
+#[tokio::test]
+async fn test_server_events_echo_and_close() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::events::ServerEvent;
+
+    let mut server = Server::bind("127.0.0.1:9036").await?;
+    let server_task = tokio::spawn(async move {
+        let mut events = Vec::new();
+        loop {
+            let event = server.next_event().await.unwrap();
+            if let ServerEvent::DataReceived { addr, stream_id, data } = &event {
+                server.send_on_stream(*addr, *stream_id, data).await.unwrap();
+            }
+            let closed = matches!(event, ServerEvent::Closed { .. });
+            events.push(event);
+            if closed {
+                return (events, server.session_count().await);
+            }
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9036", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+
+    // Each message comes back in order on the same stream
+    for message in [&b"one"[..], b"two", b"three"] {
+        client.send_on_stream(stream_id, message).await?;
+        let echo = loop {
+            let packets = timeout(Duration::from_secs(5), client.recv()).await??;
+            if let Some(packet) = packets.into_iter().next() {
+                break packet;
+            }
+        };
+        assert_eq!(echo.0, stream_id);
+        assert_eq!(&echo.1[..], message);
+    }
+
+    client.close(CloseReason::Normal, Some("done".to_string())).await?;
+    let (events, sessions) = timeout(Duration::from_secs(5), server_task).await??;
+
+    let client_addr = match events.first() {
+        Some(ServerEvent::SessionEstablished { addr, session_id }) => {
+            assert_eq!(*session_id, client.session_id());
+            *addr
+        }
+        other => panic!("expected SessionEstablished first, got {:?}", other),
+    };
+    assert_eq!(client_addr.port(), client.local_addr()?.port());
+    let received: Vec<_> = events.iter()
+        .filter_map(|event| match event {
+            ServerEvent::DataReceived { data, .. } => Some(data.to_vec()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(received, vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]);
+    assert_eq!(events.last(), Some(&ServerEvent::Closed { addr: client_addr, reason: CloseReason::Normal, message: Some("done".to_string()) }));
+    assert_eq!(sessions, 0);
+
+    Ok(())
+}
+
+/// Test that a traced connection produces a span tree under one trace
+#[tokio::test]
+async fn test_connection_span_tree() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::events::ServerEvent;
+    use jsp_transport::otel::{self, InMemoryExporter};
+    use std::sync::Arc;
+
+    let exporter = Arc::new(InMemoryExporter::new());
+    otel::init_tracer_with_exporter("integration-test", exporter.clone()).unwrap();
+
+    let mut server = Server::bind("127.0.0.1:9037").await?;
+    tokio::spawn(async move {
+        loop {
+            if let Ok(ServerEvent::DataReceived { addr, stream_id, data }) = server.next_event().await {
+                let _ = server.send_on_stream(addr, stream_id, &data).await;
+            }
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9037", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    client.send_on_stream(stream_id, b"traced").await?;
+    loop {
+        let packets = timeout(Duration::from_secs(5), client.recv()).await??;
+        if !packets.is_empty() {
+            break;
+        }
+    }
+    let root = client.trace_context().expect("connection has no trace");
+    client.close(CloseReason::Normal, None).await?;
+
+    // Other tests' connections share the exporter, so look at this trace only
+    let spans: Vec<_> = exporter.spans().into_iter()
+        .filter(|span| span.trace_id == root.trace_id.to_string())
+        .collect();
+    let root_span = spans.iter().find(|span| span.name == "connection").expect("root span not exported");
+    assert_eq!(root_span.span_id, root.span_id.to_string());
+    assert_eq!(root_span.parent_span_id, None);
+    assert_eq!(root_span.attributes["role"], "client");
+
+    for name in ["handshake", "send_on_stream", "recv"] {
+        let span = spans.iter().find(|span| span.name == name).unwrap_or_else(|| panic!("no {} span", name));
+        assert_eq!(span.parent_span_id, Some(root.span_id.to_string()), "{} is not a child of the connection", name);
+        assert_eq!(span.service_name, "integration-test");
+    }
+    let send = spans.iter().find(|span| span.name == "send_on_stream").unwrap();
+    assert_eq!(send.attributes["bytes"], "6");
+    let handshake = spans.iter().find(|span| span.name == "handshake").unwrap();
+    assert_eq!(handshake.attributes["session.id"], client.session_id().to_string());
+
+    Ok(())
+}
+
+/// Test that RTT and payload size histograms are fed by live traffic
+#[tokio::test]
+async fn test_prometheus_histograms() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+
+    let mut server = Server::bind("127.0.0.1:9038").await?;
+    let server_task = tokio::spawn(async move {
+        loop {
+            if let jsp_transport::events::ServerEvent::DataReceived { addr, stream_id, data } = server.next_event().await.unwrap() {
+                server.send_on_stream(addr, stream_id, &data).await.unwrap();
+            }
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9038", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+
+    // Waiting for each echo also processes the server's ACK
+    for _ in 0..3 {
+        client.send_on_stream(stream_id, &[7u8; 300]).await?;
+        loop {
+            let packets = timeout(Duration::from_secs(5), client.recv()).await??;
+            if !packets.is_empty() {
+                break;
+            }
+        }
+    }
+    server_task.abort();
+
+    let output = jsp_transport::prometheus::export_metrics().unwrap();
+    for histogram in ["jsp_rtt_seconds", "jsp_packet_payload_bytes"] {
+        let value = |suffix: &str, label: &str| -> f64 {
+            output.lines()
+                .find(|line| line.starts_with(&format!("{}{}", histogram, suffix)) && line.contains(label))
+                .and_then(|line| line.rsplit(' ').next()?.parse().ok())
+                .unwrap_or_else(|| panic!("{}{} {} not exported", histogram, suffix, label))
+        };
+        assert!(value("_bucket", "le=\"+Inf\"") > 0.0);
+        assert!(value("_sum", "") > 0.0);
+        assert!(value("_count", "") > 0.0);
+    }
+    // 300-byte payloads fall between the 256 and 512 byte bounds
+    let in_bucket = |le: &str| output.lines()
+        .find(|line| line.starts_with(&format!("jsp_packet_payload_bytes_bucket{{le=\"{}\"}}", le)))
+        .and_then(|line| line.rsplit(' ').next()?.parse::<f64>().ok())
+        .unwrap();
+    assert!(in_bucket("512") - in_bucket("256") >= 3.0);
+
+    Ok(())
+}
+
+/// Test that each connection gets its own labeled series
+#[tokio::test]
+async fn test_prometheus_per_connection_series() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+
+    let mut server = Server::bind("127.0.0.1:9039").await?;
+    let server_task = tokio::spawn(async move {
+        loop {
+            if let jsp_transport::events::ServerEvent::DataReceived { addr, stream_id, data } = server.next_event().await.unwrap() {
+                server.send_on_stream(addr, stream_id, &data).await.unwrap();
+            }
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut clients = Vec::new();
+    for message in [&b"first"[..], b"second"] {
+        let mut client = Connection::connect_with_config("127.0.0.1:9039", ConnectionConfig::default()).await?;
+        client.handshake().await?;
+        let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+        client.send_on_stream(stream_id, message).await?;
+        loop {
+            let packets = timeout(Duration::from_secs(5), client.recv()).await??;
+            if !packets.is_empty() {
+                break;
+            }
+        }
+        clients.push(client);
+    }
+    server_task.abort();
+
+    let output = jsp_transport::prometheus::export_metrics().unwrap();
+    for client in &clients {
+        let label = format!("session_id=\"{}\"", client.session_id());
+        for metric in ["jsp_connection_bytes_sent_total", "jsp_connection_bytes_received_total", "jsp_connection_cwnd_bytes"] {
+            let value: f64 = output.lines()
+                .find(|line| line.starts_with(&format!("{}{{", metric)) && line.contains(&label))
+                .and_then(|line| line.rsplit(' ').next()?.parse().ok())
+                .unwrap_or_else(|| panic!("no {} series for {}", metric, label));
+            assert!(value > 0.0, "{} for {} is {}", metric, label, value);
+        }
+        assert!(output.contains("peer=\"127.0.0.1:9039\""));
+    }
+    assert_ne!(clients[0].session_id(), clients[1].session_id());
+
+    // Dropped connections leave the export
+    let label = format!("session_id=\"{}\"", clients[0].session_id());
+    clients.remove(0);
+    let output = jsp_transport::prometheus::export_metrics().unwrap();
+    assert!(!output.contains(&label));
+
+    Ok(())
+}
+
+/// Handshake and stream 100 messages over a loopback WebRTC data channel
+#[tokio::test]
+async fn test_webrtc_data_channel_connection() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::config::ConnectStrategy;
+    use jsp_transport::signaling::SignalingServer;
+    use jsp_transport::transport_selector::TransportType;
+    use jsp_transport::webrtc::WebRTCConfig;
+
+    const MESSAGES: usize = 100;
+
+    tokio::spawn(async { SignalingServer::new("127.0.0.1:9040").run().await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let config = |peer_id: &str| {
+        ConnectionConfig::builder()
+            .connect_strategy(ConnectStrategy::WebRtc)
+            .rate_limit_messages(10_000)
+            .webrtc(WebRTCConfig {
+                bind_addr: "127.0.0.1:0".to_string(),
+                signaling_server: Some("127.0.0.1:9040".to_string()),
+                peer_id: Some(peer_id.to_string()),
+                ..WebRTCConfig::default()
+            })
+            .build()
+    };
+
+    let server_config = config("webrtc-server");
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen_with_config("127.0.0.1:0", server_config).await.unwrap();
+        assert_eq!(server.active_transport(), TransportType::WebRtc);
+        let mut messages = Vec::new();
+        while messages.len() < MESSAGES {
+            messages.extend(server.recv().await.unwrap());
+            server.flush_acks().await.unwrap();
+        }
+        messages
+    });
+
+    // The offer is repeated until the server has registered
+    let mut client = Connection::connect_with_config("webrtc-server", config("webrtc-client")).await?;
+    assert_eq!(client.active_transport(), TransportType::WebRtc);
+    client.handshake().await?;
+    assert!(client.session_id() > 0);
+
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    for i in 0..MESSAGES {
+        let message = format!("message {} over a data channel", i);
+        client.send_on_stream_wait(stream_id, message.as_bytes(), Some(Duration::from_secs(10))).await?;
+    }
+
+    // The data channel is ordered, so messages arrive as sent
+    let messages = timeout(Duration::from_secs(20), server_task).await??;
+    assert_eq!(messages.len(), MESSAGES);
+    for (i, (received_stream, data)) in messages.iter().enumerate() {
+        assert_eq!(*received_stream, stream_id);
+        assert_eq!(data[..], *format!("message {} over a data channel", i).as_bytes());
+    }
+
+    Ok(())
+}
+
+/// Server that asks every new client for a retry token
+fn retry_server_config() -> ServerConfig {
+    use jsp_transport::ddos_protection::DdosConfig;
+
+    ServerConfig::builder()
+        .ddos_config(DdosConfig {
+            retry_threshold: Some(0),
+            ..DdosConfig::default()
+        })
+        .build()
+}
+
+/// Test that a client completes the handshake through a server's Retry
+#[tokio::test]
+async fn test_handshake_through_retry() -> Result<()> {
+    use jsp_transport::events::ServerEvent;
+
+    let mut server = Server::bind_with_config("127.0.0.1:9044", retry_server_config()).await?;
+    let server_task = tokio::spawn(async move {
+        loop {
+            if let ServerEvent::SessionEstablished { session_id, .. } = server.next_event().await.unwrap() {
+                return (session_id, server.key_exchanges());
+            }
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9044", ConnectionConfig::default()).await?;
+    timeout(Duration::from_secs(5), client.handshake()).await??;
+
+    let (session_id, key_exchanges) = timeout(Duration::from_secs(5), server_task).await??;
+    assert_eq!(client.session_id(), session_id);
+    assert_eq!(key_exchanges, 1);
+
+    Ok(())
+}
+
+/// Test that ClientHellos without a valid retry token never reach the key exchange
+#[tokio::test]
+async fn test_retry_token_required_before_key_exchange() -> Result<()> {
+    use jsp_core::session::Session;
+    use jsp_core::types::handshake::{ClientHello, RetryFrame};
+    use jsp_core::types::header::{Header, FRAME_TYPE_RETRY};
+
+    let mut server = Server::bind_with_config("127.0.0.1:9045", retry_server_config()).await?;
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    socket.connect("127.0.0.1:9045").await?;
+    let mut session = Session::new();
+    let hello = session.generate_client_hello()?;
+    let with_token = |hello: &[u8], token: Vec<u8>| -> Result<Vec<u8>> {
+        let mut hello: ClientHello = serde_cbor::from_slice(hello)?;
+        hello.retry_token = Some(token);
+        Ok(serde_cbor::to_vec(&hello)?)
+    };
+
+    // A bare hello is answered with a Retry
+    socket.send(&hello).await?;
+    server.accept().await?;
+    let mut buf = [0u8; 2048];
+    let len = timeout(Duration::from_secs(2), socket.recv(&mut buf)).await??;
+    let header_len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+    let header: Header = serde_cbor::from_slice(&buf[2..2 + header_len])?;
+    assert_eq!(header.msg_type, FRAME_TYPE_RETRY);
+    let retry: RetryFrame = serde_cbor::from_slice(&buf[2 + header_len..len])?;
+
+    // Forged tokens, and real ones lifted onto another hello, are rejected
+    socket.send(&with_token(&hello, vec![0u8; 40])?).await?;
+    assert!(server.accept().await.is_err());
+    let other = Session::new().generate_client_hello()?;
+    socket.send(&with_token(&other, retry.token.clone())?).await?;
+    assert!(server.accept().await.is_err());
+    assert_eq!(server.key_exchanges(), 0);
+    assert_eq!(server.session_count().await, 0);
+
+    // The genuine token gets the hello through
+    socket.send(&with_token(&hello, retry.token)?).await?;
+    server.accept().await?;
+    assert_eq!(server.key_exchanges(), 1);
+    let len = timeout(Duration::from_secs(2), socket.recv(&mut buf)).await??;
+    session.process_server_hello(&buf[..len])?;
+
+    Ok(())
+}
+
+/// Test that sessions beyond `max_sessions_per_ip` are refused with a Close
+#[tokio::test]
+async fn test_max_sessions_per_ip() -> Result<()> {
+    let config = ServerConfig::builder().max_sessions_per_ip(Some(2)).build();
+    let mut server = Server::bind_with_config("127.0.0.1:9046", config).await?;
+    let (stop_tx, mut stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let server_task = tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = &mut stop_rx => return server,
+                event = server.next_event() => { event.unwrap(); }
+            }
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut clients = Vec::new();
+    for _ in 0..2 {
+        let mut client = Connection::connect_with_config("127.0.0.1:9046", ConnectionConfig::default()).await?;
+        client.handshake().await?;
+        clients.push(client);
+    }
+
+    // The Close fails the third handshake without waiting out the retries
+    let mut third = Connection::connect_with_config("127.0.0.1:9046", ConnectionConfig::default()).await?;
+    let err = timeout(Duration::from_secs(1), third.handshake()).await?.unwrap_err();
+    assert!(err.to_string().contains("refused"), "{}", err);
+
+    stop_tx.send(()).unwrap();
+    let server = server_task.await?;
+    assert_eq!(server.session_count().await, 2);
+    assert_eq!(server.metrics().handshakes_rejected, 1);
+
+    Ok(())
+}
+
+/// Test that a banned address loses its sessions and cannot open new ones
+#[tokio::test]
+async fn test_banned_ip_never_creates_sessions() -> Result<()> {
+    use jsp_core::session::Session;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let mut server = Server::bind("127.0.0.1:9047").await?;
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    socket.connect("127.0.0.1:9047").await?;
+
+    socket.send(&Session::new().generate_client_hello()?).await?;
+    server.accept().await?;
+    assert_eq!(server.session_count().await, 1);
+
+    // Banning drops the existing session
+    server.ban_ip(localhost, Duration::from_secs(60)).await;
+    assert_eq!(server.session_count().await, 0);
+    assert_eq!(server.banned_ips().await.iter().map(|entry| entry.ip).collect::<Vec<_>>(), vec![localhost]);
+
+    // New hellos are dropped before they are parsed
+    for _ in 0..3 {
+        socket.send(&Session::new().generate_client_hello()?).await?;
+        assert!(server.accept().await.is_err());
+    }
+    assert_eq!(server.session_count().await, 0);
+    assert_eq!(server.key_exchanges(), 1);
+    assert_eq!(server.metrics().packets_dropped, 3);
+
+    server.unban_ip(localhost).await;
+    assert!(server.banned_ips().await.is_empty());
+    socket.send(&Session::new().generate_client_hello()?).await?;
+    server.accept().await?;
+    assert_eq!(server.session_count().await, 1);
+
+    Ok(())
+}
+
+/// Send `message` on `stream_id` and wait for the server to echo it back
+async fn echo_round_trip(client: &mut Connection, stream_id: u32, message: &[u8]) -> Result<()> {
+    client.send_on_stream(stream_id, message).await?;
+    loop {
+        let packets = timeout(Duration::from_secs(2), client.recv()).await??;
+        if let Some((_, data)) = packets.into_iter().next() {
+            assert_eq!(&data[..], message);
+            return Ok(());
+        }
+    }
+}
+
+/// Test that a migration whose new path never validates leaves the connection on the old path
+#[tokio::test]
+async fn test_unvalidated_migration_keeps_old_path() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_core::types::header::{Header, FRAME_TYPE_PATH_CHALLENGE};
+    use jsp_transport::events::ServerEvent;
+
+    let mut server = Server::bind("127.0.0.1:9049").await?;
+    tokio::spawn(async move {
+        loop {
+            if let Ok(ServerEvent::DataReceived { addr, stream_id, data }) = server.next_event().await {
+                server.send_on_stream(addr, stream_id, &data).await.unwrap();
+            }
+        }
+    });
+    // The client's challenges from its new socket never arrive
+    spawn_proxy("127.0.0.1:9048", "127.0.0.1:9049".parse()?, Duration::ZERO, |from_client, packet| {
+        let header_len = u16::from_be_bytes([packet[0], packet[1]]) as usize;
+        let challenge = packet.get(2..2 + header_len)
+            .and_then(|bytes| serde_cbor::from_slice::<Header>(bytes).ok())
+            .is_some_and(|header| header.msg_type == FRAME_TYPE_PATH_CHALLENGE);
+        !(from_client && challenge)
+    }).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9048", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    let old_addr = client.local_addr()?;
+
+    client.migrate("127.0.0.1:0").await?;
+    assert!(client.is_migrating());
+
+    // Data keeps flowing on the old path while the new one is probed...
+    echo_round_trip(&mut client, stream_id, b"while probing").await?;
+    assert_eq!(client.local_addr()?, old_addr);
+
+    // ...and after it has been given up
+    let given_up = timeout(Duration::from_secs(5), async {
+        while client.is_migrating() {
+            let _ = timeout(Duration::from_millis(50), client.next_event()).await;
+        }
+    });
+    given_up.await?;
+    assert_eq!(client.local_addr()?, old_addr);
+    echo_round_trip(&mut client, stream_id, b"after giving up").await?;
+
+    Ok(())
+}
+
+/// Test that a PATH_CHALLENGE naming the session from a stranger's address doesn't move the peer
+#[tokio::test]
+async fn test_spoofed_path_challenge_does_not_migrate() -> Result<()> {
+    use jsp_core::types::connection_id::ConnectionId;
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_core::types::header::{Header, FRAME_TYPE_PATH_CHALLENGE};
+    use jsp_core::types::path_validation::PathChallenge;
+    use jsp_transport::events::ConnectionEvent;
+
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen("127.0.0.1:9078").await.unwrap();
+        let stream_id = server.open_stream(1, DeliveryMode::Reliable).unwrap();
+        let mut migrations = 0;
+        loop {
+            match server.next_event().await.unwrap() {
+                ConnectionEvent::DataReceived { data, .. } => {
+                    server.send_on_stream(stream_id, &data).await.unwrap();
+                }
+                ConnectionEvent::PeerMigrated { .. } => migrations += 1,
+                ConnectionEvent::Closed { .. } => return migrations,
+                _ => {}
+            }
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9078", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    echo_round_trip(&mut client, stream_id, b"before").await?;
+
+    // The session ID travels in the clear, so anyone on the path can name it
+    let attacker = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let payload = serde_cbor::to_vec(&PathChallenge { token: [7; 8] })?;
+    let mut header = Header::new(0, FRAME_TYPE_PATH_CHALLENGE, 0, 0, 0, 0, DeliveryMode::Reliable, None, Some(payload.len() as u32));
+    header.connection_id = Some(ConnectionId::from_u64(client.session_id()));
+    let header_bytes = serde_cbor::to_vec(&header)?;
+    let mut packet = (header_bytes.len() as u16).to_be_bytes().to_vec();
+    packet.extend_from_slice(&header_bytes);
+    packet.extend_from_slice(&payload);
+    attacker.send_to(&packet, "127.0.0.1:9078").await?;
+
+    // The server challenges the new address instead of switching to it...
+    let mut buf = [0u8; 2048];
+    let len = timeout(Duration::from_secs(2), attacker.recv(&mut buf)).await??;
+    let header_len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+    let reply: Header = serde_cbor::from_slice(&buf[2..2 + header_len.min(len - 2)])?;
+    assert_eq!(reply.msg_type, FRAME_TYPE_PATH_CHALLENGE);
+
+    // ...and, with the challenge unanswered, keeps talking to the client
+    echo_round_trip(&mut client, stream_id, b"after").await?;
+    client.close(CloseReason::Normal, None).await?;
+    assert_eq!(timeout(Duration::from_secs(5), server_task).await??, 0);
+
+    Ok(())
+}
+
+/// Test that hellos from an unvalidated address never get more than 3x their size back
+#[tokio::test]
+async fn test_server_hello_respects_amplification_limit() -> Result<()> {
+    use jsp_core::session::Session;
+    use jsp_core::types::handshake::ClientHello;
+
+    let mut server = Server::bind("127.0.0.1:9053").await?;
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    socket.connect("127.0.0.1:9053").await?;
+    let mut buf = [0u8; 4096];
+
+    // A genuine hello carries a Kyber key, so the ServerHello fits its budget
+    let hello = Session::new().generate_client_hello()?;
+    socket.send(&hello).await?;
+    server.accept().await?;
+    let mut received = timeout(Duration::from_secs(2), socket.recv(&mut buf)).await??;
+    let mut sent = hello.len();
+    assert!(received <= 3 * sent);
+
+    // The same hello stripped down, as a spoofer would replay it to reflect ServerHellos
+    let mut small: ClientHello = serde_cbor::from_slice(&hello)?;
+    small.kyber_public_key.clear();
+    small.cipher_suites.clear();
+    small.supported_formats.clear();
+    let small = serde_cbor::to_vec(&small)?;
+    assert!(3 * small.len() < received);
+
+    for _ in 0..20 {
+        socket.send(&small).await?;
+        server.accept().await?;
+        sent += small.len();
+        while let Ok(len) = timeout(Duration::from_millis(50), socket.recv(&mut buf)).await {
+            received += len?;
+        }
+    }
+    assert!(received <= 3 * sent, "{} bytes sent back for {} received", received, sent);
+    // Held-back ServerHellos still go out as the budget grows
+    assert!(received > 2 * hello.len());
+
+    Ok(())
+}
+
+/// Test that a sealed packet whose header was altered in flight is dropped and counted
+#[tokio::test]
+async fn test_tampered_header_fails_authentication() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_core::types::header::{Header, FRAME_TYPE_DATA};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let config = ConnectionConfig::builder().enable_key_updates(true).build();
+
+    let server_config = config.clone();
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen_with_config("127.0.0.1:9054", server_config).await.unwrap();
+        let mut received = Vec::new();
+        while received.len() < 2 {
+            received.extend(server.recv().await.unwrap().into_iter().map(|(_stream_id, data)| data));
+        }
+        (received, server.metrics().auth_failures)
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Ahead of the client's first data packet, deliver a copy with one header byte flipped
+    let tampered = AtomicBool::new(false);
+    spawn_rewriting_proxy("127.0.0.1:9055", "127.0.0.1:9054".parse()?, Duration::ZERO, move |from_client, packet| {
+        let header_len = u16::from_be_bytes([packet[0], packet[1]]) as usize;
+        let header = match serde_cbor::from_slice::<Header>(packet.get(2..2 + header_len).unwrap_or_default()) {
+            Ok(header) if from_client && header.msg_type == FRAME_TYPE_DATA => header,
+            _ => return vec![packet.to_vec()],
+        };
+        if tampered.swap(true, Ordering::SeqCst) {
+            return vec![packet.to_vec()];
+        }
+        // The last flip that still parses as the same kind of frame
+        let copy = (2..2 + header_len).rev().find_map(|i| {
+            let mut copy = packet.to_vec();
+            copy[i] ^= 0x01;
+            let altered: Header = serde_cbor::from_slice(&copy[2..2 + header_len]).ok()?;
+            (altered.msg_type == header.msg_type && altered.payload_len == header.payload_len).then_some(copy)
+        }).expect("a header byte can be flipped");
+        vec![copy, packet.to_vec()]
+    }).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9055", config).await?;
+    client.handshake().await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    client.send_on_stream(stream_id, b"first").await?;
+    client.send_on_stream(stream_id, b"second").await?;
+
+    let (received, auth_failures) = timeout(Duration::from_secs(5), server_task).await??;
+    assert_eq!(received, vec![b"first".to_vec(), b"second".to_vec()]);
+    assert_eq!(auth_failures, 1);
+
+    Ok(())
+}
+
+/// Test that a retry token echoed after its lifetime is rejected like a forged one
+#[tokio::test]
+async fn test_expired_retry_token_rejected() -> Result<()> {
+    use jsp_core::session::Session;
+    use jsp_core::types::handshake::{ClientHello, RetryFrame};
+    use jsp_core::types::header::{Header, FRAME_TYPE_RETRY};
+    use jsp_transport::ddos_protection::DdosConfig;
+
+    let config = ServerConfig::builder()
+        .ddos_config(DdosConfig {
+            retry_threshold: Some(0),
+            retry_token_lifetime: Duration::from_secs(1),
+            ..DdosConfig::default()
+        })
+        .build();
+    let mut server = Server::bind_with_config("127.0.0.1:9056", config).await?;
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    socket.connect("127.0.0.1:9056").await?;
+
+    let hello = Session::new().generate_client_hello()?;
+    socket.send(&hello).await?;
+    server.accept().await?;
+    let mut buf = [0u8; 2048];
+    let len = timeout(Duration::from_secs(2), socket.recv(&mut buf)).await??;
+    let header_len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+    let header: Header = serde_cbor::from_slice(&buf[2..2 + header_len])?;
+    assert_eq!(header.msg_type, FRAME_TYPE_RETRY);
+    let retry: RetryFrame = serde_cbor::from_slice(&buf[2 + header_len..len])?;
+
+    // Tokens carry whole seconds, so two later this one is past its lifetime
+    tokio::time::sleep(Duration::from_millis(2100)).await;
+    let mut echoed: ClientHello = serde_cbor::from_slice(&hello)?;
+    echoed.retry_token = Some(retry.token);
+    socket.send(&serde_cbor::to_vec(&echoed)?).await?;
+    assert!(server.accept().await.is_err());
+    assert_eq!(server.key_exchanges(), 0);
+    assert_eq!(server.session_count().await, 0);
+
+    Ok(())
+}
+
+/// Test that a sender never has more in flight than a slow receiver advertises
+#[tokio::test]
+async fn test_slow_receiver_window_bounds_inflight() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+
+    const MESSAGES: usize = 64;
+    let config = ConnectionConfig::builder().max_receive_buffer(8 * 1024).build();
+
+    let server_config = config.clone();
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen_with_config("127.0.0.1:9057", server_config).await.unwrap();
+        let mut received = Vec::new();
+        while received.len() < MESSAGES {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            received.extend(server.recv().await.unwrap().into_iter().map(|(_stream_id, data)| data));
+        }
+        received
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9057", config).await?;
+    client.handshake().await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+
+    let messages: Vec<Vec<u8>> = (0..MESSAGES).map(|i| vec![i as u8; 1000]).collect();
+    for message in &messages {
+        client.send_on_stream_wait(stream_id, message, Some(Duration::from_secs(5))).await?;
+        let window = client.peer_receive_window().expect("window is known after the handshake");
+        assert!(client.bytes_in_flight() as u64 <= window, "{} bytes in flight past a {} byte window", client.bytes_in_flight(), window);
+    }
+
+    let received = timeout(Duration::from_secs(10), server_task).await??;
+    assert_eq!(received, messages);
+
+    Ok(())
+}
+
+/// Test that payloads survive segmentation offload, or its absence, intact
+#[tokio::test]
+async fn test_gso_connection_delivers_payloads() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+
+    const MESSAGES: usize = 200;
+    let config = ConnectionConfig::builder().enable_gso(true).build();
+
+    let server_config = config.clone();
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen_with_config("127.0.0.1:9058", server_config).await.unwrap();
+        let mut received = Vec::new();
+        while received.len() < MESSAGES {
+            received.extend(server.recv().await.unwrap().into_iter().map(|(_stream_id, data)| data));
+        }
+        received
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9058", config).await?;
+    client.handshake().await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+
+    // Equal-sized messages sent back to back make segmentable runs
+    let messages: Vec<Vec<u8>> = (0..MESSAGES).map(|i| vec![i as u8; 1000]).collect();
+    for message in &messages {
+        client.send_on_stream(stream_id, message).await?;
+    }
+
+    let received = timeout(Duration::from_secs(10), server_task).await??;
+    assert_eq!(received, messages);
+
+    Ok(())
+}
+
+/// Test that peers preferring FlatBuffers switch their headers over, and that
+/// a CBOR-only peer still talks to them
+#[tokio::test]
+async fn test_flatbuffers_header_negotiation() -> Result<()> {
+    use jsp_core::codec::SerializationFormat;
+    use jsp_core::types::delivery::DeliveryMode;
+
+    let flatbuffers = ConnectionConfig::builder()
+        .serialization_formats(vec![SerializationFormat::FlatBuffers, SerializationFormat::Cbor])
+        .build();
+
+    for (port, client_config, expected) in [
+        (9059, flatbuffers.clone(), SerializationFormat::FlatBuffers),
+        (9060, ConnectionConfig::default(), SerializationFormat::Cbor),
+    ] {
+        let addr = format!("127.0.0.1:{}", port);
+        let server_config = flatbuffers.clone();
+        let listen_addr = addr.clone();
+        let server_task = tokio::spawn(async move {
+            let mut server = Connection::listen_with_config(&listen_addr, server_config).await.unwrap();
+            let stream_id = server.open_stream(1, DeliveryMode::Reliable).unwrap();
+            let data = loop {
+                if let Some((_stream_id, data)) = server.recv().await.unwrap().into_iter().next() {
+                    break data;
+                }
+            };
+            server.send_on_stream(stream_id, &data).await.unwrap();
+            server.flush_acks().await.unwrap();
+            server.serialization_format()
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut client = Connection::connect_with_config(&addr, client_config).await?;
+        client.handshake().await?;
+        assert_eq!(client.serialization_format(), expected);
+
+        let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+        client.send_on_stream(stream_id, b"ping").await?;
+        let reply = loop {
+            let packets = timeout(Duration::from_secs(5), client.recv()).await??;
+            if let Some((_stream_id, data)) = packets.into_iter().next() {
+                break data;
+            }
+        };
+        assert_eq!(&reply[..], b"ping");
+        assert_eq!(timeout(Duration::from_secs(5), server_task).await??, expected);
+    }
+
+    Ok(())
+}
+
+/// Test that the server encodes headers in the format it negotiated with each client
+#[tokio::test]
+async fn test_server_flatbuffers_headers() -> Result<()> {
+    use jsp_core::codec::SerializationFormat;
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::events::ServerEvent;
+
+    let flatbuffers = ConnectionConfig::builder()
+        .serialization_formats(vec![SerializationFormat::FlatBuffers])
+        .build();
+    let config = ServerConfig::builder().connection(flatbuffers.clone()).build();
+    let mut server = Server::bind_with_config("127.0.0.1:9061", config).await?;
+
+    let client_task = tokio::spawn(async move {
+        let mut client = Connection::connect_with_config("127.0.0.1:9061", flatbuffers).await.unwrap();
+        client.handshake().await.unwrap();
+        let stream_id = client.open_stream(1, DeliveryMode::Reliable).unwrap();
+        client.send_on_stream(stream_id, b"ping").await.unwrap();
+        let reply = loop {
+            if let Some((_stream_id, data)) = client.recv().await.unwrap().into_iter().next() {
+                break data;
+            }
+        };
+        (client.serialization_format(), reply)
+    });
+
+    loop {
+        let event = timeout(Duration::from_secs(5), server.next_event()).await??;
+        if let ServerEvent::DataReceived { addr, stream_id, data } = event {
+            let session = server.get_session(&addr).await.expect("session for the sender");
+            assert_eq!(session.serialization_format(), SerializationFormat::FlatBuffers);
+            server.send_on_stream(addr, stream_id, &data).await?;
+            break;
+        }
+    }
+
+    let (format, reply) = timeout(Duration::from_secs(5), client_task).await??;
+    assert_eq!(format, SerializationFormat::FlatBuffers);
+    assert_eq!(&reply[..], b"ping");
+
+    server.shutdown().await?;
+    Ok(())
+}
+
+/// Test that high priority packets skip ahead of queued bulk data without
+/// starving it
+#[tokio::test]
+async fn test_high_priority_bounded_delay_behind_bulk() -> Result<()> {
+    use jsp_core::qos::QosPriority;
+    use jsp_core::types::delivery::DeliveryMode;
+
+    const ROUNDS: usize = 10;
+    const PER_ROUND: usize = 50;
+    let config = ConnectionConfig::builder()
+        .rate_limit_messages(100_000)
+        .rate_limit_bytes(1_000_000_000)
+        .build();
+
+    let server_config = config.clone();
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen_with_config("127.0.0.1:9062", server_config).await.unwrap();
+        let mut received: std::collections::HashMap<u32, usize> = std::collections::HashMap::new();
+        while received.values().sum::<usize>() < 2 * ROUNDS * PER_ROUND {
+            for (stream_id, _data) in server.recv().await.unwrap() {
+                *received.entry(stream_id).or_default() += 1;
+            }
+        }
+        server.flush_acks().await.unwrap();
+        received
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9062", config).await?;
+    client.handshake().await?;
+    let bulk = client.open_stream(QosPriority::Bulk.value(), DeliveryMode::BestEffort)?;
+    let high = client.open_stream(QosPriority::Media.value(), DeliveryMode::Reliable)?;
+
+    for round in 0..ROUNDS {
+        // Nothing yields here, so the bulk data piles up in the send queue
+        // ahead of the high priority data
+        for i in 0..PER_ROUND {
+            client.send_on_stream(bulk, format!("bulk-{}-{}", round, i).as_bytes()).await?;
+        }
+        for i in 0..PER_ROUND {
+            client.send_on_stream_wait(high, format!("high-{}-{}", round, i).as_bytes(), Some(Duration::from_secs(5))).await?;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let received = timeout(Duration::from_secs(10), server_task).await??;
+    assert_eq!(received.get(&bulk), Some(&(ROUNDS * PER_ROUND)));
+    assert_eq!(received.get(&high), Some(&(ROUNDS * PER_ROUND)));
+
+    // Enqueue to send: high priority packets overtook the bulk data queued
+    // before them, and all the bulk data went out too
+    let bulk_counters = client.send_queue_counters(QosPriority::Bulk);
+    let high_counters = client.send_queue_counters(QosPriority::Media);
+    assert_eq!((bulk_counters.dequeued, bulk_counters.dropped), ((ROUNDS * PER_ROUND) as u64, 0));
+    assert_eq!(high_counters.dequeued, (ROUNDS * PER_ROUND) as u64);
+    assert!(
+        high_counters.queue_delay_max < bulk_counters.queue_delay_max,
+        "high priority waited up to {:?}, bulk up to {:?}", high_counters.queue_delay_max, bulk_counters.queue_delay_max
+    );
+    assert!(high_counters.queue_delay_max < Duration::from_millis(100), "{:?}", high_counters);
+
+    Ok(())
+}
+
+/// Per-stream byte and message counts on both ends of a connection
+#[tokio::test]
+async fn test_stream_stats_per_stream() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+
+    let server_task = tokio::spawn(async {
+        let mut server = Connection::listen_with_config("127.0.0.1:9063", ConnectionConfig::default()).await.unwrap();
+        let mut messages = 0;
+        while messages < 8 {
+            messages += server.recv().await.unwrap().len();
+        }
+        server.all_stream_stats()
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9063", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+    let small = client.open_stream(1, DeliveryMode::Reliable)?;
+    let large = client.open_stream(2, DeliveryMode::PartiallyReliable { ttl_ms: 5000 })?;
+    for _ in 0..3 {
+        client.send_on_stream(small, &[0x11; 100]).await?;
+    }
+    for _ in 0..5 {
+        client.send_on_stream(large, &[0x22; 1000]).await?;
+    }
+
+    let server_stats = timeout(Duration::from_secs(5), server_task).await??;
+
+    let sent = client.stream_stats(small).unwrap();
+    assert_eq!((sent.bytes_sent, sent.messages_sent), (300, 3));
+    assert_eq!((sent.bytes_received, sent.messages_received), (0, 0));
+    assert_eq!(sent.delivery_mode, DeliveryMode::Reliable);
+    assert_eq!(sent.priority, Some(1));
+    let sent = client.stream_stats(large).unwrap();
+    assert_eq!((sent.bytes_sent, sent.messages_sent), (5000, 5));
+    assert_eq!(sent.delivery_mode, DeliveryMode::PartiallyReliable { ttl_ms: 5000 });
+    assert_eq!(sent.priority, Some(2));
+    assert_eq!(client.stream_stats(99), None);
+    let ids: Vec<u32> = client.all_stream_stats().iter().map(|stats| stats.stream_id).collect();
+    assert_eq!(ids, vec![small, large]);
+
+    // The server never opened these streams; it only counts what arrived
+    assert_eq!(server_stats.len(), 2);
+    let received = server_stats.iter().find(|stats| stats.stream_id == small).unwrap();
+    assert_eq!((received.bytes_received, received.messages_received), (300, 3));
+    assert_eq!(received.bytes_sent, 0);
+    assert_eq!(received.priority, None);
+    let received = server_stats.iter().find(|stats| stats.stream_id == large).unwrap();
+    assert_eq!((received.bytes_received, received.messages_received), (5000, 5));
+
+    Ok(())
+}
+
+/// Test that a server reads the reason a client closed with
+#[tokio::test]
+async fn test_server_reads_close_reason() -> Result<()> {
+    use jsp_transport::events::ServerEvent;
+
+    let mut server = Server::bind("127.0.0.1:9064").await?;
+    let server_task = tokio::spawn(async move {
+        loop {
+            if let ServerEvent::Closed { reason, message, .. } = server.next_event().await.unwrap() {
+                return (reason, message, server.session_count().await);
+            }
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9064", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+    client.close(CloseReason::AuthenticationFailed, Some("bad token".to_string())).await?;
+
+    let (reason, message, sessions) = timeout(Duration::from_secs(5), server_task).await??;
+    assert_eq!(reason, CloseReason::AuthenticationFailed);
+    assert_eq!(message.as_deref(), Some("bad token"));
+    assert_eq!(sessions, 0);
+
+    Ok(())
+}
+
+/// Test that `recv` records why the peer closed after returning its last data
+#[tokio::test]
+async fn test_recv_records_peer_close() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+
+    let server_task = tokio::spawn(async {
+        let mut server = Connection::listen_with_config("127.0.0.1:9065", ConnectionConfig::default()).await.unwrap();
+        let mut received = Vec::new();
+        while server.peer_close().is_none() {
+            received.extend(server.recv().await.unwrap().into_iter().map(|(_, data)| data.to_vec()));
+        }
+        (received, server.peer_close().cloned(), server.is_closing())
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9065", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+    assert!(client.peer_close().is_none());
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    client.send_on_stream(stream_id, b"last words").await?;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    client.close(CloseReason::TooManyStreams, None).await?;
+
+    let (received, close, closing) = timeout(Duration::from_secs(5), server_task).await??;
+    assert_eq!(received, vec![b"last words".to_vec()]);
+    let close = close.unwrap();
+    assert_eq!(close.reason_code, CloseReason::TooManyStreams);
+    assert_eq!(close.message, None);
+    assert!(closing);
+
+    Ok(())
+}
+
+
+/// Test that a subscribed monitor receives stats frames, fragmented as needed
+#[tokio::test]
+async fn test_stats_stream_reports_sessions() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::events::ServerEvent;
+    use jsp_transport::stats_stream::{StatsFrame, STATS_STREAM_ID};
+
+    let config = ServerConfig {
+        stats_stream_interval: Some(Duration::from_millis(100)),
+        // Frames take several fragments
+        connection: ConnectionConfig { max_fragment_size: 200, ..ConnectionConfig::default() },
+        ..ServerConfig::default()
+    };
+    let mut server = Server::bind_with_config("127.0.0.1:9066", config).await?;
+    let server_task = tokio::spawn(async move {
+        loop {
+            if let ServerEvent::DataReceived { stream_id, .. } = server.next_event().await.unwrap() {
+                // Subscriptions never surface as data
+                assert_ne!(stream_id, STATS_STREAM_ID);
+            }
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9066", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    client.send_on_stream(stream_id, b"traffic").await?;
+
+    let mut monitor = Connection::connect_with_config("127.0.0.1:9066", ConnectionConfig::default()).await?;
+    monitor.handshake().await?;
+    monitor.open_reserved_stream(STATS_STREAM_ID, 0, DeliveryMode::Reliable)?;
+    monitor.send_on_stream(STATS_STREAM_ID, b"subscribe").await?;
+
+    let frame = timeout(Duration::from_secs(5), async {
+        loop {
+            for (stream_id, data) in monitor.recv().await.unwrap() {
+                if stream_id == STATS_STREAM_ID {
+                    return StatsFrame::from_bytes(&data).unwrap();
+                }
+            }
+        }
+    }).await?;
+
+    assert_eq!(frame.sessions.len(), 2);
+    let traffic = frame.sessions.iter().find(|session| session.session_id == client.session_id()).unwrap();
+    assert_eq!(traffic.metrics.packets_received, 1);
+    assert!(frame.metrics.packets_received >= 2);
+
+    server_task.abort();
+    Ok(())
+}
+
+
+/// Test that `recv_into` appends to the caller's buffer and keeps its allocation
+#[tokio::test]
+async fn test_recv_into_reuses_buffer() -> Result<()> {
+    use bytes::Bytes;
+    use jsp_core::types::delivery::DeliveryMode;
+
+    let server_task = tokio::spawn(async {
+        let mut server = Connection::listen_with_config("127.0.0.1:9067", ConnectionConfig::default()).await.unwrap();
+        let mut bufs: Vec<(u32, Bytes)> = Vec::with_capacity(16);
+        let allocation = bufs.as_ptr() as usize;
+        let mut received = Vec::new();
+        while received.len() < 6 {
+            bufs.clear();
+            let count = server.recv_into(&mut bufs).await.unwrap();
+            assert_eq!(count, bufs.len());
+            received.extend(bufs.iter().map(|(_, data)| data.to_vec()));
+        }
+        // Appends after what the caller already holds
+        bufs.clear();
+        bufs.push((0, Bytes::from_static(b"kept")));
+        while bufs.len() == 1 {
+            server.recv_into(&mut bufs).await.unwrap();
+        }
+        received.push(bufs[1].1.to_vec());
+        assert_eq!(&bufs[0].1[..], b"kept");
+        (received, bufs.as_ptr() as usize == allocation)
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9067", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    for i in 0..6 {
+        client.send_on_stream(stream_id, format!("message {}", i).as_bytes()).await?;
+    }
+    // The last one arrives once the server drained the others
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    client.send_on_stream(stream_id, b"message 6").await?;
+
+    let (received, same_allocation) = timeout(Duration::from_secs(5), server_task).await??;
+    let expected: Vec<Vec<u8>> = (0..7).map(|i| format!("message {}", i).into_bytes()).collect();
+    assert_eq!(received, expected);
+    assert!(same_allocation);
+
+    Ok(())
+}
+
+
+/// Test that `send_batch` queues every item under one lock, in order per stream
+#[tokio::test]
+async fn test_send_batch_takes_send_queue_lock_once() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+
+    let server_task = tokio::spawn(async {
+        let mut server = Connection::listen_with_config("127.0.0.1:9068", ConnectionConfig::default()).await.unwrap();
+        let mut received: Vec<(u32, Vec<u8>)> = Vec::new();
+        while received.len() < 7 {
+            received.extend(server.recv().await.unwrap().into_iter().map(|(stream_id, data)| (stream_id, data.to_vec())));
+        }
+        received
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9068", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+    let bulk = client.open_stream(1, DeliveryMode::Reliable)?;
+    let urgent = client.open_stream(3, DeliveryMode::Reliable)?;
+
+    let locks = client.send_queue_locks();
+    client.send_batch(&[
+        (bulk, b"bulk 0"), (urgent, b"urgent 0"), (bulk, b"bulk 1"),
+        (urgent, b"urgent 1"), (bulk, b"bulk 2"), (urgent, b"urgent 2"),
+    ]).await?;
+    assert_eq!(client.send_queue_locks() - locks, 1);
+
+    // Stops at the unknown stream; the item before it still goes out
+    let err = client.send_batch(&[(bulk, b"bulk 3"), (999, b"lost"), (bulk, b"never")]).await.unwrap_err();
+    assert!(err.to_string().contains("Batch item 1 (stream 999)"), "{}", err);
+    assert!(matches!(
+        err,
+        TransportError::BatchItemFailed { index: 1, stream_id: 999, ref source } if matches!(**source, TransportError::StreamNotFound(999))
+    ), "{:?}", err);
+    assert_eq!(client.stream_stats(bulk).unwrap().messages_sent, 4);
+
+    let received = timeout(Duration::from_secs(5), server_task).await??;
+    let on = |stream_id: u32| received.iter()
+        .filter(|(id, _)| *id == stream_id)
+        .map(|(_, data)| String::from_utf8(data.clone()).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(on(bulk), vec!["bulk 0", "bulk 1", "bulk 2", "bulk 3"]);
+    assert_eq!(on(urgent), vec!["urgent 0", "urgent 1", "urgent 2"]);
+
+    Ok(())
+}
+
+
+/// Test two ICE agents paired by a signaling room exchanging candidates and
+/// selecting each other's loopback address
+#[tokio::test]
+async fn test_ice_agents_pair_through_signaling_room() -> Result<()> {
+    use jsp_transport::ice::IceAgent;
+    use jsp_transport::signaling::{SignalingClient, SignalingServer};
+
+    tokio::spawn(async { SignalingServer::new("127.0.0.1:9069").run().await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let config = ConnectionConfig::builder()
+        .bind_addr("127.0.0.1:0".to_string())
+        .build();
+    // Neither agent is told the other's ID; the room pairs them
+    let mut agents = Vec::new();
+    for peer_id in ["alice", "bob"] {
+        let connection = Connection::connect_with_config("127.0.0.1:9", config.clone()).await?;
+        let mut signaling = SignalingClient::connect("127.0.0.1:9069", peer_id.to_string()).await?;
+        signaling.join("call").await?;
+        let mut agent = IceAgent::new(peer_id.to_string());
+        agent.set_signaling(signaling);
+        agents.push((agent, connection));
+    }
+    let (mut bob, mut bob_conn) = agents.pop().unwrap();
+    let (mut alice, mut alice_conn) = agents.pop().unwrap();
+
+    let (alice_selected, bob_selected) = tokio::join!(
+        alice.trickle(&mut alice_conn, Duration::from_secs(5)),
+        bob.trickle(&mut bob_conn, Duration::from_secs(5)),
+    );
+    assert_eq!(alice_selected?, Some(bob_conn.local_addr()?));
+    assert_eq!(bob_selected?, Some(alice_conn.local_addr()?));
+
+    Ok(())
+}
+
+
+/// Test two ICE agents whose host candidates can't be reached falling back
+/// to TURN relays, and releasing them on close
+///
+/// Each agent's connectivity checks reach the other through both relays,
+/// as plain datagrams to and from the relayed addresses.
+#[tokio::test]
+async fn test_ice_agents_fall_back_to_turn_relay() -> Result<()> {
+    use jsp_transport::ice::{Candidate, CandidateType, IceAgent};
+    use jsp_transport::signaling::{SignalingChannel, SignalingClient, SignalingMessage, SignalingServer};
+    use jsp_transport::turn_server::TurnServer;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    /// Advertises a silent socket in place of every host candidate
+    struct HostsUnreachable {
+        inner: SignalingClient,
+        silent: SocketAddr,
+    }
+
+    #[async_trait::async_trait]
+    impl SignalingChannel for HostsUnreachable {
+        async fn send(&mut self, msg: SignalingMessage) -> Result<()> {
+            let msg = match msg {
+                SignalingMessage::Candidate { target, candidate } => {
+                    let mut parsed: Candidate = serde_json::from_str(&candidate)?;
+                    if parsed.candidate_type == CandidateType::Host {
+                        parsed.addr = self.silent;
+                    }
+                    SignalingMessage::Candidate { target, candidate: serde_json::to_string(&parsed)? }
+                }
+                other => other,
+            };
+            self.inner.send(msg).await
+        }
+
+        async fn recv(&mut self) -> Result<SignalingMessage> {
+            self.inner.recv().await
+        }
+    }
+
+    tokio::spawn(async { SignalingServer::new("127.0.0.1:9070").run().await });
+    let turn = Arc::new(TurnServer::new("127.0.0.1:0", (41060, 41070)).await?);
+    let turn_addr = turn.local_addr()?;
+    let running = Arc::clone(&turn);
+    tokio::spawn(async move { running.run().await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let silent = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let config = ConnectionConfig::builder()
+        .bind_addr("127.0.0.1:0".to_string())
+        .build();
+    let mut agents = Vec::new();
+    for peer_id in ["alice", "bob"] {
+        let connection = Connection::connect_with_config("127.0.0.1:9", config.clone()).await?;
+        let mut signaling = SignalingClient::connect("127.0.0.1:9070", peer_id.to_string()).await?;
+        signaling.join("relayed").await?;
+        let mut agent = IceAgent::new(peer_id.to_string());
+        agent.set_signaling(HostsUnreachable { inner: signaling, silent: silent.local_addr()? });
+        agent.set_turn_server(turn_addr);
+        agents.push((agent, connection));
+    }
+    let (mut bob, mut bob_conn) = agents.pop().unwrap();
+    let (mut alice, mut alice_conn) = agents.pop().unwrap();
+
+    let (alice_selected, bob_selected) = tokio::join!(
+        alice.trickle(&mut alice_conn, Duration::from_secs(10)),
+        bob.trickle(&mut bob_conn, Duration::from_secs(10)),
+    );
+    let alice_relay = alice.relay_addr().expect("alice has no relay");
+    let bob_relay = bob.relay_addr().expect("bob has no relay");
+    assert_eq!(alice_selected?, Some(bob_relay));
+    assert_eq!(bob_selected?, Some(alice_relay));
+    assert_eq!(turn.allocation_count().await, 2);
+
+    // Closing the connections deletes the allocations
+    alice_conn.set_ice_agent(alice);
+    bob_conn.set_ice_agent(bob);
+    alice_conn.close(CloseReason::Normal, None).await?;
+    bob_conn.close(CloseReason::Normal, None).await?;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(turn.allocation_count().await, 0);
+
+    Ok(())
+}
+
+/// Test that a random mix of small, fragmented and stream-closing frames
+/// survives being coalesced into shared datagrams
+#[tokio::test]
+async fn test_coalesced_frames_all_delivered() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::events::ConnectionEvent;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::HashMap;
+
+    const STREAMS: usize = 3;
+    let config = ConnectionConfig::builder()
+        .coalescing_window_ms(5)
+        .rate_limit_messages(1_000_000)
+        .rate_limit_bytes(1 << 32)
+        .build();
+
+    let server_config = config.clone();
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen_with_config("127.0.0.1:9071", server_config).await.unwrap();
+        let mut received: HashMap<u32, Vec<bytes::Bytes>> = HashMap::new();
+        let mut finished = 0;
+        while finished < STREAMS {
+            for event in server.recv_events().await.unwrap() {
+                match event {
+                    ConnectionEvent::DataReceived { stream_id, data } => received.entry(stream_id).or_default().push(data),
+                    ConnectionEvent::StreamFinished(_) => finished += 1,
+                    _ => {}
+                }
+            }
+        }
+        server.flush_acks().await.unwrap();
+        (received, server)
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9071", config).await?;
+    client.handshake().await?;
+
+    // Same priority throughout, so the queue keeps the order they were framed in
+    let streams: Vec<u32> = (0..STREAMS)
+        .map(|_| client.open_stream(1, DeliveryMode::Reliable))
+        .collect::<Result<_, _>>()?;
+    let mut rng = StdRng::seed_from_u64(2316);
+    let mut sent: HashMap<u32, Vec<bytes::Bytes>> = HashMap::new();
+    for _ in 0..150 {
+        let stream_id = streams[rng.gen_range(0..STREAMS)];
+        // Mostly small enough to share a datagram, some fragmented
+        let len = if rng.gen_bool(0.8) { rng.gen_range(1..200) } else { rng.gen_range(1200..4000) };
+        let message: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+        client.send_on_stream(stream_id, &message).await?;
+        sent.entry(stream_id).or_default().push(message.into());
+    }
+    for &stream_id in &streams {
+        client.finish_stream(stream_id).await?;
+    }
+
+    let (received, _server) = timeout(Duration::from_secs(10), server_task).await??;
+    assert_eq!(received, sent);
+
+    Ok(())
+}
+
+/// Send messages and read their ACKs until adaptive compression decides
+/// for `reason`, returning how many were sent
+async fn send_until_reason(
+    client: &mut Connection,
+    stream_id: u32,
+    message: &[u8],
+    reason: jsp_transport::compression::adaptive::CompressionReason,
+) -> Result<usize> {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(15);
+    let mut sent = 0;
+    while client.compression_decision().reason != reason {
+        assert!(tokio::time::Instant::now() < deadline, "Still {:?}", client.compression_decision());
+        for _ in 0..4 {
+            client.send_on_stream(stream_id, message).await?;
+            sent += 1;
+        }
+        let _ = timeout(Duration::from_millis(400), client.recv_events()).await;
+    }
+    Ok(sent)
+}
+
+/// Test that adaptive compression turns compression on when the RTT jumps
+/// from 5 ms to 300 ms, and that the compressed messages arrive intact
+#[tokio::test]
+async fn test_compression_decision_follows_rtt() -> Result<()> {
+    use jsp_core::compression::payload_compression::CompressionAlgorithm;
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::compression::adaptive::{AdaptiveCompressionConfig, CompressionReason};
+    use std::sync::{Arc, Mutex};
+
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen("127.0.0.1:9072").await.unwrap();
+        let mut received = Vec::new();
+        loop {
+            let batch = server.recv().await.unwrap();
+            server.flush_acks().await.unwrap();
+            for (_stream_id, data) in batch {
+                if &data[..] == b"done" {
+                    return (received, server);
+                }
+                received.push(data);
+            }
+        }
+    });
+
+    // Each way, so round trips take twice as long
+    let delay = Arc::new(Mutex::new(Duration::from_micros(2500)));
+    spawn_delaying_proxy("127.0.0.1:9073", "127.0.0.1:9072".parse()?, Arc::clone(&delay), |_, packet| vec![packet.to_vec()]).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let config = ConnectionConfig::builder()
+        .enable_payload_compression(true)
+        .adaptive_compression_config(AdaptiveCompressionConfig {
+            update_interval: Duration::ZERO,
+            // Room for a loaded machine's loopback
+            local_rtt_threshold: Duration::from_millis(20),
+            ..AdaptiveCompressionConfig::default()
+        })
+        .rate_limit_messages(1_000_000)
+        .rate_limit_bytes(1 << 32)
+        .build();
+    let mut client = Connection::connect_with_config("127.0.0.1:9073", config).await?;
+    client.handshake().await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+
+    // Compressible, and fragmented when sent as it is
+    let message = b"adaptive compression follows the round trip time ".repeat(100);
+
+    let mut sent = send_until_reason(&mut client, stream_id, &message, CompressionReason::LocalLink).await?;
+    let local = client.compression_decision();
+    assert_eq!(local.algorithm, None);
+
+    *delay.lock().unwrap() = Duration::from_millis(150);
+    sent += send_until_reason(&mut client, stream_id, &message, CompressionReason::HighRtt).await?;
+    let remote = client.compression_decision();
+    assert_eq!(remote.algorithm, Some(CompressionAlgorithm::Zstd));
+    assert!(remote.level > 0);
+    assert_ne!(local, remote);
+    assert_eq!(client.metrics().compression, Some(remote));
+
+    // Now sent compressed: a fraction of its size on the wire
+    let bytes_before = client.metrics().bytes_sent;
+    client.send_on_stream(stream_id, &message).await?;
+    sent += 1;
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(3);
+    while client.metrics().bytes_sent == bytes_before && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let on_wire = client.metrics().bytes_sent - bytes_before;
+    assert!(on_wire > 0 && on_wire < message.len() as u64 / 4, "{} bytes on the wire", on_wire);
+
+    client.send_on_stream(stream_id, b"done").await?;
+    let (received, _server) = timeout(Duration::from_secs(10), server_task).await??;
+    assert_eq!(received.len(), sent);
+    assert!(received.iter().all(|data| data[..] == message[..]));
+
+    Ok(())
+}
+
+/// Test that a connection pool hands out the same handshaked connections
+/// again, closes surplus idle ones and replaces closed ones
+#[tokio::test]
+async fn test_connection_pool_reuses_connections() -> Result<()> {
+    use jsp_transport::events::ServerEvent;
+    use jsp_transport::pool::{ConnectionPool, PoolConfig};
+    use std::collections::HashSet;
+
+    let mut server = Server::bind("127.0.0.1:0").await?;
+    let addr = server.local_addr()?;
+    let (closed_tx, mut closed_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok(event) = server.next_event().await {
+            if let ServerEvent::Closed { reason, .. } = event {
+                let _ = closed_tx.send(reason);
+            }
+        }
+    });
+
+    let pool = ConnectionPool::new(PoolConfig {
+        max_connections_per_backend: 3,
+        min_connections_per_backend: 1,
+        idle_timeout: Some(Duration::from_millis(200)),
+        ..PoolConfig::default()
+    });
+
+    // Two at once take two handshakes
+    let first = pool.acquire(addr).await?;
+    let second = pool.acquire(addr).await?;
+    let sessions: HashSet<u64> = [first.session_id(), second.session_id()].into();
+    assert_eq!(sessions.len(), 2);
+    drop(first);
+    drop(second);
+    assert_eq!(pool.idle_count(addr), 2);
+
+    // One at a time, they come back without handshaking again
+    for _ in 0..20 {
+        let conn = pool.acquire(addr).await?;
+        assert!(sessions.contains(&conn.session_id()));
+    }
+    assert_eq!(pool.handshakes(), 2);
+
+    // Once idle past the timeout the surplus connection is closed, the warm one kept
+    let reason = timeout(Duration::from_secs(2), closed_rx.recv()).await?;
+    assert_eq!(reason, Some(CloseReason::GoingAway));
+    assert_eq!(pool.idle_count(addr), 1);
+    let mut warm = pool.acquire(addr).await?;
+    assert!(sessions.contains(&warm.session_id()));
+    assert_eq!(pool.handshakes(), 2);
+
+    // A connection closed while checked out is replaced in the background
+    warm.close(CloseReason::Normal, None).await?;
+    drop(warm);
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
+    while pool.handshakes() < 3 && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let replacement = pool.acquire(addr).await?;
+    assert!(!replacement.is_closing());
+    assert!(!sessions.contains(&replacement.session_id()));
+    assert_eq!(pool.handshakes(), 3);
+
+    Ok(())
+}
+
+/// Test that a network switch lengthens heartbeats on cellular, restarts
+/// congestion control and is reported as an event
+#[tokio::test]
+async fn test_network_switch_adjusts_heartbeat_and_congestion() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::congestion::CongestionState;
+    use jsp_transport::events::ConnectionEvent;
+    use jsp_transport::network_status::NetworkType;
+
+    const MESSAGES: usize = 20;
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen("127.0.0.1:9074").await.unwrap();
+        let mut received = 0;
+        while received < MESSAGES {
+            received += server.recv().await.unwrap().len();
+            server.flush_acks().await.unwrap();
+        }
+        server
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let config = ConnectionConfig::builder()
+        .heartbeat_interval(Duration::from_secs(2))
+        .heartbeat_cellular_interval(Duration::from_secs(8))
+        .build();
+    let mut client = Connection::connect_with_config("127.0.0.1:9074", config).await?;
+    client.handshake().await?;
+    // NewReno's initial window of ten segments
+    let initial_cwnd = 10 * client.config().mss as u64;
+
+    // ACKed data grows the window in slow start
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    for i in 0..MESSAGES {
+        client.send_on_stream(stream_id, &[i as u8; 1000]).await?;
+    }
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    while client.bytes_in_flight() > 0 && tokio::time::Instant::now() < deadline {
+        let _ = timeout(Duration::from_millis(50), client.recv_events()).await;
+    }
+    let grown_cwnd = client.metrics().congestion_window;
+    assert!(grown_cwnd > initial_cwnd, "window {} never grew past {}", grown_cwnd, initial_cwnd);
+
+    // The first report only sets the interval
+    client.set_network_type(NetworkType::Wifi).await;
+    assert_eq!(client.next_event().await?, ConnectionEvent::NetworkChanged { old: NetworkType::Unknown, new: NetworkType::Wifi });
+    assert_eq!(client.heartbeat().current_interval().await, Duration::from_secs(2));
+    assert_eq!(client.metrics().congestion_window, grown_cwnd);
+
+    // A switch to cellular is a new path
+    client.set_network_type(NetworkType::Cellular).await;
+    assert_eq!(client.next_event().await?, ConnectionEvent::NetworkChanged { old: NetworkType::Wifi, new: NetworkType::Cellular });
+    assert_eq!(client.heartbeat().current_interval().await, Duration::from_secs(8));
+    let metrics = client.metrics();
+    assert_eq!(metrics.congestion_window, initial_cwnd);
+    assert_eq!(metrics.congestion_state, Some(CongestionState::SlowStart));
+
+    // Reporting the same network again changes nothing
+    client.set_network_type(NetworkType::Cellular).await;
+    assert!(timeout(Duration::from_millis(100), client.next_event()).await.is_err());
+
+    timeout(Duration::from_secs(5), server_task).await??;
+    Ok(())
+}
+
+/// Test that sends on a closed connection fail with the reason it closed for
+#[tokio::test]
+async fn test_send_after_close_reports_reason() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::events::ConnectionEvent;
+
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen("127.0.0.1:9075").await.unwrap();
+        let stream_id = server.open_stream(1, DeliveryMode::Reliable).unwrap();
+        server.close(CloseReason::GoingAway, Some("maintenance".to_string())).await.unwrap();
+        // Closed locally, so the reason given to `close`
+        server.send_on_stream(stream_id, b"late").await.unwrap_err()
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9075", ConnectionConfig::default()).await?;
+    client.handshake().await?;
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+
+    loop {
+        let event = timeout(Duration::from_secs(5), client.next_event()).await??;
+        if matches!(event, ConnectionEvent::Closed { .. }) {
+            break;
+        }
+    }
+    // Closed by the peer, so the reason from its close frame
+    let err = client.send_on_stream(stream_id, b"too late").await.unwrap_err();
+    assert!(matches!(err, TransportError::ConnectionClosed { reason: CloseReason::GoingAway }), "{:?}", err);
+    assert!(!err.is_transient());
+
+    let err = timeout(Duration::from_secs(5), server_task).await??;
+    assert!(matches!(err, TransportError::ConnectionClosed { reason: CloseReason::GoingAway }), "{:?}", err);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_path_mtu_converges_below_capped_datagram_size() -> Result<()> {
+    use jsp_core::types::delivery::DeliveryMode;
+    use jsp_transport::mtu_discovery::{MtuDiscoveryConfig, PROBE_GRANULARITY};
+
+    // The proxy drops every datagram above this, both ways, as a path
+    // with a smaller MTU than either end's interface would
+    const CAP: usize = 1300;
+    let config = ConnectionConfig::builder()
+        .mtu_discovery(MtuDiscoveryConfig { enabled: true, base_mtu: 1200, max_mtu: 1500, ..Default::default() })
+        .build();
+    spawn_proxy("127.0.0.1:9077", "127.0.0.1:9076".parse()?, Duration::ZERO, |_, packet| packet.len() <= CAP).await?;
+
+    let server_config = config.clone();
+    let server_task = tokio::spawn(async move {
+        let mut server = Connection::listen_with_config("127.0.0.1:9076", server_config).await.unwrap();
+        let mut received = Vec::new();
+        while received.len() < 5000 {
+            for (_, data) in server.recv().await.unwrap() {
+                received.extend_from_slice(&data);
+            }
+        }
+        (received, server.path_mtu())
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Connection::connect_with_config("127.0.0.1:9077", config).await?;
+    client.handshake().await?;
+    assert_eq!(client.path_mtu(), 1200);
+
+    // Probes go out and are answered while the connection processes incoming packets
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    while client.path_mtu() + PROBE_GRANULARITY <= CAP && tokio::time::Instant::now() < deadline {
+        let _ = timeout(Duration::from_millis(50), client.next_event()).await;
+    }
+    let path_mtu = client.path_mtu();
+    assert!(path_mtu <= CAP && path_mtu + PROBE_GRANULARITY > CAP, "path MTU {}", path_mtu);
+
+    // Fragments are sized by the discovered MTU, so none is dropped
+    let stream_id = client.open_stream(1, DeliveryMode::Reliable)?;
+    let payload: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+    client.send_on_stream(stream_id, &payload).await?;
+    let (received, server_mtu) = timeout(Duration::from_secs(5), server_task).await??;
+    assert_eq!(received, payload);
+    assert!(server_mtu <= CAP, "server path MTU {}", server_mtu);
+
+    Ok(())
+}
@@ -18,6 +18,7 @@ async fn test_mobile_optimizations_integration() {
     let heartbeat_config = jsp_transport::heartbeat::HeartbeatConfig {
         foreground_interval: Duration::from_secs(5),
         background_interval: Duration::from_secs(30),
+        cellular_interval: Duration::from_secs(15),
         timeout_count: 3,
     };
     let heartbeat = jsp_transport::heartbeat::HeartbeatManager::new(heartbeat_config);